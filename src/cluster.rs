@@ -0,0 +1,292 @@
+//! Clustering of draws
+//!
+//! k-means over features of models, e.g. the values of a few parameters, to
+//! find the modes of a multimodal posterior or summarize draws by the mode
+//! they belong to.
+
+use rand::Rng;
+use runner::Sample;
+
+/// Most iterations of Lloyd's algorithm
+const MAX_ITERATIONS: usize = 100;
+
+/// Share of the points' spread clusters chosen by `select_k` must explain,
+/// unless they reach the most clusters allowed
+pub const MIN_EXPLAINED: f64 = 0.9;
+
+/// Clusters of a set of points
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clusters {
+    /// Cluster of each point
+    pub assignments: Vec<usize>,
+    /// Mean of the points of each cluster
+    pub centers: Vec<Vec<f64>>,
+    /// Sum of squared distances of the points to their cluster's center
+    pub within: f64,
+}
+
+impl Clusters {
+    /// Number of clusters
+    pub fn k(&self) -> usize {
+        self.centers.len()
+    }
+
+    /// Number of points in each cluster
+    pub fn sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.k()];
+        self.assignments.iter().for_each(|&k| sizes[k] += 1);
+        sizes
+    }
+
+    /// Index of the point of each cluster closest to its center, e.g. to
+    /// pick a representative draw of each mode
+    pub fn closest(&self, points: &[Vec<f64>]) -> Vec<usize> {
+        self.centers
+            .iter()
+            .enumerate()
+            .map(|(k, center)| {
+                let members = (0..points.len())
+                    .filter(|&i| self.assignments[i] == k)
+                    .map(|i| (i, squared_distance(&points[i], center)));
+                argmin(members).0
+            })
+            .collect()
+    }
+}
+
+fn squared_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum()
+}
+
+/// Index and value of the smallest of `xs`
+fn argmin<I: Iterator<Item = (usize, f64)>>(xs: I) -> (usize, f64) {
+    xs.fold((0, ::std::f64::INFINITY), |best, (i, x)| {
+        if x < best.1 {
+            (i, x)
+        } else {
+            best
+        }
+    })
+}
+
+fn nearest(centers: &[Vec<f64>], p: &[f64]) -> (usize, f64) {
+    argmin(centers.iter().map(|c| squared_distance(c, p)).enumerate())
+}
+
+fn valid(points: &[Vec<f64>]) -> bool {
+    match points.first() {
+        Some(p) => points.iter().all(|q| {
+            q.len() == p.len() && q.iter().all(|x| x.is_finite())
+        }),
+        None => false,
+    }
+}
+
+/// Scale each coordinate of `points` to unit standard deviation, so that
+/// features in different units weigh the same in the clusters
+///
+/// Coordinates which do not vary are left as they are.
+pub fn standardize(points: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let n = points.len() as f64;
+    let dim = points.first().map_or(0, |p| p.len());
+    let scales: Vec<f64> = (0..dim)
+        .map(|d| {
+            let mean = points.iter().map(|p| p[d]).sum::<f64>() / n;
+            let var = points
+                .iter()
+                .map(|p| (p[d] - mean).powi(2))
+                .sum::<f64>()
+                / n;
+            if var > 0.0 { var.sqrt() } else { 1.0 }
+        })
+        .collect();
+    points
+        .iter()
+        .map(|p| p.iter().zip(&scales).map(|(x, s)| x / s).collect())
+        .collect()
+}
+
+/// k-means clusters of `points` by Lloyd's algorithm from k-means++ seeds
+///
+/// Clusters which end up without points are dropped, so there may be fewer
+/// than `k`. `None` if `k` is zero, there are no points, or the points are
+/// not finite or differ in dimension.
+pub fn k_means<R: Rng>(
+    rng: &mut R,
+    points: &[Vec<f64>],
+    k: usize,
+) -> Option<Clusters> {
+    if k == 0 || !valid(points) {
+        return None;
+    }
+    let mut centers = vec![points[rng.gen_range(0, points.len())].clone()];
+    while centers.len() < k {
+        let weights: Vec<f64> =
+            points.iter().map(|p| nearest(&centers, p).1).collect();
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            break;
+        }
+        let mut u = rng.gen::<f64>() * total;
+        let next = weights
+            .iter()
+            .position(|w| {
+                u -= w;
+                u <= 0.0
+            })
+            .unwrap_or(points.len() - 1);
+        centers.push(points[next].clone());
+    }
+
+    let mut assignments = vec![0; points.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let updated: Vec<usize> =
+            points.iter().map(|p| nearest(&centers, p).0).collect();
+        let converged = updated == assignments;
+        assignments = updated;
+        for (k, center) in centers.iter_mut().enumerate() {
+            let members: Vec<&Vec<f64>> = points
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, &a)| a == k)
+                .map(|(p, _)| p)
+                .collect();
+            if !members.is_empty() {
+                let n = members.len() as f64;
+                for (d, c) in center.iter_mut().enumerate() {
+                    *c = members.iter().map(|p| p[d]).sum::<f64>() / n;
+                }
+            }
+        }
+        if converged {
+            break;
+        }
+    }
+
+    let used: Vec<usize> = (0..centers.len())
+        .filter(|k| assignments.contains(k))
+        .collect();
+    let assignments: Vec<usize> = assignments
+        .iter()
+        .map(|a| used.iter().position(|k| k == a).unwrap())
+        .collect();
+    let centers: Vec<Vec<f64>> =
+        used.iter().map(|&k| centers[k].clone()).collect();
+    let within = points
+        .iter()
+        .zip(&assignments)
+        .map(|(p, &k)| squared_distance(p, &centers[k]))
+        .sum();
+    Some(Clusters {
+        assignments,
+        centers,
+        within,
+    })
+}
+
+/// k-means clusters of `points` with the fewest clusters, up to `max_k`,
+/// explaining `min_explained` of the points' spread
+///
+/// The spread explained by k clusters is one minus their within-cluster
+/// sum of squares relative to that of a single cluster. `None` as for
+/// `k_means`.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate rand;
+/// # use rmcmc::cluster::{select_k, MIN_EXPLAINED};
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # fn main() {
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let points: Vec<Vec<f64>> = (0..20)
+///     .map(|i| vec![if i < 10 { -5.0 } else { 5.0 } + 0.1 * i as f64])
+///     .collect();
+///
+/// let clusters = select_k(&mut rng, &points, 5, MIN_EXPLAINED).unwrap();
+/// assert_eq!(clusters.k(), 2);
+/// assert_eq!(clusters.sizes(), vec![10, 10]);
+/// # }
+/// ```
+pub fn select_k<R: Rng>(
+    rng: &mut R,
+    points: &[Vec<f64>],
+    max_k: usize,
+    min_explained: f64,
+) -> Option<Clusters> {
+    let mut best = k_means(rng, points, 1)?;
+    let total = best.within;
+    for k in 2..=max_k.min(points.len()) {
+        if best.within <= (1.0 - min_explained) * total {
+            break;
+        }
+        best = k_means(rng, points, k)?;
+    }
+    Some(best)
+}
+
+impl<M> Sample<M> {
+    /// Clusters of the standardized `features` of this sample's draws,
+    /// pooling the chains, by `select_k` with `MIN_EXPLAINED`
+    ///
+    /// Assignments are in the order of `draws`. E.g. to check whether a
+    /// posterior is multimodal and how much mass each mode has.
+    pub fn clusters<R: Rng>(
+        &self,
+        rng: &mut R,
+        features: fn(&M) -> Vec<f64>,
+        max_k: usize,
+    ) -> Option<Clusters> {
+        let points: Vec<Vec<f64>> = self.draws().map(features).collect();
+        select_k(rng, &standardize(&points), max_k, MIN_EXPLAINED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn separated_clusters_are_found() {
+        let mut rng = StdRng::from_seed([0; 32]);
+        let points: Vec<Vec<f64>> = (0..30)
+            .map(|i| {
+                let center = [0.0, 5.0, 10.0][i % 3];
+                vec![center + 0.01 * i as f64, -center]
+            })
+            .collect();
+        let clusters = select_k(&mut rng, &points, 6, MIN_EXPLAINED).unwrap();
+        assert_eq!(clusters.k(), 3);
+        assert_eq!(clusters.sizes(), vec![10, 10, 10]);
+        let a = &clusters.assignments;
+        for i in 0..30 {
+            assert_eq!(a[i], a[i % 3]);
+        }
+        assert!(a[0] != a[1] && a[1] != a[2]);
+        for (k, &i) in clusters.closest(&points).iter().enumerate() {
+            assert_eq!(a[i], k);
+        }
+
+        let one = select_k(&mut rng, &points[..1], 6, MIN_EXPLAINED).unwrap();
+        assert_eq!((one.k(), one.assignments), (1, vec![0]));
+        assert_eq!(k_means(&mut rng, &points, 0), None);
+        assert_eq!(k_means(&mut rng, &[vec![0.0], vec![]], 1), None);
+    }
+
+    #[test]
+    fn sample_clusters_pool_chains() {
+        let mut rng = StdRng::from_seed([0; 32]);
+        let chains = vec![vec![-3.0, -3.1, -2.9], vec![3.0, 3.1, 2.9, 3.2]];
+        let sample = Sample::new(vec![], chains);
+        let clusters = sample.clusters(&mut rng, |x| vec![*x], 4).unwrap();
+        assert_eq!(clusters.k(), 2);
+        let mut sizes = clusters.sizes();
+        sizes.sort();
+        assert_eq!(sizes, vec![3, 4]);
+        let scaled = standardize(&[vec![1.0, 2.0], vec![3.0, 2.0]]);
+        assert_eq!(scaled, vec![vec![1.0, 2.0], vec![3.0, 2.0]]);
+    }
+}
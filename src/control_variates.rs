@@ -0,0 +1,233 @@
+//! Zero-variance control variates
+//!
+//! The score `∇ ln π(x)` of the posterior has expectation zero under the
+//! posterior, so any linear combination of its components can be subtracted
+//! from a function of the draws without biasing its mean. Choosing the
+//! combination by least squares removes the part of the function explained
+//! by the score, which often reduces the Monte Carlo error of posterior
+//! expectations considerably, and exactly for functions linear in the
+//! score, e.g. the mean of a Gaussian posterior.
+
+use nalgebra::{DMatrix, DVector};
+
+/// Estimate of a posterior expectation with first order zero-variance
+/// control variates
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControlVariateEstimate {
+    /// Control variate estimate of the expectation
+    pub mean: f64,
+    /// Plain average of the function over the draws
+    pub naive_mean: f64,
+    /// Coefficient of each score component
+    pub coefficients: DVector<f64>,
+    /// Ratio of the controlled variance to the function's variance
+    pub variance_ratio: f64,
+}
+
+/// Accumulates the moments needed for a zero-variance control variate
+/// estimate, so draws can be processed as they are generated without being
+/// stored.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate nalgebra;
+/// # use rmcmc::control_variates::ZeroVariance;
+/// # use nalgebra::DVector;
+/// # fn main() {
+/// // Draws from N(2, 1), whose score is 2 - x.
+/// let mut zv = ZeroVariance::new(1);
+/// for &x in [1.5, 2.7, 3.1, 0.4, 2.2].iter() {
+///     zv.push(x, &DVector::from_column_slice(1, &[2.0 - x]));
+/// }
+///
+/// let estimate = zv.estimate().unwrap();
+/// assert!((estimate.mean - 2.0).abs() < 1E-8);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ZeroVariance {
+    n: usize,
+    sum_f: f64,
+    sum_ff: f64,
+    sum_z: DVector<f64>,
+    sum_zf: DVector<f64>,
+    sum_zz: DMatrix<f64>,
+}
+
+impl ZeroVariance {
+    /// Accumulator for scores of dimension `dim`
+    pub fn new(dim: usize) -> Self {
+        ZeroVariance {
+            n: 0,
+            sum_f: 0.0,
+            sum_ff: 0.0,
+            sum_z: DVector::zeros(dim),
+            sum_zf: DVector::zeros(dim),
+            sum_zz: DMatrix::zeros(dim, dim),
+        }
+    }
+
+    /// Number of draws added
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    /// Add a draw's function value `f` and log posterior gradient `score`.
+    pub fn push(&mut self, f: f64, score: &DVector<f64>) {
+        assert_eq!(
+            score.len(),
+            self.sum_z.len(),
+            "Score has the wrong dimension."
+        );
+        self.n += 1;
+        self.sum_f += f;
+        self.sum_ff += f * f;
+        self.sum_z += score;
+        self.sum_zf += score * f;
+        self.sum_zz += score * score.transpose();
+    }
+
+    /// Control variate estimate from the draws so far, or `None` with fewer
+    /// than two draws. If the scores' covariance is singular the estimate
+    /// falls back to the plain average.
+    pub fn estimate(&self) -> Option<ControlVariateEstimate> {
+        if self.n < 2 {
+            return None;
+        }
+        let n = self.n as f64;
+        let mean_f = self.sum_f / n;
+        let mean_z = &self.sum_z / n;
+
+        let var_f = self.sum_ff / n - mean_f * mean_f;
+        let cov_zf = &self.sum_zf / n - &mean_z * mean_f;
+        let cov_zz = &self.sum_zz / n - &mean_z * mean_z.transpose();
+
+        let coefficients = cov_zz
+            .lu()
+            .solve(&cov_zf)
+            .filter(|c| c.iter().all(|x| x.is_finite()))
+            .unwrap_or_else(|| DVector::zeros(mean_z.len()));
+
+        let explained = cov_zf.dot(&coefficients);
+        let variance_ratio = if var_f > 0.0 {
+            ((var_f - explained) / var_f).max(0.0)
+        } else {
+            1.0
+        };
+
+        Some(ControlVariateEstimate {
+            mean: mean_f - coefficients.dot(&mean_z),
+            naive_mean: mean_f,
+            coefficients,
+            variance_ratio,
+        })
+    }
+}
+
+/// Zero-variance control variate estimate of the posterior expectation of
+/// `f` from `draws`, where `score` gives the gradient of the log posterior
+/// at a draw. Returns `None` with fewer than two draws.
+pub fn zero_variance<M, F, G>(
+    draws: &[M],
+    f: F,
+    score: G,
+) -> Option<ControlVariateEstimate>
+where
+    F: Fn(&M) -> f64,
+    G: Fn(&M) -> DVector<f64>,
+{
+    let first = draws.first()?;
+    let mut zv = ZeroVariance::new(score(first).len());
+    draws.iter().for_each(|m| zv.push(f(m), &score(m)));
+    zv.estimate()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Clone, Debug)]
+    struct Model {
+        a: f64,
+        b: f64,
+    }
+
+    // Independent N(1, 2) and N(-3, 0.5) posteriors
+    fn score(m: &Model) -> DVector<f64> {
+        DVector::from_column_slice(
+            2,
+            &[-(m.a - 1.0) / 4.0, -(m.b + 3.0) / 0.25],
+        )
+    }
+
+    fn draws(n: usize, rng: &mut StdRng) -> Vec<Model> {
+        let a: Vec<f64> = Gaussian::new(1.0, 2.0).unwrap().sample(n, rng);
+        let b: Vec<f64> = Gaussian::new(-3.0, 0.5).unwrap().sample(n, rng);
+        a.into_iter()
+            .zip(b)
+            .map(|(a, b)| Model { a, b })
+            .collect()
+    }
+
+    #[test]
+    fn linear_functions_have_zero_variance() {
+        let draws = draws(50, &mut StdRng::from_seed(SEED));
+        let estimate =
+            zero_variance(&draws, |m| m.a + 2.0 * m.b, score).unwrap();
+
+        assert!((estimate.mean - (1.0 - 6.0)).abs() < 1E-8);
+        assert!((estimate.naive_mean - (1.0 - 6.0)).abs() > 1E-3);
+        assert!(estimate.variance_ratio < 1E-8);
+        assert!((estimate.coefficients[0] + 4.0).abs() < 1E-8);
+        assert!((estimate.coefficients[1] + 0.5).abs() < 1E-8);
+    }
+
+    #[test]
+    fn reduces_error_of_nonlinear_functions() {
+        // E[a^2 + a] = 1 + 4 + 1, only partly explained by the score.
+        let mut rng = StdRng::from_seed(SEED);
+        let f = |m: &Model| m.a * m.a + m.a;
+        let (cv_error, naive_error) = (0..50).fold(
+            (0.0, 0.0),
+            |(cv, naive), _| {
+                let draws = draws(100, &mut rng);
+                let estimate = zero_variance(&draws, f, score).unwrap();
+                (
+                    cv + (estimate.mean - 6.0).powi(2),
+                    naive + (estimate.naive_mean - 6.0).powi(2),
+                )
+            },
+        );
+        assert!(cv_error < naive_error);
+    }
+
+    #[test]
+    fn too_few_draws() {
+        let draws = draws(1, &mut StdRng::from_seed(SEED));
+        assert!(zero_variance(&draws, |m| m.a, score).is_none());
+        assert!(zero_variance(&Vec::<Model>::new(), |m| m.a, score).is_none());
+        assert!(ZeroVariance::new(2).estimate().is_none());
+    }
+
+    #[test]
+    fn singular_scores_fall_back_to_the_average() {
+        let draws = draws(20, &mut StdRng::from_seed(SEED));
+        let estimate = zero_variance(&draws, |m| m.a, |_| {
+            DVector::from_column_slice(1, &[0.0])
+        })
+        .unwrap();
+        assert_eq!(estimate.mean, estimate.naive_mean);
+        assert_eq!(estimate.variance_ratio, 1.0);
+    }
+}
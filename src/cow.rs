@@ -0,0 +1,151 @@
+//! Models sharing large immutable data between copies
+//!
+//! Steppers clone the model on every proposal. When the model carries data,
+//! e.g. an observed series, every clone would copy it. `CowModel` keeps the
+//! data behind an `Arc` so clones and lens updates only copy the (small)
+//! parameter block, while the data is shared by every copy.
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Model made of shared immutable data `D` and a parameter block `P`
+///
+/// Lenses into the parameter block are made with `make_cow_lens!`.
+///
+/// # Example
+/// ```
+/// #[macro_use] extern crate rmcmc;
+/// # use rmcmc::lens::*;
+/// # use rmcmc::cow::CowModel;
+/// # fn main() {
+/// #[derive(Copy, Clone, Debug)]
+/// struct Params {
+///     mu: f64,
+/// }
+///
+/// let model = CowModel::new(vec![1.0, 2.0, 3.0], Params { mu: 0.0 });
+/// let lens = make_cow_lens!(Vec<f64>, Params, f64, mu);
+///
+/// let updated = lens.set(&model, 2.0);
+/// assert_eq!(lens.get(&updated), 2.0);
+/// assert!(updated.shares_data(&model));
+/// # }
+/// ```
+pub struct CowModel<D, P> {
+    data: Arc<D>,
+    pub params: P,
+}
+
+impl<D, P> CowModel<D, P> {
+    pub fn new(data: D, params: P) -> Self {
+        CowModel {
+            data: Arc::new(data),
+            params,
+        }
+    }
+
+    /// The shared data
+    pub fn data(&self) -> &D {
+        &self.data
+    }
+
+    /// A model with the same data and new parameters.
+    pub fn with_params(&self, params: P) -> Self {
+        CowModel {
+            data: self.data.clone(),
+            params,
+        }
+    }
+
+    /// Whether both models hold the same copy of the data.
+    pub fn shares_data(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+}
+
+impl<D, P: Clone> Clone for CowModel<D, P> {
+    fn clone(&self) -> Self {
+        self.with_params(self.params.clone())
+    }
+}
+
+impl<D, P: fmt::Debug> fmt::Debug for CowModel<D, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CowModel {{ params: {:?} }}", self.params)
+    }
+}
+
+/// Lens into a field of a `CowModel`'s parameter block, which must be `Copy`.
+#[macro_export]
+macro_rules! make_cow_lens {
+    ($data: ty, $kind: ident, $ptype: ty, $param: ident) => {
+        Lens::new(
+            |s: &$crate::cow::CowModel<$data, $kind>| s.params.$param.clone(),
+            |s: &$crate::cow::CowModel<$data, $kind>, x: $ptype| {
+                s.with_params($kind { $param: x, ..s.params })
+            },
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use runner::Runner;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+    use steppers::SRWM;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Copy, Clone, Debug)]
+    struct Params {
+        mu: f64,
+        sigma: f64,
+    }
+
+    type Model = CowModel<Vec<f64>, Params>;
+
+    #[test]
+    fn lens_updates_share_data() {
+        let params = Params { mu: 0.0, sigma: 1.0 };
+        let model = CowModel::new(vec![0.0; 1000], params);
+        let mu = make_cow_lens!(Vec<f64>, Params, f64, mu);
+
+        let updated = mu.set(&model, 1.5);
+        assert_eq!(updated.params.mu, 1.5);
+        assert_eq!(updated.params.sigma, 1.0);
+        assert_eq!(model.params.mu, 0.0);
+        assert!(updated.shares_data(&model));
+        assert!(model.clone().shares_data(&model));
+    }
+
+    #[test]
+    fn draws_share_the_initial_data() {
+        let mut rng = StdRng::from_seed(SEED);
+        let data = Gaussian::new(1.0, 1.0).unwrap().sample(20, &mut rng);
+        let init = CowModel::new(data, Params { mu: 0.0, sigma: 1.0 });
+
+        let parameter = Parameter::new(
+            "mu".to_string(),
+            Gaussian::new(0.0, 10.0).unwrap(),
+            make_cow_lens!(Vec<f64>, Params, f64, mu),
+        );
+        let log_likelihood = |m: &Model| {
+            let g = Gaussian::new(m.params.mu, m.params.sigma).unwrap();
+            m.data().iter().map(|x| g.ln_f(x)).sum::<f64>()
+        };
+        let alg = SRWM::new(parameter, log_likelihood, Some(0.5)).unwrap();
+
+        let results = Runner::new(alg)
+            .warmup(10)
+            .samples(50)
+            .run(&mut rng, init.clone());
+        assert!(results[0].iter().all(|m| m.shares_data(&init)));
+        assert!(results[0].iter().any(|m| m.params.mu != 0.0));
+    }
+}
@@ -0,0 +1,93 @@
+//! Priors evaluated over a batch of values at once
+
+use rv::dist::{
+    Beta, Cauchy, Exponential, Gamma, Gaussian, InvGamma, LogNormal, Poisson,
+    StudentsT, Uniform,
+};
+use rv::traits::Rv;
+use dist::GaussianRW;
+use nalgebra::DVector;
+
+/// Log density of many values of a prior at once, e.g. the candidates of a
+/// multiple-try proposal or the particles of a population, the prior's
+/// counterpart of batched log likelihoods
+///
+/// The default evaluates the values one by one. Implementations may
+/// override it with vectorized math, or hand the batch to an accelerator.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate rv;
+/// # use rmcmc::dist::BatchLnF;
+/// # use rv::dist::Gaussian;
+/// # use rv::traits::Rv;
+/// # fn main() {
+/// let prior = Gaussian::new(1.0, 2.0).unwrap();
+/// let xs = [0.0, 1.0, 4.0];
+/// let batch = prior.ln_f_batch(&xs);
+/// for (x, ln_f) in xs.iter().zip(batch) {
+///     assert!((prior.ln_f(x) - ln_f).abs() < 1E-12);
+/// }
+/// # }
+/// ```
+pub trait BatchLnF<T>: Rv<T> {
+    /// Log density of each of `xs`, in order
+    fn ln_f_batch(&self, xs: &[T]) -> Vec<f64> {
+        xs.iter().map(|x| self.ln_f(x)).collect()
+    }
+}
+
+impl BatchLnF<f64> for Gaussian {
+    fn ln_f_batch(&self, xs: &[f64]) -> Vec<f64> {
+        // The normalizing constant is shared by the whole batch.
+        let ln_z =
+            self.sigma.ln() + 0.5 * (2.0 * ::std::f64::consts::PI).ln();
+        let precision = self.sigma.recip();
+        xs.iter()
+            .map(|x| {
+                let k = (x - self.mu) * precision;
+                -0.5 * k * k - ln_z
+            })
+            .collect()
+    }
+}
+
+macro_rules! impl_one_by_one {
+    ($dist: ty, $($x: ty),+) => {
+        $(impl BatchLnF<$x> for $dist {})+
+    };
+}
+
+impl_one_by_one!(Gaussian, f32);
+impl_one_by_one!(Beta, f32, f64);
+impl_one_by_one!(Cauchy, f32, f64);
+impl_one_by_one!(Exponential, f32, f64);
+impl_one_by_one!(Gamma, f32, f64);
+impl_one_by_one!(InvGamma, f32, f64);
+impl_one_by_one!(LogNormal, f32, f64);
+impl_one_by_one!(StudentsT, f32, f64);
+impl_one_by_one!(Uniform, f32, f64);
+impl_one_by_one!(Poisson, u16, u32);
+impl_one_by_one!(GaussianRW, DVector<f64>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn vectorized_gaussian_matches_one_by_one() {
+        let mut rng = StdRng::from_seed([0; 32]);
+        let prior = Gaussian::new(-3.0, 0.25).unwrap();
+        let wide = Gaussian::new(0.0, 5.0).unwrap();
+        let xs: Vec<f64> = wide.sample(100, &mut rng);
+        let batch = prior.ln_f_batch(&xs);
+        assert_eq!(batch.len(), xs.len());
+        for (x, ln_f) in xs.iter().zip(batch) {
+            assert!((prior.ln_f(x) - ln_f).abs() < 1E-10);
+        }
+        assert!(BatchLnF::<f64>::ln_f_batch(&prior, &[]).is_empty());
+    }
+}
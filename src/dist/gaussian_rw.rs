@@ -0,0 +1,181 @@
+//! Gaussian random walk over a path
+
+use std::io;
+use rand::Rng;
+use nalgebra::DVector;
+use rv::dist::Gaussian;
+use rv::traits::Rv;
+use utils::invalid_input;
+
+/// Gaussian random walk prior over a path `x` of fixed length
+///
+/// The first point is drawn from the anchoring distribution `init` and each
+/// subsequent point from `N(x[t - 1], sigma)`. The anchor's density is part
+/// of `ln_f`, so the start of the path is not left improper.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate rv;
+/// # extern crate nalgebra;
+/// # use rmcmc::dist::GaussianRW;
+/// # use rv::dist::Gaussian;
+/// # use rv::traits::Rv;
+/// # use nalgebra::DVector;
+/// # fn main() {
+/// let init = Gaussian::new(0.0, 10.0).unwrap();
+/// let rw = GaussianRW::new(init, 1.0, 4).unwrap();
+/// let x = DVector::from_column_slice(4, &[0.0, 0.5, 0.2, 1.0]);
+///
+/// // Only the terms touching x[1..3] are needed to compare paths differing
+/// // in that block.
+/// let mut y = x.clone();
+/// y[1] = -0.5;
+/// y[2] = 0.1;
+/// let delta = rw.block_ln_f(&y, 1, 3) - rw.block_ln_f(&x, 1, 3);
+/// assert!((delta - (rw.ln_f(&y) - rw.ln_f(&x))).abs() < 1E-10);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct GaussianRW {
+    /// Distribution of the first point
+    pub init: Gaussian,
+    /// Standard deviation of each step
+    pub sigma: f64,
+    /// Length of the path
+    pub len: usize,
+}
+
+impl GaussianRW {
+    pub fn new(init: Gaussian, sigma: f64, len: usize) -> io::Result<Self> {
+        if !(sigma > 0.0 && sigma.is_finite()) {
+            Err(invalid_input("sigma must be finite and greater than zero"))
+        } else if len == 0 {
+            Err(invalid_input("len must be greater than zero"))
+        } else {
+            Ok(GaussianRW { init, sigma, len })
+        }
+    }
+
+    fn step_ln_f(&self, from: f64, to: f64) -> f64 {
+        let z = (to - from) / self.sigma;
+        -0.5 * z * z
+            - self.sigma.ln()
+            - 0.5 * (2.0 * ::std::f64::consts::PI).ln()
+    }
+
+    /// Log density of the terms involving `x[start..end]`, i.e. the density
+    /// of that block conditional on the rest of the path up to a constant.
+    ///
+    /// The difference of `block_ln_f` between two paths which only differ in
+    /// `start..end` equals the difference of their `ln_f`, at a cost
+    /// proportional to the block's length rather than the path's.
+    pub fn block_ln_f(
+        &self,
+        x: &DVector<f64>,
+        start: usize,
+        end: usize,
+    ) -> f64 {
+        let end = end.min(x.len());
+        if start >= end {
+            return 0.0;
+        }
+
+        let init_term = if start == 0 {
+            self.init.ln_f(&x[0])
+        } else {
+            0.0
+        };
+
+        // Steps into the block and the step out of it to x[end].
+        let first_step = start.max(1);
+        let last_step = (end + 1).min(x.len());
+        (first_step..last_step)
+            .fold(init_term, |acc, t| acc + self.step_ln_f(x[t - 1], x[t]))
+    }
+}
+
+impl Rv<DVector<f64>> for GaussianRW {
+    fn ln_f(&self, x: &DVector<f64>) -> f64 {
+        if x.len() != self.len {
+            return ::std::f64::NEG_INFINITY;
+        }
+        self.block_ln_f(x, 0, x.len())
+    }
+
+    fn draw<R: Rng>(&self, rng: &mut R) -> DVector<f64> {
+        let mut x = DVector::zeros(self.len);
+        x[0] = self.init.draw(rng);
+        let step = Gaussian::new(0.0, self.sigma).unwrap();
+        for t in 1..self.len {
+            let dx: f64 = step.draw(rng);
+            x[t] = x[t - 1] + dx;
+        }
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    fn naive(rw: &GaussianRW, x: &DVector<f64>) -> f64 {
+        let step = Gaussian::new(0.0, rw.sigma).unwrap();
+        (1..x.len()).fold(rw.init.ln_f(&x[0]), |acc, t| {
+            acc + step.ln_f(&(x[t] - x[t - 1]))
+        })
+    }
+
+    #[test]
+    fn ln_f_includes_initial_point() {
+        let rw = GaussianRW::new(Gaussian::new(1.0, 2.0).unwrap(), 0.5, 5)
+            .unwrap();
+        let x = DVector::from_column_slice(5, &[0.3, 0.1, -0.4, 0.2, 0.9]);
+        assert!((rw.ln_f(&x) - naive(&rw, &x)).abs() < 1E-10);
+
+        // Moving the whole path changes the anchor's density.
+        let shifted = x.add_scalar(3.0);
+        assert!(rw.ln_f(&shifted) < rw.ln_f(&x));
+    }
+
+    #[test]
+    fn block_ln_f_matches_full_differences() {
+        let mut rng = StdRng::from_seed(SEED);
+        let rw = GaussianRW::new(Gaussian::new(0.0, 1.0).unwrap(), 0.7, 8)
+            .unwrap();
+        let x = rw.draw(&mut rng);
+
+        for start in 0..8 {
+            for end in (start + 1)..9 {
+                let mut y = x.clone();
+                for t in start..end {
+                    y[t] += 0.3 * (t as f64) - 1.0;
+                }
+                let block_delta = rw.block_ln_f(&y, start, end)
+                    - rw.block_ln_f(&x, start, end);
+                let full_delta = rw.ln_f(&y) - rw.ln_f(&x);
+                assert!(
+                    (block_delta - full_delta).abs() < 1E-10,
+                    "{}..{}: {} != {}",
+                    start,
+                    end,
+                    block_delta,
+                    full_delta
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_length_is_outside_support() {
+        let rw = GaussianRW::new(Gaussian::new(0.0, 1.0).unwrap(), 1.0, 3)
+            .unwrap();
+        assert!(rw.ln_f(&DVector::zeros(2)).is_infinite());
+        assert!(GaussianRW::new(Gaussian::new(0.0, 1.0).unwrap(), 0.0, 3)
+            .is_err());
+    }
+}
@@ -0,0 +1,7 @@
+//! Distributions for use as priors which are not provided by `rv`
+
+pub mod batch;
+pub mod gaussian_rw;
+
+pub use self::batch::BatchLnF;
+pub use self::gaussian_rw::GaussianRW;
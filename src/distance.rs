@@ -0,0 +1,160 @@
+//! Distances between sets of draws
+//!
+//! Wasserstein distances compare two samples without assuming a form for
+//! either, e.g. prior and posterior draws of a quantity, draws of two runs
+//! which should agree, or draws of a sampler under test and exact draws.
+
+use nalgebra::DVector;
+use rand::Rng;
+use rand::distributions::StandardNormal;
+use runner::Sample;
+
+fn sorted(x: &[f64]) -> Vec<f64> {
+    let mut x = x.to_vec();
+    x.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    x
+}
+
+/// First Wasserstein distance between the empirical distributions of two
+/// sets of draws, the area between their distribution functions
+///
+/// The sets may have different numbers of draws. `None` if either is empty
+/// or has a non-finite draw.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # use rmcmc::distance::wasserstein;
+/// # fn main() {
+/// let a = [0.0, 1.0, 2.0, 3.0];
+/// let shifted = [0.5, 1.5, 2.5, 3.5];
+/// assert_eq!(wasserstein(&a, &shifted), Some(0.5));
+/// # }
+/// ```
+pub fn wasserstein(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    if a.iter().chain(b).any(|x| !x.is_finite()) {
+        return None;
+    }
+    let (a, b) = (sorted(a), sorted(b));
+    let (na, nb) = (a.len() as f64, b.len() as f64);
+    let (mut i, mut j) = (0, 0);
+    let mut x = a[0].min(b[0]);
+    let mut distance = 0.0;
+    while i < a.len() || j < b.len() {
+        let next = match (a.get(i), b.get(j)) {
+            (Some(&p), Some(&q)) => p.min(q),
+            (Some(&p), None) => p,
+            (None, Some(&q)) => q,
+            (None, None) => unreachable!(),
+        };
+        distance += (i as f64 / na - j as f64 / nb).abs() * (next - x);
+        x = next;
+        while i < a.len() && a[i] == x {
+            i += 1;
+        }
+        while j < b.len() && b[j] == x {
+            j += 1;
+        }
+    }
+    Some(distance)
+}
+
+/// Sliced Wasserstein distance between two sets of vector draws, the mean
+/// first Wasserstein distance of their projections on `n_projections`
+/// random directions
+///
+/// `None` without projections, if either set is empty or has a non-finite
+/// draw, or if the draws differ in dimension.
+pub fn sliced_wasserstein<R: Rng>(
+    rng: &mut R,
+    a: &[DVector<f64>],
+    b: &[DVector<f64>],
+    n_projections: usize,
+) -> Option<f64> {
+    let dim = a.first()?.len();
+    if n_projections == 0 || a.iter().chain(b).any(|x| x.len() != dim) {
+        return None;
+    }
+    let total = (0..n_projections).try_fold(0.0, |total, _| {
+        let direction: DVector<f64> =
+            DVector::from_fn(dim, |_, _| rng.sample(StandardNormal));
+        let direction = direction.normalize();
+        let project =
+            |x: &[DVector<f64>]| x.iter().map(|v| v.dot(&direction)).collect();
+        let (pa, pb): (Vec<f64>, Vec<f64>) = (project(a), project(b));
+        wasserstein(&pa, &pb).map(|d| total + d)
+    })?;
+    Some(total / n_projections as f64)
+}
+
+impl<M> Sample<M> {
+    /// First Wasserstein distance between the draws of `quantity` in this
+    /// sample and in `other`, pooling the chains of each
+    ///
+    /// E.g. to check that runs with different steppers or seeds agree.
+    pub fn wasserstein(
+        &self,
+        other: &Sample<M>,
+        quantity: fn(&M) -> f64,
+    ) -> Option<f64> {
+        let a: Vec<f64> = self.draws().map(quantity).collect();
+        let b: Vec<f64> = other.draws().map(quantity).collect();
+        wasserstein(&a, &b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[test]
+    fn wasserstein_of_a_shift_is_the_shift() {
+        let a = vec![0.0, 1.0, 2.0, 3.0];
+        let b: Vec<f64> = a.iter().map(|x| x + 0.5).collect();
+        assert!((wasserstein(&a, &b).unwrap() - 0.5).abs() < 1E-12);
+        assert_eq!(wasserstein(&a, &a), Some(0.0));
+        // Different numbers of draws of the same distribution
+        let c = vec![0.0, 0.0, 1.0, 1.0, 2.0, 2.0, 3.0, 3.0];
+        assert!(wasserstein(&a, &c).unwrap() < 1E-12);
+        assert_eq!(wasserstein(&a, &[]), None);
+
+        let first = Sample::new(vec![], vec![vec![0.0, 1.0], vec![2.0, 3.0]]);
+        let second = Sample::new(vec![], vec![b]);
+        let d = first.wasserstein(&second, |x| *x).unwrap();
+        assert!((d - 0.5).abs() < 1E-12);
+    }
+
+    #[test]
+    fn sliced_wasserstein_grows_with_separation() {
+        let mut rng = StdRng::from_seed(SEED);
+        let g = Gaussian::new(0.0, 1.0).unwrap();
+        let draws = |shift: f64, rng: &mut StdRng| -> Vec<DVector<f64>> {
+            (0..500)
+                .map(|_| {
+                    let x: Vec<f64> = g.sample(3, rng);
+                    DVector::from_column_slice(3, &x).add_scalar(shift)
+                })
+                .collect()
+        };
+        let a = draws(0.0, &mut rng);
+        let b = draws(0.0, &mut rng);
+        let c = draws(1.0, &mut rng);
+
+        let near = sliced_wasserstein(&mut rng, &a, &b, 50).unwrap();
+        let far = sliced_wasserstein(&mut rng, &a, &c, 50).unwrap();
+        assert!(near < 0.2);
+        assert!(far > 0.5);
+        assert_eq!(sliced_wasserstein(&mut rng, &a, &c, 0), None);
+        let flat = vec![DVector::zeros(2)];
+        assert_eq!(sliced_wasserstein(&mut rng, &a, &flat, 10), None);
+    }
+}
@@ -0,0 +1,343 @@
+//! Events emitted by steppers as they step
+//!
+//! Statistics summarize a stepper's state when asked; events record what
+//! happened in each step as it happens. A stepper given an `EventSink` with
+//! `SteppingAlg::set_event_sink` emits into it, and `Runner::run_with_events`
+//! gives each chain its own sink and returns what was collected.
+//!
+//! A sink made with `EventSink::verbose` also receives the breakdown of
+//! every Metropolis update into prior and likelihood, for finding out why
+//! a chain is stuck without editing the steppers, and `Event::log_line`
+//! formats events as structured log lines.
+//!
+//! The crate has no logging dependency, so the breakdown goes into the
+//! sink rather than to `tracing` or `log` directly. Per-chain sinks can
+//! be read back in tests and tools, and forwarding them to a logger is
+//! one call per drained event, e.g. `info!("{}", event.log_line())`.
+
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use parameter::ParamId;
+use steppers::AdaptationStatus;
+use steppers::util::MetroplisUpdate;
+
+/// Something which happened in a step
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A proposal for `parameter` was accepted
+    ProposalAccepted { parameter: ParamId, log_alpha: f64 },
+    /// A proposal for `parameter` was rejected
+    ProposalRejected { parameter: ParamId, log_alpha: f64 },
+    /// The adaptor of `parameter`'s stepper updated its scale to `scale`
+    AdaptationUpdated { parameter: ParamId, scale: f64 },
+    /// A step of `parameter` met a non-finite value, e.g. a NaN acceptance
+    /// ratio
+    NumericalWarning { parameter: ParamId, message: String },
+    /// The lens of `parameter` failed to get or set its value, so the step
+    /// was rejected
+    LensFailed { parameter: ParamId, message: String },
+    /// Breakdown of a Metropolis update of `parameter`, emitted only into
+    /// verbose sinks
+    ProposalScored { parameter: ParamId, scores: ScoreBreakdown },
+}
+
+/// Prior and likelihood of the current and proposed models of a
+/// Metropolis update, with its outcome
+///
+/// Log likelihoods are those the stepper scores with, e.g. tempered, and
+/// for a stepper of several parameters the prior is that of all of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoreBreakdown {
+    pub current_prior: f64,
+    pub current_log_likelihood: f64,
+    pub proposed_prior: f64,
+    /// `None` when the proposal was outside the prior's support, so its
+    /// likelihood was not evaluated
+    pub proposed_log_likelihood: Option<f64>,
+    pub log_alpha: f64,
+    pub accepted: bool,
+}
+
+impl Event {
+    /// The parameter the event concerns
+    pub fn parameter(&self) -> &ParamId {
+        match self {
+            Event::ProposalAccepted { parameter, .. } => parameter,
+            Event::ProposalRejected { parameter, .. } => parameter,
+            Event::AdaptationUpdated { parameter, .. } => parameter,
+            Event::NumericalWarning { parameter, .. } => parameter,
+            Event::LensFailed { parameter, .. } => parameter,
+            Event::ProposalScored { parameter, .. } => parameter,
+        }
+    }
+
+    /// The event as a line of `key=value` pairs, the first of them its
+    /// kind, e.g. for writing to a log
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate rmcmc;
+    /// # use rmcmc::events::Event;
+    /// # use rmcmc::parameter::ParamId;
+    /// # fn main() {
+    /// let event = Event::ProposalRejected {
+    ///     parameter: ParamId("mu".to_string()),
+    ///     log_alpha: -2.5,
+    /// };
+    /// assert_eq!(
+    ///     event.log_line(),
+    ///     "event=proposal_rejected parameter=mu log_alpha=-2.5"
+    /// );
+    /// # }
+    /// ```
+    pub fn log_line(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::ProposalAccepted {
+                parameter,
+                log_alpha,
+            } => write!(
+                f,
+                "event=proposal_accepted parameter={} log_alpha={}",
+                parameter, log_alpha
+            ),
+            Event::ProposalRejected {
+                parameter,
+                log_alpha,
+            } => write!(
+                f,
+                "event=proposal_rejected parameter={} log_alpha={}",
+                parameter, log_alpha
+            ),
+            Event::AdaptationUpdated { parameter, scale } => write!(
+                f,
+                "event=adaptation_updated parameter={} scale={}",
+                parameter, scale
+            ),
+            Event::NumericalWarning { parameter, message } => write!(
+                f,
+                "event=numerical_warning parameter={} message={:?}",
+                parameter, message
+            ),
+            Event::LensFailed { parameter, message } => write!(
+                f,
+                "event=lens_failed parameter={} message={:?}",
+                parameter, message
+            ),
+            Event::ProposalScored { parameter, scores } => {
+                write!(
+                    f,
+                    "event=proposal_scored parameter={} current_prior={} \
+                     current_log_likelihood={} proposed_prior={} \
+                     proposed_log_likelihood=",
+                    parameter,
+                    scores.current_prior,
+                    scores.current_log_likelihood,
+                    scores.proposed_prior,
+                )?;
+                match scores.proposed_log_likelihood {
+                    Some(ln_l) => write!(f, "{}", ln_l)?,
+                    None => write!(f, "unevaluated")?,
+                }
+                write!(
+                    f,
+                    " log_alpha={} decision={}",
+                    scores.log_alpha,
+                    if scores.accepted { "accepted" } else { "rejected" }
+                )
+            }
+        }
+    }
+}
+
+/// Buffer of events emitted by the steppers sharing it
+///
+/// Clones share the buffer, so the steppers of a group emit into one sink,
+/// in the order they step.
+#[derive(Clone, Debug, Default)]
+pub struct EventSink {
+    events: Arc<Mutex<Vec<Event>>>,
+    verbose: bool,
+}
+
+impl EventSink {
+    pub fn new() -> Self {
+        EventSink::default()
+    }
+
+    /// A sink which also receives an `Event::ProposalScored` for every
+    /// Metropolis update. Steppers compute the breakdown only for verbose
+    /// sinks, so ordinary sinks cost nothing extra.
+    pub fn verbose() -> Self {
+        EventSink {
+            verbose: true,
+            ..EventSink::default()
+        }
+    }
+
+    /// Whether the sink receives score breakdowns
+    pub fn is_verbose(&self) -> bool {
+        self.verbose
+    }
+
+    pub fn emit(&self, event: Event) {
+        self.events
+            .lock()
+            .expect("Failed to get access to event sink")
+            .push(event);
+    }
+
+    /// Emit the outcome of a Metropolis update of `parameter`, with a
+    /// warning if its acceptance ratio was NaN.
+    pub fn emit_update<T: Clone>(
+        &self,
+        parameter: &ParamId,
+        update: &MetroplisUpdate<T>,
+    ) {
+        let log_alpha = update.log_alpha();
+        if log_alpha.is_nan() {
+            self.emit(Event::NumericalWarning {
+                parameter: parameter.clone(),
+                message: "NaN acceptance ratio".to_string(),
+            });
+        }
+        let parameter = parameter.clone();
+        self.emit(if update.is_accepted() {
+            Event::ProposalAccepted {
+                parameter,
+                log_alpha,
+            }
+        } else {
+            Event::ProposalRejected {
+                parameter,
+                log_alpha,
+            }
+        });
+    }
+
+    /// Emit the breakdown of a Metropolis update of `parameter` into
+    /// prior and likelihood, if the sink is verbose. `current_prior` is
+    /// only called then.
+    pub fn emit_scores<T, P>(
+        &self,
+        parameter: &ParamId,
+        update: &MetroplisUpdate<T>,
+        current_prior: P,
+        proposed_prior: f64,
+    ) where
+        T: Clone,
+        P: FnOnce() -> f64,
+    {
+        if !self.verbose {
+            return;
+        }
+        let scores = update.scores();
+        let current_prior = current_prior();
+        // The likelihood is not evaluated outside the prior's support.
+        let proposed_log_likelihood = if proposed_prior.is_finite() {
+            Some(scores.proposed - proposed_prior)
+        } else {
+            None
+        };
+        self.emit(Event::ProposalScored {
+            parameter: parameter.clone(),
+            scores: ScoreBreakdown {
+                current_prior,
+                current_log_likelihood: scores.current - current_prior,
+                proposed_prior,
+                proposed_log_likelihood,
+                log_alpha: scores.log_alpha,
+                accepted: update.is_accepted(),
+            },
+        });
+    }
+
+    /// Emit the scale of `parameter`'s adaptor after an update, if it is
+    /// adapting.
+    pub fn emit_adaptation(
+        &self,
+        parameter: &ParamId,
+        status: AdaptationStatus,
+        scale: f64,
+    ) {
+        if let AdaptationStatus::Enabled = status {
+            self.emit(Event::AdaptationUpdated {
+                parameter: parameter.clone(),
+                scale,
+            });
+        }
+    }
+
+    /// Emit a failure of `parameter`'s lens.
+    pub fn emit_lens_failure(&self, parameter: &ParamId, err: &io::Error) {
+        self.emit(Event::LensFailed {
+            parameter: parameter.clone(),
+            message: err.to_string(),
+        });
+    }
+
+    /// Remove and return the events, oldest first.
+    pub fn drain(&self) -> Vec<Event> {
+        self.events
+            .lock()
+            .expect("Failed to get access to event sink")
+            .drain(..)
+            .collect()
+    }
+
+    /// Copy of the events, oldest first
+    pub fn events(&self) -> Vec<Event> {
+        self.events
+            .lock()
+            .expect("Failed to get access to event sink")
+            .clone()
+    }
+
+    /// Whether any event from the `start`th on satisfies `f`
+    pub fn any_since<F>(&self, start: usize, f: F) -> bool
+    where
+        F: Fn(&Event) -> bool,
+    {
+        self.events
+            .lock()
+            .expect("Failed to get access to event sink")
+            .iter()
+            .skip(start)
+            .any(f)
+    }
+
+    pub fn len(&self) -> usize {
+        self.events
+            .lock()
+            .expect("Failed to get access to event sink")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Fraction of the proposals for `parameter` in `events` which were
+/// accepted, if there were any
+pub fn acceptance_rate(events: &[Event], parameter: &ParamId) -> Option<f64> {
+    let (accepted, total) = events
+        .iter()
+        .filter(|e| e.parameter() == parameter)
+        .fold((0, 0), |(accepted, total), e| match e {
+            Event::ProposalAccepted { .. } => (accepted + 1, total + 1),
+            Event::ProposalRejected { .. } => (accepted, total + 1),
+            _ => (accepted, total),
+        });
+    if total == 0 {
+        None
+    } else {
+        Some(accepted as f64 / total as f64)
+    }
+}
@@ -0,0 +1,172 @@
+//! Log evidence by thermodynamic integration
+//!
+//! The log evidence of a model is the integral over `β` from 0 to 1 of the
+//! expected log likelihood under the power posterior `p(θ) L(θ)^β`. Running
+//! a chain at each `β` of a ladder, e.g. with a `likelihood::Tempered`
+//! likelihood, and recording the untempered log likelihood of its draws
+//! gives the traces `thermodynamic_integration` integrates.
+
+use std::io;
+use summary::effective_sample_size;
+use utils::invalid_input;
+
+/// Ladder of `n` temperatures from 0 to 1, spaced as `(i / (n - 1))^5` to
+/// put most of them near 0, where the expected log likelihood changes
+/// fastest
+pub fn power_posterior_ladder(n: usize) -> Vec<f64> {
+    assert!(n > 1, "a ladder requires at least two temperatures.");
+    (0..n)
+        .map(|i| (i as f64 / (n - 1) as f64).powi(5))
+        .collect()
+}
+
+/// Estimate of a log evidence
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LogEvidence {
+    /// Trapezoidal estimate of the integral
+    pub estimate: f64,
+    /// Monte Carlo standard error from the traces' effective sample sizes
+    pub std_error: f64,
+    /// Half the gap between the left and right Riemann sums, which bound
+    /// the integral as the expected log likelihood grows with `β`
+    pub discretization_error: f64,
+}
+
+/// Log evidence from the log likelihoods of draws at each temperature,
+/// given as `(β, trace)` pairs in any order
+///
+/// The temperatures must lie in [0, 1] and include both ends, and every
+/// trace needs at least four finite values; otherwise an `InvalidInput`
+/// error is returned.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # use rmcmc::evidence::thermodynamic_integration;
+/// # fn main() {
+/// // A likelihood whose expected value is -1 at every temperature
+/// let traces = vec![
+///     (0.0, vec![-0.5, -1.5, -0.5, -1.5]),
+///     (1.0, vec![-1.0, -1.0, -1.0, -1.0]),
+/// ];
+/// let evidence = thermodynamic_integration(&traces).unwrap();
+/// assert_eq!(evidence.estimate, -1.0);
+/// # }
+/// ```
+pub fn thermodynamic_integration(
+    traces: &[(f64, Vec<f64>)],
+) -> io::Result<LogEvidence> {
+    let mut points: Vec<(f64, f64, f64)> = Vec::with_capacity(traces.len());
+    for (beta, trace) in traces.iter() {
+        if !(*beta >= 0.0 && *beta <= 1.0) {
+            return Err(invalid_input("temperatures must lie in [0, 1]"));
+        }
+        if trace.len() < 4 || trace.iter().any(|x| !x.is_finite()) {
+            return Err(invalid_input(
+                "traces need at least four finite values",
+            ));
+        }
+        let n = trace.len() as f64;
+        let mean = trace.iter().sum::<f64>() / n;
+        let var = trace.iter().map(|x| (x - mean).powi(2)).sum::<f64>()
+            / (n - 1.0);
+        // A constant trace has no Monte Carlo error.
+        let ess = effective_sample_size(&[trace.clone()]).unwrap_or(n);
+        points.push((*beta, mean, var / ess));
+    }
+    // Every temperature is a number in [0, 1], so they are ordered.
+    points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    match (points.first(), points.last()) {
+        (Some(first), Some(last)) if first.0 == 0.0 && last.0 == 1.0 => (),
+        _ => return Err(invalid_input("temperatures must span 0 to 1")),
+    }
+
+    let mut estimate = 0.0;
+    let mut variance = 0.0;
+    let (mut left, mut right) = (0.0, 0.0);
+    // Trapezoid weight of each point, half of each neighbouring interval
+    let mut weights = vec![0.0; points.len()];
+    for i in 1..points.len() {
+        let width = points[i].0 - points[i - 1].0;
+        estimate += width * (points[i].1 + points[i - 1].1) / 2.0;
+        left += width * points[i - 1].1;
+        right += width * points[i].1;
+        weights[i - 1] += width / 2.0;
+        weights[i] += width / 2.0;
+    }
+    for (w, (_, _, var)) in weights.iter().zip(points.iter()) {
+        variance += w * w * var;
+    }
+
+    Ok(LogEvidence {
+        estimate,
+        std_error: variance.sqrt(),
+        discretization_error: (right - left).abs() / 2.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use likelihood::{DeltaLogLikelihood, Tempered};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+    use std::f64::consts::PI;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[test]
+    fn recovers_the_evidence_of_a_gaussian_mean() {
+        // x_i ~ N(mu, 1) with mu ~ N(0, 1), so the power posterior at beta
+        // is N(beta * sum / (1 + beta * n), 1 / (1 + beta * n)).
+        let mut rng = StdRng::from_seed(SEED);
+        let xs: Vec<f64> =
+            Gaussian::new(1.0, 1.0).unwrap().sample(20, &mut rng);
+        let n = xs.len() as f64;
+        let sum: f64 = xs.iter().sum();
+        let sum_sq: f64 = xs.iter().map(|x| x * x).sum();
+        let ln_l = |mu: f64| -> f64 {
+            xs.iter()
+                .map(|x| Gaussian::new(mu, 1.0).unwrap().ln_f(x))
+                .sum()
+        };
+        let exact = -0.5 * n * (2.0 * PI).ln()
+            - 0.5 * (1.0 + n).ln()
+            - 0.5 * (sum_sq - sum * sum / (1.0 + n));
+
+        let traces: Vec<(f64, Vec<f64>)> = power_posterior_ladder(30)
+            .into_iter()
+            .map(|beta| {
+                let precision = 1.0 + beta * n;
+                let mean = beta * sum / precision;
+                let posterior =
+                    Gaussian::new(mean, precision.powf(-0.5)).unwrap();
+                let mus: Vec<f64> = posterior.sample(2000, &mut rng);
+                (beta, mus.into_iter().map(&ln_l).collect())
+            })
+            .collect();
+
+        let evidence = thermodynamic_integration(&traces).unwrap();
+        let error = (evidence.estimate - exact).abs();
+        let tolerance =
+            4.0 * evidence.std_error + evidence.discretization_error;
+        assert!(error < tolerance);
+        assert!(error < 0.5);
+        assert!(evidence.std_error > 0.0);
+
+        let hot_only: Vec<(f64, Vec<f64>)> = traces[1..].to_vec();
+        assert!(thermodynamic_integration(&hot_only).is_err());
+        for &beta in &[std::f64::NAN, -0.5, 1.5] {
+            let mut bad = traces.clone();
+            bad.push((beta, traces[0].1.clone()));
+            assert!(thermodynamic_integration(&bad).is_err());
+        }
+
+        let tempered = Tempered::new(|mu: &f64| ln_l(*mu), 0.25);
+        assert_eq!(tempered.ln_f(&1.0), 0.25 * ln_l(1.0));
+        let prior = Tempered::new(|_: &f64| std::f64::NEG_INFINITY, 0.0);
+        assert_eq!(prior.ln_f(&1.0), 0.0);
+    }
+}
@@ -0,0 +1,216 @@
+//! Dependency graph between a model's parameters
+
+use std::collections::{BTreeMap, BTreeSet};
+use rand::Rng;
+
+use parameter::ParamId;
+use steppers::SteppingAlg;
+
+/// Directed graph with an edge from each parameter to the parameters whose
+/// priors read it
+///
+/// The edges are metadata declared with `Parameter::depends_on`. A
+/// `Parameter`'s prior is a fixed `Rv` which never reads the model, so the
+/// graph, like `Group::in_dependency_order`, reflects only what users
+/// declare.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # use rmcmc::graph::ModelGraph;
+/// # use rmcmc::parameter::ParamId;
+/// # fn main() {
+/// let id = |s: &str| ParamId(s.to_string());
+///
+/// // y ~ N(mu, sigma), mu ~ N(m0, 1)
+/// let mut graph = ModelGraph::new();
+/// graph.add(id("mu"), vec![id("m0")]);
+/// graph.add(id("y"), vec![id("mu"), id("sigma")]);
+///
+/// assert_eq!(graph.children(&id("mu")), vec![id("y")]);
+/// assert_eq!(graph.dependents(&id("m0")), vec![id("mu"), id("y")]);
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModelGraph {
+    parents: BTreeMap<ParamId, BTreeSet<ParamId>>,
+}
+
+impl ModelGraph {
+    pub fn new() -> Self {
+        ModelGraph {
+            parents: BTreeMap::new(),
+        }
+    }
+
+    /// Graph of the parameters updated by `stepper` and their declared
+    /// dependencies.
+    pub fn from_stepper<M, R, A>(stepper: &A) -> Self
+    where
+        R: Rng,
+        A: SteppingAlg<M, R> + ?Sized,
+    {
+        let mut graph = ModelGraph::new();
+        for (id, dependencies) in stepper.dependencies() {
+            graph.add(id, dependencies);
+        }
+        graph
+    }
+
+    /// Add a parameter whose prior reads `dependencies`. Dependencies not yet
+    /// in the graph are added without parents of their own.
+    pub fn add(&mut self, id: ParamId, dependencies: Vec<ParamId>) {
+        for dependency in dependencies.iter() {
+            self.parents
+                .entry(dependency.clone())
+                .or_default();
+        }
+        self.parents
+            .entry(id)
+            .or_default()
+            .extend(dependencies);
+    }
+
+    /// Every parameter in the graph
+    pub fn parameters(&self) -> Vec<ParamId> {
+        self.parents.keys().cloned().collect()
+    }
+
+    /// Parameters read by `id`'s prior
+    pub fn parents(&self, id: &ParamId) -> Vec<ParamId> {
+        self.parents
+            .get(id)
+            .map(|ps| ps.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Parameters whose priors read `id`
+    pub fn children(&self, id: &ParamId) -> Vec<ParamId> {
+        self.parents
+            .iter()
+            .filter(|(_, ps)| ps.contains(id))
+            .map(|(child, _)| child.clone())
+            .collect()
+    }
+
+    /// Parameters whose priors are affected, directly or indirectly, by a
+    /// change in `id`, i.e. those whose cached scores must be invalidated.
+    pub fn dependents(&self, id: &ParamId) -> Vec<ParamId> {
+        let mut seen = BTreeSet::new();
+        let mut stack = self.children(id);
+        while let Some(next) = stack.pop() {
+            if seen.insert(next.clone()) {
+                stack.extend(self.children(&next));
+            }
+        }
+        seen.into_iter().collect()
+    }
+
+    /// Parameters ordered so each comes after those it depends on, or `None`
+    /// if the dependencies are cyclic.
+    pub fn topological_order(&self) -> Option<Vec<ParamId>> {
+        let mut order: Vec<ParamId> = Vec::with_capacity(self.parents.len());
+        let mut placed: BTreeSet<ParamId> = BTreeSet::new();
+
+        while order.len() < self.parents.len() {
+            let ready: Vec<ParamId> = self
+                .parents
+                .iter()
+                .filter(|(id, ps)| {
+                    !placed.contains(*id)
+                        && ps.iter().all(|p| placed.contains(p))
+                })
+                .map(|(id, _)| id.clone())
+                .collect();
+
+            if ready.is_empty() {
+                return None;
+            }
+            placed.extend(ready.iter().cloned());
+            order.extend(ready);
+        }
+        Some(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use steppers::{Group, SRWM};
+
+    fn id(s: &str) -> ParamId {
+        ParamId(s.to_string())
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    struct Model {
+        mu: f64,
+        theta: f64,
+    }
+
+    fn log_likelihood(_m: &Model) -> f64 {
+        0.0
+    }
+
+    #[test]
+    fn from_stepper_reads_declared_dependencies() {
+        let mu = Parameter::new(
+            "mu".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, mu),
+        );
+        let theta = Parameter::new_dependent_with_deps(
+            "theta".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, theta),
+            vec![id("mu")],
+        );
+
+        let group: Group<Model, StdRng> = Group::new(vec![
+            Box::new(SRWM::new(mu, log_likelihood, Some(1.0)).unwrap()),
+            Box::new(SRWM::new(theta, log_likelihood, Some(1.0)).unwrap()),
+        ]);
+
+        let graph = ModelGraph::from_stepper(&group);
+        assert_eq!(graph.parameters(), vec![id("mu"), id("theta")]);
+        assert_eq!(graph.parents(&id("theta")), vec![id("mu")]);
+        assert_eq!(graph.children(&id("mu")), vec![id("theta")]);
+        assert!(graph.parents(&id("mu")).is_empty());
+    }
+
+    #[test]
+    fn dependents_are_transitive() {
+        let mut graph = ModelGraph::new();
+        graph.add(id("c"), vec![id("b")]);
+        graph.add(id("b"), vec![id("a")]);
+        graph.add(id("d"), vec![id("a")]);
+
+        assert_eq!(
+            graph.dependents(&id("a")),
+            vec![id("b"), id("c"), id("d")]
+        );
+        assert_eq!(graph.dependents(&id("b")), vec![id("c")]);
+        assert!(graph.dependents(&id("c")).is_empty());
+    }
+
+    #[test]
+    fn topological_order() {
+        let mut graph = ModelGraph::new();
+        graph.add(id("y"), vec![id("mu"), id("sigma")]);
+        graph.add(id("mu"), vec![id("m0")]);
+
+        let order = graph.topological_order().unwrap();
+        let position =
+            |name: &str| order.iter().position(|p| p == &id(name)).unwrap();
+        assert!(position("m0") < position("mu"));
+        assert!(position("mu") < position("y"));
+        assert!(position("sigma") < position("y"));
+
+        graph.add(id("m0"), vec![id("y")]);
+        assert_eq!(graph.topological_order(), None);
+    }
+}
@@ -1,3 +1,11 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::Arc;
+use nalgebra::DVector;
+use utils::invalid_input;
+#[cfg(feature = "ndarray")]
+use ndarray::Array1;
+
 /// A container for getting and setting a value in a struct
 ///
 /// # Parameters
@@ -26,30 +34,81 @@
 
 pub struct Lens<T, S> {
     // Getter function
-    pub get_func: fn(&S) -> T,
+    pub get_func: Arc<dyn Fn(&S) -> io::Result<T> + Send + Sync>,
     // Setter function
-    pub set_func: fn(&S, T) -> S,
+    pub set_func: Arc<dyn Fn(&S, T) -> io::Result<S> + Send + Sync>,
 }
 
 impl<T, S> Clone for Lens<T, S> {
     fn clone(&self) -> Self {
-        Lens { ..*self }
+        Lens {
+            get_func: self.get_func.clone(),
+            set_func: self.set_func.clone(),
+        }
     }
 }
 
-impl<T, S> Lens<T, S> {
+impl<T: 'static, S: 'static> Lens<T, S> {
     pub fn new(get: fn(&S) -> T, set: fn(&S, T) -> S) -> Self {
+        Lens::from_fns(get, set)
+    }
+
+    /// Lens from closures, which unlike the functions taken by `new` may
+    /// capture state, e.g. the key of a map entry
+    pub fn from_fns<G, F>(get: G, set: F) -> Self
+    where
+        G: 'static + Fn(&S) -> T + Send + Sync,
+        F: 'static + Fn(&S, T) -> S + Send + Sync,
+    {
+        Lens::fallible(move |s: &S| Ok(get(s)), move |s: &S, x| Ok(set(s, x)))
+    }
+
+    /// Lens which may fail to get or set its value, e.g. as the model has
+    /// no such entry. Steppers reject steps in which their lens fails,
+    /// emitting an `Event::LensFailed`.
+    pub fn fallible<G, F>(get: G, set: F) -> Self
+    where
+        G: 'static + Fn(&S) -> io::Result<T> + Send + Sync,
+        F: 'static + Fn(&S, T) -> io::Result<S> + Send + Sync,
+    {
         Lens {
-            get_func: get,
-            set_func: set,
+            get_func: Arc::new(get),
+            set_func: Arc::new(set),
         }
     }
 
+    /// Lens through this lens's value into a part of it, e.g. an entry of
+    /// a map field
+    pub fn then<U: 'static>(&self, inner: Lens<U, T>) -> Lens<U, S> {
+        let outer = self.clone();
+        let outer_set = self.clone();
+        let inner_set = inner.clone();
+        Lens::fallible(
+            move |s: &S| inner.try_get(&outer.try_get(s)?),
+            move |s: &S, x: U| {
+                let part = inner_set.try_set(&outer_set.try_get(s)?, x)?;
+                outer_set.try_set(s, part)
+            },
+        )
+    }
+}
+
+impl<T, S> Lens<T, S> {
+    /// Copy of `s` with the value set to `x`. Panics if the lens fails.
     pub fn set(&self, s: &S, x: T) -> S {
-        (self.set_func)(&s, x)
+        self.try_set(s, x).unwrap_or_else(|err| panic!("{}", err))
     }
 
+    /// The value in `s`. Panics if the lens fails.
     pub fn get(&self, s: &S) -> T {
+        self.try_get(s).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_set(&self, s: &S, x: T) -> io::Result<S> {
+        (self.set_func)(&s, x)
+    }
+
+    pub fn try_get(&self, s: &S) -> io::Result<T> {
         (self.get_func)(&s)
     }
 
@@ -58,6 +117,106 @@ impl<T, S> Lens<T, S> {
     }
 }
 
+/// Maps from names to values, whose entries lenses can update, for models
+/// whose parameters are only known at runtime
+pub trait FieldMap<T>: Clone {
+    /// The value stored under `key`, if any
+    fn field(&self, key: &str) -> Option<&T>;
+    /// Copy of the map with `value` stored under `key`
+    fn with_field(&self, key: &str, value: T) -> Self;
+}
+
+impl<T: Clone> FieldMap<T> for HashMap<String, T> {
+    fn field(&self, key: &str) -> Option<&T> {
+        self.get(key)
+    }
+
+    fn with_field(&self, key: &str, value: T) -> Self {
+        let mut map = self.clone();
+        map.insert(key.to_string(), value);
+        map
+    }
+}
+
+impl<T: Clone> FieldMap<T> for BTreeMap<String, T> {
+    fn field(&self, key: &str) -> Option<&T> {
+        self.get(key)
+    }
+
+    fn with_field(&self, key: &str, value: T) -> Self {
+        let mut map = self.clone();
+        map.insert(key.to_string(), value);
+        map
+    }
+}
+
+impl<T, S> Lens<T, S>
+where
+    T: 'static + Clone,
+    S: 'static + FieldMap<T>,
+{
+    /// Lens on the entry `key` of maps like `map`, e.g. one coefficient per
+    /// factor level found in the data. Combine with `then` to reach a map
+    /// field of a model.
+    ///
+    /// Fails with `InvalidInput` if `map` has no entry `key`. Getting or
+    /// setting the entry of a map without it fails likewise, rather than
+    /// adding the entry.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate rmcmc;
+    /// # use rmcmc::lens::Lens;
+    /// # use std::collections::HashMap;
+    /// # fn main() {
+    /// #[derive(Clone, Debug)]
+    /// struct Model {
+    ///     effects: HashMap<String, f64>,
+    /// }
+    ///
+    /// let effects: HashMap<String, f64> =
+    ///     vec![("a".to_string(), 0.5), ("b".to_string(), -1.0)]
+    ///         .into_iter()
+    ///         .collect();
+    /// let m = Model { effects };
+    ///
+    /// let effects = Lens::new(
+    ///     |m: &Model| m.effects.clone(),
+    ///     |m: &Model, effects| Model { effects, ..m.clone() },
+    /// );
+    /// let b = effects.then(Lens::map_key("b", &m.effects).unwrap());
+    /// assert_eq!(b.get(&m), -1.0);
+    /// assert_eq!(b.set(&m, 2.0).effects["b"], 2.0);
+    ///
+    /// assert!(Lens::<f64, _>::map_key("c", &m.effects).is_err());
+    /// let without_b = Model { effects: HashMap::new() };
+    /// assert!(b.try_get(&without_b).is_err());
+    /// # }
+    /// ```
+    pub fn map_key(key: &str, map: &S) -> io::Result<Self> {
+        if map.field(key).is_none() {
+            return Err(missing_entry(key));
+        }
+        let get_key = key.to_string();
+        let set_key = key.to_string();
+        Ok(Lens::fallible(
+            move |s: &S| {
+                s.field(&get_key)
+                    .cloned()
+                    .ok_or_else(|| missing_entry(&get_key))
+            },
+            move |s: &S, x: T| match s.field(&set_key) {
+                Some(_) => Ok(s.with_field(&set_key, x)),
+                None => Err(missing_entry(&set_key)),
+            },
+        ))
+    }
+}
+
+fn missing_entry(key: &str) -> io::Error {
+    invalid_input(format!("no entry {} in map", key))
+}
+
 #[macro_export]
 macro_rules! make_lens {
     ($kind: ident, $ptype: ty, $param: ident) => {
@@ -78,6 +237,187 @@ macro_rules! make_lens_clone {
     };
 }
 
+/// A model whose fields are known, e.g. to check every field is sampled
+pub trait McmcModel {
+    /// Names of the model's parameter fields, in declaration order,
+    /// leaving out data
+    fn fields() -> &'static [&'static str];
+}
+
+/// Declare a model struct, generating a lens for each field as an
+/// associated function of the same name and implementing `McmcModel`.
+///
+/// Fields which hold data rather than parameters go in a `data` section
+/// after the struct. They are part of the struct but get no lenses and are
+/// not listed by `McmcModel::fields`, so `Registry::build_complete` does
+/// not expect them to be sampled.
+///
+/// Lenses clone the model to set a field, so the model must be `Clone`,
+/// and clone the field to get it.
+///
+/// # Example
+/// ```
+/// #[macro_use] extern crate rmcmc;
+/// # use rmcmc::lens::McmcModel;
+///
+/// # fn main() {
+/// mcmc_model! {
+///     #[derive(Clone, Debug)]
+///     struct Model {
+///         mu: f64,
+///         sigma: f64,
+///     }
+///     data {
+///         /// Observations
+///         xs: Vec<f64>,
+///     }
+/// }
+///
+/// let m = Model { mu: 0.0, sigma: 1.0, xs: vec![1.0, 2.0] };
+/// let m = Model::mu().set(&m, 1.5);
+/// assert_eq!(Model::mu().get(&m), 1.5);
+/// assert_eq!(Model::fields(), &["mu", "sigma"]);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! mcmc_model {
+    (
+        $(#[$attr: meta])*
+        $vis: vis struct $kind: ident {
+            $(
+                $(#[$fattr: meta])*
+                $fvis: vis $field: ident : $ftype: ty
+            ),* $(,)*
+        }
+        data {
+            $(
+                $(#[$dattr: meta])*
+                $dvis: vis $dfield: ident : $dtype: ty
+            ),* $(,)*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $kind {
+            $(
+                $(#[$fattr])*
+                $fvis $field: $ftype,
+            )*
+            $(
+                $(#[$dattr])*
+                $dvis $dfield: $dtype,
+            )*
+        }
+
+        #[allow(dead_code)]
+        impl $kind {
+            $(
+                pub fn $field() -> $crate::lens::Lens<$ftype, $kind> {
+                    $crate::lens::Lens::new(
+                        |s: &$kind| s.$field.clone(),
+                        |s: &$kind, x: $ftype| {
+                            let mut s = s.clone();
+                            s.$field = x;
+                            s
+                        },
+                    )
+                }
+            )*
+        }
+
+        impl $crate::lens::McmcModel for $kind {
+            fn fields() -> &'static [&'static str] {
+                &[$(stringify!($field)),*]
+            }
+        }
+    };
+    (
+        $(#[$attr: meta])*
+        $vis: vis struct $kind: ident {
+            $(
+                $(#[$fattr: meta])*
+                $fvis: vis $field: ident : $ftype: ty
+            ),* $(,)*
+        }
+    ) => {
+        mcmc_model! {
+            $(#[$attr])*
+            $vis struct $kind {
+                $(
+                    $(#[$fattr])*
+                    $fvis $field: $ftype
+                ),*
+            }
+            data {}
+        }
+    };
+}
+
+/// Vector types which vector steppers can update through a
+/// `Lens<DVector<f64>, S>`, e.g. made with `make_lens_vector!`
+///
+/// Values are copied to and from a `DVector` on every get and set, so
+/// models can keep plain `Vec<f64>` fields, or `ndarray::Array1<f64>` ones
+/// with the `ndarray` feature, at the cost of O(d) copies per step.
+pub trait AsDVector {
+    fn to_dvector(&self) -> DVector<f64>;
+    fn from_dvector(x: DVector<f64>) -> Self;
+}
+
+impl AsDVector for Vec<f64> {
+    fn to_dvector(&self) -> DVector<f64> {
+        DVector::from_column_slice(self.len(), self)
+    }
+
+    fn from_dvector(x: DVector<f64>) -> Self {
+        x.iter().cloned().collect()
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl AsDVector for Array1<f64> {
+    fn to_dvector(&self) -> DVector<f64> {
+        DVector::from_iterator(self.len(), self.iter().cloned())
+    }
+
+    fn from_dvector(x: DVector<f64>) -> Self {
+        x.iter().cloned().collect()
+    }
+}
+
+/// Lens viewing a `Vec<f64>` (or other `AsDVector`) field as a
+/// `DVector<f64>`, so vector steppers can update it.
+///
+/// # Example
+/// ```
+/// #[macro_use] extern crate rmcmc;
+/// # use rmcmc::lens::*;
+///
+/// # fn main() {
+/// struct Foo {
+///     pub bar: Vec<f64>,
+/// }
+///
+/// let lens = make_lens_vector!(Foo, Vec<f64>, bar);
+/// let a = Foo { bar: vec![1.0, 2.0] };
+/// assert_eq!(lens.get(&a)[1], 2.0);
+///
+/// let b = lens.set(&a, lens.get(&a) * 2.0);
+/// assert_eq!(b.bar, vec![2.0, 4.0]);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! make_lens_vector {
+    ($kind: ident, $ptype: ty, $param: ident) => {
+        Lens::new(
+            |s: &$kind| $crate::lens::AsDVector::to_dvector(&(*s).$param),
+            |s: &$kind, x| $kind {
+                $param: <$ptype as $crate::lens::AsDVector>::from_dvector(x),
+                ..*s
+            },
+        )
+    };
+}
+
 #[cfg(test)]
 mod tests {
     //extern crate assert;
@@ -118,4 +458,77 @@ mod tests {
         let b = len.set(&a, 2);
         assert!(b.bar == 2);
     }
+
+    #[test]
+    fn vector_lens_round_trips() {
+        #[derive(Clone, Debug, PartialEq)]
+        struct Foo {
+            pub bar: Vec<f64>,
+            pub baz: i32,
+        }
+
+        let lens = make_lens_vector!(Foo, Vec<f64>, bar);
+        let a = Foo { bar: vec![1.0, 2.0, 3.0], baz: 4 };
+        let expected = DVector::from_column_slice(3, &[1.0, 2.0, 3.0]);
+        assert_eq!(lens.get(&a), expected);
+        assert_eq!(lens.set(&a, lens.get(&a)), a);
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn ndarray_lens_round_trips() {
+        #[derive(Clone, Debug, PartialEq)]
+        struct Foo {
+            pub bar: Array1<f64>,
+        }
+
+        let lens = make_lens_vector!(Foo, Array1<f64>, bar);
+        let a = Foo { bar: Array1::from_vec(vec![1.0, 2.0]) };
+        let b = lens.set(&a, lens.get(&a) * 2.0);
+        assert_eq!(b.bar, Array1::from_vec(vec![2.0, 4.0]));
+    }
+
+    #[test]
+    fn map_key_lenses_update_one_entry() {
+        let levels: BTreeMap<String, f64> =
+            vec![("x".to_string(), 1.0), ("y".to_string(), 2.0)]
+                .into_iter()
+                .collect();
+        let y = Lens::map_key("y", &levels).unwrap();
+        let updated = y.set(&levels, 5.0);
+        assert_eq!(y.get(&updated), 5.0);
+        assert_eq!(updated["x"], 1.0);
+        assert_eq!(levels["y"], 2.0);
+
+        let err = Lens::<f64, _>::map_key("z", &levels).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn mcmc_models_list_parameters_but_not_data() {
+        mcmc_model! {
+            #[derive(Clone)]
+            struct Bare {
+                a: f64,
+                b: i32,
+            }
+        }
+        mcmc_model! {
+            #[derive(Clone)]
+            struct WithData {
+                a: f64,
+            }
+            data {
+                xs: Vec<f64>,
+                n: usize,
+            }
+        }
+
+        assert_eq!(Bare::fields(), &["a", "b"]);
+        assert_eq!(Bare::b().get(&Bare { a: 0.0, b: 3 }), 3);
+        assert_eq!(WithData::fields(), &["a"]);
+        let m = WithData { a: 0.0, xs: vec![1.0], n: 1 };
+        let m = WithData::a().set(&m, 2.0);
+        assert_eq!((m.a, m.xs, m.n), (2.0, vec![1.0], 1));
+    }
 }
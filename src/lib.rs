@@ -4,20 +4,48 @@
 #[cfg(feature = "serde_support")]
 #[macro_use]
 extern crate serde_derive;
+#[cfg(feature = "config")]
+extern crate serde_yaml;
+#[cfg(feature = "config")]
+extern crate toml;
 
 extern crate alga;
 extern crate typenum;
 extern crate nalgebra;
+#[cfg(feature = "ndarray")]
+extern crate ndarray;
 extern crate rand;
+#[cfg(test)]
+extern crate rand_xorshift;
 extern crate reduce;
 extern crate rv;
 extern crate rayon;
+extern crate special;
 
 #[macro_use]
 pub mod lens;
+#[macro_use]
+pub mod cow;
+pub mod cluster;
+pub mod control_variates;
+pub mod dist;
+pub mod distance;
+pub mod events;
+pub mod evidence;
+pub mod graph;
+pub mod likelihood;
+pub mod log_density;
+pub mod metric;
+pub mod notebook;
+pub mod overlap;
 pub mod parameter;
+pub mod ppc;
+pub mod rao_blackwell;
+pub mod stacking;
+pub mod stein;
 pub mod runner;
 pub mod statistics;
 pub mod steppers;
 pub mod summary;
 pub mod utils;
+pub mod validation;
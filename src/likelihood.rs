@@ -1,21 +1,372 @@
-use nalgebra::base::dimension::Dim; 
+use nalgebra::base::dimension::Dim;
 use nalgebra::base::VectorN;
 use nalgebra::DefaultAllocator;
 use nalgebra::allocator::Allocator;
 use std::fmt;
-use common::Model;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use parameter::ParamId;
 
 
 /// Likelihood Calculation
-pub trait Likelihood<M: Model>: Sync + Clone + fmt::Debug {
+pub trait Likelihood<M>: Sync + Clone + fmt::Debug {
     fn ln_f(&self, model: &M) -> f64;
 }
 
 /// Likelihood Calculation with Gradient
-pub trait LikelihoodWithGradient<M: Model>: Likelihood<M>
+pub trait LikelihoodWithGradient<M>: Likelihood<M>
 where
     DefaultAllocator: Allocator<f64, Self::D>
 {
     type D: Dim;
     fn grad_ln_f(&self, model: &M) -> VectorN<f64, Self::D>;
 }
+
+/// Log likelihood which may compute the change between two models cheaply
+///
+/// Steppers call `delta` with the current model, a proposal differing from it
+/// only in the parameter `changed`, and use the result in place of a full
+/// evaluation of the proposal. Returning `None` falls back to `ln_f`.
+///
+/// Every `Fn(&M) -> f64` is a `DeltaLogLikelihood` without a delta, so
+/// closures can be used wherever one is expected.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # use rmcmc::likelihood::DeltaLogLikelihood;
+/// # use rmcmc::parameter::ParamId;
+/// # fn main() {
+/// #[derive(Clone)]
+/// struct Model {
+///     a: f64,
+///     b: f64,
+/// }
+///
+/// // ln f = -a^2 / 2 - b^2 / 2 decomposes over the parameters.
+/// #[derive(Clone)]
+/// struct Decomposed;
+///
+/// impl DeltaLogLikelihood<Model> for Decomposed {
+///     fn ln_f(&self, m: &Model) -> f64 {
+///         -0.5 * (m.a * m.a + m.b * m.b)
+///     }
+///
+///     fn delta(&self, current: &Model, proposed: &Model, changed: &ParamId)
+///         -> Option<f64>
+///     {
+///         match changed.0.as_str() {
+///             "a" => Some(-0.5 * (proposed.a.powi(2) - current.a.powi(2))),
+///             "b" => Some(-0.5 * (proposed.b.powi(2) - current.b.powi(2))),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// let current = Model { a: 1.0, b: 2.0 };
+/// let proposed = Model { a: 3.0, ..current.clone() };
+/// let delta = Decomposed.delta(&current, &proposed, &ParamId("a".into()));
+/// assert_eq!(delta, Some(-4.0));
+/// # }
+/// ```
+pub trait DeltaLogLikelihood<M> {
+    /// Log likelihood of the model
+    fn ln_f(&self, model: &M) -> f64;
+
+    /// Change in log likelihood from `current` to `proposed`, if available.
+    fn delta(
+        &self,
+        _current: &M,
+        _proposed: &M,
+        _changed: &ParamId,
+    ) -> Option<f64> {
+        None
+    }
+}
+
+impl<M, F> DeltaLogLikelihood<M> for F
+where
+    F: Fn(&M) -> f64,
+{
+    fn ln_f(&self, model: &M) -> f64 {
+        self(model)
+    }
+}
+
+/// Log likelihood reading its data from a shared, immutable context rather
+/// than from the model
+///
+/// The data lives behind an `Arc`, so every stepper and chain holding a copy
+/// of this likelihood shares a single copy of the data and models carry only
+/// parameters.
+///
+/// This is as far as the separation of data from models goes for now.
+/// Steppers still take likelihoods of the model alone and a `Runner` holds
+/// no data, so there is no `Runner::with_data` passing `&D` to every
+/// likelihood call: that needs `SteppingAlg::step` to take the data, which
+/// would break every stepper. Until then each stepper's likelihood holds a
+/// handle to the shared data, as below.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # use std::sync::Arc;
+/// # use rmcmc::likelihood::{DataLogLikelihood, DeltaLogLikelihood};
+/// # fn main() {
+/// struct Model {
+///     mu: f64,
+/// }
+///
+/// let data = Arc::new(vec![0.5, 1.5, 1.0]);
+/// let ln_f = |m: &Model, xs: &Vec<f64>| {
+///     xs.iter().map(|x| -0.5 * (x - m.mu) * (x - m.mu)).sum()
+/// };
+/// let log_likelihood = DataLogLikelihood::new(data, ln_f);
+///
+/// assert_eq!(log_likelihood.ln_f(&Model { mu: 1.0 }), -0.25);
+/// # }
+/// ```
+pub struct DataLogLikelihood<M, D, F>
+where
+    F: Fn(&M, &D) -> f64,
+{
+    data: Arc<D>,
+    func: F,
+    phantom_m: PhantomData<fn(&M)>,
+}
+
+impl<M, D, F> DataLogLikelihood<M, D, F>
+where
+    F: Fn(&M, &D) -> f64,
+{
+    pub fn new(data: Arc<D>, func: F) -> Self {
+        DataLogLikelihood {
+            data,
+            func,
+            phantom_m: PhantomData,
+        }
+    }
+
+    /// The shared data
+    pub fn data(&self) -> &Arc<D> {
+        &self.data
+    }
+}
+
+impl<M, D, F> Clone for DataLogLikelihood<M, D, F>
+where
+    F: Fn(&M, &D) -> f64 + Clone,
+{
+    fn clone(&self) -> Self {
+        DataLogLikelihood::new(self.data.clone(), self.func.clone())
+    }
+}
+
+impl<M, D, F> fmt::Debug for DataLogLikelihood<M, D, F>
+where
+    F: Fn(&M, &D) -> f64,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DataLogLikelihood {{ }}")
+    }
+}
+
+impl<M, D, F> DeltaLogLikelihood<M> for DataLogLikelihood<M, D, F>
+where
+    F: Fn(&M, &D) -> f64,
+{
+    fn ln_f(&self, model: &M) -> f64 {
+        (self.func)(model, &self.data)
+    }
+}
+
+/// Log likelihood raised to the power `beta`, giving a stepper the power
+/// posterior `p(θ) L(θ)^β` between the prior at 0 and the posterior at 1
+///
+/// Runs at a ladder of `beta`s give the traces `evidence::
+/// thermodynamic_integration` estimates the log evidence from.
+#[derive(Clone, Debug)]
+pub struct Tempered<L> {
+    pub log_likelihood: L,
+    pub beta: f64,
+}
+
+impl<L> Tempered<L> {
+    pub fn new(log_likelihood: L, beta: f64) -> Self {
+        assert!(
+            beta >= 0.0 && beta <= 1.0,
+            "beta must be between zero and one."
+        );
+        Tempered {
+            log_likelihood,
+            beta,
+        }
+    }
+}
+
+impl<M, L: DeltaLogLikelihood<M>> DeltaLogLikelihood<M> for Tempered<L> {
+    fn ln_f(&self, model: &M) -> f64 {
+        // Zero rather than NaN for impossible models at beta = 0
+        if self.beta == 0.0 {
+            return 0.0;
+        }
+        self.beta * self.log_likelihood.ln_f(model)
+    }
+
+    fn delta(
+        &self,
+        current: &M,
+        proposed: &M,
+        changed: &ParamId,
+    ) -> Option<f64> {
+        if self.beta == 0.0 {
+            return Some(0.0);
+        }
+        self.log_likelihood
+            .delta(current, proposed, changed)
+            .map(|d| self.beta * d)
+    }
+}
+
+/// Log likelihood of a type which can be named, holding any other behind an
+/// `Arc`
+///
+/// Steppers are generic over their likelihood, so one built from a closure
+/// has a type which cannot be written down. Wrapping the closure gives a
+/// stepper a type that can be returned from a function or stored in a
+/// struct, and clones of it share the one closure.
+///
+/// # Example
+/// ```
+/// # #[macro_use] extern crate rmcmc;
+/// # extern crate rv;
+/// # use std::sync::Arc;
+/// # use rmcmc::lens::*;
+/// # use rmcmc::likelihood::{DeltaLogLikelihood, SharedLogLikelihood};
+/// # use rmcmc::parameter::Parameter;
+/// # use rmcmc::steppers::SRWM;
+/// # use rv::dist::Gaussian;
+/// #[derive(Clone, Copy, Debug)]
+/// struct Model {
+///     mu: f64,
+/// }
+///
+/// type Stepper =
+///     SRWM<Gaussian, f64, f64, Model, SharedLogLikelihood<Model>>;
+///
+/// fn stepper(data: Arc<Vec<f64>>) -> Stepper {
+///     let log_likelihood = SharedLogLikelihood::new(move |m: &Model| {
+///         data.iter().map(|x| -0.5 * (x - m.mu) * (x - m.mu)).sum()
+///     });
+///     let mu = Parameter::new(
+///         "mu".to_string(),
+///         Gaussian::new(0.0, 1.0).unwrap(),
+///         make_lens!(Model, f64, mu),
+///     );
+///     SRWM::new(mu, log_likelihood, None).unwrap()
+/// }
+///
+/// # fn main() {
+/// let stepper = stepper(Arc::new(vec![0.5, 1.5]));
+/// assert_eq!(stepper.log_likelihood.ln_f(&Model { mu: 1.0 }), -0.25);
+/// # }
+/// ```
+pub struct SharedLogLikelihood<M> {
+    inner: Arc<dyn DeltaLogLikelihood<M> + Send + Sync>,
+}
+
+impl<M> SharedLogLikelihood<M> {
+    pub fn new<L>(log_likelihood: L) -> Self
+    where
+        L: DeltaLogLikelihood<M> + Send + Sync + 'static,
+    {
+        SharedLogLikelihood {
+            inner: Arc::new(log_likelihood),
+        }
+    }
+}
+
+impl<M> Clone for SharedLogLikelihood<M> {
+    fn clone(&self) -> Self {
+        SharedLogLikelihood {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<M> fmt::Debug for SharedLogLikelihood<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedLogLikelihood {{ }}")
+    }
+}
+
+impl<M> DeltaLogLikelihood<M> for SharedLogLikelihood<M> {
+    fn ln_f(&self, model: &M) -> f64 {
+        self.inner.ln_f(model)
+    }
+
+    fn delta(
+        &self,
+        current: &M,
+        proposed: &M,
+        changed: &ParamId,
+    ) -> Option<f64> {
+        self.inner.delta(current, proposed, changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use runner::Runner;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+    use steppers::SRWM;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Copy, Clone, Debug)]
+    struct Model {
+        mu: f64,
+    }
+
+    fn gaussian_ln_f(m: &Model, xs: &Vec<f64>) -> f64 {
+        let g = Gaussian::new(m.mu, 1.0).unwrap();
+        xs.iter().map(|x| g.ln_f(x)).sum()
+    }
+
+    #[test]
+    fn steppers_share_one_copy_of_the_data() {
+        let mut rng = StdRng::from_seed(SEED);
+        let data: Arc<Vec<f64>> =
+            Arc::new(Gaussian::new(2.0, 1.0).unwrap().sample(50, &mut rng));
+        let log_likelihood =
+            DataLogLikelihood::new(data.clone(), gaussian_ln_f);
+
+        let m = Model { mu: 1.0 };
+        assert_eq!(log_likelihood.ln_f(&m), gaussian_ln_f(&m, &data));
+
+        let parameter = Parameter::new(
+            "mu".to_string(),
+            Gaussian::new(0.0, 10.0).unwrap(),
+            make_lens!(Model, f64, mu),
+        );
+        let alg = SRWM::new(parameter, log_likelihood, Some(0.5)).unwrap();
+        let results = Runner::new(alg.clone())
+            .warmup(100)
+            .samples(200)
+            .chains(2)
+            .run(&mut rng, Model { mu: 0.0 });
+
+        // Only the likelihood's handle, no copies, refers to the data.
+        assert_eq!(Arc::strong_count(&data), 2);
+        assert!(alg.log_likelihood.data().len() == 50);
+
+        let mean: f64 = results[0].iter().map(|m| m.mu).sum::<f64>() / 200.0;
+        assert!((mean - 2.0).abs() < 0.5);
+    }
+}
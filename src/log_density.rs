@@ -0,0 +1,127 @@
+//! Log densities with explicit rules for non-finite values
+//!
+//! Scores of models are sums of log priors and log likelihoods, and
+//! steppers accept proposals by the difference of two scores. Either can
+//! be non-finite: `-inf` outside a prior's support, `NaN` from a likelihood
+//! evaluated where it is undefined. `LogDensity` gives every stepper the
+//! same rules for combining and comparing them:
+//!
+//! * A sum with an undefined (`NaN`) term is undefined.
+//! * Otherwise a sum with a `-inf` term is `-inf`, even if another term is
+//!   `+inf`: a model outside a prior's support has zero density whatever
+//!   its likelihood.
+//! * The log ratio of two densities is undefined if either is undefined,
+//!   if both are zero (a chain stuck outside the support) or if both are
+//!   infinite. Otherwise it is the difference of the two, with a zero
+//!   proposal giving `-inf` and a zero current density `+inf`.
+//!
+//! An undefined ratio is `NaN`, which `metropolis_select` always rejects
+//! and `EventSink::emit_update` reports as a `NumericalWarning`.
+
+use std::ops::Add;
+
+/// The log of a density or mass, e.g. the score of a model
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct LogDensity(pub f64);
+
+impl LogDensity {
+    pub fn value(&self) -> f64 {
+        self.0
+    }
+
+    /// Whether the density is zero, e.g. outside a prior's support
+    pub fn is_zero(&self) -> bool {
+        self.0 == ::std::f64::NEG_INFINITY
+    }
+
+    /// Whether the density is undefined, i.e. `NaN`
+    pub fn is_undefined(&self) -> bool {
+        self.0.is_nan()
+    }
+
+    /// Log of the ratio of this density to `current`, the log acceptance
+    /// ratio of a proposal with this density under a symmetric kernel
+    pub fn ratio(&self, current: LogDensity) -> f64 {
+        let (proposed, current) = (self.0, current.0);
+        // Undefined when either density is, or when both are the same
+        // infinity, whose difference says nothing about their ratio.
+        if proposed.is_nan()
+            || current.is_nan()
+            || (proposed.is_infinite() && proposed == current)
+        {
+            ::std::f64::NAN
+        } else {
+            // Differences of a non-finite and a finite value, or of two
+            // infinities of opposite signs, are already as the rules say.
+            proposed - current
+        }
+    }
+
+    /// Add the log density computed by `other`, without computing it when
+    /// this density already decides the sum, e.g. to skip evaluating a
+    /// likelihood outside the prior's support
+    pub fn plus<F>(self, other: F) -> LogDensity
+    where
+        F: FnOnce() -> f64,
+    {
+        if self.is_zero() || self.is_undefined() {
+            self
+        } else {
+            self + LogDensity(other())
+        }
+    }
+}
+
+impl Add for LogDensity {
+    type Output = LogDensity;
+
+    fn add(self, other: LogDensity) -> LogDensity {
+        if self.is_undefined() || other.is_undefined() {
+            LogDensity(::std::f64::NAN)
+        } else if self.is_zero() || other.is_zero() {
+            LogDensity(::std::f64::NEG_INFINITY)
+        } else {
+            LogDensity(self.0 + other.0)
+        }
+    }
+}
+
+impl From<f64> for LogDensity {
+    fn from(x: f64) -> Self {
+        LogDensity(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::{INFINITY, NAN, NEG_INFINITY};
+
+    fn d(x: f64) -> LogDensity {
+        LogDensity(x)
+    }
+
+    #[test]
+    fn sums_follow_the_rules() {
+        assert_eq!(d(1.0) + d(2.0), d(3.0));
+        assert!((d(NAN) + d(NEG_INFINITY)).is_undefined());
+        assert!((d(NEG_INFINITY) + d(INFINITY)).is_zero());
+        assert!((d(INFINITY) + d(NEG_INFINITY)).is_zero());
+        assert_eq!(d(INFINITY) + d(1.0), d(INFINITY));
+        assert_eq!(d(1.0).plus(|| 2.0), d(3.0));
+        assert!(d(NEG_INFINITY).plus(|| panic!("not skipped")).is_zero());
+    }
+
+    #[test]
+    fn ratios_follow_the_rules() {
+        assert_eq!(d(1.0).ratio(d(3.0)), -2.0);
+        assert_eq!(d(NEG_INFINITY).ratio(d(0.0)), NEG_INFINITY);
+        assert_eq!(d(0.0).ratio(d(NEG_INFINITY)), INFINITY);
+        assert_eq!(d(INFINITY).ratio(d(NEG_INFINITY)), INFINITY);
+        assert_eq!(d(NEG_INFINITY).ratio(d(INFINITY)), NEG_INFINITY);
+        assert!(d(NEG_INFINITY).ratio(d(NEG_INFINITY)).is_nan());
+        assert!(d(INFINITY).ratio(d(INFINITY)).is_nan());
+        assert!(d(NAN).ratio(d(0.0)).is_nan());
+        assert!(d(0.0).ratio(d(NAN)).is_nan());
+    }
+}
@@ -0,0 +1,232 @@
+//! Distances between models
+//!
+//! Diagnostics usually look at the trace of one scalar at a time. Given a
+//! distance between whole models, `esjd` measures how far a chain moves per
+//! step and `distance_ratio` compares how far apart chains are with how far
+//! each wanders, without choosing which quantities to trace.
+
+use nalgebra::DVector;
+use lens::Lens;
+
+/// A distance between two models
+pub trait ModelMetric<M> {
+    fn distance(&self, a: &M, b: &M) -> f64;
+}
+
+impl<M, F> ModelMetric<M> for F
+where
+    F: Fn(&M, &M) -> f64,
+{
+    fn distance(&self, a: &M, b: &M) -> f64 {
+        self(a, b)
+    }
+}
+
+/// Euclidean distance over `f64` and `DVector<f64>` fields of a model read
+/// through lenses, each scaled by a weight
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate nalgebra;
+/// # use rmcmc::lens::Lens;
+/// # use rmcmc::metric::{LensMetric, ModelMetric};
+/// # use nalgebra::DVector;
+/// # fn main() {
+/// #[derive(Clone, Debug)]
+/// struct Model {
+///     mu: f64,
+///     x: DVector<f64>,
+/// }
+///
+/// let mu = Lens::new(
+///     |m: &Model| m.mu,
+///     |m: &Model, mu| Model { mu, ..m.clone() },
+/// );
+/// let x = Lens::new(
+///     |m: &Model| m.x.clone(),
+///     |m: &Model, x| Model { x, ..m.clone() },
+/// );
+/// let metric = LensMetric::new().scalar(mu, 1.0).vector(x, 1.0);
+/// let a = Model { mu: 0.0, x: DVector::from_element(2, 0.0) };
+/// let b = Model { mu: 2.0, x: DVector::from_element(2, 2.0) };
+/// assert_eq!(metric.distance(&a, &b), 12f64.sqrt());
+/// # }
+/// ```
+pub struct LensMetric<M> {
+    scalars: Vec<(Lens<f64, M>, f64)>,
+    vectors: Vec<(Lens<DVector<f64>, M>, f64)>,
+}
+
+impl<M> Clone for LensMetric<M> {
+    fn clone(&self) -> Self {
+        LensMetric {
+            scalars: self.scalars.clone(),
+            vectors: self.vectors.clone(),
+        }
+    }
+}
+
+impl<M> Default for LensMetric<M> {
+    fn default() -> Self {
+        LensMetric {
+            scalars: Vec::new(),
+            vectors: Vec::new(),
+        }
+    }
+}
+
+impl<M> LensMetric<M> {
+    /// A metric over no fields, under which every model is at distance 0
+    pub fn new() -> Self {
+        LensMetric::default()
+    }
+
+    /// Include the field read by `lens`, its differences scaled by
+    /// `weight`, e.g. one over its posterior standard deviation.
+    pub fn scalar(&self, lens: Lens<f64, M>, weight: f64) -> Self {
+        let mut metric = self.clone();
+        metric.scalars.push((lens, weight));
+        metric
+    }
+
+    /// Include the vector field read by `lens`, its differences scaled by
+    /// `weight`.
+    pub fn vector(&self, lens: Lens<DVector<f64>, M>, weight: f64) -> Self {
+        let mut metric = self.clone();
+        metric.vectors.push((lens, weight));
+        metric
+    }
+}
+
+impl<M> ModelMetric<M> for LensMetric<M> {
+    fn distance(&self, a: &M, b: &M) -> f64 {
+        let scalars: f64 = self
+            .scalars
+            .iter()
+            .map(|(lens, w)| (w * (lens.get(a) - lens.get(b))).powi(2))
+            .sum();
+        let vectors: f64 = self
+            .vectors
+            .iter()
+            .map(|(lens, w)| {
+                (lens.get(a) - lens.get(b)).norm_squared() * w * w
+            })
+            .sum();
+        (scalars + vectors).sqrt()
+    }
+}
+
+/// Expected squared jumping distance of `chain`, the mean squared distance
+/// between consecutive draws, if it has more than one draw
+///
+/// Larger is better: it grows with the acceptance rate and the size of
+/// accepted moves, so it is a common target when tuning proposals.
+pub fn esjd<M, D: ModelMetric<M>>(chain: &[M], metric: &D) -> Option<f64> {
+    if chain.len() < 2 {
+        return None;
+    }
+    let total: f64 = chain
+        .windows(2)
+        .map(|w| metric.distance(&w[0], &w[1]).powi(2))
+        .sum();
+    Some(total / (chain.len() - 1) as f64)
+}
+
+/// Ratio of the mean distance between draws of different chains at the
+/// same iteration to the mean distance between draws of one chain half its
+/// length apart
+///
+/// Near 1 when the chains have mixed, as draws of different chains are
+/// then no further apart than distant draws of the same chain; well above 1
+/// while chains are stuck in different regions. Requires at least two
+/// chains of at least two draws, truncating them to the shortest.
+pub fn distance_ratio<M, D>(chains: &[Vec<M>], metric: &D) -> Option<f64>
+where
+    D: ModelMetric<M>,
+{
+    let n = chains.iter().map(|c| c.len()).min()?;
+    if chains.len() < 2 || n < 2 {
+        return None;
+    }
+    let half = n / 2;
+
+    let mut between = 0.0;
+    let mut n_between = 0;
+    for t in 0..n {
+        for i in 0..chains.len() {
+            for j in (i + 1)..chains.len() {
+                between += metric.distance(&chains[i][t], &chains[j][t]);
+                n_between += 1;
+            }
+        }
+    }
+
+    let mut within = 0.0;
+    let mut n_within = 0;
+    for chain in chains.iter() {
+        for t in 0..(n - half) {
+            within += metric.distance(&chain[t], &chain[t + half]);
+            n_within += 1;
+        }
+    }
+
+    let within = within / n_within as f64;
+    if within > 0.0 {
+        Some((between / n_between as f64) / within)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Clone, Debug)]
+    struct Model {
+        x: f64,
+    }
+
+    #[test]
+    fn esjd_of_a_known_path() {
+        let metric = LensMetric::new().scalar(make_lens!(Model, f64, x), 2.0);
+        let chain: Vec<Model> =
+            [0.0, 1.0, 1.0, 3.0].iter().map(|&x| Model { x }).collect();
+        // Weighted squared jumps of 4, 0 and 16
+        assert_eq!(esjd(&chain, &metric), Some(20.0 / 3.0));
+        assert_eq!(esjd(&chain[..1], &metric), None);
+
+        let closure = |a: &Model, b: &Model| (a.x - b.x).abs();
+        assert_eq!(esjd(&chain, &closure), Some(5.0 / 3.0));
+    }
+
+    #[test]
+    fn distance_ratio_detects_separated_chains() {
+        let mut rng = StdRng::from_seed(SEED);
+        let metric = |a: &Model, b: &Model| (a.x - b.x).abs();
+        let draws = |mean: f64, rng: &mut StdRng| -> Vec<Model> {
+            Gaussian::new(mean, 1.0)
+                .unwrap()
+                .sample(500, rng)
+                .into_iter()
+                .map(|x| Model { x })
+                .collect()
+        };
+
+        let mixed = vec![draws(0.0, &mut rng), draws(0.0, &mut rng)];
+        let ratio = distance_ratio(&mixed, &metric).unwrap();
+        assert!((ratio - 1.0).abs() < 0.1);
+
+        let separated = vec![draws(0.0, &mut rng), draws(5.0, &mut rng)];
+        assert!(distance_ratio(&separated, &metric).unwrap() > 2.0);
+
+        assert_eq!(distance_ratio(&mixed[..1], &metric), None);
+    }
+}
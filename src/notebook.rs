@@ -0,0 +1,366 @@
+//! Display helpers for Jupyter notebooks running the evcxr kernel
+//!
+//! evcxr renders any value with an `evcxr_display` method as the content it
+//! prints between its begin and end markers. Summary tables render as HTML
+//! and trace plots as inline SVG.
+
+use std::fmt;
+
+use ppc::PredictiveCheck;
+use runner::Sample;
+
+fn evcxr_print(mime: &str, content: &str) {
+    println!("EVCXR_BEGIN_CONTENT {}\n{}\nEVCXR_END_CONTENT", mime, content);
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Posterior summary of one quantity over every draw of a sample
+///
+/// NaN and infinite draws are left out of the statistics and counted in
+/// `n_non_finite`. The statistics are `None` when no finite draws remain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParameterSummary {
+    pub name: String,
+    pub mean: Option<f64>,
+    pub sd: Option<f64>,
+    /// 5%, 50% and 95% quantiles
+    pub quantiles: Option<[f64; 3]>,
+    /// Number of finite draws summarized
+    pub n_draws: usize,
+    /// Number of NaN or infinite draws left out
+    pub n_non_finite: usize,
+}
+
+impl ParameterSummary {
+    /// Summarize the finite `values`.
+    pub fn new(name: String, values: &[f64]) -> Self {
+        let mut sorted: Vec<f64> =
+            values.iter().cloned().filter(|x| x.is_finite()).collect();
+        let n_non_finite = values.len() - sorted.len();
+        if sorted.is_empty() {
+            return ParameterSummary {
+                name,
+                mean: None,
+                sd: None,
+                quantiles: None,
+                n_draws: 0,
+                n_non_finite,
+            };
+        }
+        let n = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / n;
+        let var = sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>()
+            / (n - 1.0).max(1.0);
+
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let quantile = |q: f64| {
+            let i = (q * (sorted.len() - 1) as f64).round() as usize;
+            sorted[i]
+        };
+
+        ParameterSummary {
+            name,
+            mean: Some(mean),
+            sd: Some(var.sqrt()),
+            quantiles: Some([quantile(0.05), quantile(0.5), quantile(0.95)]),
+            n_draws: sorted.len(),
+            n_non_finite,
+        }
+    }
+
+    /// The 5%, 50% and 95% quantiles, each `None` without finite draws
+    pub fn quantile_values(&self) -> [Option<f64>; 3] {
+        match self.quantiles {
+            Some([q5, q50, q95]) => [Some(q5), Some(q50), Some(q95)],
+            None => [None; 3],
+        }
+    }
+}
+
+// A statistic with four decimals, or "-" if there is none
+fn format_statistic(x: Option<f64>) -> String {
+    x.map_or_else(|| "-".to_string(), |x| format!("{:.4}", x))
+}
+
+/// Table of posterior summaries, shown as text with `Display` and as HTML
+/// in notebooks
+#[derive(Clone, Debug, PartialEq)]
+pub struct SummaryTable {
+    pub rows: Vec<ParameterSummary>,
+}
+
+impl SummaryTable {
+    /// HTML table with one row per quantity
+    pub fn to_html(&self) -> String {
+        let rows: String = self
+            .rows
+            .iter()
+            .map(|r| {
+                let q = r.quantile_values();
+                format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td>\
+                     <td>{}</td><td>{}</td><td>{}</td>\
+                     <td>{}</td></tr>",
+                    escape(&r.name),
+                    format_statistic(r.mean),
+                    format_statistic(r.sd),
+                    format_statistic(q[0]),
+                    format_statistic(q[1]),
+                    format_statistic(q[2]),
+                    r.n_draws
+                )
+            })
+            .collect();
+        format!(
+            "<table><thead><tr><th>parameter</th><th>mean</th><th>sd</th>\
+             <th>5%</th><th>50%</th><th>95%</th><th>draws</th></tr>\
+             </thead><tbody>{}</tbody></table>",
+            rows
+        )
+    }
+
+    /// Render in an evcxr notebook.
+    pub fn evcxr_display(&self) {
+        evcxr_print("text/html", &self.to_html());
+    }
+}
+
+impl fmt::Display for SummaryTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8}",
+            "parameter", "mean", "sd", "5%", "50%", "95%", "draws"
+        )?;
+        for r in self.rows.iter() {
+            let q = r.quantile_values();
+            writeln!(
+                f,
+                "{:<12} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8}",
+                r.name,
+                format_statistic(r.mean),
+                format_statistic(r.sd),
+                format_statistic(q[0]),
+                format_statistic(q[1]),
+                format_statistic(q[2]),
+                r.n_draws
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<M> Sample<M> {
+    /// Summarize the named quantities over every draw.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate rmcmc;
+    /// # use rmcmc::runner::Sample;
+    /// # fn main() {
+    /// let sample = Sample::new(vec![], vec![vec![1.0, 2.0], vec![3.0]]);
+    /// let table = sample.summary_table(&[("x", |x: &f64| *x)]);
+    ///
+    /// assert_eq!(table.rows[0].mean, Some(2.0));
+    /// assert!(table.to_html().starts_with("<table>"));
+    /// # }
+    /// ```
+    pub fn summary_table(&self, quantities: &[(&str, fn(&M) -> f64)])
+        -> SummaryTable
+    {
+        let rows = quantities
+            .iter()
+            .map(|(name, f)| {
+                let values: Vec<f64> = self.draws().map(f).collect();
+                ParameterSummary::new(name.to_string(), &values)
+            })
+            .collect();
+        SummaryTable { rows }
+    }
+
+    /// Trace plot of a quantity for every chain.
+    pub fn trace_plot(&self, f: fn(&M) -> f64) -> TracePlot {
+        TracePlot::new(
+            self.chains
+                .iter()
+                .map(|chain| chain.iter().map(f).collect())
+                .collect(),
+        )
+    }
+}
+
+/// Line plot of each chain's values against the draw index
+#[derive(Clone, Debug, PartialEq)]
+pub struct TracePlot {
+    pub traces: Vec<Vec<f64>>,
+    pub width: usize,
+    pub height: usize,
+}
+
+const TRACE_COLORS: [&str; 6] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b",
+];
+
+impl TracePlot {
+    pub fn new(traces: Vec<Vec<f64>>) -> Self {
+        TracePlot {
+            traces,
+            width: 600,
+            height: 200,
+        }
+    }
+
+    pub fn size(&self, width: usize, height: usize) -> Self {
+        TracePlot {
+            width,
+            height,
+            ..(*self).clone()
+        }
+    }
+
+    /// SVG image of the plot
+    pub fn to_svg(&self) -> String {
+        let values = self.traces.iter().flat_map(|t| t.iter().cloned());
+        let (lo, hi) = values.fold(
+            (std::f64::INFINITY, std::f64::NEG_INFINITY),
+            |(lo, hi), x| (lo.min(x), hi.max(x)),
+        );
+        let span = if hi > lo { hi - lo } else { 1.0 };
+        let len = self.traces.iter().map(|t| t.len()).max().unwrap_or(0);
+        let dx = self.width as f64 / (len.max(2) - 1) as f64;
+        let height = self.height as f64;
+
+        let lines: String = self
+            .traces
+            .iter()
+            .enumerate()
+            .map(|(i, trace)| {
+                let points: Vec<String> = trace
+                    .iter()
+                    .enumerate()
+                    .map(|(j, x)| {
+                        let y = height * (1.0 - (x - lo) / span);
+                        format!("{:.1},{:.1}", j as f64 * dx, y)
+                    })
+                    .collect();
+                format!(
+                    "<polyline fill=\"none\" stroke=\"{}\" \
+                     stroke-width=\"1\" points=\"{}\"/>",
+                    TRACE_COLORS[i % TRACE_COLORS.len()],
+                    points.join(" ")
+                )
+            })
+            .collect();
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" \
+             height=\"{}\">{}</svg>",
+            self.width, self.height, lines
+        )
+    }
+
+    /// Render in an evcxr notebook.
+    pub fn evcxr_display(&self) {
+        evcxr_print("image/svg+xml", &self.to_svg());
+    }
+}
+
+impl PredictiveCheck {
+    /// HTML table row of this check
+    fn to_html_row(&self) -> String {
+        format!(
+            "<tr><td>{}</td><td>{:.4}</td><td>{:.4}</td><td>{:.3}</td></tr>",
+            escape(&self.name),
+            self.observed,
+            self.replicated_mean(),
+            self.p_value
+        )
+    }
+
+    /// Render in an evcxr notebook.
+    pub fn evcxr_display(&self) {
+        evcxr_print("text/html", &checks_html(&[self.clone()]));
+    }
+}
+
+/// HTML table of posterior predictive checks, one row per statistic
+pub fn checks_html(checks: &[PredictiveCheck]) -> String {
+    let rows: String = checks.iter().map(|c| c.to_html_row()).collect();
+    format!(
+        "<table><thead><tr><th>statistic</th><th>observed</th>\
+         <th>replicated mean</th><th>p</th></tr></thead>\
+         <tbody>{}</tbody></table>",
+        rows
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Sample<(f64, f64)> {
+        Sample::new(
+            vec![],
+            vec![
+                vec![(0.0, 1.0), (1.0, 2.0), (2.0, 3.0)],
+                vec![(3.0, 4.0), (4.0, 5.0)],
+            ],
+        )
+    }
+
+    #[test]
+    fn summary_table_over_every_chain() {
+        let table =
+            sample().summary_table(&[("a", |m| m.0), ("a<b", |m| m.1)]);
+        assert_eq!(table.rows.len(), 2);
+
+        let a = &table.rows[0];
+        assert_eq!(a.mean, Some(2.0));
+        assert!((a.sd.unwrap() - 2.5f64.sqrt()).abs() < 1E-12);
+        assert_eq!(a.quantiles, Some([0.0, 2.0, 4.0]));
+        assert_eq!(a.n_draws, 5);
+        assert_eq!(a.n_non_finite, 0);
+
+        let html = table.to_html();
+        assert_eq!(html.matches("<tr>").count(), 3);
+        assert!(html.contains("<td>a&lt;b</td>"));
+        assert_eq!(table.to_string().lines().count(), 3);
+    }
+
+    #[test]
+    fn non_finite_draws_are_left_out() {
+        let nan = std::f64::NAN;
+        let values = [1.0, nan, 3.0, std::f64::INFINITY, 2.0];
+        let summary = ParameterSummary::new("x".to_string(), &values);
+        assert_eq!(summary.mean, Some(2.0));
+        assert_eq!(summary.quantiles, Some([1.0, 2.0, 3.0]));
+        assert_eq!(summary.n_draws, 3);
+        assert_eq!(summary.n_non_finite, 2);
+
+        let empty = ParameterSummary::new("x".to_string(), &[nan]);
+        assert_eq!(empty.mean, None);
+        assert_eq!(empty.sd, None);
+        assert_eq!(empty.quantiles, None);
+        assert_eq!(empty.n_non_finite, 1);
+        assert_eq!(ParameterSummary::new("x".to_string(), &[]).n_draws, 0);
+
+        let table = SummaryTable { rows: vec![empty] };
+        assert!(table.to_string().lines().nth(1).unwrap().contains(" - "));
+        assert!(table.to_html().contains("<td>-</td>"));
+    }
+
+    #[test]
+    fn trace_plot_draws_a_line_per_chain() {
+        let svg = sample().trace_plot(|m| m.0).size(100, 50).to_svg();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("width=\"100\""));
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        // The extremes map to the top and bottom of the plot.
+        assert!(svg.contains("0.0,50.0"));
+        assert!(svg.contains("50.0,0.0"));
+    }
+}
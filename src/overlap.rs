@@ -0,0 +1,236 @@
+//! Prior-posterior overlap
+//!
+//! How much the data inform each quantity of a model, measured by comparing
+//! its draws from the prior with its draws from the posterior. A posterior
+//! overlapping its prior almost entirely means the quantity is determined by
+//! the prior rather than the data, which is worth knowing before reading
+//! anything into its estimate.
+
+use std::fmt;
+use distance::wasserstein;
+use runner::Sample;
+
+/// Points at which densities are evaluated to integrate their overlap
+const GRID_POINTS: usize = 512;
+
+fn mean_sd(x: &[f64]) -> (f64, f64) {
+    let n = x.len() as f64;
+    let mean = x.iter().sum::<f64>() / n;
+    let var = x.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, var.sqrt())
+}
+
+fn sorted(x: &[f64]) -> Vec<f64> {
+    let mut x = x.to_vec();
+    x.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    x
+}
+
+/// Silverman's rule of thumb bandwidth of a Gaussian kernel density estimate
+/// of the sorted draws `x`
+fn bandwidth(x: &[f64]) -> f64 {
+    let n = x.len();
+    let (_, sd) = mean_sd(x);
+    let iqr = x[(3 * n) / 4] - x[n / 4];
+    let spread = if iqr > 0.0 { sd.min(iqr / 1.34) } else { sd };
+    0.9 * spread * (n as f64).powf(-0.2)
+}
+
+fn kde(x: &[f64], h: f64, at: f64) -> f64 {
+    let norm = x.len() as f64 * h * (2.0 * ::std::f64::consts::PI).sqrt();
+    x.iter()
+        .map(|xi| (-0.5 * ((at - xi) / h).powi(2)).exp())
+        .sum::<f64>()
+        / norm
+}
+
+/// Overlap coefficient of the Gaussian kernel density estimates of two sets
+/// of draws, the integral of the smaller of the two densities
+///
+/// One for identical distributions and zero for disjoint ones. `None` unless
+/// both sets have at least two draws, are finite and vary.
+pub fn kde_overlap(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    if a.iter().chain(b).any(|x| !x.is_finite()) {
+        return None;
+    }
+    let (a, b) = (sorted(a), sorted(b));
+    let (ha, hb) = (bandwidth(&a), bandwidth(&b));
+    if !(ha > 0.0 && hb > 0.0) {
+        return None;
+    }
+    let lower = (a[0] - 4.0 * ha).min(b[0] - 4.0 * hb);
+    let upper = (a[a.len() - 1] + 4.0 * ha).max(b[b.len() - 1] + 4.0 * hb);
+    let step = (upper - lower) / (GRID_POINTS - 1) as f64;
+    let overlap: f64 = (0..GRID_POINTS)
+        .map(|i| {
+            let x = lower + step * i as f64;
+            let weight = if i == 0 || i == GRID_POINTS - 1 { 0.5 } else { 1.0 };
+            weight * kde(&a, ha, x).min(kde(&b, hb, x))
+        })
+        .sum();
+    Some((overlap * step).min(1.0))
+}
+
+/// How far the posterior of a quantity moved from its prior
+#[derive(Clone, Debug, PartialEq)]
+pub struct PriorPosteriorOverlap {
+    pub name: String,
+    /// Overlap coefficient of the prior and posterior densities
+    pub overlap: Option<f64>,
+    /// Wasserstein distance between prior and posterior, in prior standard
+    /// deviations
+    pub distance: Option<f64>,
+    /// Posterior standard deviation relative to the prior's, near one when
+    /// the data did not narrow the prior
+    pub contraction: Option<f64>,
+}
+
+impl PriorPosteriorOverlap {
+    /// Whether the data barely inform the quantity: its posterior overlaps
+    /// the prior by more than `max_overlap`
+    pub fn weakly_informed(&self, max_overlap: f64) -> bool {
+        self.overlap.map_or(false, |overlap| overlap > max_overlap)
+    }
+}
+
+impl fmt::Display for PriorPosteriorOverlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |x: Option<f64>| {
+            x.map_or("-".to_string(), |x| format!("{:.3}", x))
+        };
+        write!(
+            f,
+            "{}: overlap = {}, distance = {}, contraction = {}",
+            self.name,
+            show(self.overlap),
+            show(self.distance),
+            show(self.contraction)
+        )
+    }
+}
+
+/// Overlap of the prior and posterior of each of `quantities`, e.g. to find
+/// parameters the data barely inform
+///
+/// `prior` are models drawn from the prior, e.g. with
+/// `Runner::sample_prior` or the models of a `PriorPredictive`.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate rand;
+/// # extern crate rv;
+/// # use rmcmc::overlap::prior_posterior_overlap;
+/// # use rmcmc::runner::Sample;
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # use rv::dist::Gaussian;
+/// # use rv::traits::Rv;
+/// # fn main() {
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let prior: Vec<f64> =
+///     Gaussian::new(0.0, 1.0).unwrap().sample(2000, &mut rng);
+/// let narrow: Vec<f64> =
+///     Gaussian::new(0.5, 0.1).unwrap().sample(2000, &mut rng);
+/// let quantities: [(&str, fn(&f64) -> f64); 1] = [("x", |x| *x)];
+///
+/// let overlaps = prior_posterior_overlap(
+///     &prior,
+///     &Sample::new(vec![], vec![narrow]),
+///     &quantities,
+/// );
+/// assert!(!overlaps[0].weakly_informed(0.5));
+/// assert!(overlaps[0].contraction.unwrap() < 0.2);
+/// # }
+/// ```
+pub fn prior_posterior_overlap<M>(
+    prior: &[M],
+    posterior: &Sample<M>,
+    quantities: &[(&str, fn(&M) -> f64)],
+) -> Vec<PriorPosteriorOverlap> {
+    quantities
+        .iter()
+        .map(|(name, f)| {
+            let prior: Vec<f64> = prior.iter().map(f).collect();
+            let posterior: Vec<f64> = posterior
+                .chains
+                .iter()
+                .flat_map(|chain| chain.iter().map(f))
+                .collect();
+            let scales = if prior.len() > 1 && posterior.len() > 1 {
+                Some((mean_sd(&prior).1, mean_sd(&posterior).1))
+            } else {
+                None
+            };
+            let prior_sd = scales.map(|(sd, _)| sd).filter(|sd| *sd > 0.0);
+            PriorPosteriorOverlap {
+                name: name.to_string(),
+                overlap: kde_overlap(&prior, &posterior),
+                distance: wasserstein(&prior, &posterior)
+                    .and_then(|d| prior_sd.map(|sd| d / sd)),
+                contraction: scales
+                    .and_then(|(_, sd)| prior_sd.map(|prior| sd / prior)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[test]
+    fn uninformed_parameters_are_flagged() {
+        #[derive(Clone, Debug)]
+        struct Model {
+            x: f64,
+            y: f64,
+        }
+
+        let mut rng = StdRng::from_seed(SEED);
+        let wide = Gaussian::new(0.0, 3.0).unwrap();
+        let draw = |rng: &mut StdRng, x: &Gaussian, y: &Gaussian| Model {
+            x: x.draw(rng),
+            y: y.draw(rng),
+        };
+        let prior: Vec<Model> =
+            (0..2000).map(|_| draw(&mut rng, &wide, &wide)).collect();
+        // The data pin down x but say nothing about y.
+        let informed = Gaussian::new(2.0, 0.2).unwrap();
+        let posterior: Vec<Vec<Model>> = (0..2)
+            .map(|_| {
+                (0..1000)
+                    .map(|_| draw(&mut rng, &informed, &wide))
+                    .collect()
+            })
+            .collect();
+
+        let quantities: [(&str, fn(&Model) -> f64); 2] =
+            [("x", |m| m.x), ("y", |m| m.y)];
+        let overlaps = prior_posterior_overlap(
+            &prior,
+            &Sample::new(vec![], posterior),
+            &quantities,
+        );
+        let (x, y) = (&overlaps[0], &overlaps[1]);
+        assert!(x.overlap.unwrap() < 0.3);
+        assert!(!x.weakly_informed(0.9));
+        assert!(x.distance.unwrap() > 0.5);
+        assert!((x.contraction.unwrap() - 0.2 / 3.0).abs() < 0.02);
+
+        assert!(y.overlap.unwrap() > 0.9);
+        assert!(y.weakly_informed(0.9));
+        assert!(y.distance.unwrap() < 0.1);
+        assert!((y.contraction.unwrap() - 1.0).abs() < 0.1);
+        assert!(y.to_string().starts_with("y: overlap = 0.9"));
+    }
+}
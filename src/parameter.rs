@@ -1,9 +1,21 @@
 extern crate rv;
+use dist::BatchLnF;
 use lens::*;
 use rand::Rng;
 use rv::traits::Rv;
 use std::fmt;
 
+/// Identifier of a parameter, given by its unique name
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct ParamId(pub String);
+
+impl fmt::Display for ParamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// Parameter Struct
 /// D: Rv Implementation
 /// T: Parameter Type
@@ -12,12 +24,14 @@ pub struct Parameter<R, T, S>
 where
     R: Rv<T> + Clone,
 {
-    // Name of parameter (must be unique)
-    pub name: String,
+    // Identifier of parameter, its name (must be unique)
+    id: ParamId,
     // Prior distribution
     pub prior: R,
     // Lens to update value
     pub lens: Lens<T, S>,
+    // Parameters read by this parameter's prior
+    pub dependencies: Vec<ParamId>,
 }
 
 impl<D, T, S> fmt::Debug for Parameter<D, T, S>
@@ -25,7 +39,7 @@ where
     D: Rv<T> + Clone,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Parameter {{ name: {} }}", self.name)
+        write!(f, "Parameter {{ name: {} }}", self.id)
     }
 }
 
@@ -35,9 +49,10 @@ where
 {
     fn clone(&self) -> Parameter<D, T, S> {
         Parameter {
-            name: self.name.clone(),
+            id: self.id.clone(),
             prior: self.prior.clone(),
             lens: self.lens.clone(),
+            dependencies: self.dependencies.clone(),
         }
     }
 }
@@ -49,18 +64,63 @@ where
 {
     pub fn new(name: String, prior: D, lens: Lens<T, S>) -> Self {
         Parameter {
-            name,
+            id: ParamId(name),
             prior,
             lens,
+            dependencies: Vec::new(),
         }
     }
 
+    /// Create a parameter whose prior reads the parameters `dependencies`,
+    /// e.g. a group level mean in a hierarchical model.
+    pub fn new_dependent_with_deps(
+        name: String,
+        prior: D,
+        lens: Lens<T, S>,
+        dependencies: Vec<ParamId>,
+    ) -> Self {
+        Parameter {
+            id: ParamId(name),
+            prior,
+            lens,
+            dependencies,
+        }
+    }
+
+    /// Declare the parameters this parameter's prior reads.
+    pub fn depends_on(&self, dependencies: Vec<ParamId>) -> Self {
+        Parameter {
+            dependencies,
+            ..(*self).clone()
+        }
+    }
+
+    /// Name of this parameter
+    pub fn name(&self) -> &str {
+        &self.id.0
+    }
+
+    /// Identifier of this parameter
+    pub fn id(&self) -> &ParamId {
+        &self.id
+    }
+
     /// Create a new version of the struct with a randomly drawn value from the value's
     /// distribution.
     pub fn draw<R: Rng>(&self, s: &S, rng: &mut R) -> S {
         let new_value = self.prior.draw(rng);
         self.lens.set(s, new_value)
     }
+
+    /// Log prior of this parameter's value in each of `models`, evaluated
+    /// as one batch
+    pub fn ln_prior_batch(&self, models: &[S]) -> Vec<f64>
+    where
+        D: BatchLnF<T>,
+    {
+        let values: Vec<T> = models.iter().map(|s| self.lens.get(s)).collect();
+        self.prior.ln_f_batch(&values)
+    }
 }
 
 #[cfg(test)]
@@ -79,6 +139,26 @@ mod tests {
             Beta::jeffreys(),
             make_lens!(Foo, f64, bar),
         );
-        assert!(p.name == "test".to_string());
+        assert_eq!(p.name(), "test");
+        assert_eq!(p.id(), &ParamId("test".to_string()));
+        assert!(p.dependencies.is_empty());
+    }
+
+    #[test]
+    fn dependencies() {
+        struct Foo {
+            bar: f64,
+        }
+
+        let p = Parameter::new_dependent_with_deps(
+            "test".to_string(),
+            Beta::jeffreys(),
+            make_lens!(Foo, f64, bar),
+            vec![ParamId("a".to_string())],
+        );
+        assert_eq!(p.dependencies, vec![ParamId("a".to_string())]);
+
+        let q = p.depends_on(vec![]);
+        assert!(q.dependencies.is_empty());
     }
 }
@@ -0,0 +1,359 @@
+//! Posterior and prior predictive checks
+//!
+//! Compare test statistics of the observed data with their distribution over
+//! datasets replicated from posterior draws, or simulated from the priors
+//! before fitting.
+
+use std::fmt;
+use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+use steppers::SteppingAlg;
+
+/// A named test statistic of a dataset
+pub struct TestStatistic<X> {
+    pub name: String,
+    pub func: fn(&X) -> f64,
+}
+
+impl<X> TestStatistic<X> {
+    pub fn new(name: String, func: fn(&X) -> f64) -> Self {
+        TestStatistic { name, func }
+    }
+
+    pub fn eval(&self, data: &X) -> f64 {
+        (self.func)(data)
+    }
+}
+
+impl<X> Clone for TestStatistic<X> {
+    fn clone(&self) -> Self {
+        TestStatistic {
+            name: self.name.clone(),
+            func: self.func,
+        }
+    }
+}
+
+impl<X> fmt::Debug for TestStatistic<X> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TestStatistic {{ name: {} }}", self.name)
+    }
+}
+
+/// Result of a posterior predictive check for one test statistic
+#[derive(Clone, Debug, PartialEq)]
+pub struct PredictiveCheck {
+    pub name: String,
+    /// Statistic of the observed data
+    pub observed: f64,
+    /// Statistic of each replicated dataset, in the order of the draws
+    pub replicated: Vec<f64>,
+    /// Fraction of replications with a statistic at least the observed one
+    pub p_value: f64,
+}
+
+impl PredictiveCheck {
+    fn new(name: String, observed: f64, replicated: Vec<f64>) -> Self {
+        let n_extreme = replicated.iter().filter(|&&t| t >= observed).count();
+        let p_value = n_extreme as f64 / replicated.len() as f64;
+        PredictiveCheck {
+            name,
+            observed,
+            replicated,
+            p_value,
+        }
+    }
+
+    /// Mean of the replicated statistics
+    pub fn replicated_mean(&self) -> f64 {
+        self.replicated.iter().sum::<f64>() / self.replicated.len() as f64
+    }
+}
+
+impl fmt::Display for PredictiveCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: observed = {:.4}, replicated mean = {:.4}, p = {:.3}",
+            self.name,
+            self.observed,
+            self.replicated_mean(),
+            self.p_value
+        )
+    }
+}
+
+/// Posterior predictive p-values of `statistics`.
+///
+/// A dataset is replicated from every draw with `replicate`, in parallel,
+/// each using its own generator seeded from `rng` so results are
+/// reproducible. The p-value of a statistic is the fraction of replicated
+/// datasets whose statistic is at least the observed data's; values near 0
+/// or 1 indicate the model fails to reproduce that feature of the data.
+///
+/// # Parameters
+/// * `rng` Random number generator
+/// * `draws` Posterior draws, e.g. a flattened `Runner::run` output
+/// * `observed` Observed dataset
+/// * `replicate` Draw a dataset from the model
+/// * `statistics` Test statistics to check
+pub fn ppc<M, X, R, G>(
+    rng: &mut R,
+    draws: &[M],
+    observed: &X,
+    replicate: G,
+    statistics: &[TestStatistic<X>],
+) -> Vec<PredictiveCheck>
+where
+    M: Sync,
+    X: Send,
+    R: Rng,
+    G: Fn(&M, &mut StdRng) -> X + Sync,
+{
+    assert!(!draws.is_empty(), "ppc requires at least one draw.");
+
+    let replicated: Vec<Vec<f64>> = draws
+        .par_iter()
+        .zip(seeds(rng, draws.len()).into_par_iter())
+        .map(|(m, seed)| {
+            let mut rng = StdRng::from_seed(seed);
+            let data = replicate(m, &mut rng);
+            statistics.iter().map(|s| s.eval(&data)).collect()
+        })
+        .collect();
+
+    statistics
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            PredictiveCheck::new(
+                s.name.clone(),
+                s.eval(observed),
+                replicated.iter().map(|ts| ts[i]).collect(),
+            )
+        })
+        .collect()
+}
+
+/// A seed from `rng` for each of `n` generators
+fn seeds<R: Rng>(rng: &mut R, n: usize) -> Vec<<StdRng as SeedableRng>::Seed> {
+    (0..n)
+        .map(|_| {
+            let mut seed = <StdRng as SeedableRng>::Seed::default();
+            rng.fill(&mut seed);
+            seed
+        })
+        .collect()
+}
+
+/// Models drawn from the priors with a dataset simulated from each
+///
+/// The prior counterpart of `ppc`: checking that simulated datasets look
+/// plausible before fitting catches priors which put their mass on
+/// unreasonable data.
+#[derive(Clone, Debug)]
+pub struct PriorPredictive<M, X> {
+    /// Models drawn from the priors
+    pub models: Vec<M>,
+    /// Dataset simulated from each model, in the order of `models`
+    pub datasets: Vec<X>,
+}
+
+impl<M, X> PriorPredictive<M, X>
+where
+    M: Clone + Sync,
+    X: Send,
+{
+    /// Draw `n` models from the priors of the parameters `stepper` updates
+    /// and simulate a dataset from each.
+    ///
+    /// Models are drawn with `stepper.draw_prior` from `init_model`, which
+    /// provides the values of fixed parameters and of anything `stepper`
+    /// does not update. Datasets are simulated in parallel, each with its
+    /// own generator seeded from `rng`, as in `ppc`.
+    ///
+    /// # Parameters
+    /// * `rng` Random number generator
+    /// * `stepper` Stepper holding the parameters and their priors
+    /// * `init_model` Model the prior draws start from
+    /// * `n` Number of models to draw
+    /// * `simulate` Draw a dataset from the model
+    pub fn new<A, R, G>(
+        rng: &mut R,
+        stepper: &A,
+        init_model: &M,
+        n: usize,
+        simulate: G,
+    ) -> Self
+    where
+        A: SteppingAlg<M, R>,
+        R: Rng,
+        G: Fn(&M, &mut StdRng) -> X + Sync,
+    {
+        let models: Vec<M> = (0..n)
+            .map(|_| stepper.draw_prior(rng, init_model.clone()))
+            .collect();
+        let datasets = models
+            .par_iter()
+            .zip(seeds(rng, n).into_par_iter())
+            .map(|(m, seed)| simulate(m, &mut StdRng::from_seed(seed)))
+            .collect();
+        PriorPredictive { models, datasets }
+    }
+
+    /// Value of `statistic` for each simulated dataset
+    pub fn statistic(&self, statistic: &TestStatistic<X>) -> Vec<f64> {
+        self.datasets.iter().map(|x| statistic.eval(x)).collect()
+    }
+
+    /// Prior predictive p-values of `statistics` for the observed data.
+    pub fn check(
+        &self,
+        observed: &X,
+        statistics: &[TestStatistic<X>],
+    ) -> Vec<PredictiveCheck> {
+        assert!(
+            !self.datasets.is_empty(),
+            "check requires at least one simulated dataset."
+        );
+        statistics
+            .iter()
+            .map(|s| {
+                PredictiveCheck::new(
+                    s.name.clone(),
+                    s.eval(observed),
+                    self.statistic(s),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Report of posterior predictive checks, one line per statistic
+pub fn summary(checks: &[PredictiveCheck]) -> String {
+    checks
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+    use steppers::SRWM;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    fn mean(xs: &Vec<f64>) -> f64 {
+        xs.iter().sum::<f64>() / xs.len() as f64
+    }
+
+    fn max(xs: &Vec<f64>) -> f64 {
+        xs.iter().cloned().fold(std::f64::NEG_INFINITY, f64::max)
+    }
+
+    #[test]
+    fn p_values_detect_misfit() {
+        let mut rng = StdRng::from_seed(SEED);
+        let observed: Vec<f64> =
+            Gaussian::new(0.0, 1.0).unwrap().sample(50, &mut rng);
+        // The mean's posterior is right but the data's spread is too small.
+        let posterior = Gaussian::new(mean(&observed), 50f64.sqrt().recip())
+            .unwrap();
+        let draws: Vec<(f64, f64)> = posterior
+            .sample(500, &mut rng)
+            .into_iter()
+            .map(|mu| (mu, 0.3))
+            .collect();
+        let replicate = |m: &(f64, f64), rng: &mut StdRng| {
+            Gaussian::new(m.0, m.1).unwrap().sample(50, rng)
+        };
+        let statistics = vec![
+            TestStatistic::new("mean".to_string(), mean),
+            TestStatistic::new("max".to_string(), max),
+        ];
+
+        let checks = ppc(&mut rng, &draws, &observed, replicate, &statistics);
+        assert_eq!(checks.len(), 2);
+        assert_eq!(checks[0].name, "mean");
+        assert_eq!(checks[0].replicated.len(), 500);
+        assert!(checks[0].p_value > 0.01 && checks[0].p_value < 0.99);
+        assert_eq!(checks[1].p_value, 0.0);
+
+        let report = summary(&checks);
+        assert_eq!(report.lines().count(), 2);
+        assert!(report.starts_with("mean: observed = "));
+    }
+
+    #[test]
+    fn replications_are_reproducible() {
+        let draws: Vec<f64> = (0..100).map(|i| i as f64 / 100.0).collect();
+        let replicate = |&m: &f64, rng: &mut StdRng| {
+            Gaussian::new(m, 1.0).unwrap().sample(5, rng)
+        };
+        let statistics = vec![TestStatistic::new("mean".to_string(), mean)];
+        let observed = vec![0.0; 5];
+
+        let a = ppc(
+            &mut StdRng::from_seed(SEED),
+            &draws,
+            &observed,
+            replicate,
+            &statistics,
+        );
+        let b = ppc(
+            &mut StdRng::from_seed(SEED),
+            &draws,
+            &observed,
+            replicate,
+            &statistics,
+        );
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn prior_predictive_simulates_from_the_priors() {
+        #[derive(Clone, Debug)]
+        struct Model {
+            mu: f64,
+            sigma: f64,
+        }
+
+        let mu = Parameter::new(
+            "mu".to_string(),
+            Gaussian::new(3.0, 1.0).unwrap(),
+            make_lens!(Model, f64, mu),
+        );
+        let stepper = SRWM::new(mu, |_: &Model| 0.0, Some(1.0)).unwrap();
+        let init = Model { mu: 0.0, sigma: 0.5 };
+        let simulate = |m: &Model, rng: &mut StdRng| {
+            Gaussian::new(m.mu, m.sigma).unwrap().sample(10, rng)
+        };
+
+        let mut rng = StdRng::from_seed(SEED);
+        let prior: PriorPredictive<Model, Vec<f64>> =
+            PriorPredictive::new(&mut rng, &stepper, &init, 2000, simulate);
+        assert_eq!(prior.datasets.len(), 2000);
+        assert!(prior.models.iter().all(|m| m.sigma == 0.5));
+
+        // The mean of a dataset is N(3, sqrt(1 + 0.5^2 / 10)) a priori.
+        let means = prior.statistic(&TestStatistic::new(
+            "mean".to_string(),
+            mean,
+        ));
+        let mean_of_means = means.iter().sum::<f64>() / 2000.0;
+        assert!((mean_of_means - 3.0).abs() < 0.1);
+
+        let observed = vec![30.0; 10];
+        let statistics = vec![TestStatistic::new("mean".to_string(), mean)];
+        let checks = prior.check(&observed, &statistics);
+        assert_eq!(checks[0].p_value, 0.0);
+    }
+}
@@ -0,0 +1,257 @@
+//! Rao-Blackwellized means of Metropolis chains
+//!
+//! A Metropolis step moves to its proposal `y` with probability `α` and
+//! otherwise stays at `x`, so `α f(y) + (1 - α) f(x)` is an unbiased
+//! estimate of the next draw's `f` with the accept/reject coin averaged
+//! out. Averaging it over the steps recycles rejected proposals, which a
+//! plain average of the draws throws away, and so gives a lower variance
+//! estimate of the posterior mean of `f`.
+//!
+//! Steppers report the quantities needed as `StatisticValue::Proposal`,
+//! e.g. `SRWM::emit_proposals`, or as the records of a `ProposalCache`.
+
+use parameter::ParamId;
+use statistics::{Statistic, StatisticValue};
+use steppers::util::ProposalRecord;
+
+/// Waste-recycling estimate of the posterior mean of a function of a
+/// scalar parameter, accumulated from the statistics of each step
+///
+/// # Example
+/// ```
+/// # #[macro_use] extern crate rmcmc;
+/// # extern crate rand;
+/// # extern crate rv;
+/// # use rmcmc::lens::*;
+/// # use rmcmc::parameter::Parameter;
+/// # use rmcmc::rao_blackwell::RaoBlackwellMean;
+/// # use rmcmc::steppers::{SteppingAlg, SRWM};
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # use rv::dist::Gaussian;
+/// # fn main() {
+/// #[derive(Clone, Copy, Debug)]
+/// struct Model {
+///     x: f64,
+/// }
+///
+/// let parameter = Parameter::new(
+///     "x".to_string(),
+///     Gaussian::new(1.0, 1.0).unwrap(),
+///     make_lens!(Model, f64, x),
+/// );
+/// let mut estimate = RaoBlackwellMean::new(parameter.id().clone());
+/// let mut stepper = SRWM::new(parameter, |_: &Model| 0.0, Some(1.0))
+///     .unwrap()
+///     .emit_proposals();
+///
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let mut model = Model { x: 1.0 };
+/// for _ in 0..5000 {
+///     model = stepper.step(&mut rng, model);
+///     estimate.observe::<Model, StdRng>(&stepper.get_statistics());
+/// }
+/// assert!((estimate.mean().unwrap() - 1.0).abs() < 0.1);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RaoBlackwellMean {
+    parameter: ParamId,
+    f: fn(f64) -> f64,
+    sum: f64,
+    n: usize,
+}
+
+fn identity(x: f64) -> f64 {
+    x
+}
+
+impl RaoBlackwellMean {
+    /// Estimate of the posterior mean of `parameter`
+    pub fn new(parameter: ParamId) -> Self {
+        RaoBlackwellMean::of(parameter, identity)
+    }
+
+    /// Estimate of the posterior mean of `f` of `parameter`
+    pub fn of(parameter: ParamId, f: fn(f64) -> f64) -> Self {
+        RaoBlackwellMean {
+            parameter,
+            f,
+            sum: 0.0,
+            n: 0,
+        }
+    }
+
+    /// Add the step described by the parameter's `Proposal` statistic in
+    /// `statistics`, returning whether there was one.
+    ///
+    /// A stepper which did not propose a value, e.g. because the parameter
+    /// is fixed, reports no proposal and the step is skipped.
+    pub fn observe<M, R>(&mut self, statistics: &[Statistic<M, R>]) -> bool {
+        let proposal = statistics
+            .iter()
+            .filter(|s| s.parameter == self.parameter)
+            .filter_map(|s| match s.value {
+                StatisticValue::Proposal {
+                    current,
+                    proposed,
+                    log_alpha,
+                } => Some((current, proposed, log_alpha)),
+                _ => None,
+            })
+            .next();
+        match proposal {
+            Some((current, proposed, log_alpha)) => {
+                self.push(current, proposed, log_alpha);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Add the step described by `record`, returning whether it was a
+    /// proposal for the parameter.
+    pub fn observe_record(&mut self, record: &ProposalRecord) -> bool {
+        if record.parameter != self.parameter {
+            return false;
+        }
+        self.push(record.current, record.proposed, record.log_alpha());
+        true
+    }
+
+    /// Add a step from `current` which proposed `proposed` with log
+    /// acceptance ratio `log_alpha`.
+    pub fn push(&mut self, current: f64, proposed: f64, log_alpha: f64) {
+        // A NaN ratio is a rejection, as in `metropolis_select`.
+        let alpha = if log_alpha.is_nan() {
+            0.0
+        } else {
+            log_alpha.exp().min(1.0)
+        };
+        let f_proposed = if alpha > 0.0 { (self.f)(proposed) } else { 0.0 };
+        self.sum += alpha * f_proposed + (1.0 - alpha) * (self.f)(current);
+        self.n += 1;
+    }
+
+    /// Number of steps observed
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    /// The estimate, if any steps have been observed
+    pub fn mean(&self) -> Option<f64> {
+        if self.n == 0 {
+            None
+        } else {
+            Some(self.sum / self.n as f64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use steppers::{SteppingAlg, SRWM};
+    use steppers::util::ProposalCache;
+
+    #[derive(Clone, Copy, Debug)]
+    struct Model {
+        x: f64,
+    }
+
+    #[test]
+    fn recycling_reduces_the_error_of_the_mean() {
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let id = parameter.id().clone();
+        let stepper = SRWM::new(parameter, |_: &Model| 0.0, Some(2.5))
+            .unwrap()
+            .emit_proposals();
+
+        let (mut naive_error, mut rb_error) = (0.0, 0.0);
+        for seed in 0..100u8 {
+            let mut rng = StdRng::from_seed([seed; 32]);
+            let mut stepper = stepper.clone();
+            let mut estimate = RaoBlackwellMean::of(id.clone(), |x| x * x);
+            let mut model = Model { x: 0.0 };
+            let mut naive = 0.0;
+            for _ in 0..500 {
+                model = stepper.step(&mut rng, model);
+                naive += model.x * model.x / 500.0;
+                let statistics = stepper.get_statistics();
+                assert!(estimate.observe::<Model, StdRng>(&statistics));
+            }
+            assert_eq!(estimate.n(), 500);
+            naive_error += (naive - 1.0).powi(2);
+            rb_error += (estimate.mean().unwrap() - 1.0).powi(2);
+        }
+        assert!(rb_error < naive_error);
+    }
+
+    #[test]
+    fn steps_without_proposals_are_skipped() {
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let id = parameter.id().clone();
+        let mut quiet = SRWM::new(parameter, |_: &Model| 0.0, None).unwrap();
+        let mut rng = StdRng::from_seed([0; 32]);
+        quiet.step(&mut rng, Model { x: 0.0 });
+
+        let mut estimate = RaoBlackwellMean::new(id.clone());
+        let statistics = quiet.get_statistics();
+        assert!(!estimate.observe::<Model, StdRng>(&statistics));
+        assert_eq!(estimate.mean(), None);
+
+        estimate.push(1.0, 3.0, 0.5f64.ln());
+        estimate.push(1.0, 3.0, std::f64::NAN);
+        assert_eq!(estimate.mean(), Some(1.5));
+    }
+
+    #[test]
+    fn cached_proposals_give_the_same_estimate() {
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let id = parameter.id().clone();
+        let cache = ProposalCache::new(1000);
+        let mut stepper = SRWM::new(parameter, |_: &Model| 0.0, None)
+            .unwrap()
+            .emit_proposals()
+            .record_proposals(cache.clone());
+
+        let mut rng = StdRng::from_seed([0; 32]);
+        let mut from_statistics = RaoBlackwellMean::new(id.clone());
+        let mut model = Model { x: 0.0 };
+        for _ in 0..200 {
+            model = stepper.step(&mut rng, model);
+            let statistics = stepper.get_statistics();
+            from_statistics.observe::<Model, StdRng>(&statistics);
+        }
+
+        let records = cache.drain();
+        assert_eq!(records.len(), 200);
+        assert!(cache.is_empty());
+        let mut from_records = RaoBlackwellMean::new(id);
+        assert!(records.iter().all(|r| from_records.observe_record(r)));
+        assert_eq!(from_records.mean(), from_statistics.mean());
+
+        let other = ProposalRecord {
+            parameter: ParamId("y".to_string()),
+            ..records[0].clone()
+        };
+        assert!(!from_records.observe_record(&other));
+    }
+}
@@ -0,0 +1,338 @@
+//! Sequential data assimilation by resample-move
+//!
+//! A posterior sample is updated as data arrives instead of rerunning the
+//! chains: each draw becomes a weighted particle, new data reweights the
+//! particles by its likelihood and, when the weights degenerate, the
+//! particles are resampled and rejuvenated with a few steps of a stepper
+//! targeting the updated posterior.
+
+use std::fmt;
+use std::io;
+use rand::prelude::*;
+use rayon::prelude::*;
+
+use runner::draw_seed;
+use steppers::{AdaptationMode, SteppingAlg};
+use utils::invalid_data;
+
+/// Weighted particles approximating a posterior updated as data arrives
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate rand;
+/// # use rmcmc::runner::ResampleMove;
+/// # use rmcmc::steppers::Mock;
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # fn main() {
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let draws: Vec<f64> = (0..100).map(|i| i as f64 / 100.0).collect();
+/// let mut particles = ResampleMove::new(draws);
+///
+/// // Data favouring large values; the mock kernel leaves particles as is.
+/// let kernel = Mock::new(0.0, |x: f64| x);
+/// particles
+///     .assimilate(&mut rng, |&x: &f64| 10.0 * x, &kernel)
+///     .unwrap();
+/// assert!(particles.mean(|&x| x) > 0.5);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ResampleMove<M> {
+    particles: Vec<M>,
+    log_weights: Vec<f64>,
+    rejuvenation_steps: usize,
+    ess_threshold: f64,
+    resamples: usize,
+}
+
+impl<M> fmt::Debug for ResampleMove<M>
+where
+    M: Clone + Send + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ResampleMove {{ particles: {}, ess: {:.1}, resamples: {} }}",
+            self.particles.len(),
+            self.ess(),
+            self.resamples
+        )
+    }
+}
+
+impl<M> ResampleMove<M>
+where
+    M: Clone + Send + Sync,
+{
+    /// Equally weighted particles from posterior draws, e.g. the flattened
+    /// output of `Runner::run`.
+    pub fn new(draws: Vec<M>) -> Self {
+        assert!(!draws.is_empty(), "ResampleMove requires at least one draw.");
+        ResampleMove {
+            log_weights: vec![0.0; draws.len()],
+            particles: draws,
+            rejuvenation_steps: 5,
+            ess_threshold: 0.5,
+            resamples: 0,
+        }
+    }
+
+    /// Number of stepper steps taken by each particle after resampling.
+    pub fn rejuvenation_steps(&self, steps: usize) -> Self {
+        ResampleMove {
+            rejuvenation_steps: steps,
+            ..(*self).clone()
+        }
+    }
+
+    /// Resample when the effective sample size falls below `fraction` of
+    /// the number of particles.
+    pub fn ess_threshold(&self, fraction: f64) -> Self {
+        assert!(
+            fraction >= 0.0 && fraction <= 1.0,
+            "ess threshold must be between zero and one."
+        );
+        ResampleMove {
+            ess_threshold: fraction,
+            ..(*self).clone()
+        }
+    }
+
+    /// The particles, weighted by `weights`
+    pub fn particles(&self) -> &[M] {
+        &self.particles
+    }
+
+    /// Normalized weight of each particle
+    pub fn weights(&self) -> Vec<f64> {
+        let max = self
+            .log_weights
+            .iter()
+            .cloned()
+            .fold(std::f64::NEG_INFINITY, f64::max);
+        let unnormalized: Vec<f64> =
+            self.log_weights.iter().map(|w| (w - max).exp()).collect();
+        let total: f64 = unnormalized.iter().sum();
+        unnormalized.iter().map(|w| w / total).collect()
+    }
+
+    /// Effective sample size of the weighted particles
+    pub fn ess(&self) -> f64 {
+        let weights = self.weights();
+        1.0 / weights.iter().map(|w| w * w).sum::<f64>()
+    }
+
+    /// Number of times the particles have been resampled
+    pub fn resamples(&self) -> usize {
+        self.resamples
+    }
+
+    /// Weighted posterior mean of `f`
+    pub fn mean<F: Fn(&M) -> f64>(&self, f: F) -> f64 {
+        self.particles
+            .iter()
+            .zip(self.weights())
+            .map(|(m, w)| w * f(m))
+            .sum()
+    }
+
+    /// Condition on new data.
+    ///
+    /// Each particle is reweighted by `log_likelihood`, the log likelihood
+    /// of the new data alone. When the effective sample size falls below
+    /// the threshold, the particles are resampled in proportion to their
+    /// weights and each takes `rejuvenation_steps` steps of its own copy of
+    /// `kernel`, which must target the posterior given all data so far.
+    /// Adaptation is disabled so the kernel leaves that posterior
+    /// invariant, and each copy forgets the scores cached by `kernel` for
+    /// whichever model it last stepped. Returns whether the particles were
+    /// resampled.
+    ///
+    /// # Errors
+    /// Fails with `InvalidData`, leaving the particles untouched, when no
+    /// particle has a finite likelihood for the new data.
+    pub fn assimilate<A, R, L>(
+        &mut self,
+        rng: &mut R,
+        log_likelihood: L,
+        kernel: &A,
+    ) -> io::Result<bool>
+    where
+        A: SteppingAlg<M, R> + Clone + Send + Sync,
+        R: SeedableRng + Rng + Send,
+        L: Fn(&M) -> f64 + Sync,
+    {
+        let increments: Vec<f64> =
+            self.particles.par_iter().map(|m| log_likelihood(m)).collect();
+        let log_weights: Vec<f64> = self
+            .log_weights
+            .iter()
+            .zip(increments)
+            .map(|(w, dw)| {
+                // A NaN likelihood rules a particle out rather than poison
+                // every weight.
+                w + if dw.is_nan() { std::f64::NEG_INFINITY } else { dw }
+            })
+            .collect();
+        if log_weights.iter().all(|w| *w == std::f64::NEG_INFINITY) {
+            return Err(invalid_data(
+                "no particle has a finite likelihood for the new data",
+            ));
+        }
+        self.log_weights = log_weights;
+
+        let n = self.particles.len();
+        if self.ess() >= self.ess_threshold * n as f64 {
+            return Ok(false);
+        }
+        self.resample(rng);
+
+        let mut kernel = kernel.clone();
+        kernel.set_adapt(AdaptationMode::Disabled);
+        let steps = self.rejuvenation_steps;
+        let rngs: Vec<R> = (0..n)
+            .map(|_| R::from_seed(draw_seed::<R, _>(rng)))
+            .collect();
+        self.particles = self
+            .particles
+            .par_iter()
+            .zip(rngs.into_par_iter())
+            .map(|(m, mut rng)| {
+                let mut kernel = kernel.clone();
+                kernel.invalidate_cache();
+                (0..steps).fold(m.clone(), |m, _| kernel.step(&mut rng, m))
+            })
+            .collect();
+        Ok(true)
+    }
+
+    /// Systematic resampling in proportion to the weights, after which every
+    /// particle has the same weight.
+    fn resample<R: Rng>(&mut self, rng: &mut R) {
+        let n = self.particles.len();
+        let weights = self.weights();
+        let offset: f64 = rng.gen();
+        let mut cumulative = 0.0;
+        let mut j = 0;
+        let resampled = (0..n)
+            .map(|i| {
+                let u = (i as f64 + offset) / n as f64;
+                while j < n - 1 && cumulative + weights[j] < u {
+                    cumulative += weights[j];
+                    j += 1;
+                }
+                self.particles[j].clone()
+            })
+            .collect();
+        self.particles = resampled;
+        self.log_weights = vec![0.0; n];
+        self.resamples += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+    use runner::Runner;
+    use steppers::SRWM;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Clone, Debug)]
+    struct Model {
+        mu: f64,
+    }
+
+    fn log_likelihood(data: &[f64], m: &Model) -> f64 {
+        let g = Gaussian::new(m.mu, 1.0).unwrap();
+        data.iter().map(|x| g.ln_f(x)).sum()
+    }
+
+    fn kernel(
+        data: Vec<f64>,
+    ) -> SRWM<Gaussian, f64, f64, Model, impl Fn(&Model) -> f64 + Clone + Sync>
+    {
+        let parameter = Parameter::new(
+            "mu".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, mu),
+        );
+        let ll = move |m: &Model| log_likelihood(&data, m);
+        SRWM::new(parameter, ll, Some(0.5)).unwrap()
+    }
+
+    #[test]
+    fn assimilation_matches_the_conjugate_posterior() {
+        let mut rng = StdRng::from_seed(SEED);
+        let data: Vec<f64> =
+            Gaussian::new(2.0, 1.0).unwrap().sample(20, &mut rng);
+        let (first, second) = data.split_at(10);
+
+        let draws: Vec<Model> = Runner::new(kernel(first.to_vec()))
+            .chains(2)
+            .warmup(500)
+            .samples(1000)
+            .run(&mut rng, Model { mu: 0.0 })
+            .into_iter()
+            .flatten()
+            .collect();
+        // Always resample so the rejuvenation is exercised.
+        let mut particles = ResampleMove::new(draws)
+            .rejuvenation_steps(10)
+            .ess_threshold(1.0);
+
+        let second = second.to_vec();
+        let resampled = particles.assimilate(
+            &mut rng,
+            |m: &Model| log_likelihood(&second, m),
+            &kernel(data.clone()),
+        );
+        assert!(resampled.unwrap());
+        assert_eq!(particles.resamples(), 1);
+        assert!((particles.ess() - 2000.0).abs() < 1E-6);
+
+        // Under a N(0, 1) prior the posterior mean is sum(x) / (n + 1).
+        let expected = data.iter().sum::<f64>() / 21.0;
+        assert!((particles.mean(|m| m.mu) - expected).abs() < 0.05);
+    }
+
+    #[test]
+    fn small_updates_only_reweight() {
+        let mut rng = StdRng::from_seed(SEED);
+        let draws: Vec<Model> =
+            (0..100).map(|i| Model { mu: i as f64 / 100.0 }).collect();
+        let mut particles = ResampleMove::new(draws).ess_threshold(0.1);
+
+        let flat = |_: &Model| 0.0;
+        let kernel = kernel(vec![]);
+        assert!(!particles.assimilate(&mut rng, flat, &kernel).unwrap());
+        assert!((particles.ess() - 100.0).abs() < 1E-9);
+
+        let nan = |m: &Model| if m.mu < 0.5 { std::f64::NAN } else { 0.0 };
+        assert!(!particles.assimilate(&mut rng, nan, &kernel).unwrap());
+        assert!((particles.ess() - 50.0).abs() < 1E-9);
+        assert!(particles.weights()[0] == 0.0);
+    }
+
+    #[test]
+    fn data_no_particle_explains_is_an_error() {
+        let mut rng = StdRng::from_seed(SEED);
+        let draws: Vec<Model> =
+            (0..10).map(|i| Model { mu: i as f64 }).collect();
+        let mut particles = ResampleMove::new(draws);
+
+        let impossible = |_: &Model| std::f64::NAN;
+        let err = particles
+            .assimilate(&mut rng, impossible, &kernel(vec![]))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!((particles.ess() - 10.0).abs() < 1E-9);
+    }
+}
@@ -0,0 +1,125 @@
+//! Runner for a batched stepper advancing every chain at once
+
+use std::marker::PhantomData;
+use rand::Rng;
+
+use parameter::ParamId;
+use steppers::AdaptationMode;
+use steppers::batch::BatchSteppingAlg;
+
+/// Runs a `BatchSteppingAlg` over several chains in lockstep on the calling
+/// thread, with the same settings as `Runner`.
+pub struct BatchRunner<M, B, R>
+where
+    M: Clone,
+    B: BatchSteppingAlg<M, R> + Clone,
+    R: Rng,
+{
+    pub stepper: B,
+    pub n_chains: usize,
+    pub warmup_steps: usize,
+    pub samples: usize,
+    pub thinning: usize,
+    phantom_m: PhantomData<M>,
+    phantom_r: PhantomData<R>,
+}
+
+impl<M, B, R> Clone for BatchRunner<M, B, R>
+where
+    M: Clone,
+    B: BatchSteppingAlg<M, R> + Clone,
+    R: Rng,
+{
+    fn clone(&self) -> Self {
+        BatchRunner {
+            stepper: self.stepper.clone(),
+            n_chains: self.n_chains,
+            warmup_steps: self.warmup_steps,
+            samples: self.samples,
+            thinning: self.thinning,
+            phantom_m: PhantomData,
+            phantom_r: PhantomData,
+        }
+    }
+}
+
+impl<M, B, R> BatchRunner<M, B, R>
+where
+    M: Clone,
+    B: BatchSteppingAlg<M, R> + Clone,
+    R: Rng,
+{
+    pub fn new(stepper: B) -> Self {
+        BatchRunner {
+            stepper,
+            n_chains: 1,
+            warmup_steps: 1000,
+            samples: 1000,
+            thinning: 1,
+            phantom_m: PhantomData,
+            phantom_r: PhantomData,
+        }
+    }
+
+    pub fn chains(&self, n_chains: usize) -> Self {
+        assert!(n_chains > 0, "chains must be greater than 0.");
+        BatchRunner {
+            n_chains,
+            ..(*self).clone()
+        }
+    }
+
+    pub fn warmup(&self, steps: usize) -> Self {
+        BatchRunner {
+            warmup_steps: steps,
+            ..(*self).clone()
+        }
+    }
+
+    pub fn samples(&self, steps: usize) -> Self {
+        BatchRunner {
+            samples: steps,
+            ..(*self).clone()
+        }
+    }
+
+    pub fn thinning(&self, thinning: usize) -> Self {
+        assert!(thinning > 0, "thinning must be greater than 0.");
+        BatchRunner {
+            thinning,
+            ..(*self).clone()
+        }
+    }
+
+    /// Parameters updated by this runner's stepper
+    pub fn parameters(&self) -> Vec<ParamId> {
+        self.stepper.parameters()
+    }
+
+    /// Run every chain from `init_model`, returning the draws of each chain.
+    pub fn run(&self, rng: &mut R, init_model: M) -> Vec<Vec<M>> {
+        let mut stepper = self.stepper.clone();
+        stepper.reset();
+
+        stepper.set_adapt(AdaptationMode::Enabled);
+        let models = vec![init_model; self.n_chains];
+        let warmed = (0..self.warmup_steps)
+            .fold(models, |ms, _| stepper.step_batch(rng, ms));
+
+        stepper.set_adapt(AdaptationMode::Disabled);
+        let mut draws: Vec<Vec<M>> = (0..self.n_chains)
+            .map(|_| Vec::with_capacity(self.samples))
+            .collect();
+        (0..(self.samples * self.thinning)).fold(warmed, |ms, i| {
+            let next = stepper.step_batch(rng, ms);
+            if i % self.thinning == 0 {
+                draws
+                    .iter_mut()
+                    .zip(next.iter())
+                    .for_each(|(chain, m)| chain.push(m.clone()));
+            }
+            next
+        });
+        draws
+    }
+}
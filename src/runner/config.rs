@@ -0,0 +1,295 @@
+//! Experiments described in TOML or YAML files
+//!
+//! An `Experiment` holds everything about a run except the model: the
+//! number of chains, warmup and draws, the steppers as a `StepperSpec` and
+//! how draws are kept. Parameters and their likelihoods stay in Rust,
+//! registered with a `Registry`, so sweeps over run settings can be managed
+//! with configuration files alone.
+//!
+//! ```toml
+//! chains = 4
+//! warmup = 500
+//! samples = 2000
+//! output = { Reservoir = { capacity = 500 } }
+//!
+//! [stepper]
+//! Group = [
+//!     { Srwm = { parameter = "mu" } },
+//!     { Srwm = { parameter = "sigma", bounds = [0.0, inf] } },
+//! ]
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use rand::prelude::*;
+use serde_yaml;
+use toml;
+
+use runner::{Reservoir, Runner};
+use steppers::{Registry, SpecStepper, StepperSpec};
+use utils::invalid_input;
+
+/// How the draws of each chain are kept
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Output {
+    /// Keep every draw
+    All,
+    /// Keep a uniform random subset of at most `capacity` draws
+    Reservoir { capacity: usize },
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output::All
+    }
+}
+
+fn one() -> usize {
+    1
+}
+
+fn thousand() -> usize {
+    1000
+}
+
+/// Description of a run, with the same defaults as `Runner::new`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Experiment {
+    #[serde(default = "one")]
+    pub chains: usize,
+    #[serde(default = "thousand")]
+    pub warmup: usize,
+    #[serde(default = "thousand")]
+    pub samples: usize,
+    #[serde(default = "one")]
+    pub thinning: usize,
+    #[serde(default)]
+    pub keep_warmup: bool,
+    #[serde(default)]
+    pub output: Output,
+    pub stepper: StepperSpec,
+}
+
+impl Experiment {
+    /// Parse an experiment from TOML.
+    pub fn from_toml(s: &str) -> io::Result<Self> {
+        toml::from_str(s).map_err(invalid_input)
+    }
+
+    /// Parse an experiment from YAML.
+    pub fn from_yaml(s: &str) -> io::Result<Self> {
+        serde_yaml::from_str(s).map_err(invalid_input)
+    }
+
+    /// The experiment as TOML, e.g. to write out the points of a sweep.
+    pub fn to_toml(&self) -> io::Result<String> {
+        toml::to_string(self).map_err(invalid_input)
+    }
+
+    /// Read an experiment from a `.toml`, `.yaml` or `.yml` file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Experiment::from_toml(&contents),
+            Some("yaml") | Some("yml") => Experiment::from_yaml(&contents),
+            _ => Err(invalid_input(format!(
+                "unknown experiment format: {}",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Check the run settings and the stepper specification, e.g. for
+    /// every point of a sweep before running any of them.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.thinning == 0 {
+            return Err(invalid_input("thinning must be greater than 0"));
+        }
+        self.stepper.validate()
+    }
+
+    /// Build the runner this experiment describes from the parameters of
+    /// `registry`, once it is valid.
+    pub fn runner<M, R>(
+        &self,
+        registry: &Registry<M, R>,
+    ) -> io::Result<Runner<M, SpecStepper<M, R>, R>>
+    where
+        M: 'static + Clone + Send + Sync + fmt::Debug,
+        R: 'static + SeedableRng + Rng + fmt::Debug + Send + Sync,
+    {
+        self.validate()?;
+        let stepper = registry.build(&self.stepper)?;
+        let runner = Runner::new(stepper)
+            .chains(self.chains)
+            .warmup(self.warmup)
+            .samples(self.samples)
+            .thinning(self.thinning);
+        Ok(if self.keep_warmup {
+            runner.keep_warmup()
+        } else {
+            runner
+        })
+    }
+
+    /// Run the experiment from `init_model`, returning each chain's kept
+    /// draws.
+    pub fn run<M, R>(
+        &self,
+        registry: &Registry<M, R>,
+        rng: &mut R,
+        init_model: M,
+    ) -> io::Result<Vec<Vec<M>>>
+    where
+        M: 'static + Clone + Send + Sync + fmt::Debug,
+        R: 'static + SeedableRng + Rng + fmt::Debug + Send + Sync,
+    {
+        let runner = self.runner(registry)?;
+        Ok(match self.output {
+            Output::All => runner.run(rng, init_model),
+            Output::Reservoir { capacity } => runner
+                .run_into(rng, init_model, Reservoir::new(capacity))
+                .into_iter()
+                .map(|reservoir| reservoir.into_draws())
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use steppers::ProposalKernel;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Copy, Clone, Debug)]
+    struct Model {
+        mu: f64,
+        sigma: f64,
+    }
+
+    fn log_likelihood(m: &Model) -> f64 {
+        -0.5 * (m.mu - 1.0).powi(2) - 0.5 * (m.sigma - 2.0).powi(2)
+    }
+
+    fn registry() -> Registry<Model, StdRng> {
+        let mut registry = Registry::new();
+        registry.scalar(
+            Parameter::new(
+                "mu".to_string(),
+                Gaussian::new(0.0, 1.0).unwrap(),
+                make_lens!(Model, f64, mu),
+            ),
+            log_likelihood,
+        );
+        registry.scalar(
+            Parameter::new(
+                "sigma".to_string(),
+                Gaussian::new(0.0, 1.0).unwrap(),
+                make_lens!(Model, f64, sigma),
+            ),
+            log_likelihood,
+        );
+        registry
+    }
+
+    const TOML: &str = r#"
+chains = 2
+warmup = 50
+samples = 200
+output = { Reservoir = { capacity = 20 } }
+
+[stepper]
+Group = [
+    { Srwm = { parameter = "mu", kernel = { StudentT = 3.0 } } },
+    { Srwm = { parameter = "sigma", bounds = [0.0, inf] } },
+]
+"#;
+
+    const YAML: &str = "
+chains: 2
+warmup: 50
+samples: 200
+output:
+  Reservoir:
+    capacity: 20
+stepper:
+  Group:
+    - Srwm:
+        parameter: mu
+        kernel:
+          StudentT: 3.0
+    - Srwm:
+        parameter: sigma
+        bounds: [0.0, .inf]
+";
+
+    #[test]
+    fn toml_and_yaml_describe_the_same_experiment() {
+        let experiment = Experiment::from_toml(TOML).unwrap();
+        assert_eq!(Experiment::from_yaml(YAML).unwrap(), experiment);
+        assert_eq!(experiment.thinning, 1);
+        assert!(!experiment.keep_warmup);
+        match &experiment.stepper {
+            StepperSpec::Group(specs) => match &specs[0] {
+                StepperSpec::Srwm { kernel, .. } => {
+                    assert_eq!(*kernel, ProposalKernel::StudentT(3.0))
+                }
+                spec => panic!("unexpected spec {:?}", spec),
+            },
+            spec => panic!("unexpected spec {:?}", spec),
+        }
+
+        let written = experiment.to_toml().unwrap();
+        assert_eq!(Experiment::from_toml(&written).unwrap(), experiment);
+    }
+
+    #[test]
+    fn runs_the_described_experiment() {
+        let mut rng = StdRng::from_seed(SEED);
+        let experiment = Experiment::from_toml(TOML).unwrap();
+        let draws = experiment
+            .run(&registry(), &mut rng, Model { mu: 0.0, sigma: 1.0 })
+            .unwrap();
+        assert_eq!(draws.len(), 2);
+        assert!(draws.iter().all(|chain| chain.len() == 20));
+        assert!(draws.iter().flatten().all(|m| m.sigma >= 0.0));
+    }
+
+    #[test]
+    fn rejects_invalid_experiments() {
+        let missing = Experiment::from_toml("chains = 2").unwrap_err();
+        assert_eq!(missing.kind(), io::ErrorKind::InvalidInput);
+
+        let unknown = "stepper: { Srwm: { parameter: nu } }";
+        let experiment = Experiment::from_yaml(unknown).unwrap();
+        assert!(experiment.runner(&registry()).is_err());
+
+        let bounds = "stepper: { Srwm: { parameter: mu, bounds: [1, 0] } }";
+        let experiment = Experiment::from_yaml(bounds).unwrap();
+        let err = experiment.validate().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(experiment.runner(&registry()).is_err());
+
+        // Unique to this process, so concurrent test runs don't collide
+        let name = format!("rmcmc_config_test_{}", std::process::id());
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("{}.toml", name));
+        fs::write(&path, TOML).unwrap();
+        assert!(Experiment::load(&path).is_ok());
+        fs::remove_file(&path).unwrap();
+        let path = dir.join(format!("{}.json", name));
+        fs::write(&path, TOML).unwrap();
+        let format = Experiment::load(&path).unwrap_err();
+        assert_eq!(format.kind(), io::ErrorKind::InvalidInput);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,379 @@
+//! Running chains on worker processes over TCP
+//!
+//! Steppers hold likelihoods and lenses as closures, which cannot be sent
+//! between processes, so every worker builds the same `Runner` itself and
+//! serves it with `serve`. The coordinating process sends each worker the
+//! run settings, a seed and the initial model, and collects the draws.
+//!
+//! The protocol is line based text. A request is
+//!
+//! ```text
+//! RUN <chains> <warmup> <samples> <thinning> <keep_warmup> <seed>
+//! <initial model>
+//! ```
+//!
+//! with the seed in hex, and the response is, for each chain,
+//! `CHAIN <draws>` followed by one line per draw, then `END`, or
+//! `ERR <message>` if the request could not be served.
+
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use rand::prelude::*;
+
+use runner::Runner;
+use steppers::SteppingAlg;
+use utils::{invalid_data, invalid_input};
+
+/// Text encoding of a model for sending between processes
+///
+/// An encoding must fit on a single line.
+pub trait Wire: Sized {
+    fn encode(&self) -> String;
+    fn decode(line: &str) -> io::Result<Self>;
+}
+
+macro_rules! impl_wire {
+    ($($kind: ty),*) => {
+        $(
+            impl Wire for $kind {
+                fn encode(&self) -> String {
+                    self.to_string()
+                }
+
+                fn decode(line: &str) -> io::Result<Self> {
+                    line.trim().parse().map_err(invalid_data)
+                }
+            }
+        )*
+    };
+}
+
+impl_wire!(f64, f32, i32, i64, u32, u64, usize, bool);
+
+fn encode_seed(seed: &[u8]) -> String {
+    seed.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_seed(hex: &str, seed: &mut [u8]) -> io::Result<()> {
+    // Slicing at byte offsets needs every character to be a single byte.
+    if !hex.is_ascii() {
+        return Err(invalid_data("seed is not hex"));
+    }
+    if hex.len() != 2 * seed.len() {
+        return Err(invalid_data("seed has the wrong length"));
+    }
+    for (i, byte) in seed.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[(2 * i)..(2 * i + 2)], 16)
+            .map_err(invalid_data)?;
+    }
+    Ok(())
+}
+
+fn read_line<B: BufRead>(reader: &mut B) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        let err_kind = io::ErrorKind::UnexpectedEof;
+        return Err(io::Error::new(err_kind, "connection closed"));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+/// Serve chains of `runner` to coordinating processes, one request per
+/// connection, until the listener fails.
+///
+/// A connection which fails, e.g. a client disconnecting before it has
+/// read the response, is passed to `on_error` and does not stop the worker.
+pub fn serve<M, A, R, F>(
+    listener: &TcpListener,
+    runner: &Runner<M, A, R>,
+    mut on_error: F,
+) -> io::Result<()>
+where
+    M: 'static + Clone + Send + Sync + Wire,
+    A: 'static + SteppingAlg<M, R> + Send + Sync + Clone,
+    R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+    F: FnMut(io::Error),
+{
+    for stream in listener.incoming() {
+        if let Err(err) = serve_one(stream?, runner) {
+            on_error(err);
+        }
+    }
+    Ok(())
+}
+
+/// Serve a single request on `stream`.
+pub fn serve_one<M, A, R>(
+    stream: TcpStream,
+    runner: &Runner<M, A, R>,
+) -> io::Result<()>
+where
+    M: 'static + Clone + Send + Sync + Wire,
+    A: 'static + SteppingAlg<M, R> + Send + Sync + Clone,
+    R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let request = read_line(&mut reader)
+        .and_then(|header| parse_request(runner, &header))
+        .and_then(|(runner, seed)| {
+            let init = M::decode(&read_line(&mut reader)?)?;
+            Ok((runner, seed, init))
+        });
+
+    match request {
+        Ok((runner, seed, init)) => {
+            let chains = runner.run(&mut R::from_seed(seed), init);
+            for chain in chains.iter() {
+                writeln!(writer, "CHAIN {}", chain.len())?;
+                for draw in chain.iter() {
+                    writeln!(writer, "{}", draw.encode())?;
+                }
+            }
+            writeln!(writer, "END")?;
+        }
+        Err(err) => writeln!(writer, "ERR {}", err)?,
+    }
+    writer.flush()
+}
+
+fn parse_request<M, A, R>(
+    runner: &Runner<M, A, R>,
+    header: &str,
+) -> io::Result<(Runner<M, A, R>, R::Seed)>
+where
+    M: 'static + Clone + Send + Sync,
+    A: 'static + SteppingAlg<M, R> + Send + Sync + Clone,
+    R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+{
+    let fields: Vec<&str> = header.split_whitespace().collect();
+    if fields.len() != 7 || fields[0] != "RUN" {
+        return Err(invalid_data(format!("malformed request: {}", header)));
+    }
+    let number = |s: &str| s.parse::<usize>().map_err(invalid_data);
+
+    let mut configured = runner
+        .chains(number(fields[1])?)
+        .warmup(number(fields[2])?)
+        .samples(number(fields[3])?)
+        .thinning(number(fields[4])?.max(1));
+    configured.keep_warmup = fields[5].parse().map_err(invalid_data)?;
+
+    let mut seed = R::Seed::default();
+    decode_seed(fields[6], seed.as_mut())?;
+    Ok((configured, seed))
+}
+
+fn request_chains<M: Wire>(
+    address: &SocketAddr,
+    header: &str,
+    init: &str,
+) -> io::Result<Vec<Vec<M>>> {
+    let stream = TcpStream::connect(address)?;
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    writeln!(writer, "{}", header)?;
+    writeln!(writer, "{}", init)?;
+    writer.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut chains = Vec::new();
+    loop {
+        let line = read_line(&mut reader)?;
+        if line == "END" {
+            return Ok(chains);
+        } else if line.starts_with("ERR ") {
+            let msg = format!("worker {}: {}", address, &line[4..]);
+            return Err(io::Error::new(io::ErrorKind::Other, msg));
+        } else if line.starts_with("CHAIN ") {
+            let n: usize = line[6..].parse().map_err(invalid_data)?;
+            let chain = (0..n)
+                .map(|_| read_line(&mut reader).and_then(|l| M::decode(&l)))
+                .collect::<io::Result<Vec<M>>>()?;
+            chains.push(chain);
+        } else {
+            return Err(invalid_data(format!("unexpected response: {}", line)));
+        }
+    }
+}
+
+impl<M, A, R> Runner<M, A, R>
+where
+    M: Clone + Sync + Send + Wire,
+    A: SteppingAlg<M, R> + Send + Sync + Clone,
+    M: 'static,
+    A: 'static,
+    R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+{
+    /// Run this config's chains on the worker processes at `workers`,
+    /// spreading the chains evenly between them.
+    ///
+    /// Each worker must `serve` a `Runner` with the same stepper; the
+    /// warmup, samples, thinning and warmup retention set here override the
+    /// worker's. Every worker is given its own seed drawn from `rng`.
+    pub fn run_distributed(
+        &self,
+        rng: &mut R,
+        init_model: M,
+        workers: &[SocketAddr],
+    ) -> io::Result<Vec<Vec<M>>> {
+        if workers.is_empty() {
+            return Err(invalid_input("no workers given"));
+        }
+        let init = init_model.encode();
+
+        let jobs: Vec<(SocketAddr, String)> = workers
+            .iter()
+            .enumerate()
+            .map(|(i, address)| {
+                let n_workers = workers.len();
+                let extra = if i < self.n_chains % n_workers { 1 } else { 0 };
+                (address, self.n_chains / n_workers + extra)
+            })
+            .filter(|&(_, n_chains)| n_chains > 0)
+            .map(|(address, n_chains)| {
+                let mut seed = R::Seed::default();
+                rng.fill(seed.as_mut());
+                let header = format!(
+                    "RUN {} {} {} {} {} {}",
+                    n_chains,
+                    self.warmup_steps,
+                    self.samples,
+                    self.thinning,
+                    self.keep_warmup,
+                    encode_seed(seed.as_mut())
+                );
+                (*address, header)
+            })
+            .collect();
+
+        // Requests block on the network, so they get their own threads
+        // rather than occupying the rayon pool the chains may run on.
+        let results: Vec<io::Result<Vec<Vec<M>>>> = thread::scope(|scope| {
+            let handles: Vec<_> = jobs
+                .iter()
+                .map(|(address, header)| {
+                    let init = &init;
+                    scope.spawn(move || request_chains(address, header, init))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Request thread panicked."))
+                .collect()
+        });
+
+        let mut chains = Vec::with_capacity(self.n_chains);
+        for result in results {
+            chains.extend(result?);
+        }
+        Ok(chains)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use steppers::SRWM;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    fn log_likelihood(x: &f64) -> f64 {
+        -0.5 * (x - 1.0) * (x - 1.0)
+    }
+
+    type Alg = SRWM<Gaussian, f64, f64, f64, fn(&f64) -> f64>;
+
+    fn runner() -> Runner<f64, Alg, StdRng> {
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            Lens::new(|x: &f64| *x, |_: &f64, y: f64| y),
+        );
+        let log_likelihood: fn(&f64) -> f64 = log_likelihood;
+        Runner::new(SRWM::new(parameter, log_likelihood, Some(1.0)).unwrap())
+    }
+
+    #[test]
+    fn workers_return_chains() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        // Serve one request for each of the two workers listed below.
+        let worker = thread::spawn(move || {
+            let runner = runner();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                serve_one(stream, &runner).unwrap();
+            }
+        });
+
+        let mut rng = StdRng::from_seed(SEED);
+        let chains = runner()
+            .chains(3)
+            .warmup(10)
+            .samples(25)
+            .run_distributed(&mut rng, 0.0, &[address, address])
+            .unwrap();
+        worker.join().unwrap();
+
+        assert_eq!(chains.len(), 3);
+        assert!(chains.iter().all(|chain| chain.len() == 25));
+        assert!(chains[0].iter().any(|&x| x != chains[0][0]));
+    }
+
+    #[test]
+    fn malformed_requests_are_reported() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let worker = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_one(stream, &runner()).unwrap();
+        });
+
+        let result: io::Result<Vec<Vec<f64>>> =
+            request_chains(&address, "RUN 1 2", "0.0");
+        worker.join().unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn failed_connections_do_not_stop_the_worker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(&listener, &runner(), |_| ()));
+
+        // A client which hangs up without sending a request
+        drop(TcpStream::connect(&address).unwrap());
+
+        let mut rng = StdRng::from_seed(SEED);
+        let chains = runner()
+            .warmup(10)
+            .samples(5)
+            .run_distributed(&mut rng, 0.0, &[address])
+            .unwrap();
+        assert_eq!(chains[0].len(), 5);
+    }
+
+    #[test]
+    fn seeds_round_trip() {
+        let seed: Vec<u8> = (0..32).map(|i| (i * 7) as u8).collect();
+        let mut decoded = [0u8; 32];
+        decode_seed(&encode_seed(&seed), &mut decoded).unwrap();
+        assert_eq!(&decoded[..], &seed[..]);
+
+        // Multi-byte characters of the right total length are rejected
+        // rather than split.
+        let euros: String = "\u{20ac}".repeat(21) + "0";
+        assert_eq!(euros.len(), 64);
+        assert!(decode_seed(&euros, &mut decoded).is_err());
+        assert!(decode_seed("zz", &mut [0u8; 1]).is_err());
+    }
+}
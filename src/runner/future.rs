@@ -0,0 +1,332 @@
+//! Running chains in the background of an async service
+//!
+//! `Runner::run_async` does the sampling on a thread of its own and returns
+//! a `std::future::Future` of the draws, so it never blocks an executor's
+//! threads, together with a stream of progress reports while it runs.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::panic;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use rand::prelude::*;
+
+use runner::{DrawSink, Runner};
+use steppers::SteppingAlg;
+
+/// Progress of one chain of a run started with `run_async`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress<M> {
+    /// Index of the chain
+    pub chain: usize,
+    /// Draws the chain has kept so far
+    pub draws: usize,
+    /// Draws the chain will keep in total
+    pub total: usize,
+    /// The chain's most recent draw
+    pub latest: M,
+}
+
+/// Keeps a chain's draws and reports every `every`th one as progress
+struct ProgressSink<M> {
+    chain: usize,
+    every: usize,
+    total: usize,
+    draws: Vec<M>,
+    queue: Arc<Mutex<Queue<M>>>,
+}
+
+impl<M: Clone> DrawSink<M> for ProgressSink<M> {
+    fn push<R: Rng>(&mut self, _rng: &mut R, draw: M) {
+        self.draws.push(draw);
+        let n = self.draws.len();
+        if n % self.every == 0 || n == self.total {
+            let progress = Progress {
+                chain: self.chain,
+                draws: n,
+                total: self.total,
+                latest: self.draws[n - 1].clone(),
+            };
+            // Nobody may be listening, which is fine.
+            self.queue.lock().unwrap().push(Some(progress));
+        }
+    }
+
+    fn draws(&self) -> &[M] {
+        &self.draws
+    }
+}
+
+// Progress reported but not yet taken by the stream, with whether every
+// chain has finished
+struct Queue<M> {
+    reports: VecDeque<Progress<M>>,
+    done: bool,
+    waker: Option<Waker>,
+}
+
+impl<M> Queue<M> {
+    // Add a report, or mark the run finished with `None`, waking the stream.
+    fn push(&mut self, progress: Option<Progress<M>>) {
+        match progress {
+            Some(progress) => self.reports.push_back(progress),
+            None => self.done = true,
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Progress of a run started with `run_async`, as an asynchronous stream
+/// ending when every chain has finished
+///
+/// `poll_next` has the signature of `futures::Stream::poll_next`, so the
+/// stream can be adapted for `futures` combinators with
+/// `futures::stream::poll_fn`; `next` awaits a single report.
+pub struct ProgressStream<M> {
+    queue: Arc<Mutex<Queue<M>>>,
+}
+
+impl<M> fmt::Debug for ProgressStream<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let queue = self.queue.lock().unwrap();
+        write!(
+            f,
+            "ProgressStream {{ pending: {}, done: {} }}",
+            queue.reports.len(),
+            queue.done
+        )
+    }
+}
+
+impl<M> ProgressStream<M> {
+    /// The next report, or `None` once the run has finished and every
+    /// report was taken
+    pub fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Progress<M>>> {
+        let mut queue = self.queue.lock().unwrap();
+        match queue.reports.pop_front() {
+            Some(progress) => Poll::Ready(Some(progress)),
+            None if queue.done => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// A future of the next report, or of `None` once the run has finished
+    /// and every report was taken
+    pub fn next<'a>(&'a mut self) -> NextProgress<'a, M> {
+        NextProgress { stream: self }
+    }
+}
+
+/// Future of the next report of a `ProgressStream`
+#[derive(Debug)]
+pub struct NextProgress<'a, M: 'a> {
+    stream: &'a mut ProgressStream<M>,
+}
+
+impl<'a, M> Future for NextProgress<'a, M> {
+    type Output = Option<Progress<M>>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Progress<M>>> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
+
+struct Shared<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// Draws of a run started with `run_async`, resolving when every chain has
+/// finished
+pub struct RunFuture<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> fmt::Debug for RunFuture<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let done = self.shared.lock().unwrap().result.is_some();
+        write!(f, "RunFuture {{ done: {} }}", done)
+    }
+}
+
+impl<T> Future for RunFuture<T> {
+    type Output = T;
+
+    /// Panics if sampling panicked.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(Ok(draws)) => Poll::Ready(draws),
+            Some(Err(err)) => panic::resume_unwind(err),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<M, A, R> Runner<M, A, R>
+where
+    M: Clone + Sync + Send,
+    A: SteppingAlg<M, R> + Send + Sync + Clone,
+    M: 'static,
+    A: 'static,
+    R: SeedableRng + Rng + fmt::Debug + Send + Sync + 'static,
+{
+    /// Run the steppers specified with this config on a background thread.
+    ///
+    /// Returns a future of the draws, as from `run`, and a stream of each
+    /// chain's progress, reported about a hundred times per chain and after
+    /// its last draw. Dropping the stream does not stop the run.
+    pub fn run_async(
+        &self,
+        rng: &mut R,
+        init_model: M,
+    ) -> (RunFuture<Vec<Vec<M>>>, ProgressStream<M>) {
+        let mut rng = R::from_rng(rng)
+            .expect("Failed to create seedable rng from input rng.");
+        let runner = self.clone();
+
+        let total = if self.keep_warmup {
+            self.warmup_steps + self.samples
+        } else {
+            self.samples
+        };
+        let queue = Arc::new(Mutex::new(Queue {
+            reports: VecDeque::new(),
+            done: false,
+            waker: None,
+        }));
+        let progress = ProgressStream {
+            queue: queue.clone(),
+        };
+        let sinks: Vec<ProgressSink<M>> = (0..self.n_chains)
+            .map(|chain| ProgressSink {
+                chain,
+                every: (total / 100).max(1),
+                total,
+                draws: Vec::with_capacity(total),
+                queue: queue.clone(),
+            })
+            .collect();
+
+        let shared = Arc::new(Mutex::new(Shared {
+            result: None,
+            waker: None,
+        }));
+        let future = RunFuture {
+            shared: shared.clone(),
+        };
+
+        thread::spawn(move || {
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                runner
+                    .run_sinks(&mut rng, init_model, sinks)
+                    .into_iter()
+                    .map(|sink| sink.draws)
+                    .collect()
+            }));
+            queue.lock().unwrap().push(None);
+            let mut shared = shared.lock().unwrap();
+            shared.result = Some(result);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        });
+
+        (future, progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use steppers::SRWM;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // Minimal executor polling a future on the current thread
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Model {
+        x: f64,
+    }
+
+    fn log_likelihood(m: &Model) -> f64 {
+        -0.5 * m.x * m.x
+    }
+
+    #[test]
+    fn run_async_reports_progress_and_resolves() {
+        let mut rng = StdRng::from_seed(SEED);
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let runner = Runner::new(
+            SRWM::new(parameter, log_likelihood, Some(1.0)).unwrap(),
+        ).warmup(100)
+        .samples(500)
+        .chains(2);
+
+        let (future, mut progress) =
+            runner.run_async(&mut rng, Model { x: 0.0 });
+        let mut updates: Vec<Progress<Model>> = Vec::new();
+        while let Some(update) = block_on(progress.next()) {
+            updates.push(update);
+        }
+        let draws = block_on(future);
+        assert_eq!(draws.len(), 2);
+        assert!(draws.iter().all(|chain| chain.len() == 500));
+
+        for chain in 0..2 {
+            let reported: Vec<&Progress<Model>> =
+                updates.iter().filter(|p| p.chain == chain).collect();
+            assert_eq!(reported.len(), 100);
+            let last = reported.last().unwrap();
+            assert_eq!(last.draws, 500);
+            assert_eq!(last.total, 500);
+            assert_eq!(last.latest, draws[chain][499]);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Callbacks at events in the life of a chain
+//!
+//! Hooks let a run be logged, plotted or checkpointed as it happens without
+//! changing the runner. Each is called on the chain's thread with a
+//! `HookContext` describing the event, so hooks shared between chains must
+//! synchronize any state they keep.
+
+use std::sync::Arc;
+
+/// Events in the life of a chain
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HookEvent {
+    /// Before the first step, with the initial model
+    ChainStart,
+    /// After the last warmup step
+    WarmupEnd,
+    /// After every `k`th draw following warmup
+    Every(usize),
+    /// After a step in which the stepper emitted a `NumericalWarning`, e.g.
+    /// for a proposal with a NaN acceptance ratio
+    Divergence,
+    /// After the last step
+    ChainEnd,
+}
+
+/// What a hook is told about an event
+#[derive(Debug)]
+pub struct HookContext<'a, M: 'a> {
+    pub event: HookEvent,
+    /// Index of the chain in the run
+    pub chain: usize,
+    /// Number of steps the chain has taken, including warmup
+    pub iteration: usize,
+    /// The chain's current model
+    pub model: &'a M,
+}
+
+/// A callback run at an event
+pub type Hook<M> = Arc<dyn Fn(&HookContext<M>) + Send + Sync>;
+
+/// Hooks registered for a run, each with the event it is called at
+pub struct Hooks<M> {
+    hooks: Vec<(HookEvent, Hook<M>)>,
+}
+
+impl<M> Clone for Hooks<M> {
+    fn clone(&self) -> Self {
+        Hooks {
+            hooks: self.hooks.clone(),
+        }
+    }
+}
+
+impl<M> Default for Hooks<M> {
+    fn default() -> Self {
+        Hooks { hooks: Vec::new() }
+    }
+}
+
+impl<M> Hooks<M> {
+    pub fn new() -> Self {
+        Hooks::default()
+    }
+
+    /// Call `hook` at `event`, after any hooks already registered for it.
+    pub fn push(&mut self, event: HookEvent, hook: Hook<M>) {
+        if let HookEvent::Every(k) = event {
+            assert!(k > 0, "hooks must be called every k > 0 draws.");
+        }
+        self.hooks.push((event, hook));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Whether any hook waits for divergences, which are only looked for
+    /// when one does
+    pub fn watches_divergence(&self) -> bool {
+        self.hooks.iter().any(|(e, _)| *e == HookEvent::Divergence)
+    }
+
+    /// Call the hooks registered for `event`.
+    pub fn fire(
+        &self,
+        event: HookEvent,
+        chain: usize,
+        iteration: usize,
+        model: &M,
+    ) {
+        self.fire_matching(|e| e == event, chain, iteration, model);
+    }
+
+    /// Call the `Every` hooks due after draw number `draw`, counting from 1.
+    pub fn fire_draw(
+        &self,
+        chain: usize,
+        iteration: usize,
+        draw: usize,
+        model: &M,
+    ) {
+        let due = |e: HookEvent| match e {
+            HookEvent::Every(k) => draw % k == 0,
+            _ => false,
+        };
+        self.fire_matching(due, chain, iteration, model);
+    }
+
+    fn fire_matching<F: Fn(HookEvent) -> bool>(
+        &self,
+        matches: F,
+        chain: usize,
+        iteration: usize,
+        model: &M,
+    ) {
+        for (event, hook) in self.hooks.iter() {
+            if matches(*event) {
+                hook(&HookContext {
+                    event: *event,
+                    chain,
+                    iteration,
+                    model,
+                });
+            }
+        }
+    }
+}
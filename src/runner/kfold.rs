@@ -0,0 +1,188 @@
+//! K-fold cross-validation over a runner
+
+use std::fmt;
+use rand::prelude::*;
+use rand::seq::SliceRandom;
+use rv::misc::logsumexp;
+
+use runner::Runner;
+use steppers::SteppingAlg;
+
+/// Held-out log predictive densities from `kfold`
+#[derive(Clone, Debug, PartialEq)]
+pub struct KFoldResult {
+    /// Fold each datum was held out in, indexed as the input data
+    pub folds: Vec<usize>,
+    /// Log predictive density of each datum when held out
+    pub pointwise: Vec<f64>,
+    /// Expected log predictive density, the sum of `pointwise`
+    pub elpd: f64,
+    /// Standard error of `elpd`
+    pub se: f64,
+}
+
+/// Estimate the expected log predictive density by K-fold cross-validation.
+///
+/// The data are shuffled into `k` folds. For each fold `factory` builds a
+/// stepper from the remaining training data, which is run with `runner`'s
+/// settings (chains, warmup, samples and thinning) on the shared rayon thread
+/// pool. Each held-out datum's log predictive density is the log of the mean
+/// of `ln_pred(draw, datum)` exponentiated over every draw of every chain.
+///
+/// # Parameters
+/// * `runner` Template for the runs, its own stepper is not used
+/// * `rng` Random number generator
+/// * `init_model` Initial model for every fold
+/// * `data` Full dataset
+/// * `k` Number of folds, between 2 and the number of data
+/// * `factory` Builds a stepper whose likelihood uses the given training data
+/// * `ln_pred` Log density of a datum given a model
+pub fn kfold<M, A, R, X, F, P>(
+    runner: &Runner<M, A, R>,
+    rng: &mut R,
+    init_model: M,
+    data: &[X],
+    k: usize,
+    factory: F,
+    ln_pred: P,
+) -> KFoldResult
+where
+    M: 'static + Clone + Send + Sync,
+    A: 'static + SteppingAlg<M, R> + Send + Sync + Clone,
+    R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+    X: Clone,
+    F: Fn(&[X]) -> A,
+    P: Fn(&M, &X) -> f64,
+{
+    let n = data.len();
+    assert!(k > 1, "kfold requires at least 2 folds.");
+    assert!(k <= n, "kfold requires at least as many data as folds.");
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.shuffle(rng);
+    let mut folds = vec![0; n];
+    for (position, &i) in order.iter().enumerate() {
+        folds[i] = position % k;
+    }
+
+    let mut pointwise = vec![0.0; n];
+    for fold in 0..k {
+        let training: Vec<X> = data
+            .iter()
+            .zip(folds.iter())
+            .filter(|(_, &f)| f != fold)
+            .map(|(x, _)| x.clone())
+            .collect();
+
+        let fold_runner = Runner {
+            stepper: factory(&training),
+            ..runner.clone()
+        };
+        let draws: Vec<M> = fold_runner
+            .run(rng, init_model.clone())
+            .into_iter()
+            .flat_map(|chain| chain.into_iter())
+            .collect();
+        let ln_n_draws = (draws.len() as f64).ln();
+
+        let held_out =
+            data.iter().enumerate().filter(|(i, _)| folds[*i] == fold);
+        for (i, x) in held_out {
+            let lns: Vec<f64> = draws.iter().map(|m| ln_pred(m, x)).collect();
+            pointwise[i] = logsumexp(&lns) - ln_n_draws;
+        }
+    }
+
+    let elpd: f64 = pointwise.iter().sum();
+    let mean = elpd / n as f64;
+    let var = pointwise
+        .iter()
+        .map(|p| (p - mean) * (p - mean))
+        .sum::<f64>()
+        / (n - 1) as f64;
+
+    KFoldResult {
+        folds,
+        pointwise,
+        elpd,
+        se: (n as f64 * var).sqrt(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::rngs::StdRng;
+    use rv::dist::*;
+    use rv::traits::Rv;
+    use steppers::SRWM;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Copy, Clone, Debug)]
+    struct Model {
+        mu: f64,
+    }
+
+    #[test]
+    fn gaussian_mean_matches_analytic_predictive() {
+        let mut rng = StdRng::from_seed(SEED);
+        let data: Vec<f64> =
+            Gaussian::new(1.0, 1.0).unwrap().sample(40, &mut rng);
+
+        let factory = |training: &[f64]| {
+            let training = training.to_vec();
+            let parameter = Parameter::new(
+                "mu".to_string(),
+                Uniform::new(-20.0, 20.0).unwrap(),
+                make_lens!(Model, f64, mu),
+            );
+            let log_likelihood = move |m: &Model| {
+                let g = Gaussian::new(m.mu, 1.0).unwrap();
+                training.iter().map(|x| g.ln_f(x)).sum::<f64>()
+            };
+            SRWM::new(parameter, log_likelihood, Some(0.3)).unwrap()
+        };
+        let ln_pred =
+            |m: &Model, x: &f64| Gaussian::new(m.mu, 1.0).unwrap().ln_f(x);
+
+        let runner = Runner::new(factory(&data))
+            .warmup(500)
+            .samples(2000)
+            .chains(2);
+        let init = Model { mu: 0.0 };
+        let result =
+            kfold(&runner, &mut rng, init, &data, 5, factory, ln_pred);
+
+        // Every datum is held out exactly once, in folds of equal size.
+        assert_eq!(result.pointwise.len(), data.len());
+        for fold in 0..5 {
+            assert_eq!(result.folds.iter().filter(|&&f| f == fold).count(), 8);
+        }
+
+        // Under a flat prior the predictive is N(mean, sqrt(1 + 1 / n)).
+        let expected: f64 = (0..data.len())
+            .map(|i| {
+                let training: Vec<f64> = (0..data.len())
+                    .filter(|&j| result.folds[j] != result.folds[i])
+                    .map(|j| data[j])
+                    .collect();
+                let n = training.len() as f64;
+                let mean = training.iter().sum::<f64>() / n;
+                Gaussian::new(mean, (1.0 + 1.0 / n).sqrt())
+                    .unwrap()
+                    .ln_f(&data[i])
+            })
+            .sum();
+
+        println!(
+            "elpd = {} +/- {}, expected = {}",
+            result.elpd, result.se, expected
+        );
+        assert!((result.elpd - expected).abs() < 0.5);
+        assert!(result.se > 0.0);
+    }
+}
@@ -2,12 +2,57 @@
 
 use std::marker::PhantomData;
 use steppers::SteppingAlg;
+use events::{self, EventSink};
+use parameter::{Parameter, ParamId};
 use rand::prelude::*;
+use rv::traits::Rv;
 use rayon;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::fmt;
+use std::ops::DerefMut;
+use std::time::{Duration, Instant, SystemTime};
 
 pub mod utils;
+mod assimilation;
+mod batch;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "distributed")]
+pub mod distributed;
+mod future;
+pub mod hooks;
+mod kfold;
+mod modes;
+mod pipeline;
+mod preset;
+mod provenance;
+mod sample;
+mod session;
+mod sink;
+mod stepper_rv;
+mod stratified;
+mod timing;
+
+pub use self::assimilation::ResampleMove;
+pub use self::batch::BatchRunner;
+pub use self::future::{NextProgress, Progress, ProgressStream, RunFuture};
+pub use self::hooks::{HookContext, HookEvent, Hooks};
+pub use self::kfold::{kfold, KFoldResult};
+pub use self::modes::{ModeSearch, Modes};
+pub use self::pipeline::{Burn, ChainIter, Pipeline, Stage, Thin, Transform};
+pub use self::preset::Preset;
+pub use self::provenance::{Host, Provenance};
+pub use self::sample::Sample;
+pub use self::session::{ChainSnapshot, ChainState, Session};
+pub use self::sink::{DrawSink, Reservoir};
+pub use self::stepper_rv::StepperRv;
+pub use self::stratified::StratifiedInit;
+pub use self::timing::{
+    slow_steps, SlowStep, StepTime, StepTimer, SLOW_MADS,
+};
+
+/// Sets a fixed parameter's value in a model
+type Fix<M> = Arc<dyn Fn(&M) -> M + Send + Sync>;
 
 pub struct Runner<M, A, R>
 where
@@ -21,6 +66,11 @@ where
     pub samples: usize,
     pub keep_warmup: bool,
     pub thinning: usize,
+    fixed: Vec<(ParamId, Fix<M>)>,
+    hooks: Hooks<M>,
+    step_timing: Option<usize>,
+    verbose: bool,
+    pool: Option<Arc<rayon::ThreadPool>>,
     phantom_m: PhantomData<M>,
     phantom_a: PhantomData<A>,
     phantom_r: PhantomData<R>,
@@ -40,6 +90,11 @@ where
             samples: self.samples,
             keep_warmup: self.keep_warmup,
             thinning: self.thinning,
+            fixed: self.fixed.clone(),
+            hooks: self.hooks.clone(),
+            step_timing: self.step_timing,
+            verbose: self.verbose,
+            pool: self.pool.clone(),
             phantom_m: PhantomData,
             phantom_a: PhantomData,
             phantom_r: PhantomData,
@@ -63,6 +118,11 @@ where
             samples: 1000,
             keep_warmup: false,
             thinning: 1,
+            fixed: Vec::new(),
+            hooks: Hooks::new(),
+            step_timing: None,
+            verbose: false,
+            pool: None,
             phantom_m: PhantomData,
             phantom_a: PhantomData,
             phantom_r: PhantomData,
@@ -112,6 +172,138 @@ where
         }
     }
 
+    /// Condition on `parameter` taking `value`: it is set in the initial
+    /// model and its stepper skips its updates, so it is constant in every
+    /// chain.
+    pub fn fix<D, T>(&self, parameter: &Parameter<D, T, M>, value: T) -> Self
+    where
+        D: Rv<T> + Clone,
+        T: 'static + Clone + Send + Sync,
+    {
+        let lens = parameter.lens.clone();
+        let set: Fix<M> = Arc::new(move |m: &M| lens.set(m, value.clone()));
+        let mut fixed: Vec<(ParamId, Fix<M>)> = self
+            .fixed
+            .iter()
+            .filter(|(id, _)| id != parameter.id())
+            .cloned()
+            .collect();
+        fixed.push((parameter.id().clone(), set));
+        Runner {
+            fixed,
+            ..(*self).clone()
+        }
+    }
+
+    /// Call `hook` at `event` in every chain, e.g. to log progress with
+    /// `HookEvent::Every(100)` or checkpoint the model at
+    /// `HookEvent::WarmupEnd`.
+    pub fn hook<F>(&self, event: HookEvent, hook: F) -> Self
+    where
+        F: Fn(&HookContext<M>) + Send + Sync + 'static,
+    {
+        let mut hooks = self.hooks.clone();
+        hooks.push(event, Arc::new(hook));
+        Runner {
+            hooks,
+            ..(*self).clone()
+        }
+    }
+
+    /// Time every `every`th step of each chain and record the times in the
+    /// provenance of samples, so steps much slower than the rest are
+    /// reported by `Sample::slow_steps` and counted in the summary.
+    ///
+    /// Timing a step reads the clock twice, so timing every step costs
+    /// little next to all but the cheapest models.
+    pub fn time_steps(&self, every: usize) -> Self {
+        assert!(every > 0, "steps must be timed every k > 0 steps.");
+        Runner {
+            step_timing: Some(every),
+            ..(*self).clone()
+        }
+    }
+
+    /// Give `run_with_events` verbose sinks, so the events of each
+    /// Metropolis update include its breakdown into prior and likelihood
+    /// as an `Event::ProposalScored`, e.g. to see why a chain is stuck.
+    pub fn verbose(&self) -> Self {
+        Runner {
+            verbose: true,
+            ..(*self).clone()
+        }
+    }
+
+    /// Run the chains on `pool` instead of rayon's global pool.
+    ///
+    /// Parallel iterators and `rayon::join`s made while stepping, e.g. in
+    /// the log-likelihood, run on the pool of the chain calling them, so
+    /// this is how a likelihood is handed threads of its own: with one
+    /// chain, every thread of the pool but the chain's is free for it, and
+    /// the chain's own thread joins in while waiting on the likelihood's
+    /// tasks.
+    pub fn thread_pool(&self, pool: Arc<rayon::ThreadPool>) -> Self {
+        Runner {
+            pool: Some(pool),
+            ..(*self).clone()
+        }
+    }
+
+    /// Run the chains on a pool of `threads` threads dedicated to this
+    /// runner; see `thread_pool`.
+    ///
+    /// Meant for a single chain of an expensive model whose log-likelihood
+    /// is itself parallel, e.g. a `par_iter` over the observations, which
+    /// then has `threads` threads to itself.
+    pub fn likelihood_threads(&self, threads: usize) -> Self {
+        assert!(threads > 0, "threads must be greater than 0.");
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .expect("Failed to create thread pool.");
+        self.thread_pool(Arc::new(pool))
+    }
+
+    /// Run `f` on this runner's pool, if it has one.
+    fn install<T, F>(&self, f: F) -> T
+    where
+        T: Send,
+        F: FnOnce() -> T + Send,
+    {
+        match self.pool {
+            Some(ref pool) => pool.install(f),
+            None => f(),
+        }
+    }
+
+    /// Parameters fixed with `fix`
+    pub fn fixed(&self) -> Vec<ParamId> {
+        self.fixed.iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    /// Parameters updated by this runner's stepper
+    pub fn parameters(&self) -> Vec<ParamId> {
+        self.stepper.parameters()
+    }
+
+    /// `n` independent draws from the joint prior of the stepper's
+    /// parameters, each drawn with `draw_prior` from a fresh clone of
+    /// `template`
+    ///
+    /// Parameters fixed with `fix` keep their values in every draw, and
+    /// anything the stepper does not update keeps its value in `template`.
+    /// E.g. the models of simulation-based calibration or the prior draws
+    /// of `prior_posterior_overlap`.
+    pub fn sample_prior(&self, rng: &mut R, template: M, n: usize) -> Vec<M> {
+        let mut stepper = self.stepper.clone();
+        let template = self.fixed.iter().fold(template, |m, (id, set)| {
+            stepper.fix(id);
+            set(&m)
+        });
+        (0..n)
+            .map(|_| stepper.draw_prior(rng, template.clone()))
+            .collect()
+    }
 
     /// Run the steppers specified with this config.
     pub fn run(&self, rng: &mut R, init_model: M) -> Vec<Vec<M>>
@@ -124,25 +316,650 @@ where
 
         let rng = Arc::new(RwLock::new(rng));
 
+        let mut stepper = self.stepper.clone();
+        let init_model = self.fixed.iter().fold(init_model, |m, (id, set)| {
+            stepper.fix(id);
+            set(&m)
+        });
+
         let results = Arc::new(RwLock::new({
             Vec::with_capacity(n_chains)
         }));
 
-        rayon::scope(|scope| {
-            (0..n_chains).for_each(|_| {
+        self.install(|| rayon::scope(|scope| {
+            (0..n_chains).for_each(|chain| {
                 let results = results.clone();
                 let init_model = init_model.clone();
                 let results = results.clone();
-                let stepper = self.stepper.clone();
+                let stepper = stepper.clone();
                 let rng = Arc::clone(&rng);
+                let hooks = &self.hooks;
                 scope.spawn(move |_| {
-                    let draws = utils::draw_from_stepper::<M, A, R>(rng, stepper, init_model, n_samples, warmup_steps, thinning, keep_warmup);
+                    let mut rng: R = SeedableRng::from_rng(
+                        rng.write()
+                            .expect("Failed to get write access to rng")
+                            .deref_mut(),
+                    ).expect("Failed to create seedable rng from input rng.");
+                    let capacity = if keep_warmup {
+                        warmup_steps + n_samples
+                    } else {
+                        n_samples
+                    };
+                    let settings = utils::ChainSettings {
+                        n_draws: n_samples,
+                        n_warmup: warmup_steps,
+                        thinning,
+                        keep_warmup,
+                        hooks,
+                        chain,
+                        events: None,
+                        timer: None,
+                    };
+                    let draws = utils::step_into_sink_with_hooks(
+                        &mut rng,
+                        stepper,
+                        init_model,
+                        Vec::with_capacity(capacity),
+                        &settings,
+                    );
                     let mut res = results.write().unwrap();
                     res.push(draws);
                 })
             });
-        });
+        }));
         let draws = results.read().unwrap().to_vec();
         draws
     }
+
+    /// As `run`, also returning the events each chain's stepper emitted,
+    /// e.g. every proposal's outcome, in the order they were emitted.
+    pub fn run_with_events(
+        &self,
+        rng: &mut R,
+        init_model: M,
+    ) -> (Vec<Vec<M>>, Vec<Vec<events::Event>>) {
+        let seeds = (0..self.n_chains)
+            .map(|_| draw_seed::<R, _>(rng))
+            .collect();
+        let sinks: Vec<EventSink> = (0..self.n_chains)
+            .map(|_| {
+                if self.verbose {
+                    EventSink::verbose()
+                } else {
+                    EventSink::new()
+                }
+            })
+            .collect();
+        let draws = (0..self.n_chains)
+            .map(|_| Vec::with_capacity(self.samples))
+            .collect();
+        let chains = self
+            .run_seeded(
+                seeds,
+                vec![init_model; self.n_chains],
+                draws,
+                sinks.iter().cloned().map(Some).collect(),
+            )
+            .into_iter()
+            .map(|(draws, _)| draws)
+            .collect();
+        (chains, sinks.iter().map(|s| s.drain()).collect())
+    }
+
+    /// Run the steppers specified with this config, labeling the draws with
+    /// the parameters they update so they can be merged with other runs,
+    /// and recording the run's provenance.
+    pub fn sample(&self, rng: &mut R, init_model: M) -> Sample<M> {
+        let seed = draw_seed::<R, _>(rng);
+        self.sample_from_seed(seed, init_model)
+    }
+
+    /// As `sample`, drawing every chain's seed from `seed`. The same seed,
+    /// configuration and initial model give the same draws.
+    pub fn sample_from_seed(&self, seed: R::Seed, init_model: M)
+        -> Sample<M>
+    {
+        self.sample_seeded(seed, vec![init_model; self.n_chains])
+    }
+
+    /// As `sample`, starting each chain from its own model of
+    /// `init_models`, e.g. overdispersed initial values from
+    /// `stratified_inits`.
+    ///
+    /// Panics unless there is one initial model per chain.
+    pub fn sample_from_inits(&self, rng: &mut R, init_models: Vec<M>)
+        -> Sample<M>
+    {
+        assert_eq!(
+            init_models.len(),
+            self.n_chains,
+            "each chain needs one initial model."
+        );
+        let seed = draw_seed::<R, _>(rng);
+        self.sample_seeded(seed, init_models)
+    }
+
+    fn sample_seeded(&self, mut seed: R::Seed, init_models: Vec<M>)
+        -> Sample<M>
+    {
+        let master_seed = seed.as_mut().to_vec();
+        let mut master = R::from_seed(seed);
+        let mut seeds: Vec<R::Seed> = (0..self.n_chains)
+            .map(|_| draw_seed::<R, _>(&mut master))
+            .collect();
+        let chain_seeds = seeds.iter_mut().map(|s| s.as_mut().to_vec());
+        let chain_seeds: Vec<Vec<u8>> = chain_seeds.collect();
+
+        let started = SystemTime::now();
+        let sinks = (0..self.n_chains)
+            .map(|_| Vec::with_capacity(self.samples))
+            .collect();
+        let (chains, timings): (Vec<Vec<M>>, Vec<_>) = self
+            .run_seeded(seeds, init_models, sinks, vec![None; self.n_chains])
+            .into_iter()
+            .unzip();
+        let (chain_durations, step_times) = timings.into_iter().unzip();
+
+        let provenance = Provenance {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            master_seed,
+            chain_seeds,
+            stepper: format!("{:?}", self.stepper),
+            spec: self.stepper.spec(),
+            n_chains: self.n_chains,
+            warmup_steps: self.warmup_steps,
+            samples: self.samples,
+            thinning: self.thinning,
+            keep_warmup: self.keep_warmup,
+            fixed: self.fixed(),
+            started,
+            finished: SystemTime::now(),
+            chain_durations,
+            step_times,
+            host: self.install(Host::current),
+        };
+
+        let mut sample = Sample::new(self.parameters(), chains);
+        sample.provenance.push(provenance);
+        sample
+    }
+
+    /// Run the steppers specified with this config, passing each chain's
+    /// draws to its own copy of `sink` instead of storing them all, e.g. a
+    /// `Reservoir` to bound memory. Returns one sink per chain.
+    pub fn run_into<S>(&self, rng: &mut R, init_model: M, sink: S) -> Vec<S>
+    where
+        S: DrawSink<M> + Clone + Send,
+    {
+        self.run_sinks(rng, init_model, vec![sink; self.n_chains])
+    }
+
+    /// Run one chain into each of `sinks`, returning them in the same order.
+    fn run_sinks<S>(&self, rng: &mut R, init_model: M, sinks: Vec<S>) -> Vec<S>
+    where
+        S: DrawSink<M> + Send,
+    {
+        let seeds = sinks.iter().map(|_| draw_seed::<R, _>(rng)).collect();
+        let events = sinks.iter().map(|_| None).collect();
+        let init_models = vec![init_model; sinks.len()];
+        self.run_seeded(seeds, init_models, sinks, events)
+            .into_iter()
+            .map(|(sink, _)| sink)
+            .collect()
+    }
+
+    /// Run one chain into each of `sinks`, the chain's generator seeded with
+    /// the corresponding seed and its first model the corresponding initial
+    /// model, returning the sinks in the same order along with the time each
+    /// chain took and the times of its steps, if they were timed.
+    fn run_seeded<S>(
+        &self,
+        seeds: Vec<R::Seed>,
+        init_models: Vec<M>,
+        sinks: Vec<S>,
+        events: Vec<Option<EventSink>>,
+    ) -> Vec<(S, (Duration, Vec<StepTime>))>
+    where
+        S: DrawSink<M> + Send,
+    {
+        let thinning = self.thinning;
+        let keep_warmup = self.keep_warmup;
+        let warmup_steps = self.warmup_steps;
+        let n_samples = self.samples;
+        let n_chains = sinks.len();
+
+        let mut stepper = self.stepper.clone();
+        self.fixed.iter().for_each(|(id, _)| stepper.fix(id));
+        let init_models = init_models.into_iter().map(|init_model| {
+            self.fixed.iter().fold(init_model, |m, (_, set)| set(&m))
+        });
+
+        let results = Arc::new(Mutex::new(Vec::with_capacity(n_chains)));
+
+        let rngs: Vec<R> = seeds.into_iter().map(R::from_seed).collect();
+
+        self.install(|| rayon::scope(|scope| {
+            let chains = sinks.into_iter().zip(rngs).zip(events);
+            chains.zip(init_models).enumerate().for_each(
+                |(i, (((sink, mut rng), events), init_model))| {
+                    let results = results.clone();
+                    let stepper = stepper.clone();
+                    let timer = self.step_timing.map(StepTimer::new);
+                    scope.spawn(move |_| {
+                        let start = Instant::now();
+                        let settings = utils::ChainSettings {
+                            n_draws: n_samples,
+                            n_warmup: warmup_steps,
+                            thinning,
+                            keep_warmup,
+                            hooks: &self.hooks,
+                            chain: i,
+                            events,
+                            timer: timer.clone(),
+                        };
+                        let sink = utils::step_into_sink_with_hooks(
+                            &mut rng,
+                            stepper,
+                            init_model,
+                            sink,
+                            &settings,
+                        );
+                        let elapsed = start.elapsed();
+                        let steps = timer.map_or(Vec::new(), |t| t.drain());
+                        let timing = (elapsed, steps);
+                        results.lock().unwrap().push((i, (sink, timing)));
+                    })
+                },
+            );
+        }));
+        let mut results = Arc::try_unwrap(results)
+            .ok()
+            .expect("Chains still hold their results.")
+            .into_inner()
+            .unwrap();
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
+
+/// A seed for `R` drawn from `rng`
+fn draw_seed<R: SeedableRng, G: Rng>(rng: &mut G) -> R::Seed {
+    let mut seed = R::Seed::default();
+    rng.fill(seed.as_mut());
+    seed
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use lens::*;
+    use rand::rngs::StdRng;
+    use rayon::prelude::*;
+    use rv::dist::Gaussian;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use steppers::SRWM;
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Model {
+        a: f64,
+    }
+
+    fn log_likelihood(_m: &Model) -> f64 {
+        0.0
+    }
+
+    #[test]
+    fn fixed_parameters_are_constant() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+
+        let free = Runner::new(
+            SRWM::new(a.clone(), log_likelihood, Some(1.0)).unwrap(),
+        ).warmup(10)
+        .samples(100);
+        let fixed = free.fix(&a, 1.0).fix(&a, 2.0).chains(2);
+        assert_eq!(fixed.fixed(), vec![a.id().clone()]);
+        assert!(free.fixed().is_empty());
+
+        let results = fixed.run(&mut rng, Model { a: 0.0 });
+        assert_eq!(results.len(), 2);
+        for chain in results.iter() {
+            assert_eq!(chain.len(), 100);
+            assert!(chain.iter().all(|m| m.a == 2.0));
+        }
+
+        let results = free.run(&mut rng, Model { a: 0.0 });
+        assert!(results[0].iter().any(|m| m.a != results[0][0].a));
+    }
+
+    #[test]
+    fn prior_draws_keep_fixed_parameters() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let runner = Runner::new(
+            SRWM::new(a.clone(), log_likelihood, Some(1.0)).unwrap(),
+        );
+
+        let draws = runner.sample_prior(&mut rng, Model { a: 5.0 }, 100);
+        assert_eq!(draws.len(), 100);
+        assert!(draws.iter().all(|m| m.a != 5.0));
+        assert!(draws.iter().any(|m| m.a != draws[0].a));
+
+        let fixed = runner.fix(&a, 2.0).sample_prior(&mut rng, draws[0], 10);
+        assert_eq!(fixed, vec![Model { a: 2.0 }; 10]);
+    }
+
+    #[test]
+    fn samples_of_separate_runs_merge() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let runner = Runner::new(
+            SRWM::new(a.clone(), log_likelihood, Some(1.0)).unwrap(),
+        ).warmup(10)
+        .samples(20);
+
+        let first = runner.chains(2).sample(&mut rng, Model { a: 0.0 });
+        let second = runner.sample(&mut rng, Model { a: 1.0 });
+        assert_eq!(first.parameters, vec![a.id().clone()]);
+
+        let merged = Sample::merge(vec![first, second]).unwrap();
+        assert_eq!(merged.n_chains(), 3);
+        assert_eq!(merged.n_draws(), 60);
+        assert_eq!(merged.provenance.len(), 2);
+    }
+
+    #[test]
+    fn provenance_reproduces_the_run() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let runner = Runner::new(
+            SRWM::new(a.clone(), log_likelihood, Some(1.0)).unwrap(),
+        ).warmup(10)
+        .samples(20)
+        .chains(3);
+
+        let sample = runner.sample(&mut rng, Model { a: 0.0 });
+        let provenance = &sample.provenance[0];
+        assert_eq!(provenance.chain_seeds.len(), 3);
+        assert_eq!(provenance.chain_durations.len(), 3);
+        assert_eq!(provenance.samples, 20);
+        assert!(provenance.stepper.starts_with("SRWM"));
+        assert!(provenance.finished >= provenance.started);
+        assert!(provenance.to_string().starts_with("rmcmc "));
+
+        let mut seed = [0; 32];
+        seed.copy_from_slice(&provenance.master_seed);
+        let rerun = runner.sample_from_seed(seed, Model { a: 0.0 });
+        assert_eq!(rerun.chains, sample.chains);
+        assert_eq!(rerun.provenance[0].chain_seeds, provenance.chain_seeds);
+    }
+
+    #[test]
+    fn timed_steps_are_kept_in_provenance() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let runner = Runner::new(
+            SRWM::new(a, log_likelihood, Some(1.0)).unwrap(),
+        ).warmup(10)
+        .samples(20)
+        .chains(2);
+
+        let sample = runner.time_steps(5).sample(&mut rng, Model { a: 0.0 });
+        let step_times = &sample.provenance[0].step_times;
+        assert_eq!(step_times.len(), 2);
+        let iterations: Vec<usize> =
+            step_times[1].iter().map(|t| t.iteration).collect();
+        assert_eq!(iterations, vec![5, 10, 15, 20, 25, 30]);
+        assert!(sample.summary(&[("a", |m| m.a)]).slow_steps.is_some());
+
+        let untimed = runner.sample(&mut rng, Model { a: 0.0 });
+        assert!(!untimed.has_step_times());
+        assert_eq!(untimed.summary(&[("a", |m| m.a)]).slow_steps, None);
+    }
+
+    #[test]
+    fn run_into_reservoirs_bounds_draws() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let runner = Runner::new(
+            SRWM::new(a, log_likelihood, Some(1.0)).unwrap(),
+        ).warmup(10)
+        .samples(1000)
+        .chains(3);
+
+        let sinks =
+            runner.run_into(&mut rng, Model { a: 0.0 }, Reservoir::new(50));
+        assert_eq!(sinks.len(), 3);
+        for sink in sinks.iter() {
+            assert_eq!(sink.seen(), 1000);
+            assert_eq!(sink.draws().len(), 50);
+        }
+
+        let vecs = runner.run_into(&mut rng, Model { a: 0.0 }, Vec::new());
+        assert!(vecs.iter().all(|draws| draws.len() == 1000));
+    }
+    #[test]
+    fn hooks_are_called_at_chain_events() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        // Starting outside the support, proposals which stay outside have
+        // a NaN acceptance ratio.
+        let log_likelihood = |m: &Model| {
+            if m.a > 1.0 {
+                std::f64::NEG_INFINITY
+            } else {
+                0.0
+            }
+        };
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let record = |events: &Arc<Mutex<Vec<(HookEvent, usize, usize)>>>| {
+            let events = events.clone();
+            move |c: &HookContext<Model>| {
+                events.lock().unwrap().push((c.event, c.chain, c.iteration))
+            }
+        };
+        let runner = Runner::new(
+            SRWM::new(a, log_likelihood, Some(1.0)).unwrap(),
+        ).warmup(10)
+        .samples(20)
+        .thinning(2)
+        .chains(2)
+        .hook(HookEvent::ChainStart, record(&events))
+        .hook(HookEvent::WarmupEnd, record(&events))
+        .hook(HookEvent::Every(5), record(&events))
+        .hook(HookEvent::ChainEnd, record(&events))
+        .hook(HookEvent::Divergence, record(&events));
+
+        runner.sample(&mut rng, Model { a: 2.0 });
+        let events = events.lock().unwrap();
+        for chain in 0..2 {
+            let of = |event: HookEvent| -> Vec<usize> {
+                events
+                    .iter()
+                    .filter(|(e, c, _)| *e == event && *c == chain)
+                    .map(|(_, _, i)| *i)
+                    .collect()
+            };
+            assert_eq!(of(HookEvent::ChainStart), vec![0]);
+            assert_eq!(of(HookEvent::WarmupEnd), vec![10]);
+            // Draws 5, 10, 15 and 20 are made at steps 9, 19, 29 and 39
+            // after warmup.
+            assert_eq!(of(HookEvent::Every(5)), vec![19, 29, 39, 49]);
+            assert_eq!(of(HookEvent::ChainEnd), vec![50]);
+        }
+        // Only adaptors see non-finite updates, so only during warmup.
+        let divergences: Vec<usize> = events
+            .iter()
+            .filter(|(e, _, _)| *e == HookEvent::Divergence)
+            .map(|(_, _, i)| *i)
+            .collect();
+        assert!(!divergences.is_empty());
+        assert!(divergences.iter().all(|&i| i <= 10));
+    }
+
+    #[test]
+    fn run_with_events_collects_every_proposal() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let runner = Runner::new(
+            SRWM::new(a.clone(), log_likelihood, Some(1.0)).unwrap(),
+        ).warmup(10)
+        .samples(100)
+        .chains(2);
+
+        let (chains, events) =
+            runner.run_with_events(&mut rng, Model { a: 0.0 });
+        assert_eq!(chains.len(), 2);
+        assert_eq!(events.len(), 2);
+        for (chain, events) in chains.iter().zip(events.iter()) {
+            let adaptations = events
+                .iter()
+                .filter(|e| match e {
+                    events::Event::AdaptationUpdated { .. } => true,
+                    _ => false,
+                })
+                .count();
+            assert_eq!(adaptations, 10);
+            assert_eq!(events.len(), 120);
+
+            // Moves after warmup are the accepted proposals.
+            let moves = chain.windows(2).filter(|w| w[0] != w[1]).count();
+            let accepted = events[20..]
+                .iter()
+                .skip(1)
+                .filter(|e| match e {
+                    events::Event::ProposalAccepted { .. } => true,
+                    _ => false,
+                })
+                .count();
+            assert_eq!(moves, accepted);
+            let rate = events::acceptance_rate(events, a.id()).unwrap();
+            assert!(rate > 0.0 && rate < 1.0);
+        }
+    }
+
+    #[test]
+    fn verbose_runs_break_down_every_update() {
+        fn shifted(m: &Model) -> f64 {
+            -(m.a - 1.0) * (m.a - 1.0)
+        }
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let runner = Runner::new(SRWM::new(a.clone(), shifted, None).unwrap())
+            .warmup(10)
+            .samples(50);
+
+        let (_, quiet) = runner.run_with_events(&mut rng, Model { a: 0.0 });
+        assert!(quiet[0].iter().all(|e| match e {
+            events::Event::ProposalScored { .. } => false,
+            _ => true,
+        }));
+
+        let (chains, events) =
+            runner.verbose().run_with_events(&mut rng, Model { a: 0.0 });
+        let breakdowns: Vec<events::ScoreBreakdown> = events[0]
+            .iter()
+            .filter_map(|e| match e {
+                events::Event::ProposalScored { scores, .. } => Some(*scores),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(breakdowns.len(), 60);
+
+        // The last draws' model is the current model of the last update.
+        let last = chains[0].last().unwrap();
+        let scores = breakdowns.last().unwrap();
+        let current = if scores.accepted {
+            (scores.proposed_prior, scores.proposed_log_likelihood.unwrap())
+        } else {
+            (scores.current_prior, scores.current_log_likelihood)
+        };
+        assert!((current.0 - a.prior.ln_f(&last.a)).abs() < 1E-10);
+        assert!((current.1 - shifted(last)).abs() < 1E-10);
+        for scores in breakdowns {
+            let proposed = scores.proposed_prior
+                + scores.proposed_log_likelihood.unwrap();
+            let current = scores.current_prior + scores.current_log_likelihood;
+            assert!((proposed - current - scores.log_alpha).abs() < 1E-10);
+        }
+
+        let line = events[0]
+            .iter()
+            .map(|e| e.log_line())
+            .find(|l| l.starts_with("event=proposal_scored"))
+            .unwrap();
+        assert!(line.starts_with("event=proposal_scored parameter=a "));
+        assert!(line.contains(" decision="));
+    }
+
+    static LIKELIHOOD_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+    // Stands in for an expensive likelihood split over the observations.
+    fn parallel_log_likelihood(m: &Model) -> f64 {
+        let threads = rayon::current_num_threads();
+        LIKELIHOOD_THREADS.store(threads, Ordering::SeqCst);
+        let observations: Vec<f64> = (0..64).map(|i| i as f64 / 64.0).collect();
+        observations
+            .par_iter()
+            .map(|x| -0.5 * (x - m.a) * (x - m.a))
+            .sum()
+    }
+
+    #[test]
+    fn likelihood_runs_on_the_runners_pool() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let runner = Runner::new(
+            SRWM::new(a.clone(), parallel_log_likelihood, Some(1.0)).unwrap(),
+        ).warmup(10)
+        .samples(20)
+        .likelihood_threads(3);
+
+        let sample = runner.sample(&mut rng, Model { a: 0.0 });
+        assert_eq!(LIKELIHOOD_THREADS.load(Ordering::SeqCst), 3);
+        assert_eq!(sample.provenance[0].host.threads, 3);
+        assert_eq!(sample.chains[0].len(), 20);
+    }
 }
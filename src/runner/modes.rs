@@ -0,0 +1,183 @@
+//! Short exploratory runs to find the modes of a posterior
+//!
+//! Chains of a multimodal posterior started from one model rarely leave its
+//! mode. A mode search spends a small share of the budget on many short
+//! chains started from prior draws, clusters where they end with k-means on
+//! features of the models, and starts the full chains from a representative
+//! of each cluster.
+
+use std::fmt;
+use rand::{Rng, SeedableRng};
+
+use cluster::{select_k, standardize, Clusters, MIN_EXPLAINED};
+use runner::{draw_seed, Runner, Sample};
+use steppers::SteppingAlg;
+
+/// Settings of a search for modes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModeSearch {
+    /// Number of exploratory chains
+    pub explorers: usize,
+    /// Steps each exploratory chain takes, with adaptation enabled
+    pub steps: usize,
+    /// Most modes to keep
+    pub max_modes: usize,
+}
+
+impl ModeSearch {
+    pub fn new(explorers: usize, steps: usize, max_modes: usize) -> Self {
+        assert!(explorers > 0, "a mode search needs an explorer.");
+        assert!(steps > 0, "explorers must take at least one step.");
+        assert!(max_modes > 0, "a mode search must keep a mode.");
+        ModeSearch {
+            explorers,
+            steps,
+            max_modes,
+        }
+    }
+}
+
+/// Modes found by a search
+#[derive(Clone, Debug)]
+pub struct Modes<M> {
+    /// Last model of each exploratory chain
+    pub endpoints: Vec<M>,
+    /// Clusters of the standardized features of the endpoints
+    pub clusters: Clusters,
+    /// Endpoint closest to the center of each cluster
+    pub representatives: Vec<M>,
+}
+
+impl<M, A, R> Runner<M, A, R>
+where
+    M: Clone + Sync + Send,
+    A: SteppingAlg<M, R> + Send + Sync + Clone,
+    M: 'static,
+    A: 'static,
+    R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+{
+    /// Run `search.explorers` short chains from prior draws and cluster
+    /// their endpoints by `features`, e.g. the values of the parameters
+    /// which separate the modes.
+    ///
+    /// Features are standardized before clustering. Fixed parameters keep
+    /// their values in the prior draws.
+    pub fn find_modes(
+        &self,
+        rng: &mut R,
+        init_model: M,
+        search: ModeSearch,
+        features: fn(&M) -> Vec<f64>,
+    ) -> Modes<M> {
+        let explorer = self
+            .warmup(search.steps)
+            .samples(1)
+            .thinning(1)
+            .drop_warmup();
+        let inits = self.sample_prior(rng, init_model, search.explorers);
+        let seeds = (0..search.explorers)
+            .map(|_| draw_seed::<R, _>(rng))
+            .collect();
+        let sinks = (0..search.explorers).map(|_| Vec::new()).collect();
+        let endpoints: Vec<M> = explorer
+            .run_seeded(seeds, inits, sinks, vec![None; search.explorers])
+            .into_iter()
+            .map(|(mut draws, _)| draws.pop().expect("explorer drew no model"))
+            .collect();
+
+        let points: Vec<Vec<f64>> = endpoints.iter().map(features).collect();
+        let points = standardize(&points);
+        let clusters =
+            select_k(rng, &points, search.max_modes, MIN_EXPLAINED)
+                .expect("explorers' features must be finite and of one length");
+        let representatives = clusters
+            .closest(&points)
+            .into_iter()
+            .map(|i| endpoints[i].clone())
+            .collect();
+        Modes {
+            endpoints,
+            clusters,
+            representatives,
+        }
+    }
+
+    /// Find the modes of the posterior with `find_modes`, then run this
+    /// runner's chains from their representatives, chain `i` starting from
+    /// mode `i % n_modes`.
+    ///
+    /// # Example
+    /// ```
+    /// # #[macro_use] extern crate rmcmc;
+    /// # extern crate rand;
+    /// # extern crate rv;
+    /// # use rmcmc::lens::*;
+    /// # use rmcmc::parameter::Parameter;
+    /// # use rmcmc::runner::{ModeSearch, Runner};
+    /// # use rmcmc::steppers::SRWM;
+    /// # use rand::SeedableRng;
+    /// # use rand::rngs::StdRng;
+    /// # use rv::dist::Gaussian;
+    /// # use rv::traits::Rv;
+    /// # fn main() {
+    /// #[derive(Clone, Copy, Debug)]
+    /// struct Model {
+    ///     x: f64,
+    /// }
+    ///
+    /// // Two narrow modes a random walk cannot cross
+    /// let x = Parameter::new(
+    ///     "x".to_string(),
+    ///     Gaussian::new(0.0, 10.0).unwrap(),
+    ///     make_lens!(Model, f64, x),
+    /// );
+    /// let log_likelihood = |m: &Model| {
+    ///     let a = Gaussian::new(-10.0, 0.5).unwrap().ln_f(&m.x);
+    ///     let b = Gaussian::new(10.0, 0.5).unwrap().ln_f(&m.x);
+    ///     a.max(b)
+    /// };
+    /// let runner = Runner::new(SRWM::new(x, log_likelihood, None).unwrap())
+    ///     .chains(2)
+    ///     .warmup(200)
+    ///     .samples(200);
+    ///
+    /// let mut rng = StdRng::from_seed([0; 32]);
+    /// let search = ModeSearch::new(20, 200, 4);
+    /// let (modes, sample) = runner.sample_modes(
+    ///     &mut rng,
+    ///     Model { x: 0.0 },
+    ///     search,
+    ///     |m| vec![m.x],
+    /// );
+    /// assert_eq!(modes.representatives.len(), 2);
+    /// let sides: Vec<bool> =
+    ///     sample.chains.iter().map(|c| c[0].x > 0.0).collect();
+    /// assert!(sides[0] != sides[1]);
+    /// # }
+    /// ```
+    pub fn sample_modes(
+        &self,
+        rng: &mut R,
+        init_model: M,
+        search: ModeSearch,
+        features: fn(&M) -> Vec<f64>,
+    ) -> (Modes<M>, Sample<M>) {
+        let modes = self.find_modes(rng, init_model, search, features);
+        let n_modes = modes.representatives.len();
+        let inits = (0..self.n_chains)
+            .map(|i| modes.representatives[i % n_modes].clone())
+            .collect();
+        let seeds = (0..self.n_chains)
+            .map(|_| draw_seed::<R, _>(rng))
+            .collect();
+        let sinks = (0..self.n_chains)
+            .map(|_| Vec::with_capacity(self.samples))
+            .collect();
+        let chains = self
+            .run_seeded(seeds, inits, sinks, vec![None; self.n_chains])
+            .into_iter()
+            .map(|(draws, _)| draws)
+            .collect();
+        (modes, Sample::new(self.parameters(), chains))
+    }
+}
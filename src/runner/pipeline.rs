@@ -0,0 +1,167 @@
+//! Post-processing of a sample's chains
+//!
+//! Stages such as burn-in removal, thinning and transformation to derived
+//! quantities are chained onto a `Sample` with `pipe` and applied to every
+//! chain, in order, only when the pipeline is collected.
+
+use runner::Sample;
+
+/// Draws of one chain as they pass through a pipeline
+pub type ChainIter<T> = Box<dyn Iterator<Item = T>>;
+
+/// A step of post-processing applied to each chain
+pub trait Stage<M> {
+    type Output;
+
+    fn apply(&self, chain: ChainIter<M>) -> ChainIter<Self::Output>;
+}
+
+/// Drop the first `n` draws of each chain.
+#[derive(Clone, Copy, Debug)]
+pub struct Burn(pub usize);
+
+impl<M: 'static> Stage<M> for Burn {
+    type Output = M;
+
+    fn apply(&self, chain: ChainIter<M>) -> ChainIter<M> {
+        Box::new(chain.skip(self.0))
+    }
+}
+
+/// Keep every `k`th draw of each chain, starting with the first.
+#[derive(Clone, Copy, Debug)]
+pub struct Thin(pub usize);
+
+impl<M: 'static> Stage<M> for Thin {
+    type Output = M;
+
+    fn apply(&self, chain: ChainIter<M>) -> ChainIter<M> {
+        assert!(self.0 > 0, "thinning must be greater than 0.");
+        Box::new(chain.step_by(self.0))
+    }
+}
+
+/// Map each draw to a derived quantity.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform<F>(pub F);
+
+impl<M, N, F> Stage<M> for Transform<F>
+where
+    M: 'static,
+    F: 'static + Fn(M) -> N + Clone,
+{
+    type Output = N;
+
+    fn apply(&self, chain: ChainIter<M>) -> ChainIter<N> {
+        Box::new(chain.map(self.0.clone()))
+    }
+}
+
+/// A sample with stages yet to be applied to its chains
+pub struct Pipeline<M, N> {
+    source: Sample<M>,
+    stages: Box<dyn Fn(ChainIter<M>) -> ChainIter<N>>,
+}
+
+impl<M: 'static> Pipeline<M, M> {
+    /// A pipeline of no stages over `sample`
+    pub fn new(sample: Sample<M>) -> Self {
+        Pipeline {
+            source: sample,
+            stages: Box::new(|chain| chain),
+        }
+    }
+}
+
+impl<M: 'static, N: 'static> Pipeline<M, N> {
+    /// Apply `stage` after the stages already in the pipeline.
+    pub fn pipe<S>(self, stage: S) -> Pipeline<M, S::Output>
+    where
+        S: 'static + Stage<N>,
+    {
+        let stages = self.stages;
+        Pipeline {
+            source: self.source,
+            stages: Box::new(move |chain| stage.apply(stages(chain))),
+        }
+    }
+
+    /// Apply the stages to every chain, keeping the sample's parameters and
+    /// provenance.
+    pub fn collect(self) -> Sample<N> {
+        let stages = self.stages;
+        let chains = self
+            .source
+            .chains
+            .into_iter()
+            .map(|chain| stages(Box::new(chain.into_iter())).collect())
+            .collect();
+        Sample {
+            parameters: self.source.parameters,
+            chains,
+            provenance: self.source.provenance,
+        }
+    }
+}
+
+impl<M: 'static> Sample<M> {
+    /// Start a pipeline applying `stage` to each chain of the sample.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate rmcmc;
+    /// # use rmcmc::runner::{Burn, Sample, Thin, Transform};
+    /// # fn main() {
+    /// let sample = Sample::new(vec![], vec![(0..10).collect::<Vec<i32>>()]);
+    /// let squares = sample
+    ///     .pipe(Burn(4))
+    ///     .pipe(Thin(2))
+    ///     .pipe(Transform(|x: i32| x * x))
+    ///     .collect();
+    /// assert_eq!(squares.chains, vec![vec![16, 36, 64]]);
+    /// # }
+    /// ```
+    pub fn pipe<S>(self, stage: S) -> Pipeline<M, S::Output>
+    where
+        S: 'static + Stage<M>,
+    {
+        Pipeline::new(self).pipe(stage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parameter::ParamId;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn stages_apply_in_order_when_collected() {
+        let parameters = vec![ParamId("x".to_string())];
+        let sample = Sample::new(
+            parameters.clone(),
+            vec![(0..10).collect(), (10..20).collect()],
+        );
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let double = move |x: i32| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            2 * x
+        };
+
+        let pipeline =
+            sample.pipe(Burn(2)).pipe(Thin(3)).pipe(Transform(double));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let processed = pipeline.collect();
+        assert_eq!(processed.parameters, parameters);
+        assert_eq!(processed.chains, vec![vec![4, 10, 16], vec![24, 30, 36]]);
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+
+        // Thinning before burning burns thinned draws.
+        let sample = Sample::new(vec![], vec![(0..10).collect::<Vec<i32>>()]);
+        let processed = sample.pipe(Thin(3)).pipe(Burn(2)).collect();
+        assert_eq!(processed.chains, vec![vec![6, 9]]);
+    }
+}
@@ -0,0 +1,166 @@
+//! Bundles of run settings for common situations
+//!
+//! A preset picks the number of chains, warmup and draws of a run together
+//! with the diagnostic thresholds its sample should meet. Builder methods
+//! called after `Runner::preset` override its choices.
+
+use std::fmt;
+use rand::{Rng, SeedableRng};
+
+use runner::Runner;
+use steppers::SteppingAlg;
+use summary::RunSummary;
+
+/// Settings of a run for a purpose
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Preset {
+    /// A quick look while building a model: two short chains and loose
+    /// thresholds
+    Fast,
+    /// Results to report: four long chains held to `r_hat < 1.01` and 400
+    /// effective draws per quantity
+    Robust,
+    /// Searching for multiple modes: many chains, warmup kept so their
+    /// paths can be inspected
+    Exploratory,
+}
+
+impl Preset {
+    pub fn chains(&self) -> usize {
+        match self {
+            Preset::Fast => 2,
+            Preset::Robust => 4,
+            Preset::Exploratory => 8,
+        }
+    }
+
+    pub fn warmup(&self) -> usize {
+        match self {
+            Preset::Fast => 500,
+            Preset::Robust => 2000,
+            Preset::Exploratory => 1000,
+        }
+    }
+
+    pub fn samples(&self) -> usize {
+        match self {
+            Preset::Fast => 500,
+            Preset::Robust => 2000,
+            Preset::Exploratory => 1000,
+        }
+    }
+
+    pub fn keep_warmup(&self) -> bool {
+        *self == Preset::Exploratory
+    }
+
+    /// Largest acceptable split `r_hat` of a quantity
+    pub fn max_r_hat(&self) -> f64 {
+        match self {
+            Preset::Fast => 1.1,
+            Preset::Robust => 1.01,
+            Preset::Exploratory => 1.05,
+        }
+    }
+
+    /// Smallest acceptable effective sample size of a quantity
+    pub fn min_ess(&self) -> f64 {
+        match self {
+            Preset::Fast => 100.0,
+            Preset::Robust => 400.0,
+            Preset::Exploratory => 100.0,
+        }
+    }
+
+    /// Names of the quantities of `summary` whose `r_hat` or effective
+    /// sample size miss this preset's thresholds, or could not be computed
+    pub fn unconverged<'a>(&self, summary: &'a RunSummary) -> Vec<&'a str> {
+        summary
+            .rows
+            .iter()
+            .filter(|row| {
+                let r_hat = row.r_hat.map_or(false, |r| r < self.max_r_hat());
+                let ess = row.ess.map_or(false, |ess| ess >= self.min_ess());
+                !(r_hat && ess)
+            })
+            .map(|row| row.stats.name.as_str())
+            .collect()
+    }
+}
+
+impl<M, A, R> Runner<M, A, R>
+where
+    M: Clone + Sync + Send,
+    A: SteppingAlg<M, R> + Send + Sync + Clone,
+    M: 'static,
+    A: 'static,
+    R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+{
+    /// Configure the chains, warmup and draws of `preset`. Builder methods
+    /// called afterwards override them.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate rmcmc;
+    /// # extern crate rand;
+    /// # use rmcmc::runner::{Preset, Runner};
+    /// # use rmcmc::steppers::Mock;
+    /// # use rand::rngs::StdRng;
+    /// # fn main() {
+    /// let runner: Runner<f64, _, StdRng> =
+    ///     Runner::new(Mock::new(0.0, |x: f64| x)).preset(Preset::Robust);
+    /// assert_eq!(runner.n_chains, 4);
+    ///
+    /// let runner = runner.samples(500);
+    /// assert_eq!((runner.warmup_steps, runner.samples), (2000, 500));
+    /// # }
+    /// ```
+    pub fn preset(&self, preset: Preset) -> Self {
+        let runner = self
+            .chains(preset.chains())
+            .warmup(preset.warmup())
+            .samples(preset.samples())
+            .thinning(1);
+        if preset.keep_warmup() {
+            runner.keep_warmup()
+        } else {
+            runner.drop_warmup()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+    use runner::Sample;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[test]
+    fn unconverged_quantities_are_named() {
+        let mut rng = StdRng::from_seed(SEED);
+        let g = Gaussian::new(0.0, 1.0).unwrap();
+        let mixed: Vec<Vec<f64>> =
+            (0..4).map(|_| g.sample(1000, &mut rng)).collect();
+        let stuck: Vec<Vec<f64>> = (0..4)
+            .map(|i| {
+                let offset = 10.0 * i as f64;
+                let draws: Vec<f64> = g.sample(1000, &mut rng);
+                draws.iter().map(|x| x + offset).collect()
+            })
+            .collect();
+
+        let quantities: [(&str, fn(&f64) -> f64); 1] = [("x", |x| *x)];
+        let mixed = Sample::new(vec![], mixed).summary(&quantities);
+        let stuck = Sample::new(vec![], stuck).summary(&quantities);
+        assert!(Preset::Robust.unconverged(&mixed).is_empty());
+        assert_eq!(Preset::Fast.unconverged(&stuck), vec!["x"]);
+
+        let short = Sample::new(vec![], vec![vec![1.0, 2.0]]);
+        let short = short.summary(&quantities);
+        assert_eq!(Preset::Fast.unconverged(&short), vec!["x"]);
+    }
+}
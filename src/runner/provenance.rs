@@ -0,0 +1,126 @@
+//! Record of how a sample was produced
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::time::{Duration, SystemTime};
+use rayon;
+
+use parameter::ParamId;
+use runner::StepTime;
+use steppers::StepperSpec;
+
+/// Machine a run was made on
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Host {
+    pub hostname: Option<String>,
+    pub os: String,
+    pub arch: String,
+    /// Threads in the pool the chains ran on
+    pub threads: usize,
+}
+
+impl Host {
+    /// Description of the current machine
+    pub fn current() -> Self {
+        let hostname = env::var("HOSTNAME")
+            .ok()
+            .or_else(|| fs::read_to_string("/etc/hostname").ok())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+        Host {
+            hostname,
+            os: env::consts::OS.to_string(),
+            arch: env::consts::ARCH.to_string(),
+            threads: rayon::current_num_threads(),
+        }
+    }
+}
+
+/// Everything needed to reproduce and report a run
+///
+/// Passing `master_seed` to `Runner::sample_from_seed` with the same runner
+/// configuration and initial model reproduces the run's draws. With the
+/// `serde_support` feature it can be saved next to the draws and read back.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Provenance {
+    /// Version of this crate
+    pub crate_version: String,
+    /// Seed every chain's seed was drawn from
+    pub master_seed: Vec<u8>,
+    /// Seed of each chain's generator
+    pub chain_seeds: Vec<Vec<u8>>,
+    /// Description of the stepper's configuration
+    pub stepper: String,
+    /// Specification of the stepper, if it was built by a `Registry`
+    pub spec: Option<StepperSpec>,
+    pub n_chains: usize,
+    pub warmup_steps: usize,
+    pub samples: usize,
+    pub thinning: usize,
+    pub keep_warmup: bool,
+    /// Parameters held fixed
+    pub fixed: Vec<ParamId>,
+    pub started: SystemTime,
+    pub finished: SystemTime,
+    /// Time taken by each chain
+    pub chain_durations: Vec<Duration>,
+    /// Times of the steps of each chain timed with `Runner::time_steps`,
+    /// empty if steps were not timed
+    pub step_times: Vec<Vec<StepTime>>,
+    pub host: Host,
+}
+
+impl Provenance {
+    /// Time from the start of the run to its end
+    pub fn elapsed(&self) -> Duration {
+        self.finished
+            .duration_since(self.started)
+            .unwrap_or_default()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "rmcmc {}", self.crate_version)?;
+        writeln!(f, "master seed: {}", hex(&self.master_seed))?;
+        for (i, seed) in self.chain_seeds.iter().enumerate() {
+            writeln!(f, "chain {} seed: {}", i, hex(seed))?;
+        }
+        writeln!(f, "stepper: {}", self.stepper)?;
+        writeln!(
+            f,
+            "chains: {}, warmup: {}, samples: {}, thinning: {}, \
+             keep warmup: {}",
+            self.n_chains,
+            self.warmup_steps,
+            self.samples,
+            self.thinning,
+            self.keep_warmup
+        )?;
+        if !self.fixed.is_empty() {
+            let fixed: Vec<String> =
+                self.fixed.iter().map(|id| id.to_string()).collect();
+            writeln!(f, "fixed: {}", fixed.join(", "))?;
+        }
+        writeln!(f, "elapsed: {:?}", self.elapsed())?;
+        let timed: usize = self.step_times.iter().map(|t| t.len()).sum();
+        if timed > 0 {
+            writeln!(f, "timed steps: {}", timed)?;
+        }
+        write!(
+            f,
+            "host: {} ({} {}, {} threads)",
+            self.host.hostname.as_ref().map_or("unknown", |h| h.as_str()),
+            self.host.os,
+            self.host.arch,
+            self.host.threads
+        )
+    }
+}
@@ -0,0 +1,182 @@
+//! Draws of one or more runs together with the parameters they update
+
+use std::io;
+#[cfg(feature = "ndarray")]
+use ndarray::Array3;
+use parameter::ParamId;
+use runner::Provenance;
+use utils::invalid_input;
+
+/// Chains of draws and the parameters updated by the stepper producing them
+///
+/// Samples from separate runs of the same model, e.g. on different machines,
+/// are combined with `merge`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample<M> {
+    pub parameters: Vec<ParamId>,
+    pub chains: Vec<Vec<M>>,
+    /// How each run making up the sample was produced
+    pub provenance: Vec<Provenance>,
+}
+
+impl<M> Sample<M> {
+    pub fn new(parameters: Vec<ParamId>, chains: Vec<Vec<M>>) -> Self {
+        Sample {
+            parameters,
+            chains,
+            provenance: Vec::new(),
+        }
+    }
+
+    /// Number of chains
+    pub fn n_chains(&self) -> usize {
+        self.chains.len()
+    }
+
+    /// Total number of draws over every chain
+    pub fn n_draws(&self) -> usize {
+        self.chains.iter().map(|chain| chain.len()).sum()
+    }
+
+    /// Every draw, chain by chain
+    pub fn draws(&self) -> impl Iterator<Item = &M> {
+        self.chains.iter().flat_map(|chain| chain.iter())
+    }
+
+    /// Combine the chains of several samples into one.
+    ///
+    /// Every sample must update the same parameters, in any order; otherwise
+    /// the draws are not of the same model and an `InvalidInput` error is
+    /// returned.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate rmcmc;
+    /// # use rmcmc::parameter::ParamId;
+    /// # use rmcmc::runner::Sample;
+    /// # fn main() {
+    /// let params = vec![ParamId("mu".to_string())];
+    /// let a = Sample::new(params.clone(), vec![vec![0.1, 0.2]]);
+    /// let b = Sample::new(params.clone(), vec![vec![0.3], vec![0.4, 0.5]]);
+    ///
+    /// let merged = Sample::merge(vec![a, b]).unwrap();
+    /// assert_eq!(merged.n_chains(), 3);
+    /// assert_eq!(merged.n_draws(), 5);
+    /// # }
+    /// ```
+    pub fn merge(samples: Vec<Sample<M>>) -> io::Result<Sample<M>> {
+        let sorted = |ids: &[ParamId]| {
+            let mut ids = ids.to_vec();
+            ids.sort();
+            ids
+        };
+
+        let mut samples = samples.into_iter();
+        let mut merged = match samples.next() {
+            Some(first) => first,
+            None => return Err(invalid_input("no samples to merge")),
+        };
+        let expected = sorted(&merged.parameters);
+
+        for sample in samples {
+            if sorted(&sample.parameters) != expected {
+                return Err(invalid_input(format!(
+                    "samples update different parameters: {:?} and {:?}",
+                    merged.parameters, sample.parameters
+                )));
+            }
+            merged.chains.extend(sample.chains);
+            merged.provenance.extend(sample.provenance);
+        }
+        Ok(merged)
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl<M> Sample<M> {
+    /// The draws as an array indexed by chain, draw and dimension, where
+    /// `extractor` gives the dimensions of each draw.
+    ///
+    /// Every chain must have the same number of draws and `extractor` must
+    /// always return as many values; otherwise an `InvalidInput` error is
+    /// returned.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate rmcmc;
+    /// # use rmcmc::runner::Sample;
+    /// # fn main() {
+    /// let sample = Sample::new(vec![], vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+    /// let array = sample.to_array(|x: &f64| vec![*x, x * x]).unwrap();
+    ///
+    /// assert_eq!(array.shape(), &[2, 2, 2]);
+    /// assert_eq!(array[[1, 0, 1]], 9.0);
+    /// # }
+    /// ```
+    pub fn to_array<F>(&self, extractor: F) -> io::Result<Array3<f64>>
+    where
+        F: Fn(&M) -> Vec<f64>,
+    {
+        let n_draws = self.chains.first().map_or(0, |chain| chain.len());
+        if self.chains.iter().any(|chain| chain.len() != n_draws) {
+            return Err(invalid_input("chains have different numbers of draws"));
+        }
+        let values: Vec<Vec<f64>> = self.draws().map(extractor).collect();
+        let dim = values.first().map_or(0, |v| v.len());
+        if values.iter().any(|v| v.len() != dim) {
+            return Err(invalid_input("draws have different dimensions"));
+        }
+
+        let flat: Vec<f64> = values.into_iter().flatten().collect();
+        Array3::from_shape_vec((self.n_chains(), n_draws, dim), flat)
+            .map_err(|err| invalid_input(&err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(s: &str) -> ParamId {
+        ParamId(s.to_string())
+    }
+
+    #[test]
+    fn merge_concatenates_chains() {
+        let a = Sample::new(vec![id("a"), id("b")], vec![vec![1, 2], vec![3]]);
+        let b = Sample::new(vec![id("b"), id("a")], vec![vec![4, 5, 6]]);
+
+        let merged = Sample::merge(vec![a, b]).unwrap();
+        assert_eq!(merged.parameters, vec![id("a"), id("b")]);
+        assert_eq!(merged.chains, vec![vec![1, 2], vec![3], vec![4, 5, 6]]);
+        let draws: Vec<i32> = merged.draws().cloned().collect();
+        assert_eq!(draws, vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn merge_rejects_incompatible_samples() {
+        let a = Sample::new(vec![id("a")], vec![vec![1]]);
+        let b = Sample::new(vec![id("a"), id("b")], vec![vec![2]]);
+        let err = Sample::merge(vec![a, b]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let none: Vec<Sample<i32>> = Vec::new();
+        assert!(Sample::merge(none).is_err());
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn to_array_rejects_ragged_draws() {
+        let ragged = Sample::new(vec![], vec![vec![1.0, 2.0], vec![3.0]]);
+        let err = ragged.to_array(|x: &f64| vec![*x]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let sample = Sample::new(vec![], vec![vec![1.0, 2.0]]);
+        let uneven = |x: &f64| vec![*x; *x as usize];
+        assert!(sample.to_array(uneven).is_err());
+
+        let array = sample.to_array(|x: &f64| vec![*x]).unwrap();
+        assert_eq!(array.shape(), &[1, 2, 1]);
+        assert_eq!(array[[0, 1, 0]], 2.0);
+    }
+}
@@ -0,0 +1,553 @@
+//! Incremental running of chains on the calling thread
+//!
+//! A `Session` advances its chains a few steps at a time and returns
+//! control in between, so interactive front ends can interleave sampling
+//! with rendering on a single thread. Each chain is the runner's own
+//! `ChainLoop`, so the runner's hooks fire and its steps are timed as in
+//! `Runner::run`. A chain's state can be copied out of
+//! a session at any point as a `ChainState` and stepped again from there,
+//! replaying the chain exactly, e.g. to inspect a numerical problem found
+//! late in a long run.
+//!
+//! Steppers hold their likelihoods as closures, so a `ChainState` lives in
+//! memory only. To save a chain, take a `ChainSnapshot` of its model, RNG,
+//! iteration and what its stepper's adaptation has learned, serialize it
+//! with the `serde_support` feature, and restore it later onto a stepper
+//! built the same way, in code or from the same `StepperSpec`.
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use rand::prelude::*;
+
+use events::{self, EventSink};
+use runner::{Hooks, Runner, StepTime, StepTimer};
+use runner::utils::{ChainLoop, ChainSettings};
+use steppers::{AdaptationMode, Reparameterizable, Reparameterize};
+use steppers::SteppingAlg;
+use steppers::adaptor::AdaptorState;
+use utils::invalid_data;
+
+/// Chains of a `Runner` advanced on demand
+pub struct Session<M, A, R>
+where
+    A: SteppingAlg<M, R>,
+    R: Rng,
+{
+    chains: Vec<(ChainLoop<M, A>, R)>,
+    draws: Vec<Vec<M>>,
+    hooks: Hooks<M>,
+    step: usize,
+    warmup_steps: usize,
+    total_steps: usize,
+}
+
+/// Everything the next steps of a chain depend on
+///
+/// Stepping a copy of the state reproduces the steps the chain took from
+/// it, draw for draw, within the same process.
+#[derive(Clone, Debug)]
+pub struct ChainState<M, A, R> {
+    pub model: M,
+    /// The stepper, including the state of its adaptors
+    pub stepper: A,
+    pub rng: R,
+    /// Number of steps taken, including warmup
+    pub iteration: usize,
+    pub warmup_steps: usize,
+}
+
+/// A chain's state as plain data, which can be saved and restored
+///
+/// Restoring the snapshot onto a stepper built as the chain's was,
+/// including any fixed parameters, continues the chain exactly, draw for
+/// draw. Sub-steppers of a `Group` with streams of their own draw new
+/// streams when restored, so only their distribution is reproduced.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct ChainSnapshot<M, R> {
+    pub model: M,
+    /// What the stepper's adaptation has learned
+    pub adaptation: Vec<AdaptorState>,
+    /// The chain's generator; its state must be serializable to save it,
+    /// e.g. that of `rand_xorshift::XorShiftRng` with the `serde1` feature
+    pub rng: R,
+    /// Number of steps taken, including warmup
+    pub iteration: usize,
+    pub warmup_steps: usize,
+}
+
+impl<M, A, R> ChainState<M, A, R>
+where
+    M: Clone,
+    A: SteppingAlg<M, R>,
+    R: Rng,
+{
+    /// Continue the chain saved as `snapshot` with `stepper`, which must
+    /// be built the same way as the chain's stepper.
+    ///
+    /// Fails with `InvalidData` if the snapshot's adaptation state does
+    /// not fit the stepper.
+    pub fn restore(
+        snapshot: ChainSnapshot<M, R>,
+        mut stepper: A,
+    ) -> io::Result<Self> {
+        let mut states = snapshot.adaptation.into_iter();
+        stepper.restore_adaptation(&mut states)?;
+        if let Some(state) = states.next() {
+            return Err(invalid_data(format!(
+                "the stepper has no use for the adaptor state of {:?}",
+                state.name
+            )));
+        }
+        stepper.invalidate_cache();
+        stepper.set_adapt(if snapshot.iteration < snapshot.warmup_steps {
+            AdaptationMode::Enabled
+        } else {
+            AdaptationMode::Disabled
+        });
+        Ok(ChainState {
+            model: snapshot.model,
+            stepper,
+            rng: snapshot.rng,
+            iteration: snapshot.iteration,
+            warmup_steps: snapshot.warmup_steps,
+        })
+    }
+
+    /// The state as plain data, without the stepper's closures
+    pub fn snapshot(&self) -> ChainSnapshot<M, R>
+    where
+        R: Clone,
+    {
+        ChainSnapshot {
+            model: self.model.clone(),
+            adaptation: self.stepper.adaptation_state(),
+            rng: self.rng.clone(),
+            iteration: self.iteration,
+            warmup_steps: self.warmup_steps,
+        }
+    }
+
+    /// Take the chain's next step, ending adaptation with warmup as a
+    /// session does.
+    pub fn step(&mut self) -> &M {
+        if self.iteration == self.warmup_steps {
+            self.stepper.set_adapt(AdaptationMode::Disabled);
+        }
+        self.model = self.stepper.step(&mut self.rng, self.model.clone());
+        self.iteration += 1;
+        &self.model
+    }
+
+    /// The models of the next `n_steps` steps from this state, leaving the
+    /// state itself untouched so it can be replayed again.
+    pub fn replay(&self, n_steps: usize) -> Vec<M>
+    where
+        A: Clone,
+        R: Clone,
+    {
+        let mut state = self.clone();
+        (0..n_steps).map(|_| state.step().clone()).collect()
+    }
+}
+
+impl<M, A, R> fmt::Debug for Session<M, A, R>
+where
+    A: SteppingAlg<M, R>,
+    R: Rng,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Session {{ chains: {}, step: {}, total_steps: {} }}",
+            self.chains.len(),
+            self.step,
+            self.total_steps
+        )
+    }
+}
+
+impl<M, A, R> Session<M, A, R>
+where
+    M: Clone,
+    A: SteppingAlg<M, R>,
+    R: Rng,
+{
+    /// Step every chain up to `n_steps` more times, stopping early when the
+    /// run is complete. Returns whether the run is complete.
+    pub fn advance(&mut self, n_steps: usize) -> bool {
+        let end = (self.step + n_steps).min(self.total_steps);
+        while self.step < end {
+            for ((chain, rng), draws) in
+                self.chains.iter_mut().zip(self.draws.iter_mut())
+            {
+                chain.step(rng, draws, &self.hooks);
+            }
+            self.step += 1;
+        }
+        self.is_done()
+    }
+
+    /// Whether every chain has made all of its steps
+    pub fn is_done(&self) -> bool {
+        self.step == self.total_steps
+    }
+
+    /// Fraction of the steps made so far
+    pub fn progress(&self) -> f64 {
+        if self.total_steps == 0 {
+            1.0
+        } else {
+            self.step as f64 / self.total_steps as f64
+        }
+    }
+
+    /// Whether the chains are still warming up
+    pub fn is_warming_up(&self) -> bool {
+        self.step < self.warmup_steps
+    }
+
+    /// The draws kept so far, one vector per chain
+    pub fn draws(&self) -> &[Vec<M>] {
+        &self.draws
+    }
+
+    /// The current state of each chain
+    pub fn current(&self) -> Vec<&M> {
+        self.chains.iter().map(|(chain, _)| chain.model()).collect()
+    }
+
+    /// Take the events each chain's stepper emitted since they were last
+    /// taken, if the session was started with `Runner::session_with_events`
+    pub fn drain_events(&self) -> Vec<Vec<events::Event>> {
+        self.chains
+            .iter()
+            .map(|(chain, _)| chain.drain_events())
+            .collect()
+    }
+
+    /// Take the times of each chain's steps recorded since they were last
+    /// taken, if the runner times steps
+    pub fn drain_step_times(&self) -> Vec<Vec<StepTime>> {
+        self.chains
+            .iter()
+            .map(|(chain, _)| chain.drain_step_times())
+            .collect()
+    }
+
+    /// Copy of the state of the `chain`th chain, from which its next steps
+    /// can be replayed
+    pub fn checkpoint(&self, chain: usize) -> ChainState<M, A, R>
+    where
+        A: Clone,
+        R: Clone,
+    {
+        let (ref chain, ref rng) = self.chains[chain];
+        ChainState {
+            model: chain.model().clone(),
+            stepper: chain.stepper.clone(),
+            rng: rng.clone(),
+            iteration: self.step,
+            warmup_steps: self.warmup_steps,
+        }
+    }
+
+    /// The state of the `chain`th chain as plain data, from which it can
+    /// be restored onto a stepper built the same way
+    pub fn snapshot(&self, chain: usize) -> ChainSnapshot<M, R>
+    where
+        R: Clone,
+    {
+        let (ref chain, ref rng) = self.chains[chain];
+        ChainSnapshot {
+            model: chain.model().clone(),
+            adaptation: chain.stepper.adaptation_state(),
+            rng: rng.clone(),
+            iteration: self.step,
+            warmup_steps: self.warmup_steps,
+        }
+    }
+
+    /// Have every chain's stepper propose through `map`, or in the
+    /// parameter's own space again with `None`.
+    ///
+    /// Meant for a map learned from the warmup draws, e.g. a flow trained
+    /// on them elsewhere, installed once `is_warming_up` turns false and
+    /// before the first draw is kept, so every draw comes from the same
+    /// kernel.
+    pub fn reparameterize(&mut self, map: Option<Arc<dyn Reparameterize>>)
+    where
+        A: Reparameterizable,
+    {
+        self.chains.iter_mut().for_each(|(chain, _)| {
+            chain.stepper.set_reparameterization(map.clone())
+        });
+    }
+
+    /// Take the draws kept so far, one vector per chain.
+    pub fn into_draws(self) -> Vec<Vec<M>> {
+        self.draws
+    }
+}
+
+impl<M, A, R> Runner<M, A, R>
+where
+    M: Clone + Sync + Send,
+    A: SteppingAlg<M, R> + Send + Sync + Clone,
+    M: 'static,
+    A: 'static,
+    R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+{
+    /// Start the chains of this config without stepping them. Advancing
+    /// the session to completion gives draws as from `run`.
+    pub fn session(&self, rng: &mut R, init_model: M) -> Session<M, A, R> {
+        self.start_session(rng, init_model, false)
+    }
+
+    /// As `session`, collecting the events each chain's stepper emits for
+    /// `Session::drain_events`.
+    pub fn session_with_events(&self, rng: &mut R, init_model: M)
+        -> Session<M, A, R>
+    {
+        self.start_session(rng, init_model, true)
+    }
+
+    fn start_session(&self, rng: &mut R, init_model: M, collect: bool)
+        -> Session<M, A, R>
+    {
+        let mut stepper = self.stepper.clone();
+        let init_model = self.fixed.iter().fold(init_model, |m, (id, set)| {
+            stepper.fix(id);
+            set(&m)
+        });
+        // Checkpoints taken before the first step adapt as the chain does.
+        stepper.set_adapt(AdaptationMode::Enabled);
+
+        let chains = (0..self.n_chains)
+            .map(|i| {
+                let chain_rng = R::from_rng(&mut *rng)
+                    .expect("Failed to create seedable rng from input rng.");
+                let events = if !collect {
+                    None
+                } else if self.verbose {
+                    Some(EventSink::verbose())
+                } else {
+                    Some(EventSink::new())
+                };
+                let settings = ChainSettings {
+                    n_draws: self.samples,
+                    n_warmup: self.warmup_steps,
+                    thinning: self.thinning,
+                    keep_warmup: self.keep_warmup,
+                    hooks: &self.hooks,
+                    chain: i,
+                    events,
+                    timer: self.step_timing.map(StepTimer::new),
+                };
+                let init = init_model.clone();
+                let chain = ChainLoop::start(stepper.clone(), init, &settings);
+                (chain, chain_rng)
+            })
+            .collect();
+
+        Session {
+            chains,
+            draws: vec![Vec::new(); self.n_chains],
+            hooks: self.hooks.clone(),
+            step: 0,
+            warmup_steps: self.warmup_steps,
+            total_steps: self.warmup_steps + self.samples * self.thinning,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use steppers::{Mock, SRWM};
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[test]
+    fn advancing_matches_the_run_configuration() {
+        let mut rng = StdRng::from_seed(SEED);
+        let runner = Runner::new(Mock::new(0, |x: i32| x + 1))
+            .warmup(5)
+            .samples(4)
+            .thinning(2)
+            .chains(2);
+
+        let mut session = runner.session(&mut rng, 0);
+        assert!(!session.advance(3));
+        assert!(session.is_warming_up());
+        assert!(session.draws().iter().all(|draws| draws.is_empty()));
+
+        assert!(!session.advance(5));
+        assert!(!session.is_warming_up());
+        assert_eq!(session.draws()[0], vec![6, 8]);
+        assert_eq!(*session.current()[1], 8);
+
+        assert!(session.advance(100));
+        assert_eq!(session.progress(), 1.0);
+        assert!(session.advance(1));
+        let draws = session.into_draws();
+        assert_eq!(draws, vec![vec![6, 8, 10, 12], vec![6, 8, 10, 12]]);
+    }
+
+    #[test]
+    fn warmup_draws_are_kept_on_request() {
+        let mut rng = StdRng::from_seed(SEED);
+        let runner = Runner::new(Mock::new(0, |x: i32| x + 1))
+            .warmup(2)
+            .samples(2)
+            .keep_warmup();
+
+        let mut session = runner.session(&mut rng, 0);
+        while !session.advance(1) {}
+        assert_eq!(session.into_draws(), vec![vec![1, 2, 3, 4]]);
+    }
+
+    #[test]
+    fn sessions_fire_hooks_time_steps_and_collect_events() {
+        use runner::{HookContext, HookEvent};
+        use std::sync::Mutex;
+
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let record = |fired: &Arc<Mutex<Vec<(HookEvent, usize)>>>| {
+            let fired = fired.clone();
+            move |context: &HookContext<i32>| {
+                let mut fired = fired.lock().unwrap();
+                fired.push((context.event, context.iteration));
+            }
+        };
+        let mut rng = StdRng::from_seed(SEED);
+        let runner = Runner::new(Mock::new(0, |x: i32| x + 1))
+            .warmup(2)
+            .samples(2)
+            .thinning(2)
+            .time_steps(3)
+            .hook(HookEvent::ChainStart, record(&fired))
+            .hook(HookEvent::WarmupEnd, record(&fired))
+            .hook(HookEvent::Every(2), record(&fired))
+            .hook(HookEvent::ChainEnd, record(&fired));
+
+        let mut session = runner.session_with_events(&mut rng, 0);
+        assert_eq!(*fired.lock().unwrap(), vec![(HookEvent::ChainStart, 0)]);
+        while !session.advance(1) {}
+        assert_eq!(
+            *fired.lock().unwrap(),
+            vec![
+                (HookEvent::ChainStart, 0),
+                (HookEvent::WarmupEnd, 2),
+                (HookEvent::Every(2), 5),
+                (HookEvent::ChainEnd, 6),
+            ]
+        );
+        let times = session.drain_step_times();
+        let timed: Vec<usize> = times[0].iter().map(|t| t.iteration).collect();
+        assert_eq!(timed, vec![3, 6]);
+        assert_eq!(session.drain_events(), vec![Vec::new()]);
+        assert_eq!(session.into_draws(), vec![vec![3, 5]]);
+    }
+
+    #[test]
+    fn checkpoints_replay_the_chain_exactly() {
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        struct Model {
+            x: f64,
+        }
+
+        let x = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let srwm = SRWM::new(x, |_m: &Model| 0.0, Some(1.0)).unwrap();
+        let mut rng = StdRng::from_seed(SEED);
+        let runner = Runner::new(srwm).warmup(15).samples(30);
+
+        let mut session = runner.session(&mut rng, Model { x: 0.0 });
+        session.advance(10);
+        let state = session.checkpoint(0);
+        assert_eq!(state.iteration, 10);
+        while !session.advance(10) {}
+
+        // Steps 11 to 15 are warmup, which the session does not keep.
+        let replayed = state.replay(35);
+        assert_eq!(&replayed[5..], &session.draws()[0][..]);
+        assert_eq!(state.replay(35), replayed);
+
+        let mut resumed = state.clone();
+        (0..35).for_each(|_| {
+            resumed.step();
+        });
+        assert_eq!(resumed.model, *session.current()[0]);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn snapshots_restore_onto_rebuilt_steppers() {
+        use rand_xorshift::XorShiftRng;
+        use serde_yaml;
+        use steppers::{Registry, StepperSpec};
+
+        #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+        struct Model {
+            x: f64,
+            y: f64,
+        }
+
+        fn log_likelihood(m: &Model) -> f64 {
+            -0.5 * (m.x - m.y).powi(2)
+        }
+
+        let mut registry: Registry<Model, XorShiftRng> = Registry::new();
+        registry.scalar(
+            Parameter::new(
+                "x".to_string(),
+                Gaussian::new(0.0, 1.0).unwrap(),
+                make_lens!(Model, f64, x),
+            ),
+            log_likelihood,
+        );
+        registry.scalar(
+            Parameter::new(
+                "y".to_string(),
+                Gaussian::new(0.0, 1.0).unwrap(),
+                make_lens!(Model, f64, y),
+            ),
+            log_likelihood,
+        );
+        let spec = StepperSpec::Group(vec![
+            StepperSpec::srwm("x"),
+            StepperSpec::srwm("y"),
+        ]);
+
+        let runner = Runner::new(registry.build(&spec).unwrap())
+            .warmup(20)
+            .samples(30);
+        let mut rng = XorShiftRng::from_seed([1; 16]);
+        let mut session = runner.session(&mut rng, Model { x: 0.0, y: 0.0 });
+        session.advance(10);
+        let saved = serde_yaml::to_string(&session.snapshot(0)).unwrap();
+        while !session.advance(10) {}
+
+        // Midway through warmup, so the adaptors' state matters.
+        let snapshot: ChainSnapshot<Model, XorShiftRng> =
+            serde_yaml::from_str(&saved).unwrap();
+        let stepper = registry.build(&spec).unwrap();
+        let restored = ChainState::restore(snapshot.clone(), stepper).unwrap();
+        assert_eq!(restored.iteration, 10);
+        let replayed = restored.replay(40);
+        assert_eq!(&replayed[10..], &session.draws()[0][..]);
+
+        let other = registry.build(&StepperSpec::srwm("x")).unwrap();
+        let err = ChainState::restore(snapshot, other).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,137 @@
+//! Destinations for the draws of a chain
+
+use rand::Rng;
+
+/// Receives a chain's draws as they are generated
+pub trait DrawSink<M> {
+    /// Receive the next draw. `rng` is the chain's generator, for sinks
+    /// which make random choices.
+    fn push<R: Rng>(&mut self, rng: &mut R, draw: M);
+    /// The draws kept so far
+    fn draws(&self) -> &[M];
+}
+
+/// Keeps every draw, in order
+impl<M> DrawSink<M> for Vec<M> {
+    fn push<R: Rng>(&mut self, _rng: &mut R, draw: M) {
+        Vec::push(self, draw);
+    }
+
+    fn draws(&self) -> &[M] {
+        self
+    }
+}
+
+/// Keeps a uniform random subsample of at most `capacity` draws
+///
+/// Memory is bounded by the capacity however many draws the chain makes, so
+/// the total need not be known in advance. Every draw received is kept with
+/// equal probability. The kept draws are not in chain order.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate rand;
+/// # use rmcmc::runner::{DrawSink, Reservoir};
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # fn main() {
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let mut reservoir = Reservoir::new(10);
+/// for i in 0..1000 {
+///     reservoir.push(&mut rng, i);
+/// }
+/// assert_eq!(reservoir.draws().len(), 10);
+/// assert_eq!(reservoir.seen(), 1000);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Reservoir<M> {
+    capacity: usize,
+    seen: usize,
+    draws: Vec<M>,
+}
+
+impl<M> Reservoir<M> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be greater than 0.");
+        Reservoir {
+            capacity,
+            seen: 0,
+            draws: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Maximum number of draws kept
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of draws received
+    pub fn seen(&self) -> usize {
+        self.seen
+    }
+
+    /// Take the kept draws
+    pub fn into_draws(self) -> Vec<M> {
+        self.draws
+    }
+}
+
+impl<M> DrawSink<M> for Reservoir<M> {
+    fn push<R: Rng>(&mut self, rng: &mut R, draw: M) {
+        self.seen += 1;
+        if self.draws.len() < self.capacity {
+            self.draws.push(draw);
+        } else {
+            let j = rng.gen_range(0, self.seen);
+            if j < self.capacity {
+                self.draws[j] = draw;
+            }
+        }
+    }
+
+    fn draws(&self) -> &[M] {
+        &self.draws
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[test]
+    fn keeps_everything_below_capacity() {
+        let mut rng = StdRng::from_seed(SEED);
+        let mut reservoir = Reservoir::new(10);
+        (0..7).for_each(|i| reservoir.push(&mut rng, i));
+        assert_eq!(reservoir.draws(), &[0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn subsample_is_uniform() {
+        let mut rng = StdRng::from_seed(SEED);
+        let n = 100;
+        let capacity = 10;
+        let n_reps = 2000;
+
+        let mut counts = vec![0usize; n];
+        for _ in 0..n_reps {
+            let mut reservoir = Reservoir::new(capacity);
+            (0..n).for_each(|i| reservoir.push(&mut rng, i));
+            assert_eq!(reservoir.draws().len(), capacity);
+            reservoir.into_draws().into_iter().for_each(|i| counts[i] += 1);
+        }
+
+        // Each draw is kept with probability 1/10, i.e. 200 times on average.
+        let expected = (n_reps * capacity / n) as f64;
+        let first_half: usize = counts[..(n / 2)].iter().sum();
+        let second_half: usize = counts[(n / 2)..].iter().sum();
+        assert!(counts.iter().all(|&c| (c as f64 - expected).abs() < 70.0));
+        assert!((first_half as f64 / second_half as f64 - 1.0).abs() < 0.1);
+    }
+}
@@ -0,0 +1,218 @@
+//! Expose a stepper as an `rv` random variable
+
+use std::fmt;
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rv::traits::Rv;
+use log_density::LogDensity;
+
+use parameter::Parameter;
+use steppers::{SteppingAlg, AdaptationMode};
+
+/// A stepper and likelihood packaged as an `Rv` over a single parameter.
+///
+/// Each call to `draw` runs a short chain from the initial model and returns
+/// the parameter's value at its end. `ln_f` evaluates the *unnormalized* log
+/// posterior, i.e. the log likelihood plus the parameter's log prior with the
+/// parameter set to the given value in the initial model.
+///
+/// # Parameters
+/// `D`: The prior's `Rv` type
+/// `T`: The parameter's type
+/// `M`: The model type
+/// `A`: The stepper type
+/// `L`: The log likelihood type
+pub struct StepperRv<D, T, M, A, L>
+where
+    D: Rv<T> + Clone,
+    M: Clone,
+    A: SteppingAlg<M, StdRng> + Clone,
+    L: Fn(&M) -> f64 + Clone + Sync,
+{
+    pub stepper: A,
+    pub parameter: Parameter<D, T, M>,
+    pub log_likelihood: L,
+    pub init_model: M,
+    pub warmup_steps: usize,
+    pub steps: usize,
+}
+
+impl<D, T, M, A, L> Clone for StepperRv<D, T, M, A, L>
+where
+    D: Rv<T> + Clone,
+    M: Clone,
+    A: SteppingAlg<M, StdRng> + Clone,
+    L: Fn(&M) -> f64 + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        StepperRv {
+            stepper: self.stepper.clone(),
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            init_model: self.init_model.clone(),
+            warmup_steps: self.warmup_steps,
+            steps: self.steps,
+        }
+    }
+}
+
+impl<D, T, M, A, L> fmt::Debug for StepperRv<D, T, M, A, L>
+where
+    D: Rv<T> + Clone,
+    M: Clone,
+    A: SteppingAlg<M, StdRng> + Clone,
+    L: Fn(&M) -> f64 + Clone + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "StepperRv {{ stepper: {:?}, warmup_steps: {}, steps: {} }}",
+            self.stepper, self.warmup_steps, self.steps
+        )
+    }
+}
+
+impl<D, T, M, A, L> StepperRv<D, T, M, A, L>
+where
+    D: Rv<T> + Clone,
+    M: Clone,
+    A: SteppingAlg<M, StdRng> + Clone,
+    L: Fn(&M) -> f64 + Clone + Sync,
+{
+    pub fn new(
+        stepper: A,
+        parameter: Parameter<D, T, M>,
+        log_likelihood: L,
+        init_model: M,
+    ) -> Self {
+        StepperRv {
+            stepper,
+            parameter,
+            log_likelihood,
+            init_model,
+            warmup_steps: 0,
+            steps: 100,
+        }
+    }
+
+    /// Number of adaptive steps taken before the chain for each draw.
+    pub fn warmup(&self, steps: usize) -> Self {
+        StepperRv {
+            warmup_steps: steps,
+            ..(*self).clone()
+        }
+    }
+
+    /// Number of non-adaptive steps taken to produce each draw.
+    pub fn steps(&self, steps: usize) -> Self {
+        assert!(steps > 0, "steps must be greater than 0.");
+        StepperRv {
+            steps,
+            ..(*self).clone()
+        }
+    }
+}
+
+impl<D, T, M, A, L> Rv<T> for StepperRv<D, T, M, A, L>
+where
+    D: Rv<T> + Clone,
+    T: Clone,
+    M: Clone,
+    A: SteppingAlg<M, StdRng> + Clone,
+    L: Fn(&M) -> f64 + Clone + Sync,
+{
+    fn ln_f(&self, x: &T) -> f64 {
+        LogDensity(self.parameter.prior.ln_f(x))
+            .plus(|| {
+                let model =
+                    self.parameter.lens.set(&self.init_model, x.clone());
+                (self.log_likelihood)(&model)
+            })
+            .value()
+    }
+
+    fn draw<R: Rng>(&self, rng: &mut R) -> T {
+        let mut rng = StdRng::from_rng(rng)
+            .expect("Failed to create seedable rng from input rng.");
+        let mut stepper = self.stepper.clone();
+        stepper.reset();
+
+        stepper.set_adapt(AdaptationMode::Enabled);
+        let warmed = (0..self.warmup_steps)
+            .fold(self.init_model.clone(), |m, _| stepper.step(&mut rng, m));
+
+        stepper.set_adapt(AdaptationMode::Disabled);
+        let model = (0..self.steps)
+            .fold(warmed, |m, _| stepper.step(&mut rng, m));
+
+        self.parameter.lens.get(&model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use lens::*;
+    use rv::dist::*;
+    use rv::misc::ks_test;
+    use rv::prelude::Cdf;
+    use steppers::SRWM;
+    use utils::multiple_tries;
+
+    const P_VAL: f64 = 0.2;
+    const N_TRIES: usize = 10;
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Copy, Clone, Debug)]
+    struct Model {
+        x: f64,
+    }
+
+    fn log_likelihood(m: &Model) -> f64 {
+        Gaussian::new(0.0, 1.0).unwrap().ln_f(&m.x)
+    }
+
+    #[test]
+    fn draws_match_posterior() {
+        let mut rng = StdRng::from_seed(SEED);
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Uniform::new(-10.0, 10.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let stepper =
+            SRWM::new(parameter.clone(), log_likelihood, Some(1.0)).unwrap();
+        let posterior =
+            StepperRv::new(stepper, parameter, log_likelihood, Model { x: 0.0 })
+                .steps(50);
+
+        let passed = multiple_tries(N_TRIES, |_| {
+            let samples: Vec<f64> = posterior.sample(500, &mut rng);
+            let (stat, p) =
+                ks_test(&samples, |s| Gaussian::new(0.0, 1.0).unwrap().cdf(&s));
+            println!("test stat = {}, p = {}", stat, p);
+            p > P_VAL
+        });
+        assert!(passed);
+    }
+
+    #[test]
+    fn ln_f_is_unnormalized_posterior() {
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Uniform::new(-10.0, 10.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let stepper =
+            SRWM::new(parameter.clone(), log_likelihood, Some(1.0)).unwrap();
+        let posterior =
+            StepperRv::new(stepper, parameter, log_likelihood, Model { x: 0.0 });
+
+        let expected = Gaussian::new(0.0, 1.0).unwrap().ln_f(&0.5)
+            + Uniform::new(-10.0, 10.0).unwrap().ln_f(&0.5);
+        assert!((posterior.ln_f(&0.5) - expected).abs() < 1E-10);
+        assert!(posterior.ln_f(&11.0).is_infinite());
+    }
+}
@@ -0,0 +1,263 @@
+//! Chain initialization stratified over prior quantiles
+//!
+//! R-hat compares the spread within chains to the spread between them, so
+//! it only detects chains stuck in different regions if they start spread
+//! out. Independent prior draws can start several chains close together;
+//! spacing each parameter's initial values across strata of its prior's
+//! quantiles spreads them by construction.
+
+use std::fmt;
+use std::sync::Arc;
+use rand::distributions::Open01;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rv::traits::InverseCdf;
+
+use parameter::{Parameter, ParamId};
+use runner::{Runner, Sample};
+use steppers::SteppingAlg;
+
+/// Sets a parameter's value in a model to its prior's quantile at `p`
+type SetQuantile<M> = Arc<dyn Fn(&M, f64) -> M + Send + Sync>;
+
+/// Parameters whose chains' initial values are stratified over quantiles of
+/// their priors
+///
+/// With `n` chains, each parameter's initial values are drawn one from each
+/// of `n` equally likely strata of its prior, i.e. at quantiles
+/// `(i + u) / n` with `u` uniform, and the strata are assigned to chains in
+/// an independent random order per parameter, a Latin hypercube. Parameters
+/// whose priors have no inverse CDF are left to `SteppingAlg::draw_prior`.
+///
+/// # Example
+/// ```
+/// # #[macro_use] extern crate rmcmc;
+/// # extern crate rand;
+/// # extern crate rv;
+/// # use rmcmc::lens::*;
+/// # use rmcmc::parameter::Parameter;
+/// # use rmcmc::runner::{Runner, StratifiedInit};
+/// # use rmcmc::steppers::SRWM;
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # use rv::dist::Gaussian;
+/// # fn main() {
+/// #[derive(Clone, Debug)]
+/// struct Model {
+///     x: f64,
+/// }
+///
+/// let x = Parameter::new(
+///     "x".to_string(),
+///     Gaussian::new(0.0, 1.0).unwrap(),
+///     make_lens!(Model, f64, x),
+/// );
+/// let strata = StratifiedInit::new().parameter(&x);
+/// let runner = Runner::new(SRWM::new(x, |_: &Model| 0.0, None).unwrap())
+///     .chains(4);
+///
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let inits = runner.stratified_inits(&mut rng, Model { x: 0.0 }, &strata);
+/// let mut xs: Vec<f64> = inits.iter().map(|m| m.x).collect();
+/// xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+/// // One initial value in each quartile of the prior
+/// assert!(xs[0] < -0.674 && xs[1] < 0.0 && xs[2] > 0.0 && xs[3] > 0.674);
+///
+/// let sample = runner.warmup(100).samples(100).sample_from_inits(
+///     &mut rng,
+///     inits,
+/// );
+/// assert_eq!(sample.n_chains(), 4);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct StratifiedInit<M> {
+    strata: Vec<(ParamId, SetQuantile<M>)>,
+}
+
+impl<M> StratifiedInit<M> {
+    pub fn new() -> Self {
+        StratifiedInit { strata: Vec::new() }
+    }
+
+    /// Stratify the initial values of `parameter` over its prior's
+    /// quantiles, replacing any earlier stratification of it.
+    pub fn parameter<D, T>(&self, parameter: &Parameter<D, T, M>) -> Self
+    where
+        D: InverseCdf<T> + Clone + Send + Sync + 'static,
+        T: 'static,
+        M: 'static,
+    {
+        let prior = parameter.prior.clone();
+        let lens = parameter.lens.clone();
+        let set: SetQuantile<M> =
+            Arc::new(move |m: &M, p: f64| lens.set(m, prior.invcdf(p)));
+        let mut strata: Vec<(ParamId, SetQuantile<M>)> = self
+            .strata
+            .iter()
+            .filter(|(id, _)| id != parameter.id())
+            .cloned()
+            .collect();
+        strata.push((parameter.id().clone(), set));
+        StratifiedInit { strata }
+    }
+
+    /// Parameters whose initial values are stratified
+    pub fn parameters(&self) -> Vec<ParamId> {
+        self.strata.iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    /// Set the stratified parameters of each of `models`, one stratum of
+    /// `models.len()` per model.
+    pub fn stratify<R: Rng>(&self, rng: &mut R, models: Vec<M>) -> Vec<M> {
+        let n = models.len();
+        self.strata.iter().fold(models, |models, (_, set)| {
+            let mut strata: Vec<usize> = (0..n).collect();
+            strata.shuffle(rng);
+            models
+                .iter()
+                .zip(strata)
+                .map(|(m, i)| {
+                    let u: f64 = rng.sample(Open01);
+                    set(m, (i as f64 + u) / n as f64)
+                })
+                .collect()
+        })
+    }
+}
+
+impl<M> Default for StratifiedInit<M> {
+    fn default() -> Self {
+        StratifiedInit::new()
+    }
+}
+
+impl<M> fmt::Debug for StratifiedInit<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StratifiedInit {{ parameters: {:?} }}", self.parameters())
+    }
+}
+
+impl<M, A, R> Runner<M, A, R>
+where
+    M: Clone + Sync + Send,
+    A: SteppingAlg<M, R> + Send + Sync + Clone,
+    M: 'static,
+    A: 'static,
+    R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+{
+    /// An initial model for each chain, drawn from the prior from
+    /// `template` with the parameters of `strata` stratified over their
+    /// priors' quantiles
+    ///
+    /// Parameters fixed with `fix` keep their values, stratified or not.
+    pub fn stratified_inits(
+        &self,
+        rng: &mut R,
+        template: M,
+        strata: &StratifiedInit<M>,
+    ) -> Vec<M> {
+        let models = self.sample_prior(rng, template, self.n_chains);
+        strata.stratify(rng, models)
+    }
+
+    /// As `sample`, starting the chains from `stratified_inits`
+    pub fn sample_stratified(
+        &self,
+        rng: &mut R,
+        template: M,
+        strata: &StratifiedInit<M>,
+    ) -> Sample<M> {
+        let inits = self.stratified_inits(rng, template, strata);
+        self.sample_from_inits(rng, inits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use rand::rngs::StdRng;
+    use rv::dist::{Gamma, Gaussian};
+    use rv::traits::Cdf;
+    use steppers::{Group, SRWM};
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Model {
+        a: f64,
+        b: f64,
+    }
+
+    fn log_likelihood(_m: &Model) -> f64 {
+        0.0
+    }
+
+    #[test]
+    fn each_stratum_starts_one_chain() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(2.0, 3.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let b = Parameter::new(
+            "b".to_string(),
+            Gamma::new(2.0, 1.0).unwrap(),
+            make_lens!(Model, f64, b),
+        );
+        // Gamma has no inverse CDF to stratify b by, but b may be fixed.
+        let strata = StratifiedInit::new().parameter(&a).parameter(&a);
+        assert_eq!(strata.parameters(), vec![a.id().clone()]);
+        let srwm = SRWM::new(a.clone(), log_likelihood, None).unwrap();
+        let runner = Runner::new(srwm).chains(5).fix(&b, 1.5);
+
+        for _ in 0..20 {
+            let template = Model { a: 0.0, b: 0.0 };
+            let inits = runner.stratified_inits(&mut rng, template, &strata);
+            let mut strata: Vec<usize> = inits
+                .iter()
+                .map(|m| (a.prior.cdf(&m.a) * 5.0) as usize)
+                .collect();
+            strata.sort();
+            assert_eq!(strata, vec![0, 1, 2, 3, 4]);
+            assert!(inits.iter().all(|m| m.b == 1.5));
+        }
+    }
+
+    #[test]
+    fn parameters_are_stratified_independently() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let b = Parameter::new(
+            "b".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, b),
+        );
+        let strata = StratifiedInit::new().parameter(&a).parameter(&b);
+        let group: Group<Model, StdRng> = Group::new(vec![
+            Box::new(SRWM::new(a, log_likelihood, None).unwrap()),
+            Box::new(SRWM::new(b, log_likelihood, None).unwrap()),
+        ]);
+
+        // Chains ordered by a are rarely also ordered by b.
+        fn ranks(x: &[f64]) -> Vec<usize> {
+            let mut order: Vec<usize> = (0..x.len()).collect();
+            order.sort_by(|&i, &j| x[i].partial_cmp(&x[j]).unwrap());
+            order
+        }
+        for _ in 0..20 {
+            let template = Model { a: 0.0, b: 0.0 };
+            let models = group.sample_prior(&mut rng, &template, 8);
+            let inits = strata.stratify(&mut rng, models);
+            let a: Vec<f64> = inits.iter().map(|m| m.a).collect();
+            let b: Vec<f64> = inits.iter().map(|m| m.b).collect();
+            assert_ne!(ranks(&a), ranks(&b));
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Wall time of individual steps
+//!
+//! A chain which is slow overall may be slow everywhere or only in some
+//! region of the parameter space, e.g. where an ODE solver stiffens or a
+//! series converges slowly. Timing every `k`th step and flagging the steps
+//! far slower than the rest points at such regions.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use runner::Sample;
+
+/// Median absolute deviations above the median beyond which a step is slow
+pub const SLOW_MADS: f64 = 5.0;
+
+/// How long one step took
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct StepTime {
+    /// Number of the step in its chain, counting warmup steps from 1
+    pub iteration: usize,
+    pub duration: Duration,
+}
+
+/// A step far slower than the others of its run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SlowStep {
+    /// Index of the chain in the sample
+    pub chain: usize,
+    pub time: StepTime,
+}
+
+/// Buffer of the times of every `every`th step of a chain
+///
+/// Clones share the buffer.
+#[derive(Clone, Debug)]
+pub struct StepTimer {
+    every: usize,
+    times: Arc<Mutex<Vec<StepTime>>>,
+}
+
+impl StepTimer {
+    pub fn new(every: usize) -> Self {
+        assert!(every > 0, "steps must be timed every k > 0 steps.");
+        StepTimer {
+            every,
+            times: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Whether step number `iteration`, counting from 1, is timed
+    pub fn is_due(&self, iteration: usize) -> bool {
+        iteration % self.every == 0
+    }
+
+    pub fn record(&self, iteration: usize, duration: Duration) {
+        self.times
+            .lock()
+            .expect("Failed to lock step times")
+            .push(StepTime {
+                iteration,
+                duration,
+            });
+    }
+
+    /// Take the times recorded so far.
+    pub fn drain(&self) -> Vec<StepTime> {
+        let mut times = self.times.lock().expect("Failed to lock step times");
+        times.drain(..).collect()
+    }
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1E-9
+}
+
+fn median(x: &mut [f64]) -> f64 {
+    x.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = x.len();
+    if n % 2 == 1 {
+        x[n / 2]
+    } else {
+        0.5 * (x[n / 2 - 1] + x[n / 2])
+    }
+}
+
+/// Steps of `chains` slower than the median step time by more than
+/// `threshold` median absolute deviations, e.g. `SLOW_MADS`, in order of
+/// chain and iteration
+///
+/// The times of every chain are pooled, since the chains run the same
+/// model. Fewer than three times give no slow steps.
+pub fn slow_steps(chains: &[Vec<StepTime>], threshold: f64) -> Vec<SlowStep> {
+    let mut secs_all: Vec<f64> = chains
+        .iter()
+        .flat_map(|times| times.iter().map(|t| secs(t.duration)))
+        .collect();
+    if secs_all.len() < 3 {
+        return Vec::new();
+    }
+    let center = median(&mut secs_all);
+    let mut deviations: Vec<f64> =
+        secs_all.iter().map(|s| (s - center).abs()).collect();
+    let mad = median(&mut deviations);
+    chains
+        .iter()
+        .enumerate()
+        .flat_map(|(chain, times)| {
+            times.iter().map(move |&time| SlowStep { chain, time })
+        })
+        .filter(|slow| secs(slow.time.duration) > center + threshold * mad)
+        .collect()
+}
+
+impl<M> Sample<M> {
+    /// Whether any run making up the sample timed its steps
+    pub fn has_step_times(&self) -> bool {
+        self.provenance
+            .iter()
+            .any(|p| p.step_times.iter().any(|t| !t.is_empty()))
+    }
+
+    /// Steps slower than the median by more than `threshold` median absolute
+    /// deviations, e.g. `SLOW_MADS`, among the steps timed in each run
+    ///
+    /// Chains are numbered as in `chains`, assuming the runs' provenance is
+    /// in the order their chains were merged. A slow step's iteration
+    /// locates the models around it, e.g. to find the region of parameter
+    /// space where the likelihood is expensive.
+    pub fn slow_steps(&self, threshold: f64) -> Vec<SlowStep> {
+        let mut offset = 0;
+        let mut slow = Vec::new();
+        for provenance in self.provenance.iter() {
+            let run = slow_steps(&provenance.step_times, threshold);
+            slow.extend(run.into_iter().map(|s| SlowStep {
+                chain: s.chain + offset,
+                time: s.time,
+            }));
+            offset += provenance.n_chains;
+        }
+        slow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn times(millis: &[u64]) -> Vec<StepTime> {
+        millis
+            .iter()
+            .enumerate()
+            .map(|(i, &ms)| StepTime {
+                iteration: 10 * (i + 1),
+                duration: Duration::from_millis(ms),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn outlying_steps_are_slow() {
+        let chains = vec![times(&[10, 11, 9, 10, 12]), times(&[10, 90, 11])];
+        let slow = slow_steps(&chains, SLOW_MADS);
+        assert_eq!(slow.len(), 1);
+        assert_eq!(slow[0].chain, 1);
+        assert_eq!(slow[0].time.iteration, 20);
+
+        assert!(slow_steps(&[times(&[10, 1000])], SLOW_MADS).is_empty());
+        let even = vec![times(&[10, 10, 10, 10])];
+        assert!(slow_steps(&even, SLOW_MADS).is_empty());
+    }
+
+    #[test]
+    fn timer_records_due_steps() {
+        let timer = StepTimer::new(3);
+        let shared = timer.clone();
+        (1..10).filter(|&i| timer.is_due(i)).for_each(|i| {
+            shared.record(i, Duration::from_millis(1));
+        });
+        let recorded: Vec<usize> =
+            timer.drain().iter().map(|t| t.iteration).collect();
+        assert_eq!(recorded, vec![3, 6, 9]);
+        assert!(timer.drain().is_empty());
+    }
+}
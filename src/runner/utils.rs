@@ -2,6 +2,11 @@ use steppers::{SteppingAlg, AdaptationMode};
 use rand::prelude::*;
 use std::sync::{Arc, RwLock};
 use std::ops::DerefMut;
+use std::time::Instant;
+use runner::DrawSink;
+use runner::hooks::{HookEvent, Hooks};
+use runner::timing::{StepTime, StepTimer};
+use events::{self, EventSink};
 
 pub fn draw_from_stepper<M, A, R>(
     rng: Arc<RwLock<&mut R>>,
@@ -17,59 +22,292 @@ where
     A: SteppingAlg<M, R> + Send + Sync + Clone,
     R: SeedableRng + Rng + std::fmt::Debug,
 {
-    let mut rng = SeedableRng::from_rng(
+    let capacity = if keep_warmup { n_warmup + n_draws } else { n_draws };
+    draw_into_sink(
+        rng,
+        stepper,
+        init,
+        Vec::with_capacity(capacity),
+        n_draws,
+        n_warmup,
+        thinning,
+        keep_warmup,
+    )
+}
+
+/// Run a chain, passing its draws to `sink` as they are made.
+pub fn draw_into_sink<M, A, R, S>(
+    rng: Arc<RwLock<&mut R>>,
+    stepper: A,
+    init: M,
+    sink: S,
+    n_draws: usize,
+    n_warmup: usize,
+    thinning: usize,
+    keep_warmup: bool,
+) -> S
+where
+    M: Clone + Sync + Send,
+    A: SteppingAlg<M, R> + Send + Sync + Clone,
+    R: SeedableRng + Rng + std::fmt::Debug,
+    S: DrawSink<M>,
+{
+    let mut rng: R = SeedableRng::from_rng(
         rng.write()
         .expect("Failed to get write access to rng")
         .deref_mut()
     ).expect("Failed to create seedable rng from input rng.");
 
+    step_into_sink(
+        &mut rng,
+        stepper,
+        init,
+        sink,
+        n_draws,
+        n_warmup,
+        thinning,
+        keep_warmup,
+    )
+}
 
-    let mut stepper = stepper.clone();
-    // let prior_sample = stepper.prior_sample(&mut rng, init_model);
-    let prior_sample = init;
+/// Run a chain with its own generator `rng`, passing its draws to `sink` as
+/// they are made.
+pub fn step_into_sink<M, A, R, S>(
+    rng: &mut R,
+    stepper: A,
+    init: M,
+    sink: S,
+    n_draws: usize,
+    n_warmup: usize,
+    thinning: usize,
+    keep_warmup: bool,
+) -> S
+where
+    M: Clone,
+    A: SteppingAlg<M, R> + Clone,
+    R: Rng,
+    S: DrawSink<M>,
+{
+    let hooks = Hooks::new();
+    let settings = ChainSettings {
+        n_draws,
+        n_warmup,
+        thinning,
+        keep_warmup,
+        hooks: &hooks,
+        chain: 0,
+        events: None,
+        timer: None,
+    };
+    step_into_sink_with_hooks(rng, stepper, init, sink, &settings)
+}
+
+/// How `step_into_sink_with_hooks` runs a chain
+pub struct ChainSettings<'a, M: 'a> {
+    pub n_draws: usize,
+    pub n_warmup: usize,
+    pub thinning: usize,
+    pub keep_warmup: bool,
+    /// Called at the chain's events with the chain's index `chain`
+    pub hooks: &'a Hooks<M>,
+    pub chain: usize,
+    /// Collects the stepper's events
+    pub events: Option<EventSink>,
+    /// Records the times of the steps due
+    pub timer: Option<StepTimer>,
+}
 
+/// As `step_into_sink`, with the draws, hooks, event sink and step timer
+/// of the chain given by `settings`.
+pub fn step_into_sink_with_hooks<M, A, R, S>(
+    rng: &mut R,
+    stepper: A,
+    init: M,
+    mut sink: S,
+    settings: &ChainSettings<M>,
+) -> S
+where
+    M: Clone,
+    A: SteppingAlg<M, R> + Clone,
+    R: Rng,
+    S: DrawSink<M>,
+{
     //TODO - Randomly initialize all model values
+    let mut chain = ChainLoop::start(stepper, init, settings);
+    while !chain.is_done() {
+        chain.step(rng, &mut sink, settings.hooks);
+    }
+    sink
+}
 
-    // WarmUp
-    stepper.set_adapt(AdaptationMode::Enabled);
-
-    let mut warmup_draws = if keep_warmup {
-        (0..n_warmup)
-            .scan(prior_sample.clone(), |m, _| { 
-                *m = stepper.step(&mut rng, (*m).clone());
-                Some(m.clone())
-            }).collect()
-   
-    } else {
-        let mp = (0..n_warmup)
-            .fold(prior_sample.clone(), |m, _| {
-                stepper.step(&mut rng, m)
-            });
-        vec![mp]
-    };
+/// A chain as run by `step_into_sink_with_hooks`, which can also be
+/// advanced one step at a time, as a `Session` does
+///
+/// The chain collects events and times steps as its `ChainSettings` say.
+/// The hooks are passed to every step rather than kept, so the chain does
+/// not borrow them.
+#[derive(Clone, Debug)]
+pub struct ChainLoop<M, A> {
+    pub stepper: A,
+    // Taken while the stepper steps from it
+    model: Option<M>,
+    iteration: usize,
+    n_draws: usize,
+    n_warmup: usize,
+    thinning: usize,
+    keep_warmup: bool,
+    chain: usize,
+    // Divergences are found in the stepper's events, kept only for the
+    // step at hand when they are not being collected.
+    events: Option<EventSink>,
+    keep_events: bool,
+    watch_divergence: bool,
+    timer: Option<StepTimer>,
+}
+
+impl<M, A> ChainLoop<M, A>
+where
+    M: Clone,
+{
+    /// A chain of `stepper` from `init`, run as `settings` say, after
+    /// firing the `ChainStart` hooks.
+    pub fn start(stepper: A, init: M, settings: &ChainSettings<M>) -> Self {
+        let watch_divergence = settings.hooks.watches_divergence();
+        let events = settings.events.clone().or_else(|| {
+            if watch_divergence {
+                Some(EventSink::new())
+            } else {
+                None
+            }
+        });
+        let chain = ChainLoop {
+            stepper,
+            model: Some(init),
+            iteration: 0,
+            n_draws: settings.n_draws,
+            n_warmup: settings.n_warmup,
+            thinning: settings.thinning,
+            keep_warmup: settings.keep_warmup,
+            chain: settings.chain,
+            events,
+            keep_events: settings.events.is_some(),
+            watch_divergence,
+            timer: settings.timer.clone(),
+        };
+        let hooks = settings.hooks;
+        hooks.fire(HookEvent::ChainStart, chain.chain, 0, chain.model());
+        chain.end_phase(hooks);
+        chain
+    }
 
-    // Draw the steps from the chain
-    stepper.set_adapt(AdaptationMode::Disabled);
+    /// The chain's current model
+    pub fn model(&self) -> &M {
+        self.model.as_ref().expect("the chain has no model")
+    }
 
-    let warmed_model: M = if warmup_draws.is_empty() {
-        prior_sample
-    } else {
-        warmup_draws.last().unwrap().clone()
-    };
+    /// Number of steps taken, including warmup
+    pub fn iteration(&self) -> usize {
+        self.iteration
+    }
+
+    /// Number of steps in the whole chain, including warmup
+    pub fn total_steps(&self) -> usize {
+        self.n_warmup + self.n_draws * self.thinning
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.iteration == self.total_steps()
+    }
+
+    pub fn is_warming_up(&self) -> bool {
+        self.iteration < self.n_warmup
+    }
+
+    /// Take the events collected so far, if the settings collect them.
+    pub fn drain_events(&self) -> Vec<events::Event> {
+        match self.events {
+            Some(ref events) if self.keep_events => events.drain(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Take the step times recorded so far, if the settings time steps.
+    pub fn drain_step_times(&self) -> Vec<StepTime> {
+        self.timer.as_ref().map_or(Vec::new(), |timer| timer.drain())
+    }
+
+    /// Take the chain's next step, passing the new model to `sink` if it
+    /// is kept and firing the `hooks` due. Does nothing once the chain is
+    /// done.
+    pub fn step<R, S>(&mut self, rng: &mut R, sink: &mut S, hooks: &Hooks<M>)
+    where
+        A: SteppingAlg<M, R>,
+        R: Rng,
+        S: DrawSink<M>,
+    {
+        if self.is_done() {
+            return;
+        }
+        if self.iteration == 0 {
+            if let Some(ref events) = self.events {
+                self.stepper.set_event_sink(events.clone());
+            }
+            self.stepper.set_adapt(AdaptationMode::Enabled);
+        }
+        if self.iteration == self.n_warmup {
+            self.stepper.set_adapt(AdaptationMode::Disabled);
+        }
+        let iteration = self.iteration + 1;
+        let model = self.model.take().expect("the chain has no model");
+
+        let start = self.events.as_ref().map_or(0, |e| e.len());
+        let timed = self.timer.as_ref().filter(|t| t.is_due(iteration));
+        let started = timed.map(|timer| (timer, Instant::now()));
+        let next = self.stepper.step(rng, model);
+        if let Some((timer, started)) = started {
+            timer.record(iteration, started.elapsed());
+        }
+        if let (true, Some(events)) = (self.watch_divergence, &self.events) {
+            let diverged = events.any_since(start, |e| match e {
+                events::Event::NumericalWarning { .. } => true,
+                _ => false,
+            });
+            if diverged {
+                let chain = self.chain;
+                hooks.fire(HookEvent::Divergence, chain, iteration, &next);
+            }
+            if !self.keep_events {
+                events.drain();
+            }
+        }
+
+        if iteration <= self.n_warmup {
+            if self.keep_warmup {
+                sink.push(rng, next.clone());
+            }
+        } else {
+            let i = iteration - self.n_warmup - 1;
+            if i % self.thinning == 0 {
+                sink.push(rng, next.clone());
+                let draw = i / self.thinning + 1;
+                hooks.fire_draw(self.chain, iteration, draw, &next);
+            }
+        }
+        self.model = Some(next);
+        self.iteration = iteration;
+        self.end_phase(hooks);
+    }
 
-    let draws: Vec<M> = (0..(n_draws * thinning))
-        .scan(warmed_model, |m, _| {
-            *m = stepper.step(&mut rng, (*m).clone());
-            Some(m.clone())
-        })
-        .step_by(thinning)
-        .collect();
-    
-    if keep_warmup {
-        warmup_draws.extend(draws);
-        warmup_draws
-    } else {
-        draws
+    // Fire the hooks of the end of warmup or of the chain when the chain
+    // has just reached it.
+    fn end_phase(&self, hooks: &Hooks<M>) {
+        let (chain, iteration) = (self.chain, self.iteration);
+        if iteration == self.n_warmup {
+            hooks.fire(HookEvent::WarmupEnd, chain, iteration, self.model());
+        }
+        if self.is_done() {
+            hooks.fire(HookEvent::ChainEnd, chain, iteration, self.model());
+        }
     }
 }
 
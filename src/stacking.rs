@@ -0,0 +1,170 @@
+//! Model averaging by stacking and pseudo-BMA
+//!
+//! Combine the predictions of several models fit to the same data with
+//! weights chosen from their pointwise out-of-sample log predictive
+//! densities, e.g. the `pointwise` field of each model's `KFoldResult`.
+//! Stacking weights the models to maximize the log score of the combined
+//! predictive and so favours models that predict different data well;
+//! pseudo-BMA weights each model by its expected log predictive density
+//! alone.
+
+use rand::Rng;
+use rand::distributions::{Distribution, Uniform, WeightedIndex};
+
+/// Largest change in any weight at which `stacking_weights` stops
+const TOLERANCE: f64 = 1E-10;
+/// Most iterations taken by `stacking_weights`
+const MAX_ITERATIONS: usize = 10_000;
+
+fn check_pointwise(pointwise: &[Vec<f64>]) -> usize {
+    assert!(!pointwise.is_empty(), "model averaging requires a model.");
+    let n = pointwise[0].len();
+    assert!(n > 0, "model averaging requires at least one datum.");
+    assert!(
+        pointwise.iter().all(|lpd| lpd.len() == n),
+        "every model must be scored on the same data."
+    );
+    n
+}
+
+/// Stacking weights of the models with pointwise log predictive densities
+/// `pointwise`, one vector per model indexed by datum.
+///
+/// The weights maximize `sum_i ln(sum_k w_k exp(pointwise[k][i]))` over the
+/// simplex, found with the EM iteration for mixture weights.
+pub fn stacking_weights(pointwise: &[Vec<f64>]) -> Vec<f64> {
+    let n = check_pointwise(pointwise);
+    let n_models = pointwise.len();
+
+    // Densities of each datum relative to its best model, which leaves the
+    // optimum unchanged and keeps them from underflowing.
+    let densities: Vec<Vec<f64>> = (0..n)
+        .map(|i| {
+            let max = pointwise
+                .iter()
+                .map(|lpd| lpd[i])
+                .fold(std::f64::NEG_INFINITY, f64::max);
+            pointwise.iter().map(|lpd| (lpd[i] - max).exp()).collect()
+        })
+        .collect();
+
+    let mut weights = vec![1.0 / n_models as f64; n_models];
+    for _ in 0..MAX_ITERATIONS {
+        let mut updated = vec![0.0; n_models];
+        for p in densities.iter() {
+            let mixture: f64 =
+                p.iter().zip(weights.iter()).map(|(p, w)| p * w).sum();
+            for k in 0..n_models {
+                updated[k] += weights[k] * p[k] / mixture / n as f64;
+            }
+        }
+        let change = updated
+            .iter()
+            .zip(weights.iter())
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max);
+        weights = updated;
+        if change < TOLERANCE {
+            break;
+        }
+    }
+    weights
+}
+
+/// Pseudo-BMA weights of the models with pointwise log predictive densities
+/// `pointwise`, proportional to the exponentiated sum of each model's.
+pub fn pseudo_bma_weights(pointwise: &[Vec<f64>]) -> Vec<f64> {
+    check_pointwise(pointwise);
+    let elpds: Vec<f64> =
+        pointwise.iter().map(|lpd| lpd.iter().sum()).collect();
+    let max = elpds.iter().cloned().fold(std::f64::NEG_INFINITY, f64::max);
+    let unnormalized: Vec<f64> =
+        elpds.iter().map(|elpd| (elpd - max).exp()).collect();
+    let total: f64 = unnormalized.iter().sum();
+    unnormalized.iter().map(|w| w / total).collect()
+}
+
+/// `n` draws from the mixture of the models' predictive draws `draws` with
+/// mixture weights `weights`.
+///
+/// Each draw picks a model with probability its weight and then one of
+/// that model's draws uniformly, so models may contribute different numbers
+/// of draws.
+pub fn averaged_draws<X, R>(
+    rng: &mut R,
+    weights: &[f64],
+    draws: &[Vec<X>],
+    n: usize,
+) -> Vec<X>
+where
+    X: Clone,
+    R: Rng,
+{
+    assert_eq!(
+        weights.len(),
+        draws.len(),
+        "every model requires a weight."
+    );
+    assert!(
+        draws
+            .iter()
+            .zip(weights.iter())
+            .all(|(d, &w)| w == 0.0 || !d.is_empty()),
+        "every weighted model requires at least one draw."
+    );
+    let models = WeightedIndex::new(weights).expect("invalid model weights.");
+    (0..n)
+        .map(|_| {
+            let model = &draws[models.sample(rng)];
+            model[Uniform::new(0, model.len()).sample(rng)].clone()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[test]
+    fn stacking_combines_complementary_models() {
+        // Each model predicts half of the data well, the first slightly
+        // better overall.
+        let first: Vec<f64> = (0..100)
+            .map(|i| if i < 50 { -1.0 } else { -3.9 })
+            .collect();
+        let second: Vec<f64> = (0..100)
+            .map(|i| if i < 50 { -4.0 } else { -1.0 })
+            .collect();
+        let pointwise = vec![first, second];
+
+        let stacking = stacking_weights(&pointwise);
+        assert!((stacking.iter().sum::<f64>() - 1.0).abs() < 1E-9);
+        assert!((stacking[0] - 0.5).abs() < 0.02);
+
+        // Pseudo-BMA puts all the weight on the better of the two.
+        let bma = pseudo_bma_weights(&pointwise);
+        assert!(bma[0] > 0.99);
+    }
+
+    #[test]
+    fn stacking_drops_a_dominated_model() {
+        let pointwise = vec![vec![-1.0; 20], vec![-2.0; 20]];
+        let weights = stacking_weights(&pointwise);
+        assert!(weights[0] > 0.999);
+    }
+
+    #[test]
+    fn averaged_draws_follow_the_weights() {
+        let mut rng = StdRng::from_seed(SEED);
+        let draws = vec![vec![0.0; 10], vec![1.0; 3], vec![]];
+        let averaged =
+            averaged_draws(&mut rng, &[0.25, 0.75, 0.0], &draws, 4000);
+        assert_eq!(averaged.len(), 4000);
+        let fraction = averaged.iter().sum::<f64>() / 4000.0;
+        assert!((fraction - 0.75).abs() < 0.03);
+    }
+}
@@ -1,11 +1,58 @@
-use steppers::SteppingAlg;
+use std::fmt;
+use std::marker::PhantomData;
+use parameter::ParamId;
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum StatisticValue {
     AcceptanceRate(f64),
-    LogLikelihood(f64)
+    LogLikelihood(f64),
+    /// Number of adaptor updates skipped for being non-finite
+    NonFiniteUpdates(usize),
+    /// Number of adaptor updates which held a degenerate proposal scale at
+    /// its floor
+    FlooredUpdates(usize),
+    /// Current proposal scale of an adaptive stepper
+    ProposalScale(f64),
+    /// Value before the last step, the value proposed and the log
+    /// acceptance ratio of the proposal
+    Proposal {
+        current: f64,
+        proposed: f64,
+        log_alpha: f64,
+    },
 }
 
+/// A statistic reported by a stepper, labeled by the parameter it describes
 pub struct Statistic<M, R> {
-    steppers: Box<SteppingAlg<M, R>>,
-    value: StatisticValue
+    pub parameter: ParamId,
+    pub value: StatisticValue,
+    phantom_m: PhantomData<M>,
+    phantom_r: PhantomData<R>,
+}
+
+impl<M, R> Statistic<M, R> {
+    pub fn new(parameter: ParamId, value: StatisticValue) -> Self {
+        Statistic {
+            parameter,
+            value,
+            phantom_m: PhantomData,
+            phantom_r: PhantomData,
+        }
+    }
+}
+
+impl<M, R> Clone for Statistic<M, R> {
+    fn clone(&self) -> Self {
+        Statistic::new(self.parameter.clone(), self.value)
+    }
+}
+
+impl<M, R> fmt::Debug for Statistic<M, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Statistic {{ parameter: {}, value: {:?} }}",
+            self.parameter, self.value
+        )
+    }
 }
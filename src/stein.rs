@@ -0,0 +1,186 @@
+//! Kernel Stein discrepancy
+//!
+//! A measure of how far a set of draws is from the posterior, computed from
+//! the draws and the gradient of the log posterior alone. Unlike diagnostics
+//! comparing chains, it detects draws which are consistently biased, e.g. from
+//! an approximate or mistuned sampler, and not only poor mixing. It goes to
+//! zero as the draws converge to the posterior.
+
+use nalgebra::DVector;
+
+/// Inverse multiquadric kernel `(c^2 + |x - y|^2)^beta`
+///
+/// With `beta` in `(-1, 0)` the discrepancy it gives detects
+/// non-convergence, see Gorham and Mackey (2017).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImqKernel {
+    pub c: f64,
+    pub beta: f64,
+}
+
+impl ImqKernel {
+    pub fn new(c: f64, beta: f64) -> Self {
+        assert!(c > 0.0, "c must be positive.");
+        assert!(
+            beta > -1.0 && beta < 0.0,
+            "beta must be between -1 and 0."
+        );
+        ImqKernel { c, beta }
+    }
+
+    /// Stein kernel between points `x` and `y` with log posterior gradients
+    /// `sx` and `sy`.
+    fn stein(
+        &self,
+        x: &DVector<f64>,
+        sx: &DVector<f64>,
+        y: &DVector<f64>,
+        sy: &DVector<f64>,
+    ) -> f64 {
+        let r = x - y;
+        let r2 = r.norm_squared();
+        let u = self.c * self.c + r2;
+        let beta = self.beta;
+        let k = u.powf(beta);
+        // grad_x k = -grad_y k = 2 beta u^(beta - 1) r
+        let dk = 2.0 * beta * u.powf(beta - 1.0);
+        let trace = -dk * x.len() as f64
+            - 4.0 * beta * (beta - 1.0) * u.powf(beta - 2.0) * r2;
+
+        sx.dot(sy) * k + dk * (sy.dot(&r) - sx.dot(&r)) + trace
+    }
+}
+
+impl Default for ImqKernel {
+    fn default() -> Self {
+        ImqKernel::new(1.0, -0.5)
+    }
+}
+
+/// Kernel Stein discrepancy of `draws` from the posterior whose log density
+/// gradient is `score`, or `None` without draws.
+///
+/// `point` maps a draw to the vector of its parameters. The cost is
+/// quadratic in the number of draws, so long chains should be thinned.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate nalgebra;
+/// # use rmcmc::stein::{kernel_stein_discrepancy, ImqKernel};
+/// # use nalgebra::DVector;
+/// # fn main() {
+/// let point = |x: &f64| DVector::from_column_slice(1, &[*x]);
+/// // Standard normal posterior
+/// let score = |x: &f64| DVector::from_column_slice(1, &[-x]);
+///
+/// let centered = vec![-1.0, -0.5, 0.0, 0.5, 1.0];
+/// let shifted: Vec<f64> = centered.iter().map(|x| x + 2.0).collect();
+///
+/// let kernel = ImqKernel::default();
+/// let good = kernel_stein_discrepancy(&centered, point, score, kernel);
+/// let bad = kernel_stein_discrepancy(&shifted, point, score, kernel);
+/// assert!(good.unwrap() < bad.unwrap());
+/// # }
+/// ```
+pub fn kernel_stein_discrepancy<M, P, G>(
+    draws: &[M],
+    point: P,
+    score: G,
+    kernel: ImqKernel,
+) -> Option<f64>
+where
+    P: Fn(&M) -> DVector<f64>,
+    G: Fn(&M) -> DVector<f64>,
+{
+    if draws.is_empty() {
+        return None;
+    }
+    let points: Vec<(DVector<f64>, DVector<f64>)> =
+        draws.iter().map(|m| (point(m), score(m))).collect();
+
+    let n = points.len();
+    let sum = (0..n).fold(0.0, |acc, i| {
+        let (ref x, ref sx) = points[i];
+        let diagonal = kernel.stein(x, sx, x, sx);
+        let off_diagonal: f64 = points[(i + 1)..]
+            .iter()
+            .map(|(y, sy)| kernel.stein(x, sx, y, sy))
+            .sum();
+        acc + diagonal + 2.0 * off_diagonal
+    });
+    Some(sum.max(0.0).sqrt() / n as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    fn point(x: &(f64, f64)) -> DVector<f64> {
+        DVector::from_column_slice(2, &[x.0, x.1])
+    }
+
+    // Independent N(0, 1) and N(1, 0.5) posteriors
+    fn score(x: &(f64, f64)) -> DVector<f64> {
+        DVector::from_column_slice(2, &[-x.0, -(x.1 - 1.0) / 0.25])
+    }
+
+    fn draws(n: usize, shift: f64, rng: &mut StdRng) -> Vec<(f64, f64)> {
+        let a: Vec<f64> = Gaussian::new(shift, 1.0).unwrap().sample(n, rng);
+        let b: Vec<f64> = Gaussian::new(1.0, 0.5).unwrap().sample(n, rng);
+        a.into_iter().zip(b).collect()
+    }
+
+    #[test]
+    fn detects_biased_draws() {
+        let mut rng = StdRng::from_seed(SEED);
+        let kernel = ImqKernel::default();
+
+        let exact = draws(300, 0.0, &mut rng);
+        let biased = draws(300, 0.3, &mut rng);
+        let ksd_exact =
+            kernel_stein_discrepancy(&exact, point, score, kernel).unwrap();
+        let ksd_biased =
+            kernel_stein_discrepancy(&biased, point, score, kernel).unwrap();
+        println!("exact = {}, biased = {}", ksd_exact, ksd_biased);
+        assert!(ksd_exact < ksd_biased);
+    }
+
+    #[test]
+    fn decreases_with_more_exact_draws() {
+        let mut rng = StdRng::from_seed(SEED);
+        let kernel = ImqKernel::default();
+
+        let few = draws(20, 0.0, &mut rng);
+        let many = draws(500, 0.0, &mut rng);
+        let ksd_few =
+            kernel_stein_discrepancy(&few, point, score, kernel).unwrap();
+        let ksd_many =
+            kernel_stein_discrepancy(&many, point, score, kernel).unwrap();
+        assert!(ksd_many < ksd_few);
+    }
+
+    #[test]
+    fn stein_kernel_is_symmetric() {
+        let kernel = ImqKernel::new(0.5, -0.3);
+        let x = (0.2, -1.0);
+        let y = (1.5, 0.7);
+        let kxy = kernel.stein(&point(&x), &score(&x), &point(&y), &score(&y));
+        let kyx = kernel.stein(&point(&y), &score(&y), &point(&x), &score(&x));
+        assert!((kxy - kyx).abs() < 1E-12);
+    }
+
+    #[test]
+    fn no_draws() {
+        let draws: Vec<(f64, f64)> = Vec::new();
+        let kernel = ImqKernel::default();
+        let ksd = kernel_stein_discrepancy(&draws, point, score, kernel);
+        assert!(ksd.is_none());
+    }
+}
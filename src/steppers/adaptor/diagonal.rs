@@ -0,0 +1,244 @@
+//! An implementation of the Diagonal Adaptor
+
+use std::io;
+use nalgebra::DVector;
+use steppers::adaptor::{AdaptorState, ScaleAdaptor};
+use steppers::{AdaptationStatus, AdaptationMode};
+use steppers::util::MetroplisUpdate;
+
+/// Smallest proposal scale of a coordinate, relative to its initial scale
+const MIN_RELATIVE_SCALE: f64 = 1E-8;
+
+/// # Diagonal Adaptor
+/// Global adaptive scaling for vector parameters which tracks only the
+/// variance of each coordinate, so every update is O(d) and no covariance
+/// matrix needs to be kept positive definite.
+///
+/// The proposal scale of coordinate *i* is *λ σ_i*, where *σ_i^2* is the
+/// stochastic variance of the coordinate and the global factor *λ* is
+/// tuned towards the target acceptance rate. `get_scale` returns *λ*.
+#[derive(Debug, Clone)]
+pub struct DiagonalAdaptor {
+    // Scale factor *λ*
+    log_lambda: f64,
+    // Stochastic mean *μ*, from the first update on
+    mu: Option<DVector<f64>>,
+    // Stochastic variance *σ^2* of each coordinate
+    variances: DVector<f64>,
+    // Proposal scale of each coordinate
+    scales: DVector<f64>,
+    // Initial proposal scales for reset
+    initial_scales: DVector<f64>,
+    // Number of adaptation steps.
+    step: usize,
+    // Alpha to stochastically optimize towards.
+    target_alpha: f64,
+    // Enables updates or not.
+    enabled: bool,
+    // Number of updates skipped for not being finite.
+    non_finite_updates: usize,
+    // Number of updates which raised a scale to its floor.
+    floored_updates: usize,
+}
+
+impl DiagonalAdaptor {
+    /// Adaptor starting from per-coordinate proposal scales `scales`.
+    pub fn new(scales: DVector<f64>) -> Self {
+        DiagonalAdaptor {
+            log_lambda: 0.0,
+            mu: None,
+            variances: scales.map(|s| s * s),
+            scales: scales.clone(),
+            initial_scales: scales,
+            step: 0,
+            target_alpha: 0.234,
+            enabled: false,
+            non_finite_updates: 0,
+            floored_updates: 0,
+        }
+    }
+
+    /// Current proposal scale of each coordinate
+    pub fn scales(&self) -> &DVector<f64> {
+        &self.scales
+    }
+
+    /// Number of updates in which a coordinate's scale collapsed below
+    /// its floor, a tiny fraction of its initial scale, and was held there.
+    pub fn floored_updates(&self) -> usize {
+        self.floored_updates
+    }
+}
+
+impl ScaleAdaptor<DVector<f64>> for DiagonalAdaptor {
+    fn get_scale(&self) -> f64 {
+        self.log_lambda.exp()
+    }
+
+    fn reset(&mut self) {
+        *self = DiagonalAdaptor {
+            enabled: self.enabled,
+            ..DiagonalAdaptor::new(self.initial_scales.clone())
+        };
+    }
+
+    fn non_finite_updates(&self) -> usize {
+        self.non_finite_updates
+    }
+
+    // The mean follows the variances once the first update has set it.
+    fn state(&self) -> AdaptorState {
+        let scales = Some(self.log_lambda)
+            .into_iter()
+            .chain(self.scales.iter().cloned());
+        let moments = self
+            .variances
+            .iter()
+            .chain(self.mu.iter().flat_map(|mu| mu.iter()))
+            .cloned();
+        AdaptorState::new(
+            scales.collect(),
+            moments.collect(),
+            vec![self.step, self.non_finite_updates, self.floored_updates],
+        )
+    }
+
+    fn restore(&mut self, state: &AdaptorState) -> io::Result<()> {
+        let dim = self.scales.len();
+        if state.moments.len() == 2 * dim {
+            state.expect(dim + 1, 2 * dim, 3)?;
+        } else {
+            state.expect(dim + 1, dim, 3)?;
+        }
+        self.log_lambda = state.scales[0];
+        self.scales = DVector::from_column_slice(dim, &state.scales[1..]);
+        self.variances =
+            DVector::from_column_slice(dim, &state.moments[..dim]);
+        self.mu = if state.moments.len() == 2 * dim {
+            Some(DVector::from_column_slice(dim, &state.moments[dim..]))
+        } else {
+            None
+        };
+        self.step = state.counts[0];
+        self.non_finite_updates = state.counts[1];
+        self.floored_updates = state.counts[2];
+        Ok(())
+    }
+
+    fn set_mode(&mut self, mode: AdaptationMode) {
+        match mode {
+            AdaptationMode::Enabled => self.enabled = true,
+            AdaptationMode::Disabled => self.enabled = false
+        }
+    }
+
+    fn get_mode(&self) -> AdaptationStatus {
+        match self.enabled {
+            true => AdaptationStatus::Enabled,
+            false => AdaptationStatus::Disabled
+        }
+    }
+
+    fn update(&mut self, update: &MetroplisUpdate<DVector<f64>>) {
+        if !self.enabled {
+            return;
+        }
+        let log_alpha = update.log_alpha();
+        // A NaN acceptance ratio carries no information about the scale,
+        // so skip it rather than corrupt the state.
+        if log_alpha.is_nan() {
+            self.non_finite_updates += 1;
+            return;
+        }
+        let value = update.value();
+        let mu = self.mu.get_or_insert_with(|| value.clone());
+
+        let g = 0.9 / ((self.step + 1) as f64).powf(0.9);
+        let delta = value - &*mu;
+        let alpha = log_alpha.exp().min(1.0);
+        let new_log_lambda =
+            self.log_lambda + g * (alpha - self.target_alpha);
+        let new_variances = &self.variances
+            + (delta.component_mul(&delta) - &self.variances) * g;
+        let lambda = new_log_lambda.exp();
+        let new_scales = new_variances.map(|v| lambda * v.sqrt());
+
+        if !lambda.is_finite() || !new_scales.iter().all(|s| s.is_finite()) {
+            self.non_finite_updates += 1;
+            return;
+        }
+        *mu += delta * g;
+        self.log_lambda = new_log_lambda;
+        self.step += 1;
+        // A coordinate which has not moved yet has no variance, so keep
+        // its previous scale rather than stop proposing moves for it.
+        // Degenerate scales would freeze the chain, so hold them at a floor.
+        let mut floored = false;
+        for i in 0..new_scales.len() {
+            if new_scales[i] > 0.0 {
+                let floor = MIN_RELATIVE_SCALE * self.initial_scales[i];
+                self.variances[i] = new_variances[i];
+                self.scales[i] = new_scales[i].max(floor);
+                floored |= new_scales[i] < floor;
+            }
+        }
+        if floored {
+            self.floored_updates += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use steppers::util::ProposalScores;
+
+    #[test]
+    fn scales_follow_each_coordinates_spread() {
+        let initial = DVector::from_element(2, 1.0);
+        let mut adaptor = DiagonalAdaptor::new(initial.clone());
+        adaptor.set_mode(AdaptationMode::Enabled);
+
+        // The first coordinate varies a hundred times more than the second.
+        for i in 0..2000 {
+            let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+            let value = DVector::from_column_slice(2, &[sign, sign * 0.01]);
+            let scores = ProposalScores::new(0.0, 0.234f64.ln());
+            adaptor.update(&MetroplisUpdate::Accepted(value, scores));
+        }
+        let scales = adaptor.scales().clone();
+        assert!((scales[0] / scales[1] / 100.0 - 1.0).abs() < 0.05);
+        assert!((adaptor.get_scale() - 1.0).abs() < 1E-6);
+
+        let nan = ProposalScores::new(0.0, std::f64::NAN);
+        adaptor.update(&MetroplisUpdate::Rejected(scales, nan));
+        assert_eq!(adaptor.non_finite_updates(), 1);
+
+        adaptor.reset();
+        assert_eq!(adaptor.scales(), &initial);
+        assert_eq!(adaptor.non_finite_updates(), 0);
+    }
+
+    #[test]
+    fn collapsing_scales_are_held_at_a_floor() {
+        let initial = DVector::from_element(2, 1.0);
+        let mut adaptor = DiagonalAdaptor::new(initial);
+        adaptor.set_mode(AdaptationMode::Enabled);
+
+        // The second coordinate has stopped moving after a long run, so its
+        // variance has all but vanished.
+        adaptor.variances[1] = 1E-30;
+        for i in 0..10 {
+            let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+            let value = DVector::from_column_slice(2, &[sign, 0.0]);
+            let scores = ProposalScores::new(0.0, 0.234f64.ln());
+            adaptor.update(&MetroplisUpdate::Accepted(value, scores));
+        }
+        assert_eq!(adaptor.scales()[1], MIN_RELATIVE_SCALE);
+        assert!(adaptor.scales()[0] > 0.1);
+        assert_eq!(adaptor.floored_updates(), 10);
+
+        adaptor.reset();
+        assert_eq!(adaptor.floored_updates(), 0);
+    }
+}
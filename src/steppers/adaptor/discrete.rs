@@ -0,0 +1,162 @@
+//! An adaptor for random walks over integers
+
+use std::io;
+use steppers::adaptor::{AdaptorState, ScaleAdaptor};
+use steppers::{AdaptationStatus, AdaptationMode};
+use steppers::util::MetroplisUpdate;
+use std::marker::PhantomData;
+use std::fmt::Debug;
+
+/// Acceptance rate `DiscreteAdaptor` targets by default
+///
+/// The 0.234 targeted by `GlobalAdaptor` is optimal only as the dimension
+/// grows; an integer parameter is walked one coordinate at a time, where
+/// the optimal rate is about 0.44.
+pub const DISCRETE_TARGET_ACCEPTANCE: f64 = 0.44;
+
+/// # Discrete Adaptor
+/// Robbins-Monro adaptation of the log proposal scale towards a target
+/// acceptance rate, without the mean and variance tracking of
+/// `GlobalAdaptor`, which integer steps make meaningless.
+#[derive(Clone, Debug)]
+pub struct DiscreteAdaptor<T>
+where
+    T: Clone
+{
+    log_scale: f64,
+    initial_scale: f64,
+    target_acceptance: f64,
+    step: usize,
+    enabled: bool,
+    non_finite_updates: usize,
+    phantom_t: PhantomData<T>
+}
+
+impl<T> DiscreteAdaptor<T>
+where
+    T: Clone
+{
+    pub fn new(scale: f64, target_acceptance: f64) -> Self {
+        assert!(
+            scale > 0.0 && scale.is_finite(),
+            "proposal scale must be finite and positive."
+        );
+        assert!(
+            target_acceptance > 0.0 && target_acceptance < 1.0,
+            "target acceptance must be between zero and one."
+        );
+        DiscreteAdaptor {
+            log_scale: scale.ln(),
+            initial_scale: scale,
+            target_acceptance,
+            step: 0,
+            enabled: false,
+            non_finite_updates: 0,
+            phantom_t: PhantomData
+        }
+    }
+
+    /// The acceptance rate being targeted
+    pub fn target_acceptance(&self) -> f64 {
+        self.target_acceptance
+    }
+}
+
+impl<T> ScaleAdaptor<T> for DiscreteAdaptor<T>
+where
+    T: 'static + Clone + Debug + Send + Sync
+{
+    fn update(&mut self, update: &MetroplisUpdate<T>) {
+        if !self.enabled {
+            return;
+        }
+        let log_alpha = update.log_alpha();
+        if log_alpha.is_nan() {
+            self.non_finite_updates += 1;
+            return;
+        }
+        let alpha = log_alpha.exp().min(1.0);
+        let g = 1.0 / ((self.step + 1) as f64).powf(0.6);
+        self.log_scale += g * (alpha - self.target_acceptance);
+        self.step += 1;
+    }
+
+    fn get_scale(&self) -> f64 {
+        self.log_scale.exp()
+    }
+
+    fn set_mode(&mut self, mode: AdaptationMode) {
+        match mode {
+            AdaptationMode::Enabled => self.enabled = true,
+            AdaptationMode::Disabled => self.enabled = false
+        }
+    }
+
+    fn get_mode(&self) -> AdaptationStatus {
+        match self.enabled {
+            true => AdaptationStatus::Enabled,
+            false => AdaptationStatus::Disabled
+        }
+    }
+
+    fn reset(&mut self) {
+        self.log_scale = self.initial_scale.ln();
+        self.step = 0;
+        self.non_finite_updates = 0;
+    }
+
+    fn non_finite_updates(&self) -> usize {
+        self.non_finite_updates
+    }
+
+    fn state(&self) -> AdaptorState {
+        AdaptorState::new(
+            vec![self.log_scale],
+            Vec::new(),
+            vec![self.step, self.non_finite_updates],
+        )
+    }
+
+    fn restore(&mut self, state: &AdaptorState) -> io::Result<()> {
+        state.expect(1, 0, 2)?;
+        self.log_scale = state.scales[0];
+        self.step = state.counts[0];
+        self.non_finite_updates = state.counts[1];
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use steppers::util::ProposalScores;
+
+    fn scores(log_alpha: f64) -> ProposalScores {
+        ProposalScores::new(0.0, log_alpha)
+    }
+
+    #[test]
+    fn scale_moves_towards_target_acceptance() {
+        let mut adaptor: DiscreteAdaptor<u32> =
+            DiscreteAdaptor::new(4.0, DISCRETE_TARGET_ACCEPTANCE);
+        adaptor.update(&MetroplisUpdate::Accepted(1, scores(0.0)));
+        assert!((adaptor.get_scale() - 4.0).abs() < 1E-12);
+
+        adaptor.set_mode(AdaptationMode::Enabled);
+        (0..100).for_each(|_| {
+            adaptor.update(&MetroplisUpdate::Accepted(1, scores(0.0)))
+        });
+        let grown = adaptor.get_scale();
+        assert!(grown > 4.0);
+
+        (0..100).for_each(|_| {
+            adaptor.update(&MetroplisUpdate::Rejected(1, scores(-10.0)))
+        });
+        assert!(adaptor.get_scale() < grown);
+
+        adaptor.update(&MetroplisUpdate::Rejected(1, scores(std::f64::NAN)));
+        assert_eq!(adaptor.non_finite_updates(), 1);
+        adaptor.reset();
+        assert!((adaptor.get_scale() - 4.0).abs() < 1E-12);
+    }
+}
@@ -0,0 +1,65 @@
+//! An adaptor which never changes its scale
+
+use std::io;
+use steppers::adaptor::{AdaptorState, ScaleAdaptor};
+use steppers::{AdaptationStatus, AdaptationMode};
+use steppers::util::MetroplisUpdate;
+use std::marker::PhantomData;
+use std::fmt::Debug;
+
+/// # Fixed Adaptor
+/// Keeps a constant proposal scale, e.g. to freeze a scale found during
+/// warmup.
+#[derive(Clone, Debug)]
+pub struct FixedAdaptor<T>
+where
+    T: Clone
+{
+    scale: f64,
+    phantom_t: PhantomData<T>
+}
+
+impl<T> FixedAdaptor<T>
+where
+    T: Clone
+{
+    pub fn new(scale: f64) -> Self {
+        FixedAdaptor {
+            scale,
+            phantom_t: PhantomData
+        }
+    }
+}
+
+impl<T> ScaleAdaptor<T> for FixedAdaptor<T>
+where
+    T: 'static + Clone + Debug + Send + Sync
+{
+    fn update(&mut self, _update: &MetroplisUpdate<T>) {}
+
+    fn get_scale(&self) -> f64 {
+        self.scale
+    }
+
+    fn set_mode(&mut self, _mode: AdaptationMode) {}
+
+    fn get_mode(&self) -> AdaptationStatus {
+        AdaptationStatus::Disabled
+    }
+
+    fn reset(&mut self) {}
+
+    fn non_finite_updates(&self) -> usize {
+        0
+    }
+
+    fn state(&self) -> AdaptorState {
+        AdaptorState::new(vec![self.scale], Vec::new(), Vec::new())
+    }
+
+    fn restore(&mut self, state: &AdaptorState) -> io::Result<()> {
+        state.expect(1, 0, 0)?;
+        self.scale = state.scales[0];
+        Ok(())
+    }
+}
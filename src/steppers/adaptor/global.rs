@@ -1,15 +1,20 @@
 //! An implementation of the Global Adaptor
 
-use steppers::adaptor::ScaleAdaptor;
+use std::io;
+use steppers::adaptor::{AdaptorState, ScaleAdaptor};
 use steppers::{AdaptationStatus, AdaptationMode};
 use steppers::util::MetroplisUpdate;
-use nalgebra::base::{Vector, Matrix, Scalar, Dim};
-use std::any::Any;
-use std::fmt::Debug;
+use nalgebra::{DMatrix, DVector};
 
+/// Default regularization `ε` of adapted covariances
+pub const DEFAULT_EPSILON: f64 = 1E-6;
 
 /// # Globally Adaptive MC Adaptor
 ///
+/// For `DVector<f64>` parameters the adaptor tracks the covariance *Σ* of
+/// the chain and proposals use *λ (Σ + ε I)*, as in Haario et al. (2001).
+/// The *ε I* floor keeps the covariance positive definite even when the
+/// chain has only explored a subspace, so a proposal can always be drawn.
 #[derive(Debug, Clone)]
 pub struct GlobalAdaptor<T, V>
 {
@@ -33,6 +38,10 @@ pub struct GlobalAdaptor<T, V>
     target_alpha: f64,
     // Enables updates or not.
     enabled: bool,
+    // Number of updates skipped for not being finite.
+    non_finite_updates: usize,
+    // Regularization *ε* added to the diagonal of adapted covariances
+    epsilon: f64,
 }
 
 impl<T, V> GlobalAdaptor<T, V>
@@ -50,13 +59,37 @@ where
             proposal_scale: initial_proposal_scale,
             target_alpha: 0.234,
             enabled: false,
+            non_finite_updates: 0,
             initial_proposal_scale,
             initial_mu: mean,
             initial_scale: scale,
+            epsilon: DEFAULT_EPSILON,
+        }
+    }
+
+    /// Add `epsilon` times the identity to the adapted covariance of
+    /// vector parameters. Zero disables the regularization.
+    pub fn epsilon(&self, epsilon: f64) -> Self {
+        assert!(
+            epsilon >= 0.0 && epsilon.is_finite(),
+            "epsilon must be finite and non-negative."
+        );
+        GlobalAdaptor {
+            epsilon,
+            ..(*self).clone()
         }
     }
 }
 
+impl GlobalAdaptor<DVector<f64>, DMatrix<f64>> {
+    /// Regularized proposal covariance *λ (Σ + ε I)*
+    pub fn covariance(&self) -> DMatrix<f64> {
+        let dim = self.scale.nrows();
+        (&self.scale + DMatrix::identity(dim, dim) * self.epsilon)
+            * self.proposal_scale
+    }
+}
+
 macro_rules! impl_adaptor_float {
     ($ttype: ty, $vtype: ty) => {
         impl ScaleAdaptor<$ttype> for GlobalAdaptor<$ttype, $vtype>
@@ -72,6 +105,30 @@ macro_rules! impl_adaptor_float {
                 self.scale = self.initial_scale.clone();
                 self.mu = self.initial_mu.clone();
                 self.enabled = false;
+                self.non_finite_updates = 0;
+            }
+
+            fn non_finite_updates(&self) -> usize {
+                self.non_finite_updates
+            }
+
+            fn state(&self) -> AdaptorState {
+                AdaptorState::new(
+                    vec![self.log_lambda, self.proposal_scale],
+                    vec![self.mu as f64, self.scale as f64],
+                    vec![self.step, self.non_finite_updates],
+                )
+            }
+
+            fn restore(&mut self, state: &AdaptorState) -> io::Result<()> {
+                state.expect(2, 2, 2)?;
+                self.log_lambda = state.scales[0];
+                self.proposal_scale = state.scales[1];
+                self.mu = state.moments[0] as $ttype;
+                self.scale = state.moments[1] as $vtype;
+                self.step = state.counts[0];
+                self.non_finite_updates = state.counts[1];
+                Ok(())
             }
         
             fn set_mode(&mut self, mode: AdaptationMode) {
@@ -90,10 +147,14 @@ macro_rules! impl_adaptor_float {
         
             fn update(&mut self, update: &MetroplisUpdate<$ttype>) {
                 if self.enabled {
-                    let (new_value, log_alpha) = match update {
-                        MetroplisUpdate::Accepted(x, y) => (x, y),
-                        MetroplisUpdate::Rejected(x, y) => (x, y),
-                    };
+                    let new_value = *update.value();
+                    let log_alpha = update.log_alpha();
+                    // A NaN acceptance ratio carries no information about
+                    // the scale, so skip it rather than corrupt the state.
+                    if log_alpha.is_nan() {
+                        self.non_finite_updates += 1;
+                        return;
+                    }
                     let alpha = log_alpha.exp();
                     let g = 0.9 / ((self.step + 1) as f64).powf(0.9);
                     let delta = new_value - self.mu;
@@ -103,21 +164,13 @@ macro_rules! impl_adaptor_float {
                     let new_sigma = self.scale + (g as $vtype) * (((delta * delta) as $vtype) - self.scale);
                     let new_proposal_scale = new_log_lambda.exp() * f64::from(new_sigma);
 
-                    assert!(
-                        new_proposal_scale > 0.0,
-                        format!(
-                            "update SrwmAdaptor = {:?}  \
-                                (new_lambda = {}, new_sigma = {})
-                                with alpha = {}, \
-                                new_value = {} \
-                                caused a bad proposal_scale...",
-                            self,
-                            new_log_lambda.exp(),
-                            new_sigma,
-                            alpha,
-                            new_value
-                        )
-                    );
+                    if !(new_proposal_scale.is_finite()
+                        && new_proposal_scale > 0.0
+                        && new_sigma.is_finite())
+                    {
+                        self.non_finite_updates += 1;
+                        return;
+                    }
 
                     self.log_lambda = new_log_lambda;
                     self.mu = new_mu;
@@ -135,13 +188,7 @@ impl_adaptor_float!(f64, f64);
 impl_adaptor_float!(u16, f64);
 impl_adaptor_float!(u32, f64);
 
-/*
-impl<N, D, S> ScaleAdaptor<Vector<N, D, S>> for GlobalAdaptor<Vector<N, D, S>, Matrix<N, D, D, S>>
-where
-    N: Scalar + Copy + PartialEq + Debug + Any,
-    D: Dim + Copy,
-    S: Copy
-{
+impl ScaleAdaptor<DVector<f64>> for GlobalAdaptor<DVector<f64>, DMatrix<f64>> {
     fn get_scale(&self) -> f64 {
         self.proposal_scale
     }
@@ -153,6 +200,33 @@ where
         self.scale = self.initial_scale.clone();
         self.mu = self.initial_mu.clone();
         self.enabled = false;
+        self.non_finite_updates = 0;
+    }
+
+    fn non_finite_updates(&self) -> usize {
+        self.non_finite_updates
+    }
+
+    fn state(&self) -> AdaptorState {
+        let moments = self.mu.iter().chain(self.scale.iter()).cloned();
+        AdaptorState::new(
+            vec![self.log_lambda, self.proposal_scale],
+            moments.collect(),
+            vec![self.step, self.non_finite_updates],
+        )
+    }
+
+    fn restore(&mut self, state: &AdaptorState) -> io::Result<()> {
+        let dim = self.mu.len();
+        state.expect(2, dim + dim * dim, 2)?;
+        self.log_lambda = state.scales[0];
+        self.proposal_scale = state.scales[1];
+        self.mu = DVector::from_column_slice(dim, &state.moments[..dim]);
+        self.scale =
+            DMatrix::from_column_slice(dim, dim, &state.moments[dim..]);
+        self.step = state.counts[0];
+        self.non_finite_updates = state.counts[1];
+        Ok(())
     }
 
     fn set_mode(&mut self, mode: AdaptationMode) {
@@ -169,9 +243,116 @@ where
         }
     }
 
-    fn update(&mut self, update: &MetroplisUpdate<Vector<N, D, S>>) {
+    fn update(&mut self, update: &MetroplisUpdate<DVector<f64>>) {
+        if !self.enabled {
+            return;
+        }
+        let log_alpha = update.log_alpha();
+        // A NaN acceptance ratio carries no information about the scale,
+        // so skip it rather than corrupt the state.
+        if log_alpha.is_nan() {
+            self.non_finite_updates += 1;
+            return;
+        }
+        let g = 0.9 / ((self.step + 1) as f64).powf(0.9);
+        let delta = update.value() - &self.mu;
+        let alpha = log_alpha.exp().min(1.0);
+        let new_log_lambda =
+            self.log_lambda + g * (alpha - self.target_alpha);
+        let new_scale = &self.scale
+            + (&delta * delta.transpose() - &self.scale) * g;
+        let new_proposal_scale =
+            self.initial_proposal_scale * new_log_lambda.exp();
+
+        if !(new_proposal_scale.is_finite()
+            && new_proposal_scale > 0.0
+            && new_scale.iter().all(|x| x.is_finite()))
+        {
+            self.non_finite_updates += 1;
+            return;
+        }
 
+        self.log_lambda = new_log_lambda;
+        self.mu += delta * g;
+        self.scale = new_scale;
+        self.step += 1;
+        self.proposal_scale = new_proposal_scale;
     }
 }
-*/
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use steppers::util::ProposalScores;
+
+    #[test]
+    fn nan_updates_are_skipped_and_counted() {
+        let mut adaptor = GlobalAdaptor::new(1.0, 0.0_f64, 1.0_f64);
+        adaptor.set_mode(AdaptationMode::Enabled);
+
+        let nan = ProposalScores::new(0.0, std::f64::NAN);
+        adaptor.update(&MetroplisUpdate::Rejected(0.5, nan));
+        assert_eq!(adaptor.non_finite_updates(), 1);
+        assert_eq!(adaptor.get_scale(), 1.0);
+
+        let scores = ProposalScores::new(0.0, -0.1);
+        adaptor.update(&MetroplisUpdate::Accepted(0.5, scores));
+        assert_eq!(adaptor.non_finite_updates(), 1);
+        assert!(adaptor.get_scale() != 1.0);
+
+        adaptor.reset();
+        assert_eq!(adaptor.non_finite_updates(), 0);
+    }
+
+    #[test]
+    fn regularized_covariance_stays_positive_definite() {
+        // A chain moving only along the diagonal has a singular covariance.
+        let mut adaptor =
+            GlobalAdaptor::new(1.0, DVector::zeros(2), DMatrix::zeros(2, 2))
+                .epsilon(1E-4);
+        adaptor.set_mode(AdaptationMode::Enabled);
+        for i in 0..100 {
+            let x = if i % 2 == 0 { 1.0 } else { -1.0 };
+            let value = DVector::from_element(2, x);
+            let scores = ProposalScores::new(0.0, 0.234f64.ln());
+            adaptor.update(&MetroplisUpdate::Accepted(value, scores));
+        }
+        assert_eq!(adaptor.non_finite_updates(), 0);
+        assert!(adaptor.scale.clone().cholesky().is_none());
+        assert!(adaptor.covariance().cholesky().is_some());
+
+        let unregularized = adaptor.epsilon(0.0);
+        assert!(unregularized.covariance().cholesky().is_none());
+
+        adaptor.reset();
+        let identity = DMatrix::<f64>::identity(2, 2);
+        assert_eq!(adaptor.covariance(), identity * 1E-4);
+    }
+
+    #[test]
+    fn restored_state_continues_the_adaptation() {
+        let fresh = |dim: usize| {
+            GlobalAdaptor::new(
+                1.0,
+                DVector::zeros(dim),
+                DMatrix::identity(dim, dim),
+            )
+        };
+        let mut adaptor = fresh(2);
+        adaptor.set_mode(AdaptationMode::Enabled);
+        for i in 0..10 {
+            let value = DVector::from_column_slice(2, &[i as f64, -1.0]);
+            let scores = ProposalScores::new(0.0, -0.5);
+            adaptor.update(&MetroplisUpdate::Accepted(value, scores));
+        }
+
+        let mut restored = fresh(2);
+        restored.restore(&adaptor.state()).unwrap();
+        assert_eq!(restored.covariance(), adaptor.covariance());
+        assert_eq!(restored.get_scale(), adaptor.get_scale());
+        assert_eq!(restored.state(), adaptor.state());
+
+        assert!(fresh(1).restore(&adaptor.state()).is_err());
+    }
+}
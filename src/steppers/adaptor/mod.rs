@@ -1,7 +1,99 @@
+use std::fmt;
+use std::io;
 use steppers::util::MetroplisUpdate;
 use steppers::{AdaptationStatus, AdaptationMode};
+use utils::invalid_data;
 
-pub trait ScaleAdaptor<T>
+/// What an adaptive stepper has learned, as plain data
+///
+/// Steppers hold their likelihoods as closures and so cannot be saved, but
+/// their adaptation can, and restored onto a stepper built the same way.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct AdaptorState {
+    /// Name of what is adapted, e.g. the parameter a scale is tuned for
+    pub name: String,
+    /// Proposal scales, e.g. a log scale factor
+    pub scales: Vec<f64>,
+    /// Running moments of the chain, e.g. a mean and covariance
+    pub moments: Vec<f64>,
+    /// Step counts the gains of the adaptation depend on
+    pub counts: Vec<usize>,
+    /// States of the chain kept to adapt from
+    pub history: Vec<Vec<f64>>,
+}
+
+impl AdaptorState {
+    /// State without a name, e.g. from an adaptor whose stepper names it
+    pub fn new(
+        scales: Vec<f64>,
+        moments: Vec<f64>,
+        counts: Vec<usize>,
+    ) -> Self {
+        AdaptorState {
+            name: String::new(),
+            scales,
+            moments,
+            counts,
+            history: Vec::new(),
+        }
+    }
+
+    /// Fail with `InvalidData` unless the state has the given number of
+    /// scales, moments and counts.
+    pub fn expect(
+        &self,
+        scales: usize,
+        moments: usize,
+        counts: usize,
+    ) -> io::Result<()> {
+        if self.scales.len() == scales
+            && self.moments.len() == moments
+            && self.counts.len() == counts
+        {
+            Ok(())
+        } else {
+            Err(invalid_data(format!(
+                "adaptor state of {:?} has {} scales, {} moments and {} \
+                 counts where {}, {} and {} were expected",
+                self.name,
+                self.scales.len(),
+                self.moments.len(),
+                self.counts.len(),
+                scales,
+                moments,
+                counts
+            )))
+        }
+    }
+}
+
+/// The next of `states`, which must be named `name`
+///
+/// Fails with `InvalidData` if there is none or it belongs to something
+/// else, e.g. because the states were taken from a different stepper.
+pub fn next_state(
+    states: &mut dyn Iterator<Item = AdaptorState>,
+    name: &str,
+) -> io::Result<AdaptorState> {
+    match states.next() {
+        Some(ref state) if state.name == name => Ok(state.clone()),
+        Some(state) => Err(invalid_data(format!(
+            "expected the adaptor state of {:?} but found that of {:?}",
+            name, state.name
+        ))),
+        None => Err(invalid_data(format!(
+            "no adaptor state left for {:?}",
+            name
+        ))),
+    }
+}
+
+/// Adapts the scale of a stepper's proposals
+///
+/// The trait is object safe so steppers can hold a
+/// `Box<dyn ScaleAdaptor<T>>` and swap adaptors at runtime.
+pub trait ScaleAdaptor<T>: ScaleAdaptorClone<T> + fmt::Debug + Send + Sync
 where
     T: Clone
 {
@@ -10,10 +102,47 @@ where
     fn set_mode(&mut self, mode: AdaptationMode);
     fn get_mode(&self) -> AdaptationStatus;
     fn reset(&mut self);
+    /// Number of updates skipped because they were not finite
+    fn non_finite_updates(&self) -> usize;
+    /// Scales, running moments and step counts, e.g. to save a chain
+    fn state(&self) -> AdaptorState;
+    /// Continue from a state taken by `state` of an adaptor of the same
+    /// kind and dimension, failing with `InvalidData` otherwise.
+    fn restore(&mut self, state: &AdaptorState) -> io::Result<()>;
+}
+
+/// Cloning of boxed adaptors, implemented for every `Clone` adaptor
+pub trait ScaleAdaptorClone<T> {
+    fn clone_box(&self) -> Box<dyn ScaleAdaptor<T>>;
+}
+
+impl<T, A> ScaleAdaptorClone<T> for A
+where
+    T: Clone,
+    A: 'static + ScaleAdaptor<T> + Clone,
+{
+    fn clone_box(&self) -> Box<dyn ScaleAdaptor<T>> {
+        Box::new(self.clone())
+    }
+}
+
+impl<T> Clone for Box<dyn ScaleAdaptor<T>>
+where
+    T: Clone
+{
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
 }
 
+mod diagonal;
+mod discrete;
+mod fixed;
 mod global;
 mod simple;
 
+pub use self::diagonal::*;
+pub use self::discrete::*;
+pub use self::fixed::*;
 pub use self::simple::*;
 pub use self::global::*;
@@ -1,9 +1,11 @@
 //! An implementation of the Simple Scaling Adaptor
 
-use steppers::adaptor::ScaleAdaptor;
+use std::io;
+use steppers::adaptor::{AdaptorState, ScaleAdaptor};
 use steppers::{AdaptationStatus, AdaptationMode};
 use steppers::util::MetroplisUpdate;
 use std::marker::PhantomData;
+use std::fmt::Debug;
 
 /// # Simple Adaptor
 /// A simple scale adaptor derived from
@@ -19,6 +21,7 @@ where
     scale: f64,
     initial_scale: f64,
     enabled: bool,
+    non_finite_updates: usize,
     phantom_t: PhantomData<T>
 }
 
@@ -35,6 +38,7 @@ where
             scale,
             initial_scale: scale,
             enabled: false,
+            non_finite_updates: 0,
             phantom_t: PhantomData
         }
     }
@@ -42,12 +46,34 @@ where
 
 impl<T> ScaleAdaptor<T> for SimpleAdaptor<T>
 where
-    T: Clone
+    T: 'static + Clone + Debug + Send + Sync
 {
     fn reset(&mut self) {
         self.alpha_sum = 0.0;
         self.n_updates = 0;
         self.scale = self.initial_scale;
+        self.non_finite_updates = 0;
+    }
+
+    fn non_finite_updates(&self) -> usize {
+        self.non_finite_updates
+    }
+
+    fn state(&self) -> AdaptorState {
+        AdaptorState::new(
+            vec![self.scale],
+            vec![self.alpha_sum],
+            vec![self.n_updates, self.non_finite_updates],
+        )
+    }
+
+    fn restore(&mut self, state: &AdaptorState) -> io::Result<()> {
+        state.expect(1, 1, 2)?;
+        self.scale = state.scales[0];
+        self.alpha_sum = state.moments[0];
+        self.n_updates = state.counts[0];
+        self.non_finite_updates = state.counts[1];
+        Ok(())
     }
 
     fn get_scale(&self) -> f64 {
@@ -69,11 +95,12 @@ where
     }
 
     fn update(&mut self, update: &MetroplisUpdate<T>) {
-        let alpha = match update {
-            MetroplisUpdate::Accepted(_, a) => a,
-            MetroplisUpdate::Rejected(_, a) => a,
-        };
-        self.alpha_sum += *alpha;
+        let alpha = update.log_alpha();
+        if alpha.is_nan() {
+            self.non_finite_updates += 1;
+            return;
+        }
+        self.alpha_sum += alpha;
 
         if self.n_updates >= self.adapt_interval {
             let alpha_mean: f64 = self.alpha_sum / (self.n_updates as f64);
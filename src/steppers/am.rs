@@ -0,0 +1,768 @@
+//! # Adaptive Metropolis
+//! Random walk Metropolis over a vector parameter with a proposal
+//! covariance learned from the chain, as in Haario, Saksman and Tamminen
+//! (2001).
+
+use std::fmt;
+use std::io;
+use rand::Rng;
+use rand::distributions::StandardNormal;
+
+use nalgebra::{DMatrix, DVector};
+use rv::traits::Rv;
+
+use parameter::{Parameter, ParamId};
+use likelihood::DeltaLogLikelihood;
+use steppers::{SteppingAlg, AdaptationStatus, AdaptationMode, util};
+use steppers::adaptor::{next_state, AdaptorState, DEFAULT_EPSILON};
+use steppers::tempering::Tempering;
+use statistics::{Statistic, StatisticValue};
+use events::EventSink;
+use utils::invalid_input;
+
+/// Steps adapting before the chain's covariance replaces the initial one
+pub const DEFAULT_BURN_IN: usize = 100;
+
+/// Configuration of an `AdaptiveMetropolis` stepper
+///
+/// # Example
+/// ```
+/// # #[macro_use] extern crate rmcmc;
+/// # extern crate nalgebra;
+/// # extern crate rand;
+/// # extern crate rv;
+/// # use rmcmc::lens::*;
+/// # use rmcmc::parameter::Parameter;
+/// # use rmcmc::runner::Runner;
+/// # use rmcmc::steppers::AMBuilder;
+/// # use nalgebra::{DMatrix, DVector};
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # use rv::dist::MvGaussian;
+/// # fn main() {
+/// #[derive(Clone, Debug)]
+/// struct Model {
+///     x: DVector<f64>,
+/// }
+///
+/// let cov = DMatrix::from_row_slice(2, 2, &[1.0, 0.9, 0.9, 1.0]);
+/// let x = Parameter::new(
+///     "x".to_string(),
+///     MvGaussian::new(DVector::zeros(2), cov).unwrap(),
+///     make_lens_clone!(Model, DVector<f64>, x),
+/// );
+/// let am = AMBuilder::new(x, |_: &Model| 0.0, DMatrix::identity(2, 2))
+///     .burn_in(200)
+///     .build()
+///     .unwrap();
+///
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let init = Model { x: DVector::zeros(2) };
+/// let draws = Runner::new(am).warmup(2000).samples(100).run(&mut rng, init);
+/// assert_eq!(draws[0].len(), 100);
+/// # }
+/// ```
+pub struct AMBuilder<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    parameter: Parameter<D, DVector<f64>, M>,
+    log_likelihood: L,
+    initial_covariance: DMatrix<f64>,
+    scaling: Option<f64>,
+    epsilon: f64,
+    burn_in: usize,
+    update_every: usize,
+}
+
+impl<D, M, L> Clone for AMBuilder<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        AMBuilder {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            initial_covariance: self.initial_covariance.clone(),
+            scaling: self.scaling,
+            epsilon: self.epsilon,
+            burn_in: self.burn_in,
+            update_every: self.update_every,
+        }
+    }
+}
+
+impl<D, M, L> AMBuilder<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    /// Adaptive Metropolis for `parameter`, proposing with
+    /// `initial_covariance` until the chain has adapted for `burn_in` steps
+    pub fn new(
+        parameter: Parameter<D, DVector<f64>, M>,
+        log_likelihood: L,
+        initial_covariance: DMatrix<f64>,
+    ) -> Self {
+        AMBuilder {
+            parameter,
+            log_likelihood,
+            initial_covariance,
+            scaling: None,
+            epsilon: DEFAULT_EPSILON,
+            burn_in: DEFAULT_BURN_IN,
+            update_every: 1,
+        }
+    }
+
+    /// Scale the chain's covariance by `scaling` in proposals instead of
+    /// the default *2.38² / d*, optimal for Gaussian targets.
+    pub fn scaling(&self, scaling: f64) -> Self {
+        AMBuilder {
+            scaling: Some(scaling),
+            ..(*self).clone()
+        }
+    }
+
+    /// Add `epsilon` times the identity to the chain's covariance, keeping
+    /// proposals non-degenerate when the chain has only explored a
+    /// subspace. Zero disables the regularization.
+    pub fn epsilon(&self, epsilon: f64) -> Self {
+        AMBuilder {
+            epsilon,
+            ..(*self).clone()
+        }
+    }
+
+    /// Propose with the initial covariance for the first `burn_in` steps
+    /// of adaptation, while the chain's covariance is still noisy.
+    pub fn burn_in(&self, burn_in: usize) -> Self {
+        AMBuilder {
+            burn_in,
+            ..(*self).clone()
+        }
+    }
+
+    /// Refactor the proposal covariance only every `update_every` adaptive
+    /// steps past burn-in, by default every step. The running moments
+    /// still take in every step, but the O(d³) Cholesky factorization is
+    /// paid once per `update_every` steps.
+    pub fn update_every(&self, update_every: usize) -> Self {
+        AMBuilder {
+            update_every,
+            ..(*self).clone()
+        }
+    }
+
+    /// The stepper, or an `InvalidInput` error if the initial covariance
+    /// is not square and positive definite, the scaling is not finite and
+    /// positive, `epsilon` is negative or not finite, `burn_in` is less
+    /// than two steps or `update_every` is zero.
+    pub fn build(&self) -> io::Result<AdaptiveMetropolis<D, M, L>> {
+        let id = self.parameter.id();
+        let dim = self.initial_covariance.nrows();
+        if dim == 0 || self.initial_covariance.ncols() != dim {
+            return Err(invalid_input(format!(
+                "AdaptiveMetropolis for {}: the initial covariance must be \
+                 a non-empty square matrix.",
+                id
+            )));
+        }
+        let chol = match self.initial_covariance.clone().cholesky() {
+            Some(chol) => chol.unpack(),
+            None => {
+                return Err(invalid_input(format!(
+                    "AdaptiveMetropolis for {}: the initial covariance is \
+                     not positive definite.",
+                    id
+                )))
+            }
+        };
+        let scaling = self.scaling.unwrap_or(2.38 * 2.38 / dim as f64);
+        if !(scaling > 0.0 && scaling.is_finite()) {
+            return Err(invalid_input(format!(
+                "AdaptiveMetropolis for {}: scaling must be finite and \
+                 positive.",
+                id
+            )));
+        }
+        if !(self.epsilon >= 0.0 && self.epsilon.is_finite()) {
+            return Err(invalid_input(format!(
+                "AdaptiveMetropolis for {}: epsilon must be finite and \
+                 non-negative.",
+                id
+            )));
+        }
+        if self.burn_in < 2 {
+            return Err(invalid_input(format!(
+                "AdaptiveMetropolis for {}: burn_in must be at least 2 \
+                 steps to estimate a covariance.",
+                id
+            )));
+        }
+        if self.update_every == 0 {
+            return Err(invalid_input(format!(
+                "AdaptiveMetropolis for {}: update_every must be at least \
+                 1 step.",
+                id
+            )));
+        }
+        Ok(AdaptiveMetropolis {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            mh: util::MHCore::new(),
+            temperature: 1.0,
+            initial_chol: chol.clone(),
+            scaling,
+            epsilon: self.epsilon,
+            burn_in: self.burn_in,
+            update_every: self.update_every,
+            mean: DVector::zeros(dim),
+            squares: DMatrix::zeros(dim, dim),
+            n: 0,
+            chol,
+            adapting: false,
+            singular_updates: 0,
+            fixed: false,
+            prior_cache: None,
+            events: None,
+        })
+    }
+}
+
+/// Adaptive Metropolis over a `DVector<f64>` parameter
+///
+/// While adaptation is enabled the stepper tracks the mean and covariance
+/// *Σ* of the chain's states. After `burn_in` adaptive steps proposals are
+/// Gaussian with covariance *s (Σ + ε I)*, where *s* is *2.38² / d* unless
+/// set with `AMBuilder::scaling`; before then they use the initial
+/// covariance. The covariance is frozen when adaptation is disabled, so
+/// draws after warmup come from a fixed random walk kernel.
+///
+/// Unlike an `SRWM` with a `GlobalAdaptor`, which tunes a single scale
+/// towards a target acceptance rate, the proposals follow the chain's
+/// empirical covariance, at a cost of O(d³) for its Cholesky factor each
+/// time it is refreshed while adapting: every step, or every
+/// `AMBuilder::update_every` steps.
+///
+/// Each refresh factors the covariance from scratch; the factor is not
+/// updated with rank-one modifications between refreshes. Nor is the
+/// covariance projected onto the nearest positive definite matrix: the
+/// *ε I* term keeps it positive definite, and a refresh which still fails
+/// to factor keeps the previous factor and is counted by
+/// `singular_updates`.
+pub struct AdaptiveMetropolis<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    pub parameter: Parameter<D, DVector<f64>, M>,
+    pub log_likelihood: L,
+    pub mh: util::MHCore,
+    /// Temperature of the tempered target; see `Tempering`
+    pub temperature: f64,
+    pub fixed: bool,
+    initial_chol: DMatrix<f64>,
+    scaling: f64,
+    epsilon: f64,
+    burn_in: usize,
+    update_every: usize,
+    // Running mean and sum of squared deviations of the adaptive steps
+    mean: DVector<f64>,
+    squares: DMatrix<f64>,
+    n: usize,
+    // Lower Cholesky factor of the proposal covariance
+    chol: DMatrix<f64>,
+    adapting: bool,
+    singular_updates: usize,
+    prior_cache: Option<util::PriorCache>,
+    events: Option<EventSink>,
+}
+
+impl<D, M, L> AdaptiveMetropolis<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    /// Covariance of the chain's states while adapting, once it has at
+    /// least two
+    pub fn empirical_covariance(&self) -> Option<DMatrix<f64>> {
+        if self.n < 2 {
+            None
+        } else {
+            Some(&self.squares / (self.n - 1) as f64)
+        }
+    }
+
+    /// Covariance of the current proposals
+    pub fn proposal_covariance(&self) -> DMatrix<f64> {
+        &self.chol * self.chol.transpose()
+    }
+
+    /// Adaptive steps whose regularized covariance had no Cholesky factor,
+    /// leaving the previous proposal covariance in place
+    pub fn singular_updates(&self) -> usize {
+        self.singular_updates
+    }
+
+    /// Add the chain's state `x` to the running moments and refresh the
+    /// proposal covariance past burn-in.
+    fn adapt(&mut self, x: &DVector<f64>) {
+        if !x.iter().all(|v| v.is_finite()) {
+            return;
+        }
+        self.n += 1;
+        let delta = x - &self.mean;
+        self.mean += &delta / self.n as f64;
+        self.squares += &delta * (x - &self.mean).transpose();
+        if self.n < self.burn_in
+            || (self.n - self.burn_in) % self.update_every != 0
+        {
+            return;
+        }
+        let dim = self.mean.len();
+        let covariance = self.empirical_covariance().unwrap()
+            + DMatrix::identity(dim, dim) * self.epsilon;
+        match (covariance * self.scaling).cholesky() {
+            Some(chol) => self.chol = chol.unpack(),
+            None => self.singular_updates += 1,
+        }
+    }
+}
+
+impl<D, M, L> Clone for AdaptiveMetropolis<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        AdaptiveMetropolis {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            mh: self.mh,
+            temperature: self.temperature,
+            fixed: self.fixed,
+            initial_chol: self.initial_chol.clone(),
+            scaling: self.scaling,
+            epsilon: self.epsilon,
+            burn_in: self.burn_in,
+            update_every: self.update_every,
+            mean: self.mean.clone(),
+            squares: self.squares.clone(),
+            n: self.n,
+            chol: self.chol.clone(),
+            adapting: self.adapting,
+            singular_updates: self.singular_updates,
+            prior_cache: self.prior_cache.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<D, M, L> Tempering for AdaptiveMetropolis<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn set_temperature(&mut self, temperature: f64) {
+        assert!(
+            temperature > 0.0 && temperature.is_finite(),
+            "temperature must be finite and positive."
+        );
+        self.temperature = temperature;
+        // The cached score is of the previous target.
+        self.mh.current_score = None;
+    }
+
+    fn temperature(&self) -> f64 {
+        self.temperature
+    }
+}
+
+impl<D, M, L> fmt::Debug for AdaptiveMetropolis<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AdaptiveMetropolis {{ parameter: {:?}, current_score: {:?}, \
+             scaling: {}, epsilon: {}, burn_in: {} }}",
+            self.parameter,
+            self.mh.current_score,
+            self.scaling,
+            self.epsilon,
+            self.burn_in
+        )
+    }
+}
+
+impl<D, M, L, R> SteppingAlg<M, R> for AdaptiveMetropolis<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+    R: Rng,
+{
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        self.adapting = match mode {
+            AdaptationMode::Enabled => true,
+            AdaptationMode::Disabled => false,
+        };
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        if self.adapting {
+            AdaptationStatus::Enabled
+        } else {
+            AdaptationStatus::Disabled
+        }
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        let singular = self.singular_updates;
+        self.mh
+            .acceptance
+            .rate()
+            .map(StatisticValue::AcceptanceRate)
+            .into_iter()
+            .chain(
+                Some(StatisticValue::NonFiniteUpdates(singular))
+                    .filter(|_| singular > 0),
+            )
+            .map(|value| Statistic::new(self.parameter.id().clone(), value))
+            .collect()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        vec![self.parameter.id().clone()]
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        vec![(self.parameter.id().clone(), self.parameter.dependencies.clone())]
+    }
+
+    fn reset(&mut self) {
+        self.mh.reset();
+        let dim = self.mean.len();
+        self.mean = DVector::zeros(dim);
+        self.squares = DMatrix::zeros(dim, dim);
+        self.n = 0;
+        self.chol = self.initial_chol.clone();
+        self.singular_updates = 0;
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.mh.invalidate();
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.fixed |= parameter == self.parameter.id();
+    }
+
+    fn set_prior_cache(&mut self, cache: util::PriorCache) {
+        self.prior_cache = Some(cache);
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        self.parameter.draw(&model, rng)
+    }
+
+    // The Cholesky factor of the proposal covariance is the scale, and
+    // the running mean and sum of squares are the moments.
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        let moments = self.mean.iter().chain(self.squares.iter()).cloned();
+        vec![AdaptorState {
+            name: self.parameter.name().to_string(),
+            ..AdaptorState::new(
+                self.chol.iter().cloned().collect(),
+                moments.collect(),
+                vec![self.n, self.singular_updates],
+            )
+        }]
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        let state = next_state(states, self.parameter.name())?;
+        let dim = self.mean.len();
+        state.expect(dim * dim, dim + dim * dim, 2)?;
+        self.chol = DMatrix::from_column_slice(dim, dim, &state.scales);
+        self.mean = DVector::from_column_slice(dim, &state.moments[..dim]);
+        self.squares =
+            DMatrix::from_column_slice(dim, dim, &state.moments[dim..]);
+        self.n = state.counts[0];
+        self.singular_updates = state.counts[1];
+        Ok(())
+    }
+
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        let current_value = match self.parameter.lens.try_get(&model) {
+            Ok(value) => value,
+            Err(err) => {
+                let id = self.parameter.id();
+                let events = &self.events;
+                return util::reject_lens_failure(events, id, &err, model);
+            }
+        };
+        let dim = self.mean.len();
+        if current_value.len() != dim {
+            panic!(
+                "AdaptiveMetropolis for {}: the parameter has length {} but \
+                 the proposal covariance is {} by {}.",
+                self.parameter.id(),
+                current_value.len(),
+                dim,
+                dim
+            );
+        }
+        let current_prior = match self.mh.current_prior {
+            Some(prior) => prior,
+            None => match self.prior_cache {
+                Some(ref cache) => cache
+                    .get_or_insert_with(self.parameter.id(), || {
+                        self.parameter.prior.ln_f(&current_value)
+                    }),
+                None => self.parameter.prior.ln_f(&current_value),
+            },
+        };
+        let log_likelihood = util::ScaledLogLikelihood::new(
+            &self.log_likelihood,
+            self.temperature.recip(),
+        );
+        let current_score = self.mh.current_score(|| {
+            log_likelihood.ln_f(&model) + current_prior
+        });
+
+        let z: DVector<f64> =
+            DVector::from_fn(dim, |_, _| rng.sample(StandardNormal));
+        let proposed_value = &current_value + &self.chol * z;
+        let new_model =
+            self.parameter.lens.try_set(&model, proposed_value.clone());
+        let new_model = match new_model {
+            Ok(new_model) => new_model,
+            Err(err) => {
+                let id = self.parameter.id();
+                let events = &self.events;
+                return util::reject_lens_failure(events, id, &err, model);
+            }
+        };
+        let prior_score = self.parameter.prior.ln_f(&proposed_value);
+        let new_score = util::proposal_score(
+            &log_likelihood,
+            self.parameter.id(),
+            &model,
+            current_score,
+            || current_prior,
+            &new_model,
+            prior_score,
+        );
+
+        let scores = util::ProposalScores::new(current_score, new_score);
+        let update = util::metropolis_select(
+            rng,
+            scores,
+            proposed_value,
+            current_value,
+        );
+        self.mh.record_with_prior(&update, prior_score);
+        if self.adapting {
+            self.adapt(update.value());
+        }
+        if let Some(ref events) = self.events {
+            let id = self.parameter.id();
+            events.emit_update(id, &update);
+            events.emit_scores(id, &update, || current_prior, prior_score);
+        }
+        match update {
+            util::MetroplisUpdate::Accepted(_, _) => {
+                if let Some(ref cache) = self.prior_cache {
+                    cache.insert(self.parameter.id().clone(), prior_score);
+                }
+                new_model
+            }
+            util::MetroplisUpdate::Rejected(_, _) => model,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use runner::Runner;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::MvGaussian;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Clone, Debug)]
+    struct Model {
+        x: DVector<f64>,
+    }
+
+    fn correlated() -> Parameter<MvGaussian, DVector<f64>, Model> {
+        let cov = DMatrix::from_row_slice(
+            3,
+            3,
+            &[4.0, 1.8, 0.0, 1.8, 1.0, 0.0, 0.0, 0.0, 0.25],
+        );
+        Parameter::new(
+            "x".to_string(),
+            MvGaussian::new(DVector::zeros(3), cov).unwrap(),
+            make_lens_clone!(Model, DVector<f64>, x),
+        )
+    }
+
+    #[test]
+    fn proposals_learn_the_target_covariance() {
+        let mut rng = StdRng::from_seed(SEED);
+        let mut am = AMBuilder::new(
+            correlated(),
+            |_: &Model| 0.0,
+            DMatrix::identity(3, 3) * 0.01,
+        )
+        .build()
+        .unwrap();
+
+        SteppingAlg::<Model, StdRng>::set_adapt(
+            &mut am,
+            AdaptationMode::Enabled,
+        );
+        let mut m = Model { x: DVector::zeros(3) };
+        for _ in 0..20000 {
+            m = am.step(&mut rng, m);
+        }
+        let empirical = am.empirical_covariance().unwrap();
+        assert!((empirical[(0, 0)] - 4.0).abs() < 0.8);
+        assert!((empirical[(0, 1)] - 1.8).abs() < 0.4);
+        assert!((empirical[(2, 2)] - 0.25).abs() < 0.05);
+
+        let scaled = (empirical + DMatrix::identity(3, 3) * DEFAULT_EPSILON)
+            * (2.38 * 2.38 / 3.0);
+        assert!((am.proposal_covariance() - scaled).amax() < 1E-9);
+        let rate = am.mh.acceptance.rate().unwrap();
+        assert!(rate > 0.15 && rate < 0.5);
+
+        // Frozen once adaptation is disabled
+        SteppingAlg::<Model, StdRng>::set_adapt(
+            &mut am,
+            AdaptationMode::Disabled,
+        );
+        let frozen = am.proposal_covariance();
+        for _ in 0..100 {
+            m = am.step(&mut rng, m);
+        }
+        assert_eq!(am.proposal_covariance(), frozen);
+
+        SteppingAlg::<Model, StdRng>::reset(&mut am);
+        assert_eq!(am.empirical_covariance(), None);
+        let initial = DMatrix::identity(3, 3) * 0.01;
+        assert!((am.proposal_covariance() - initial).amax() < 1E-12);
+    }
+
+    #[test]
+    fn proposals_are_refreshed_every_few_steps() {
+        let mut rng = StdRng::from_seed(SEED);
+        let initial = DMatrix::identity(3, 3) * 0.01;
+        let mut am = AMBuilder::new(correlated(), |_: &Model| 0.0, initial)
+            .burn_in(10)
+            .update_every(25)
+            .build()
+            .unwrap();
+
+        SteppingAlg::<Model, StdRng>::set_adapt(
+            &mut am,
+            AdaptationMode::Enabled,
+        );
+        let mut m = Model { x: DVector::zeros(3) };
+        let mut refreshes = 0;
+        for _ in 0..20000 {
+            let before = am.proposal_covariance();
+            m = am.step(&mut rng, m);
+            if am.proposal_covariance() != before {
+                refreshes += 1;
+                assert_eq!((am.n - 10) % 25, 0);
+            }
+        }
+        assert!(refreshes > 700);
+
+        // The last refresh is at most 24 steps behind the moments.
+        let empirical = am.empirical_covariance().unwrap();
+        assert!((empirical[(0, 0)] - 4.0).abs() < 0.8);
+        assert!((empirical[(0, 1)] - 1.8).abs() < 0.4);
+        let scaled = (empirical + DMatrix::identity(3, 3) * DEFAULT_EPSILON)
+            * (2.38 * 2.38 / 3.0);
+        assert!((am.proposal_covariance() - scaled).amax() < 0.05);
+    }
+
+    #[test]
+    fn samples_a_correlated_gaussian() {
+        let mut rng = StdRng::from_seed(SEED);
+        let identity = DMatrix::identity(3, 3);
+        let am = AMBuilder::new(correlated(), |_: &Model| 0.0, identity)
+            .build()
+            .unwrap();
+        let draws = Runner::new(am)
+            .warmup(5000)
+            .samples(20000)
+            .run(&mut rng, Model { x: DVector::zeros(3) });
+        let n = draws[0].len() as f64;
+        let mean: f64 = draws[0].iter().map(|m| m.x[0]).sum::<f64>() / n;
+        let var: f64 = draws[0]
+            .iter()
+            .map(|m| (m.x[0] - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        assert!(mean.abs() < 0.3);
+        assert!((var - 4.0).abs() < 0.8);
+    }
+
+    #[test]
+    fn invalid_configurations_are_rejected() {
+        let identity = DMatrix::identity(3, 3);
+        let builder = AMBuilder::new(correlated(), |_: &Model| 0.0, identity);
+        assert!(builder.build().is_ok());
+        let not_square = AMBuilder::new(
+            correlated(),
+            |_: &Model| 0.0,
+            DMatrix::identity(3, 2),
+        );
+        assert!(not_square.build().is_err());
+        let indefinite = AMBuilder::new(
+            correlated(),
+            |_: &Model| 0.0,
+            -DMatrix::identity(3, 3),
+        );
+        assert!(indefinite.build().is_err());
+        assert!(builder.scaling(0.0).build().is_err());
+        assert!(builder.epsilon(-1.0).build().is_err());
+        assert!(builder.burn_in(1).build().is_err());
+        assert!(builder.update_every(0).build().is_err());
+        assert_eq!(builder.build().unwrap().burn_in, DEFAULT_BURN_IN);
+    }
+}
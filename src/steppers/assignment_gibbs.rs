@@ -0,0 +1,284 @@
+//! # Assignment Gibbs
+//! Draws each observation's component of a finite mixture from its exact
+//! conditional given the mixture's weights and components.
+
+use std::fmt;
+use rand::Rng;
+
+use rv::dist::Mixture;
+use rv::misc::{ln_pflip, pflip};
+use rv::traits::Rv;
+use lens::Lens;
+use parameter::ParamId;
+use utils::likelihood::MixtureLikelihood;
+
+use steppers::{SteppingAlg, AdaptationStatus, AdaptationMode};
+use steppers::util::PriorCache;
+use statistics::Statistic;
+use events::{Event, EventSink};
+
+/// Gibbs stepper for the per-observation component assignments of a
+/// mixture, the sampled alternative to summing them out with
+/// `MixtureLikelihood::marginal_log_likelihood`
+///
+/// Each step every assignment is drawn in turn from the probabilities of
+/// the components having produced its observation. The weights and
+/// components are then updated by other steppers using
+/// `MixtureLikelihood::complete_log_likelihood`.
+///
+/// # Parameters
+/// `X`: The type of a datum
+/// `D`: The type of a component
+/// `M`: The model type
+pub struct AssignmentGibbs<X, D, M> {
+    pub name: String,
+    pub likelihood: MixtureLikelihood<X>,
+    pub assignments: Lens<Vec<usize>, M>,
+    pub mixture: fn(&M) -> Mixture<D>,
+    /// Parameters of the mixture, i.e. of the assignments' prior
+    pub dependencies: Vec<ParamId>,
+    pub fixed: bool,
+    prior_cache: Option<PriorCache>,
+    events: Option<EventSink>,
+}
+
+impl<X, D, M> AssignmentGibbs<X, D, M>
+where
+    D: Rv<X>,
+{
+    pub fn new(
+        name: String,
+        likelihood: &MixtureLikelihood<X>,
+        assignments: Lens<Vec<usize>, M>,
+        mixture: fn(&M) -> Mixture<D>,
+    ) -> Self {
+        AssignmentGibbs {
+            name,
+            likelihood: likelihood.clone(),
+            assignments,
+            mixture,
+            dependencies: Vec::new(),
+            fixed: false,
+            prior_cache: None,
+            events: None,
+        }
+    }
+
+    /// Declare the parameters the mixture's weights and components read.
+    pub fn depends_on(&self, dependencies: Vec<ParamId>) -> Self {
+        AssignmentGibbs {
+            dependencies,
+            ..self.clone()
+        }
+    }
+
+    /// Identifier of the assignments
+    pub fn id(&self) -> ParamId {
+        ParamId(self.name.clone())
+    }
+}
+
+impl<X, D, M> Clone for AssignmentGibbs<X, D, M> {
+    fn clone(&self) -> Self {
+        AssignmentGibbs {
+            name: self.name.clone(),
+            likelihood: self.likelihood.clone(),
+            assignments: self.assignments.clone(),
+            mixture: self.mixture,
+            dependencies: self.dependencies.clone(),
+            fixed: self.fixed,
+            prior_cache: self.prior_cache.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<X, D, M> fmt::Debug for AssignmentGibbs<X, D, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AssignmentGibbs {{ name: {:?}, observations: {} }}",
+            self.name,
+            self.likelihood.len()
+        )
+    }
+}
+
+impl<X, D, M, R> SteppingAlg<M, R> for AssignmentGibbs<X, D, M>
+where
+    D: Rv<X>,
+    R: Rng,
+{
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        if let Some(ref cache) = self.prior_cache {
+            cache.remove(&self.id());
+        }
+        let mixture = (self.mixture)(&model);
+        let assignments: Vec<usize> = (0..self.likelihood.len())
+            .map(|i| {
+                let ln_weights =
+                    self.likelihood.component_ln_weights(i, &mixture);
+                ln_pflip(&ln_weights, 1, false, rng)[0]
+            })
+            .collect();
+        // Gibbs draws are proposals which are always accepted.
+        if let Some(ref events) = self.events {
+            events.emit(Event::ProposalAccepted {
+                parameter: self.id(),
+                log_alpha: 0.0,
+            });
+        }
+        self.assignments.set(&model, assignments)
+    }
+
+    fn set_adapt(&mut self, _mode: AdaptationMode) {}
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        AdaptationStatus::Disabled
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        Vec::new()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        vec![self.id()]
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        vec![(self.id(), self.dependencies.clone())]
+    }
+
+    fn reset(&mut self) {}
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.fixed |= *parameter == self.id();
+    }
+
+    fn set_prior_cache(&mut self, cache: PriorCache) {
+        self.prior_cache = Some(cache);
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        let mixture = (self.mixture)(&model);
+        let assignments = pflip(&mixture.weights, self.likelihood.len(), rng);
+        self.assignments.set(&model, assignments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parameter::Parameter;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::{Beta, Gaussian};
+    use steppers::{Group, SRWM};
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Clone, Debug)]
+    struct Model {
+        weight: f64,
+        z: Vec<usize>,
+    }
+
+    fn mixture(m: &Model) -> Mixture<Gaussian> {
+        Mixture::new(
+            vec![m.weight, 1.0 - m.weight],
+            vec![
+                Gaussian::new(-2.0, 1.0).unwrap(),
+                Gaussian::new(2.0, 1.0).unwrap(),
+            ],
+        ).unwrap()
+    }
+
+    fn weight() -> Parameter<Beta, f64, Model> {
+        Parameter::new(
+            "weight".to_string(),
+            Beta::new(1.0, 1.0).unwrap(),
+            Lens::new(|m: &Model| m.weight, |m: &Model, weight| Model {
+                weight,
+                ..m.clone()
+            }),
+        )
+    }
+
+    fn mean_weight<A: SteppingAlg<Model, StdRng>>(
+        stepper: &mut A,
+        init: Model,
+        rng: &mut StdRng,
+    ) -> f64 {
+        let warm = (0..500).fold(init, |m, _| stepper.step(rng, m));
+        let (_, total) = (0..2000).fold((warm, 0.0), |(m, total), _| {
+            let m = stepper.step(rng, m);
+            let w = m.weight;
+            (m, total + w)
+        });
+        total / 2000.0
+    }
+
+    #[test]
+    fn sampled_and_marginalized_assignments_agree() {
+        let mut rng = StdRng::from_seed(SEED);
+        let truth = Model {
+            weight: 0.3,
+            z: Vec::new(),
+        };
+        let data: Vec<f64> = mixture(&truth).sample(300, &mut rng);
+        let likelihood = MixtureLikelihood::new(data);
+        let init = Model {
+            weight: 0.5,
+            z: vec![0; likelihood.len()],
+        };
+
+        let mut marginal = SRWM::new(
+            weight(),
+            likelihood.marginal_log_likelihood(mixture),
+            Some(0.05),
+        ).unwrap()
+        .bounded(0.0, 1.0);
+        let marginal_mean = mean_weight(&mut marginal, init.clone(), &mut rng);
+
+        let assignments = Lens::new(|m: &Model| m.z.clone(), |m: &Model, z| {
+            Model { z, ..m.clone() }
+        });
+        let gibbs = AssignmentGibbs::new(
+            "z".to_string(),
+            &likelihood,
+            assignments.clone(),
+            mixture,
+        ).depends_on(vec![weight().id().clone()]);
+        let mut sampled: Group<Model, StdRng> = Group::new(vec![
+            Box::new(gibbs.clone()),
+            Box::new(
+                SRWM::new(
+                    weight(),
+                    likelihood.complete_log_likelihood(assignments, mixture),
+                    Some(0.05),
+                ).unwrap()
+                .bounded(0.0, 1.0),
+            ),
+        ]);
+        let sampled_mean = mean_weight(&mut sampled, init.clone(), &mut rng);
+
+        assert!((marginal_mean - 0.3).abs() < 0.07);
+        assert!((sampled_mean - marginal_mean).abs() < 0.05);
+
+        let prior_draw: Model =
+            SteppingAlg::<Model, StdRng>::draw_prior(&gibbs, &mut rng, init);
+        assert_eq!(prior_draw.z.len(), likelihood.len());
+        assert!(prior_draw.z.iter().any(|&k| k == 0));
+        assert!(prior_draw.z.iter().any(|&k| k == 1));
+    }
+}
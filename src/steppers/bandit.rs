@@ -0,0 +1,404 @@
+//! # Kernel Bandit
+//! Chooses between proposal kernels of one parameter during warmup.
+
+use std::fmt;
+use std::io;
+use rand::Rng;
+use events::EventSink;
+use parameter::ParamId;
+use statistics::Statistic;
+use steppers::{AdaptationMode, AdaptationStatus, BoxedStepper, SteppingAlg};
+use steppers::util;
+use steppers::adaptor::{next_state, AdaptorState};
+use utils::invalid_input;
+
+/// Name of the adaptation state of a bandit's rewards
+const BANDIT_ID: &str = "bandit";
+
+/// Experimental stepper treating several steppers of the same parameters,
+/// e.g. SRWMs with Gaussian, Student's t and mode jumping proposals, as
+/// the arms of a bandit
+///
+/// While adaptation is enabled each step pulls an arm by the UCB1 rule,
+/// rewarding it with the squared distance `jump` the chain moved, so arms
+/// with a larger expected squared jumping distance (ESJD) are pulled more
+/// often. When adaptation is disabled at the end of warmup the bandit
+/// commits to the arm with the largest mean reward and uses only it.
+///
+/// Choosing arms by their past performance does not leave the posterior
+/// invariant, so draws are only valid after the bandit has committed.
+pub struct KernelBandit<M, R: Rng> {
+    arms: Vec<BoxedStepper<M, R>>,
+    jump: fn(&M, &M) -> f64,
+    pulls: Vec<usize>,
+    rewards: Vec<f64>,
+    adapting: bool,
+    chosen: Option<usize>,
+    last: Option<usize>,
+}
+
+impl<M, R: Rng> KernelBandit<M, R> {
+    /// A bandit over `arms`, measuring the squared distance between two
+    /// models with `jump`
+    ///
+    /// Fails with `InvalidInput` if there are no arms or they update
+    /// different parameters.
+    pub fn new(
+        arms: Vec<BoxedStepper<M, R>>,
+        jump: fn(&M, &M) -> f64,
+    ) -> io::Result<Self> {
+        let parameters = match arms.first() {
+            Some(arm) => arm.parameters(),
+            None => {
+                return Err(invalid_input(
+                    "a kernel bandit needs at least one arm",
+                ))
+            }
+        };
+        if arms.iter().any(|arm| arm.parameters() != parameters) {
+            return Err(invalid_input(
+                "the arms of a kernel bandit must update the same parameters",
+            ));
+        }
+        let n = arms.len();
+        Ok(KernelBandit {
+            arms,
+            jump,
+            pulls: vec![0; n],
+            rewards: vec![0.0; n],
+            adapting: false,
+            chosen: None,
+            last: None,
+        })
+    }
+
+    /// The arm committed to, once adaptation has been disabled
+    pub fn chosen(&self) -> Option<usize> {
+        self.chosen
+    }
+
+    /// Mean squared jumping distance of each arm, if it has been pulled
+    pub fn esjd(&self) -> Vec<Option<f64>> {
+        self.pulls
+            .iter()
+            .zip(&self.rewards)
+            .map(|(&n, &total)| {
+                if n > 0 {
+                    Some(total / n as f64)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // Arm with the largest mean reward so far, the first if none were pulled
+    fn best(&self) -> usize {
+        self.esjd()
+            .iter()
+            .enumerate()
+            .fold((0, ::std::f64::NEG_INFINITY), |(best, max), (i, esjd)| {
+                match esjd {
+                    Some(x) if *x > max => (i, *x),
+                    _ => (best, max),
+                }
+            })
+            .0
+    }
+
+    // UCB1 choice of arm, with rewards relative to the best mean reward
+    fn pull(&self) -> usize {
+        if let Some(i) = self.pulls.iter().position(|&n| n == 0) {
+            return i;
+        }
+        let esjd: Vec<f64> = self.esjd().into_iter().flatten().collect();
+        let max = esjd.iter().cloned().fold(0.0, f64::max);
+        let total: usize = self.pulls.iter().sum();
+        let ln_total = (total as f64).ln();
+        let bound = |i: usize| {
+            let mean = if max > 0.0 { esjd[i] / max } else { 0.0 };
+            mean + (2.0 * ln_total / self.pulls[i] as f64).sqrt()
+        };
+        (1..self.arms.len()).fold(0, |best, i| {
+            if bound(i) > bound(best) {
+                i
+            } else {
+                best
+            }
+        })
+    }
+
+    // Step with `arm`, first forgetting the scores it cached when it was
+    // last pulled, since other arms have moved the chain since.
+    fn step_arm(&mut self, arm: usize, rng: &mut R, model: M) -> M {
+        if self.last != Some(arm) {
+            self.arms[arm].invalidate_cache();
+            self.last = Some(arm);
+        }
+        self.arms[arm].step(rng, model)
+    }
+}
+
+impl<M, R: Rng> Clone for KernelBandit<M, R> {
+    fn clone(&self) -> Self {
+        KernelBandit {
+            arms: self.arms.clone(),
+            jump: self.jump,
+            pulls: self.pulls.clone(),
+            rewards: self.rewards.clone(),
+            adapting: self.adapting,
+            chosen: self.chosen,
+            last: self.last,
+        }
+    }
+}
+
+impl<M, R: Rng> fmt::Debug for KernelBandit<M, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "KernelBandit {{ arms: {:?}, pulls: {:?}, chosen: {:?} }}",
+            self.arms, self.pulls, self.chosen
+        )
+    }
+}
+
+impl<M, R> SteppingAlg<M, R> for KernelBandit<M, R>
+where
+    M: Clone,
+    R: Rng,
+{
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        if !self.adapting {
+            let arm = self.chosen.unwrap_or_else(|| self.best());
+            return self.step_arm(arm, rng, model);
+        }
+        let arm = self.pull();
+        let next = self.step_arm(arm, rng, model.clone());
+        let reward = (self.jump)(&model, &next);
+        // A non-finite distance says nothing about the arm's ESJD.
+        if reward.is_finite() {
+            self.pulls[arm] += 1;
+            self.rewards[arm] += reward;
+        }
+        next
+    }
+
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        self.arms.iter_mut().for_each(|arm| arm.set_adapt(mode));
+        match mode {
+            AdaptationMode::Enabled => {
+                self.adapting = true;
+                self.chosen = None;
+            }
+            AdaptationMode::Disabled => {
+                self.adapting = false;
+                self.chosen = Some(self.best());
+            }
+        }
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        if self.adapting {
+            AdaptationStatus::Enabled
+        } else {
+            AdaptationStatus::Disabled
+        }
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        let arm = self.chosen.unwrap_or_else(|| self.best());
+        self.arms[arm].get_statistics()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        self.arms[0].parameters()
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        self.arms[0].dependencies()
+    }
+
+    fn reset(&mut self) {
+        self.arms.iter_mut().for_each(|arm| arm.reset());
+        self.pulls.iter_mut().for_each(|n| *n = 0);
+        self.rewards.iter_mut().for_each(|r| *r = 0.0);
+        self.chosen = None;
+        self.last = None;
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.arms.iter_mut().for_each(|arm| arm.invalidate_cache());
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.arms.iter_mut().for_each(|arm| arm.fix(parameter));
+    }
+
+    fn set_prior_cache(&mut self, cache: util::PriorCache) {
+        self.arms
+            .iter_mut()
+            .for_each(|arm| arm.set_prior_cache(cache.clone()));
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.arms
+            .iter_mut()
+            .for_each(|arm| arm.set_event_sink(sink.clone()));
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        self.arms[0].draw_prior(rng, model)
+    }
+
+    // The rewards of the arms, followed by each arm's own state
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        let own = AdaptorState {
+            name: BANDIT_ID.to_string(),
+            ..AdaptorState::new(
+                Vec::new(),
+                self.rewards.clone(),
+                self.pulls.clone(),
+            )
+        };
+        Some(own)
+            .into_iter()
+            .chain(self.arms.iter().flat_map(|arm| arm.adaptation_state()))
+            .collect()
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        let own = next_state(states, BANDIT_ID)?;
+        let n = self.arms.len();
+        own.expect(0, n, n)?;
+        self.rewards = own.moments;
+        self.pulls = own.counts;
+        for arm in self.arms.iter_mut() {
+            arm.restore_adaptation(states)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use rv::misc::ks_test;
+    use rv::prelude::Cdf;
+    use steppers::{IntoBoxedStepper, SRWM};
+    use utils::multiple_tries;
+
+    const SEED: [u8; 32] = [0; 32];
+    const P_VAL: f64 = 0.2;
+    const N_TRIES: usize = 10;
+
+    #[derive(Copy, Clone, Debug)]
+    struct Model {
+        x: f64,
+        y: f64,
+    }
+
+    fn srwm(scale: f64) -> BoxedStepper<Model, StdRng> {
+        let x = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        SRWM::new(x, |_m: &Model| 0.0, None)
+            .unwrap()
+            .proposal_scale(scale)
+            .boxed()
+    }
+
+    fn jump(a: &Model, b: &Model) -> f64 {
+        (a.x - b.x).powi(2)
+    }
+
+    #[test]
+    fn bandit_commits_to_the_arm_jumping_furthest() {
+        let arms = vec![srwm(0.01), srwm(2.4), srwm(100.0)];
+        let mut bandit = KernelBandit::new(arms, jump).unwrap();
+        let mut rng = StdRng::from_seed(SEED);
+        let mut m = Model { x: 0.0, y: 0.0 };
+
+        bandit.set_adapt(AdaptationMode::Enabled);
+        for _ in 0..3000 {
+            m = bandit.step(&mut rng, m);
+        }
+        assert!(bandit.pulls[1] > bandit.pulls[0]);
+        assert!(bandit.pulls[1] > bandit.pulls[2]);
+        bandit.set_adapt(AdaptationMode::Disabled);
+        assert_eq!(bandit.chosen(), Some(1));
+
+        let esjd = bandit.esjd();
+        assert!(esjd[1].unwrap() > esjd[0].unwrap());
+        assert!(esjd[1].unwrap() > esjd[2].unwrap());
+    }
+
+    #[test]
+    fn arms_must_update_the_same_parameters() {
+        let y = Parameter::new(
+            "y".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, y),
+        );
+        let other = SRWM::new(y, |_m: &Model| 0.0, None).unwrap().boxed();
+        let bandit = KernelBandit::new(vec![srwm(1.0), other], jump);
+        assert!(bandit.is_err());
+        assert!(KernelBandit::<Model, StdRng>::new(vec![], jump).is_err());
+    }
+
+    #[test]
+    fn alternating_arms_leave_the_posterior_invariant() {
+        let data: Vec<f64> = (0..40).map(|i| (i % 5) as f64 * 0.5).collect();
+        let arm = |scale: f64| {
+            let x = Parameter::new(
+                "x".to_string(),
+                Gaussian::new(0.0, 1.0).unwrap(),
+                make_lens!(Model, f64, x),
+            );
+            let data = data.clone();
+            let log_likelihood = move |m: &Model| -> f64 {
+                data.iter().map(|y| -0.5 * (y - m.x).powi(2)).sum()
+            };
+            SRWM::new(x, log_likelihood, None)
+                .unwrap()
+                .proposal_scale(scale)
+                .boxed()
+        };
+        // Identical arms, so alternating between them the chain follows
+        // one kernel, as long as neither steps from a stale score.
+        let mut bandit = KernelBandit::new(vec![arm(0.3), arm(0.3)], jump)
+            .unwrap();
+        bandit.set_adapt(AdaptationMode::Disabled);
+
+        let n = data.len() as f64;
+        let mean = data.iter().sum::<f64>() / (n + 1.0);
+        let posterior = Gaussian::new(mean, (n + 1.0).sqrt().recip()).unwrap();
+
+        let mut rng = StdRng::from_seed(SEED);
+        let mut m = Model { x: 0.0, y: 0.0 };
+        let passed = multiple_tries(N_TRIES, |_| {
+            let mut xs = Vec::new();
+            for i in 0..200_000 {
+                bandit.chosen = Some(i % 2);
+                m = bandit.step(&mut rng, m);
+                if i % 10 == 0 {
+                    xs.push(m.x);
+                }
+            }
+            let (stat, p) = ks_test(&xs, |x| posterior.cdf(&x));
+            println!("test stat = {}, p = {}", stat, p);
+            p > P_VAL
+        });
+        assert!(passed);
+    }
+}
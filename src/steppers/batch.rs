@@ -0,0 +1,403 @@
+//! # Batched Stepping
+//! Steppers which advance several chains at once, so the per-step overhead
+//! and likelihood evaluations can be shared across chains.
+
+use std::f64::NEG_INFINITY;
+use std::fmt;
+use rand::Rng;
+use rand::distributions::StandardNormal;
+
+use rv::traits::Rv;
+use log_density::LogDensity;
+use parameter::{Parameter, ParamId};
+use steppers::{SteppingAlg, AdaptationStatus, AdaptationMode, util};
+use events::EventSink;
+
+/// A stepping algorithm which advances a batch of chains together.
+pub trait BatchSteppingAlg<M, R: Rng>: fmt::Debug
+{
+    // Advance every chain's state by one step.
+    fn step_batch(&mut self, rng: &mut R, models: Vec<M>) -> Vec<M>;
+    // Set the adaptation mode
+    fn set_adapt(&mut self, mode: AdaptationMode);
+    // Return the adaptation status.
+    fn get_adapt(&self) -> AdaptationStatus;
+    // Return the parameters updated by this stepper.
+    fn parameters(&self) -> Vec<ParamId>;
+    // Reset the current stepper to it's initial state
+    fn reset(&mut self);
+    // Report events, e.g. lens failures, to `sink`.
+    fn set_event_sink(&mut self, _sink: EventSink) {}
+}
+
+/// Batch any `SteppingAlg` by keeping one copy of it per chain.
+///
+/// This shares no work between chains but lets every stepper run under a
+/// `BatchRunner`.
+#[derive(Clone, Debug)]
+pub struct Batched<A> {
+    pub template: A,
+    steppers: Vec<A>,
+}
+
+impl<A: Clone> Batched<A> {
+    pub fn new(template: A) -> Self {
+        Batched {
+            template,
+            steppers: Vec::new(),
+        }
+    }
+}
+
+impl<M, R, A> BatchSteppingAlg<M, R> for Batched<A>
+where
+    R: Rng,
+    A: SteppingAlg<M, R> + Clone,
+{
+    fn step_batch(&mut self, rng: &mut R, models: Vec<M>) -> Vec<M> {
+        while self.steppers.len() < models.len() {
+            self.steppers.push(self.template.clone());
+        }
+        models
+            .into_iter()
+            .zip(self.steppers.iter_mut())
+            .map(|(m, stepper)| stepper.step(rng, m))
+            .collect()
+    }
+
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        self.template.set_adapt(mode);
+        self.steppers.iter_mut().for_each(|s| s.set_adapt(mode));
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        self.template.get_adapt()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        self.template.parameters()
+    }
+
+    fn reset(&mut self) {
+        self.template.reset();
+        self.steppers.clear();
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.template.set_event_sink(sink.clone());
+        self.steppers
+            .iter_mut()
+            .for_each(|s| s.set_event_sink(sink.clone()));
+    }
+}
+
+/// Symmetric Random Walk Metropolis over a batch of chains
+///
+/// The log likelihood is evaluated for the whole batch of proposals in one
+/// call, so it can be vectorized across chains. Proposals add Gaussian noise
+/// with a fixed scale to a continuous parameter.
+pub struct BatchSRWM<D, M, L>
+where
+    D: Rv<f64> + Clone,
+    M: 'static + Clone,
+    L: Fn(&[M]) -> Vec<f64> + Clone + Sync,
+{
+    pub parameter: Parameter<D, f64, M>,
+    pub log_likelihood: L,
+    pub proposal_scale: f64,
+    current_scores: Option<Vec<f64>>,
+    acceptance: util::AcceptanceCounter,
+    events: Option<EventSink>,
+}
+
+impl<D, M, L> BatchSRWM<D, M, L>
+where
+    D: Rv<f64> + Clone,
+    M: 'static + Clone,
+    L: Fn(&[M]) -> Vec<f64> + Clone + Sync,
+{
+    pub fn new(
+        parameter: Parameter<D, f64, M>,
+        log_likelihood: L,
+        proposal_scale: f64,
+    ) -> Self {
+        assert!(
+            proposal_scale > 0.0 && proposal_scale.is_finite(),
+            "proposal scale must be finite and positive."
+        );
+        BatchSRWM {
+            parameter,
+            log_likelihood,
+            proposal_scale,
+            current_scores: None,
+            acceptance: util::AcceptanceCounter::new(),
+            events: None,
+        }
+    }
+
+    /// Fraction of proposals accepted across all chains
+    pub fn acceptance_rate(&self) -> Option<f64> {
+        self.acceptance.rate()
+    }
+
+    fn scores(&self, models: &[M]) -> Vec<f64> {
+        let log_likelihoods = (self.log_likelihood)(models);
+        assert_eq!(
+            log_likelihoods.len(),
+            models.len(),
+            "Batched log likelihood must return one value per model."
+        );
+        models
+            .iter()
+            .zip(log_likelihoods)
+            .map(|(m, ll)| match self.parameter.lens.try_get(m) {
+                Ok(value) => {
+                    let prior = self.parameter.prior.ln_f(&value);
+                    (LogDensity(prior) + LogDensity(ll)).value()
+                }
+                // `step_batch` rejects proposals from such models.
+                Err(_) => NEG_INFINITY,
+            })
+            .collect()
+    }
+}
+
+impl<D, M, L> Clone for BatchSRWM<D, M, L>
+where
+    D: Rv<f64> + Clone,
+    M: 'static + Clone,
+    L: Fn(&[M]) -> Vec<f64> + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        BatchSRWM {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            proposal_scale: self.proposal_scale,
+            current_scores: self.current_scores.clone(),
+            acceptance: self.acceptance,
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<D, M, L> fmt::Debug for BatchSRWM<D, M, L>
+where
+    D: Rv<f64> + Clone,
+    M: 'static + Clone,
+    L: Fn(&[M]) -> Vec<f64> + Clone + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BatchSRWM {{ parameter: {:?}, proposal_scale: {} }}",
+            self.parameter, self.proposal_scale
+        )
+    }
+}
+
+impl<D, M, L, R> BatchSteppingAlg<M, R> for BatchSRWM<D, M, L>
+where
+    D: Rv<f64> + Clone,
+    M: 'static + Clone,
+    L: Fn(&[M]) -> Vec<f64> + Clone + Sync,
+    R: Rng,
+{
+    fn step_batch(&mut self, rng: &mut R, models: Vec<M>) -> Vec<M> {
+        let current_scores = match self.current_scores.take() {
+            Some(ref scores) if scores.len() == models.len() => scores.clone(),
+            _ => self.scores(&models),
+        };
+
+        let lens = &self.parameter.lens;
+        let (proposals, failed): (Vec<M>, Vec<bool>) = models
+            .iter()
+            .map(|m| {
+                let z: f64 = rng.sample(StandardNormal);
+                let proposal = lens
+                    .try_get(m)
+                    .and_then(|x| lens.try_set(m, x + self.proposal_scale * z));
+                match proposal {
+                    Ok(proposal) => (proposal, false),
+                    Err(err) => {
+                        let id = self.parameter.id();
+                        let events = &self.events;
+                        let m = m.clone();
+                        (util::reject_lens_failure(events, id, &err, m), true)
+                    }
+                }
+            })
+            .unzip();
+        // A chain whose lens failed stays where it is.
+        let proposed_scores: Vec<f64> = self
+            .scores(&proposals)
+            .into_iter()
+            .zip(failed)
+            .map(|(score, failed)| if failed { NEG_INFINITY } else { score })
+            .collect();
+
+        let (next, scores): (Vec<M>, Vec<f64>) = models
+            .into_iter()
+            .zip(proposals)
+            .zip(current_scores.into_iter().zip(proposed_scores))
+            .map(|((current, proposed), (current_score, proposed_score))| {
+                let update = util::metropolis_select(
+                    rng,
+                    util::ProposalScores::new(current_score, proposed_score),
+                    proposed,
+                    current,
+                );
+                self.acceptance.record(&update);
+                match update {
+                    util::MetroplisUpdate::Accepted(m, scores) => {
+                        (m, scores.proposed)
+                    }
+                    util::MetroplisUpdate::Rejected(m, scores) => {
+                        (m, scores.current)
+                    }
+                }
+            })
+            .unzip();
+
+        self.current_scores = Some(scores);
+        next
+    }
+
+    fn set_adapt(&mut self, _mode: AdaptationMode) {}
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        AdaptationStatus::Disabled
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        vec![self.parameter.id().clone()]
+    }
+
+    fn reset(&mut self) {
+        self.current_scores = None;
+        self.acceptance.reset();
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use lens::*;
+    use runner::BatchRunner;
+    use rv::dist::*;
+    use rv::misc::ks_test;
+    use rv::prelude::Cdf;
+    use utils::multiple_tries;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use steppers::SRWM;
+
+    const P_VAL: f64 = 0.2;
+    const N_TRIES: usize = 10;
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Copy, Clone, Debug)]
+    struct Model {
+        x: f64,
+    }
+
+    fn parameter() -> Parameter<Gaussian, f64, Model> {
+        Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        )
+    }
+
+    fn check_posterior<B>(stepper: B) -> bool
+    where
+        B: BatchSteppingAlg<Model, StdRng> + Clone,
+    {
+        let mut rng = StdRng::from_seed(SEED);
+        multiple_tries(N_TRIES, |_| {
+            let results = BatchRunner::new(stepper.clone())
+                .chains(8)
+                .warmup(100)
+                .samples(200)
+                .thinning(5)
+                .run(&mut rng, Model { x: 0.0 });
+            assert_eq!(results.len(), 8);
+            assert!(results.iter().all(|chain| chain.len() == 200));
+
+            let samples: Vec<f64> = results
+                .iter()
+                .flat_map(|chain| chain.iter().map(|m| m.x))
+                .collect();
+            // The likelihood N(x | 1, 1) and prior give N(0.5, 1 / sqrt(2)).
+            let (stat, p) = ks_test(&samples, |s| {
+                Gaussian::new(0.5, 0.5f64.sqrt()).unwrap().cdf(&s)
+            });
+            println!("test stat = {}, p = {}", stat, p);
+            p > P_VAL
+        })
+    }
+
+    #[test]
+    fn batch_srwm_gaussian_posterior() {
+        let log_likelihood = |models: &[Model]| {
+            models
+                .iter()
+                .map(|m| -0.5 * (m.x - 1.0) * (m.x - 1.0))
+                .collect()
+        };
+        let stepper = BatchSRWM::new(parameter(), log_likelihood, 1.0);
+        assert!(check_posterior(stepper));
+    }
+
+    #[test]
+    fn batched_srwm_gaussian_posterior() {
+        let log_likelihood = |m: &Model| -0.5 * (m.x - 1.0) * (m.x - 1.0);
+        let stepper =
+            SRWM::new(parameter(), log_likelihood, Some(1.0)).unwrap();
+        assert!(check_posterior(Batched::new(stepper)));
+    }
+
+    #[test]
+    fn lens_failures_leave_their_chain_in_place() {
+        use events::Event;
+        use std::collections::BTreeMap;
+        type Map = BTreeMap<String, f64>;
+
+        let mut model: Map = BTreeMap::new();
+        model.insert("a".to_string(), 0.0);
+        let parameter = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            Lens::map_key("a", &model).unwrap(),
+        );
+        let log_likelihood = |models: &[Map]| vec![0.0; models.len()];
+        let mut stepper = BatchSRWM::new(parameter, log_likelihood, 1.0);
+        let sink = EventSink::new();
+        BatchSteppingAlg::<Map, StdRng>::set_event_sink(
+            &mut stepper,
+            sink.clone(),
+        );
+        let mut rng = StdRng::from_seed(SEED);
+
+        let mut missing: Map = BTreeMap::new();
+        missing.insert("b".to_string(), 1.0);
+        let models = (0..10).fold(vec![model, missing.clone()], |ms, _| {
+            stepper.step_batch(&mut rng, ms)
+        });
+        assert!(models[0]["a"] != 0.0);
+        assert_eq!(models[1], missing);
+        let failures = sink
+            .drain()
+            .into_iter()
+            .filter(|e| match e {
+                Event::LensFailed { .. } => true,
+                _ => false,
+            })
+            .count();
+        assert_eq!(failures, 10);
+    }
+}
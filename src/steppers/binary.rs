@@ -0,0 +1,955 @@
+//! # Binary steppers
+//! Metropolis updates of binary parameters, e.g. the inclusion indicators
+//! of a spike-and-slab prior or the indicators of mixture components.
+//!
+//! `BinaryMetropolis` proposes flipping a random subset of the bits at
+//! once, with the expected size of the subset adapted during warmup.
+//! `BinaryGibbsMetropolis` sweeps the bits one at a time, drawing each from
+//! its full conditional; it is the better choice when bits interact
+//! strongly, at the cost of an evaluation of the likelihood per bit.
+
+use std::fmt;
+use std::io;
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use rv::traits::Rv;
+use parameter::{Parameter, ParamId};
+use likelihood::DeltaLogLikelihood;
+use steppers::{SteppingAlg, AdaptationStatus, AdaptationMode, util};
+use steppers::adaptor::{
+    next_state, AdaptorState, DiscreteAdaptor, ScaleAdaptor,
+    DISCRETE_TARGET_ACCEPTANCE,
+};
+use statistics::{Statistic, StatisticValue};
+use events::EventSink;
+use utils::invalid_input;
+
+/// Initial scale of `BinaryMetropolis` proposals, flipping each bit with
+/// probability one half
+pub const DEFAULT_FLIP_SCALE: f64 = 1.0;
+
+/// Values made of bits which binary steppers can flip
+pub trait Binary: 'static + Clone + fmt::Debug + Send + Sync {
+    /// Number of bits
+    fn n_bits(&self) -> usize;
+    /// Flip bit `i`.
+    fn flip(&mut self, i: usize);
+}
+
+impl Binary for bool {
+    fn n_bits(&self) -> usize {
+        1
+    }
+
+    fn flip(&mut self, _i: usize) {
+        *self = !*self;
+    }
+}
+
+impl Binary for Vec<bool> {
+    fn n_bits(&self) -> usize {
+        self.len()
+    }
+
+    fn flip(&mut self, i: usize) {
+        self[i] = !self[i];
+    }
+}
+
+/// Probability of flipping each bit at proposal scale `scale`
+fn flip_probability(scale: f64) -> f64 {
+    1.0 - 0.5f64.powf(scale)
+}
+
+/// Log probability with which a bit drawn from its full conditional moves,
+/// *π' / (π + π')*, from the log ratio of the densities with it flipped and
+/// not
+fn log_move_probability(log_ratio: f64) -> f64 {
+    if log_ratio > 0.0 {
+        -(-log_ratio).exp().ln_1p()
+    } else {
+        log_ratio - log_ratio.exp().ln_1p()
+    }
+}
+
+/// Configuration of a `BinaryMetropolis` stepper
+///
+/// # Example
+/// ```
+/// # #[macro_use] extern crate rmcmc;
+/// # extern crate rand;
+/// # extern crate rv;
+/// # use rmcmc::lens::*;
+/// # use rmcmc::parameter::Parameter;
+/// # use rmcmc::runner::Runner;
+/// # use rmcmc::steppers::BinaryMetropolisBuilder;
+/// # use rmcmc::utils::MultiRv;
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # use rv::dist::Bernoulli;
+/// # fn main() {
+/// #[derive(Clone, Debug)]
+/// struct Model {
+///     included: Vec<bool>,
+/// }
+///
+/// let included = Parameter::new(
+///     "included".to_string(),
+///     MultiRv::new(10, Bernoulli::new(0.2).unwrap()),
+///     make_lens_clone!(Model, Vec<bool>, included),
+/// );
+/// let alg = BinaryMetropolisBuilder::new(included, |_: &Model| 0.0)
+///     .scale(0.2)
+///     .build()
+///     .unwrap();
+///
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let init = Model { included: vec![false; 10] };
+/// let draws = Runner::new(alg).warmup(100).samples(100).run(&mut rng, init);
+/// assert_eq!(draws[0].len(), 100);
+/// # }
+/// ```
+pub struct BinaryMetropolisBuilder<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    parameter: Parameter<D, T, M>,
+    log_likelihood: L,
+    scale: f64,
+    target_acceptance: f64,
+    adapt: bool,
+}
+
+impl<D, T, M, L> Clone for BinaryMetropolisBuilder<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        BinaryMetropolisBuilder {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            scale: self.scale,
+            target_acceptance: self.target_acceptance,
+            adapt: self.adapt,
+        }
+    }
+}
+
+impl<D, T, M, L> BinaryMetropolisBuilder<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    pub fn new(parameter: Parameter<D, T, M>, log_likelihood: L) -> Self {
+        BinaryMetropolisBuilder {
+            parameter,
+            log_likelihood,
+            scale: DEFAULT_FLIP_SCALE,
+            target_acceptance: DISCRETE_TARGET_ACCEPTANCE,
+            adapt: true,
+        }
+    }
+
+    /// Start from proposals flipping each bit with probability
+    /// *1 - 2^-scale*, instead of one half.
+    pub fn scale(&self, scale: f64) -> Self {
+        BinaryMetropolisBuilder {
+            scale,
+            ..(*self).clone()
+        }
+    }
+
+    /// Adapt the scale towards `target_acceptance` instead of
+    /// `DISCRETE_TARGET_ACCEPTANCE`.
+    pub fn target_acceptance(&self, target_acceptance: f64) -> Self {
+        BinaryMetropolisBuilder {
+            target_acceptance,
+            ..(*self).clone()
+        }
+    }
+
+    /// Keep the initial scale during warmup instead of adapting it.
+    pub fn fixed_scale(&self) -> Self {
+        BinaryMetropolisBuilder {
+            adapt: false,
+            ..(*self).clone()
+        }
+    }
+
+    /// The stepper, or an `InvalidInput` error if the scale is not finite
+    /// and positive or the target acceptance is not between zero and one.
+    pub fn build(&self) -> io::Result<BinaryMetropolis<D, T, M, L>> {
+        let id = self.parameter.id();
+        if !(self.scale > 0.0 && self.scale.is_finite()) {
+            return Err(invalid_input(format!(
+                "BinaryMetropolis for {}: scale must be finite and positive.",
+                id
+            )));
+        }
+        if !(self.target_acceptance > 0.0 && self.target_acceptance < 1.0) {
+            return Err(invalid_input(format!(
+                "BinaryMetropolis for {}: target acceptance must be between \
+                 zero and one.",
+                id
+            )));
+        }
+        Ok(BinaryMetropolis {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            mh: util::MHCore::new(),
+            fixed: false,
+            adapt: self.adapt,
+            adaptor: Box::new(DiscreteAdaptor::new(
+                self.scale,
+                self.target_acceptance,
+            )),
+            prior_cache: None,
+            events: None,
+        })
+    }
+}
+
+/// Metropolis stepper flipping random subsets of a binary parameter's bits
+///
+/// Each bit is flipped with probability *1 - 2^-scale*; proposals which
+/// would flip no bit flip one chosen uniformly instead. The proposal is
+/// symmetric, and the scale is adapted during warmup towards a target
+/// acceptance rate.
+pub struct BinaryMetropolis<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    pub parameter: Parameter<D, T, M>,
+    pub log_likelihood: L,
+    pub mh: util::MHCore,
+    pub fixed: bool,
+    adapt: bool,
+    adaptor: Box<dyn ScaleAdaptor<T>>,
+    prior_cache: Option<util::PriorCache>,
+    events: Option<EventSink>,
+}
+
+impl<D, T, M, L> BinaryMetropolis<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    /// Probability with which proposals flip each bit
+    pub fn flip_probability(&self) -> f64 {
+        flip_probability(self.adaptor.get_scale())
+    }
+
+    fn current_prior(&self, value: &T) -> f64 {
+        current_prior(&self.parameter, &self.prior_cache, value)
+    }
+}
+
+fn current_prior<D, T, M>(
+    parameter: &Parameter<D, T, M>,
+    prior_cache: &Option<util::PriorCache>,
+    value: &T,
+) -> f64
+where
+    D: Rv<T> + Clone,
+{
+    match prior_cache {
+        Some(ref cache) => cache
+            .get_or_insert_with(parameter.id(), || {
+                parameter.prior.ln_f(value)
+            }),
+        None => parameter.prior.ln_f(value),
+    }
+}
+
+impl<D, T, M, L> Clone for BinaryMetropolis<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        BinaryMetropolis {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            mh: self.mh,
+            fixed: self.fixed,
+            adapt: self.adapt,
+            adaptor: self.adaptor.clone(),
+            prior_cache: self.prior_cache.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<D, T, M, L> fmt::Debug for BinaryMetropolis<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BinaryMetropolis {{ parameter: {:?}, scale: {} }}",
+            self.parameter,
+            self.adaptor.get_scale()
+        )
+    }
+}
+
+impl<D, T, M, L, R> SteppingAlg<M, R> for BinaryMetropolis<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+    R: Rng,
+{
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        match mode {
+            AdaptationMode::Enabled if !self.adapt => {}
+            mode => self.adaptor.set_mode(mode),
+        }
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        self.adaptor.get_mode()
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        let non_finite = self.adaptor.non_finite_updates();
+        self.mh
+            .acceptance
+            .rate()
+            .map(StatisticValue::AcceptanceRate)
+            .into_iter()
+            .chain(Some(StatisticValue::ProposalScale(
+                self.adaptor.get_scale(),
+            )))
+            .chain(
+                Some(StatisticValue::NonFiniteUpdates(non_finite))
+                    .filter(|_| non_finite > 0),
+            )
+            .map(|value| Statistic::new(self.parameter.id().clone(), value))
+            .collect()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        vec![self.parameter.id().clone()]
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        vec![(self.parameter.id().clone(), self.parameter.dependencies.clone())]
+    }
+
+    fn reset(&mut self) {
+        self.mh.reset();
+        self.adaptor.reset();
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.mh.invalidate();
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.fixed |= parameter == self.parameter.id();
+    }
+
+    fn set_prior_cache(&mut self, cache: util::PriorCache) {
+        self.prior_cache = Some(cache);
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        self.parameter.draw(&model, rng)
+    }
+
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        vec![AdaptorState {
+            name: self.parameter.name().to_string(),
+            ..self.adaptor.state()
+        }]
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        let name = self.parameter.name();
+        self.adaptor.restore(&next_state(states, name)?)
+    }
+
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        let id = self.parameter.id();
+        let current_value = match self.parameter.lens.try_get(&model) {
+            Ok(value) => value,
+            Err(err) => {
+                return util::reject_lens_failure(&self.events, id, &err, model)
+            }
+        };
+        let n_bits = current_value.n_bits();
+        if n_bits == 0 {
+            return model;
+        }
+        let current_score = self.mh.current_score(|| {
+            self.log_likelihood.ln_f(&model)
+                + self.current_prior(&current_value)
+        });
+
+        let p = self.flip_probability();
+        let mut proposed_value = current_value.clone();
+        let mut flipped = 0;
+        for i in 0..n_bits {
+            if rng.gen::<f64>() < p {
+                proposed_value.flip(i);
+                flipped += 1;
+            }
+        }
+        if flipped == 0 {
+            proposed_value.flip(rng.gen_range(0, n_bits));
+        }
+
+        let new_model =
+            match self.parameter.lens.try_set(&model, proposed_value.clone()) {
+                Ok(new_model) => new_model,
+                Err(err) => {
+                    let events = &self.events;
+                    return util::reject_lens_failure(events, id, &err, model);
+                }
+            };
+        let prior_score = self.parameter.prior.ln_f(&proposed_value);
+        let new_score = util::proposal_score(
+            &self.log_likelihood,
+            id,
+            &model,
+            current_score,
+            || self.current_prior(&current_value),
+            &new_model,
+            prior_score,
+        );
+        let scores = util::ProposalScores::new(current_score, new_score);
+        // The current value moves into the update, so score it beforehand.
+        let verbose_prior = match self.events {
+            Some(ref events) if events.is_verbose() => {
+                self.current_prior(&current_value)
+            }
+            _ => 0.0,
+        };
+
+        let update =
+            util::metropolis_select(rng, scores, proposed_value, current_value);
+        self.adaptor.update(&update);
+        self.mh.record(&update);
+        if let Some(ref events) = self.events {
+            events.emit_update(id, &update);
+            events.emit_scores(
+                id,
+                &update,
+                || verbose_prior,
+                prior_score,
+            );
+            let scale = self.adaptor.get_scale();
+            events.emit_adaptation(id, self.adaptor.get_mode(), scale);
+        }
+        if update.is_accepted() {
+            if let Some(ref cache) = self.prior_cache {
+                cache.insert(id.clone(), prior_score);
+            }
+            new_model
+        } else {
+            model
+        }
+    }
+}
+
+/// Configuration of a `BinaryGibbsMetropolis` stepper
+pub struct BinaryGibbsMetropolisBuilder<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    parameter: Parameter<D, T, M>,
+    log_likelihood: L,
+    systematic: bool,
+}
+
+impl<D, T, M, L> Clone for BinaryGibbsMetropolisBuilder<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        BinaryGibbsMetropolisBuilder {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            systematic: self.systematic,
+        }
+    }
+}
+
+impl<D, T, M, L> BinaryGibbsMetropolisBuilder<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    pub fn new(parameter: Parameter<D, T, M>, log_likelihood: L) -> Self {
+        BinaryGibbsMetropolisBuilder {
+            parameter,
+            log_likelihood,
+            systematic: false,
+        }
+    }
+
+    /// Visit the bits in index order each sweep instead of in a random
+    /// order.
+    pub fn systematic_scan(&self) -> Self {
+        BinaryGibbsMetropolisBuilder {
+            systematic: true,
+            ..(*self).clone()
+        }
+    }
+
+    pub fn build(&self) -> BinaryGibbsMetropolis<D, T, M, L> {
+        BinaryGibbsMetropolis {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            mh: util::MHCore::new(),
+            fixed: false,
+            systematic: self.systematic,
+            prior_cache: None,
+            events: None,
+        }
+    }
+}
+
+/// Stepper sweeping a binary parameter's bits, drawing each in turn from
+/// its full conditional
+///
+/// Each draw is carried out as a proposal to flip the bit, accepted with
+/// the probability of the flipped value under the full conditional, so
+/// the acceptance rate reported is the share of bits which moved. Unlike a
+/// Metropolis flip, a bit the posterior is indifferent to does not simply
+/// alternate between values.
+pub struct BinaryGibbsMetropolis<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    pub parameter: Parameter<D, T, M>,
+    pub log_likelihood: L,
+    pub mh: util::MHCore,
+    pub fixed: bool,
+    systematic: bool,
+    prior_cache: Option<util::PriorCache>,
+    events: Option<EventSink>,
+}
+
+impl<D, T, M, L> Clone for BinaryGibbsMetropolis<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        BinaryGibbsMetropolis {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            mh: self.mh,
+            fixed: self.fixed,
+            systematic: self.systematic,
+            prior_cache: self.prior_cache.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<D, T, M, L> fmt::Debug for BinaryGibbsMetropolis<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BinaryGibbsMetropolis {{ parameter: {:?}, systematic: {} }}",
+            self.parameter, self.systematic
+        )
+    }
+}
+
+impl<D, T, M, L, R> SteppingAlg<M, R> for BinaryGibbsMetropolis<D, T, M, L>
+where
+    D: Rv<T> + Clone,
+    T: Binary,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+    R: Rng,
+{
+    fn set_adapt(&mut self, _mode: AdaptationMode) {}
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        AdaptationStatus::Disabled
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        self.mh
+            .acceptance
+            .rate()
+            .map(StatisticValue::AcceptanceRate)
+            .into_iter()
+            .map(|value| Statistic::new(self.parameter.id().clone(), value))
+            .collect()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        vec![self.parameter.id().clone()]
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        vec![(self.parameter.id().clone(), self.parameter.dependencies.clone())]
+    }
+
+    fn reset(&mut self) {
+        self.mh.reset();
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.mh.invalidate();
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.fixed |= parameter == self.parameter.id();
+    }
+
+    fn set_prior_cache(&mut self, cache: util::PriorCache) {
+        self.prior_cache = Some(cache);
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        self.parameter.draw(&model, rng)
+    }
+
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        let id = self.parameter.id();
+        let mut value = match self.parameter.lens.try_get(&model) {
+            Ok(value) => value,
+            Err(err) => {
+                return util::reject_lens_failure(&self.events, id, &err, model)
+            }
+        };
+        let mut prior =
+            current_prior(&self.parameter, &self.prior_cache, &value);
+        let mut score = self.mh.current_score(|| {
+            self.log_likelihood.ln_f(&model) + prior
+        });
+        self.mh.current_score = Some(score);
+
+        let mut order: Vec<usize> = (0..value.n_bits()).collect();
+        if !self.systematic {
+            order.shuffle(rng);
+        }
+        let mut model = model;
+        for i in order {
+            let mut proposed_value = value.clone();
+            proposed_value.flip(i);
+            let new_model = match self
+                .parameter
+                .lens
+                .try_set(&model, proposed_value.clone())
+            {
+                Ok(new_model) => new_model,
+                Err(err) => {
+                    let events = &self.events;
+                    return util::reject_lens_failure(events, id, &err, model);
+                }
+            };
+            let proposed_prior = self.parameter.prior.ln_f(&proposed_value);
+            let new_score = util::proposal_score(
+                &self.log_likelihood,
+                id,
+                &model,
+                score,
+                || prior,
+                &new_model,
+                proposed_prior,
+            );
+            let scores = util::ProposalScores::new(score, new_score);
+            let scores = util::ProposalScores {
+                log_alpha: log_move_probability(scores.log_alpha),
+                ..scores
+            };
+            let update =
+                util::metropolis_select(rng, scores, proposed_value, value);
+            self.mh.record(&update);
+            if let Some(ref events) = self.events {
+                events.emit_update(id, &update);
+                events.emit_scores(id, &update, || prior, proposed_prior);
+            }
+            match update {
+                util::MetroplisUpdate::Accepted(accepted, _) => {
+                    value = accepted;
+                    prior = proposed_prior;
+                    score = new_score;
+                    model = new_model;
+                }
+                util::MetroplisUpdate::Rejected(rejected, _) => {
+                    value = rejected;
+                }
+            }
+        }
+        if let Some(ref cache) = self.prior_cache {
+            cache.insert(id.clone(), prior);
+        }
+        model
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use runner::Runner;
+    use rv::dist::{Bernoulli, Gaussian};
+    use utils::{multiple_tries, MultiRv};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    const N_TRIES: usize = 10;
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Clone, Debug)]
+    struct Model {
+        z: Vec<bool>,
+    }
+
+    type Indicators = Parameter<MultiRv<bool, Bernoulli>, Vec<bool>, Model>;
+
+    fn indicators(dims: usize) -> Indicators {
+        Parameter::new(
+            "z".to_string(),
+            MultiRv::new(dims, Bernoulli::new(0.5).unwrap()),
+            make_lens_clone!(Model, Vec<bool>, z),
+        )
+    }
+
+    /// Share of draws in which each bit is set
+    fn frequencies(draws: &[Model]) -> Vec<f64> {
+        let n = draws.len() as f64;
+        (0..draws[0].z.len())
+            .map(|i| draws.iter().filter(|m| m.z[i]).count() as f64 / n)
+            .collect()
+    }
+
+    fn sigmoid(x: f64) -> f64 {
+        1.0 / (1.0 + (-x).exp())
+    }
+
+    /// Independent bits with known marginals: bit `i` is set with
+    /// probability `sigmoid(WEIGHTS[i])`.
+    const WEIGHTS: [f64; 5] = [-2.0, -1.0, 0.0, 1.0, 2.0];
+
+    fn weighted(m: &Model) -> f64 {
+        m.z.iter()
+            .zip(WEIGHTS.iter())
+            .map(|(&z, w)| if z { *w } else { 0.0 })
+            .sum()
+    }
+
+    fn matches_marginals<A>(alg: A) -> bool
+    where
+        A: 'static + SteppingAlg<Model, StdRng> + Clone + Send + Sync,
+    {
+        let mut rng = StdRng::from_seed(SEED);
+        multiple_tries(N_TRIES, |_| {
+            let draws = Runner::new(alg.clone())
+                .warmup(500)
+                .samples(2000)
+                .thinning(2)
+                .run(&mut rng, Model { z: vec![false; 5] });
+            let freqs = frequencies(&draws[0]);
+            println!("frequencies = {:?}", freqs);
+            freqs
+                .iter()
+                .zip(WEIGHTS.iter())
+                .all(|(f, &w)| (f - sigmoid(w)).abs() < 0.05)
+        })
+    }
+
+    #[test]
+    fn metropolis_matches_marginals() {
+        let alg = BinaryMetropolisBuilder::new(indicators(5), weighted)
+            .build()
+            .unwrap();
+        assert!(matches_marginals(alg));
+    }
+
+    #[test]
+    fn gibbs_metropolis_matches_marginals() {
+        let builder =
+            BinaryGibbsMetropolisBuilder::new(indicators(5), weighted);
+        assert!(matches_marginals(builder.build()));
+        assert!(matches_marginals(builder.systematic_scan().build()));
+    }
+
+    #[test]
+    fn mixture_indicators_find_their_components() {
+        let mut rng = StdRng::from_seed(SEED);
+        let dims = 30;
+        let g1 = Gaussian::new(0.0, 1.0).unwrap();
+        let g2 = Gaussian::new(3.0, 0.9).unwrap();
+
+        let passed = multiple_tries(N_TRIES, |_| {
+            // Data from a mixture of two Gaussians, a quarter from g2
+            let p: f64 = 0.75;
+            let samples: Vec<f64> = (0..dims)
+                .map(|_| {
+                    if rng.gen::<f64>() < p {
+                        g1.draw(&mut rng)
+                    } else {
+                        g2.draw(&mut rng)
+                    }
+                })
+                .collect();
+            let (g1, g2) = (g1.clone(), g2.clone());
+            let log_likelihood = move |m: &Model| -> f64 {
+                m.z.iter()
+                    .zip(samples.iter())
+                    .map(|(&a, y)| if a { g1.ln_f(y) } else { g2.ln_f(y) })
+                    .sum()
+            };
+
+            let alg = BinaryMetropolisBuilder::new(
+                indicators(dims),
+                log_likelihood,
+            ).build()
+            .unwrap();
+            let init = Model { z: vec![false; dims] };
+            let draws = Runner::new(alg).chains(1).run(&mut rng, init);
+
+            let mut inferred_p: Vec<f64> = draws[0]
+                .iter()
+                .map(|m| m.z.iter().filter(|&&a| a).count() as f64)
+                .map(|count| count / dims as f64)
+                .collect();
+            inferred_p.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let (iqr_l, iqr_u) = (inferred_p[250], inferred_p[750]);
+            p > iqr_l && p < iqr_u
+        });
+        assert!(passed);
+    }
+
+    #[test]
+    fn scalar_indicator_follows_its_prior() {
+        #[derive(Clone, Debug)]
+        struct Spike {
+            included: bool,
+        }
+        let included = Parameter::new(
+            "included".to_string(),
+            Bernoulli::new(0.3).unwrap(),
+            make_lens!(Spike, bool, included),
+        );
+        let mut alg = BinaryGibbsMetropolisBuilder::new(
+            included,
+            |_: &Spike| 0.0,
+        ).build();
+
+        let mut rng = StdRng::from_seed(SEED);
+        let mut m = Spike { included: false };
+        let n = 20_000;
+        let set = (0..n)
+            .filter(|_| {
+                m = alg.step(&mut rng, m.clone());
+                m.included
+            })
+            .count();
+        assert!((set as f64 / n as f64 - 0.3).abs() < 0.02);
+    }
+
+    #[test]
+    fn cached_score_is_the_current_models() {
+        let log_likelihood =
+            |m: &Model| m.z.iter().filter(|&&a| a).count() as f64;
+        let mut alg =
+            BinaryMetropolisBuilder::new(indicators(5), log_likelihood)
+                .build()
+                .unwrap();
+        let prior = alg.parameter.prior.clone();
+
+        let mut rng = StdRng::from_seed(SEED);
+        let mut m = Model { z: vec![false; 5] };
+        for _ in 0..50 {
+            m = alg.step(&mut rng, m);
+            let score = log_likelihood(&m) + prior.ln_f(&m.z);
+            assert!((alg.mh.current_score.unwrap() - score).abs() < 1E-12);
+        }
+        SteppingAlg::<Model, StdRng>::reset(&mut alg);
+        assert_eq!(alg.mh.current_score, None);
+    }
+
+    #[test]
+    fn builder_rejects_invalid_settings() {
+        let builder = BinaryMetropolisBuilder::new(indicators(3), weighted);
+        let err = builder.scale(0.0).build().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(builder.target_acceptance(1.0).build().is_err());
+
+        let mut fixed = builder.scale(0.5).fixed_scale().build().unwrap();
+        SteppingAlg::<Model, StdRng>::set_adapt(
+            &mut fixed,
+            AdaptationMode::Enabled,
+        );
+        match SteppingAlg::<Model, StdRng>::get_adapt(&fixed) {
+            AdaptationStatus::Disabled => {}
+            _ => panic!("a fixed scale must not adapt"),
+        }
+        let p = fixed.flip_probability();
+        assert!((p - flip_probability(0.5)).abs() < 1E-12);
+    }
+}
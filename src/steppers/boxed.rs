@@ -0,0 +1,239 @@
+//! Steppers with their types erased
+//!
+//! A stepper's type names its parameter, prior and likelihood, so steppers
+//! of different parameters cannot share a `Vec` or a struct field as they
+//! are. A `BoxedStepper` hides the type while staying cloneable and safe to
+//! send between threads, so it can still be handed to a `Runner`.
+
+use std::fmt;
+use std::io;
+use rand::Rng;
+use events::EventSink;
+use parameter::ParamId;
+use statistics::Statistic;
+use steppers::{AdaptationMode, AdaptationStatus, SteppingAlg, util};
+use steppers::adaptor::AdaptorState;
+use steppers::spec::StepperSpec;
+
+/// A stepper which can be cloned behind a box
+trait CloneStepper<M, R: Rng>: SteppingAlg<M, R> + Send + Sync {
+    fn clone_box(&self) -> Box<dyn CloneStepper<M, R>>;
+}
+
+impl<M, R, A> CloneStepper<M, R> for A
+where
+    R: Rng,
+    A: 'static + SteppingAlg<M, R> + Clone + Send + Sync,
+{
+    fn clone_box(&self) -> Box<dyn CloneStepper<M, R>> {
+        Box::new(self.clone())
+    }
+}
+
+/// Any cloneable stepper of `M`, behind a box
+///
+/// # Example
+/// ```
+/// # #[macro_use] extern crate rmcmc;
+/// # extern crate rand;
+/// # extern crate rv;
+/// # use rmcmc::lens::*;
+/// # use rmcmc::parameter::Parameter;
+/// # use rmcmc::steppers::{BoxedStepper, IntoBoxedStepper};
+/// # use rmcmc::steppers::{SteppingAlg, SRWM};
+/// # use rand::rngs::StdRng;
+/// # use rv::dist::Gaussian;
+/// # fn main() {
+/// #[derive(Clone, Copy, Debug)]
+/// struct Model {
+///     a: f64,
+///     b: f64,
+/// }
+///
+/// let a = Parameter::new(
+///     "a".to_string(),
+///     Gaussian::new(0.0, 1.0).unwrap(),
+///     make_lens!(Model, f64, a),
+/// );
+/// let b = Parameter::new(
+///     "b".to_string(),
+///     Gaussian::new(0.0, 1.0).unwrap(),
+///     make_lens!(Model, f64, b),
+/// );
+/// let ids = vec![a.id().clone(), b.id().clone()];
+///
+/// // Two steppers of different types, as their likelihoods differ
+/// let steppers: Vec<BoxedStepper<Model, StdRng>> = vec![
+///     SRWM::new(a, |m: &Model| -m.a * m.a, None).unwrap().boxed(),
+///     SRWM::new(b, |m: &Model| -m.b.abs(), None).unwrap().boxed(),
+/// ];
+/// let cloned = steppers.clone();
+/// assert_eq!(cloned[0].parameters(), vec![ids[0].clone()]);
+/// assert_eq!(cloned[1].parameters(), vec![ids[1].clone()]);
+/// # }
+/// ```
+pub struct BoxedStepper<M, R: Rng> {
+    stepper: Box<dyn CloneStepper<M, R>>,
+}
+
+impl<M, R: Rng> BoxedStepper<M, R> {
+    pub fn new<A>(stepper: A) -> Self
+    where
+        A: 'static + SteppingAlg<M, R> + Clone + Send + Sync,
+    {
+        BoxedStepper {
+            stepper: Box::new(stepper),
+        }
+    }
+}
+
+/// Conversion of any cloneable stepper into a `BoxedStepper`
+pub trait IntoBoxedStepper<M, R: Rng> {
+    fn boxed(self) -> BoxedStepper<M, R>;
+}
+
+impl<M, R, A> IntoBoxedStepper<M, R> for A
+where
+    R: Rng,
+    A: 'static + SteppingAlg<M, R> + Clone + Send + Sync,
+{
+    fn boxed(self) -> BoxedStepper<M, R> {
+        BoxedStepper::new(self)
+    }
+}
+
+impl<M, R: Rng> Clone for BoxedStepper<M, R> {
+    fn clone(&self) -> Self {
+        BoxedStepper {
+            stepper: self.stepper.clone_box(),
+        }
+    }
+}
+
+impl<M, R: Rng> fmt::Debug for BoxedStepper<M, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.stepper.fmt(f)
+    }
+}
+
+impl<M, R: Rng> SteppingAlg<M, R> for BoxedStepper<M, R> {
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        self.stepper.step(rng, model)
+    }
+
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        self.stepper.set_adapt(mode)
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        self.stepper.get_adapt()
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        self.stepper.get_statistics()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        self.stepper.parameters()
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        self.stepper.dependencies()
+    }
+
+    fn reset(&mut self) {
+        self.stepper.reset()
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.stepper.invalidate_cache()
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.stepper.fix(parameter)
+    }
+
+    fn set_prior_cache(&mut self, cache: util::PriorCache) {
+        self.stepper.set_prior_cache(cache)
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.stepper.set_event_sink(sink)
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        self.stepper.draw_prior(rng, model)
+    }
+
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        self.stepper.adaptation_state()
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        self.stepper.restore_adaptation(states)
+    }
+
+    fn spec(&self) -> Option<StepperSpec> {
+        self.stepper.spec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use runner::Runner;
+    use rv::dist::Gaussian;
+    use steppers::SRWM;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Model {
+        a: f64,
+    }
+
+    fn flat(_m: &Model) -> f64 {
+        0.0
+    }
+
+    #[test]
+    fn clones_step_independently_across_threads() {
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let center = 3.0;
+        let srwm = SRWM::new(a.clone(), move |m: &Model| {
+            -0.5 * (m.a - center) * (m.a - center)
+        }, Some(1.0)).unwrap();
+        let prior_only = SRWM::new(a.clone(), flat, None).unwrap();
+        let steppers: Vec<BoxedStepper<Model, StdRng>> =
+            vec![srwm.boxed(), prior_only.boxed()];
+        assert_eq!(steppers[1].parameters(), vec![a.id().clone()]);
+
+        let mut rng = StdRng::from_seed(SEED);
+        let draws = Runner::new(steppers[0].clone())
+            .warmup(100)
+            .samples(500)
+            .chains(2)
+            .run(&mut rng, Model { a: 0.0 });
+        // The posterior is Gaussian with mean 1.5.
+        for chain in draws.iter() {
+            let mean: f64 = chain.iter().map(|m| m.a).sum::<f64>() / 500.0;
+            assert!((mean - 1.5).abs() < 0.3);
+        }
+        // The runner stepped clones, leaving the original untouched.
+        match steppers[0].get_adapt() {
+            AdaptationStatus::Disabled => (),
+            status => panic!("unexpected adaptation status {:?}", status),
+        }
+    }
+}
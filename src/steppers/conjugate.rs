@@ -0,0 +1,241 @@
+//! # Conjugate Gibbs
+//! Draws a parameter exactly from its conjugate posterior given sufficient
+//! statistics of the data.
+
+use std::fmt;
+use std::marker::PhantomData;
+use rand::Rng;
+
+use rv::data::DataOrSuffStat;
+use rv::traits::{ConjugatePrior, HasSuffStat, Rv};
+use parameter::{Parameter, ParamId};
+
+use steppers::{SteppingAlg, AdaptationStatus, AdaptationMode, util};
+use steppers::util::PriorCache;
+use statistics::Statistic;
+use events::{Event, EventSink};
+
+/// Gibbs stepper for a parameter whose prior is conjugate to the data model.
+///
+/// The parameter's value is the data model itself (e.g. a `Gaussian` under a
+/// `NormalGamma` prior). Each step the sufficient statistic of the data
+/// attributed to the parameter is requested from `suffstat` and the new value
+/// is drawn from the analytic posterior, so no loop over the data is needed
+/// when the statistic is maintained or precomputed by the caller.
+///
+/// # Parameters
+/// `Pr`: The conjugate prior
+/// `X`: The type of a datum
+/// `Fx`: The data model (the parameter's type)
+/// `M`: The model type
+/// `S`: The sufficient statistic function
+pub struct ConjugateGibbs<Pr, X, Fx, M, S>
+where
+    Pr: ConjugatePrior<X, Fx> + Clone,
+    Fx: Rv<X> + HasSuffStat<X>,
+    M: 'static + Clone,
+    S: Fn(&M) -> Fx::Stat + Clone + Sync,
+{
+    pub parameter: Parameter<Pr, Fx, M>,
+    pub suffstat: S,
+    pub fixed: bool,
+    prior_cache: Option<PriorCache>,
+    events: Option<EventSink>,
+    phantom_x: PhantomData<X>,
+}
+
+impl<Pr, X, Fx, M, S> ConjugateGibbs<Pr, X, Fx, M, S>
+where
+    Pr: ConjugatePrior<X, Fx> + Clone,
+    Fx: Rv<X> + HasSuffStat<X>,
+    M: 'static + Clone,
+    S: Fn(&M) -> Fx::Stat + Clone + Sync,
+{
+    pub fn new(parameter: Parameter<Pr, Fx, M>, suffstat: S) -> Self {
+        ConjugateGibbs {
+            parameter,
+            suffstat,
+            fixed: false,
+            prior_cache: None,
+            events: None,
+            phantom_x: PhantomData,
+        }
+    }
+
+    /// The posterior of the parameter given the model's current state.
+    pub fn posterior(&self, model: &M) -> Pr::Posterior {
+        let stat = (self.suffstat)(model);
+        self.parameter
+            .prior
+            .posterior(&DataOrSuffStat::SuffStat(&stat))
+    }
+}
+
+impl<Pr, X, Fx, M, S> Clone for ConjugateGibbs<Pr, X, Fx, M, S>
+where
+    Pr: ConjugatePrior<X, Fx> + Clone,
+    Fx: Rv<X> + HasSuffStat<X>,
+    M: 'static + Clone,
+    S: Fn(&M) -> Fx::Stat + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        ConjugateGibbs {
+            parameter: self.parameter.clone(),
+            suffstat: self.suffstat.clone(),
+            fixed: self.fixed,
+            prior_cache: self.prior_cache.clone(),
+            events: self.events.clone(),
+            phantom_x: PhantomData,
+        }
+    }
+}
+
+impl<Pr, X, Fx, M, S> fmt::Debug for ConjugateGibbs<Pr, X, Fx, M, S>
+where
+    Pr: ConjugatePrior<X, Fx> + Clone,
+    Fx: Rv<X> + HasSuffStat<X>,
+    M: 'static + Clone,
+    S: Fn(&M) -> Fx::Stat + Clone + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ConjugateGibbs {{ parameter: {:?} }}", self.parameter)
+    }
+}
+
+impl<Pr, X, Fx, M, S, R> SteppingAlg<M, R> for ConjugateGibbs<Pr, X, Fx, M, S>
+where
+    Pr: ConjugatePrior<X, Fx> + Clone,
+    Fx: Rv<X> + HasSuffStat<X>,
+    M: 'static + Clone,
+    S: Fn(&M) -> Fx::Stat + Clone + Sync,
+    R: Rng,
+{
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        let new_value = self.posterior(&model).draw(rng);
+        let new_model = match self.parameter.lens.try_set(&model, new_value) {
+            Ok(new_model) => new_model,
+            Err(err) => {
+                let id = self.parameter.id();
+                return util::reject_lens_failure(&self.events, id, &err, model);
+            }
+        };
+        if let Some(ref cache) = self.prior_cache {
+            cache.remove(self.parameter.id());
+        }
+        // Gibbs draws are proposals which are always accepted.
+        if let Some(ref events) = self.events {
+            events.emit(Event::ProposalAccepted {
+                parameter: self.parameter.id().clone(),
+                log_alpha: 0.0,
+            });
+        }
+        new_model
+    }
+
+    fn set_adapt(&mut self, _mode: AdaptationMode) {}
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        AdaptationStatus::Disabled
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        Vec::new()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        vec![self.parameter.id().clone()]
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        vec![(self.parameter.id().clone(), self.parameter.dependencies.clone())]
+    }
+
+    fn reset(&mut self) {}
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.fixed |= parameter == self.parameter.id();
+    }
+
+    fn set_prior_cache(&mut self, cache: PriorCache) {
+        self.prior_cache = Some(cache);
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        self.parameter.draw(&model, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use lens::*;
+    use runner::Runner;
+    use rv::data::BernoulliSuffStat;
+    use rv::dist::*;
+    use rv::traits::{Mean, SuffStat, Variance};
+    use utils::multiple_tries;
+    use rand::SeedableRng;
+
+    const N_TRIES: usize = 10;
+    const SEED: [u8; 32] = [0; 32];
+
+    #[test]
+    fn beta_bernoulli_posterior() {
+        #[derive(Clone, Debug)]
+        struct Model {
+            coin: Bernoulli,
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+
+        let parameter = Parameter::new(
+            "coin".to_string(),
+            Beta::new(2.0, 3.0).unwrap(),
+            make_lens_clone!(Model, Bernoulli, coin),
+        );
+
+        let data: Vec<bool> = Bernoulli::new(0.3).unwrap().sample(50, &mut rng);
+        let mut stat = BernoulliSuffStat::new();
+        stat.observe_many(&data);
+        let expected = Beta::new(
+            2.0 + stat.k as f64,
+            3.0 + (stat.n - stat.k) as f64,
+        ).unwrap();
+
+        let alg: ConjugateGibbs<_, bool, _, _, _> =
+            ConjugateGibbs::new(parameter, move |_: &Model| stat.clone());
+
+        let passed = multiple_tries(N_TRIES, |_| {
+            let m = Model { coin: Bernoulli::new(0.5).unwrap() };
+            let results: Vec<Vec<Model>> = Runner::new(alg.clone())
+                .warmup(0)
+                .chains(1)
+                .run(&mut rng, m);
+
+            let samples: Vec<f64> = results
+                .iter()
+                .flat_map(|chain| chain.iter().map(|g| g.coin.p))
+                .collect();
+
+            // Draws are independent, so the sample mean's error is known.
+            let n = samples.len() as f64;
+            let mean: f64 = samples.iter().sum::<f64>() / n;
+            let expected_mean: f64 = expected.mean().unwrap();
+            let expected_var: f64 = expected.variance().unwrap();
+            println!("mean = {}, expected = {}", mean, expected_mean);
+            (mean - expected_mean).abs() < 3.0 * (expected_var / n).sqrt()
+        });
+        assert!(passed);
+    }
+}
@@ -0,0 +1,135 @@
+//! Cross-correlations of parameters updated by separate steppers
+//!
+//! Updating strongly correlated parameters one at a time mixes slowly, as
+//! each stepper can only move along its own axis of a narrow ridge. A
+//! `CorrelationMonitor` tracks the posterior correlations of scalar
+//! parameters during warmup and recommends which to update jointly.
+
+use std::fmt;
+use nalgebra::{DMatrix, DVector};
+use parameter::ParamId;
+
+/// Running correlations of scalar parameters, from Welford's update
+#[derive(Clone)]
+pub struct CorrelationMonitor<M> {
+    parameters: Vec<(ParamId, fn(&M) -> f64)>,
+    n: usize,
+    means: DVector<f64>,
+    // Sums of products of deviations from the running means
+    comoments: DMatrix<f64>,
+}
+
+impl<M> CorrelationMonitor<M> {
+    /// Track the parameters with the given ids, each read from the model
+    /// by its function.
+    pub fn new(parameters: Vec<(ParamId, fn(&M) -> f64)>) -> Self {
+        let dim = parameters.len();
+        CorrelationMonitor {
+            parameters,
+            n: 0,
+            means: DVector::zeros(dim),
+            comoments: DMatrix::zeros(dim, dim),
+        }
+    }
+
+    /// Add the parameters' values in `model`.
+    pub fn observe(&mut self, model: &M) {
+        let values = DVector::from_iterator(
+            self.parameters.len(),
+            self.parameters.iter().map(|(_, value)| value(model)),
+        );
+        if !values.iter().all(|x| x.is_finite()) {
+            return;
+        }
+        self.n += 1;
+        let before = &values - &self.means;
+        self.means += &before / self.n as f64;
+        let after = &values - &self.means;
+        self.comoments += &before * after.transpose();
+    }
+
+    /// Number of models observed
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    /// Forget every observation.
+    pub fn reset(&mut self) {
+        let dim = self.parameters.len();
+        self.n = 0;
+        self.means = DVector::zeros(dim);
+        self.comoments = DMatrix::zeros(dim, dim);
+    }
+
+    fn index(&self, parameter: &ParamId) -> Option<usize> {
+        self.parameters.iter().position(|(id, _)| id == parameter)
+    }
+
+    fn correlation_at(&self, i: usize, j: usize) -> Option<f64> {
+        let spread = (self.comoments[(i, i)] * self.comoments[(j, j)]).sqrt();
+        if self.n < 2 || !(spread > 0.0) {
+            None
+        } else {
+            Some(self.comoments[(i, j)] / spread)
+        }
+    }
+
+    /// Correlation of two tracked parameters, if both have varied
+    pub fn correlation(&self, a: &ParamId, b: &ParamId) -> Option<f64> {
+        self.correlation_at(self.index(a)?, self.index(b)?)
+    }
+
+    /// Groups of parameters linked by correlations of at least `threshold`
+    /// in magnitude, which are better updated by a joint stepper, e.g. a
+    /// `VectorSRWM` over a vector of them.
+    ///
+    /// Parameters correlated with no other are left out, and the groups and
+    /// their members are in the order the parameters are tracked.
+    pub fn blocks(&self, threshold: f64) -> Vec<Vec<ParamId>> {
+        let dim = self.parameters.len();
+        // Label each parameter with the smallest index of its group.
+        let mut labels: Vec<usize> = (0..dim).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..dim {
+                for j in (i + 1)..dim {
+                    let linked = self
+                        .correlation_at(i, j)
+                        .map_or(false, |r| r.abs() >= threshold);
+                    if linked && labels[i] != labels[j] {
+                        let label = labels[i].min(labels[j]);
+                        labels[i] = label;
+                        labels[j] = label;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        (0..dim)
+            .filter_map(|label| {
+                let block: Vec<ParamId> = (0..dim)
+                    .filter(|&i| labels[i] == label)
+                    .map(|i| self.parameters[i].0.clone())
+                    .collect();
+                if block.len() > 1 {
+                    Some(block)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+impl<M> fmt::Debug for CorrelationMonitor<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ids: Vec<&ParamId> =
+            self.parameters.iter().map(|(id, _)| id).collect();
+        write!(
+            f,
+            "CorrelationMonitor {{ parameters: {:?}, n: {} }}",
+            ids, self.n
+        )
+    }
+}
@@ -0,0 +1,388 @@
+//! # Gibbs
+//! Draws a parameter exactly from a full conditional distribution supplied
+//! by the caller.
+
+use std::fmt;
+use std::marker::PhantomData;
+use rand::Rng;
+
+use rv::traits::Rv;
+use parameter::{Parameter, ParamId};
+
+use steppers::{SteppingAlg, AdaptationStatus, AdaptationMode, util};
+use steppers::util::PriorCache;
+use statistics::Statistic;
+use events::{Event, EventSink};
+
+/// Configuration of a `Gibbs` stepper
+///
+/// # Example
+/// ```
+/// # #[macro_use] extern crate rmcmc;
+/// # extern crate rand;
+/// # extern crate rv;
+/// # use rmcmc::lens::*;
+/// # use rmcmc::parameter::Parameter;
+/// # use rmcmc::runner::Runner;
+/// # use rmcmc::steppers::GibbsBuilder;
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # use rv::dist::Gaussian;
+/// # fn main() {
+/// #[derive(Clone, Debug)]
+/// struct Model {
+///     mu: f64,
+///     data_sum: f64,
+///     data_len: usize,
+/// }
+///
+/// // A standard normal prior on the mean of unit variance data
+/// let mu = Parameter::new(
+///     "mu".to_string(),
+///     Gaussian::new(0.0, 1.0).unwrap(),
+///     make_lens!(Model, f64, mu),
+/// );
+/// let gibbs = GibbsBuilder::new(mu, |m: &Model| {
+///     let precision = 1.0 + m.data_len as f64;
+///     Gaussian::new(m.data_sum / precision, precision.recip().sqrt())
+///         .unwrap()
+/// })
+/// .check_support()
+/// .build();
+///
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let init = Model { mu: 0.0, data_sum: 30.0, data_len: 9 };
+/// let draws = Runner::new(gibbs).warmup(0).samples(100).run(&mut rng, init);
+/// assert_eq!(draws[0].len(), 100);
+/// # }
+/// ```
+pub struct GibbsBuilder<D, T, M, C, F>
+where
+    D: Rv<T> + Clone,
+    M: 'static + Clone,
+    C: Rv<T>,
+    F: Fn(&M) -> C + Clone + Sync,
+{
+    parameter: Parameter<D, T, M>,
+    conditional: F,
+    check_support: bool,
+    phantom_c: PhantomData<C>,
+}
+
+impl<D, T, M, C, F> Clone for GibbsBuilder<D, T, M, C, F>
+where
+    D: Rv<T> + Clone,
+    M: 'static + Clone,
+    C: Rv<T>,
+    F: Fn(&M) -> C + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        GibbsBuilder {
+            parameter: self.parameter.clone(),
+            conditional: self.conditional.clone(),
+            check_support: self.check_support,
+            phantom_c: PhantomData,
+        }
+    }
+}
+
+impl<D, T, M, C, F> GibbsBuilder<D, T, M, C, F>
+where
+    D: Rv<T> + Clone,
+    M: 'static + Clone,
+    C: Rv<T>,
+    F: Fn(&M) -> C + Clone + Sync,
+{
+    /// Gibbs stepper for `parameter` drawing from `conditional(model)`, its
+    /// distribution given every other value of the model
+    pub fn new(parameter: Parameter<D, T, M>, conditional: F) -> Self {
+        GibbsBuilder {
+            parameter,
+            conditional,
+            check_support: false,
+            phantom_c: PhantomData,
+        }
+    }
+
+    /// Reject draws outside the support of the parameter's prior with a
+    /// numerical warning, guarding against conditionals derived wrongly.
+    /// Costs an evaluation of the prior each step.
+    pub fn check_support(&self) -> Self {
+        GibbsBuilder {
+            check_support: true,
+            ..(*self).clone()
+        }
+    }
+
+    pub fn build(&self) -> Gibbs<D, T, M, C, F> {
+        Gibbs {
+            parameter: self.parameter.clone(),
+            conditional: self.conditional.clone(),
+            check_support: self.check_support,
+            fixed: false,
+            prior_cache: None,
+            events: None,
+            phantom_c: PhantomData,
+        }
+    }
+}
+
+/// Gibbs stepper for a parameter with a known full conditional
+///
+/// Each step replaces the parameter's value with a draw from the
+/// conditional the stepper was built with, evaluated at the current model.
+/// Draws are always accepted, so within a `Group` of other steppers the
+/// conditional must be that of the posterior the group targets.
+///
+/// # Parameters
+/// `D`: The parameter's prior
+/// `T`: The parameter's type
+/// `M`: The model type
+/// `C`: The conditional distribution
+/// `F`: The function from a model to its conditional
+pub struct Gibbs<D, T, M, C, F>
+where
+    D: Rv<T> + Clone,
+    M: 'static + Clone,
+    C: Rv<T>,
+    F: Fn(&M) -> C + Clone + Sync,
+{
+    pub parameter: Parameter<D, T, M>,
+    pub conditional: F,
+    pub fixed: bool,
+    check_support: bool,
+    prior_cache: Option<PriorCache>,
+    events: Option<EventSink>,
+    phantom_c: PhantomData<C>,
+}
+
+impl<D, T, M, C, F> Clone for Gibbs<D, T, M, C, F>
+where
+    D: Rv<T> + Clone,
+    M: 'static + Clone,
+    C: Rv<T>,
+    F: Fn(&M) -> C + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        Gibbs {
+            parameter: self.parameter.clone(),
+            conditional: self.conditional.clone(),
+            fixed: self.fixed,
+            check_support: self.check_support,
+            prior_cache: self.prior_cache.clone(),
+            events: self.events.clone(),
+            phantom_c: PhantomData,
+        }
+    }
+}
+
+impl<D, T, M, C, F> fmt::Debug for Gibbs<D, T, M, C, F>
+where
+    D: Rv<T> + Clone,
+    M: 'static + Clone,
+    C: Rv<T>,
+    F: Fn(&M) -> C + Clone + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Gibbs {{ parameter: {:?} }}", self.parameter)
+    }
+}
+
+impl<D, T, M, C, F, R> SteppingAlg<M, R> for Gibbs<D, T, M, C, F>
+where
+    D: Rv<T> + Clone,
+    M: 'static + Clone,
+    C: Rv<T>,
+    F: Fn(&M) -> C + Clone + Sync,
+    R: Rng,
+{
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        let id = self.parameter.id();
+        let new_value = (self.conditional)(&model).draw(rng);
+        let prior = if self.check_support {
+            let prior = self.parameter.prior.ln_f(&new_value);
+            if !prior.is_finite() {
+                if let Some(ref events) = self.events {
+                    events.emit(Event::NumericalWarning {
+                        parameter: id.clone(),
+                        message: "conditional draw outside the prior's \
+                                  support"
+                            .to_string(),
+                    });
+                }
+                return model;
+            }
+            Some(prior)
+        } else {
+            None
+        };
+        let new_model = match self.parameter.lens.try_set(&model, new_value) {
+            Ok(new_model) => new_model,
+            Err(err) => {
+                return util::reject_lens_failure(&self.events, id, &err, model)
+            }
+        };
+        if let Some(ref cache) = self.prior_cache {
+            match prior {
+                Some(prior) => cache.insert(id.clone(), prior),
+                None => cache.remove(id),
+            }
+        }
+        // Gibbs draws are proposals which are always accepted.
+        if let Some(ref events) = self.events {
+            events.emit(Event::ProposalAccepted {
+                parameter: id.clone(),
+                log_alpha: 0.0,
+            });
+        }
+        new_model
+    }
+
+    fn set_adapt(&mut self, _mode: AdaptationMode) {}
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        AdaptationStatus::Disabled
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        Vec::new()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        vec![self.parameter.id().clone()]
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        vec![(self.parameter.id().clone(), self.parameter.dependencies.clone())]
+    }
+
+    fn reset(&mut self) {}
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.fixed |= parameter == self.parameter.id();
+    }
+
+    fn set_prior_cache(&mut self, cache: PriorCache) {
+        self.prior_cache = Some(cache);
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        self.parameter.draw(&model, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use steppers::Group;
+    use rv::dist::{Gaussian, Uniform};
+    use rv::misc::ks_test;
+    use rv::prelude::Cdf;
+    use utils::multiple_tries;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    const P_VAL: f64 = 0.2;
+    const N_TRIES: usize = 10;
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Clone, Copy, Debug)]
+    struct Model {
+        x: f64,
+        y: f64,
+    }
+
+    #[test]
+    fn group_of_conditionals_samples_the_joint() {
+        // A bivariate standard normal with correlation RHO
+        const RHO: f64 = 0.8;
+        fn conditional(other: f64) -> Gaussian {
+            Gaussian::new(RHO * other, (1.0 - RHO * RHO).sqrt()).unwrap()
+        }
+        let x = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let y = Parameter::new(
+            "y".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, y),
+        );
+        let gibbs_x = GibbsBuilder::new(x, |m: &Model| conditional(m.y));
+        let gibbs_y = GibbsBuilder::new(y, |m: &Model| conditional(m.x));
+
+        let mut rng = StdRng::from_seed(SEED);
+        let passed = multiple_tries(N_TRIES, |_| {
+            let mut group: Group<Model, StdRng> = Group::new(vec![
+                Box::new(gibbs_x.build()),
+                Box::new(gibbs_y.build()),
+            ]);
+            let mut m = Model { x: 3.0, y: -3.0 };
+            let xs: Vec<f64> = (0..5000)
+                .filter_map(|i| {
+                    m = group.step(&mut rng, m);
+                    if i % 5 == 0 { Some(m.x) } else { None }
+                })
+                .collect();
+            let standard = Gaussian::new(0.0, 1.0).unwrap();
+            let (stat, p) = ks_test(&xs, |s| standard.cdf(&s));
+            println!("test stat = {}, p = {}", stat, p);
+            p > P_VAL
+        });
+        assert!(passed);
+    }
+
+    #[test]
+    fn draws_outside_the_support_are_rejected() {
+        let x = Parameter::new(
+            "x".to_string(),
+            Uniform::new(0.0, 10.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        // Wrong for a positive parameter: half the draws are negative
+        let builder =
+            GibbsBuilder::new(x, |_: &Model| Gaussian::new(0.0, 1.0).unwrap());
+        let mut rng = StdRng::from_seed(SEED);
+        let init = Model { x: 1.0, y: 0.0 };
+
+        let mut unchecked = builder.build();
+        let negative = (0..100)
+            .map(|_| unchecked.step(&mut rng, init).x)
+            .filter(|&x| x < 0.0)
+            .count();
+        assert!(negative > 0);
+
+        let mut checked = builder.check_support().build();
+        let sink = EventSink::new();
+        SteppingAlg::<Model, StdRng>::set_event_sink(
+            &mut checked,
+            sink.clone(),
+        );
+        let mut m = init;
+        for _ in 0..100 {
+            m = checked.step(&mut rng, m);
+            assert!(m.x > 0.0);
+        }
+        let warnings = sink
+            .drain()
+            .iter()
+            .filter(|e| match e {
+                Event::NumericalWarning { .. } => true,
+                _ => false,
+            })
+            .count();
+        assert!(warnings > 0 && warnings < 100);
+    }
+}
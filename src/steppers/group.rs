@@ -1,16 +1,36 @@
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeSet, HashMap};
+use std::io;
 use std::marker::PhantomData;
 use steppers::{SteppingAlg, AdaptationStatus, AdaptationMode};
+use steppers::util::PriorCache;
+use steppers::adaptor::AdaptorState;
+use steppers::correlation::CorrelationMonitor;
+use steppers::tempering::{Tempering, TemperingStepper};
 use reduce::Reduce;
 use statistics::Statistic;
+use parameter::ParamId;
+use events::EventSink;
+use utils::invalid_input;
 use std::fmt;
 
 /// Stepper Group
-pub struct Group<M, R: Rng>
+///
+/// Sub-steppers are boxed as `S`, any stepper by default. A group made
+/// with `Group::tempered` holds steppers which can also be tempered, and
+/// tempers them together.
+pub struct Group<M, R: Rng, S: ?Sized = dyn SteppingAlg<M, R>>
 where
     M: Clone,
 {
-    steppers: Vec<Box<(dyn SteppingAlg<M, R> + 'static)>>,
+    steppers: Vec<Box<S>>,
+    // Shared by the sub-steppers when some parameter has more than one
+    prior_cache: Option<PriorCache>,
+    correlations: Option<CorrelationMonitor<M>>,
+    // Derives a generator for each key from the chain's generator, set when
+    // sub-steppers have streams of their own
+    split: Option<fn(&mut R, &[String]) -> Vec<R>>,
+    streams: Vec<R>,
     phantom_m: PhantomData<M>,
 }
 
@@ -19,17 +39,237 @@ where
     M: Clone,
 {
     pub fn new(steppers: Vec<Box<(dyn SteppingAlg<M, R> + 'static)>>) -> Self {
+        Group::with_steppers(steppers)
+    }
+}
+
+impl<M, R: Rng> Group<M, R, dyn TemperingStepper<M, R>>
+where
+    M: Clone,
+{
+    /// A group of steppers which can be tempered, setting the temperature
+    /// of every one of them when the group's is set
+    pub fn tempered(steppers: Vec<Box<dyn TemperingStepper<M, R>>>) -> Self {
+        Group::with_steppers(steppers)
+    }
+}
+
+impl<M, R, S> Group<M, R, S>
+where
+    M: Clone,
+    R: Rng,
+    S: ?Sized + SteppingAlg<M, R>,
+{
+    fn with_steppers(steppers: Vec<Box<S>>) -> Self {
+        let prior_cache = PriorCache::for_steppers(
+            steppers.iter().map(|s| s.parameters()),
+        );
+        let mut steppers = steppers;
+        if let Some(ref cache) = prior_cache {
+            steppers
+                .iter_mut()
+                .for_each(|s| s.set_prior_cache(cache.clone()));
+        }
         Group {
             steppers: steppers,
+            prior_cache,
+            correlations: None,
+            split: None,
+            streams: Vec::new(),
             phantom_m: PhantomData,
         }
     }
+
+    /// Reorder the sub-steppers into a systematic scan updating each
+    /// parameter after those its prior depends on, e.g. hyperparameters
+    /// before the parameters drawn from them. Steppers with no dependency
+    /// between them keep their relative order.
+    ///
+    /// The order follows the dependencies declared with
+    /// `Parameter::depends_on`, which are metadata only: priors are fixed
+    /// `Rv`s that never read the model.
+    ///
+    /// Fails with `InvalidInput` naming the parameters involved if the
+    /// dependencies between the sub-steppers are cyclic.
+    pub fn in_dependency_order(self) -> io::Result<Self> {
+        let parameters: Vec<BTreeSet<ParamId>> = self
+            .steppers
+            .iter()
+            .map(|s| s.parameters().into_iter().collect())
+            .collect();
+        let dependencies: Vec<BTreeSet<ParamId>> = self
+            .steppers
+            .iter()
+            .map(|s| {
+                s.dependencies().into_iter().flat_map(|(_, ds)| ds).collect()
+            })
+            .collect();
+        // Stepper i must come before stepper j if j's priors read any of
+        // i's parameters.
+        let n = self.steppers.len();
+        let before = |i: usize, j: usize| {
+            i != j && !dependencies[j].is_disjoint(&parameters[i])
+        };
+
+        let mut placed = vec![false; n];
+        let mut order = Vec::with_capacity(n);
+        while order.len() < n {
+            let next = (0..n).find(|&j| {
+                !placed[j] && (0..n).all(|i| placed[i] || !before(i, j))
+            });
+            match next {
+                Some(j) => {
+                    placed[j] = true;
+                    order.push(j);
+                }
+                None => {
+                    let cycle: BTreeSet<&ParamId> = (0..n)
+                        .filter(|&i| !placed[i])
+                        .flat_map(|i| parameters[i].iter())
+                        .collect();
+                    let cycle: Vec<String> =
+                        cycle.iter().map(|id| id.to_string()).collect();
+                    return Err(invalid_input(format!(
+                        "cyclic dependencies between {}",
+                        cycle.join(", ")
+                    )));
+                }
+            }
+        }
+
+        let mut steppers: Vec<Option<_>> =
+            self.steppers.into_iter().map(Some).collect();
+        let steppers = order
+            .into_iter()
+            .map(|i| steppers[i].take().unwrap())
+            .collect();
+        Ok(Group {
+            steppers,
+            streams: Vec::new(),
+            ..self
+        })
+    }
+
+    /// Step each sub-stepper with a generator of its own, seeded on the
+    /// first step from the chain's generator and the parameters the
+    /// sub-stepper updates.
+    ///
+    /// A sub-stepper then sees the same random numbers however the group is
+    /// ordered and whichever other steppers it holds, so reordering or
+    /// adding steppers leaves the draws of the others' parameters
+    /// reproducible from the chain's seed, as long as their inputs are.
+    pub fn independent_streams(self) -> Self
+    where
+        R: SeedableRng,
+    {
+        Group {
+            split: Some(split_streams::<R>),
+            ..self
+        }
+    }
+
+    /// Key of each sub-stepper's stream: its parameters, numbered when
+    /// several sub-steppers update the same ones
+    fn stream_keys(&self) -> Vec<String> {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        self.steppers
+            .iter()
+            .map(|s| {
+                let ids: Vec<String> =
+                    s.parameters().iter().map(|id| id.to_string()).collect();
+                let ids = ids.join(",");
+                let n = seen.entry(ids.clone()).or_insert(0);
+                *n += 1;
+                format!("{}#{}", ids, n)
+            })
+            .collect()
+    }
+
+    /// Track the correlations of the sub-steppers' parameters with
+    /// `monitor` while any of them adapt, i.e. during warmup.
+    pub fn monitor_correlations(self, monitor: CorrelationMonitor<M>) -> Self {
+        Group {
+            correlations: Some(monitor),
+            ..self
+        }
+    }
+
+    /// The correlations observed during warmup, if monitored
+    pub fn correlations(&self) -> Option<&CorrelationMonitor<M>> {
+        self.correlations.as_ref()
+    }
+
+    /// Groups of parameters whose warmup correlation is at least
+    /// `threshold` in magnitude, recommended for joint updates. Empty when
+    /// correlations are not monitored.
+    pub fn recommended_blocks(&self, threshold: f64) -> Vec<Vec<ParamId>> {
+        self.correlations
+            .as_ref()
+            .map_or_else(Vec::new, |c| c.blocks(threshold))
+    }
+
+    /// `n` independent draws from the joint prior of the sub-steppers'
+    /// parameters, each drawn with `draw_prior` from a fresh clone of
+    /// `template`
+    ///
+    /// Parameters are drawn in the sub-steppers' order, so a group in
+    /// dependency order draws hyperparameters before the parameters whose
+    /// priors read them. Anything no sub-stepper updates keeps its value in
+    /// `template`.
+    pub fn sample_prior(&self, rng: &mut R, template: &M, n: usize) -> Vec<M> {
+        (0..n)
+            .map(|_| {
+                self.steppers
+                    .iter()
+                    .fold(template.clone(), |m, s| s.draw_prior(rng, m))
+            })
+            .collect()
+    }
+}
+
+/// Generators seeded from a seed drawn from `rng` hashed with each key
+fn split_streams<R>(rng: &mut R, keys: &[String]) -> Vec<R>
+where
+    R: SeedableRng + Rng,
+{
+    let mut base = R::Seed::default();
+    rng.fill(base.as_mut());
+    keys.iter()
+        .map(|key| {
+            let mut seed = R::Seed::default();
+            for (j, chunk) in seed.as_mut().chunks_mut(8).enumerate() {
+                let word = stream_seed_word(base.as_mut(), key, j as u64);
+                chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+            }
+            R::from_seed(seed)
+        })
+        .collect()
+}
+
+/// Word `j` of the seed of `key`'s stream: FNV-1a over the base seed, the
+/// key and `j`, finished with the SplitMix64 mixer
+///
+/// The hash is fixed here rather than taken from the standard library,
+/// whose hashers may change between releases, so streams are reproducible
+/// across toolchains and platforms.
+fn stream_seed_word(base: &[u8], key: &str, j: u64) -> u64 {
+    let j = j.to_le_bytes();
+    let bytes = base.iter().chain(key.as_bytes()).chain(j.iter());
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
 }
 
-impl<M, R> fmt::Debug for Group<M, R> 
+impl<M, R, S> fmt::Debug for Group<M, R, S>
 where
     M: Clone + fmt::Debug,
-    R: Rng
+    R: Rng,
+    S: ?Sized + SteppingAlg<M, R>,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Group {{ samplers: ")?;
@@ -39,15 +279,51 @@ where
 }
 
 
-impl<M, R: Rng> SteppingAlg<M, R> for Group<M, R>
+impl<M, R, S> SteppingAlg<M, R> for Group<M, R, S>
 where
     M: Clone + fmt::Debug,
+    R: Rng,
+    S: ?Sized + SteppingAlg<M, R>,
 {
     fn step(&mut self, rng: &mut R, model: M) -> M {
-        self
-            .steppers
-            .iter_mut()
-            .fold(model, |x, stepper| stepper.step(rng, x))
+        if let Some(ref cache) = self.prior_cache {
+            cache.clear();
+        }
+        // The steppers before each one may have moved parameters its
+        // likelihood reads, so the score it cached last sweep is stale.
+        let sweep = self.steppers.len() > 1;
+        let step = |stepper: &mut Box<S>, rng: &mut R, x: M| {
+            if sweep {
+                stepper.invalidate_cache();
+            }
+            stepper.step(rng, x)
+        };
+        let model = match self.split {
+            Some(split) => {
+                if self.streams.is_empty() {
+                    let keys = self.stream_keys();
+                    self.streams = split(rng, &keys);
+                }
+                self.steppers
+                    .iter_mut()
+                    .zip(self.streams.iter_mut())
+                    .fold(model, |x, (stepper, rng)| step(stepper, rng, x))
+            }
+            None => self
+                .steppers
+                .iter_mut()
+                .fold(model, |x, stepper| step(stepper, rng, x)),
+        };
+        let adapting = match self.get_adapt() {
+            AdaptationStatus::Disabled => false,
+            _ => true,
+        };
+        if let Some(ref mut correlations) = self.correlations {
+            if adapting {
+                correlations.observe(&model);
+            }
+        }
+        model
     }
 
     fn set_adapt(&mut self, mode: AdaptationMode) {
@@ -79,22 +355,517 @@ where
             .collect()
     }
 
+    fn parameters(&self) -> Vec<ParamId> {
+        self
+            .steppers
+            .iter()
+            .flat_map(|s| s.parameters())
+            .collect()
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        self
+            .steppers
+            .iter()
+            .flat_map(|s| s.dependencies())
+            .collect()
+    }
+
     fn reset(&mut self) {
+        if let Some(ref mut correlations) = self.correlations {
+            correlations.reset();
+        }
         self
             .steppers
             .iter_mut()
             .for_each(|s| s.reset())
     }
-    
+
+    fn invalidate_cache(&mut self) {
+        self
+            .steppers
+            .iter_mut()
+            .for_each(|s| s.invalidate_cache())
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self
+            .steppers
+            .iter_mut()
+            .for_each(|s| s.fix(parameter))
+    }
+
+    fn set_prior_cache(&mut self, cache: PriorCache) {
+        self.prior_cache = Some(cache.clone());
+        self
+            .steppers
+            .iter_mut()
+            .for_each(|s| s.set_prior_cache(cache.clone()))
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self
+            .steppers
+            .iter_mut()
+            .for_each(|s| s.set_event_sink(sink.clone()))
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        self
+            .steppers
+            .iter()
+            .fold(model, |m, s| s.draw_prior(rng, m))
+    }
+
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        self.steppers
+            .iter()
+            .flat_map(|stepper| stepper.adaptation_state())
+            .collect()
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        for stepper in self.steppers.iter_mut() {
+            stepper.restore_adaptation(states)?;
+        }
+        Ok(())
+    }
+
     /*
     fn substeppers(&self) -> Option<&Vec<Box<SteppingAlg<M, R>>>> {
         Some(&self.steppers)
     }
+    */
+}
 
-    fn prior_sample(&self, rand: &mut R, m: M) -> M {
+impl<M, R: Rng> Tempering for Group<M, R, dyn TemperingStepper<M, R>>
+where
+    M: Clone,
+{
+    fn set_temperature(&mut self, temperature: f64) {
         self
             .steppers
-            .fold(m, |mp, s| { s.prior_sample(rand, mp) })
+            .iter_mut()
+            .for_each(|s| s.set_temperature(temperature))
+    }
+
+    /// The temperature of the sub-steppers, 1 for an empty group
+    fn temperature(&self) -> f64 {
+        self.steppers.first().map_or(1.0, |s| s.temperature())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use lens::*;
+    use parameter::Parameter;
+    use rv::dist::Gaussian;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use statistics::StatisticValue;
+    use steppers::SRWM;
+    use likelihood::DeltaLogLikelihood;
+    use rv::misc::ks_test;
+    use rv::traits::{Cdf, Mean, Rv, Variance};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use utils::multiple_tries;
+    const SEED: [u8; 32] = [0; 32];
+    const P_VAL: f64 = 0.2;
+    const N_TRIES: usize = 10;
+
+    #[derive(Copy, Clone, Debug)]
+    struct Model {
+        a: f64,
+        b: f64,
+    }
+
+    fn log_likelihood(_m: &Model) -> f64 {
+        0.0
+    }
+
+    #[test]
+    fn statistics_are_labeled_by_parameter() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let b = Parameter::new(
+            "b".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, b),
+        );
+
+        let mut group: Group<Model, StdRng> = Group::new(vec![
+            Box::new(SRWM::new(a, log_likelihood, Some(1.0)).unwrap()),
+            Box::new(SRWM::new(b, log_likelihood, Some(1.0)).unwrap()),
+        ]);
+
+        let ids: Vec<String> =
+            group.parameters().into_iter().map(|id| id.0).collect();
+        assert_eq!(ids, vec!["a".to_string(), "b".to_string()]);
+
+        (0..100).fold(Model { a: 0.0, b: 0.0 }, |m, _| group.step(&mut rng, m));
+
+        let stats = group.get_statistics();
+        assert_eq!(stats.len(), 2);
+        for (stat, name) in stats.iter().zip(ids.iter()) {
+            assert_eq!(&stat.parameter.0, name);
+            match stat.value {
+                StatisticValue::AcceptanceRate(rate) => {
+                    assert!(rate > 0.0 && rate < 1.0)
+                }
+                _ => panic!("Expected an acceptance rate"),
+            }
+        }
+
+        group.reset();
+        assert!(group.get_statistics().is_empty());
+    }
+
+    #[test]
+    fn fix_skips_only_the_fixed_parameter() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let b = Parameter::new(
+            "b".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, b),
+        );
+
+        let mut group: Group<Model, StdRng> = Group::new(vec![
+            Box::new(SRWM::new(a.clone(), log_likelihood, Some(1.0)).unwrap()),
+            Box::new(SRWM::new(b, log_likelihood, Some(1.0)).unwrap()),
+        ]);
+        group.fix(a.id());
+
+        let m = (0..100)
+            .fold(Model { a: 0.5, b: 0.5 }, |m, _| group.step(&mut rng, m));
+        assert_eq!(m.a, 0.5);
+        assert!(m.b != 0.5);
+
+        // Only the free parameter records acceptances.
+        let stats = group.get_statistics();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].parameter.0, "b");
+    }
+
+    #[test]
+    fn independent_streams_survive_reordering() {
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let b = Parameter::new(
+            "b".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, b),
+        );
+        let srwm_a = SRWM::new(a, log_likelihood, Some(1.0)).unwrap();
+        let srwm_b = SRWM::new(b, log_likelihood, Some(1.0)).unwrap();
+        let trace_of_b = |group: &mut Group<Model, StdRng>| -> Vec<f64> {
+            let mut rng = StdRng::from_seed(SEED);
+            let mut m = Model { a: 0.0, b: 0.0 };
+            (0..50)
+                .map(|_| {
+                    m = group.step(&mut rng, m);
+                    m.b
+                })
+                .collect()
+        };
+
+        let mut alone: Group<Model, StdRng> =
+            Group::new(vec![Box::new(srwm_b.clone())]).independent_streams();
+        let mut after: Group<Model, StdRng> = Group::new(vec![
+            Box::new(srwm_a.clone()),
+            Box::new(srwm_b.clone()),
+        ]).independent_streams();
+        let mut before: Group<Model, StdRng> = Group::new(vec![
+            Box::new(srwm_b.clone()),
+            Box::new(srwm_a.clone()),
+        ]).independent_streams();
+        let trace = trace_of_b(&mut alone);
+        assert_eq!(trace_of_b(&mut after), trace);
+        assert_eq!(trace_of_b(&mut before), trace);
+
+        // Sharing the chain's generator, b's draws depend on a's stepper.
+        let mut shared: Group<Model, StdRng> =
+            Group::new(vec![Box::new(srwm_a), Box::new(srwm_b)]);
+        assert_ne!(trace_of_b(&mut shared), trace);
+    }
+
+    #[test]
+    fn stream_seeds_are_pinned() {
+        // Changing these values breaks replays of recorded chains.
+        let base = [0u8; 32];
+        assert_eq!(stream_seed_word(&base, "x", 0), 0xd19c_0612_0f2f_9669);
+        assert_eq!(stream_seed_word(&base, "x", 1), 0xcfb6_3e57_37f8_c030);
+        assert_eq!(stream_seed_word(&base, "y", 0), 0x3953_e24c_129c_92f9);
+    }
+
+    #[test]
+    fn prior_draws_are_independent_of_each_other() {
+        let mut rng = StdRng::from_seed(SEED);
+        let a = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(3.0, 1.0).unwrap(),
+            make_lens!(Model, f64, a),
+        );
+        let group: Group<Model, StdRng> = Group::new(vec![Box::new(
+            SRWM::new(a, log_likelihood, Some(1.0)).unwrap(),
+        )]);
+
+        let template = Model { a: 0.0, b: -1.0 };
+        let draws = group.sample_prior(&mut rng, &template, 2000);
+        assert_eq!(draws.len(), 2000);
+        assert!(draws.iter().all(|m| m.b == -1.0));
+        let a: Vec<f64> = draws.iter().map(|m| m.a).collect();
+        let mean = a.iter().sum::<f64>() / 2000.0;
+        assert!((mean - 3.0).abs() < 0.1);
+        // Consecutive draws are uncorrelated, unlike steps of a chain.
+        let lag_one = a
+            .windows(2)
+            .map(|w| (w[0] - mean) * (w[1] - mean))
+            .sum::<f64>()
+            / 2000.0;
+        assert!(lag_one.abs() < 0.1);
+    }
+
+    #[derive(Clone, Debug)]
+    struct CountingPrior {
+        evals: Arc<AtomicUsize>,
+    }
+
+    impl Rv<f64> for CountingPrior {
+        fn ln_f(&self, x: &f64) -> f64 {
+            self.evals.fetch_add(1, Ordering::SeqCst);
+            Gaussian::new(0.0, 1.0).unwrap().ln_f(x)
+        }
+
+        fn draw<R: Rng>(&self, rng: &mut R) -> f64 {
+            Gaussian::new(0.0, 1.0).unwrap().draw(rng)
+        }
+    }
+
+    impl Mean<f64> for CountingPrior {
+        fn mean(&self) -> Option<f64> {
+            Some(0.0)
+        }
+    }
+
+    impl Variance<f64> for CountingPrior {
+        fn variance(&self) -> Option<f64> {
+            Some(1.0)
+        }
+    }
+
+    // A likelihood independent of the model, with free deltas.
+    #[derive(Clone, Debug)]
+    struct Flat;
+
+    impl DeltaLogLikelihood<Model> for Flat {
+        fn ln_f(&self, _m: &Model) -> f64 {
+            0.0
+        }
+
+        fn delta(&self, _: &Model, _: &Model, _: &ParamId) -> Option<f64> {
+            Some(0.0)
+        }
+    }
+
+    #[test]
+    fn prior_scores_are_shared_within_a_sweep() {
+        let n_sweeps = 100;
+        let evals = Arc::new(AtomicUsize::new(0));
+        let a = Parameter::new(
+            "a".to_string(),
+            CountingPrior { evals: evals.clone() },
+            make_lens!(Model, f64, a),
+        );
+        let stepper = SRWM::new(a, Flat, Some(1.0)).unwrap();
+
+        // Two steppers of the same parameter each score the current value.
+        let mut rng = StdRng::from_seed(SEED);
+        let mut first = stepper.clone();
+        let mut second = stepper.clone();
+        (0..n_sweeps).fold(Model { a: 0.0, b: 0.0 }, |m, _| {
+            let m = SteppingAlg::<Model, StdRng>::step(&mut first, &mut rng, m);
+            SteppingAlg::<Model, StdRng>::step(&mut second, &mut rng, m)
+        });
+        let unshared = evals.swap(0, Ordering::SeqCst);
+
+        // In a group the second reuses the first's score.
+        let mut rng = StdRng::from_seed(SEED);
+        let mut group: Group<Model, StdRng> = Group::new(vec![
+            Box::new(stepper.clone()),
+            Box::new(stepper.clone()),
+        ]);
+        (0..n_sweeps)
+            .fold(Model { a: 0.0, b: 0.0 }, |m, _| group.step(&mut rng, m));
+        let shared = evals.load(Ordering::SeqCst);
+
+        println!("unshared = {}, shared = {}", unshared, shared);
+        assert!(unshared >= 4 * n_sweeps);
+        assert!(shared <= 3 * n_sweeps + 2);
+    }
+    // a and b are N(0, 1) a priori and observed to differ by N(0, 1/10),
+    // so a - b is N(0, 2/21) a posteriori.
+    fn coupled(m: &Model) -> f64 {
+        -5.0 * (m.a - m.b) * (m.a - m.b)
+    }
+
+    #[test]
+    fn sweeps_sample_a_coupled_posterior() {
+        let mut rng = StdRng::from_seed(SEED);
+        let prior = Gaussian::new(0.0, 1.0).unwrap();
+        let a = Parameter::new(
+            "a".to_string(),
+            prior.clone(),
+            make_lens!(Model, f64, a),
+        );
+        let b = Parameter::new(
+            "b".to_string(),
+            prior,
+            make_lens!(Model, f64, b),
+        );
+        let mut group: Group<Model, StdRng> = Group::new(vec![
+            Box::new(SRWM::new(a, coupled, Some(0.5)).unwrap()),
+            Box::new(SRWM::new(b, coupled, Some(0.5)).unwrap()),
+        ]);
+        let posterior = Gaussian::new(0.0, (2.0_f64 / 21.0).sqrt()).unwrap();
+
+        let passed = multiple_tries(N_TRIES, |_| {
+            let mut m = Model { a: 0.0, b: 0.0 };
+            let mut samples = Vec::new();
+            for i in 0..100_000 {
+                m = group.step(&mut rng, m);
+                if i % 10 == 0 {
+                    samples.push(m.a - m.b);
+                }
+            }
+            let (stat, p) = ks_test(&samples, |x| posterior.cdf(&x));
+            println!("test stat = {}, p = {}", stat, p);
+            p > P_VAL
+        });
+        assert!(passed);
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    struct Triple {
+        a: f64,
+        b: f64,
+        c: f64,
+    }
+
+    // a and b lie close to a line, c is independent of both.
+    fn ridge(m: &Triple) -> f64 {
+        -(m.a - m.b) * (m.a - m.b) / (2.0 * 0.01)
+    }
+
+    #[test]
+    fn correlated_parameters_are_recommended_as_a_block() {
+        let mut rng = StdRng::from_seed(SEED);
+        let prior = Gaussian::new(0.0, 1.0).unwrap();
+        let a = Parameter::new(
+            "a".to_string(),
+            prior.clone(),
+            make_lens!(Triple, f64, a),
+        );
+        let b = Parameter::new(
+            "b".to_string(),
+            prior.clone(),
+            make_lens!(Triple, f64, b),
+        );
+        let c = Parameter::new(
+            "c".to_string(),
+            prior.clone(),
+            make_lens!(Triple, f64, c),
+        );
+        let monitor = CorrelationMonitor::new(vec![
+            (a.id().clone(), (|m: &Triple| m.a) as fn(&Triple) -> f64),
+            (b.id().clone(), |m: &Triple| m.b),
+            (c.id().clone(), |m: &Triple| m.c),
+        ]);
+        let mut group: Group<Triple, StdRng> = Group::new(vec![
+            Box::new(SRWM::new(a.clone(), ridge, Some(1.0)).unwrap()),
+            Box::new(SRWM::new(b.clone(), ridge, Some(1.0)).unwrap()),
+            Box::new(SRWM::new(c.clone(), ridge, Some(1.0)).unwrap()),
+        ]).monitor_correlations(monitor);
+
+        // Nothing is recorded outside of warmup.
+        let init = Triple { a: 0.0, b: 0.0, c: 0.0 };
+        let m = (0..100).fold(init, |m, _| group.step(&mut rng, m));
+        assert_eq!(group.correlations().unwrap().n(), 0);
+
+        group.set_adapt(AdaptationMode::Enabled);
+        (0..5000).fold(m, |m, _| group.step(&mut rng, m));
+        let correlations = group.correlations().unwrap();
+        assert_eq!(correlations.n(), 5000);
+        let r_ab = correlations.correlation(a.id(), b.id()).unwrap();
+        let r_ac = correlations.correlation(a.id(), c.id()).unwrap();
+        assert!(r_ab > 0.9);
+        assert!(r_ac.abs() < 0.2);
+        assert_eq!(
+            group.recommended_blocks(0.5),
+            vec![vec![a.id().clone(), b.id().clone()]]
+        );
+
+        group.reset();
+        assert_eq!(group.correlations().unwrap().n(), 0);
+        assert!(group.recommended_blocks(0.5).is_empty());
+    }
+
+    #[test]
+    fn dependencies_order_the_sweep() {
+        let prior = Gaussian::new(0.0, 1.0).unwrap();
+        // c's prior reads b, whose prior reads a.
+        let a = Parameter::new(
+            "a".to_string(),
+            prior.clone(),
+            make_lens!(Triple, f64, a),
+        );
+        let b = Parameter::new(
+            "b".to_string(),
+            prior.clone(),
+            make_lens!(Triple, f64, b),
+        ).depends_on(vec![a.id().clone()]);
+        let c = Parameter::new(
+            "c".to_string(),
+            prior.clone(),
+            make_lens!(Triple, f64, c),
+        ).depends_on(vec![b.id().clone()]);
+        let group = |a: &Parameter<Gaussian, f64, Triple>,
+                     b: &Parameter<Gaussian, f64, Triple>,
+                     c: &Parameter<Gaussian, f64, Triple>|
+         -> Group<Triple, StdRng> {
+            Group::new(vec![
+                Box::new(SRWM::new(c.clone(), ridge, Some(1.0)).unwrap()),
+                Box::new(SRWM::new(b.clone(), ridge, Some(1.0)).unwrap()),
+                Box::new(SRWM::new(a.clone(), ridge, Some(1.0)).unwrap()),
+            ])
+        };
+
+        let ordered = group(&a, &b, &c).in_dependency_order().unwrap();
+        assert_eq!(
+            ordered.parameters(),
+            vec![a.id().clone(), b.id().clone(), c.id().clone()]
+        );
+
+        let cyclic = a.depends_on(vec![c.id().clone()]);
+        let err = group(&cyclic, &b, &c).in_dependency_order().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("a, b, c"));
     }
-    */
 }
@@ -1,217 +1,1032 @@
-//! Kameleon MCMC
+//! # Kameleon MCMC
+//! Metropolis-Hastings over a vector parameter whose proposals follow the
+//! local covariance of the target, learned with a Gaussian kernel from a
+//! subsample of the chain's history, as in Sejdinovic, Strathmann, Lomeli,
+//! Andrieu and Gretton (2014).
 
-// use std::format;
-
-extern crate rand;
+use std::fmt;
+use std::io;
 use rand::Rng;
-use rand::distributions::Uniform;
+use rand::distributions::StandardNormal;
 use rand::seq::index;
-use std::cmp;
-
-extern crate num_traits;
-use self::num_traits::sign::Signed;
 
-extern crate nalgebra;
-use self::nalgebra::base::*;
-use self::nalgebra::base::storage::Storage;
-// use self::nalgebra::base::dimension::U1;
+use nalgebra::{DMatrix, DVector};
+use rv::traits::Rv;
 
-use rv::traits::{Mean, Rv, Variance};
+use parameter::{Parameter, ParamId};
+use likelihood::DeltaLogLikelihood;
+use steppers::{SteppingAlg, AdaptationStatus, AdaptationMode, util};
+use steppers::tempering::Tempering;
+use steppers::adaptor::{
+    next_state, AdaptorState,
+};
+use statistics::{Statistic, StatisticValue};
+use events::EventSink;
+use utils::{invalid_data, invalid_input};
 
-use parameter::Parameter;
-use steppers::{SteppingAlg, AnnealingAlg};
+/// Default scale *γ* of the isotropic part of proposals
+pub const DEFAULT_GAMMA: f64 = 0.2;
+/// Default scale *ν* of the kernel part of proposals
+pub const DEFAULT_NU: f64 = 1.0;
+/// Default number of past states the kernel is built from
+pub const DEFAULT_SUBSAMPLE_SIZE: usize = 100;
+/// Default number of past states kept to draw subsamples from
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
 
-use std::marker::PhantomData;
+// Acceptance rate the adapted scales are tuned towards
+const TARGET_ACCEPTANCE: f64 = 0.234;
 
-#[derive(Debug)]
-struct KameleonAdaptor<N, D, S> 
+/// Configuration of a `Kameleon` stepper
+///
+/// # Example
+/// ```
+/// # #[macro_use] extern crate rmcmc;
+/// # extern crate nalgebra;
+/// # extern crate rand;
+/// # extern crate rv;
+/// # use rmcmc::lens::*;
+/// # use rmcmc::parameter::Parameter;
+/// # use rmcmc::runner::Runner;
+/// # use rmcmc::steppers::KameleonBuilder;
+/// # use nalgebra::{DMatrix, DVector};
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # use rv::dist::MvGaussian;
+/// # fn main() {
+/// #[derive(Clone, Debug)]
+/// struct Model {
+///     x: DVector<f64>,
+/// }
+///
+/// let cov = DMatrix::from_row_slice(2, 2, &[1.0, 0.9, 0.9, 1.0]);
+/// let x = Parameter::new(
+///     "x".to_string(),
+///     MvGaussian::new(DVector::zeros(2), cov).unwrap(),
+///     make_lens_clone!(Model, DVector<f64>, x),
+/// );
+/// let kameleon = KameleonBuilder::new(x, |_: &Model| 0.0)
+///     .subsample_size(50)
+///     .build()
+///     .unwrap();
+///
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let init = Model { x: DVector::zeros(2) };
+/// let draws =
+///     Runner::new(kameleon).warmup(1000).samples(100).run(&mut rng, init);
+/// assert_eq!(draws[0].len(), 100);
+/// # }
+/// ```
+pub struct KameleonBuilder<D, M, L>
 where
-    N: Scalar + PartialOrd + Signed,
-    D: Dim,
-    S: Storage<N, D> + Clone,
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
 {
-    // history of values
-    history: Vec<Vector<N, D, S>>,
-    subset_indicies: index::IndexVec,
-    subset_size: usize,
-    // scaling parameter nu
-    adapt_nu: bool,
+    parameter: Parameter<D, DVector<f64>, M>,
+    log_likelihood: L,
+    gamma: f64,
     nu: f64,
-    // scaling parameter gamma
     adapt_gamma: bool,
-    gamma: f64,
-    // Number of current adaptation step.
-    step: usize,
-    // Alpha to stochastically optimize towards
-    target_alpha: f64,
-    // enable updates or not
-    enabled: bool,
+    adapt_nu: bool,
+    bandwidth: Option<f64>,
+    subsample_size: usize,
+    history_size: usize,
 }
 
-impl<N, D, S> KameleonAdaptor<N, D, S>
+impl<D, M, L> Clone for KameleonBuilder<D, M, L>
 where
-    N: Scalar + PartialOrd + Signed,
-    D: Dim,
-    S: Storage<N, D> + Clone
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
 {
-    pub fn new(initial_gamma: f64, adapt_gamma: bool, initial_nu: f64, adapt_nu: bool, subset_size: usize) -> Self {
-        let history = Vec::new();
-        let subset_indicies: index::IndexVec = index::IndexVec::from(vec![0 as usize]);
-
-        KameleonAdaptor {
-            history: history.to_owned(),
-            subset_indicies: subset_indicies.to_owned(),
-            subset_size,
-            adapt_nu,
-            nu: initial_nu,
-            adapt_gamma,
-            gamma: initial_gamma,
-            step: 0,
-            target_alpha: 0.234,
-            enabled: true,
+    fn clone(&self) -> Self {
+        KameleonBuilder {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            gamma: self.gamma,
+            nu: self.nu,
+            adapt_gamma: self.adapt_gamma,
+            adapt_nu: self.adapt_nu,
+            bandwidth: self.bandwidth,
+            subsample_size: self.subsample_size,
+            history_size: self.history_size,
         }
     }
+}
 
-    fn enable(&mut self) {
-        self.enabled = true
+impl<D, M, L> KameleonBuilder<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    /// Kameleon MCMC for `parameter`, adapting *ν* but not *γ*, with the
+    /// kernel's bandwidth set from its subsample
+    pub fn new(
+        parameter: Parameter<D, DVector<f64>, M>,
+        log_likelihood: L,
+    ) -> Self {
+        KameleonBuilder {
+            parameter,
+            log_likelihood,
+            gamma: DEFAULT_GAMMA,
+            nu: DEFAULT_NU,
+            adapt_gamma: false,
+            adapt_nu: true,
+            bandwidth: None,
+            subsample_size: DEFAULT_SUBSAMPLE_SIZE,
+            history_size: DEFAULT_HISTORY_SIZE,
+        }
     }
 
-    fn disable(&mut self) {
-        self.enabled = false;
+    /// Initial scale *γ* of the isotropic part of proposals, which lets
+    /// the chain leave the span of its history.
+    pub fn gamma(&self, gamma: f64) -> Self {
+        KameleonBuilder {
+            gamma,
+            ..(*self).clone()
+        }
     }
 
-    fn update<R: Rng>(&mut self, rng: &mut R, alpha: f64, new_value: Vector<N, D, S>) {
-        if self.enabled {
-            self.step += 1;
-            let g = 0.9 / (self.step as f64).powf(0.9);
-            let delta = alpha - self.target_alpha;
-            if self.adapt_nu {
-                self.nu = self.nu + g * delta;
-            }
-            
-            // resample the subset of points
-            let p_draw_new = 1.0 / (self.step as f64).sqrt();
-            let p = rng.sample(Uniform::new(0.0, 1.0));
-            if p < p_draw_new {
-                self.subset_indicies = index::sample(
-                    rng,
-                    self.history.len(),
-                    cmp::min(self.history.len(), self.subset_size)
-                )
-            }
+    /// Initial scale *ν* of the part of proposals following the kernel's
+    /// covariance.
+    pub fn nu(&self, nu: f64) -> Self {
+        KameleonBuilder {
+            nu,
+            ..(*self).clone()
         }
     }
-}
 
+    /// Whether *γ* is scaled towards the target acceptance rate while
+    /// adapting.
+    pub fn adapt_gamma(&self, adapt_gamma: bool) -> Self {
+        KameleonBuilder {
+            adapt_gamma,
+            ..(*self).clone()
+        }
+    }
 
+    /// Whether *ν* is scaled towards the target acceptance rate while
+    /// adapting.
+    pub fn adapt_nu(&self, adapt_nu: bool) -> Self {
+        KameleonBuilder {
+            adapt_nu,
+            ..(*self).clone()
+        }
+    }
+
+    /// Use `bandwidth` for the Gaussian kernel instead of the median
+    /// distance between the points of each subsample.
+    pub fn bandwidth(&self, bandwidth: f64) -> Self {
+        KameleonBuilder {
+            bandwidth: Some(bandwidth),
+            ..(*self).clone()
+        }
+    }
+
+    /// Build the kernel from `subsample_size` past states.
+    pub fn subsample_size(&self, subsample_size: usize) -> Self {
+        KameleonBuilder {
+            subsample_size,
+            ..(*self).clone()
+        }
+    }
 
+    /// Keep a uniform sample of `history_size` of the states visited while
+    /// adapting to draw subsamples from.
+    pub fn history_size(&self, history_size: usize) -> Self {
+        KameleonBuilder {
+            history_size,
+            ..(*self).clone()
+        }
+    }
 
-/// MCMC Kameleon 
-/// 
-/// R: Rv with Mean and Variance traits
-/// N: Scalar Type
-/// D: Nimensionality
-/// S: Storage Type
-/// L: Likelihood Type
-pub struct Kameleon<R, N, D, S, SV, M, L>
+    /// The stepper, or an `InvalidInput` error if *γ*, *ν* or the
+    /// bandwidth are not finite and positive, the subsample has fewer than
+    /// two states or the history is smaller than the subsample.
+    pub fn build(&self) -> io::Result<Kameleon<D, M, L>> {
+        let id = self.parameter.id();
+        let positive = |x: f64| x > 0.0 && x.is_finite();
+        if !positive(self.gamma) {
+            return Err(invalid_input(format!(
+                "Kameleon for {}: gamma must be finite and positive.",
+                id
+            )));
+        }
+        if !positive(self.nu) {
+            return Err(invalid_input(format!(
+                "Kameleon for {}: nu must be finite and positive.",
+                id
+            )));
+        }
+        if !self.bandwidth.map_or(true, positive) {
+            return Err(invalid_input(format!(
+                "Kameleon for {}: the bandwidth must be finite and positive.",
+                id
+            )));
+        }
+        if self.subsample_size < 2 {
+            return Err(invalid_input(format!(
+                "Kameleon for {}: the subsample must hold at least 2 states.",
+                id
+            )));
+        }
+        if self.history_size < self.subsample_size {
+            return Err(invalid_input(format!(
+                "Kameleon for {}: the history of {} states cannot hold a \
+                 subsample of {}.",
+                id, self.history_size, self.subsample_size
+            )));
+        }
+        Ok(Kameleon {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            mh: util::MHCore::new(),
+            temperature: 1.0,
+            fixed: false,
+            gamma: self.gamma,
+            nu: self.nu,
+            adapt_gamma: self.adapt_gamma,
+            adapt_nu: self.adapt_nu,
+            fixed_bandwidth: self.bandwidth,
+            bandwidth: self.bandwidth.unwrap_or(1.0),
+            subsample_size: self.subsample_size,
+            history_size: self.history_size,
+            log_lambda: 0.0,
+            adapted_steps: 0,
+            non_finite_updates: 0,
+            adapting: false,
+            history: Vec::new(),
+            visited: 0,
+            subsample: Vec::new(),
+            prior_cache: None,
+            events: None,
+        })
+    }
+}
+
+/// Kameleon MCMC over a `DVector<f64>` parameter
+///
+/// Proposals from *x* are Gaussian with covariance
+/// *γ² I + ν² M H Mᵀ*, where the columns of *M* are twice the gradients at
+/// *x* of the Gaussian kernel *exp(−‖x − z‖² / σ²)* centred on each point
+/// *z* of a subsample of past states, and *H* centres them. Near those
+/// states the proposals follow the local shape of the target, e.g. along a
+/// curved ridge, which a single global covariance as in
+/// `AdaptiveMetropolis` cannot. The covariance depends on *x*, so the
+/// acceptance ratio includes the Hastings correction, at a cost of
+/// O(d³ + d² n) per step for a subsample of *n* states.
+///
+/// While adaptation is enabled the stepper keeps a uniform sample of the
+/// states it visits and redraws the subsample from it with probability
+/// *1 / √t* at adaptive step *t*, so adaptation diminishes. The scales
+/// chosen with `KameleonBuilder::adapt_nu` and `adapt_gamma` are
+/// multiplied by a common factor, which the Robbins-Monro rule of
+/// `GlobalAdaptor` tunes towards an acceptance rate of 0.234; no covariance
+/// is tracked, since the kernel supplies the shape. Disabling adaptation
+/// freezes the subsample and scales, so draws after warmup come from a
+/// fixed kernel.
+pub struct Kameleon<D, M, L>
 where
-    R: Rv<Vector<N, D, S>> + Variance<SquareMatrix<N, D, SV>> + Mean<Vector<N, D, S>> + Clone,
-    N: Scalar + PartialOrd + Signed,
-    D: Dim,
-    S: Storage<N, D>,
-    SV: Storage<N, D, D>,
-    M: Clone + 'static,
-    L: Fn(&M) -> f64 + Sync + Clone
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
 {
-    pub parameter: Parameter<R, Vector<N, D, S>, M>,
+    pub parameter: Parameter<D, DVector<f64>, M>,
     pub log_likelihood: L,
-    pub current_score: Option<f64>,
+    pub mh: util::MHCore,
+    /// Temperature of the tempered target; see `Tempering`
     pub temperature: f64,
-    pub log_acceptance: f64,
-    pub sv_phantom: PhantomData<SV>,
+    pub fixed: bool,
+    gamma: f64,
+    nu: f64,
+    adapt_gamma: bool,
+    adapt_nu: bool,
+    fixed_bandwidth: Option<f64>,
+    // Bandwidth σ of the kernel
+    bandwidth: f64,
+    subsample_size: usize,
+    history_size: usize,
+    // Log of the factor multiplying the adapted scales
+    log_lambda: f64,
+    adapted_steps: usize,
+    non_finite_updates: usize,
+    adapting: bool,
+    // Uniform sample of the `visited` states seen while adapting
+    history: Vec<DVector<f64>>,
+    visited: usize,
+    subsample: Vec<DVector<f64>>,
+    prior_cache: Option<util::PriorCache>,
+    events: Option<EventSink>,
 }
 
-impl<R, N, D, S, SV, M, L> Kameleon<R, N, D, S, SV, M, L>
+impl<D, M, L> Kameleon<D, M, L>
 where
-    R: Rv<Vector<N, D, S>> + Variance<SquareMatrix<N, D, SV>> + Mean<Vector<N, D, S>> + Clone,
-    N: Scalar + PartialOrd + Signed,
-    D: Dim,
-    S: Storage<N, D>,
-    SV: Storage<N, D, D>,
-    M: Clone + 'static,
-    L: Fn(&M) -> f64 + Sync + Clone
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
 {
-    pub fn new(
-        parameter: Parameter<R, Vector<N, D, S>, M>,
-        log_likelihood: L
-    ) -> Self {
+    fn adapted_scale(&self) -> f64 {
+        self.log_lambda.exp()
+    }
+
+    // The Robbins-Monro step of `GlobalAdaptor` on the log scale, skipping
+    // NaN acceptance ratios.
+    fn adapt_scale(&mut self, log_alpha: f64) {
+        if log_alpha.is_nan() {
+            self.non_finite_updates += 1;
+            return;
+        }
+        let g = 0.9 / ((self.adapted_steps + 1) as f64).powf(0.9);
+        let alpha = log_alpha.exp().min(1.0);
+        self.log_lambda += g * (alpha - TARGET_ACCEPTANCE);
+        self.adapted_steps += 1;
+    }
+
+    /// Current scale *γ* of the isotropic part of proposals
+    pub fn current_gamma(&self) -> f64 {
+        if self.adapt_gamma {
+            self.gamma * self.adapted_scale().sqrt()
+        } else {
+            self.gamma
+        }
+    }
+
+    /// Current scale *ν* of the kernel part of proposals
+    pub fn current_nu(&self) -> f64 {
+        if self.adapt_nu {
+            self.nu * self.adapted_scale().sqrt()
+        } else {
+            self.nu
+        }
+    }
+
+    /// Current bandwidth *σ* of the kernel
+    pub fn bandwidth(&self) -> f64 {
+        self.bandwidth
+    }
+
+    /// Past states the kernel is built from
+    pub fn subsample(&self) -> &[DVector<f64>] {
+        &self.subsample
+    }
+
+    /// Centred gradients *M H* of the kernel at `x`, one column per state
+    /// of the subsample
+    fn kernel_basis(&self, x: &DVector<f64>) -> DMatrix<f64> {
+        let n = self.subsample.len();
+        let s2 = self.bandwidth * self.bandwidth;
+        let mut basis = DMatrix::zeros(x.len(), n);
+        for (i, z) in self.subsample.iter().enumerate() {
+            let diff = x - z;
+            let k = (-diff.norm_squared() / s2).exp();
+            basis.set_column(i, &(diff * (-4.0 * k / s2)));
+        }
+        if n == 0 {
+            return basis;
+        }
+        let ones = DVector::from_element(n, 1.0);
+        let mean = &basis * &ones / n as f64;
+        basis - mean * ones.transpose()
+    }
+
+    /// Log density, up to a constant, of proposing `to` from `from`, whose
+    /// kernel basis is `basis`
+    fn ln_proposal(
+        &self,
+        basis: &DMatrix<f64>,
+        from: &DVector<f64>,
+        to: &DVector<f64>,
+    ) -> f64 {
+        let gamma = self.current_gamma();
+        let nu = self.current_nu();
+        let dim = from.len();
+        let mut covariance = DMatrix::identity(dim, dim) * (gamma * gamma);
+        // nalgebra leaves products over an empty inner dimension
+        // uninitialized, so the kernel part is left out until there is a
+        // subsample.
+        if basis.ncols() > 0 {
+            covariance += basis * basis.transpose() * (nu * nu);
+        }
+        // γ² I keeps the covariance positive definite.
+        let chol = covariance
+            .cholesky()
+            .expect("Kameleon: proposal covariance is not positive definite")
+            .unpack();
+        let residual = chol
+            .solve_lower_triangular(&(to - from))
+            .expect("Kameleon: singular Cholesky factor");
+        let ln_det: f64 = chol.diagonal().iter().map(|l| l.ln()).sum();
+        -ln_det - 0.5 * residual.norm_squared()
+    }
+
+    /// Add the chain's state `x` to the history and occasionally redraw
+    /// the subsample from it.
+    fn adapt<R: Rng>(&mut self, rng: &mut R, x: &DVector<f64>) {
+        if !x.iter().all(|v| v.is_finite()) {
+            return;
+        }
+        self.visited += 1;
+        if self.history.len() < self.history_size {
+            self.history.push(x.clone());
+        } else {
+            let i = rng.gen_range(0, self.visited);
+            if i < self.history_size {
+                self.history[i] = x.clone();
+            }
+        }
+        if self.history.len() < 2
+            || rng.gen::<f64>() * (self.visited as f64).sqrt() > 1.0
+        {
+            return;
+        }
+        let n = self.history.len().min(self.subsample_size);
+        self.subsample = index::sample(rng, self.history.len(), n)
+            .into_iter()
+            .map(|i| self.history[i].clone())
+            .collect();
+        if self.fixed_bandwidth.is_none() {
+            let median = median_distance(&self.subsample);
+            if median > 0.0 {
+                self.bandwidth = median;
+            }
+        }
+    }
+}
+
+/// Median distance between pairs of `points`
+fn median_distance(points: &[DVector<f64>]) -> f64 {
+    let mut distances: Vec<f64> = points
+        .iter()
+        .enumerate()
+        .flat_map(|(i, x)| points[..i].iter().map(move |y| (x - y).norm()))
+        .collect();
+    if distances.is_empty() {
+        return 0.0;
+    }
+    distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    distances[distances.len() / 2]
+}
+
+impl<D, M, L> Clone for Kameleon<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn clone(&self) -> Self {
         Kameleon {
-            parameter,
-            log_likelihood,
-            current_score: None,
-            temperature: 1.0,
-            log_acceptance: 0.0,
-            sv_phantom: PhantomData,
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            mh: self.mh,
+            temperature: self.temperature,
+            fixed: self.fixed,
+            gamma: self.gamma,
+            nu: self.nu,
+            adapt_gamma: self.adapt_gamma,
+            adapt_nu: self.adapt_nu,
+            fixed_bandwidth: self.fixed_bandwidth,
+            bandwidth: self.bandwidth,
+            subsample_size: self.subsample_size,
+            history_size: self.history_size,
+            log_lambda: self.log_lambda,
+            adapted_steps: self.adapted_steps,
+            non_finite_updates: self.non_finite_updates,
+            adapting: self.adapting,
+            history: self.history.clone(),
+            visited: self.visited,
+            subsample: self.subsample.clone(),
+            prior_cache: self.prior_cache.clone(),
+            events: self.events.clone(),
         }
     }
 }
 
+impl<D, M, L> Tempering for Kameleon<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn set_temperature(&mut self, temperature: f64) {
+        assert!(
+            temperature > 0.0 && temperature.is_finite(),
+            "temperature must be finite and positive."
+        );
+        self.temperature = temperature;
+        // The cached score is of the previous target.
+        self.mh.current_score = None;
+    }
 
-macro_rules! impl_traits {
-    ($kind: ty) => {
-        impl<R, D, S, SV, M, L> SteppingAlg<M> for Kameleon<R, $kind, D, S, SV, M, L>
-        where
-            R: Rv<Vector<$kind, D, S>> + Variance<SquareMatrix<$kind, D, SV>> + Mean<Vector<$kind, D, S>> + Clone,
-            D: Dim,
-            S: Storage<$kind, D>,
-            SV: Storage<$kind, D, D>,
-            M: Clone + 'static,
-            L: Fn(&M) -> f64 + Sync + Clone
-        {
-            fn step<Rn: Rng>(&self, rng: &mut Rn, model: &mut M) -> Self { std::unimplemented!(); }
-            fn adapt_on(&self) -> Self { std::unimplemented!(); }
-            fn adapt_off(&self) -> Self { std::unimplemented!(); }
-        }
-
-        impl<R, D, S, SV, M, L> Clone for Kameleon<R, $kind, D, S, SV, M, L>
-            where
-                R: Rv<Vector<$kind, D, S>> + Variance<SquareMatrix<$kind, D, SV>> + Mean<Vector<$kind, D, S>> + Clone,
-                D: Dim,
-                S: Storage<$kind, D>,
-                SV: Storage<$kind, D, D>,
-                M: Clone + 'static,
-                L: Fn(&M) -> f64 + Sync + Clone
-        {
-            fn clone(&self) -> Self {
-                Kameleon {
-                    parameter: self.parameter.clone(),
-                    log_likelihood: self.log_likelihood.clone(),
-                    current_score: self.current_score,
-                    temperature: self.temperature,
-                    log_acceptance: self.log_acceptance,
-                    sv_phantom: self.sv_phantom,
-                }
+    fn temperature(&self) -> f64 {
+        self.temperature
+    }
+}
+
+impl<D, M, L> fmt::Debug for Kameleon<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Kameleon {{ parameter: {:?}, current_score: {:?}, gamma: {}, \
+             nu: {}, bandwidth: {}, subsample_size: {} }}",
+            self.parameter,
+            self.mh.current_score,
+            self.current_gamma(),
+            self.current_nu(),
+            self.bandwidth,
+            self.subsample_size
+        )
+    }
+}
+
+impl<D, M, L, R> SteppingAlg<M, R> for Kameleon<D, M, L>
+where
+    D: Rv<DVector<f64>> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+    R: Rng,
+{
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        self.adapting = match mode {
+            AdaptationMode::Enabled => true,
+            AdaptationMode::Disabled => false,
+        };
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        if self.adapting {
+            AdaptationStatus::Enabled
+        } else {
+            AdaptationStatus::Disabled
+        }
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        let non_finite = self.non_finite_updates;
+        self.mh
+            .acceptance
+            .rate()
+            .map(StatisticValue::AcceptanceRate)
+            .into_iter()
+            .chain(Some(StatisticValue::ProposalScale(self.current_nu())))
+            .chain(
+                Some(StatisticValue::NonFiniteUpdates(non_finite))
+                    .filter(|_| non_finite > 0),
+            )
+            .map(|value| Statistic::new(self.parameter.id().clone(), value))
+            .collect()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        vec![self.parameter.id().clone()]
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        vec![(self.parameter.id().clone(), self.parameter.dependencies.clone())]
+    }
+
+    fn reset(&mut self) {
+        self.mh.reset();
+        self.log_lambda = 0.0;
+        self.adapted_steps = 0;
+        self.non_finite_updates = 0;
+        self.history.clear();
+        self.visited = 0;
+        self.subsample.clear();
+        self.bandwidth = self.fixed_bandwidth.unwrap_or(1.0);
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.mh.invalidate();
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.fixed |= parameter == self.parameter.id();
+    }
+
+    fn set_prior_cache(&mut self, cache: util::PriorCache) {
+        self.prior_cache = Some(cache);
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        self.parameter.draw(&model, rng)
+    }
+
+    // The bandwidth and log scale, the history and the subsample.
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        let kernel = AdaptorState {
+            name: self.parameter.name().to_string(),
+            scales: vec![self.bandwidth, self.log_lambda],
+            moments: Vec::new(),
+            counts: vec![
+                self.visited,
+                self.history.len(),
+                self.adapted_steps,
+                self.non_finite_updates,
+            ],
+            history: self
+                .history
+                .iter()
+                .chain(self.subsample.iter())
+                .map(|x| x.iter().cloned().collect())
+                .collect(),
+        };
+        vec![kernel]
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        let kernel = next_state(states, self.parameter.name())?;
+        kernel.expect(2, 0, 4)?;
+        let (visited, n_history) = (kernel.counts[0], kernel.counts[1]);
+        if n_history > kernel.history.len() {
+            return Err(invalid_data(format!(
+                "the kernel state of {} has {} states but a history of {}",
+                self.parameter.id(),
+                kernel.history.len(),
+                n_history
+            )));
+        }
+        let points: Vec<DVector<f64>> = kernel
+            .history
+            .iter()
+            .map(|x| DVector::from_column_slice(x.len(), x))
+            .collect();
+        self.bandwidth = kernel.scales[0];
+        self.log_lambda = kernel.scales[1];
+        self.adapted_steps = kernel.counts[2];
+        self.non_finite_updates = kernel.counts[3];
+        self.visited = visited;
+        self.history = points[..n_history].to_vec();
+        self.subsample = points[n_history..].to_vec();
+
+        Ok(())
+    }
+
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        let current_value = match self.parameter.lens.try_get(&model) {
+            Ok(value) => value,
+            Err(err) => {
+                let id = self.parameter.id();
+                let events = &self.events;
+                return util::reject_lens_failure(events, id, &err, model);
             }
+        };
+        let dim = current_value.len();
+        if let Some(z) = self.subsample.first().filter(|z| z.len() != dim) {
+            panic!(
+                "Kameleon for {}: the parameter has length {} but the \
+                 subsample's states have length {}.",
+                self.parameter.id(),
+                dim,
+                z.len()
+            );
         }
+        let current_prior = match self.mh.current_prior {
+            Some(prior) => prior,
+            None => match self.prior_cache {
+                Some(ref cache) => cache
+                    .get_or_insert_with(self.parameter.id(), || {
+                        self.parameter.prior.ln_f(&current_value)
+                    }),
+                None => self.parameter.prior.ln_f(&current_value),
+            },
+        };
+        let log_likelihood = util::ScaledLogLikelihood::new(
+            &self.log_likelihood,
+            self.temperature.recip(),
+        );
+        let current_score = self.mh.current_score(|| {
+            log_likelihood.ln_f(&model) + current_prior
+        });
 
-        impl<R, D, S, SV, M, L> AnnealingAlg<M> for Kameleon<R, $kind, D, S, SV, M, L>
-        where
-            R: Rv<Vector<$kind, D, S>> + Variance<SquareMatrix<$kind, D, SV>> + Mean<Vector<$kind, D, S>> + Clone,
-            D: Dim,
-            S: Storage<$kind, D>,
-            SV: Storage<$kind, D, D>,
-            M: Clone + 'static,
-            L: Fn(&M) -> f64 + Sync + Clone
-         {
-            fn set_temperature(&self, t: f64) -> Self {
-                Kameleon {
-                    temperature: t,
-                    ..(*self).clone()
+        let basis = self.kernel_basis(&current_value);
+        let isotropic: DVector<f64> =
+            DVector::from_fn(dim, |_, _| rng.sample(StandardNormal));
+        let mut proposed_value = &current_value
+            + isotropic * self.current_gamma();
+        if basis.ncols() > 0 {
+            let along_kernel: DVector<f64> = DVector::from_fn(
+                basis.ncols(),
+                |_, _| rng.sample(StandardNormal),
+            );
+            proposed_value += &basis * along_kernel * self.current_nu();
+        }
+        let new_model =
+            self.parameter.lens.try_set(&model, proposed_value.clone());
+        let new_model = match new_model {
+            Ok(new_model) => new_model,
+            Err(err) => {
+                let id = self.parameter.id();
+                let events = &self.events;
+                return util::reject_lens_failure(events, id, &err, model);
+            }
+        };
+        let prior_score = self.parameter.prior.ln_f(&proposed_value);
+        let new_score = util::proposal_score(
+            &log_likelihood,
+            self.parameter.id(),
+            &model,
+            current_score,
+            || current_prior,
+            &new_model,
+            prior_score,
+        );
+
+        let forward = self.ln_proposal(&basis, &current_value, &proposed_value);
+        let reverse_basis = self.kernel_basis(&proposed_value);
+        let reverse =
+            self.ln_proposal(&reverse_basis, &proposed_value, &current_value);
+        let scores = util::ProposalScores::new(current_score, new_score)
+            .hastings(reverse - forward);
+        let update = util::metropolis_select(
+            rng,
+            scores,
+            proposed_value,
+            current_value,
+        );
+        self.mh.record_with_prior(&update, prior_score);
+        if self.adapting {
+            self.adapt_scale(update.log_alpha());
+            self.adapt(rng, update.value());
+        }
+        if let Some(ref events) = self.events {
+            let id = self.parameter.id();
+            events.emit_update(id, &update);
+            events.emit_scores(id, &update, || current_prior, prior_score);
+            let status = if self.adapting {
+                AdaptationStatus::Enabled
+            } else {
+                AdaptationStatus::Disabled
+            };
+            events.emit_adaptation(id, status, self.current_nu());
+        }
+        match update {
+            util::MetroplisUpdate::Accepted(_, _) => {
+                if let Some(ref cache) = self.prior_cache {
+                    cache.insert(self.parameter.id().clone(), prior_score);
                 }
+                new_model
             }
-         }
-    };
+            util::MetroplisUpdate::Rejected(_, _) => model,
+        }
+    }
 }
 
-impl_traits!(f64);
-impl_traits!(f32);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use runner::Runner;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::{Gaussian, MvGaussian};
+    use rv::misc::ks_test;
+    use rv::prelude::Cdf;
+    use utils::multiple_tries;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Clone, Debug)]
+    struct Model {
+        x: DVector<f64>,
+        y: Vec<f64>,
+    }
+
+    // Regressors of the observations in `Model::y`
+    const T: [f64; 5] = [-2.0, -1.0, 0.0, 1.0, 2.0];
+
+    fn prior_covariance() -> DMatrix<f64> {
+        DMatrix::from_row_slice(2, 2, &[1.0, 0.6, 0.6, 1.0])
+    }
+
+    fn parameter() -> Parameter<MvGaussian, DVector<f64>, Model> {
+        Parameter::new(
+            "x".to_string(),
+            MvGaussian::new(DVector::zeros(2), prior_covariance()).unwrap(),
+            Lens::new(
+                |m: &Model| m.x.clone(),
+                |m: &Model, x| Model { x, y: m.y.clone() },
+            ),
+        )
+    }
+
+    // Observations y_j ~ N(x_0 + x_1 t_j, 1)
+    fn log_likelihood(m: &Model) -> f64 {
+        m.y.iter()
+            .zip(T.iter())
+            .map(|(y, t)| -0.5 * (y - m.x[0] - m.x[1] * t).powi(2))
+            .sum()
+    }
+
+    fn simulate<R: Rng>(rng: &mut R, m: &Model) -> Vec<f64> {
+        T.iter()
+            .map(|t| m.x[0] + m.x[1] * t + rng.sample(StandardNormal))
+            .collect()
+    }
+
+    fn model() -> Model {
+        Model { x: DVector::zeros(2), y: vec![0.0; T.len()] }
+    }
+
+    #[test]
+    fn invalid_configurations_are_rejected() {
+        let builder = KameleonBuilder::new(parameter(), log_likelihood);
+        assert!(builder.build().is_ok());
+        assert!(builder.gamma(0.0).build().is_err());
+        assert!(builder.nu(std::f64::INFINITY).build().is_err());
+        assert!(builder.bandwidth(-1.0).build().is_err());
+        assert!(builder.subsample_size(1).build().is_err());
+        assert!(builder.subsample_size(10).history_size(5).build().is_err());
+        let built = builder.build().unwrap();
+        assert_eq!(built.current_gamma(), DEFAULT_GAMMA);
+        assert_eq!(built.current_nu(), DEFAULT_NU);
+    }
+
+    #[test]
+    fn proposals_are_isotropic_without_a_subsample() {
+        let alg = KameleonBuilder::new(parameter(), log_likelihood)
+            .gamma(0.5)
+            .build()
+            .unwrap();
+        let from = DVector::from_column_slice(2, &[1.0, -1.0]);
+        let to = DVector::from_column_slice(2, &[1.5, 0.0]);
+        let basis = alg.kernel_basis(&from);
+        assert_eq!(basis.ncols(), 0);
+
+        // -ln(γ²) - |to - from|² / 2γ²
+        let expected = -2.0 * 0.5_f64.ln() - 1.25 / (2.0 * 0.25);
+        let ln_proposal = alg.ln_proposal(&basis, &from, &to);
+        assert!((ln_proposal - expected).abs() < 1E-12);
+    }
+
+    #[test]
+    fn subsample_is_drawn_from_the_history_while_adapting() {
+        let mut rng = StdRng::from_seed(SEED);
+        let mut alg = KameleonBuilder::new(parameter(), |_: &Model| 0.0)
+            .subsample_size(20)
+            .history_size(50)
+            .build()
+            .unwrap();
+        let mut m = model();
+
+        // Without adaptation proposals are isotropic.
+        for _ in 0..100 {
+            m = alg.step(&mut rng, m);
+        }
+        assert!(alg.subsample().is_empty());
+        assert_eq!(alg.current_nu(), DEFAULT_NU);
+
+        SteppingAlg::<Model, StdRng>::set_adapt(
+            &mut alg,
+            AdaptationMode::Enabled,
+        );
+        for _ in 0..5000 {
+            m = alg.step(&mut rng, m);
+        }
+        assert_eq!(alg.history.len(), 50);
+        assert_eq!(alg.subsample().len(), 20);
+        assert!(alg.bandwidth() > 0.1 && alg.bandwidth() < 10.0);
+        assert!(alg.current_nu() != DEFAULT_NU);
+        assert_eq!(alg.current_gamma(), DEFAULT_GAMMA);
+        let rate = alg.mh.acceptance.rate().unwrap();
+        assert!(rate > 0.1 && rate < 0.5);
+
+        // Frozen once adaptation is disabled
+        SteppingAlg::<Model, StdRng>::set_adapt(
+            &mut alg,
+            AdaptationMode::Disabled,
+        );
+        let subsample = alg.subsample().to_vec();
+        let nu = alg.current_nu();
+        for _ in 0..100 {
+            m = alg.step(&mut rng, m);
+        }
+        assert_eq!(alg.subsample(), &subsample[..]);
+        assert_eq!(alg.current_nu(), nu);
+
+        SteppingAlg::<Model, StdRng>::reset(&mut alg);
+        assert!(alg.subsample().is_empty());
+        assert_eq!(alg.current_nu(), DEFAULT_NU);
+    }
+
+    type Builder = KameleonBuilder<MvGaussian, Model, fn(&Model) -> f64>;
+
+    fn geweke_step(
+        alg: &mut Kameleon<MvGaussian, Model, fn(&Model) -> f64>,
+        rng: &mut StdRng,
+        m: Model,
+    ) -> Model {
+        let y = simulate(rng, &m);
+        // The data changed under the cached score.
+        alg.mh.current_score = None;
+        alg.step(rng, Model { y, ..m })
+    }
+
+    // Geweke's (2004) successive-conditional simulator: alternately draw
+    // data given the parameter and step the parameter given the data. If
+    // the stepper leaves the posterior invariant the parameter's draws
+    // follow its prior. Adaptation is frozen after warmup so the kernel
+    // is fixed while testing, and the draws are thinned heavily as the
+    // simulator mixes slowly.
+    fn geweke_test(builder: Builder) -> bool {
+        let mut rng = StdRng::from_seed(SEED);
+        let cov = prior_covariance();
+        multiple_tries(5, |_| {
+            let mut alg = builder.build().unwrap();
+            let mut m = model();
+            SteppingAlg::<Model, StdRng>::set_adapt(
+                &mut alg,
+                AdaptationMode::Enabled,
+            );
+            for _ in 0..2000 {
+                m = geweke_step(&mut alg, &mut rng, m);
+            }
+            SteppingAlg::<Model, StdRng>::set_adapt(
+                &mut alg,
+                AdaptationMode::Disabled,
+            );
+            let mut draws = Vec::new();
+            for i in 0..50000 {
+                m = geweke_step(&mut alg, &mut rng, m);
+                if i % 50 == 0 {
+                    draws.push(m.x.clone());
+                }
+            }
+            (0..2).all(|i| {
+                let marginal = Gaussian::new(0.0, cov[(i, i)].sqrt()).unwrap();
+                let samples: Vec<f64> = draws.iter().map(|x| x[i]).collect();
+                let (stat, p) = ks_test(&samples, |s| marginal.cdf(&s));
+                println!("x[{}]: test stat = {}, p = {}", i, stat, p);
+                p > 0.05
+            })
+        })
+    }
+
+    #[test]
+    fn geweke_test_adapting_nu() {
+        let builder: Builder = KameleonBuilder::new(
+            parameter(),
+            log_likelihood as fn(&Model) -> f64,
+        )
+        .subsample_size(20)
+        .history_size(200);
+        assert!(geweke_test(builder));
+    }
+
+    #[test]
+    fn geweke_test_adapting_gamma_with_fixed_bandwidth() {
+        let builder: Builder = KameleonBuilder::new(
+            parameter(),
+            log_likelihood as fn(&Model) -> f64,
+        )
+        .adapt_gamma(true)
+        .adapt_nu(false)
+        .nu(0.5)
+        .bandwidth(0.5)
+        .subsample_size(20)
+        .history_size(200);
+        assert!(geweke_test(builder));
+    }
+
+    #[test]
+    fn samples_a_correlated_gaussian() {
+        let mut rng = StdRng::from_seed(SEED);
+        let alg = KameleonBuilder::new(parameter(), |_: &Model| 0.0)
+            .subsample_size(30)
+            .build()
+            .unwrap();
+        let draws = Runner::new(alg)
+            .warmup(2000)
+            .samples(10000)
+            .thinning(2)
+            .run(&mut rng, model());
+        let n = draws[0].len() as f64;
+        let mean = |i: usize| draws[0].iter().map(|m| m.x[i]).sum::<f64>() / n;
+        let (m0, m1) = (mean(0), mean(1));
+        let cov: f64 = draws[0]
+            .iter()
+            .map(|m| (m.x[0] - m0) * (m.x[1] - m1))
+            .sum::<f64>()
+            / n;
+        assert!(m0.abs() < 0.15 && m1.abs() < 0.15);
+        assert!((cov - 0.6).abs() < 0.15);
+    }
+}
@@ -2,8 +2,12 @@
 //!
 
 use std::fmt::Debug;
+use std::io;
 use rand::Rng;
 use statistics::Statistic;
+use parameter::ParamId;
+use events::EventSink;
+use steppers::adaptor::AdaptorState;
 
 pub mod util;
 
@@ -33,37 +37,105 @@ pub trait SteppingAlg<M, R: Rng>: Debug
     fn get_adapt(&self) -> AdaptationStatus;
     // Return a list of statistics
     fn get_statistics(&self) -> Vec<Statistic<M, R>>;
+    // Return the parameters updated by this stepper, none by default.
+    fn parameters(&self) -> Vec<ParamId> {
+        Vec::new()
+    }
+    // Return the parameters updated by this stepper with those their priors
+    // depend on. By default the priors depend on nothing else.
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        self.parameters()
+            .into_iter()
+            .map(|parameter| (parameter, Vec::new()))
+            .collect()
+    }
     // Reset the current stepper to it's initial state
     fn reset(&mut self);
+    // Forget the scores cached for the current model, e.g. after another
+    // stepper moved the chain. Steppers without a cache have nothing to do.
+    fn invalidate_cache(&mut self) {}
+    // Stop updating the given parameter, treating its value as constant.
+    fn fix(&mut self, _parameter: &ParamId) {}
+    // Share a cache of prior scores with the other steppers of a sweep.
+    fn set_prior_cache(&mut self, _cache: util::PriorCache) {}
+    // Emit the events of each step into the sink.
+    fn set_event_sink(&mut self, _sink: EventSink) {}
+    // Draw the parameters updated by this stepper from their priors,
+    // leaving fixed parameters as they are. Steppers updating no
+    // parameters return the model unchanged.
+    fn draw_prior(&self, _rng: &mut R, model: M) -> M {
+        model
+    }
+    // Return what the stepper's adaptation has learned, in a fixed order.
+    // Steppers which do not adapt have nothing to return.
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        Vec::new()
+    }
+    // Continue adapting from states returned by `adaptation_state` of a
+    // stepper built the same way, taking those of this stepper in order.
+    fn restore_adaptation(
+        &mut self,
+        _states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+    // Return the specification the stepper was built from, if it was built
+    // by a `Registry`, so runs can record it.
+    fn spec(&self) -> Option<StepperSpec> {
+        None
+    }
     /*
     // Return a list of sub steppers
     fn substeppers(&self) -> Option<&Vec<Box<SteppingAlg<M, R>>>>;
-    // Sample from prior
-    fn prior_sample(&self, &mut R, model: M) -> M;
     */
 }
 
-/*
- * /// A stepping algorithm which supports annealing
- * pub trait AnnealingAlg: SteppingAlg {
- *     // Sets the temperature for the next step operation.
- *     fn set_temperature(&self, t: f64) -> Self;
- * }
- */
-
 pub mod adaptor;
+pub mod am;
+mod assignment_gibbs;
+mod bandit;
+pub mod binary;
+pub mod batch;
+mod boxed;
+mod correlation;
 mod group;
+pub mod kameleon;
 mod srwm;
-// mod binary_gibbs_metropolis;
-mod binary_metropolis;
+mod vector_srwm;
+mod conjugate;
+pub mod gibbs;
 mod mock;
-
-// mod kameleon;
+mod repeat;
+pub mod reparameterize;
+pub mod slice;
+mod spec;
+pub mod tempering;
 
 // pub use self::adaptor;
+pub use self::am::{AMBuilder, AdaptiveMetropolis};
+pub use self::assignment_gibbs::AssignmentGibbs;
+pub use self::bandit::KernelBandit;
+pub use self::binary::{
+    Binary, BinaryGibbsMetropolis, BinaryGibbsMetropolisBuilder,
+    BinaryMetropolis, BinaryMetropolisBuilder,
+};
+pub use self::batch::{BatchSteppingAlg, Batched, BatchSRWM};
+pub use self::boxed::{BoxedStepper, IntoBoxedStepper};
+pub use self::correlation::CorrelationMonitor;
 pub use self::group::Group;
-pub use self::srwm::SRWM;
+pub use self::kameleon::{Kameleon, KameleonBuilder};
+pub use self::srwm::{SRWM, ProposalKernel, IntegerKernel};
+pub use self::util::ModeJumps;
+pub use self::vector_srwm::{VectorSRWM, ProposalMode, NoiseKernel};
+pub use self::conjugate::ConjugateGibbs;
+pub use self::gibbs::{Gibbs, GibbsBuilder};
 pub use self::mock::Mock;
-// pub use self::binary_gibbs_metropolis::BinaryGibbsMetropolis;
-pub use self::binary_metropolis::BinaryMetropolis;
-// pub use self::kameleon::Kameleon;
+pub use self::repeat::Repeat;
+pub use self::reparameterize::{
+    AffineMap, Reparameterizable, Reparameterize,
+};
+pub use self::slice::SliceSampler;
+pub use self::spec::{Registry, SpecStepper, StepperSpec};
+pub use self::tempering::{
+    ParallelTempering, SimulatedTempering, Tempering, TemperingStepper,
+};
@@ -0,0 +1,140 @@
+//! Reparameterizations of vector parameters learned outside the sampler
+//!
+//! A random walk mixes slowly on a posterior with strong correlations or
+//! varying curvature. An invertible map learned from warmup draws, e.g. a
+//! normalizing flow trained elsewhere or the affine map of the draws'
+//! covariance, can carry the posterior to something close to a standard
+//! normal, where the walk then proposes.
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use nalgebra::{DMatrix, DVector};
+
+use utils::invalid_input;
+
+/// An invertible map `z = f(x)` of a parameter's space, with the log
+/// absolute determinant of the Jacobian of its inverse
+///
+/// Steppers proposing in `z` correct for the change of volume with the
+/// Jacobian, so the chain still targets the posterior of `x`; the map only
+/// changes how fast it mixes. Both directions must keep the dimension.
+pub trait Reparameterize: fmt::Debug + Send + Sync {
+    /// `z = f(x)`
+    fn forward(&self, x: &DVector<f64>) -> DVector<f64>;
+    /// `x = f⁻¹(z)` and `ln |det ∂x/∂z|` at `z`
+    fn inverse(&self, z: &DVector<f64>) -> (DVector<f64>, f64);
+}
+
+/// Steppers which can propose through a `Reparameterize` map, installed
+/// e.g. with `Session::reparameterize` between warmup and sampling
+pub trait Reparameterizable {
+    /// Propose through `map`, or in the parameter's own space if `None`.
+    fn set_reparameterization(&mut self, map: Option<Arc<dyn Reparameterize>>);
+}
+
+/// The affine map `x = μ + L z` with `L` the Cholesky factor of a
+/// covariance, standardizing a Gaussian with mean `μ` and that covariance
+#[derive(Clone, Debug, PartialEq)]
+pub struct AffineMap {
+    mean: DVector<f64>,
+    chol: DMatrix<f64>,
+    ln_det: f64,
+}
+
+impl AffineMap {
+    /// Fails with `InvalidInput` unless `covariance` is a positive definite
+    /// matrix of the same dimension as `mean`.
+    pub fn new(
+        mean: DVector<f64>,
+        covariance: DMatrix<f64>,
+    ) -> io::Result<Self> {
+        let dim = mean.len();
+        if covariance.nrows() != dim || covariance.ncols() != dim {
+            return Err(invalid_input(format!(
+                "an affine map of dimension {} needs a {} by {} \
+                 covariance.",
+                dim, dim, dim
+            )));
+        }
+        let chol = match covariance.cholesky() {
+            Some(chol) => chol.unpack(),
+            None => {
+                return Err(invalid_input(
+                    "the covariance of an affine map must be positive \
+                     definite.",
+                ))
+            }
+        };
+        let ln_det = chol.diagonal().iter().map(|d| d.ln()).sum();
+        Ok(AffineMap { mean, chol, ln_det })
+    }
+
+    /// The map standardizing the mean and covariance of `draws`, e.g. the
+    /// warmup draws of a parameter
+    ///
+    /// Fails with `InvalidInput` with fewer draws than one more than their
+    /// dimension, or if their covariance is singular.
+    pub fn from_draws(draws: &[DVector<f64>]) -> io::Result<Self> {
+        let dim = draws.first().map_or(0, |x| x.len());
+        if dim == 0 || draws.len() <= dim {
+            return Err(invalid_input(
+                "an affine map needs more draws than dimensions.",
+            ));
+        }
+        let n = draws.len() as f64;
+        let mean = draws
+            .iter()
+            .fold(DVector::zeros(dim), |sum, x| sum + x)
+            / n;
+        let covariance = draws.iter().fold(DMatrix::zeros(dim, dim), |s, x| {
+            let delta = x - &mean;
+            s + &delta * delta.transpose()
+        }) / (n - 1.0);
+        AffineMap::new(mean, covariance)
+    }
+}
+
+impl Reparameterize for AffineMap {
+    fn forward(&self, x: &DVector<f64>) -> DVector<f64> {
+        self.chol
+            .solve_lower_triangular(&(x - &self.mean))
+            .expect("the Cholesky factor is invertible")
+    }
+
+    fn inverse(&self, z: &DVector<f64>) -> (DVector<f64>, f64) {
+        (&self.mean + &self.chol * z, self.ln_det)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn affine_map_inverts_and_standardizes() {
+        let draws: Vec<DVector<f64>> = (0..200)
+            .map(|i| {
+                let (a, b) = ((i % 10) as f64, (i / 10) as f64);
+                DVector::from_column_slice(2, &[a + 3.0, 2.0 * a + 0.5 * b])
+            })
+            .collect();
+        let map = AffineMap::from_draws(&draws).unwrap();
+
+        let zs: Vec<DVector<f64>> =
+            draws.iter().map(|x| map.forward(x)).collect();
+        let standardized = AffineMap::from_draws(&zs).unwrap();
+        let identity = DMatrix::<f64>::identity(2, 2);
+        assert!((standardized.chol - identity).amax() < 1E-9);
+        assert!(standardized.mean.amax() < 1E-9);
+
+        let (x, ln_det) = map.inverse(&zs[17]);
+        assert!((x - &draws[17]).amax() < 1E-9);
+        let covariance = &map.chol * map.chol.transpose();
+        assert!((ln_det - 0.5 * covariance.determinant().ln()).abs() < 1E-9);
+
+        assert!(AffineMap::from_draws(&draws[..2]).is_err());
+        let singular = DMatrix::from_element(2, 2, 1.0);
+        assert!(AffineMap::new(DVector::zeros(2), singular).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! # Repeat
+//! Runs a stepper several times in each step of the stepper containing it.
+
+use std::io;
+use rand::Rng;
+use events::EventSink;
+use parameter::ParamId;
+use statistics::Statistic;
+use steppers::{AdaptationMode, AdaptationStatus, SteppingAlg, util};
+use steppers::adaptor::AdaptorState;
+
+/// Take `k` steps of the inner stepper for every step, e.g. several updates
+/// of a slowly mixing latent block per sweep of a `Group`
+///
+/// Every sub-step leaves the posterior invariant, so the nested stepper
+/// does too. The other steppers of a group are unaffected.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate rand;
+/// # use rmcmc::steppers::{Group, Mock, Repeat, SteppingAlg};
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # fn main() {
+/// #[derive(Clone, Copy, Debug)]
+/// struct Model {
+///     outer: u32,
+///     inner: u32,
+/// }
+///
+/// let outer = Mock::new(Model { outer: 0, inner: 0 }, |m: Model| Model {
+///     outer: m.outer + 1,
+///     ..m
+/// });
+/// let inner = Mock::new(Model { outer: 0, inner: 0 }, |m: Model| Model {
+///     inner: m.inner + 1,
+///     ..m
+/// });
+/// let mut sweep: Group<Model, StdRng> =
+///     Group::new(vec![Box::new(outer), Box::new(Repeat(inner, 5))]);
+///
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let m = sweep.step(&mut rng, Model { outer: 0, inner: 0 });
+/// assert_eq!((m.outer, m.inner), (1, 5));
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Repeat<A>(pub A, pub usize);
+
+impl<A, M, R> SteppingAlg<M, R> for Repeat<A>
+where
+    A: SteppingAlg<M, R>,
+    R: Rng,
+{
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        assert!(self.1 > 0, "repetitions must be greater than 0.");
+        let inner = &mut self.0;
+        (0..self.1).fold(model, |m, _| inner.step(rng, m))
+    }
+
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        self.0.set_adapt(mode)
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        self.0.get_adapt()
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        self.0.get_statistics()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        self.0.parameters()
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        self.0.dependencies()
+    }
+
+    fn reset(&mut self) {
+        self.0.reset()
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.0.invalidate_cache()
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.0.fix(parameter)
+    }
+
+    fn set_prior_cache(&mut self, cache: util::PriorCache) {
+        self.0.set_prior_cache(cache)
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.0.set_event_sink(sink)
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        self.0.draw_prior(rng, model)
+    }
+
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        self.0.adaptation_state()
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        self.0.restore_adaptation(states)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use events::{acceptance_rate, Event};
+    use lens::*;
+    use parameter::Parameter;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use steppers::SRWM;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Copy, Clone, Debug)]
+    struct Model {
+        x: f64,
+    }
+
+    #[test]
+    fn inner_stepper_takes_k_steps_per_step() {
+        let x = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let srwm = SRWM::new(x.clone(), |_m: &Model| 0.0, Some(1.0)).unwrap();
+        let mut repeated = Repeat(srwm, 4);
+        let sink = EventSink::new();
+        SteppingAlg::<Model, StdRng>::set_event_sink(
+            &mut repeated,
+            sink.clone(),
+        );
+        assert_eq!(
+            SteppingAlg::<Model, StdRng>::parameters(&repeated),
+            vec![x.id().clone()]
+        );
+
+        let mut rng = StdRng::from_seed(SEED);
+        let mut m = Model { x: 0.0 };
+        let mut total = 0.0;
+        for _ in 0..2000 {
+            m = repeated.step(&mut rng, m);
+            total += m.x;
+        }
+        let events = sink.events();
+        let proposals = events
+            .iter()
+            .filter(|e| match e {
+                Event::ProposalAccepted { .. } => true,
+                Event::ProposalRejected { .. } => true,
+                _ => false,
+            })
+            .count();
+        assert_eq!(proposals, 4 * 2000);
+        assert!(acceptance_rate(&events, x.id()).unwrap() > 0.0);
+        assert!((total / 2000.0).abs() < 0.15);
+    }
+}
@@ -0,0 +1,486 @@
+//! # Slice Sampling
+//! Univariate slice sampling with stepping out and shrinkage, as in Neal
+//! (2003).
+
+use std::fmt;
+use std::io;
+use rand::Rng;
+use rv::traits::Rv;
+
+use parameter::{Parameter, ParamId};
+use likelihood::DeltaLogLikelihood;
+use log_density::LogDensity;
+use steppers::{SteppingAlg, AdaptationStatus, AdaptationMode, util};
+use steppers::adaptor::{next_state, AdaptorState};
+use statistics::{Statistic, StatisticValue};
+use events::EventSink;
+
+/// Default most steps out of the initial interval on either side
+pub const DEFAULT_MAX_STEPS_OUT: usize = 32;
+/// Most shrinkages of the interval before a step gives up and stays put
+const MAX_SHRINKS: usize = 200;
+
+/// Slice sampler over an `f64` parameter
+///
+/// Each step draws a level under the posterior density at the current
+/// value, steps an interval of `width` out until both ends are below the
+/// level, then draws uniformly from the interval, shrinking it towards the
+/// current value after each draw outside the slice. Every step moves and
+/// there is no acceptance rate to tune, and a poor `width` costs extra
+/// evaluations rather than mixing.
+///
+/// While adaptation is enabled `width` follows twice the mean distance
+/// moved, a rough scale of the slices.
+pub struct SliceSampler<D, M, L>
+where
+    D: Rv<f64> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    pub parameter: Parameter<D, f64, M>,
+    pub log_likelihood: L,
+    pub width: f64,
+    pub max_steps_out: usize,
+    pub fixed: bool,
+    initial_width: f64,
+    adapt_width: bool,
+    adapting: bool,
+    // Steps taken while adapting, and the distance they moved in total
+    adapted_steps: usize,
+    distance: f64,
+    // Steps taken and the evaluations of the posterior they made
+    steps: usize,
+    evaluations: usize,
+    prior_cache: Option<util::PriorCache>,
+    events: Option<EventSink>,
+}
+
+impl<D, M, L> SliceSampler<D, M, L>
+where
+    D: Rv<f64> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    /// Slice sampler starting from intervals of `width`, which should be
+    /// about the scale of the posterior
+    pub fn new(
+        parameter: Parameter<D, f64, M>,
+        log_likelihood: L,
+        width: f64,
+    ) -> Self {
+        assert!(
+            width > 0.0 && width.is_finite(),
+            "slice width must be finite and positive."
+        );
+        SliceSampler {
+            parameter,
+            log_likelihood,
+            width,
+            max_steps_out: DEFAULT_MAX_STEPS_OUT,
+            fixed: false,
+            initial_width: width,
+            adapt_width: true,
+            adapting: false,
+            adapted_steps: 0,
+            distance: 0.0,
+            steps: 0,
+            evaluations: 0,
+            prior_cache: None,
+            events: None,
+        }
+    }
+
+    /// Step out at most `max_steps_out` widths in total from the initial
+    /// interval. Zero keeps the initial interval, which then must cover
+    /// most of the posterior.
+    pub fn max_steps_out(&self, max_steps_out: usize) -> Self {
+        SliceSampler {
+            max_steps_out,
+            ..(*self).clone()
+        }
+    }
+
+    /// Keep `width` during warmup instead of adapting it.
+    pub fn fixed_width(&self) -> Self {
+        SliceSampler {
+            adapt_width: false,
+            ..(*self).clone()
+        }
+    }
+
+    /// Mean number of evaluations of the posterior per step
+    pub fn mean_evaluations(&self) -> Option<f64> {
+        if self.steps == 0 {
+            None
+        } else {
+            Some(self.evaluations as f64 / self.steps as f64)
+        }
+    }
+
+    fn current_prior(&self, value: f64) -> f64 {
+        match self.prior_cache {
+            Some(ref cache) => cache
+                .get_or_insert_with(self.parameter.id(), || {
+                    self.parameter.prior.ln_f(&value)
+                }),
+            None => self.parameter.prior.ln_f(&value),
+        }
+    }
+}
+
+impl<D, M, L> Clone for SliceSampler<D, M, L>
+where
+    D: Rv<f64> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        SliceSampler {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            width: self.width,
+            max_steps_out: self.max_steps_out,
+            fixed: self.fixed,
+            initial_width: self.initial_width,
+            adapt_width: self.adapt_width,
+            adapting: self.adapting,
+            adapted_steps: self.adapted_steps,
+            distance: self.distance,
+            steps: self.steps,
+            evaluations: self.evaluations,
+            prior_cache: self.prior_cache.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<D, M, L> fmt::Debug for SliceSampler<D, M, L>
+where
+    D: Rv<f64> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SliceSampler {{ parameter: {:?}, width: {}, \
+             max_steps_out: {} }}",
+            self.parameter, self.width, self.max_steps_out
+        )
+    }
+}
+
+impl<D, M, L, R> SteppingAlg<M, R> for SliceSampler<D, M, L>
+where
+    D: Rv<f64> + Clone,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+    R: Rng,
+{
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        self.adapting = match mode {
+            AdaptationMode::Enabled => self.adapt_width,
+            AdaptationMode::Disabled => false,
+        };
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        if self.adapting {
+            AdaptationStatus::Enabled
+        } else {
+            AdaptationStatus::Disabled
+        }
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        vec![Statistic::new(
+            self.parameter.id().clone(),
+            StatisticValue::ProposalScale(self.width),
+        )]
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        vec![self.parameter.id().clone()]
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        vec![(self.parameter.id().clone(), self.parameter.dependencies.clone())]
+    }
+
+    fn reset(&mut self) {
+        self.width = self.initial_width;
+        self.adapted_steps = 0;
+        self.distance = 0.0;
+        self.steps = 0;
+        self.evaluations = 0;
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.fixed |= parameter == self.parameter.id();
+    }
+
+    fn set_prior_cache(&mut self, cache: util::PriorCache) {
+        self.prior_cache = Some(cache);
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        self.parameter.draw(&model, rng)
+    }
+
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        vec![AdaptorState {
+            name: self.parameter.name().to_string(),
+            ..AdaptorState::new(
+                vec![self.width],
+                vec![self.distance],
+                vec![self.adapted_steps],
+            )
+        }]
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        let state = next_state(states, self.parameter.name())?;
+        state.expect(1, 1, 1)?;
+        self.width = state.scales[0];
+        self.distance = state.moments[0];
+        self.adapted_steps = state.counts[0];
+        Ok(())
+    }
+
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        let x0 = match self.parameter.lens.try_get(&model) {
+            Ok(value) => value,
+            Err(err) => {
+                let id = self.parameter.id();
+                let events = &self.events;
+                return util::reject_lens_failure(events, id, &err, model);
+            }
+        };
+        let id = self.parameter.id();
+        let current_prior = self.current_prior(x0);
+        let current_score = LogDensity(current_prior)
+            .plus(|| self.log_likelihood.ln_f(&model))
+            .value();
+        self.steps += 1;
+        self.evaluations += 1;
+        if !current_score.is_finite() {
+            // No slice to draw from; leave the value for other steppers.
+            return model;
+        }
+
+        // Log posterior at `x` with its log prior, and the model there
+        let mut evaluations = 0;
+        let mut score = |x: f64| -> (f64, f64, Option<M>) {
+            evaluations += 1;
+            let candidate = match self.parameter.lens.try_set(&model, x) {
+                Ok(candidate) => candidate,
+                Err(_) => return (::std::f64::NEG_INFINITY, 0.0, None),
+            };
+            let prior = self.parameter.prior.ln_f(&x);
+            let score = util::proposal_score(
+                &self.log_likelihood,
+                id,
+                &model,
+                current_score,
+                || current_prior,
+                &candidate,
+                prior,
+            );
+            (score, prior, Some(candidate))
+        };
+
+        let level = current_score + rng.gen::<f64>().ln();
+        let above = |s: f64| s > level;
+
+        // Step out
+        let w = self.width;
+        let mut lower = x0 - w * rng.gen::<f64>();
+        let mut upper = lower + w;
+        let mut left = (self.max_steps_out as f64 * rng.gen::<f64>()) as usize;
+        let mut right = self.max_steps_out.saturating_sub(left + 1);
+        while left > 0 && above(score(lower).0) {
+            lower -= w;
+            left -= 1;
+        }
+        while right > 0 && above(score(upper).0) {
+            upper += w;
+            right -= 1;
+        }
+
+        // Shrink
+        let mut next = None;
+        for _ in 0..MAX_SHRINKS {
+            let x1 = lower + (upper - lower) * rng.gen::<f64>();
+            let (s, prior, candidate) = score(x1);
+            if above(s) {
+                next = candidate.map(|m| (x1, prior, m));
+                break;
+            }
+            if x1 < x0 {
+                lower = x1;
+            } else {
+                upper = x1;
+            }
+        }
+        self.evaluations += evaluations;
+
+        match next {
+            Some((x1, prior, new_model)) => {
+                if self.adapting {
+                    self.adapted_steps += 1;
+                    self.distance += (x1 - x0).abs();
+                    let width = 2.0 * self.distance / self.adapted_steps as f64;
+                    if width > 0.0 && width.is_finite() {
+                        self.width = width;
+                    }
+                }
+                if let Some(ref cache) = self.prior_cache {
+                    cache.insert(id.clone(), prior);
+                }
+                new_model
+            }
+            None => model,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::*;
+    use runner::Runner;
+    use rv::dist::{Exponential, Gamma, Gaussian};
+    use rv::misc::ks_test;
+    use rv::prelude::Cdf;
+    use utils::multiple_tries;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    const P_VAL: f64 = 0.2;
+    const N_TRIES: usize = 10;
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Copy, Clone, Debug)]
+    struct Model {
+        x: f64,
+    }
+
+    fn passes_ks<D, C>(
+        sampler: SliceSampler<D, Model, fn(&Model) -> f64>,
+        target: C,
+    ) -> bool
+    where
+        D: 'static + Rv<f64> + Clone + Send + Sync,
+        C: Cdf<f64>,
+    {
+        let mut rng = StdRng::from_seed(SEED);
+        multiple_tries(N_TRIES, |_| {
+            let draws = Runner::new(sampler.clone())
+                .warmup(500)
+                .samples(1000)
+                .thinning(3)
+                .run(&mut rng, Model { x: 1.0 });
+            let samples: Vec<f64> = draws[0].iter().map(|m| m.x).collect();
+            let (stat, p) = ks_test(&samples, |s| target.cdf(&s));
+            println!("test stat = {}, p = {}", stat, p);
+            p > P_VAL
+        })
+    }
+
+    #[test]
+    fn gaussian_posterior_from_a_poor_width() {
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 10.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        fn log_likelihood(m: &Model) -> f64 {
+            Gaussian::new(2.0, 0.5).unwrap().ln_f(&m.x)
+        }
+        // The posterior of a Gaussian prior and likelihood
+        let precision = 1.0 / 100.0 + 1.0 / 0.25;
+        let mean = (2.0 / 0.25) / precision;
+        let posterior = Gaussian::new(mean, precision.recip().sqrt()).unwrap();
+
+        let sampler = SliceSampler::new(
+            parameter,
+            log_likelihood as fn(&Model) -> f64,
+            100.0,
+        );
+        assert!(passes_ks(sampler, posterior));
+    }
+
+    #[test]
+    fn bounded_support_is_respected() {
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Exponential::new(1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        fn log_likelihood(m: &Model) -> f64 {
+            // A Gamma(3, 1) posterior with the exponential prior
+            2.0 * m.x.ln()
+        }
+        let sampler = SliceSampler::new(
+            parameter,
+            log_likelihood as fn(&Model) -> f64,
+            1.0,
+        );
+        assert!(passes_ks(sampler, Gamma::new(3.0, 1.0).unwrap()));
+    }
+
+    #[test]
+    fn width_adapts_to_the_posterior_scale() {
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let mut rng = StdRng::from_seed(SEED);
+        let mut sampler = SliceSampler::new(parameter, |_: &Model| 0.0, 0.01);
+        let mut m = Model { x: 0.0 };
+
+        SteppingAlg::<Model, StdRng>::set_adapt(
+            &mut sampler,
+            AdaptationMode::Enabled,
+        );
+        for _ in 0..2000 {
+            m = sampler.step(&mut rng, m);
+        }
+        assert!(sampler.width > 1.0 && sampler.width < 3.0);
+        let adapted = sampler.mean_evaluations().unwrap();
+
+        SteppingAlg::<Model, StdRng>::set_adapt(
+            &mut sampler,
+            AdaptationMode::Disabled,
+        );
+        let width = sampler.width;
+        m = sampler.step(&mut rng, m);
+        assert_eq!(sampler.width, width);
+        assert!(m.x.is_finite());
+        assert!(adapted > 2.0);
+
+        let mut fixed = sampler.fixed_width();
+        SteppingAlg::<Model, StdRng>::reset(&mut fixed);
+        SteppingAlg::<Model, StdRng>::set_adapt(
+            &mut fixed,
+            AdaptationMode::Enabled,
+        );
+        assert_eq!(fixed.width, 0.01);
+        fixed.step(&mut rng, m);
+        assert_eq!(fixed.width, 0.01);
+    }
+}
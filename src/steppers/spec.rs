@@ -0,0 +1,794 @@
+//! # Stepper Specifications
+//! Stepper configurations as plain data, e.g. read from a configuration file
+//! or recorded with a run, and a registry building steppers from them.
+//!
+//! Likelihoods and lenses are code and so not part of a specification.
+//! They are registered with a `Registry` under their parameter's name, and
+//! specifications refer to parameters by that name.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use nalgebra::DVector;
+use rand::Rng;
+
+use rv::traits::{Mean, Rv, Variance};
+use lens::McmcModel;
+use likelihood::DeltaLogLikelihood;
+use parameter::{Parameter, ParamId};
+use statistics::Statistic;
+use events::EventSink;
+use utils::invalid_input;
+use steppers::adaptor::AdaptorState;
+use steppers::{
+    AdaptationMode, AdaptationStatus, BinaryGibbsMetropolisBuilder,
+    BinaryMetropolisBuilder, BoxedStepper, IntoBoxedStepper, NoiseKernel,
+    ProposalKernel, ProposalMode, SteppingAlg, VectorSRWM, SRWM, util,
+};
+
+/// Configuration of a stepper
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum StepperSpec {
+    /// `SRWM` on a scalar parameter
+    Srwm {
+        parameter: String,
+        /// Initial proposal scale, adapted during warmup
+        proposal_scale: Option<f64>,
+        /// Proposal scale which is never adapted, overriding
+        /// `proposal_scale`
+        fixed_scale: Option<f64>,
+        bounds: Option<(f64, f64)>,
+        #[cfg_attr(feature = "serde_support", serde(default))]
+        kernel: ProposalKernel,
+    },
+    /// `VectorSRWM` on a vector parameter
+    VectorSrwm {
+        parameter: String,
+        proposal_scales: Vec<f64>,
+        #[cfg_attr(feature = "serde_support", serde(default))]
+        mode: ProposalMode,
+        #[cfg_attr(feature = "serde_support", serde(default))]
+        noise: NoiseKernel,
+        /// Adapt each coordinate's proposal scale during warmup
+        #[cfg_attr(feature = "serde_support", serde(default))]
+        diagonal_adaptation: bool,
+    },
+    /// `BinaryMetropolis` on a binary vector parameter
+    BinaryMetropolis { parameter: String },
+    /// `BinaryGibbsMetropolis` on a binary vector parameter
+    BinaryGibbsMetropolis { parameter: String },
+    /// Steppers applied in turn each step
+    Group(Vec<StepperSpec>),
+}
+
+impl StepperSpec {
+    /// `SRWM` with the default settings
+    pub fn srwm(parameter: &str) -> Self {
+        StepperSpec::Srwm {
+            parameter: parameter.to_string(),
+            proposal_scale: None,
+            fixed_scale: None,
+            bounds: None,
+            kernel: ProposalKernel::Gaussian,
+        }
+    }
+
+    /// Names of the parameters the specification updates
+    pub fn parameters(&self) -> Vec<String> {
+        match self {
+            StepperSpec::Srwm { parameter, .. }
+            | StepperSpec::VectorSrwm { parameter, .. }
+            | StepperSpec::BinaryMetropolis { parameter }
+            | StepperSpec::BinaryGibbsMetropolis { parameter } => {
+                vec![parameter.clone()]
+            }
+            StepperSpec::Group(specs) => {
+                specs.iter().flat_map(|s| s.parameters()).collect()
+            }
+        }
+    }
+
+    /// Check the settings of every stepper in the specification, e.g.
+    /// before building it from a configuration file. Fails with
+    /// `InvalidInput` on settings the steppers' builders would panic on.
+    pub fn validate(&self) -> io::Result<()> {
+        let positive = |s: f64| s > 0.0 && s.is_finite();
+        match self {
+            StepperSpec::Srwm {
+                parameter,
+                proposal_scale,
+                fixed_scale,
+                bounds,
+                kernel,
+            } => {
+                let mut scales = proposal_scale.iter().chain(fixed_scale);
+                if !scales.all(|&s| positive(s)) {
+                    return Err(invalid_input(format!(
+                        "proposal scale of {} must be finite and positive",
+                        parameter
+                    )));
+                }
+                if let Some((lower, upper)) = bounds {
+                    if !(lower < upper) {
+                        return Err(invalid_input(format!(
+                            "lower bound of {} must be less than its upper \
+                             bound",
+                            parameter
+                        )));
+                    }
+                }
+                if let ProposalKernel::StudentT(df) = kernel {
+                    if !(*df > 0.0) {
+                        return Err(invalid_input(format!(
+                            "StudentT degrees of freedom for {} must be \
+                             positive",
+                            parameter
+                        )));
+                    }
+                }
+                Ok(())
+            }
+            StepperSpec::VectorSrwm {
+                parameter,
+                proposal_scales,
+                ..
+            } => {
+                if proposal_scales.iter().all(|&s| positive(s)) {
+                    Ok(())
+                } else {
+                    Err(invalid_input(format!(
+                        "proposal scales of {} must be finite and positive",
+                        parameter
+                    )))
+                }
+            }
+            StepperSpec::BinaryMetropolis { .. }
+            | StepperSpec::BinaryGibbsMetropolis { .. } => Ok(()),
+            StepperSpec::Group(specs) => {
+                specs.iter().map(|s| s.validate()).collect()
+            }
+        }
+    }
+}
+
+type Factory<M, R> =
+    Arc<dyn Fn(&StepperSpec) -> io::Result<BoxedStepper<M, R>> + Send + Sync>;
+
+/// Parameters, with their likelihoods, which specifications can refer to
+pub struct Registry<M, R: Rng> {
+    factories: BTreeMap<String, Factory<M, R>>,
+}
+
+impl<M, R: Rng> Clone for Registry<M, R> {
+    fn clone(&self) -> Self {
+        Registry {
+            factories: self.factories.clone(),
+        }
+    }
+}
+
+impl<M, R: Rng> fmt::Debug for Registry<M, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&String> = self.factories.keys().collect();
+        write!(f, "Registry {{ parameters: {:?} }}", names)
+    }
+}
+
+impl<M, R> Default for Registry<M, R>
+where
+    M: 'static + Clone + fmt::Debug,
+    R: 'static + Rng,
+{
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+impl<M, R> Registry<M, R>
+where
+    M: 'static + Clone + fmt::Debug,
+    R: 'static + Rng,
+{
+    pub fn new() -> Self {
+        Registry {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    /// Names of the registered parameters
+    pub fn parameters(&self) -> Vec<String> {
+        self.factories.keys().cloned().collect()
+    }
+
+    fn insert(&mut self, name: String, factory: Factory<M, R>) {
+        self.factories.insert(name, factory);
+    }
+
+    /// Register a scalar parameter, updated by `Srwm` specifications.
+    pub fn scalar<D, L>(
+        &mut self,
+        parameter: Parameter<D, f64, M>,
+        log_likelihood: L,
+    ) where
+        D: 'static
+            + Rv<f64>
+            + Variance<f64>
+            + Mean<f64>
+            + Clone
+            + fmt::Debug
+            + Send
+            + Sync,
+        L: 'static + DeltaLogLikelihood<M> + Clone + Sync + Send,
+    {
+        let name = parameter.name().to_string();
+        self.insert(
+            name.clone(),
+            Arc::new(move |spec| match spec {
+                StepperSpec::Srwm {
+                    proposal_scale,
+                    fixed_scale,
+                    bounds,
+                    kernel,
+                    ..
+                } => {
+                    let mut srwm = SRWM::new(
+                        parameter.clone(),
+                        log_likelihood.clone(),
+                        *proposal_scale,
+                    )
+                    .ok_or_else(|| {
+                        invalid_input(format!(
+                            "prior of {} lacks a mean or variance",
+                            name
+                        ))
+                    })?
+                    .kernel(*kernel);
+                    if let Some(scale) = fixed_scale {
+                        srwm = srwm.proposal_scale(*scale);
+                    }
+                    if let Some((lower, upper)) = bounds {
+                        srwm = srwm.bounded(*lower, *upper);
+                    }
+                    Ok(srwm.boxed())
+                }
+                _ => Err(invalid_input(format!(
+                    "{} is a scalar parameter, updated only by Srwm",
+                    name
+                ))),
+            }),
+        );
+    }
+
+    /// Register a vector parameter, updated by `VectorSrwm` specifications.
+    pub fn vector<D, L>(
+        &mut self,
+        parameter: Parameter<D, DVector<f64>, M>,
+        log_likelihood: L,
+    ) where
+        D: 'static + Rv<DVector<f64>> + Clone + Send + Sync,
+        L: 'static + DeltaLogLikelihood<M> + Clone + Sync + Send,
+    {
+        let name = parameter.name().to_string();
+        self.insert(
+            name.clone(),
+            Arc::new(move |spec| match spec {
+                StepperSpec::VectorSrwm {
+                    proposal_scales,
+                    mode,
+                    noise,
+                    diagonal_adaptation,
+                    ..
+                } => {
+                    let scales = DVector::from_column_slice(
+                        proposal_scales.len(),
+                        proposal_scales,
+                    );
+                    let mut stepper = VectorSRWM::new(
+                        parameter.clone(),
+                        log_likelihood.clone(),
+                        scales,
+                    );
+                    stepper.mode = *mode;
+                    stepper.noise = *noise;
+                    if *diagonal_adaptation {
+                        stepper = stepper.diagonal_adaptation();
+                    }
+                    Ok(stepper.boxed())
+                }
+                _ => Err(invalid_input(format!(
+                    "{} is a vector parameter, updated only by VectorSrwm",
+                    name
+                ))),
+            }),
+        );
+    }
+
+    /// Register a binary vector parameter, updated by `BinaryMetropolis`
+    /// and `BinaryGibbsMetropolis` specifications.
+    pub fn binary<D, L>(
+        &mut self,
+        parameter: Parameter<D, Vec<bool>, M>,
+        log_likelihood: L,
+    ) where
+        D: 'static + Rv<Vec<bool>> + Clone + fmt::Debug + Send + Sync,
+        L: 'static + DeltaLogLikelihood<M> + Clone + Sync + Send,
+    {
+        let name = parameter.name().to_string();
+        self.insert(
+            name.clone(),
+            Arc::new(move |spec| match spec {
+                StepperSpec::BinaryMetropolis { .. } => {
+                    let stepper = BinaryMetropolisBuilder::new(
+                        parameter.clone(),
+                        log_likelihood.clone(),
+                    )
+                    .build()?;
+                    Ok(stepper.boxed())
+                }
+                StepperSpec::BinaryGibbsMetropolis { .. } => {
+                    let stepper = BinaryGibbsMetropolisBuilder::new(
+                        parameter.clone(),
+                        log_likelihood.clone(),
+                    )
+                    .build();
+                    Ok(stepper.boxed())
+                }
+                _ => Err(invalid_input(format!(
+                    "{} is a binary parameter, updated only by \
+                     BinaryMetropolis or BinaryGibbsMetropolis",
+                    name
+                ))),
+            }),
+        );
+    }
+
+    fn build_into(
+        &self,
+        spec: &StepperSpec,
+        steppers: &mut Vec<BoxedStepper<M, R>>,
+    ) -> io::Result<()> {
+        match spec {
+            StepperSpec::Group(specs) => specs
+                .iter()
+                .map(|s| self.build_into(s, steppers))
+                .collect(),
+            StepperSpec::Srwm { parameter, .. }
+            | StepperSpec::VectorSrwm { parameter, .. }
+            | StepperSpec::BinaryMetropolis { parameter }
+            | StepperSpec::BinaryGibbsMetropolis { parameter } => {
+                let factory =
+                    self.factories.get(parameter).ok_or_else(|| {
+                        invalid_input(format!(
+                            "unknown parameter {}",
+                            parameter
+                        ))
+                    })?;
+                steppers.push(factory(spec)?);
+                Ok(())
+            }
+        }
+    }
+
+    /// Build the stepper `spec` describes. Fails if it refers to a parameter
+    /// which is not registered or with a stepper unsuited to its kind, or
+    /// if its settings are invalid.
+    pub fn build(&self, spec: &StepperSpec) -> io::Result<SpecStepper<M, R>> {
+        spec.validate()?;
+        let mut steppers = Vec::new();
+        self.build_into(spec, &mut steppers)?;
+
+        let prior_cache = util::PriorCache::for_steppers(
+            steppers.iter().map(|s| s.parameters()),
+        );
+        if let Some(ref cache) = prior_cache {
+            steppers
+                .iter_mut()
+                .for_each(|s| s.set_prior_cache(cache.clone()));
+        }
+        Ok(SpecStepper {
+            spec: spec.clone(),
+            steppers,
+            prior_cache,
+        })
+    }
+}
+
+impl<M, R> Registry<M, R>
+where
+    M: 'static + Clone + fmt::Debug + McmcModel,
+    R: 'static + Rng,
+{
+    /// Fields of the model which `spec` does not update, i.e. which stay
+    /// at their initial values, by the convention that parameters are
+    /// named after the fields they update
+    pub fn unsampled(&self, spec: &StepperSpec) -> Vec<&'static str> {
+        let sampled = spec.parameters();
+        M::fields()
+            .iter()
+            .cloned()
+            .filter(|field| !sampled.iter().any(|p| p == field))
+            .collect()
+    }
+
+    /// As `build`, also failing if any field of the model is left
+    /// unsampled, catching parameters left out of the specification or
+    /// never registered. Fields which are data should be declared in the
+    /// `data` section of `mcmc_model!`.
+    pub fn build_complete(
+        &self,
+        spec: &StepperSpec,
+    ) -> io::Result<SpecStepper<M, R>> {
+        let unsampled = self.unsampled(spec);
+        if !unsampled.is_empty() {
+            return Err(invalid_input(format!(
+                "fields {} are not sampled",
+                unsampled.join(", ")
+            )));
+        }
+        self.build(spec)
+    }
+}
+
+/// A stepper built from a `StepperSpec`
+///
+/// Grouped steppers are applied in turn each step, sharing prior scores
+/// within a sweep as in `Group`. Unlike `Group` it can be cloned, so it can
+/// be used with `Runner`.
+pub struct SpecStepper<M, R: Rng> {
+    spec: StepperSpec,
+    steppers: Vec<BoxedStepper<M, R>>,
+    prior_cache: Option<util::PriorCache>,
+}
+
+impl<M, R: Rng> SpecStepper<M, R> {
+    /// The specification this stepper was built from
+    pub fn spec(&self) -> &StepperSpec {
+        &self.spec
+    }
+}
+
+impl<M, R: Rng> Clone for SpecStepper<M, R> {
+    fn clone(&self) -> Self {
+        let mut cloned = SpecStepper {
+            spec: self.spec.clone(),
+            steppers: self.steppers.clone(),
+            prior_cache: self.prior_cache.as_ref().map(|_| {
+                util::PriorCache::new()
+            }),
+        };
+        if let Some(cache) = cloned.prior_cache.clone() {
+            cloned
+                .steppers
+                .iter_mut()
+                .for_each(|s| s.set_prior_cache(cache.clone()));
+        }
+        cloned
+    }
+}
+
+impl<M, R: Rng> fmt::Debug for SpecStepper<M, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SpecStepper {{ spec: {:?} }}", self.spec)
+    }
+}
+
+impl<M, R: Rng> SteppingAlg<M, R> for SpecStepper<M, R> {
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        if let Some(ref cache) = self.prior_cache {
+            cache.clear();
+        }
+        // As in `Group`, earlier steppers may have moved parameters the
+        // likelihood of later ones reads.
+        let sweep = self.steppers.len() > 1;
+        self.steppers.iter_mut().fold(model, |m, stepper| {
+            if sweep {
+                stepper.invalidate_cache();
+            }
+            stepper.step(rng, m)
+        })
+    }
+
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        self.steppers.iter_mut().for_each(|s| s.set_adapt(mode));
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        let statuses: Vec<AdaptationStatus> =
+            self.steppers.iter().map(|s| s.get_adapt()).collect();
+        let all = |f: fn(&AdaptationStatus) -> bool| statuses.iter().all(f);
+        if all(|s| match s {
+            AdaptationStatus::Enabled => true,
+            _ => false,
+        }) {
+            AdaptationStatus::Enabled
+        } else if all(|s| match s {
+            AdaptationStatus::Disabled => true,
+            _ => false,
+        }) {
+            AdaptationStatus::Disabled
+        } else {
+            AdaptationStatus::Mixed
+        }
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        self.steppers
+            .iter()
+            .flat_map(|s| s.get_statistics())
+            .collect()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        self.steppers.iter().flat_map(|s| s.parameters()).collect()
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        self.steppers.iter().flat_map(|s| s.dependencies()).collect()
+    }
+
+    fn reset(&mut self) {
+        if let Some(ref cache) = self.prior_cache {
+            cache.clear();
+        }
+        self.steppers.iter_mut().for_each(|s| s.reset());
+    }
+
+    fn invalidate_cache(&mut self) {
+        if let Some(ref cache) = self.prior_cache {
+            cache.clear();
+        }
+        self.steppers.iter_mut().for_each(|s| s.invalidate_cache());
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.steppers.iter_mut().for_each(|s| s.fix(parameter));
+    }
+
+    fn set_prior_cache(&mut self, cache: util::PriorCache) {
+        self.prior_cache = Some(cache.clone());
+        self.steppers
+            .iter_mut()
+            .for_each(|s| s.set_prior_cache(cache.clone()));
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.steppers
+            .iter_mut()
+            .for_each(|s| s.set_event_sink(sink.clone()));
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        self.steppers
+            .iter()
+            .fold(model, |m, stepper| stepper.draw_prior(rng, m))
+    }
+
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        self.steppers
+            .iter()
+            .flat_map(|stepper| stepper.adaptation_state())
+            .collect()
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        for stepper in self.steppers.iter_mut() {
+            stepper.restore_adaptation(states)?;
+        }
+        Ok(())
+    }
+
+    fn spec(&self) -> Option<StepperSpec> {
+        Some(self.spec.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lens::Lens;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use runner::Runner;
+    use rv::dist::{Gaussian, MvGaussian};
+
+    const SEED: [u8; 32] = [0; 32];
+
+    mcmc_model! {
+        #[derive(Clone, Debug)]
+        struct Model {
+            mu: f64,
+            sigma: f64,
+            z: DVector<f64>,
+        }
+        data {
+            scale: f64,
+        }
+    }
+
+    fn log_likelihood(m: &Model) -> f64 {
+        let (mu, sigma) = (m.mu / m.scale, m.sigma / m.scale);
+        -0.5 * (mu - 1.0).powi(2) - 0.5 * (sigma - 2.0).powi(2)
+    }
+
+    fn registry() -> Registry<Model, StdRng> {
+        let mut registry = Registry::new();
+        registry.scalar(
+            Parameter::new(
+                "mu".to_string(),
+                Gaussian::new(0.0, 1.0).unwrap(),
+                Model::mu(),
+            ),
+            log_likelihood,
+        );
+        registry.scalar(
+            Parameter::new(
+                "sigma".to_string(),
+                Gaussian::new(0.0, 1.0).unwrap(),
+                Lens::new(|m: &Model| m.sigma, |m: &Model, sigma| Model {
+                    sigma,
+                    ..m.clone()
+                }),
+            ),
+            log_likelihood,
+        );
+        registry.vector(
+            Parameter::new(
+                "z".to_string(),
+                MvGaussian::standard(2).unwrap(),
+                Lens::new(|m: &Model| m.z.clone(), |m: &Model, z| Model {
+                    z,
+                    ..m.clone()
+                }),
+            ),
+            log_likelihood,
+        );
+        registry
+    }
+
+    fn init() -> Model {
+        Model {
+            mu: 0.0,
+            sigma: 1.0,
+            z: DVector::zeros(2),
+            scale: 1.0,
+        }
+    }
+
+    #[test]
+    fn builds_groups_usable_by_runner() {
+        let spec = StepperSpec::Group(vec![
+            StepperSpec::srwm("mu"),
+            StepperSpec::Srwm {
+                parameter: "sigma".to_string(),
+                proposal_scale: None,
+                fixed_scale: Some(0.5),
+                bounds: Some((0.0, std::f64::INFINITY)),
+                kernel: ProposalKernel::StudentT(3.0),
+            },
+            StepperSpec::VectorSrwm {
+                parameter: "z".to_string(),
+                proposal_scales: vec![0.5, 0.5],
+                mode: ProposalMode::ElementWise(1),
+                noise: NoiseKernel::White,
+                diagonal_adaptation: true,
+            },
+        ]);
+        assert_eq!(spec.parameters(), vec!["mu", "sigma", "z"]);
+
+        let stepper = registry().build(&spec).unwrap();
+        assert_eq!(stepper.spec(), &spec);
+        assert_eq!(stepper.parameters().len(), 3);
+
+        let mut rng = StdRng::from_seed(SEED);
+        let draws = Runner::new(stepper)
+            .warmup(10)
+            .samples(50)
+            .run(&mut rng, init());
+        let chain = &draws[0];
+        assert!(chain.iter().any(|m| m.mu != 0.0));
+        assert!(chain.iter().all(|m| m.sigma >= 0.0));
+        assert!(chain.iter().any(|m| m.z[1] != 0.0));
+    }
+
+    #[test]
+    fn provenance_records_the_spec() {
+        let spec = StepperSpec::Group(vec![
+            StepperSpec::srwm("mu"),
+            StepperSpec::srwm("sigma"),
+        ]);
+        let stepper = registry().build(&spec).unwrap();
+        let mut rng = StdRng::from_seed(SEED);
+        let sample = Runner::new(stepper)
+            .warmup(10)
+            .samples(10)
+            .sample(&mut rng, init());
+        let provenance = &sample.provenance[0];
+        assert_eq!(provenance.spec, Some(spec));
+
+        #[cfg(feature = "config")]
+        {
+            use runner::Provenance;
+            let yaml = serde_yaml::to_string(provenance).unwrap();
+            let read: Provenance = serde_yaml::from_str(&yaml).unwrap();
+            assert_eq!(&read, provenance);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_or_mismatched_parameters() {
+        let registry = registry();
+        let unknown = registry.build(&StepperSpec::srwm("nu")).unwrap_err();
+        assert_eq!(unknown.kind(), io::ErrorKind::InvalidInput);
+
+        let mismatched = StepperSpec::BinaryMetropolis {
+            parameter: "mu".to_string(),
+        };
+        assert!(registry.build(&mismatched).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_srwm_settings() {
+        let registry = registry();
+        let srwm = |fixed_scale, bounds, kernel| StepperSpec::Srwm {
+            parameter: "mu".to_string(),
+            proposal_scale: None,
+            fixed_scale,
+            bounds,
+            kernel,
+        };
+        let gaussian = ProposalKernel::Gaussian;
+        let invalid = vec![
+            srwm(None, Some((1.0, 0.0)), gaussian),
+            srwm(None, Some((0.0, std::f64::NAN)), gaussian),
+            srwm(None, None, ProposalKernel::StudentT(0.0)),
+            srwm(Some(-1.0), None, gaussian),
+        ];
+        let vector = StepperSpec::VectorSrwm {
+            parameter: "z".to_string(),
+            proposal_scales: vec![0.5, 0.0],
+            mode: ProposalMode::Joint,
+            noise: NoiseKernel::White,
+            diagonal_adaptation: false,
+        };
+        let group =
+            StepperSpec::Group(vec![StepperSpec::srwm("sigma"), vector]);
+        for spec in invalid.iter().chain(Some(&group)) {
+            let err = registry.build(spec).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        let valid = srwm(Some(0.5), Some((-1.0, 1.0)), gaussian);
+        assert!(registry.build(&valid).is_ok());
+    }
+
+    #[test]
+    fn build_complete_names_unsampled_fields() {
+        let registry = registry();
+        let partial = StepperSpec::Group(vec![
+            StepperSpec::srwm("mu"),
+            StepperSpec::srwm("sigma"),
+        ]);
+        assert_eq!(registry.unsampled(&partial), vec!["z"]);
+        let err = registry.build_complete(&partial).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "fields z are not sampled");
+
+        let complete = StepperSpec::Group(vec![
+            StepperSpec::srwm("mu"),
+            StepperSpec::srwm("sigma"),
+            StepperSpec::VectorSrwm {
+                parameter: "z".to_string(),
+                proposal_scales: vec![0.5, 0.5],
+                mode: ProposalMode::Joint,
+                noise: NoiseKernel::White,
+                diagonal_adaptation: false,
+            },
+        ]);
+        assert!(registry.unsampled(&complete).is_empty());
+        assert!(registry.build_complete(&complete).is_ok());
+    }
+}
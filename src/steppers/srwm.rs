@@ -1,18 +1,133 @@
 //! Symmetric Random Walk Metropolis
 
 use std::fmt;
+use std::io;
+use std::marker::PhantomData;
 extern crate rand;
 use rand::Rng;
+use rand::distributions::StandardNormal;
+use special::Gamma;
 
-use rv::dist::{Gaussian, Geometric};
+use rv::dist::{Cauchy, StudentsT};
 use rv::traits::{Mean, Rv, Variance};
 
-use parameter::Parameter;
+use parameter::{Parameter, ParamId};
+use likelihood::DeltaLogLikelihood;
 use steppers::{SteppingAlg, AdaptationStatus, AdaptationMode, util};
-use statistics::Statistic;
-use steppers::adaptor::{ScaleAdaptor, GlobalAdaptor};
+use steppers::tempering::Tempering;
+use statistics::{Statistic, StatisticValue};
+use events::EventSink;
+use steppers::adaptor::{
+    next_state, AdaptorState, ScaleAdaptor, GlobalAdaptor, FixedAdaptor,
+    DiscreteAdaptor, DISCRETE_TARGET_ACCEPTANCE,
+};
+
+pub trait RWT: fmt::Debug + Clone + Copy + 'static {}
+
+/// Symmetric kernel from which continuous proposal increments are drawn
+///
+/// Increments are a standard draw from the kernel multiplied by the
+/// adaptor's proposal scale, so the scale is the kernel's scale parameter
+/// (the standard deviation only for `Gaussian`).
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum ProposalKernel {
+    Gaussian,
+    /// Student's t with the given degrees of freedom
+    StudentT(f64),
+    Cauchy,
+}
+
+impl Default for ProposalKernel {
+    fn default() -> Self {
+        ProposalKernel::Gaussian
+    }
+}
+
+impl ProposalKernel {
+    /// Draw a standard (unit scale, zero location) increment.
+    pub fn draw<R: Rng>(&self, rng: &mut R) -> f64 {
+        match self {
+            ProposalKernel::Gaussian => rng.sample(StandardNormal),
+            ProposalKernel::StudentT(df) => StudentsT::new(*df)
+                .expect("StudentT kernel requires positive degrees of freedom")
+                .draw(rng),
+            ProposalKernel::Cauchy => Cauchy::new(0.0, 1.0).unwrap().draw(rng),
+        }
+    }
+}
+
+/// Distribution of the magnitudes of integer proposal steps
+///
+/// Steps are a magnitude drawn from the kernel at the adaptor's proposal
+/// scale, moved up or down at random. Near a bound of the parameter's
+/// support, steps are drawn from the kernel truncated to the support and
+/// the acceptance ratio corrected for the asymmetry this introduces, so
+/// chains move along the bound instead of proposing outside it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum IntegerKernel {
+    /// Geometric magnitudes, favouring short steps with occasional long
+    /// ones
+    Geometric,
+    /// Poisson magnitudes, whose second moment is the square of the
+    /// proposal scale, concentrating steps around the scale
+    Poisson,
+}
 
-pub trait RWT: fmt::Debug + Clone + Copy {}
+impl Default for IntegerKernel {
+    fn default() -> Self {
+        IntegerKernel::Geometric
+    }
+}
+
+impl IntegerKernel {
+    /// Probability of a step magnitude of at most `max`, which may be
+    /// infinite
+    fn cdf(self, scale: f64, max: f64) -> f64 {
+        match self {
+            IntegerKernel::Geometric => {
+                1.0 - (1.0 - geometric_p(scale)).powf(max + 1.0)
+            }
+            IntegerKernel::Poisson => {
+                let rate = poisson_rate(scale);
+                if max >= poisson_cutoff(rate) {
+                    1.0
+                } else {
+                    (0..=max as u64)
+                        .map(|k| poisson_pmf(rate, k))
+                        .sum::<f64>()
+                        .min(1.0)
+                }
+            }
+        }
+    }
+
+    /// Draw a step magnitude of at most `max` by inverting the CDF.
+    fn draw<R: Rng>(self, rng: &mut R, scale: f64, max: f64) -> f64 {
+        let u = rng.gen::<f64>() * self.cdf(scale, max);
+        match self {
+            IntegerKernel::Geometric => {
+                // The smallest m with 1 - (1 - p)^(m + 1) >= u
+                let p = geometric_p(scale);
+                let m = ((-u).ln_1p() / (-p).ln_1p()).ceil() - 1.0;
+                m.max(0.0).min(max)
+            }
+            IntegerKernel::Poisson => {
+                let rate = poisson_rate(scale);
+                let last = max.min(poisson_cutoff(rate)) as u64;
+                let mut cdf = 0.0;
+                for k in 0..last {
+                    cdf += poisson_pmf(rate, k);
+                    if cdf >= u {
+                        return k as f64;
+                    }
+                }
+                last as f64
+            }
+        }
+    }
+}
 
 
 /// Symmetric Random Walk Metropolis Stepping Algorithm
@@ -21,15 +136,29 @@ where
     D: Rv<T> + Variance<V> + Mean<T> + Clone + fmt::Debug,
     T: RWT,
     M: 'static + Clone + fmt::Debug,
-    L: Fn(&M) -> f64 + Clone + Sync,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
     V: Clone + fmt::Debug
 {
     pub parameter: Parameter<D, T, M>,
     pub log_likelihood: L,
-    pub current_score: Option<f64>,
+    pub mh: util::MHCore,
+    /// Temperature `T` of the target `p(θ) L(θ)^(1 / T)`, 1 for the
+    /// posterior; see `Tempering`
     pub temperature: f64,
-    pub log_acceptance: f64,
-    adaptor: GlobalAdaptor<T, V>
+    pub bounds: Option<(f64, f64)>,
+    pub kernel: ProposalKernel,
+    pub integer_kernel: IntegerKernel,
+    pub mode_jumps: Option<util::ModeJumps>,
+    pub fixed: bool,
+    prior_cache: Option<util::PriorCache>,
+    // Whether to report each step's proposal as a statistic
+    emit_proposals: bool,
+    // Value and proposal of the last step, when emitting proposals
+    last_proposal: Option<(f64, f64)>,
+    proposal_cache: Option<util::ProposalCache>,
+    events: Option<EventSink>,
+    adaptor: Box<dyn ScaleAdaptor<T>>,
+    phantom_v: PhantomData<V>,
 }
 
 impl <D, T, V, M, L> fmt::Debug for SRWM<D, T, V, M, L>
@@ -37,11 +166,11 @@ where
     D: Rv<T> + Variance<V> + Mean<T> + Clone + fmt::Debug,
     T: RWT,
     M: 'static + Clone + fmt::Debug,
-    L: Fn(&M) -> f64 + Clone + Sync,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
     V: Clone + fmt::Debug
 { 
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "SRWM {{ parameter: {:?}, current_score: {:?}, adaptor: {:?} }}", self.parameter, self.current_score, self.adaptor)
+        write!(f, "SRWM {{ parameter: {:?}, current_score: {:?}, adaptor: {:?} }}", self.parameter, self.mh.current_score, self.adaptor)
     }
 }
 
@@ -51,8 +180,9 @@ where
     D: Rv<T> + Variance<V> + Mean<T> + Clone + fmt::Debug,
     T: RWT,
     M: 'static + Clone + fmt::Debug,
-    L: Fn(&M) -> f64 + Clone + Sync,
-    V: Clone + fmt::Debug + Copy
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+    V: Clone + fmt::Debug + Copy,
+    GlobalAdaptor<T, V>: ScaleAdaptor<T> + 'static
 {
     pub fn new(
         parameter: Parameter<D, T, M>,
@@ -71,34 +201,236 @@ where
         Some(SRWM {
             parameter,
             log_likelihood,
-            current_score: None,
-            log_acceptance: 0.0,
+            mh: util::MHCore::new(),
             temperature: 1.0,
-            adaptor: adaptor,
+            bounds: None,
+            kernel: ProposalKernel::Gaussian,
+            integer_kernel: IntegerKernel::Geometric,
+            mode_jumps: None,
+            fixed: false,
+            prior_cache: None,
+            emit_proposals: false,
+            last_proposal: None,
+            proposal_cache: None,
+            events: None,
+            adaptor: Box::new(adaptor),
+            phantom_v: PhantomData,
         })
     }
 }
 
+impl<D, T, V, M, L> SRWM<D, T, V, M, L>
+where
+    D: Rv<T> + Variance<V> + Mean<T> + Clone + fmt::Debug,
+    T: RWT,
+    M: 'static + Clone + fmt::Debug,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+    V: Clone + fmt::Debug
+{
+    /// Replace the proposal scale adaptor, e.g. with a `FixedAdaptor` to
+    /// freeze the scale found during warmup.
+    pub fn set_adaptor(&mut self, adaptor: Box<dyn ScaleAdaptor<T>>) {
+        self.adaptor = adaptor;
+    }
+
+    /// Use a previously adapted proposal scale, e.g. from an earlier run on
+    /// similar data, and never adapt it so warmup can be skipped.
+    pub fn proposal_scale(&self, scale: f64) -> Self
+    where
+        FixedAdaptor<T>: ScaleAdaptor<T>,
+    {
+        assert!(
+            scale > 0.0 && scale.is_finite(),
+            "proposal scale must be finite and positive."
+        );
+        SRWM {
+            adaptor: Box::new(FixedAdaptor::new(scale)),
+            ..(*self).clone()
+        }
+    }
+
+    /// Adapt the scale of integer proposals towards `target_acceptance`,
+    /// by default `DISCRETE_TARGET_ACCEPTANCE`, instead of the 0.234 the
+    /// default adaptor targets for continuous parameters.
+    pub fn discrete_adaptor(&self, target_acceptance: Option<f64>) -> Self
+    where
+        DiscreteAdaptor<T>: ScaleAdaptor<T>,
+    {
+        let adaptor = DiscreteAdaptor::new(
+            self.adaptor.get_scale(),
+            target_acceptance.unwrap_or(DISCRETE_TARGET_ACCEPTANCE),
+        );
+        SRWM {
+            adaptor: Box::new(adaptor),
+            ..(*self).clone()
+        }
+    }
+
+    /// Reflect continuous proposals at `lower` and `upper` so they stay in
+    /// the prior's support. Either bound may be infinite. Integer proposals
+    /// are instead drawn from their `IntegerKernel` truncated to the
+    /// integers within the bounds.
+    pub fn bounded(&self, lower: f64, upper: f64) -> Self {
+        assert!(lower < upper, "lower bound must be less than upper bound.");
+        SRWM {
+            bounds: Some((lower, upper)),
+            ..(*self).clone()
+        }
+    }
+
+    /// Draw continuous proposal increments from `kernel`, e.g. a heavy
+    /// tailed `StudentT` or `Cauchy` to escape local modes.
+    pub fn kernel(&self, kernel: ProposalKernel) -> Self {
+        if let ProposalKernel::StudentT(df) = kernel {
+            assert!(df > 0.0, "StudentT degrees of freedom must be positive.");
+        }
+        SRWM {
+            kernel,
+            ..(*self).clone()
+        }
+    }
+
+    /// Draw the magnitudes of integer proposal steps from `kernel`.
+    pub fn integer_kernel(&self, kernel: IntegerKernel) -> Self {
+        SRWM {
+            integer_kernel: kernel,
+            ..(*self).clone()
+        }
+    }
+
+    /// Make some continuous proposals large jumps, drawn from the prior or
+    /// a widened random walk, to move between modes. Jumps do not adapt
+    /// the proposal scale.
+    pub fn mode_jumps(&self, jumps: util::ModeJumps) -> Self {
+        SRWM {
+            mode_jumps: Some(jumps),
+            ..(*self).clone()
+        }
+    }
+
+    /// Report each step's value, proposal and log acceptance ratio as a
+    /// `StatisticValue::Proposal`, e.g. for a `RaoBlackwellMean`.
+    pub fn emit_proposals(&self) -> Self {
+        SRWM {
+            emit_proposals: true,
+            ..(*self).clone()
+        }
+    }
+
+    /// Record every proposal, accepted or not, in `cache`.
+    pub fn record_proposals(&self, cache: util::ProposalCache) -> Self {
+        SRWM {
+            proposal_cache: Some(cache),
+            ..(*self).clone()
+        }
+    }
+
+    /// The current proposal scale adaptor
+    pub fn adaptor(&self) -> &dyn ScaleAdaptor<T> {
+        self.adaptor.as_ref()
+    }
+
+    /// Log prior of the parameter's current value, reusing the score from
+    /// the shared prior cache when another stepper already computed it.
+    fn current_prior(&self, value: &T) -> f64 {
+        match self.prior_cache {
+            Some(ref cache) => cache
+                .get_or_insert_with(self.parameter.id(), || {
+                    self.parameter.prior.ln_f(value)
+                }),
+            None => self.parameter.prior.ln_f(value),
+        }
+    }
+}
+
+impl<D, T, V, M, L> Tempering for SRWM<D, T, V, M, L>
+where
+    D: Rv<T> + Variance<V> + Mean<T> + Clone + fmt::Debug,
+    T: RWT,
+    M: 'static + Clone + fmt::Debug,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+    V: Clone + fmt::Debug
+{
+    fn set_temperature(&mut self, temperature: f64) {
+        assert!(
+            temperature > 0.0 && temperature.is_finite(),
+            "temperature must be finite and positive."
+        );
+        self.temperature = temperature;
+        // The cached score is of the previous target.
+        self.mh.current_score = None;
+    }
+
+    fn temperature(&self) -> f64 {
+        self.temperature
+    }
+}
+
 impl<D, T, V, M, L> Clone for SRWM<D, T, V, M, L>
 where
         D: Rv<T> + Variance<V> + Mean<T> + Clone + fmt::Debug,
         T: RWT,
         M: 'static + Clone + fmt::Debug,
-        L: Fn(&M) -> f64 + Clone + Sync,
+        L: DeltaLogLikelihood<M> + Clone + Sync,
         V: Clone + fmt::Debug
 {
     fn clone(&self) -> Self {
         SRWM {
             parameter: self.parameter.clone(),
             log_likelihood: self.log_likelihood.clone(),
-            current_score: self.current_score,
-            log_acceptance: self.log_acceptance,
+            mh: self.mh,
+            bounds: self.bounds,
+            kernel: self.kernel,
+            integer_kernel: self.integer_kernel,
+            mode_jumps: self.mode_jumps,
+            fixed: self.fixed,
+            prior_cache: self.prior_cache.clone(),
+            emit_proposals: self.emit_proposals,
+            last_proposal: self.last_proposal,
+            proposal_cache: self.proposal_cache.clone(),
+            events: self.events.clone(),
             adaptor: self.adaptor.clone(),
-            temperature: 1.0
+            temperature: self.temperature,
+            phantom_v: PhantomData,
         }
     }
 }
 
+/// Smallest geometric success probability used for integer proposals
+const MIN_GEOMETRIC_P: f64 = 1E-12;
+
+/// Success probability of the geometric step magnitudes of integer walks
+/// with the given proposal scale, clamped to the valid range `(0, 1]` so
+/// extreme adapted scales cannot produce an invalid distribution.
+fn geometric_p(scale: f64) -> f64 {
+    let s2 = scale * scale;
+    let p = ((4.0 * s2 + 1.0).sqrt() + 1.0) / (2.0 * s2);
+    if p.is_nan() {
+        // Only an infinite scale gets here.
+        MIN_GEOMETRIC_P
+    } else {
+        p.max(MIN_GEOMETRIC_P).min(1.0)
+    }
+}
+
+/// Rate of the Poisson step magnitudes of integer walks with the given
+/// proposal scale, whose second moment `λ + λ²` is the squared scale
+fn poisson_rate(scale: f64) -> f64 {
+    0.5 * ((4.0 * scale * scale + 1.0).sqrt() - 1.0)
+}
+
+/// Magnitude beyond which Poisson steps are negligibly likely
+fn poisson_cutoff(rate: f64) -> f64 {
+    rate + 40.0 * rate.sqrt() + 40.0
+}
+
+fn poisson_pmf(rate: f64, k: u64) -> f64 {
+    if rate == 0.0 {
+        return if k == 0 { 1.0 } else { 0.0 };
+    }
+    let k = k as f64;
+    (k * rate.ln() - rate - Gamma::ln_gamma(k + 1.0).0).exp()
+}
 
 macro_rules! impl_traits_ordinal {
     ($dtype: ty, $vtype: ty) => {
@@ -108,7 +440,7 @@ macro_rules! impl_traits_ordinal {
         where 
             D: Rv<$dtype> + Variance<$vtype> + Mean<$dtype> + Clone + fmt::Debug,
             M: 'static + Clone + fmt::Debug,
-            L: Fn(&M) -> f64 + Clone + Sync + fmt::Debug,
+            L: DeltaLogLikelihood<M> + Clone + Sync + fmt::Debug,
             R: Rng
         {
             fn set_adapt(&mut self, mode: AdaptationMode) {
@@ -120,69 +452,215 @@ macro_rules! impl_traits_ordinal {
             }
 
             fn get_statistics(&self) -> Vec<Statistic<M, R>> {
-                Vec::new()
+                let non_finite = self.adaptor.non_finite_updates();
+                self.mh
+                    .acceptance
+                    .rate()
+                    .map(StatisticValue::AcceptanceRate)
+                    .into_iter()
+                    .chain(Some(StatisticValue::ProposalScale(
+                        self.adaptor.get_scale(),
+                    )))
+                    .chain(
+                        Some(StatisticValue::NonFiniteUpdates(non_finite))
+                            .filter(|_| non_finite > 0)
+                    )
+                    .chain(self.last_proposal.map(|(current, proposed)| {
+                        StatisticValue::Proposal {
+                            current,
+                            proposed,
+                            log_alpha: self.mh.log_acceptance,
+                        }
+                    }))
+                    .map(|value| {
+                        Statistic::new(self.parameter.id().clone(), value)
+                    })
+                    .collect()
+            }
+
+            fn parameters(&self) -> Vec<ParamId> {
+                vec![self.parameter.id().clone()]
+            }
+
+            fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+                vec![(
+                    self.parameter.id().clone(),
+                    self.parameter.dependencies.clone(),
+                )]
             }
 
             fn reset(&mut self) {
-                self.current_score = None;
+                self.mh.reset();
                 self.adaptor.reset();
             }
 
+            fn invalidate_cache(&mut self) {
+                self.mh.invalidate();
+            }
+
+            fn fix(&mut self, parameter: &ParamId) {
+                self.fixed |= parameter == self.parameter.id();
+            }
+
+            fn set_prior_cache(&mut self, cache: util::PriorCache) {
+                self.prior_cache = Some(cache);
+            }
+
+            fn set_event_sink(&mut self, sink: EventSink) {
+                self.events = Some(sink);
+            }
+
+            fn draw_prior(&self, rng: &mut R, model: M) -> M {
+                if self.fixed {
+                    return model;
+                }
+                self.parameter.draw(&model, rng)
+            }
+
+            fn adaptation_state(&self) -> Vec<AdaptorState> {
+                vec![AdaptorState {
+                    name: self.parameter.name().to_string(),
+                    ..self.adaptor.state()
+                }]
+            }
+
+            fn restore_adaptation(
+                &mut self,
+                states: &mut dyn Iterator<Item = AdaptorState>,
+            ) -> io::Result<()> {
+                let name = self.parameter.name();
+                self.adaptor.restore(&next_state(states, name)?)
+            }
+
             /*
             fn substeppers(&self) -> Option<&Vec<Box<SteppingAlg<M, R>>>> {
                 None
             }
-
-            fn prior_sample(&self, rng: &mut R, m: M) -> M {
-                self.parameter.draw(&m, &mut rng)
-            }
             */
 
             fn step(&mut self, rng: &mut R, model: M) -> M {
-                let current_value = self.parameter.lens.get(&model);
-                let current_score = self.current_score.unwrap_or_else(|| {
-                    (self.log_likelihood)(&model) + self.parameter.prior.ln_f(&current_value)
+                self.last_proposal = None;
+                if self.fixed {
+                    return model;
+                }
+                let current_value = match self.parameter.lens.try_get(&model) {
+                    Ok(value) => value,
+                    Err(err) => return util::reject_lens_failure(
+                        &self.events, self.parameter.id(), &err, model
+                    ),
+                };
+                let log_likelihood = util::ScaledLogLikelihood::new(
+                    &self.log_likelihood,
+                    self.temperature.recip(),
+                );
+                let current_score = self.mh.current_score(|| {
+                    log_likelihood.ln_f(&model) + self.current_prior(&current_value)
                 });
 
-                // propose new value
-                let geom_p = ((4.0 * self.adaptor.proposal_scale * self.adaptor.proposal_scale + 1.0).sqrt() + 1.0) / (2.0 * self.adaptor.proposal_scale * self.adaptor.proposal_scale);
-                let proposal_dist = Geometric::new(geom_p).unwrap();
-                let mag: $dtype = proposal_dist.draw(rng);
-
-                let proposed_new_value = if rng.gen() {
-                    current_value + mag
-                } else {
-                    if mag > current_value {
-                        0
+                // propose new value within the support
+                let scale = self.adaptor.get_scale();
+                let kernel = self.integer_kernel;
+                let max_value = <$dtype>::max_value() as f64;
+                let (lower, upper) = self.bounds.map_or(
+                    (0.0, max_value),
+                    |(lower, upper)| {
+                        (lower.ceil().max(0.0), upper.floor().min(max_value))
+                    },
+                );
+                // Normalizer of the truncated proposal from x, up to a half
+                let normalizer = |x: f64| {
+                    kernel.cdf(scale, (upper - x).max(0.0))
+                        + kernel.cdf(scale, (x - lower).max(0.0))
+                };
+                let x = f64::from(current_value);
+                let (room_up, room_down) =
+                    ((upper - x).max(0.0), (x - lower).max(0.0));
+                let up_mass = kernel.cdf(scale, room_up);
+                let current_normalizer =
+                    up_mass + kernel.cdf(scale, room_down);
+                let proposed_new_value =
+                    if rng.gen::<f64>() * current_normalizer < up_mass {
+                        let mag = kernel.draw(rng, scale, room_up);
+                        current_value + mag as $dtype
                     } else {
-                        current_value - mag
-                    }
+                        let mag = kernel.draw(rng, scale, room_down);
+                        current_value - mag as $dtype
+                    };
+                let proposed_normalizer =
+                    normalizer(f64::from(proposed_new_value));
+                let new_model = self.parameter.lens.try_set(&model, proposed_new_value);
+                let new_model = match new_model {
+                    Ok(new_model) => new_model,
+                    Err(err) => return util::reject_lens_failure(
+                        &self.events, self.parameter.id(), &err, model
+                    ),
                 };
-                let new_model = self.parameter.lens.set(&model, proposed_new_value);
                 let prior_score = self.parameter.prior.ln_f(&proposed_new_value);
 
-                // If the prior score is infinite, we've likely moved out of it's support.
-                // Continue with the infinite value to rejection.
-                let new_score = if prior_score.is_finite() {
-                    (self.log_likelihood)(&new_model) + prior_score
-                } else {
+                let new_score = util::proposal_score(
+                    &log_likelihood,
+                    self.parameter.id(),
+                    &model,
+                    current_score,
+                    || self.current_prior(&current_value),
+                    &new_model,
                     prior_score
-                };
-
-                let log_alpha = new_score - current_score;
+                );
 
-                let update = util::metropolis_select(rng, log_alpha, proposed_new_value, current_value);
+                // Truncation makes proposals from near a bound asymmetric.
+                let truncation = if current_normalizer > 0.0
+                    && proposed_normalizer > 0.0
+                {
+                    current_normalizer.ln() - proposed_normalizer.ln()
+                } else {
+                    0.0
+                };
+                let scores = util::ProposalScores::new(current_score, new_score)
+                    .hastings(truncation);
+
+                if self.emit_proposals {
+                    self.last_proposal = Some((
+                        f64::from(current_value),
+                        f64::from(proposed_new_value),
+                    ));
+                }
+                let update = util::metropolis_select(rng, scores, proposed_new_value, current_value);
                 self.adaptor.update(&update);
+                self.mh.record(&update);
+                if let Some(ref events) = self.events {
+                    let id = self.parameter.id();
+                    events.emit_update(&id, &update);
+                    events.emit_scores(
+                        &id,
+                        &update,
+                        || self.current_prior(&current_value),
+                        prior_score,
+                    );
+                    let scale = self.adaptor.get_scale();
+                    events.emit_adaptation(&id, self.adaptor.get_mode(), scale);
+                }
+                if let Some(ref cache) = self.proposal_cache {
+                    cache.push(util::ProposalRecord {
+                        parameter: self.parameter.id().clone(),
+                        current: f64::from(current_value),
+                        proposed: f64::from(proposed_new_value),
+                        current_score,
+                        proposed_score: new_score,
+                        log_alpha: update.log_alpha(),
+                        accepted: update.is_accepted(),
+                    });
+                }
                 match update{
                     util::MetroplisUpdate::Accepted(_, _) => {
-                        self.current_score = Some(new_score);
-                        self.log_acceptance = log_alpha;
+                        if let Some(ref cache) = self.prior_cache {
+                            cache.insert(
+                                self.parameter.id().clone(),
+                                prior_score,
+                            );
+                        }
                         new_model
                     },
-                    util::MetroplisUpdate::Rejected(_, _) => {
-                        self.log_acceptance = log_alpha;
-                        model
-                    }
+                    util::MetroplisUpdate::Rejected(_, _) => model
                 }
             }
         }
@@ -199,7 +677,7 @@ macro_rules! impl_traits_continuous {
         where
             D: Rv<$dtype> + Variance<$vtype> + Mean<$dtype> + Clone + fmt::Debug,
             M: 'static + Clone + fmt::Debug,
-            L: Fn(&M) -> f64 + Clone + Sync,
+            L: DeltaLogLikelihood<M> + Clone + Sync,
             R: Rng
         {
             fn set_adapt(&mut self, mode: AdaptationMode) {
@@ -211,59 +689,198 @@ macro_rules! impl_traits_continuous {
             }
 
             fn get_statistics(&self) -> Vec<Statistic<M, R>> {
-                Vec::new()
+                let non_finite = self.adaptor.non_finite_updates();
+                self.mh
+                    .acceptance
+                    .rate()
+                    .map(StatisticValue::AcceptanceRate)
+                    .into_iter()
+                    .chain(
+                        Some(StatisticValue::NonFiniteUpdates(non_finite))
+                            .filter(|_| non_finite > 0)
+                    )
+                    .chain(self.last_proposal.map(|(current, proposed)| {
+                        StatisticValue::Proposal {
+                            current,
+                            proposed,
+                            log_alpha: self.mh.log_acceptance,
+                        }
+                    }))
+                    .map(|value| {
+                        Statistic::new(self.parameter.id().clone(), value)
+                    })
+                    .collect()
+            }
+
+            fn parameters(&self) -> Vec<ParamId> {
+                vec![self.parameter.id().clone()]
+            }
+
+            fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+                vec![(
+                    self.parameter.id().clone(),
+                    self.parameter.dependencies.clone(),
+                )]
             }
 
             fn reset(&mut self) {
-                self.current_score = None;
+                self.mh.reset();
                 self.adaptor.reset();
             }
 
+            fn invalidate_cache(&mut self) {
+                self.mh.invalidate();
+            }
+
+            fn fix(&mut self, parameter: &ParamId) {
+                self.fixed |= parameter == self.parameter.id();
+            }
+
+            fn set_prior_cache(&mut self, cache: util::PriorCache) {
+                self.prior_cache = Some(cache);
+            }
+
+            fn set_event_sink(&mut self, sink: EventSink) {
+                self.events = Some(sink);
+            }
+
+            fn draw_prior(&self, rng: &mut R, model: M) -> M {
+                if self.fixed {
+                    return model;
+                }
+                self.parameter.draw(&model, rng)
+            }
+
+            fn adaptation_state(&self) -> Vec<AdaptorState> {
+                vec![AdaptorState {
+                    name: self.parameter.name().to_string(),
+                    ..self.adaptor.state()
+                }]
+            }
+
+            fn restore_adaptation(
+                &mut self,
+                states: &mut dyn Iterator<Item = AdaptorState>,
+            ) -> io::Result<()> {
+                let name = self.parameter.name();
+                self.adaptor.restore(&next_state(states, name)?)
+            }
+
             /*
             fn substeppers(&self) -> Option<&Vec<Box<SteppingAlg<M, R>>>> {
                 None
             }
-
-            fn prior_sample(&self, rng: &mut R, m: M) -> M {
-                self.parameter.draw(&m, &mut rng)
-            }
             */
 
             fn step(&mut self, rng: &mut R, model: M) -> M {
-                let current_value = self.parameter.lens.get(&model);
-                let current_score = self.current_score.unwrap_or_else(|| {
-                    (self.log_likelihood)(&model) + self.parameter.prior.ln_f(&current_value)
+                self.last_proposal = None;
+                if self.fixed {
+                    return model;
+                }
+                let current_value = match self.parameter.lens.try_get(&model) {
+                    Ok(value) => value,
+                    Err(err) => return util::reject_lens_failure(
+                        &self.events, self.parameter.id(), &err, model
+                    ),
+                };
+                let log_likelihood = util::ScaledLogLikelihood::new(
+                    &self.log_likelihood,
+                    self.temperature.recip(),
+                );
+                let current_score = self.mh.current_score(|| {
+                    log_likelihood.ln_f(&model) + self.current_prior(&current_value)
                 });
 
                 // propose new value
-                let proposal_dist = Gaussian::new(f64::from(current_value), self.adaptor.proposal_scale).unwrap();
-
-                let proposed_new_value = proposal_dist.draw(rng);
-                let new_model = self.parameter.lens.set(&model, proposed_new_value);
+                let move_kind = self.mode_jumps
+                    .map_or(util::Move::Local, |jumps| jumps.choose(rng));
+                let proposed_new_value: $dtype = match move_kind {
+                    util::Move::Prior => self.parameter.prior.draw(rng),
+                    util::Move::Local | util::Move::Wide(_) => {
+                        let width = match move_kind {
+                            util::Move::Wide(width) => width,
+                            _ => 1.0,
+                        };
+                        let scale = width * self.adaptor.get_scale();
+                        let x = f64::from(current_value) + scale * self.kernel.draw(rng);
+                        match self.bounds {
+                            Some((lower, upper)) => util::reflect(x, lower, upper) as $dtype,
+                            None => x as $dtype
+                        }
+                    }
+                };
+                let new_model = self.parameter.lens.try_set(&model, proposed_new_value);
+                let new_model = match new_model {
+                    Ok(new_model) => new_model,
+                    Err(err) => return util::reject_lens_failure(
+                        &self.events, self.parameter.id(), &err, model
+                    ),
+                };
                 let prior_score = self.parameter.prior.ln_f(&proposed_new_value);
 
-                // If the prior score is infinite, we've likely moved out of it's support.
-                // Continue with the infinite value to rejection.
-                let new_score = if prior_score.is_finite() {
-                    (self.log_likelihood)(&new_model) + prior_score
-                } else {
+                let new_score = util::proposal_score(
+                    &log_likelihood,
+                    self.parameter.id(),
+                    &model,
+                    current_score,
+                    || self.current_prior(&current_value),
+                    &new_model,
                     prior_score
-                };
+                );
 
-                let log_alpha = new_score - current_score;
-                let update = util::metropolis_select(rng, log_alpha, proposed_new_value, current_value);
-                self.adaptor.update(&update);
+                let mut scores = util::ProposalScores::new(current_score, new_score);
+                if move_kind == util::Move::Prior {
+                    // The prior's ratio cancels with the proposal's.
+                    let current_prior = self.current_prior(&current_value);
+                    scores = scores.hastings(current_prior - prior_score);
+                }
+                if self.emit_proposals {
+                    self.last_proposal = Some((
+                        f64::from(current_value),
+                        f64::from(proposed_new_value),
+                    ));
+                }
+                let update = util::metropolis_select(rng, scores, proposed_new_value, current_value);
+                // Jumps say nothing about the scale of local moves.
+                if move_kind == util::Move::Local {
+                    self.adaptor.update(&update);
+                }
+                self.mh.record(&update);
+                if let Some(ref events) = self.events {
+                    let id = self.parameter.id();
+                    events.emit_update(&id, &update);
+                    events.emit_scores(
+                        &id,
+                        &update,
+                        || self.current_prior(&current_value),
+                        prior_score,
+                    );
+                    let scale = self.adaptor.get_scale();
+                    events.emit_adaptation(&id, self.adaptor.get_mode(), scale);
+                }
+                if let Some(ref cache) = self.proposal_cache {
+                    cache.push(util::ProposalRecord {
+                        parameter: self.parameter.id().clone(),
+                        current: f64::from(current_value),
+                        proposed: f64::from(proposed_new_value),
+                        current_score,
+                        proposed_score: new_score,
+                        log_alpha: update.log_alpha(),
+                        accepted: update.is_accepted(),
+                    });
+                }
 
                 match update { 
                     util::MetroplisUpdate::Accepted(_, _) => {
-                        self.current_score = Some(new_score);
-                        self.log_acceptance = log_alpha;
+                        if let Some(ref cache) = self.prior_cache {
+                            cache.insert(
+                                self.parameter.id().clone(),
+                                prior_score,
+                            );
+                        }
                         new_model
                     },
-                    util::MetroplisUpdate::Rejected(_, _) => {
-                        self.log_acceptance = log_alpha;
-                        model
-                    }
+                    util::MetroplisUpdate::Rejected(_, _) => model
                 }
             }
         }
@@ -482,13 +1099,638 @@ mod tests {
             let expected_sigma_dist =
                 InvGamma::new(new_alpha, new_beta).unwrap();
 
-            // save samples to debug
-            // utils::write_samples_to_file(Path::new(&format!("/tmp/samples_{}", i)), &samples).unwrap();
-
             let (stat, p) = ks_test(&samples, |s| expected_sigma_dist.cdf(&s));
             println!("test stat = {}, p = {}", stat, p);
             p > P_VAL
         });
         assert!(passed);
     }
+
+    #[test]
+    fn delta_log_likelihood_replaces_full_evaluations() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use parameter::ParamId;
+
+        #[derive(Copy, Clone, Debug)]
+        struct Model {
+            x: f64,
+        }
+
+        #[derive(Clone)]
+        struct CountingLikelihood {
+            full_evaluations: Arc<AtomicUsize>,
+        }
+
+        impl DeltaLogLikelihood<Model> for CountingLikelihood {
+            fn ln_f(&self, m: &Model) -> f64 {
+                self.full_evaluations.fetch_add(1, Ordering::SeqCst);
+                -0.5 * m.x * m.x
+            }
+
+            fn delta(&self, current: &Model, proposed: &Model, changed: &ParamId)
+                -> Option<f64>
+            {
+                assert_eq!(changed, &ParamId("x".to_string()));
+                Some(-0.5 * (proposed.x * proposed.x - current.x * current.x))
+            }
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let full_evaluations = Arc::new(AtomicUsize::new(0));
+
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Uniform::new(-10.0, 10.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let log_likelihood = CountingLikelihood {
+            full_evaluations: full_evaluations.clone(),
+        };
+        let mut alg = SRWM::new(parameter, log_likelihood, Some(1.0)).unwrap();
+
+        let samples: Vec<f64> = (0..10000)
+            .scan(Model { x: 0.0 }, |m, _| {
+                *m = alg.step(&mut rng, *m);
+                Some(m.x)
+            })
+            .step_by(10)
+            .collect();
+
+        assert_eq!(full_evaluations.load(Ordering::SeqCst), 1);
+
+        let (stat, p) =
+            ks_test(&samples, |s| Gaussian::new(0.0, 1.0).unwrap().cdf(&s));
+        println!("test stat = {}, p = {}", stat, p);
+        assert!(p > P_VAL);
+    }
+
+    #[test]
+    fn adaptor_can_be_swapped() {
+        use steppers::adaptor::FixedAdaptor;
+
+        #[derive(Copy, Clone, Debug)]
+        struct Model {
+            x: f64,
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let log_likelihood = |_: &Model| 0.0;
+        let mut alg = SRWM::new(parameter, log_likelihood, Some(0.1)).unwrap();
+
+        SteppingAlg::<Model, rand::rngs::StdRng>::set_adapt(
+            &mut alg,
+            AdaptationMode::Enabled
+        );
+        let m = (0..1000).fold(Model { x: 0.0 }, |m, _| alg.step(&mut rng, m));
+        assert!(alg.adaptor().get_scale() != 0.1);
+
+        alg.set_adaptor(Box::new(FixedAdaptor::new(0.5)));
+        SteppingAlg::<Model, rand::rngs::StdRng>::set_adapt(
+            &mut alg,
+            AdaptationMode::Enabled
+        );
+        (0..100).fold(m, |m, _| alg.step(&mut rng, m));
+        assert_eq!(alg.adaptor().get_scale(), 0.5);
+
+        let cloned = alg.clone();
+        assert_eq!(cloned.adaptor().get_scale(), 0.5);
+    }
+
+    #[test]
+    fn warm_start_from_adapted_scale() {
+        #[derive(Copy, Clone, Debug)]
+        struct Model {
+            x: f64,
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let log_likelihood = |_: &Model| 0.0;
+
+        // Adapt once, then reuse the scale found.
+        let mut first = SRWM::new(parameter, log_likelihood, Some(0.1)).unwrap();
+        SteppingAlg::<Model, rand::rngs::StdRng>::set_adapt(
+            &mut first,
+            AdaptationMode::Enabled
+        );
+        (0..1000).fold(Model { x: 0.0 }, |m, _| first.step(&mut rng, m));
+        let adapted = first.adaptor().get_scale();
+
+        let second = first.proposal_scale(adapted);
+        assert_eq!(second.adaptor().get_scale(), adapted);
+        match SteppingAlg::<Model, rand::rngs::StdRng>::get_adapt(&second) {
+            AdaptationStatus::Disabled => {}
+            status => panic!("Expected adaptation to be disabled: {:?}", status),
+        }
+
+        let passed = multiple_tries(N_TRIES, |_| {
+            let results: Vec<Vec<Model>> = Runner::new(second.clone())
+                .warmup(0)
+                .thinning(10)
+                .chains(2)
+                .run(&mut rng, Model { x: 0.0 });
+            let samples: Vec<f64> = results
+                .iter()
+                .flat_map(|chain| chain.iter().map(|m| m.x))
+                .collect();
+            let (stat, p) = ks_test(&samples, |s| {
+                Gaussian::new(0.0, 1.0).unwrap().cdf(&s)
+            });
+            println!("test stat = {}, p = {}", stat, p);
+            p > P_VAL
+        });
+        assert!(passed);
+    }
+
+    #[test]
+    fn non_finite_updates_are_reported() {
+        #[derive(Copy, Clone, Debug)]
+        struct Model {
+            x: f64,
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        // Every log_alpha is -inf - -inf = NaN
+        let log_likelihood = |_: &Model| std::f64::NEG_INFINITY;
+        let mut alg = SRWM::new(parameter, log_likelihood, Some(1.0)).unwrap();
+
+        SteppingAlg::<Model, rand::rngs::StdRng>::set_adapt(
+            &mut alg,
+            AdaptationMode::Enabled
+        );
+        (0..10).fold(Model { x: 0.0 }, |m, _| alg.step(&mut rng, m));
+
+        let stats: Vec<Statistic<Model, rand::rngs::StdRng>> =
+            alg.get_statistics();
+        assert!(stats.iter().any(|s| {
+            s.value == StatisticValue::NonFiniteUpdates(10)
+        }));
+        assert!(stats.iter().any(|s| {
+            s.value == StatisticValue::AcceptanceRate(0.0)
+        }));
+    }
+
+    #[test]
+    fn discrete_adaptor_targets_integer_acceptance() {
+        // Poisson prior with the integer mean the adaptor starts from
+        #[derive(Clone, Debug)]
+        struct Counts(Poisson);
+
+        impl Rv<u32> for Counts {
+            fn ln_f(&self, x: &u32) -> f64 {
+                self.0.ln_f(x)
+            }
+
+            fn draw<R: Rng>(&self, rng: &mut R) -> u32 {
+                self.0.draw(rng)
+            }
+        }
+
+        impl Mean<u32> for Counts {
+            fn mean(&self) -> Option<u32> {
+                self.0.mean().map(|m: f64| m as u32)
+            }
+        }
+
+        impl Variance<f64> for Counts {
+            fn variance(&self) -> Option<f64> {
+                self.0.variance()
+            }
+        }
+
+        #[derive(Copy, Clone, Debug)]
+        struct Model {
+            n: u32,
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+
+        let parameter = Parameter::new(
+            "n".to_string(),
+            Counts(Poisson::new(20.0).unwrap()),
+            make_lens!(Model, u32, n),
+        );
+        fn log_likelihood(_: &Model) -> f64 {
+            0.0
+        }
+        let log_likelihood: fn(&Model) -> f64 = log_likelihood;
+        let start = SRWM::new(parameter, log_likelihood, Some(1.0)).unwrap();
+
+        // A unit scale clamps the geometric step to always be zero, so
+        // every proposal is accepted until the scale grows.
+        let mut alg = start.discrete_adaptor(None);
+        SteppingAlg::<Model, rand::rngs::StdRng>::set_adapt(
+            &mut alg,
+            AdaptationMode::Enabled
+        );
+        (0..5000).fold(Model { n: 20 }, |m, _| alg.step(&mut rng, m));
+
+        let stats: Vec<Statistic<Model, rand::rngs::StdRng>> =
+            alg.get_statistics();
+        let scale = stats
+            .iter()
+            .filter_map(|s| match s.value {
+                StatisticValue::ProposalScale(scale) => Some(scale),
+                _ => None,
+            })
+            .next()
+            .unwrap();
+        assert!(scale > 1.0);
+
+        let mut fixed = start.proposal_scale(scale);
+        (0..5000).fold(Model { n: 20 }, |m, _| fixed.step(&mut rng, m));
+        let stats: Vec<Statistic<Model, rand::rngs::StdRng>> =
+            fixed.get_statistics();
+        match stats[0].value {
+            StatisticValue::AcceptanceRate(rate) => {
+                println!("scale = {}, acceptance = {}", scale, rate);
+                assert!((rate - DISCRETE_TARGET_ACCEPTANCE).abs() < 0.1);
+            }
+            _ => panic!("Expected an acceptance rate"),
+        }
+    }
+
+    // Uniform prior on the integers lower..=upper
+    #[derive(Clone, Debug)]
+    struct UniformCount(u32, u32);
+
+    impl Rv<u32> for UniformCount {
+        fn ln_f(&self, x: &u32) -> f64 {
+            if *x < self.0 || *x > self.1 {
+                std::f64::NEG_INFINITY
+            } else {
+                -f64::from(self.1 - self.0 + 1).ln()
+            }
+        }
+
+        fn draw<R: Rng>(&self, rng: &mut R) -> u32 {
+            rng.gen_range(self.0, self.1 + 1)
+        }
+    }
+
+    impl Mean<u32> for UniformCount {
+        fn mean(&self) -> Option<u32> {
+            Some((self.0 + self.1) / 2)
+        }
+    }
+
+    impl Variance<f64> for UniformCount {
+        fn variance(&self) -> Option<f64> {
+            let n = f64::from(self.1 - self.0 + 1);
+            Some((n * n - 1.0) / 12.0)
+        }
+    }
+
+    #[test]
+    fn truncated_integer_proposals_keep_the_posterior() {
+        #[derive(Copy, Clone, Debug)]
+        struct Model {
+            n: u32,
+        }
+
+        fn log_likelihood(_: &Model) -> f64 {
+            0.0
+        }
+        let log_likelihood: fn(&Model) -> f64 = log_likelihood;
+
+        // Steps as wide as the support are truncated on most proposals,
+        // and without the correction the walk favours the middle.
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let cases = vec![
+            (2, 7, Some((2.0, 7.0)), IntegerKernel::Geometric),
+            (2, 7, Some((1.5, 7.5)), IntegerKernel::Poisson),
+            // The lower bound of unsigned parameters is always zero.
+            (0, 5, None, IntegerKernel::Geometric),
+        ];
+        for (lower, upper, bounds, kernel) in cases {
+            let parameter = Parameter::new(
+                "n".to_string(),
+                UniformCount(lower, upper),
+                make_lens!(Model, u32, n),
+            );
+            let mut alg = SRWM::new(parameter, log_likelihood, None)
+                .unwrap()
+                .integer_kernel(kernel)
+                .proposal_scale(4.0);
+            if let Some((lower, upper)) = bounds {
+                alg = alg.bounded(lower, upper);
+            }
+
+            let passed = multiple_tries(N_TRIES, |_| {
+                let mut counts = vec![0; 6];
+                let mut m = Model { n: lower };
+                for _ in 0..30000 {
+                    m = alg.step(&mut rng, m);
+                    counts[(m.n - lower) as usize] += 1;
+                }
+                println!("{:?}: {:?}", kernel, counts);
+                counts.iter().all(|&c| (c as f64 / 5000.0 - 1.0).abs() < 0.06)
+            });
+            assert!(passed);
+        }
+    }
+
+    #[test]
+    fn proposal_records_keep_the_hastings_correction() {
+        #[derive(Copy, Clone, Debug)]
+        struct Model {
+            n: u32,
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let parameter = Parameter::new(
+            "n".to_string(),
+            UniformCount(2, 7),
+            make_lens!(Model, u32, n),
+        );
+        let log_likelihood: fn(&Model) -> f64 = |_| 0.0;
+        let cache = util::ProposalCache::new(1000);
+        let mut alg = SRWM::new(parameter, log_likelihood, None)
+            .unwrap()
+            .proposal_scale(4.0)
+            .bounded(2.0, 7.0)
+            .record_proposals(cache.clone());
+
+        let mut m = Model { n: 2 };
+        for _ in 0..1000 {
+            m = alg.step(&mut rng, m);
+        }
+        // Every score is the same, so only the truncation moves the ratio.
+        let records = cache.records();
+        assert!(records
+            .iter()
+            .all(|r| (r.proposed_score - r.current_score).abs() < 1E-12));
+        assert!(records.iter().any(|r| r.log_alpha() < -1E-3));
+        assert!(records.iter().all(|r| r.log_alpha() < 0.0 || r.accepted));
+    }
+
+    #[test]
+    fn integer_kernel_draws_follow_their_truncated_cdf() {
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        for &kernel in &[IntegerKernel::Geometric, IntegerKernel::Poisson] {
+            for &scale in &[0.5, 3.0, 40.0] {
+                let inf = std::f64::INFINITY;
+                assert!((kernel.cdf(scale, inf) - 1.0).abs() < 1E-12);
+                assert!(kernel.cdf(scale, 0.0) > 0.0);
+
+                let max = 4.0;
+                let mut counts = vec![0; 5];
+                for _ in 0..20000 {
+                    let m = kernel.draw(&mut rng, scale, max);
+                    assert!(m >= 0.0 && m <= max && m == m.floor());
+                    counts[m as usize] += 1;
+                }
+                let total = kernel.cdf(scale, max);
+                let mut below = 0.0;
+                for (m, &count) in counts.iter().enumerate() {
+                    let cdf = kernel.cdf(scale, m as f64);
+                    let expected = (cdf - below) / total;
+                    below = cdf;
+                    assert!((count as f64 / 20000.0 - expected).abs() < 0.015);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn geometric_p_is_clamped_to_its_valid_range() {
+        assert_eq!(geometric_p(0.0), 1.0);
+        assert_eq!(geometric_p(0.5), 1.0);
+        assert_eq!(geometric_p(std::f64::INFINITY), MIN_GEOMETRIC_P);
+        let p = geometric_p(10.0);
+        assert!(p > 0.0 && p < 1.0);
+    }
+
+    #[test]
+    fn proposal_cache_keeps_the_latest_proposals() {
+        #[derive(Copy, Clone, Debug)]
+        struct Model {
+            x: f64,
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let cache = util::ProposalCache::new(10);
+        let mut alg = SRWM::new(parameter, |_: &Model| 0.0, Some(2.0))
+            .unwrap()
+            .record_proposals(cache.clone());
+
+        let mut model = Model { x: 0.0 };
+        for _ in 0..50 {
+            let before = model.x;
+            model = alg.step(&mut rng, model);
+            let last = cache.records().pop().unwrap();
+            assert_eq!(last.current, before);
+            let after = if last.accepted { last.proposed } else { last.current };
+            assert_eq!(after, model.x);
+        }
+        assert_eq!(cache.len(), 10);
+    }
+
+    #[test]
+    fn bounded_proposals_stay_in_support() {
+        #[derive(Copy, Clone, Debug)]
+        struct Model {
+            x: f64,
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Uniform::new(0.0, 1.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let log_likelihood = |_: &Model| 0.0;
+        let alg_start = SRWM::new(parameter, log_likelihood, Some(2.0))
+            .unwrap()
+            .bounded(0.0, 1.0);
+
+        let passed = multiple_tries(N_TRIES, |_| {
+            let m = Model { x: 0.5 };
+            let mut alg = alg_start.clone();
+            let samples: Vec<f64> = (0..10000)
+                .scan(m, |m, _| {
+                    *m = alg.step(&mut rng, *m);
+                    Some(m.x)
+                })
+                .step_by(10)
+                .collect();
+
+            // Reflected proposals never leave the uniform prior's support.
+            let stats: Vec<Statistic<Model, rand::rngs::StdRng>> =
+                alg.get_statistics();
+            assert_eq!(stats[0].value, StatisticValue::AcceptanceRate(1.0));
+
+            let (stat, p) =
+                ks_test(&samples, |s| Uniform::new(0.0, 1.0).unwrap().cdf(&s));
+            println!("test stat = {}, p = {}", stat, p);
+            p > P_VAL
+        });
+        assert!(passed);
+    }
+
+    #[test]
+    fn reflect_folds_into_bounds() {
+        assert_eq!(util::reflect(0.5, 0.0, 1.0), 0.5);
+        assert!((util::reflect(1.25, 0.0, 1.0) - 0.75).abs() < 1E-12);
+        assert!((util::reflect(-0.25, 0.0, 1.0) - 0.25).abs() < 1E-12);
+        assert!((util::reflect(2.25, 0.0, 1.0) - 0.25).abs() < 1E-12);
+        assert_eq!(util::reflect(-1.0, 0.0, std::f64::INFINITY), 1.0);
+        assert_eq!(util::reflect(3.0, std::f64::NEG_INFINITY, 2.0), 1.0);
+    }
+
+    #[test]
+    fn heavy_tailed_kernels() {
+        #[derive(Copy, Clone, Debug)]
+        struct Model {
+            x: f64,
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Uniform::new(-10.0, 10.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        let log_likelihood =
+            |m: &Model| Gaussian::new(0.0, 1.0).unwrap().ln_f(&m.x);
+
+        for kernel in vec![ProposalKernel::StudentT(3.0), ProposalKernel::Cauchy] {
+            let alg_start = SRWM::new(parameter.clone(), log_likelihood, Some(1.0))
+                .unwrap()
+                .kernel(kernel);
+
+            let passed = multiple_tries(N_TRIES, |_| {
+                let m = Model { x: 0.0 };
+                let results: Vec<Vec<Model>> =
+                    Runner::new(alg_start.clone())
+                    .thinning(10)
+                    .chains(1)
+                    .run(&mut rng, m);
+
+                let samples: Vec<f64> = results
+                    .iter()
+                    .flat_map(|chain| chain.iter().map(|g| g.x))
+                    .collect();
+
+                let (stat, p) =
+                    ks_test(&samples, |s| Gaussian::new(0.0, 1.0).unwrap().cdf(&s));
+                println!("{:?}: test stat = {}, p = {}", kernel, stat, p);
+                p > P_VAL
+            });
+            assert!(passed);
+        }
+    }
+
+    #[test]
+    fn lens_failures_reject_the_step() {
+        use events::{Event, EventSink};
+        use rand::rngs::StdRng;
+        use std::collections::BTreeMap;
+        type Model = BTreeMap<String, f64>;
+
+        let mut model: Model = BTreeMap::new();
+        model.insert("a".to_string(), 0.0);
+        let parameter = Parameter::new(
+            "a".to_string(),
+            Gaussian::new(0.0, 1.0).unwrap(),
+            Lens::map_key("a", &model).unwrap(),
+        );
+        let mut alg = SRWM::new(parameter, |_m: &Model| 0.0, Some(1.0))
+            .unwrap();
+        let sink = EventSink::new();
+        SteppingAlg::<Model, StdRng>::set_event_sink(&mut alg, sink.clone());
+        let mut rng = StdRng::from_seed(SEED);
+
+        let moved = (0..10).fold(model, |m, _| alg.step(&mut rng, m));
+        assert!(moved["a"] != 0.0);
+        assert!(sink.drain().iter().all(|e| match e {
+            Event::LensFailed { .. } => false,
+            _ => true,
+        }));
+
+        let mut missing: Model = BTreeMap::new();
+        missing.insert("b".to_string(), 1.0);
+        assert_eq!(alg.step(&mut rng, missing.clone()), missing);
+        assert_eq!(
+            sink.drain(),
+            vec![Event::LensFailed {
+                parameter: ParamId("a".to_string()),
+                message: "no entry a in map".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn mode_jumps_visit_both_modes() {
+        #[derive(Copy, Clone, Debug)]
+        struct Model {
+            x: f64,
+        }
+
+        let parameter = Parameter::new(
+            "x".to_string(),
+            Gaussian::new(0.0, 5.0).unwrap(),
+            make_lens!(Model, f64, x),
+        );
+        // Equal modes at -5 and 5, too far apart for steps of 0.5 to cross
+        let log_likelihood = |m: &Model| {
+            let a = Gaussian::new(-5.0, 0.5).unwrap().ln_f(&m.x);
+            let b = Gaussian::new(5.0, 0.5).unwrap().ln_f(&m.x);
+            a.max(b) + (1.0 + (-(a - b).abs()).exp()).ln()
+        };
+        let local = SRWM::new(parameter.clone(), log_likelihood, None)
+            .unwrap()
+            .proposal_scale(0.5);
+        let jumping = local.mode_jumps(util::ModeJumps::new(0.1, 0.5, 20.0));
+
+        let fraction_positive = |alg: &mut SRWM<_, f64, f64, Model, _>| {
+            let mut rng = rand::rngs::StdRng::from_seed(SEED);
+            let mut m = Model { x: 5.0 };
+            let n = 20_000;
+            let positive = (0..n)
+                .filter(|_| {
+                    m = alg.step(&mut rng, m);
+                    m.x > 0.0
+                })
+                .count();
+            positive as f64 / n as f64
+        };
+        assert!(fraction_positive(&mut local.clone()) > 0.99);
+        assert!((fraction_positive(&mut jumping.clone()) - 0.5).abs() < 0.1);
+
+        // Draws from the prior are always accepted when it is the posterior.
+        let mut prior_draws = SRWM::new(parameter, |_m: &Model| 0.0, None)
+            .unwrap()
+            .mode_jumps(util::ModeJumps::new(1.0, 1.0, 1.0));
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        (0..100).fold(Model { x: 0.0 }, |m, _| {
+            let next = prior_draws.step(&mut rng, m);
+            assert!(prior_draws.mh.log_acceptance.abs() < 1E-10);
+            next
+        });
+    }
 }
@@ -0,0 +1,1449 @@
+//! # Tempering
+//! Flattens the posterior over a ladder of temperatures so chains can
+//! cross between its modes. Simulated tempering moves a single chain up
+//! and down the ladder; parallel tempering runs a replica at every
+//! temperature and swaps their states.
+
+use std::fmt;
+use std::io;
+use nalgebra::DMatrix;
+use rand::Rng;
+use events::EventSink;
+use lens::Lens;
+use likelihood::DeltaLogLikelihood;
+use parameter::ParamId;
+use statistics::{Statistic, StatisticValue};
+use steppers::{AdaptationMode, AdaptationStatus, Repeat, SteppingAlg};
+use steppers::util;
+use steppers::adaptor::{next_state, AdaptorState};
+use utils::invalid_input;
+
+/// Name the acceptance rate of temperature moves is reported under
+pub const TEMPERATURE_ID: &str = "temperature";
+
+/// Steppers whose target can be tempered to `p(θ) L(θ)^(1 / T)` at a
+/// temperature `T`, flattening the likelihood for `T > 1`
+pub trait Tempering {
+    /// Target the posterior tempered to `temperature`, 1 for the posterior
+    /// itself.
+    fn set_temperature(&mut self, temperature: f64);
+    /// The temperature currently targeted
+    fn temperature(&self) -> f64;
+}
+
+/// A stepper which can be tempered, so a `Group` of them can be boxed
+/// together and still be tempered
+pub trait TemperingStepper<M, R: Rng>: SteppingAlg<M, R> + Tempering {}
+
+impl<M, R, A> TemperingStepper<M, R> for A
+where
+    R: Rng,
+    A: SteppingAlg<M, R> + Tempering,
+{
+}
+
+/// Ladder of `n` temperatures from 1 to `max_temperature` in geometric
+/// progression, so neighbouring temperatures differ by a constant ratio
+///
+/// Fails with `InvalidInput` if `n` is less than two or `max_temperature`
+/// is not finite and greater than one.
+pub fn geometric_ladder(
+    n: usize,
+    max_temperature: f64,
+) -> io::Result<Vec<f64>> {
+    if n < 2 {
+        return Err(invalid_input(
+            "a ladder requires at least two temperatures.",
+        ));
+    }
+    if !(max_temperature > 1.0 && max_temperature.is_finite()) {
+        return Err(invalid_input(
+            "the largest temperature must be finite and greater than one.",
+        ));
+    }
+    Ok((0..n)
+        .map(|i| max_temperature.powf(i as f64 / (n - 1) as f64))
+        .collect())
+}
+
+/// Simulated tempering of a `Tempering` stepper
+///
+/// The model carries the index of its temperature in a ladder, read and
+/// written through a lens. Each step the inner stepper updates the model
+/// at that temperature, and then a move to a neighbouring temperature is
+/// proposed and accepted by the Metropolis rule on the joint target
+/// `w_k p(θ) L(θ)^(1 / T_k)`. Only the untempered log likelihood is
+/// evaluated for the move, once per step.
+///
+/// The weights `w_k` should be near the inverse of the normalizing
+/// constant of each tempered target, so the chain spends similar time at
+/// every temperature. They are learned while adaptation is enabled, by
+/// the Wang-Landau rule: the log weight of the temperature visited is
+/// lowered by an increment, which is halved whenever the visits since it
+/// last changed are roughly even across the ladder. Adapting the weights
+/// does not leave the target invariant, so draws are only valid once
+/// adaptation is disabled at the end of warmup.
+///
+/// With `adapt_ladder` the temperatures between the first and the last
+/// are also adapted while adaptation is enabled, as in Vousden, Farr and
+/// Mandel (2016): the gap in log temperature between two neighbours
+/// widens when moves between them are accepted more often than moves
+/// between other neighbours, and narrows when they are accepted less
+/// often, evening out the acceptance along the ladder. The mean
+/// acceptance probability of moves between each pair of neighbours is
+/// reported by `neighbour_acceptance`.
+///
+/// Draws of the posterior are the models at index 0, whose temperature
+/// must be 1. Stepping a model whose index is off the ladder panics.
+///
+/// # Example
+/// ```
+/// # #[macro_use] extern crate rmcmc;
+/// # extern crate rand;
+/// # extern crate rv;
+/// # use rmcmc::lens::*;
+/// # use rmcmc::parameter::Parameter;
+/// # use rmcmc::runner::Runner;
+/// # use rmcmc::steppers::tempering::geometric_ladder;
+/// # use rmcmc::steppers::{SimulatedTempering, SRWM};
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # use rv::dist::Uniform;
+/// # fn main() {
+/// #[derive(Clone, Debug)]
+/// struct Model {
+///     x: f64,
+///     level: usize,
+/// }
+///
+/// // Narrow modes at -5 and 5, far apart for a random walk
+/// fn log_likelihood(m: &Model) -> f64 {
+///     let d = m.x.abs() - 5.0;
+///     -d * d / (2.0 * 0.3 * 0.3)
+/// }
+///
+/// let x = Parameter::new(
+///     "x".to_string(),
+///     Uniform::new(-10.0, 10.0).unwrap(),
+///     make_lens!(Model, f64, x),
+/// );
+/// let srwm = SRWM::new(x, log_likelihood, Some(0.5)).unwrap();
+/// let tempering = SimulatedTempering::new(
+///     srwm,
+///     log_likelihood,
+///     geometric_ladder(6, 200.0).unwrap(),
+///     make_lens!(Model, usize, level),
+/// )
+/// .unwrap();
+///
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let draws = Runner::new(tempering)
+///     .warmup(2000)
+///     .samples(4000)
+///     .run(&mut rng, Model { x: 5.0, level: 0 });
+/// let posterior: Vec<f64> = draws[0]
+///     .iter()
+///     .filter(|m| m.level == 0)
+///     .map(|m| m.x)
+///     .collect();
+/// // Both modes are visited at the posterior's temperature.
+/// assert!(posterior.iter().any(|&x| x < 0.0));
+/// assert!(posterior.iter().any(|&x| x > 0.0));
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SimulatedTempering<M, S, L> {
+    stepper: S,
+    log_likelihood: L,
+    temperatures: Vec<f64>,
+    initial_temperatures: Vec<f64>,
+    level: Lens<usize, M>,
+    log_weights: Vec<f64>,
+    // Visits to each temperature since the increment last changed
+    visits: Vec<usize>,
+    initial_increment: f64,
+    increment: f64,
+    flatness: f64,
+    adapting: bool,
+    moves: util::AcceptanceCounter,
+    // Sum of acceptance probabilities and number of moves proposed
+    // between temperatures k and k + 1
+    neighbour_alpha: Vec<f64>,
+    neighbour_moves: Vec<usize>,
+    // Steps over which the gain of ladder adaptation halves, if adapted
+    ladder_lag: Option<f64>,
+    ladder_steps: usize,
+}
+
+impl<M, S, L> SimulatedTempering<M, S, L>
+where
+    M: 'static,
+    S: Tempering,
+    L: DeltaLogLikelihood<M>,
+{
+    /// Temper `stepper` over `temperatures`, storing the index of the
+    /// model's temperature through `level`. `log_likelihood` must be the
+    /// untempered likelihood of the stepper.
+    ///
+    /// Fails with `InvalidInput` unless there are at least two finite,
+    /// increasing temperatures, the first of them 1.
+    pub fn new(
+        stepper: S,
+        log_likelihood: L,
+        temperatures: Vec<f64>,
+        level: Lens<usize, M>,
+    ) -> io::Result<Self> {
+        check_ladder(&temperatures)?;
+        let n = temperatures.len();
+        Ok(SimulatedTempering {
+            stepper,
+            log_likelihood,
+            initial_temperatures: temperatures.clone(),
+            temperatures,
+            level,
+            log_weights: vec![0.0; n],
+            visits: vec![0; n],
+            initial_increment: 1.0,
+            increment: 1.0,
+            flatness: 0.8,
+            adapting: false,
+            moves: util::AcceptanceCounter::new(),
+            neighbour_alpha: vec![0.0; n - 1],
+            neighbour_moves: vec![0; n - 1],
+            ladder_lag: None,
+            ladder_steps: 0,
+        })
+    }
+
+    /// The temperatures of the ladder, as adapted so far
+    pub fn temperatures(&self) -> &[f64] {
+        &self.temperatures
+    }
+
+    /// Mean acceptance probability of the moves proposed between each
+    /// temperature and the next, if any were
+    pub fn neighbour_acceptance(&self) -> Vec<Option<f64>> {
+        self.neighbour_alpha
+            .iter()
+            .zip(&self.neighbour_moves)
+            .map(|(&alpha, &n)| {
+                if n > 0 {
+                    Some(alpha / n as f64)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Log weight of each temperature, relative to the first
+    pub fn log_weights(&self) -> &[f64] {
+        &self.log_weights
+    }
+
+    /// The inner stepper
+    pub fn stepper(&self) -> &S {
+        &self.stepper
+    }
+
+    // Set the inner stepper's temperature to that of `level`, keeping its
+    // cached score if it is already there.
+    fn heat(&mut self, level: usize) {
+        let temperature = self.temperatures[level];
+        if self.stepper.temperature() != temperature {
+            self.stepper.set_temperature(temperature);
+        }
+    }
+
+    // Wang-Landau update of the weights after a step ending at `level`
+    fn adapt(&mut self, level: usize) {
+        self.log_weights[level] -= self.increment;
+        let first = self.log_weights[0];
+        self.log_weights.iter_mut().for_each(|w| *w -= first);
+
+        self.visits[level] += 1;
+        let total: usize = self.visits.iter().sum();
+        let fewest = *self.visits.iter().min().unwrap();
+        let mean = total as f64 / self.visits.len() as f64;
+        if fewest as f64 >= self.flatness * mean {
+            self.increment /= 2.0;
+            self.visits.iter_mut().for_each(|v| *v = 0);
+        }
+    }
+
+    // Move the gap in log temperature between `pair` and the next by the
+    // difference of its move's acceptance probability `alpha` from the
+    // mean over all moves, keeping the first and last temperatures.
+    fn respace(&mut self, pair: usize, alpha: f64) {
+        let lag = match self.ladder_lag {
+            Some(lag) => lag,
+            None => return,
+        };
+        let moves: usize = self.neighbour_moves.iter().sum();
+        let mean = self.neighbour_alpha.iter().sum::<f64>() / moves as f64;
+        self.ladder_steps += 1;
+        let gain = LADDER_GAIN * lag / (lag + self.ladder_steps as f64);
+
+        let mut gaps = log_gaps(&self.temperatures);
+        gaps[pair] *= (gain * (alpha - mean)).exp();
+        respace_ladder(&mut self.temperatures, &gaps);
+    }
+}
+
+/// Largest change in a log gap of the ladder per step, as a fraction of
+/// the gap, before the gain decays
+const LADDER_GAIN: f64 = 0.1;
+
+fn check_ladder(temperatures: &[f64]) -> io::Result<()> {
+    let increasing = temperatures.windows(2).all(|t| t[0] < t[1]);
+    if temperatures.len() < 2
+        || temperatures[0] != 1.0
+        || !increasing
+        || !temperatures.iter().all(|t| t.is_finite())
+    {
+        return Err(invalid_input(
+            "tempering needs at least two finite, increasing temperatures \
+             starting from 1.",
+        ));
+    }
+    Ok(())
+}
+
+// Gaps in log temperature between each temperature and the next
+fn log_gaps(temperatures: &[f64]) -> Vec<f64> {
+    temperatures
+        .windows(2)
+        .map(|t| t[1].ln() - t[0].ln())
+        .collect()
+}
+
+// Place the temperatures between the first and the last so their log gaps
+// are in proportion to `gaps`.
+fn respace_ladder(temperatures: &mut [f64], gaps: &[f64]) {
+    let n = temperatures.len();
+    let span = temperatures[n - 1].ln();
+    let total: f64 = gaps.iter().sum();
+    let mut ln_t = 0.0;
+    for k in 1..n - 1 {
+        ln_t += gaps[k - 1] * span / total;
+        temperatures[k] = ln_t.exp();
+    }
+}
+
+impl<M, S, L> SimulatedTempering<M, S, L>
+where
+    M: Clone,
+    S: Clone,
+    L: Clone,
+{
+    /// Start the Wang-Landau increment of the log weights at `increment`,
+    /// by default 1. Larger increments learn the weights faster but more
+    /// coarsely.
+    ///
+    /// Fails with `InvalidInput` unless `increment` is finite and positive.
+    pub fn initial_increment(&self, increment: f64) -> io::Result<Self> {
+        check_increment(increment)?;
+        Ok(SimulatedTempering {
+            initial_increment: increment,
+            increment,
+            ..(*self).clone()
+        })
+    }
+
+    /// Halve the increment once the fewest visits to a temperature are at
+    /// least `flatness` times the mean, by default 0.8.
+    ///
+    /// Fails with `InvalidInput` unless `flatness` is in (0, 1].
+    pub fn flatness(&self, flatness: f64) -> io::Result<Self> {
+        if !(flatness > 0.0 && flatness <= 1.0) {
+            return Err(invalid_input("flatness must be in (0, 1]."));
+        }
+        Ok(SimulatedTempering {
+            flatness,
+            ..(*self).clone()
+        })
+    }
+
+    /// Adapt the temperatures between the first and the last while
+    /// adaptation is enabled, with a gain halving after `lag` adaptive
+    /// steps. Longer lags keep adapting for longer.
+    ///
+    /// Fails with `InvalidInput` unless `lag` is finite and positive.
+    pub fn adapt_ladder(&self, lag: f64) -> io::Result<Self> {
+        if !(lag > 0.0 && lag.is_finite()) {
+            return Err(invalid_input("lag must be finite and positive."));
+        }
+        Ok(SimulatedTempering {
+            ladder_lag: Some(lag),
+            ..(*self).clone()
+        })
+    }
+
+    /// Start from the log weights of an earlier run, e.g. from
+    /// `log_weights` after a warmup on similar data, with the increment
+    /// already at `increment`.
+    ///
+    /// Fails with `InvalidInput` unless there is one finite log weight per
+    /// temperature and `increment` is finite and positive.
+    pub fn warm_start(
+        &self,
+        log_weights: Vec<f64>,
+        increment: f64,
+    ) -> io::Result<Self> {
+        if log_weights.len() != self.temperatures.len()
+            || !log_weights.iter().all(|w| w.is_finite())
+        {
+            return Err(invalid_input(
+                "there must be one finite log weight per temperature.",
+            ));
+        }
+        check_increment(increment)?;
+        Ok(SimulatedTempering {
+            log_weights,
+            increment,
+            ..(*self).clone()
+        })
+    }
+}
+
+fn check_increment(increment: f64) -> io::Result<()> {
+    if increment > 0.0 && increment.is_finite() {
+        Ok(())
+    } else {
+        Err(invalid_input("increment must be finite and positive."))
+    }
+}
+
+impl<M, S, L> fmt::Debug for SimulatedTempering<M, S, L>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SimulatedTempering {{ stepper: {:?}, temperatures: {:?}, \
+             log_weights: {:?} }}",
+            self.stepper, self.temperatures, self.log_weights
+        )
+    }
+}
+
+impl<M, S, L, R> SteppingAlg<M, R> for SimulatedTempering<M, S, L>
+where
+    M: 'static,
+    S: SteppingAlg<M, R> + Tempering,
+    L: DeltaLogLikelihood<M>,
+    R: Rng,
+{
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        let n = self.temperatures.len();
+        let level = self.level.get(&model);
+        assert!(
+            level < n,
+            "level {} is off the ladder of {} temperatures.",
+            level,
+            n
+        );
+        self.heat(level);
+        let model = self.stepper.step(rng, model);
+
+        // Propose a neighbouring temperature, rejecting moves off the
+        // ladder so the proposal stays symmetric.
+        let proposed = if rng.gen() {
+            level + 1
+        } else {
+            level.wrapping_sub(1)
+        };
+        let next = if proposed < n {
+            let ln_l = self.log_likelihood.ln_f(&model);
+            let score = |k: usize| {
+                self.log_weights[k] + ln_l / self.temperatures[k]
+            };
+            let scores =
+                util::ProposalScores::new(score(level), score(proposed));
+            let update = util::metropolis_select(rng, scores, proposed, level);
+            self.moves.record(&update);
+            let alpha = update.log_alpha().exp();
+            if !alpha.is_nan() {
+                let pair = level.min(proposed);
+                self.neighbour_alpha[pair] += alpha.min(1.0);
+                self.neighbour_moves[pair] += 1;
+                if self.adapting {
+                    self.respace(pair, alpha.min(1.0));
+                }
+            }
+            *update.value()
+        } else {
+            level
+        };
+
+        if self.adapting {
+            self.adapt(next);
+        }
+        self.level.set(&model, next)
+    }
+
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        self.adapting = match mode {
+            AdaptationMode::Enabled => true,
+            AdaptationMode::Disabled => false,
+        };
+        self.stepper.set_adapt(mode);
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        match (self.adapting, self.stepper.get_adapt()) {
+            (true, AdaptationStatus::Enabled) => AdaptationStatus::Enabled,
+            (false, AdaptationStatus::Disabled) => AdaptationStatus::Disabled,
+            _ => AdaptationStatus::Mixed,
+        }
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        let mut statistics = self.stepper.get_statistics();
+        if let Some(rate) = self.moves.rate() {
+            statistics.push(Statistic::new(
+                ParamId(TEMPERATURE_ID.to_string()),
+                StatisticValue::AcceptanceRate(rate),
+            ));
+        }
+        statistics
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        self.stepper.parameters()
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        self.stepper.dependencies()
+    }
+
+    fn reset(&mut self) {
+        self.stepper.reset();
+        self.log_weights.iter_mut().for_each(|w| *w = 0.0);
+        self.visits.iter_mut().for_each(|v| *v = 0);
+        self.increment = self.initial_increment;
+        self.moves.reset();
+        self.temperatures = self.initial_temperatures.clone();
+        self.neighbour_alpha.iter_mut().for_each(|a| *a = 0.0);
+        self.neighbour_moves.iter_mut().for_each(|n| *n = 0);
+        self.ladder_steps = 0;
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.stepper.invalidate_cache();
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.stepper.fix(parameter)
+    }
+
+    fn set_prior_cache(&mut self, cache: util::PriorCache) {
+        self.stepper.set_prior_cache(cache)
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.stepper.set_event_sink(sink)
+    }
+
+    /// Draw from the prior, starting at the posterior's temperature.
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        let model = self.stepper.draw_prior(rng, model);
+        self.level.set(&model, 0)
+    }
+
+    // The ladder and its weights, followed by the tempered stepper's state
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        let scales = self
+            .temperatures
+            .iter()
+            .cloned()
+            .chain(Some(self.increment));
+        let moments = self
+            .log_weights
+            .iter()
+            .chain(self.neighbour_alpha.iter())
+            .cloned();
+        let counts = self
+            .visits
+            .iter()
+            .chain(self.neighbour_moves.iter())
+            .cloned()
+            .chain(Some(self.ladder_steps));
+        let own = AdaptorState {
+            name: TEMPERATURE_ID.to_string(),
+            ..AdaptorState::new(
+                scales.collect(),
+                moments.collect(),
+                counts.collect(),
+            )
+        };
+        Some(own)
+            .into_iter()
+            .chain(self.stepper.adaptation_state())
+            .collect()
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        let own = next_state(states, TEMPERATURE_ID)?;
+        let n = self.temperatures.len();
+        own.expect(n + 1, 2 * n - 1, 2 * n)?;
+        self.temperatures = own.scales[..n].to_vec();
+        self.increment = own.scales[n];
+        self.log_weights = own.moments[..n].to_vec();
+        self.neighbour_alpha = own.moments[n..].to_vec();
+        self.visits = own.counts[..n].to_vec();
+        self.neighbour_moves = own.counts[n..2 * n - 1].to_vec();
+        self.ladder_steps = own.counts[2 * n - 1];
+        self.stepper.restore_adaptation(states)
+    }
+}
+
+/// Parallel tempering of a `Tempering` stepper
+///
+/// A replica of the stepper runs at each temperature of a ladder, each on
+/// its own model. Every step each replica steps its model, and then swaps
+/// of the models at neighbouring temperatures are proposed, from the
+/// hottest pair down, and accepted by the Metropolis rule on the joint
+/// target `Π_k p(θ_k) L(θ_k)^(1 / T_k)`. The untempered log likelihood of
+/// every replica's model is evaluated once per step.
+///
+/// The model stepped is the one at temperature 1, whose draws are of the
+/// posterior. The hotter models are kept by the stepper, starting as
+/// copies of the first model it steps. They are not part of its
+/// adaptation state, so they start again from the model after a reset or
+/// when a chain is restored from a snapshot.
+///
+/// With `adapt_ladder` the temperatures between the first and the last
+/// are adapted while adaptation is enabled, by the scheme of Vousden, Farr
+/// and Mandel (2016): the log gap between two neighbours grows by the
+/// difference of their swap acceptance probability from that of the next
+/// pair up the ladder, with a decaying gain, evening out the acceptance
+/// along the ladder. Adapting the ladder does not leave the target
+/// invariant, so draws are only valid once adaptation is disabled at the
+/// end of warmup.
+///
+/// `swap_acceptance` gives the mean acceptance probability of swaps
+/// between every pair of temperatures, e.g. for
+/// `RunSummary::with_swap_acceptance`.
+///
+/// # Example
+/// ```
+/// # #[macro_use] extern crate rmcmc;
+/// # extern crate rand;
+/// # extern crate rv;
+/// # use rmcmc::lens::*;
+/// # use rmcmc::parameter::Parameter;
+/// # use rmcmc::runner::Runner;
+/// # use rmcmc::steppers::tempering::geometric_ladder;
+/// # use rmcmc::steppers::{ParallelTempering, SRWM};
+/// # use rand::SeedableRng;
+/// # use rand::rngs::StdRng;
+/// # use rv::dist::Uniform;
+/// # fn main() {
+/// #[derive(Clone, Debug)]
+/// struct Model {
+///     x: f64,
+/// }
+///
+/// // Narrow modes at -5 and 5, far apart for a random walk
+/// fn log_likelihood(m: &Model) -> f64 {
+///     let d = m.x.abs() - 5.0;
+///     -d * d / (2.0 * 0.3 * 0.3)
+/// }
+///
+/// let x = Parameter::new(
+///     "x".to_string(),
+///     Uniform::new(-10.0, 10.0).unwrap(),
+///     make_lens!(Model, f64, x),
+/// );
+/// let srwm = SRWM::new(x, log_likelihood, Some(0.5)).unwrap();
+/// let tempering = ParallelTempering::new(
+///     srwm,
+///     log_likelihood,
+///     geometric_ladder(6, 200.0).unwrap(),
+/// )
+/// .unwrap()
+/// .adapt_ladder(100.0)
+/// .unwrap();
+///
+/// let mut rng = StdRng::from_seed([0; 32]);
+/// let draws = Runner::new(tempering)
+///     .warmup(1000)
+///     .samples(2000)
+///     .run(&mut rng, Model { x: 5.0 });
+/// // Both modes are visited at the posterior's temperature.
+/// assert!(draws[0].iter().any(|m| m.x < 0.0));
+/// assert!(draws[0].iter().any(|m| m.x > 0.0));
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ParallelTempering<M, S, L> {
+    replicas: Vec<S>,
+    log_likelihood: L,
+    temperatures: Vec<f64>,
+    initial_temperatures: Vec<f64>,
+    // Models of the replicas above temperature 1, once stepped
+    hot: Vec<M>,
+    adapting: bool,
+    swaps: util::AcceptanceCounter,
+    // Sum over steps of the acceptance probability of a swap between each
+    // pair of temperatures, row-major
+    pair_alpha: Vec<f64>,
+    pair_steps: usize,
+    // Steps over which the gain of ladder adaptation halves, if adapted
+    ladder_lag: Option<f64>,
+    ladder_steps: usize,
+}
+
+impl<M, S, L> ParallelTempering<M, S, L>
+where
+    S: Tempering + Clone,
+{
+    /// Run a replica of `stepper` at each of `temperatures`.
+    /// `log_likelihood` must be the untempered likelihood of the stepper.
+    ///
+    /// Fails with `InvalidInput` unless there are at least two finite,
+    /// increasing temperatures, the first of them 1.
+    pub fn new(
+        stepper: S,
+        log_likelihood: L,
+        temperatures: Vec<f64>,
+    ) -> io::Result<Self> {
+        check_ladder(&temperatures)?;
+        let replicas = temperatures
+            .iter()
+            .map(|&t| {
+                let mut replica = stepper.clone();
+                replica.set_temperature(t);
+                replica
+            })
+            .collect();
+        let n = temperatures.len();
+        Ok(ParallelTempering {
+            replicas,
+            log_likelihood,
+            initial_temperatures: temperatures.clone(),
+            temperatures,
+            hot: Vec::new(),
+            adapting: false,
+            swaps: util::AcceptanceCounter::new(),
+            pair_alpha: vec![0.0; n * n],
+            pair_steps: 0,
+            ladder_lag: None,
+            ladder_steps: 0,
+        })
+    }
+
+    /// The temperatures of the ladder, as adapted so far
+    pub fn temperatures(&self) -> &[f64] {
+        &self.temperatures
+    }
+
+    /// Mean acceptance probability of a swap between each pair of
+    /// temperatures, evaluated on the replicas' models before each step's
+    /// swaps, if a step was taken. The entries next to the diagonal are
+    /// of the swaps proposed.
+    pub fn swap_acceptance(&self) -> Option<DMatrix<f64>> {
+        if self.pair_steps == 0 {
+            return None;
+        }
+        let n = self.temperatures.len();
+        let steps = self.pair_steps as f64;
+        let sums = DMatrix::from_row_slice(n, n, &self.pair_alpha);
+        Some(sums.map(|a| a / steps))
+    }
+
+    /// The replica stepping the model at temperature 1
+    pub fn stepper(&self) -> &S {
+        &self.replicas[0]
+    }
+
+    // Scores of the models at temperatures `i` and `j`, with log
+    // likelihoods `ln_l`, before and after swapping them
+    fn swap_scores(
+        &self,
+        ln_l: &[f64],
+        i: usize,
+        j: usize,
+    ) -> util::ProposalScores {
+        let (t_i, t_j) = (self.temperatures[i], self.temperatures[j]);
+        util::ProposalScores::new(
+            ln_l[i] / t_i + ln_l[j] / t_j,
+            ln_l[j] / t_i + ln_l[i] / t_j,
+        )
+    }
+
+    fn record_pairs(&mut self, ln_l: &[f64]) {
+        let n = self.temperatures.len();
+        for i in 0..n {
+            self.pair_alpha[i * n + i] += 1.0;
+            for j in i + 1..n {
+                let alpha = acceptance(self.swap_scores(ln_l, i, j));
+                self.pair_alpha[i * n + j] += alpha;
+                self.pair_alpha[j * n + i] += alpha;
+            }
+        }
+        self.pair_steps += 1;
+    }
+
+    // Vousden, Farr and Mandel's update of the log gaps from the swap
+    // acceptance probabilities `alphas` of a step, keeping the first and
+    // last temperatures
+    fn respace(&mut self, alphas: &[f64]) {
+        let lag = match self.ladder_lag {
+            Some(lag) => lag,
+            None => return,
+        };
+        self.ladder_steps += 1;
+        let gain = LADDER_GAIN * lag / (lag + self.ladder_steps as f64);
+
+        let mut gaps = log_gaps(&self.temperatures);
+        for k in 0..gaps.len() - 1 {
+            gaps[k] *= (gain * (alphas[k] - alphas[k + 1])).exp();
+        }
+        respace_ladder(&mut self.temperatures, &gaps);
+        self.heat();
+    }
+
+    // Set each replica to its temperature on the ladder.
+    fn heat(&mut self) {
+        for (replica, &t) in self.replicas.iter_mut().zip(&self.temperatures)
+        {
+            if replica.temperature() != t {
+                replica.set_temperature(t);
+            }
+        }
+    }
+}
+
+impl<M, S, L> ParallelTempering<M, S, L>
+where
+    M: Clone,
+    S: Clone,
+    L: Clone,
+{
+    /// Adapt the temperatures between the first and the last while
+    /// adaptation is enabled, with a gain halving after `lag` adaptive
+    /// steps. Longer lags keep adapting for longer.
+    ///
+    /// Fails with `InvalidInput` unless `lag` is finite and positive.
+    pub fn adapt_ladder(&self, lag: f64) -> io::Result<Self> {
+        if !(lag > 0.0 && lag.is_finite()) {
+            return Err(invalid_input("lag must be finite and positive."));
+        }
+        Ok(ParallelTempering {
+            ladder_lag: Some(lag),
+            ..(*self).clone()
+        })
+    }
+}
+
+// Acceptance probability of a proposal, taking a NaN ratio as a rejection
+fn acceptance(scores: util::ProposalScores) -> f64 {
+    let alpha = scores.log_alpha.exp().min(1.0);
+    if alpha.is_nan() {
+        0.0
+    } else {
+        alpha
+    }
+}
+
+impl<M, S, L> fmt::Debug for ParallelTempering<M, S, L>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ParallelTempering {{ stepper: {:?}, temperatures: {:?} }}",
+            self.replicas[0], self.temperatures
+        )
+    }
+}
+
+impl<M, S, L, R> SteppingAlg<M, R> for ParallelTempering<M, S, L>
+where
+    M: Clone,
+    S: SteppingAlg<M, R> + Tempering + Clone,
+    L: DeltaLogLikelihood<M>,
+    R: Rng,
+{
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        let n = self.temperatures.len();
+        if self.hot.is_empty() {
+            self.hot = vec![model.clone(); n - 1];
+        }
+        let mut models: Vec<M> = Some(model)
+            .into_iter()
+            .chain(self.hot.drain(..))
+            .zip(self.replicas.iter_mut())
+            .map(|(m, replica)| replica.step(rng, m))
+            .collect();
+        let mut ln_l: Vec<f64> = models
+            .iter()
+            .map(|m| self.log_likelihood.ln_f(m))
+            .collect();
+        self.record_pairs(&ln_l);
+
+        // Swap from the hottest pair down, so a model can fall through the
+        // whole ladder in one step.
+        let mut alphas = vec![0.0; n - 1];
+        for k in (0..n - 1).rev() {
+            let scores = self.swap_scores(&ln_l, k, k + 1);
+            alphas[k] = acceptance(scores);
+            let update = util::metropolis_select(rng, scores, true, false);
+            self.swaps.record(&update);
+            if update.is_accepted() {
+                models.swap(k, k + 1);
+                ln_l.swap(k, k + 1);
+                // Their cached scores are of the models they had.
+                self.replicas[k].invalidate_cache();
+                self.replicas[k + 1].invalidate_cache();
+            }
+        }
+        if self.adapting {
+            self.respace(&alphas);
+        }
+
+        let cold = models.remove(0);
+        self.hot = models;
+        cold
+    }
+
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        self.adapting = match mode {
+            AdaptationMode::Enabled => true,
+            AdaptationMode::Disabled => false,
+        };
+        self.replicas.iter_mut().for_each(|r| r.set_adapt(mode));
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        match (self.adapting, self.replicas[0].get_adapt()) {
+            (true, AdaptationStatus::Enabled) => AdaptationStatus::Enabled,
+            (false, AdaptationStatus::Disabled) => AdaptationStatus::Disabled,
+            _ => AdaptationStatus::Mixed,
+        }
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        let mut statistics = self.replicas[0].get_statistics();
+        if let Some(rate) = self.swaps.rate() {
+            statistics.push(Statistic::new(
+                ParamId(TEMPERATURE_ID.to_string()),
+                StatisticValue::AcceptanceRate(rate),
+            ));
+        }
+        statistics
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        self.replicas[0].parameters()
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        self.replicas[0].dependencies()
+    }
+
+    fn reset(&mut self) {
+        self.replicas.iter_mut().for_each(|r| r.reset());
+        self.temperatures = self.initial_temperatures.clone();
+        self.heat();
+        self.hot.clear();
+        self.swaps.reset();
+        self.pair_alpha.iter_mut().for_each(|a| *a = 0.0);
+        self.pair_steps = 0;
+        self.ladder_steps = 0;
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.replicas.iter_mut().for_each(|r| r.invalidate_cache());
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.replicas.iter_mut().for_each(|r| r.fix(parameter));
+    }
+
+    // The replicas step models other than the one the steppers around them
+    // see, so they keep out of any prior cache shared with those.
+
+    // Only the posterior's replica reports events, which are then of the
+    // model being drawn.
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.replicas[0].set_event_sink(sink)
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        self.replicas[0].draw_prior(rng, model)
+    }
+
+    // The ladder and the swap acceptance, followed by each replica's state
+    // from the coldest up
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        let own = AdaptorState {
+            name: TEMPERATURE_ID.to_string(),
+            ..AdaptorState::new(
+                self.temperatures.clone(),
+                self.pair_alpha.clone(),
+                vec![self.pair_steps, self.ladder_steps],
+            )
+        };
+        Some(own)
+            .into_iter()
+            .chain(self.replicas.iter().flat_map(|r| r.adaptation_state()))
+            .collect()
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        let own = next_state(states, TEMPERATURE_ID)?;
+        let n = self.temperatures.len();
+        own.expect(n, n * n, 2)?;
+        self.temperatures = own.scales;
+        self.heat();
+        self.pair_alpha = own.moments;
+        self.pair_steps = own.counts[0];
+        self.ladder_steps = own.counts[1];
+        for replica in self.replicas.iter_mut() {
+            replica.restore_adaptation(states)?;
+        }
+        Ok(())
+    }
+}
+
+impl<A: Tempering> Tempering for Repeat<A> {
+    fn set_temperature(&mut self, temperature: f64) {
+        self.0.set_temperature(temperature)
+    }
+
+    fn temperature(&self) -> f64 {
+        self.0.temperature()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{DMatrix, DVector};
+    use parameter::Parameter;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use runner::Runner;
+    use rv::dist::{Gaussian, MvGaussian, Uniform};
+    use rv::misc::{ks_test, logsumexp};
+    use rv::traits::{Cdf, Rv};
+    use steppers::{AMBuilder, Group, VectorSRWM, SRWM};
+    use utils::multiple_tries;
+
+    const P_VAL: f64 = 0.2;
+    const N_TRIES: usize = 10;
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Clone, Copy, Debug)]
+    struct Model {
+        x: f64,
+        level: usize,
+    }
+
+    fn x() -> Parameter<Uniform, f64, Model> {
+        Parameter::new(
+            "x".to_string(),
+            Uniform::new(-20.0, 20.0).unwrap(),
+            make_lens!(Model, f64, x),
+        )
+    }
+
+    fn standard(m: &Model) -> f64 {
+        Gaussian::new(0.0, 1.0).unwrap().ln_f(&m.x)
+    }
+
+    #[test]
+    fn ladders_are_validated() {
+        let ladder = geometric_ladder(3, 16.0).unwrap();
+        assert_eq!(ladder, vec![1.0, 4.0, 16.0]);
+        assert!(geometric_ladder(1, 16.0).is_err());
+        assert!(geometric_ladder(3, 1.0).is_err());
+        assert!(geometric_ladder(3, std::f64::NAN).is_err());
+
+        let srwm = SRWM::new(x(), standard, None).unwrap();
+        let level = make_lens!(Model, usize, level);
+        let tempering = |temperatures: Vec<f64>| {
+            SimulatedTempering::new(
+                srwm.clone(),
+                standard,
+                temperatures,
+                level.clone(),
+            )
+        };
+        assert!(tempering(vec![1.0]).is_err());
+        assert!(tempering(vec![2.0, 4.0]).is_err());
+        assert!(tempering(vec![1.0, 4.0, 3.0]).is_err());
+        assert!(tempering(vec![1.0, std::f64::INFINITY]).is_err());
+
+        let tempering = tempering(ladder).unwrap();
+        assert!(tempering.initial_increment(0.5).is_ok());
+        assert!(tempering.initial_increment(0.0).is_err());
+        assert!(tempering.flatness(1.0).is_ok());
+        assert!(tempering.flatness(1.5).is_err());
+        assert!(tempering.warm_start(vec![0.0, -1.0, -2.0], 0.1).is_ok());
+        assert!(tempering.warm_start(vec![0.0, -1.0], 0.1).is_err());
+        assert!(tempering.warm_start(vec![0.0; 3], std::f64::NAN).is_err());
+
+        let parallel = |temperatures: Vec<f64>| {
+            ParallelTempering::new(srwm.clone(), standard, temperatures)
+        };
+        assert!(parallel(vec![1.0]).is_err());
+        assert!(parallel(vec![1.0, 4.0, 3.0]).is_err());
+        let parallel: ParallelTempering<Model, _, _> =
+            parallel(vec![1.0, 2.0]).unwrap();
+        assert!(parallel.adapt_ladder(100.0).is_ok());
+        assert!(parallel.adapt_ladder(-1.0).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "off the ladder")]
+    fn levels_off_the_ladder_panic() {
+        let srwm = SRWM::new(x(), standard, None).unwrap();
+        let mut tempering = SimulatedTempering::new(
+            srwm,
+            standard,
+            vec![1.0, 2.0],
+            make_lens!(Model, usize, level),
+        )
+        .unwrap();
+        let mut rng = StdRng::from_seed(SEED);
+        tempering.step(&mut rng, Model { x: 0.0, level: 2 });
+    }
+
+    // Whether `stepper` at temperature 4 samples `tempered`, reading the
+    // draws with `x`
+    fn samples_at_temperature_4<M, S>(
+        mut stepper: S,
+        init: M,
+        x: fn(&M) -> f64,
+        tempered: Gaussian,
+    ) -> bool
+    where
+        M: Clone,
+        S: SteppingAlg<M, StdRng> + Tempering,
+    {
+        stepper.set_temperature(4.0);
+        assert_eq!(stepper.temperature(), 4.0);
+        let mut rng = StdRng::from_seed(SEED);
+        multiple_tries(N_TRIES, |_| {
+            let mut m = init.clone();
+            let xs: Vec<f64> = (0..5000)
+                .filter_map(|i| {
+                    m = stepper.step(&mut rng, m.clone());
+                    if i % 10 == 0 { Some(x(&m)) } else { None }
+                })
+                .collect();
+            let (stat, p) = ks_test(&xs, |x| tempered.cdf(&x));
+            println!("test stat = {}, p = {}", stat, p);
+            p > P_VAL
+        })
+    }
+
+    #[test]
+    fn srwm_samples_the_tempered_posterior() {
+        // A standard normal likelihood at temperature 4 is N(0, 2²).
+        let srwm = SRWM::new(x(), standard, Some(2.0)).unwrap();
+        assert!(samples_at_temperature_4(
+            srwm,
+            Model { x: 0.0, level: 0 },
+            |m| m.x,
+            Gaussian::new(0.0, 2.0).unwrap(),
+        ));
+    }
+
+    #[test]
+    fn groups_temper_their_steppers() {
+        let srwm = SRWM::new(x(), standard, Some(2.0)).unwrap();
+        let group: Group<Model, StdRng, _> =
+            Group::tempered(vec![Box::new(srwm)]);
+        assert!(samples_at_temperature_4(
+            group,
+            Model { x: 0.0, level: 0 },
+            |m| m.x,
+            Gaussian::new(0.0, 2.0).unwrap(),
+        ));
+    }
+
+    #[derive(Clone, Debug)]
+    struct VectorModel {
+        x: DVector<f64>,
+    }
+
+    // A standard normal prior and likelihood of x[0]: tempered to N(0, 0.8)
+    fn vector_x() -> Parameter<MvGaussian, DVector<f64>, VectorModel> {
+        Parameter::new(
+            "x".to_string(),
+            MvGaussian::new(DVector::zeros(1), DMatrix::identity(1, 1))
+                .unwrap(),
+            make_lens_clone!(VectorModel, DVector<f64>, x),
+        )
+    }
+
+    fn vector_standard(m: &VectorModel) -> f64 {
+        Gaussian::new(0.0, 1.0).unwrap().ln_f(&m.x[0])
+    }
+
+    #[test]
+    fn vector_steppers_sample_the_tempered_posterior() {
+        let init = VectorModel { x: DVector::zeros(1) };
+        let tempered = Gaussian::new(0.0, 0.8f64.sqrt()).unwrap();
+
+        let srwm = VectorSRWM::new(
+            vector_x(),
+            vector_standard,
+            DVector::from_element(1, 1.5),
+        );
+        assert!(samples_at_temperature_4(
+            srwm,
+            init.clone(),
+            |m| m.x[0],
+            tempered.clone(),
+        ));
+
+        let am = AMBuilder::new(
+            vector_x(),
+            vector_standard,
+            DMatrix::identity(1, 1) * 2.0,
+        )
+        .build()
+        .unwrap();
+        assert!(samples_at_temperature_4(am, init, |m| m.x[0], tempered));
+    }
+
+    #[test]
+    fn tempering_crosses_between_weighted_modes() {
+        // Narrow modes at -5 and 5 holding 30% and 70% of the mass
+        fn bimodal(m: &Model) -> f64 {
+            let left = Gaussian::new(-5.0, 0.3).unwrap().ln_f(&m.x);
+            let right = Gaussian::new(5.0, 0.3).unwrap().ln_f(&m.x);
+            logsumexp(&[0.3f64.ln() + left, 0.7f64.ln() + right])
+        }
+        let srwm = SRWM::new(x(), bimodal, Some(0.5)).unwrap();
+        let ladder = geometric_ladder(6, 400.0).unwrap();
+        let tempering = SimulatedTempering::new(
+            srwm,
+            bimodal,
+            ladder,
+            make_lens!(Model, usize, level),
+        )
+        .unwrap();
+
+        let mut rng = StdRng::from_seed(SEED);
+        let draws = Runner::new(tempering)
+            .warmup(5000)
+            .samples(30000)
+            .run(&mut rng, Model { x: -5.0, level: 0 });
+
+        // The adapted weights spread the chain over the ladder.
+        let mut occupancy = vec![0usize; 6];
+        draws[0].iter().for_each(|m| occupancy[m.level] += 1);
+        println!("occupancy = {:?}", occupancy);
+        assert!(occupancy.iter().all(|&n| n > 30000 / 12));
+
+        let posterior: Vec<f64> = draws[0]
+            .iter()
+            .filter(|m| m.level == 0)
+            .map(|m| m.x)
+            .collect();
+        let right = posterior.iter().filter(|&&x| x > 0.0).count() as f64
+            / posterior.len() as f64;
+        println!("right = {}", right);
+        assert!((right - 0.7).abs() < 0.1);
+    }
+
+    #[test]
+    fn adapted_ladders_even_out_neighbour_acceptance() {
+        type Sharp = SRWM<Uniform, f64, f64, Model, fn(&Model) -> f64>;
+        type Tempered = SimulatedTempering<Model, Sharp, fn(&Model) -> f64>;
+        fn sharp(m: &Model) -> f64 {
+            Gaussian::new(0.0, 0.1).unwrap().ln_f(&m.x)
+        }
+        let likelihood: fn(&Model) -> f64 = sharp;
+        let tempering = |temperatures: Vec<f64>| -> Tempered {
+            let srwm = SRWM::new(x(), likelihood, Some(0.5)).unwrap();
+            SimulatedTempering::new(
+                srwm,
+                likelihood,
+                temperatures,
+                make_lens!(Model, usize, level),
+            )
+            .unwrap()
+        };
+        let mut rng = StdRng::from_seed(SEED);
+        let mut run = |tempering: &mut Tempered, mode: AdaptationMode| {
+            SteppingAlg::<Model, StdRng>::set_adapt(tempering, mode);
+            let mut m = Model { x: 0.0, level: 0 };
+            for _ in 0..20000 {
+                m = tempering.step(&mut rng, m);
+            }
+        };
+        let evenness = |tempering: &Tempered| {
+            let acceptance: Vec<f64> = tempering
+                .neighbour_acceptance()
+                .into_iter()
+                .map(|a| a.unwrap())
+                .collect();
+            println!("acceptance = {:?}", acceptance);
+            let min = acceptance.iter().cloned().fold(1.0, f64::min);
+            let max = acceptance.iter().cloned().fold(0.0, f64::max);
+            (min, max)
+        };
+
+        // Almost no moves cross the wide gap at the top of the ladder.
+        let ladder = vec![1.0, 1.1, 1.2, 1E4];
+        let mut fixed = tempering(ladder.clone());
+        run(&mut fixed, AdaptationMode::Enabled);
+        assert_eq!(fixed.temperatures(), &ladder[..]);
+        let (min, _) = evenness(&fixed);
+        assert!(min < 0.05);
+
+        let mut adapted = tempering(ladder).adapt_ladder(1000.0).unwrap();
+        run(&mut adapted, AdaptationMode::Enabled);
+        let temperatures = adapted.temperatures().to_vec();
+        println!("temperatures = {:?}", temperatures);
+        assert_eq!(temperatures[0], 1.0);
+        assert_eq!(temperatures[3], 1E4);
+        assert!(temperatures.windows(2).all(|t| t[0] < t[1]));
+
+        // Sampling on the adapted ladder, with its weights, moves evenly.
+        let mut frozen = tempering(temperatures)
+            .warm_start(adapted.log_weights().to_vec(), 1E-3)
+            .unwrap();
+        run(&mut frozen, AdaptationMode::Disabled);
+        let (min, max) = evenness(&frozen);
+        assert!(min > 0.2);
+        assert!(max / min < 1.5);
+
+        SteppingAlg::<Model, StdRng>::reset(&mut adapted);
+        assert_eq!(adapted.temperatures(), &[1.0, 1.1, 1.2, 1E4][..]);
+        assert!(adapted.neighbour_acceptance().iter().all(|a| a.is_none()));
+    }
+
+    #[test]
+    fn parallel_tempering_samples_the_posterior() {
+        let srwm = SRWM::new(x(), standard, Some(1.0)).unwrap();
+        let mut tempering =
+            ParallelTempering::new(srwm, standard, vec![1.0, 2.0, 4.0])
+                .unwrap();
+        let mut rng = StdRng::from_seed(SEED);
+        let gaussian = Gaussian::new(0.0, 1.0).unwrap();
+        assert!(multiple_tries(N_TRIES, |_| {
+            let mut m = Model { x: 0.0, level: 0 };
+            let xs: Vec<f64> = (0..5000)
+                .filter_map(|i| {
+                    m = tempering.step(&mut rng, m);
+                    if i % 10 == 0 { Some(m.x) } else { None }
+                })
+                .collect();
+            let (stat, p) = ks_test(&xs, |x| gaussian.cdf(&x));
+            println!("test stat = {}, p = {}", stat, p);
+            p > P_VAL
+        }));
+    }
+
+    #[test]
+    fn parallel_tempering_crosses_between_weighted_modes() {
+        // Narrow modes at -5 and 5 holding 30% and 70% of the mass
+        fn bimodal(m: &Model) -> f64 {
+            let left = Gaussian::new(-5.0, 0.3).unwrap().ln_f(&m.x);
+            let right = Gaussian::new(5.0, 0.3).unwrap().ln_f(&m.x);
+            logsumexp(&[0.3f64.ln() + left, 0.7f64.ln() + right])
+        }
+        let srwm = SRWM::new(x(), bimodal, Some(0.5)).unwrap();
+        let ladder = geometric_ladder(6, 400.0).unwrap();
+        let mut tempering =
+            ParallelTempering::new(srwm, bimodal, ladder).unwrap();
+        assert!(tempering.swap_acceptance().is_none());
+
+        let mut rng = StdRng::from_seed(SEED);
+        let mut m = Model { x: -5.0, level: 0 };
+        let xs: Vec<f64> = (0..20000)
+            .map(|_| {
+                m = tempering.step(&mut rng, m);
+                m.x
+            })
+            .collect();
+        let right =
+            xs.iter().filter(|&&x| x > 0.0).count() as f64 / xs.len() as f64;
+        println!("right = {}", right);
+        assert!((right - 0.7).abs() < 0.1);
+
+        let acceptance = tempering.swap_acceptance().unwrap();
+        println!("swap acceptance = {}", acceptance);
+        assert_eq!(acceptance, acceptance.transpose());
+        assert!((0..6).all(|k| acceptance[(k, k)] == 1.0));
+        assert!(acceptance.iter().all(|&a| a >= 0.0 && a <= 1.0));
+        // Swaps between distant temperatures are rarely accepted.
+        assert!(acceptance[(0, 5)] < acceptance[(0, 1)]);
+    }
+
+    #[test]
+    fn adapted_parallel_ladders_even_out_swap_acceptance() {
+        type Sharp = SRWM<Uniform, f64, f64, Model, fn(&Model) -> f64>;
+        type Parallel = ParallelTempering<Model, Sharp, fn(&Model) -> f64>;
+        fn sharp(m: &Model) -> f64 {
+            Gaussian::new(0.0, 0.1).unwrap().ln_f(&m.x)
+        }
+        let likelihood: fn(&Model) -> f64 = sharp;
+        let tempering = |temperatures: Vec<f64>| -> Parallel {
+            let srwm = SRWM::new(x(), likelihood, Some(0.5)).unwrap();
+            ParallelTempering::new(srwm, likelihood, temperatures).unwrap()
+        };
+        let mut rng = StdRng::from_seed(SEED);
+        let mut run = |tempering: &mut Parallel, mode: AdaptationMode| {
+            SteppingAlg::<Model, StdRng>::set_adapt(tempering, mode);
+            let mut m = Model { x: 0.0, level: 0 };
+            for _ in 0..20000 {
+                m = tempering.step(&mut rng, m);
+            }
+        };
+        let evenness = |tempering: &Parallel| {
+            let acceptance = tempering.swap_acceptance().unwrap();
+            let neighbours: Vec<f64> =
+                (0..3).map(|k| acceptance[(k, k + 1)]).collect();
+            println!("acceptance = {:?}", neighbours);
+            let min = neighbours.iter().cloned().fold(1.0, f64::min);
+            let max = neighbours.iter().cloned().fold(0.0, f64::max);
+            (min, max)
+        };
+
+        // Almost no swaps cross the wide gap at the top of the ladder.
+        let ladder = vec![1.0, 1.1, 1.2, 1E4];
+        let mut fixed = tempering(ladder.clone());
+        run(&mut fixed, AdaptationMode::Enabled);
+        assert_eq!(fixed.temperatures(), &ladder[..]);
+        let (min, _) = evenness(&fixed);
+        assert!(min < 0.05);
+
+        let mut adapted = tempering(ladder).adapt_ladder(5000.0).unwrap();
+        run(&mut adapted, AdaptationMode::Enabled);
+        let temperatures = adapted.temperatures().to_vec();
+        println!("temperatures = {:?}", temperatures);
+        assert_eq!(temperatures[0], 1.0);
+        assert_eq!(temperatures[3], 1E4);
+        assert!(temperatures.windows(2).all(|t| t[0] < t[1]));
+        assert_eq!(adapted.stepper().temperature(), 1.0);
+
+        // Sampling on the adapted ladder swaps evenly.
+        let mut frozen = tempering(temperatures);
+        run(&mut frozen, AdaptationMode::Disabled);
+        let (min, max) = evenness(&frozen);
+        assert!(min > 0.2);
+        assert!(max / min < 1.5);
+
+        SteppingAlg::<Model, StdRng>::reset(&mut adapted);
+        assert_eq!(adapted.temperatures(), &[1.0, 1.1, 1.2, 1E4][..]);
+        assert!(adapted.swap_acceptance().is_none());
+    }
+}
@@ -1,4 +1,43 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
 use rand::Rng;
+use likelihood::DeltaLogLikelihood;
+use log_density::LogDensity;
+use parameter::ParamId;
+use events::EventSink;
+
+/// Scores of the two models a Metropolis update chose between
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProposalScores {
+    /// Score of the chain's model before the update
+    pub current: f64,
+    /// Score of the proposed model
+    pub proposed: f64,
+    /// Log acceptance ratio, the difference of the scores plus the log
+    /// Hastings ratio of an asymmetric proposal
+    pub log_alpha: f64,
+}
+
+impl ProposalScores {
+    /// Scores of a proposal under a symmetric kernel
+    pub fn new(current: f64, proposed: f64) -> Self {
+        ProposalScores {
+            current,
+            proposed,
+            log_alpha: log_acceptance(proposed, current),
+        }
+    }
+
+    /// Add the log Hastings ratio of an asymmetric proposal to the log
+    /// acceptance ratio.
+    pub fn hastings(&self, log_ratio: f64) -> Self {
+        ProposalScores {
+            log_alpha: self.log_alpha + log_ratio,
+            ..*self
+        }
+    }
+}
 
 /// Status given to a Metropolis update
 #[derive(Clone, Debug)]
@@ -7,30 +46,577 @@ where
     M: Clone
 {
     /// The update was accepted
-    Accepted(M, f64),
+    Accepted(M, ProposalScores),
     /// The update was rejected
-    Rejected(M, f64),
+    Rejected(M, ProposalScores),
+}
+
+impl<M> MetroplisUpdate<M>
+where
+    M: Clone
+{
+    /// The value of the chain after the update.
+    pub fn value(&self) -> &M {
+        match self {
+            MetroplisUpdate::Accepted(x, _) => x,
+            MetroplisUpdate::Rejected(x, _) => x,
+        }
+    }
+
+    /// The scores of the current and proposed models.
+    pub fn scores(&self) -> ProposalScores {
+        match self {
+            MetroplisUpdate::Accepted(_, s) => *s,
+            MetroplisUpdate::Rejected(_, s) => *s,
+        }
+    }
+
+    /// The log acceptance ratio of the update.
+    pub fn log_alpha(&self) -> f64 {
+        self.scores().log_alpha
+    }
+
+    /// Whether the proposal was accepted.
+    pub fn is_accepted(&self) -> bool {
+        match self {
+            MetroplisUpdate::Accepted(_, _) => true,
+            MetroplisUpdate::Rejected(_, _) => false,
+        }
+    }
+}
+
+/// Log prior scores of parameters' current values, shared by the steppers of
+/// a `Group` and cleared at the start of each sweep
+///
+/// A stepper reads its parameter's score before evaluating it and stores the
+/// new score when it accepts a proposal, so other steppers of the same
+/// parameter in the sweep reuse it.
+#[derive(Clone, Debug, Default)]
+pub struct PriorCache {
+    scores: Arc<RwLock<BTreeMap<ParamId, f64>>>,
+}
+
+impl PriorCache {
+    pub fn new() -> Self {
+        PriorCache::default()
+    }
+
+    /// A cache for steppers updating `parameters`, one vector per stepper,
+    /// or `None` when no parameter is updated by two of them. Steppers only
+    /// reuse scores of their own parameters, so without such a parameter a
+    /// cache would take its lock every step without saving an evaluation.
+    pub fn for_steppers<I>(parameters: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = Vec<ParamId>>,
+    {
+        let mut seen = BTreeSet::new();
+        let shared = parameters.into_iter().any(|ids| {
+            let ids: BTreeSet<ParamId> = ids.into_iter().collect();
+            let shared = !seen.is_disjoint(&ids);
+            seen.extend(ids);
+            shared
+        });
+        if shared {
+            Some(PriorCache::new())
+        } else {
+            None
+        }
+    }
+
+    /// The cached score of `parameter`, computing and storing it if absent.
+    pub fn get_or_insert_with<F>(&self, parameter: &ParamId, f: F) -> f64
+    where
+        F: FnOnce() -> f64,
+    {
+        if let Some(score) = self.get(parameter) {
+            return score;
+        }
+        let score = f();
+        self.insert(parameter.clone(), score);
+        score
+    }
+
+    pub fn get(&self, parameter: &ParamId) -> Option<f64> {
+        self.scores
+            .read()
+            .expect("Failed to get read access to prior cache")
+            .get(parameter)
+            .cloned()
+    }
+
+    pub fn insert(&self, parameter: ParamId, score: f64) {
+        self.scores
+            .write()
+            .expect("Failed to get write access to prior cache")
+            .insert(parameter, score);
+    }
+
+    /// Drop the score of `parameter`, e.g. after changing its value without
+    /// evaluating its prior.
+    pub fn remove(&self, parameter: &ParamId) {
+        self.scores
+            .write()
+            .expect("Failed to get write access to prior cache")
+            .remove(parameter);
+    }
+
+    pub fn clear(&self) {
+        self.scores
+            .write()
+            .expect("Failed to get write access to prior cache")
+            .clear();
+    }
+}
+
+/// A proposal made by a stepper, whether accepted or not
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProposalRecord {
+    pub parameter: ParamId,
+    /// Value before the step
+    pub current: f64,
+    pub proposed: f64,
+    /// Unnormalized log posterior of the current value
+    pub current_score: f64,
+    /// Unnormalized log posterior of the proposed value
+    pub proposed_score: f64,
+    /// Log acceptance ratio, including any Hastings correction, e.g. for
+    /// integer walks truncated at a bound
+    pub log_alpha: f64,
+    pub accepted: bool,
+}
+
+impl ProposalRecord {
+    /// Log acceptance ratio of the proposal
+    pub fn log_alpha(&self) -> f64 {
+        self.log_alpha
+    }
+}
+
+/// Buffer of the most recent proposals of the steppers sharing it
+///
+/// Clones share the buffer, so one cache can be given to several steppers
+/// and drained from outside, e.g. to plot the proposal cloud or feed
+/// estimators which recycle rejected proposals. The steppers of every
+/// chain of a runner share it too, interleaving their records.
+#[derive(Clone, Debug)]
+pub struct ProposalCache {
+    records: Arc<Mutex<VecDeque<ProposalRecord>>>,
+    capacity: usize,
+}
+
+impl ProposalCache {
+    /// Cache keeping the `capacity` most recent proposals
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be greater than 0.");
+        ProposalCache {
+            records: Arc::new(Mutex::new(VecDeque::new())),
+            capacity,
+        }
+    }
+
+    pub fn push(&self, record: ProposalRecord) {
+        let mut records = self
+            .records
+            .lock()
+            .expect("Failed to get access to proposal cache");
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Remove and return the cached proposals, oldest first.
+    pub fn drain(&self) -> Vec<ProposalRecord> {
+        self.records
+            .lock()
+            .expect("Failed to get access to proposal cache")
+            .drain(..)
+            .collect()
+    }
+
+    /// Copy of the cached proposals, oldest first
+    pub fn records(&self) -> Vec<ProposalRecord> {
+        self.records
+            .lock()
+            .expect("Failed to get access to proposal cache")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records
+            .lock()
+            .expect("Failed to get access to proposal cache")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Running count of accepted Metropolis updates
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AcceptanceCounter {
+    n_steps: usize,
+    n_accepted: usize,
+}
+
+impl AcceptanceCounter {
+    pub fn new() -> Self {
+        AcceptanceCounter::default()
+    }
+
+    /// Record the outcome of an update.
+    pub fn record<M: Clone>(&mut self, update: &MetroplisUpdate<M>) {
+        self.n_steps += 1;
+        if let MetroplisUpdate::Accepted(_, _) = update {
+            self.n_accepted += 1;
+        }
+    }
+
+    /// Fraction of recorded updates which were accepted.
+    pub fn rate(&self) -> Option<f64> {
+        if self.n_steps == 0 {
+            None
+        } else {
+            Some(self.n_accepted as f64 / self.n_steps as f64)
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = AcceptanceCounter::default();
+    }
+}
+
+/// Accept/reject bookkeeping shared by the Metropolis steppers
+///
+/// Holds the score of the chain's current model so it is not recomputed
+/// every step, the log acceptance ratio of the last proposal and the
+/// running acceptance count. An accepted proposal's score, and its log
+/// prior when the stepper tracks it, become the current ones.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MHCore {
+    /// Score of the current model, once known
+    pub current_score: Option<f64>,
+    /// Log prior of the current value, for steppers which track it
+    pub current_prior: Option<f64>,
+    /// Log acceptance ratio of the last proposal
+    pub log_acceptance: f64,
+    pub acceptance: AcceptanceCounter,
+}
+
+impl MHCore {
+    pub fn new() -> Self {
+        MHCore::default()
+    }
+
+    /// Score of the current model, computed with `score` if not yet known
+    pub fn current_score<F>(&self, score: F) -> f64
+    where
+        F: FnOnce() -> f64,
+    {
+        self.current_score.unwrap_or_else(score)
+    }
+
+    /// Record the outcome of a proposal.
+    pub fn record<T: Clone>(&mut self, update: &MetroplisUpdate<T>) {
+        let scores = update.scores();
+        self.log_acceptance = scores.log_alpha;
+        self.acceptance.record(update);
+        if update.is_accepted() {
+            self.current_score = Some(scores.proposed);
+        }
+    }
+
+    /// Record the outcome of a proposal whose value has log prior
+    /// `proposed_prior`.
+    pub fn record_with_prior<T: Clone>(
+        &mut self,
+        update: &MetroplisUpdate<T>,
+        proposed_prior: f64,
+    ) {
+        self.record(update);
+        if update.is_accepted() {
+            self.current_prior = Some(proposed_prior);
+        }
+    }
+
+    /// Forget the current scores, keeping the acceptance count, e.g. when
+    /// the model was changed by something other than this stepper.
+    pub fn invalidate(&mut self) {
+        self.current_score = None;
+        self.current_prior = None;
+    }
+
+    /// Forget the current scores and the acceptance count, e.g. when the
+    /// stepper is reused for another chain.
+    pub fn reset(&mut self) {
+        *self = MHCore::default();
+    }
+}
+
+/// Occasional large proposals for multimodal posteriors, letting a random
+/// walk jump between modes it would rarely cross
+///
+/// Each step is a jump with probability `probability`. A jump is drawn
+/// from the prior with probability `prior_weight`, and otherwise from the
+/// stepper's random walk with its scale multiplied by `width`. Each kind
+/// of move is a valid Metropolis-Hastings update on its own, with the
+/// prior's ratio removed for prior draws, so choosing between them at
+/// random leaves the posterior invariant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModeJumps {
+    pub probability: f64,
+    pub prior_weight: f64,
+    pub width: f64,
+}
+
+/// The kind of move a step makes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Move {
+    /// The stepper's usual random walk
+    Local,
+    /// An independent draw from the prior
+    Prior,
+    /// A random walk with its scale multiplied by the given width
+    Wide(f64),
+}
+
+impl ModeJumps {
+    pub fn new(probability: f64, prior_weight: f64, width: f64) -> Self {
+        assert!(
+            probability >= 0.0 && probability <= 1.0,
+            "jump probability must be in [0, 1]."
+        );
+        assert!(
+            prior_weight >= 0.0 && prior_weight <= 1.0,
+            "prior weight must be in [0, 1]."
+        );
+        assert!(width > 0.0, "jump width must be positive.");
+        ModeJumps {
+            probability,
+            prior_weight,
+            width,
+        }
+    }
+
+    /// Choose the kind of the next move.
+    pub fn choose<R: Rng>(&self, rng: &mut R) -> Move {
+        if !rng.gen_bool(self.probability) {
+            Move::Local
+        } else if rng.gen_bool(self.prior_weight) {
+            Move::Prior
+        } else {
+            Move::Wide(self.width)
+        }
+    }
+}
+
+/// Reject a step in which the lens of `parameter` failed with `err`,
+/// returning `model` unchanged and reporting the failure to `events`.
+pub fn reject_lens_failure<M>(
+    events: &Option<EventSink>,
+    parameter: &ParamId,
+    err: &io::Error,
+    model: M,
+) -> M {
+    if let Some(ref events) = events {
+        events.emit_lens_failure(parameter, err);
+    }
+    model
+}
+
+/// Reflect `x` back into `[lower, upper]` as many times as needed.
+///
+/// Reflection preserves the symmetry of a random walk proposal, so no
+/// correction to the acceptance ratio is required. Either bound may be
+/// infinite.
+pub fn reflect(x: f64, lower: f64, upper: f64) -> f64 {
+    match (lower.is_finite(), upper.is_finite()) {
+        (true, true) => {
+            let width = upper - lower;
+            let period = 2.0 * width;
+            let y = ((x - lower) % period + period) % period;
+            if y > width {
+                upper - (y - width)
+            } else {
+                lower + y
+            }
+        },
+        (true, false) if x < lower => 2.0 * lower - x,
+        (false, true) if x > upper => 2.0 * upper - x,
+        _ => x,
+    }
 }
 
 /// Metropolis Update
-/// Given a symmetric proposal distribution, this function will update proportional to the
-/// likelihood.
+/// Accepts the proposal with probability given by the log acceptance ratio
+/// of its scores.
 ///
 /// # Parameters
 /// * `rng` random number generator
-/// * `log_likelihood_delta` Difference between current and posposed log_likelihoods.
+/// * `scores` Scores of the current and proposed models
 /// * `proposed` Candidate new model
 /// * `current` Current value
+///
+/// A NaN log acceptance ratio (e.g. from `-inf - -inf`) always rejects.
 pub fn metropolis_select<M: Clone, R: Rng>(
     rng: &mut R,
-    log_likelihood_delta: f64,
+    scores: ProposalScores,
     proposed: M,
     current: M
 ) -> MetroplisUpdate<M> {
 
-    if rng.gen::<f64>().ln() < log_likelihood_delta {
-        MetroplisUpdate::Accepted(proposed, log_likelihood_delta)
+    if rng.gen::<f64>().ln() < scores.log_alpha {
+        MetroplisUpdate::Accepted(proposed, scores)
     } else {
-        MetroplisUpdate::Rejected(current, log_likelihood_delta)
+        MetroplisUpdate::Rejected(current, scores)
+    }
+}
+
+/// Log likelihood multiplied by `scale`, e.g. `1 / T` for a stepper
+/// targeting a posterior tempered to temperature `T`
+///
+/// Borrows the likelihood for a single step, where `likelihood::Tempered`
+/// owns it.
+pub struct ScaledLogLikelihood<'a, L: 'a> {
+    pub log_likelihood: &'a L,
+    pub scale: f64,
+}
+
+impl<'a, L> ScaledLogLikelihood<'a, L> {
+    pub fn new(log_likelihood: &'a L, scale: f64) -> Self {
+        ScaledLogLikelihood {
+            log_likelihood,
+            scale,
+        }
+    }
+}
+
+impl<'a, M, L> DeltaLogLikelihood<M> for ScaledLogLikelihood<'a, L>
+where
+    L: DeltaLogLikelihood<M>,
+{
+    fn ln_f(&self, model: &M) -> f64 {
+        self.scale * self.log_likelihood.ln_f(model)
+    }
+
+    fn delta(
+        &self,
+        current: &M,
+        proposed: &M,
+        changed: &ParamId,
+    ) -> Option<f64> {
+        self.log_likelihood
+            .delta(current, proposed, changed)
+            .map(|d| self.scale * d)
+    }
+}
+
+/// Score (log likelihood plus log prior) of a proposed model
+///
+/// If the proposal is outside the prior's support the prior score is
+/// returned as is. Otherwise the likelihood's delta is used when available,
+/// falling back to a full evaluation of the proposal.
+///
+/// # Parameters
+/// * `log_likelihood` Likelihood of the model
+/// * `changed` Parameter in which the proposal differs from the current model
+/// * `current` Current model
+/// * `current_score` Score of the current model
+/// * `current_prior` Log prior of the current value, only called for deltas
+/// * `proposed` Candidate new model
+/// * `proposed_prior` Log prior of the proposed value
+pub fn proposal_score<M, L, P>(
+    log_likelihood: &L,
+    changed: &ParamId,
+    current: &M,
+    current_score: f64,
+    current_prior: P,
+    proposed: &M,
+    proposed_prior: f64,
+) -> f64
+where
+    L: DeltaLogLikelihood<M>,
+    P: FnOnce() -> f64,
+{
+    // Outside the prior's support the likelihood is not evaluated and the
+    // proposal is rejected.
+    LogDensity(proposed_prior)
+        .plus(|| match log_likelihood.delta(current, proposed, changed) {
+            Some(delta) => current_score - current_prior() + delta,
+            None => log_likelihood.ln_f(proposed),
+        })
+        .value()
+}
+
+/// Log acceptance ratio of a proposal scored `proposed_score` from a model
+/// scored `current_score` under a symmetric kernel, following the rules of
+/// `LogDensity::ratio` for non-finite scores
+pub fn log_acceptance(proposed_score: f64, current_score: f64) -> f64 {
+    LogDensity(proposed_score).ratio(LogDensity(current_score))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn ids(names: &[&str]) -> Vec<ParamId> {
+        names.iter().map(|name| ParamId(name.to_string())).collect()
+    }
+
+    #[test]
+    fn prior_caches_are_only_made_for_shared_parameters() {
+        let distinct = vec![ids(&["a", "b"]), ids(&["c"]), ids(&["a"])];
+        assert!(PriorCache::for_steppers(distinct.clone()).is_some());
+        assert!(PriorCache::for_steppers(distinct[..2].to_vec()).is_none());
+        assert!(PriorCache::for_steppers(vec![ids(&["a", "a"])]).is_none());
+        assert!(PriorCache::for_steppers(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn updates_carry_the_scores_of_both_models() {
+        let mut rng = StdRng::from_seed([0; 32]);
+        let scores = ProposalScores::new(-1.0, -3.0);
+        assert_eq!(scores.log_alpha, -2.0);
+        let scores = scores.hastings(2.5);
+        assert_eq!((scores.current, scores.proposed), (-1.0, -3.0));
+
+        let update = metropolis_select(&mut rng, scores, 1, 0);
+        assert!(update.is_accepted());
+        assert_eq!(update.scores(), scores);
+        assert_eq!(update.log_alpha(), 0.5);
+
+        let scores = ProposalScores::new(::std::f64::NEG_INFINITY, -1.0);
+        let update = metropolis_select(&mut rng, scores, 1, 0);
+        assert!(update.is_accepted());
+    }
+
+    #[test]
+    fn mh_core_keeps_the_score_of_the_current_model() {
+        let mut core = MHCore::new();
+        assert_eq!(core.current_score(|| -3.0), -3.0);
+
+        let accepted =
+            MetroplisUpdate::Accepted(1, ProposalScores::new(-3.0, -2.5));
+        core.record_with_prior(&accepted, -1.0);
+        assert_eq!(core.current_score(|| panic!("score is known")), -2.5);
+        assert_eq!(core.current_prior, Some(-1.0));
+
+        let rejected =
+            MetroplisUpdate::Rejected(1, ProposalScores::new(-2.5, -6.5));
+        core.record_with_prior(&rejected, -2.0);
+        assert_eq!(core.current_score, Some(-2.5));
+        assert_eq!(core.current_prior, Some(-1.0));
+        assert_eq!(core.log_acceptance, -4.0);
+        assert_eq!(core.acceptance.rate(), Some(0.5));
+
+        core.reset();
+        assert_eq!(core.current_score, None);
+        assert_eq!(core.acceptance.rate(), None);
     }
 }
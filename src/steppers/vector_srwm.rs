@@ -0,0 +1,1271 @@
+//! Symmetric Random Walk Metropolis over vector parameters
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use rand::Rng;
+use rand::distributions::StandardNormal;
+use rand::seq::index;
+
+use nalgebra::{DMatrix, DVector, Real};
+use rv::traits::Rv;
+
+use parameter::{Parameter, ParamId};
+use likelihood::DeltaLogLikelihood;
+use steppers::{SteppingAlg, AdaptationStatus, AdaptationMode, util};
+use steppers::adaptor::{
+    next_state, AdaptorState, DiagonalAdaptor, ScaleAdaptor,
+};
+use steppers::reparameterize::{Reparameterizable, Reparameterize};
+use steppers::tempering::Tempering;
+use statistics::{Statistic, StatisticValue};
+use events::EventSink;
+use utils::invalid_input;
+
+/// Which coordinates of the vector are perturbed in each proposal
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum ProposalMode {
+    /// Every coordinate is perturbed at once
+    Joint,
+    /// A uniformly random subset of this many coordinates is perturbed
+    ElementWise(usize),
+    /// A uniformly random contiguous window of this many coordinates is
+    /// perturbed, e.g. a stretch of a latent time series path
+    Block(usize),
+}
+
+impl Default for ProposalMode {
+    fn default() -> Self {
+        ProposalMode::Joint
+    }
+}
+
+/// Correlation structure of the noise added to perturbed coordinates
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum NoiseKernel {
+    /// Independent standard normal noise per coordinate
+    White,
+    /// Stationary AR(1) noise with unit marginal variance and the given
+    /// lag-one correlation, running over the perturbed coordinates in order.
+    /// Suited to smooth latent paths such as time series states.
+    AR1(f64),
+    /// Gaussian random walk over the perturbed coordinates, pinned to zero
+    /// at the unperturbed neighbours on either side (a Brownian bridge when
+    /// both exist), so a block update joins the fixed path smoothly.
+    Bridge,
+}
+
+impl Default for NoiseKernel {
+    fn default() -> Self {
+        NoiseKernel::White
+    }
+}
+
+/// Symmetric Random Walk Metropolis over a `DVector<f64>` parameter, or a
+/// `DVector<f32>` one to halve the memory of very large latent fields
+///
+/// Proposals add Gaussian noise with a per-coordinate scale to every
+/// coordinate, a random subset of them or a random contiguous block of them,
+/// avoiding any dense covariance computations.
+///
+/// Only the parameter itself is stored in `N`. The proposal scales and the
+/// `DiagonalAdaptor` stay in `f64`, so with `f32` the scales take as much
+/// memory as the parameter did before, and each adaptive step copies the
+/// new value into a `DVector<f64>` for the adaptor. Disable adaptation
+/// after warmup to avoid that copy on long runs.
+pub struct VectorSRWM<D, M, L, N = f64>
+where
+    D: Rv<DVector<N>> + Clone,
+    N: Real,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    pub parameter: Parameter<D, DVector<N>, M>,
+    pub log_likelihood: L,
+    pub mh: util::MHCore,
+    /// Temperature of the tempered target; see `Tempering`
+    pub temperature: f64,
+    pub proposal_scales: DVector<f64>,
+    pub mode: ProposalMode,
+    pub noise: NoiseKernel,
+    pub block_prior: Option<fn(&D, &DVector<N>, usize, usize) -> f64>,
+    pub mode_jumps: Option<util::ModeJumps>,
+    pub fixed: bool,
+    prior_cache: Option<util::PriorCache>,
+    events: Option<EventSink>,
+    adaptor: Option<DiagonalAdaptor>,
+    reparameterization: Option<Arc<dyn Reparameterize>>,
+}
+
+impl<D, M, L, N> VectorSRWM<D, M, L, N>
+where
+    D: Rv<DVector<N>> + Clone,
+    N: Real,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    pub fn new(
+        parameter: Parameter<D, DVector<N>, M>,
+        log_likelihood: L,
+        proposal_scales: DVector<f64>,
+    ) -> Self {
+        VectorSRWM {
+            parameter,
+            log_likelihood,
+            mh: util::MHCore::new(),
+            temperature: 1.0,
+            proposal_scales,
+            mode: ProposalMode::Joint,
+            noise: NoiseKernel::White,
+            block_prior: None,
+            mode_jumps: None,
+            fixed: false,
+            prior_cache: None,
+            events: None,
+            adaptor: None,
+            reparameterization: None,
+        }
+    }
+
+    /// Make some proposals large jumps, drawn from the prior or a widened
+    /// random walk, to move between modes. Jumps do not adapt the
+    /// proposal scales.
+    pub fn mode_jumps(&self, jumps: util::ModeJumps) -> Self {
+        VectorSRWM {
+            mode_jumps: Some(jumps),
+            ..(*self).clone()
+        }
+    }
+
+    /// Use previously tuned per-coordinate proposal scales, e.g. from an
+    /// earlier run on similar data.
+    pub fn proposal_scales(&self, proposal_scales: DVector<f64>) -> Self {
+        assert!(
+            proposal_scales.iter().all(|s| *s > 0.0 && s.is_finite()),
+            "proposal scales must be finite and positive."
+        );
+        VectorSRWM {
+            adaptor: self
+                .adaptor
+                .as_ref()
+                .map(|_| DiagonalAdaptor::new(proposal_scales.clone())),
+            proposal_scales,
+            ..(*self).clone()
+        }
+    }
+
+    /// Use the variances of a previously tuned proposal covariance, e.g. an
+    /// `AdaptiveMetropolis` stepper's `proposal_covariance`, as the proposal
+    /// scales. Coordinates are perturbed independently, so the correlations
+    /// are dropped; use `AMBuilder` with the covariance to keep them.
+    pub fn proposal_covariance(&self, covariance: &DMatrix<f64>) -> Self {
+        assert!(
+            covariance.is_square(),
+            "proposal covariance must be a square matrix."
+        );
+        self.proposal_scales(covariance.diagonal().map(f64::sqrt))
+    }
+
+    /// Adapt each coordinate's proposal scale to its spread during warmup,
+    /// starting from the current scales. Only per-coordinate variances are
+    /// tracked, so adaptation costs O(d) per step.
+    pub fn diagonal_adaptation(&self) -> Self {
+        VectorSRWM {
+            adaptor: Some(DiagonalAdaptor::new(self.proposal_scales.clone())),
+            ..(*self).clone()
+        }
+    }
+
+    /// Propose in the space of `map` rather than the parameter's own, e.g.
+    /// an `AffineMap` of earlier draws, with the proposal scales and mode
+    /// applying to the mapped coordinates.
+    ///
+    /// The map's Jacobian enters the acceptance ratio, so every proposal
+    /// evaluates the whole prior and scales are not adapted while a map is
+    /// in place. Mode jumps from the prior are still made in the
+    /// parameter's space.
+    pub fn reparameterize(&self, map: Arc<dyn Reparameterize>) -> Self {
+        VectorSRWM {
+            reparameterization: Some(map),
+            ..(*self).clone()
+        }
+    }
+
+    /// The proposal scale adaptor, if the scales are adapted
+    pub fn adaptor(&self) -> Option<&DiagonalAdaptor> {
+        self.adaptor.as_ref()
+    }
+
+    /// Check that the parameter's value in `model`, the proposal scales and
+    /// the adaptor's scales all have the same length, naming the parameter
+    /// and the lengths which differ if not.
+    ///
+    /// `step` panics with this error on a mismatch, before the proposal or
+    /// prior is evaluated, so check the initial model to handle it instead.
+    pub fn check_dimensions(&self, model: &M) -> io::Result<()> {
+        self.check_length(self.parameter.lens.get(model).len())
+    }
+
+    fn check_length(&self, dim: usize) -> io::Result<()> {
+        let mismatch = |what: &str, len: usize| {
+            invalid_input(format!(
+                "VectorSRWM for {}: the parameter has length {} but {} \
+                 have length {}.",
+                self.parameter.id(),
+                dim,
+                what,
+                len
+            ))
+        };
+        if self.proposal_scales.len() != dim {
+            let len = self.proposal_scales.len();
+            return Err(mismatch("proposal_scales", len));
+        }
+        match self.adaptor.as_ref().map(|a| a.scales().len()) {
+            Some(len) if len != dim => {
+                Err(mismatch("the adaptor's scales", len))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Perturb every coordinate in each proposal.
+    pub fn joint(&self) -> Self {
+        VectorSRWM {
+            mode: ProposalMode::Joint,
+            ..(*self).clone()
+        }
+    }
+
+    /// Perturb a random subset of `n` coordinates in each proposal.
+    pub fn element_wise(&self, n: usize) -> Self {
+        assert!(n > 0, "element_wise subset size must be greater than 0.");
+        VectorSRWM {
+            mode: ProposalMode::ElementWise(n),
+            ..(*self).clone()
+        }
+    }
+
+    /// Perturb a random contiguous window of `window` coordinates in each
+    /// proposal using bridge noise, leaving the rest of the path fixed.
+    pub fn block(&self, window: usize) -> Self {
+        assert!(window > 0, "block window must be greater than 0.");
+        VectorSRWM {
+            mode: ProposalMode::Block(window),
+            noise: NoiseKernel::Bridge,
+            ..(*self).clone()
+        }
+    }
+
+    /// Add independent noise to the perturbed coordinates.
+    pub fn white_noise(&self) -> Self {
+        VectorSRWM {
+            noise: NoiseKernel::White,
+            ..(*self).clone()
+        }
+    }
+
+    /// Add AR(1) correlated noise with lag-one correlation `phi` to the
+    /// perturbed coordinates.
+    pub fn ar1(&self, phi: f64) -> Self {
+        assert!(phi.abs() < 1.0, "AR(1) correlation must be in (-1, 1).");
+        VectorSRWM {
+            noise: NoiseKernel::AR1(phi),
+            ..(*self).clone()
+        }
+    }
+
+    /// Add random walk noise pinned at the unperturbed neighbours.
+    pub fn bridge(&self) -> Self {
+        VectorSRWM {
+            noise: NoiseKernel::Bridge,
+            ..(*self).clone()
+        }
+    }
+
+    /// Evaluate the prior of block updates with `block_ln_f(prior, x, start,
+    /// end)`, the log density of the terms involving `x[start..end]`, rather
+    /// than the whole path's `ln_f`. See `GaussianRW::block_ln_f`.
+    pub fn block_prior(
+        &self,
+        block_ln_f: fn(&D, &DVector<N>, usize, usize) -> f64,
+    ) -> Self {
+        VectorSRWM {
+            block_prior: Some(block_ln_f),
+            ..(*self).clone()
+        }
+    }
+
+    /// Log prior of `proposed`, which differs from `current` only at
+    /// `indices`.
+    fn proposed_prior(
+        &self,
+        current: &DVector<N>,
+        current_prior: f64,
+        proposed: &DVector<N>,
+        indices: &[usize],
+    ) -> f64 {
+        let prior = &self.parameter.prior;
+        match (self.mode, self.block_prior, indices.first(), indices.last()) {
+            (
+                ProposalMode::Block(_),
+                Some(block_ln_f),
+                Some(&start),
+                Some(&last),
+            ) => {
+                current_prior + block_ln_f(prior, proposed, start, last + 1)
+                    - block_ln_f(prior, current, start, last + 1)
+            }
+            _ => prior.ln_f(proposed),
+        }
+    }
+
+    /// Indices of the coordinates to perturb in the next proposal, in
+    /// increasing order.
+    fn proposal_indices<R: Rng>(&self, rng: &mut R, dim: usize) -> Vec<usize> {
+        match self.mode {
+            ProposalMode::Joint => (0..dim).collect(),
+            ProposalMode::ElementWise(n) => {
+                let mut indices =
+                    index::sample(rng, dim, n.min(dim)).into_vec();
+                indices.sort();
+                indices
+            }
+            ProposalMode::Block(window) => {
+                let window = window.min(dim);
+                let start = rng.gen_range(0, dim - window + 1);
+                (start..(start + window)).collect()
+            }
+        }
+    }
+
+    /// Noise for the perturbed coordinates `indices` of a vector of length
+    /// `dim`.
+    fn noise<R: Rng>(
+        &self,
+        rng: &mut R,
+        indices: &[usize],
+        dim: usize,
+    ) -> Vec<f64> {
+        let n = indices.len();
+        match self.noise {
+            NoiseKernel::White => {
+                (0..n).map(|_| rng.sample(StandardNormal)).collect()
+            }
+            NoiseKernel::AR1(phi) => {
+                let innovation_scale = (1.0 - phi * phi).sqrt();
+                (0..n)
+                    .scan(None, |prev: &mut Option<f64>, _| {
+                        let z: f64 = rng.sample(StandardNormal);
+                        let e = match *prev {
+                            Some(p) => phi * p + innovation_scale * z,
+                            None => z,
+                        };
+                        *prev = Some(e);
+                        Some(e)
+                    })
+                    .collect()
+            }
+            NoiseKernel::Bridge => {
+                let pinned_left = indices.first().map_or(false, |&i| i > 0);
+                let pinned_right =
+                    indices.last().map_or(false, |&i| i + 1 < dim);
+
+                // Walk one step past the window to reach the right neighbour.
+                let walk: Vec<f64> = (0..(n + 1))
+                    .scan(0.0, |w: &mut f64, _| {
+                        let z: f64 = rng.sample(StandardNormal);
+                        *w += z;
+                        Some(*w)
+                    })
+                    .collect();
+
+                if pinned_left && pinned_right {
+                    let end = walk[n];
+                    (0..n)
+                        .map(|k| {
+                            walk[k] - (k + 1) as f64 / (n + 1) as f64 * end
+                        })
+                        .collect()
+                } else if pinned_right {
+                    walk[..n].iter().rev().cloned().collect()
+                } else {
+                    walk[..n].to_vec()
+                }
+            }
+        }
+    }
+
+    /// Proposal from `current` perturbed by `width` times the proposal
+    /// scales in the space of `map`, with the log ratio of the Jacobians of
+    /// `map`'s inverse at the proposal and at `current`
+    fn reparameterized_proposal<R: Rng>(
+        &self,
+        map: &dyn Reparameterize,
+        rng: &mut R,
+        current: &DVector<N>,
+        width: f64,
+    ) -> (DVector<N>, f64) {
+        let x: DVector<f64> = current.map(|v| v.to_subset().unwrap());
+        let z = map.forward(&x);
+        if let Err(err) = self.check_length(z.len()) {
+            panic!("{} (in the reparameterized space)", err);
+        }
+        let (_, ln_jacobian) = map.inverse(&z);
+        let indices = self.proposal_indices(rng, z.len());
+        let noise = self.noise(rng, &indices, z.len());
+        let mut proposed = z;
+        for (&i, e) in indices.iter().zip(noise) {
+            proposed[i] += width * self.proposal_scales[i] * e;
+        }
+        let (proposed, proposed_ln_jacobian) = map.inverse(&proposed);
+        let proposed = proposed.map(|v| N::from_subset(&v));
+        (proposed, proposed_ln_jacobian - ln_jacobian)
+    }
+}
+
+impl<D, M, L, N> Reparameterizable for VectorSRWM<D, M, L, N>
+where
+    D: Rv<DVector<N>> + Clone,
+    N: Real,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn set_reparameterization(&mut self, map: Option<Arc<dyn Reparameterize>>) {
+        self.reparameterization = map;
+    }
+}
+
+/// The update with its value in double precision, as adaptors keep it;
+/// this copies the whole vector
+fn to_f64<N: Real>(
+    update: &util::MetroplisUpdate<DVector<N>>,
+) -> util::MetroplisUpdate<DVector<f64>> {
+    let value = update.value().map(|x| x.to_subset().unwrap());
+    match update {
+        util::MetroplisUpdate::Accepted(_, scores) => {
+            util::MetroplisUpdate::Accepted(value, *scores)
+        }
+        util::MetroplisUpdate::Rejected(_, scores) => {
+            util::MetroplisUpdate::Rejected(value, *scores)
+        }
+    }
+}
+
+impl<D, M, L, N> Clone for VectorSRWM<D, M, L, N>
+where
+    D: Rv<DVector<N>> + Clone,
+    N: Real,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn clone(&self) -> Self {
+        VectorSRWM {
+            parameter: self.parameter.clone(),
+            log_likelihood: self.log_likelihood.clone(),
+            mh: self.mh,
+            temperature: self.temperature,
+            proposal_scales: self.proposal_scales.clone(),
+            mode: self.mode,
+            noise: self.noise,
+            block_prior: self.block_prior,
+            mode_jumps: self.mode_jumps,
+            fixed: self.fixed,
+            prior_cache: self.prior_cache.clone(),
+            events: self.events.clone(),
+            adaptor: self.adaptor.clone(),
+            reparameterization: self.reparameterization.clone(),
+        }
+    }
+}
+
+impl<D, M, L, N> Tempering for VectorSRWM<D, M, L, N>
+where
+    D: Rv<DVector<N>> + Clone,
+    N: Real,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn set_temperature(&mut self, temperature: f64) {
+        assert!(
+            temperature > 0.0 && temperature.is_finite(),
+            "temperature must be finite and positive."
+        );
+        self.temperature = temperature;
+        // The cached score is of the previous target.
+        self.mh.current_score = None;
+    }
+
+    fn temperature(&self) -> f64 {
+        self.temperature
+    }
+}
+
+impl<D, M, L, N> fmt::Debug for VectorSRWM<D, M, L, N>
+where
+    D: Rv<DVector<N>> + Clone,
+    N: Real,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "VectorSRWM {{ parameter: {:?}, current_score: {:?}, \
+             mode: {:?}, noise: {:?} }}",
+            self.parameter, self.mh.current_score, self.mode, self.noise
+        )
+    }
+}
+
+impl<D, M, L, N, R> SteppingAlg<M, R> for VectorSRWM<D, M, L, N>
+where
+    D: Rv<DVector<N>> + Clone,
+    N: Real,
+    M: 'static + Clone,
+    L: DeltaLogLikelihood<M> + Clone + Sync,
+    R: Rng,
+{
+    fn set_adapt(&mut self, mode: AdaptationMode) {
+        if let Some(ref mut adaptor) = self.adaptor {
+            adaptor.set_mode(mode);
+        }
+    }
+
+    fn get_adapt(&self) -> AdaptationStatus {
+        self.adaptor
+            .as_ref()
+            .map_or(AdaptationStatus::Disabled, |a| a.get_mode())
+    }
+
+    fn get_statistics(&self) -> Vec<Statistic<M, R>> {
+        let adaptor = self.adaptor.as_ref();
+        let non_finite = adaptor.map_or(0, |a| a.non_finite_updates());
+        let floored = adaptor.map_or(0, |a| a.floored_updates());
+        self.mh
+            .acceptance
+            .rate()
+            .map(StatisticValue::AcceptanceRate)
+            .into_iter()
+            .chain(adaptor.map(|a| {
+                StatisticValue::ProposalScale(a.get_scale())
+            }))
+            .chain(
+                Some(StatisticValue::NonFiniteUpdates(non_finite))
+                    .filter(|_| non_finite > 0),
+            )
+            .chain(
+                Some(StatisticValue::FlooredUpdates(floored))
+                    .filter(|_| floored > 0),
+            )
+            .map(|value| Statistic::new(self.parameter.id().clone(), value))
+            .collect()
+    }
+
+    fn parameters(&self) -> Vec<ParamId> {
+        vec![self.parameter.id().clone()]
+    }
+
+    fn dependencies(&self) -> Vec<(ParamId, Vec<ParamId>)> {
+        vec![(self.parameter.id().clone(), self.parameter.dependencies.clone())]
+    }
+
+    fn reset(&mut self) {
+        self.mh.reset();
+        if let Some(ref mut adaptor) = self.adaptor {
+            adaptor.reset();
+            self.proposal_scales = adaptor.scales().clone();
+        }
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.mh.invalidate();
+    }
+
+    fn fix(&mut self, parameter: &ParamId) {
+        self.fixed |= parameter == self.parameter.id();
+    }
+
+    fn set_prior_cache(&mut self, cache: util::PriorCache) {
+        self.prior_cache = Some(cache);
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    fn draw_prior(&self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        self.parameter.draw(&model, rng)
+    }
+
+    fn adaptation_state(&self) -> Vec<AdaptorState> {
+        self.adaptor
+            .iter()
+            .map(|adaptor| AdaptorState {
+                name: self.parameter.name().to_string(),
+                ..adaptor.state()
+            })
+            .collect()
+    }
+
+    fn restore_adaptation(
+        &mut self,
+        states: &mut dyn Iterator<Item = AdaptorState>,
+    ) -> io::Result<()> {
+        if let Some(ref mut adaptor) = self.adaptor {
+            adaptor.restore(&next_state(states, self.parameter.name())?)?;
+            self.proposal_scales.copy_from(adaptor.scales());
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, rng: &mut R, model: M) -> M {
+        if self.fixed {
+            return model;
+        }
+        let current_value = match self.parameter.lens.try_get(&model) {
+            Ok(value) => value,
+            Err(err) => {
+                let id = self.parameter.id();
+                let events = &self.events;
+                return util::reject_lens_failure(events, id, &err, model);
+            }
+        };
+        if let Err(err) = self.check_length(current_value.len()) {
+            panic!("{}", err);
+        }
+        let current_prior = match self.mh.current_prior {
+            Some(prior) => prior,
+            None => match self.prior_cache {
+                Some(ref cache) => cache
+                    .get_or_insert_with(self.parameter.id(), || {
+                        self.parameter.prior.ln_f(&current_value)
+                    }),
+                None => self.parameter.prior.ln_f(&current_value),
+            },
+        };
+        let log_likelihood = util::ScaledLogLikelihood::new(
+            &self.log_likelihood,
+            self.temperature.recip(),
+        );
+        let current_score = self.mh.current_score(|| {
+            log_likelihood.ln_f(&model) + current_prior
+        });
+
+        // propose new value
+        let move_kind = self
+            .mode_jumps
+            .map_or(util::Move::Local, |jumps| jumps.choose(rng));
+        let width = match move_kind {
+            util::Move::Wide(width) => width,
+            _ => 1.0,
+        };
+        let mut ln_jacobian_ratio = 0.0;
+        let (proposed_new_value, indices) = match move_kind {
+            util::Move::Prior => (self.parameter.prior.draw(rng), Vec::new()),
+            _ if self.reparameterization.is_some() => {
+                let map = self.reparameterization.clone().unwrap();
+                let (proposed, ratio) = self.reparameterized_proposal(
+                    map.as_ref(),
+                    rng,
+                    &current_value,
+                    width,
+                );
+                ln_jacobian_ratio = ratio;
+                // Every coordinate may change, so no block prior applies.
+                (proposed, Vec::new())
+            }
+            util::Move::Local | util::Move::Wide(_) => {
+                let mut proposed = current_value.clone();
+                let indices = self.proposal_indices(rng, current_value.len());
+                let noise = self.noise(rng, &indices, current_value.len());
+                for (&i, z) in indices.iter().zip(noise) {
+                    let step = width * self.proposal_scales[i] * z;
+                    proposed[i] += N::from_subset(&step);
+                }
+                (proposed, indices)
+            }
+        };
+
+        let new_model =
+            self.parameter.lens.try_set(&model, proposed_new_value.clone());
+        let new_model = match new_model {
+            Ok(new_model) => new_model,
+            Err(err) => {
+                let id = self.parameter.id();
+                let events = &self.events;
+                return util::reject_lens_failure(events, id, &err, model);
+            }
+        };
+        let prior_score = match move_kind {
+            util::Move::Prior => self.parameter.prior.ln_f(&proposed_new_value),
+            _ => self.proposed_prior(
+                &current_value,
+                current_prior,
+                &proposed_new_value,
+                &indices,
+            ),
+        };
+
+        let new_score = util::proposal_score(
+            &log_likelihood,
+            self.parameter.id(),
+            &model,
+            current_score,
+            || current_prior,
+            &new_model,
+            prior_score,
+        );
+
+        let mut scores = util::ProposalScores::new(current_score, new_score);
+        if move_kind == util::Move::Prior {
+            // The prior's ratio cancels with the proposal's.
+            scores = scores.hastings(current_prior - prior_score);
+        } else if self.reparameterization.is_some() {
+            scores = scores.hastings(ln_jacobian_ratio);
+        }
+        let update = util::metropolis_select(
+            rng,
+            scores,
+            proposed_new_value,
+            current_value,
+        );
+
+        self.mh.record_with_prior(&update, prior_score);
+        if let Some(ref mut adaptor) = self.adaptor {
+            // Jumps say nothing about the scales of local moves, and the
+            // adaptor tracks the parameter's space rather than the map's.
+            let enabled = match adaptor.get_mode() {
+                AdaptationStatus::Enabled => true,
+                _ => false,
+            };
+            let adapts = enabled && self.reparameterization.is_none();
+            if move_kind == util::Move::Local && adapts {
+                adaptor.update(&to_f64(&update));
+                self.proposal_scales.copy_from(adaptor.scales());
+            }
+        }
+        if let Some(ref events) = self.events {
+            let id = self.parameter.id();
+            events.emit_update(id, &update);
+            events.emit_scores(id, &update, || current_prior, prior_score);
+            if let Some(ref adaptor) = self.adaptor {
+                let scale = adaptor.get_scale();
+                events.emit_adaptation(id, adaptor.get_mode(), scale);
+            }
+        }
+        match update {
+            util::MetroplisUpdate::Accepted(_, _) => {
+                if let Some(ref cache) = self.prior_cache {
+                    cache.insert(self.parameter.id().clone(), prior_score);
+                }
+                new_model
+            }
+            util::MetroplisUpdate::Rejected(_, _) => model,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use lens::*;
+    use runner::Runner;
+    use nalgebra::DMatrix;
+    use rv::dist::*;
+    use rv::misc::ks_test;
+    use rv::prelude::Cdf;
+    use utils::multiple_tries;
+    use steppers::reparameterize::AffineMap;
+    use rand::SeedableRng;
+
+    const P_VAL: f64 = 0.2;
+    const N_TRIES: usize = 10;
+    const SEED: [u8; 32] = [0; 32];
+
+    #[derive(Clone, Debug)]
+    struct Model {
+        x: DVector<f64>,
+    }
+
+    // Sample a zero mean, unit variance MvGaussian whose coordinates have
+    // correlation rho^|i - j|, checking each marginal.
+    fn gaussian_posterior_coordinates(
+        mode: ProposalMode,
+        noise: NoiseKernel,
+        rho: f64,
+    ) -> bool {
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let dims = 5;
+        let cov = DMatrix::from_fn(dims, dims, |i, j| {
+            rho.powi((i as i32 - j as i32).abs())
+        });
+
+        let parameter = Parameter::new(
+            "x".to_string(),
+            MvGaussian::new(DVector::zeros(dims), cov).unwrap(),
+            make_lens_clone!(Model, DVector<f64>, x),
+        );
+        let log_likelihood = |_: &Model| 0.0;
+
+        let alg = VectorSRWM::new(
+            parameter,
+            log_likelihood,
+            DVector::from_element(dims, 1.0),
+        );
+        // Each coordinate moves less often in element-wise and block modes.
+        let (alg, thinning) = match mode {
+            ProposalMode::Joint => (alg.joint(), 20),
+            ProposalMode::ElementWise(n) => {
+                (alg.element_wise(n), 20 * dims / n)
+            }
+            ProposalMode::Block(window) => {
+                (alg.block(window), 20 * dims / window)
+            }
+        };
+        let alg = match noise {
+            NoiseKernel::White => alg.white_noise(),
+            NoiseKernel::AR1(phi) => alg.ar1(phi),
+            NoiseKernel::Bridge => alg.bridge(),
+        };
+
+        multiple_tries(N_TRIES, |_| {
+            let m = Model { x: DVector::zeros(dims) };
+            let results: Vec<Vec<Model>> = Runner::new(alg.clone())
+                .thinning(thinning)
+                .chains(1)
+                .run(&mut rng, m);
+
+            (0..dims).all(|i| {
+                let samples: Vec<f64> = results
+                    .iter()
+                    .flat_map(|chain| chain.iter().map(|g| g.x[i]))
+                    .collect();
+                let (stat, p) = ks_test(&samples, |s| {
+                    Gaussian::new(0.0, 1.0).unwrap().cdf(&s)
+                });
+                println!("x[{}]: test stat = {}, p = {}", i, stat, p);
+                p > P_VAL
+            })
+        })
+    }
+
+    #[test]
+    fn joint_mvgaussian_prior() {
+        assert!(gaussian_posterior_coordinates(
+            ProposalMode::Joint,
+            NoiseKernel::White,
+            0.0
+        ));
+    }
+
+    #[test]
+    fn element_wise_mvgaussian_prior() {
+        assert!(gaussian_posterior_coordinates(
+            ProposalMode::ElementWise(1),
+            NoiseKernel::White,
+            0.0
+        ));
+    }
+
+    #[test]
+    fn ar1_noise_correlated_mvgaussian_prior() {
+        assert!(gaussian_posterior_coordinates(
+            ProposalMode::Joint,
+            NoiseKernel::AR1(0.9),
+            0.9
+        ));
+    }
+
+    #[test]
+    fn block_bridge_correlated_mvgaussian_prior() {
+        assert!(gaussian_posterior_coordinates(
+            ProposalMode::Block(2),
+            NoiseKernel::Bridge,
+            0.9
+        ));
+    }
+
+    #[test]
+    fn diagonal_adaptation_learns_coordinate_scales() {
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let sds = [10.0, 1.0, 0.1];
+        let dims = sds.len();
+        let parameter = Parameter::new(
+            "x".to_string(),
+            MvGaussian::new(
+                DVector::zeros(dims),
+                DMatrix::from_fn(dims, dims, |i, j| {
+                    if i == j { sds[i] * sds[i] } else { 0.0 }
+                }),
+            ).unwrap(),
+            make_lens_clone!(Model, DVector<f64>, x),
+        );
+        let mut alg = VectorSRWM::new(
+            parameter,
+            |_: &Model| 0.0,
+            DVector::from_element(dims, 1.0),
+        ).diagonal_adaptation();
+
+        SteppingAlg::<Model, rand::rngs::StdRng>::set_adapt(
+            &mut alg,
+            AdaptationMode::Enabled
+        );
+        let m = (0..20000).fold(Model { x: DVector::zeros(dims) }, |m, _| {
+            alg.step(&mut rng, m)
+        });
+        SteppingAlg::<Model, rand::rngs::StdRng>::set_adapt(
+            &mut alg,
+            AdaptationMode::Disabled
+        );
+
+        // Scales keep the ratios of the coordinates' standard deviations.
+        let scales = alg.adaptor().unwrap().scales().clone();
+        assert_eq!(scales, alg.proposal_scales);
+        for i in 1..dims {
+            let ratio = (scales[i - 1] / scales[i]) / (sds[i - 1] / sds[i]);
+            assert!(ratio > 0.5 && ratio < 2.0, "ratio = {}", ratio);
+        }
+
+        let passed = multiple_tries(N_TRIES, |_| {
+            let samples: Vec<DVector<f64>> = (0..20000)
+                .scan(m.clone(), |m, _| {
+                    *m = alg.step(&mut rng, m.clone());
+                    Some(m.x.clone())
+                })
+                .step_by(20)
+                .collect();
+            (0..dims).all(|i| {
+                let xs: Vec<f64> = samples.iter().map(|x| x[i]).collect();
+                let (stat, p) = ks_test(&xs, |s| {
+                    Gaussian::new(0.0, sds[i]).unwrap().cdf(&s)
+                });
+                println!("x[{}]: test stat = {}, p = {}", i, stat, p);
+                p > P_VAL
+            })
+        });
+        assert!(passed);
+    }
+
+    #[test]
+    fn warm_start_from_a_proposal_covariance() {
+        let parameter = Parameter::new(
+            "x".to_string(),
+            MvGaussian::new(DVector::zeros(2), DMatrix::identity(2, 2))
+                .unwrap(),
+            make_lens_clone!(Model, DVector<f64>, x),
+        );
+        let covariance =
+            DMatrix::from_row_slice(2, 2, &[4.0, 0.3, 0.3, 0.25]);
+        let alg = VectorSRWM::new(
+            parameter,
+            |_: &Model| 0.0,
+            DVector::from_element(2, 1.0),
+        ).diagonal_adaptation()
+            .proposal_covariance(&covariance);
+
+        let scales = DVector::from_column_slice(2, &[2.0, 0.5]);
+        assert_eq!(alg.proposal_scales, scales);
+        assert_eq!(alg.adaptor().unwrap().scales(), &scales);
+    }
+
+    #[test]
+    fn single_precision_parameters() {
+        // Independent standard normal prior over single precision vectors
+        #[derive(Clone, Debug)]
+        struct StandardF32(usize);
+
+        impl Rv<DVector<f32>> for StandardF32 {
+            fn ln_f(&self, x: &DVector<f32>) -> f64 {
+                x.iter().map(|&xi| -0.5 * f64::from(xi).powi(2)).sum()
+            }
+
+            fn draw<R: Rng>(&self, rng: &mut R) -> DVector<f32> {
+                DVector::from_fn(self.0, |_, _| {
+                    rng.sample(StandardNormal) as f32
+                })
+            }
+        }
+
+        #[derive(Clone, Debug)]
+        struct Model32 {
+            x: DVector<f32>,
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let dims = 3;
+        let parameter = Parameter::new(
+            "x".to_string(),
+            StandardF32(dims),
+            make_lens_clone!(Model32, DVector<f32>, x),
+        );
+        let alg = VectorSRWM::new(
+            parameter,
+            |_: &Model32| 0.0,
+            DVector::from_element(dims, 1.0),
+        ).diagonal_adaptation();
+
+        let passed = multiple_tries(N_TRIES, |_| {
+            let m = Model32 { x: DVector::zeros(dims) };
+            let results: Vec<Vec<Model32>> = Runner::new(alg.clone())
+                .thinning(20)
+                .run(&mut rng, m);
+
+            (0..dims).all(|i| {
+                let samples: Vec<f64> = results[0]
+                    .iter()
+                    .map(|g| f64::from(g.x[i]))
+                    .collect();
+                let (stat, p) = ks_test(&samples, |s| {
+                    Gaussian::new(0.0, 1.0).unwrap().cdf(&s)
+                });
+                println!("x[{}]: test stat = {}, p = {}", i, stat, p);
+                p > P_VAL
+            })
+        });
+        assert!(passed);
+    }
+
+    #[test]
+    fn plain_vec_parameters() {
+        #[derive(Clone, Debug)]
+        struct VecModel {
+            x: Vec<f64>,
+        }
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let dims = 3;
+        let parameter = Parameter::new(
+            "x".to_string(),
+            MvGaussian::standard(dims).unwrap(),
+            make_lens_vector!(VecModel, Vec<f64>, x),
+        );
+        let alg = VectorSRWM::new(
+            parameter,
+            |_: &VecModel| 0.0,
+            DVector::from_element(dims, 1.0),
+        );
+
+        let passed = multiple_tries(N_TRIES, |_| {
+            let m = VecModel { x: vec![0.0; dims] };
+            let results = Runner::new(alg.clone())
+                .thinning(20)
+                .run(&mut rng, m);
+
+            (0..dims).all(|i| {
+                let samples: Vec<f64> =
+                    results[0].iter().map(|g| g.x[i]).collect();
+                let (stat, p) = ks_test(&samples, |s| {
+                    Gaussian::new(0.0, 1.0).unwrap().cdf(&s)
+                });
+                println!("x[{}]: test stat = {}, p = {}", i, stat, p);
+                p > P_VAL
+            })
+        });
+        assert!(passed);
+    }
+
+    // x = sinh(z), whose Jacobian is far from constant
+    #[derive(Debug)]
+    struct Sinh;
+
+    impl Reparameterize for Sinh {
+        fn forward(&self, x: &DVector<f64>) -> DVector<f64> {
+            x.map(|v| v.asinh())
+        }
+
+        fn inverse(&self, z: &DVector<f64>) -> (DVector<f64>, f64) {
+            (z.map(|v| v.sinh()), z.iter().map(|v| v.cosh().ln()).sum())
+        }
+    }
+
+    fn moments(draws: &[Model], i: usize) -> (f64, f64) {
+        let n = draws.len() as f64;
+        let mean = draws.iter().map(|m| m.x[i]).sum::<f64>() / n;
+        let var =
+            draws.iter().map(|m| (m.x[i] - mean).powi(2)).sum::<f64>() / n;
+        (mean, var)
+    }
+
+    #[test]
+    fn reparameterized_proposals_keep_the_target() {
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let parameter = Parameter::new(
+            "x".to_string(),
+            MvGaussian::new(DVector::zeros(2), DMatrix::identity(2, 2))
+                .unwrap(),
+            make_lens_clone!(Model, DVector<f64>, x),
+        );
+        let alg = VectorSRWM::new(
+            parameter,
+            |_: &Model| 0.0,
+            DVector::from_element(2, 1.0),
+        ).reparameterize(Arc::new(Sinh));
+        let draws = Runner::new(alg)
+            .warmup(1000)
+            .samples(50000)
+            .run(&mut rng, Model { x: DVector::zeros(2) });
+        for i in 0..2 {
+            let (mean, var) = moments(&draws[0], i);
+            assert!(mean.abs() < 0.1);
+            assert!((var - 1.0).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn map_learned_in_warmup_is_installed_for_sampling() {
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let cov = DMatrix::from_row_slice(2, 2, &[100.0, 9.9, 9.9, 1.0]);
+        let parameter = Parameter::new(
+            "x".to_string(),
+            MvGaussian::new(DVector::zeros(2), cov).unwrap(),
+            make_lens_clone!(Model, DVector<f64>, x),
+        );
+        let alg = VectorSRWM::new(
+            parameter,
+            |_: &Model| 0.0,
+            DVector::from_element(2, 0.5),
+        );
+        let runner = Runner::new(alg)
+            .warmup(5000)
+            .samples(20000)
+            .keep_warmup();
+        let mut session =
+            runner.session(&mut rng, Model { x: DVector::zeros(2) });
+        session.advance(5000);
+        assert!(!session.is_warming_up());
+
+        let warmup: Vec<DVector<f64>> =
+            session.draws()[0].iter().map(|m| m.x.clone()).collect();
+        let map = AffineMap::from_draws(&warmup).unwrap();
+        session.reparameterize(Some(Arc::new(map)));
+        session.advance(20000);
+        let draws = &session.draws()[0][5000..];
+        let (_, var_0) = moments(draws, 0);
+        let (_, var_1) = moments(draws, 1);
+        assert!((var_0 - 100.0).abs() < 15.0);
+        assert!((var_1 - 1.0).abs() < 0.15);
+    }
+
+    #[test]
+    fn block_updates_only_move_a_window() {
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let dims = 10;
+        let parameter = Parameter::new(
+            "x".to_string(),
+            MvGaussian::new(
+                DVector::zeros(dims),
+                DMatrix::identity(dims, dims),
+            ).unwrap(),
+            make_lens_clone!(Model, DVector<f64>, x),
+        );
+        let alg = VectorSRWM::new(
+            parameter,
+            |_: &Model| 0.0,
+            DVector::from_element(dims, 1.0),
+        ).block(3);
+
+        for _ in 0..100 {
+            let indices = alg.proposal_indices(&mut rng, dims);
+            assert_eq!(indices.len(), 3);
+            assert!(indices.windows(2).all(|w| w[1] == w[0] + 1));
+
+            let noise = alg.noise(&mut rng, &indices, dims);
+            assert_eq!(noise.len(), 3);
+            assert!(noise.iter().all(|z| z.is_finite()));
+        }
+    }
+
+    #[test]
+    fn block_prior_gaussian_random_walk() {
+        use dist::GaussianRW;
+
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let dims = 4;
+        let parameter = Parameter::new(
+            "x".to_string(),
+            GaussianRW::new(Gaussian::new(0.0, 1.0).unwrap(), 1.0, dims)
+                .unwrap(),
+            make_lens_clone!(Model, DVector<f64>, x),
+        );
+        let alg = VectorSRWM::new(
+            parameter,
+            |_: &Model| 0.0,
+            DVector::from_element(dims, 1.0),
+        ).block(2)
+        .block_prior(GaussianRW::block_ln_f);
+
+        let passed = multiple_tries(N_TRIES, |_| {
+            let m = Model { x: DVector::zeros(dims) };
+            let results: Vec<Vec<Model>> = Runner::new(alg.clone())
+                .thinning(40)
+                .chains(1)
+                .run(&mut rng, m);
+
+            // x[t] is the sum of t + 1 independent unit normals.
+            (0..dims).all(|i| {
+                let samples: Vec<f64> = results
+                    .iter()
+                    .flat_map(|chain| chain.iter().map(|g| g.x[i]))
+                    .collect();
+                let sd = ((i + 1) as f64).sqrt();
+                let (stat, p) = ks_test(&samples, |s| {
+                    Gaussian::new(0.0, sd).unwrap().cdf(&s)
+                });
+                println!("x[{}]: test stat = {}, p = {}", i, stat, p);
+                p > P_VAL
+            })
+        });
+        assert!(passed);
+    }
+
+    #[test]
+    fn mismatched_dimensions_are_reported() {
+        let parameter = Parameter::new(
+            "x".to_string(),
+            MvGaussian::new(DVector::zeros(3), DMatrix::identity(3, 3))
+                .unwrap(),
+            make_lens_clone!(Model, DVector<f64>, x),
+        );
+        let alg = VectorSRWM::new(
+            parameter,
+            |_: &Model| 0.0,
+            DVector::from_element(3, 1.0),
+        ).diagonal_adaptation();
+        assert!(alg.check_dimensions(&Model { x: DVector::zeros(3) }).is_ok());
+
+        let err = alg
+            .check_dimensions(&Model { x: DVector::zeros(2) })
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            "VectorSRWM for x: the parameter has length 2 but \
+             proposal_scales have length 3."
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "the parameter has length 2")]
+    fn step_panics_on_mismatched_dimensions() {
+        let mut rng = rand::rngs::StdRng::from_seed(SEED);
+        let parameter = Parameter::new(
+            "x".to_string(),
+            MvGaussian::new(DVector::zeros(3), DMatrix::identity(3, 3))
+                .unwrap(),
+            make_lens_clone!(Model, DVector<f64>, x),
+        );
+        let mut alg = VectorSRWM::new(
+            parameter,
+            |_: &Model| 0.0,
+            DVector::from_element(3, 1.0),
+        );
+        alg.step(&mut rng, Model { x: DVector::zeros(2) });
+    }
+}
@@ -1,5 +1,13 @@
+//! Summaries of runs and the diagnostics they report
+
+use std::fmt;
+use std::time::Duration;
+use nalgebra::{DMatrix, DVector};
 use steppers::SteppingAlg;
 use rand::Rng;
+use events::Event;
+use notebook::ParameterSummary;
+use runner::{Sample, SLOW_MADS};
 
 /// statistics monitoring via a summarizer
 pub trait Summarizer<A, M, R: Rng> {
@@ -19,3 +27,580 @@ impl Summarizer<NullSummary> for DefaultSummarizer
 
 }
 */
+
+/// Split R-hat of a quantity's draws in several chains, the potential
+/// scale reduction after splitting each chain in half
+///
+/// Near 1 when the chains agree with each other and between their halves;
+/// values above about 1.01 suggest running longer. `None` without at least
+/// two chains of four draws, or if no chain varies.
+pub fn split_r_hat(chains: &[Vec<f64>]) -> Option<f64> {
+    let n = chains.iter().map(|c| c.len()).min()? / 2;
+    if chains.len() < 2 || n < 2 {
+        return None;
+    }
+    let halves: Vec<&[f64]> = chains
+        .iter()
+        .flat_map(|c| vec![&c[..n], &c[c.len() - n..]])
+        .collect();
+    let m = halves.len() as f64;
+    let n = n as f64;
+
+    let means: Vec<f64> =
+        halves.iter().map(|h| h.iter().sum::<f64>() / n).collect();
+    let within = halves
+        .iter()
+        .zip(means.iter())
+        .map(|(h, mean)| {
+            h.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)
+        })
+        .sum::<f64>()
+        / m;
+    let grand_mean = means.iter().sum::<f64>() / m;
+    let between_over_n = means
+        .iter()
+        .map(|mean| (mean - grand_mean).powi(2))
+        .sum::<f64>()
+        / (m - 1.0);
+
+    if !(within > 0.0) {
+        return None;
+    }
+    let var_plus = (n - 1.0) / n * within + between_over_n;
+    Some((var_plus / within).sqrt())
+}
+
+/// Effective sample size of a quantity's draws in several chains
+///
+/// Autocorrelations are estimated over every chain, truncated to the
+/// shortest, and summed until Geyer's initial positive sequence ends. `None`
+/// with fewer than four draws per chain, or if no chain varies.
+pub fn effective_sample_size(chains: &[Vec<f64>]) -> Option<f64> {
+    let n = chains.iter().map(|c| c.len()).min()?;
+    if n < 4 {
+        return None;
+    }
+    let chains: Vec<&[f64]> = chains.iter().map(|c| &c[..n]).collect();
+    let m = chains.len() as f64;
+    let n_f = n as f64;
+
+    let means: Vec<f64> =
+        chains.iter().map(|c| c.iter().sum::<f64>() / n_f).collect();
+    // Autocovariance at `lag` averaged over the chains
+    let autocovariance = |lag: usize| -> f64 {
+        chains
+            .iter()
+            .zip(means.iter())
+            .map(|(c, mean)| {
+                (0..n - lag)
+                    .map(|t| (c[t] - mean) * (c[t + lag] - mean))
+                    .sum::<f64>()
+                    / n_f
+            })
+            .sum::<f64>()
+            / m
+    };
+
+    let within = autocovariance(0) * n_f / (n_f - 1.0);
+    let var_plus = if chains.len() > 1 {
+        let grand_mean = means.iter().sum::<f64>() / m;
+        let between_over_n = means
+            .iter()
+            .map(|mean| (mean - grand_mean).powi(2))
+            .sum::<f64>()
+            / (m - 1.0);
+        within * (n_f - 1.0) / n_f + between_over_n
+    } else {
+        within * (n_f - 1.0) / n_f
+    };
+    if !(var_plus > 0.0) {
+        return None;
+    }
+    let rho = |lag: usize| 1.0 - (within - autocovariance(lag)) / var_plus;
+
+    // Sum the autocorrelations in pairs while the pairs are positive.
+    let mut tau = -1.0;
+    let mut lag = 0;
+    while lag + 1 < n {
+        let pair = rho(lag) + rho(lag + 1);
+        if !(pair > 0.0) {
+            break;
+        }
+        tau += 2.0 * pair;
+        lag += 2;
+    }
+    // Antithetic chains can give a tiny tau; bound the ESS as Stan does.
+    Some(m * n_f / tau.max(1.0 / (m * n_f).log10()))
+}
+
+/// Posterior summary of one quantity with its convergence diagnostics
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuantitySummary {
+    pub stats: ParameterSummary,
+    pub r_hat: Option<f64>,
+    pub ess: Option<f64>,
+}
+
+/// Summary of a run: a table of quantities with their diagnostics, the
+/// number of divergences and the time taken
+///
+/// `Display` gives the usual fit summary table and `to_json` a form for
+/// other programs, e.g. to fail a CI job when `r_hat` is too large.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunSummary {
+    pub rows: Vec<QuantitySummary>,
+    pub n_chains: usize,
+    pub n_draws: usize,
+    /// Steps flagged with a `NumericalWarning`, if events were counted
+    pub divergences: Option<usize>,
+    /// Timed steps slower than the rest by `SLOW_MADS`, if steps were timed
+    pub slow_steps: Option<usize>,
+    /// Total time of the runs making up the sample, if recorded
+    pub elapsed: Option<Duration>,
+    /// Mean acceptance probability of swaps between each pair of
+    /// temperatures of a parallel tempering run, if recorded
+    pub swap_acceptance: Option<DMatrix<f64>>,
+}
+
+impl RunSummary {
+    /// Count the divergences, steps with a `NumericalWarning`, among each
+    /// chain's events, e.g. those of `Runner::run_with_events`.
+    pub fn count_divergences(&self, events: &[Vec<Event>]) -> Self {
+        let divergences = events
+            .iter()
+            .flat_map(|chain| chain.iter())
+            .filter(|e| match e {
+                Event::NumericalWarning { .. } => true,
+                _ => false,
+            })
+            .count();
+        RunSummary {
+            divergences: Some(divergences),
+            ..(*self).clone()
+        }
+    }
+
+    /// Report the swap acceptance between the temperatures of a parallel
+    /// tempering run, e.g. `ParallelTempering::swap_acceptance` of one of
+    /// its chains, so ladders which barely swap show up with the rest of
+    /// the diagnostics.
+    pub fn with_swap_acceptance(&self, acceptance: DMatrix<f64>) -> Self {
+        RunSummary {
+            swap_acceptance: Some(acceptance),
+            ..(*self).clone()
+        }
+    }
+
+    /// The summary as a JSON object, with missing and non-finite values as
+    /// `null`
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = self
+            .rows
+            .iter()
+            .map(|r| {
+                let q = r.stats.quantile_values();
+                format!(
+                    "{{\"name\":{},\"mean\":{},\"sd\":{},\"q5\":{},\
+                     \"q50\":{},\"q95\":{},\"draws\":{},\
+                     \"non_finite\":{},\"r_hat\":{},\"ess\":{}}}",
+                    json_string(&r.stats.name),
+                    json_number(r.stats.mean),
+                    json_number(r.stats.sd),
+                    json_number(q[0]),
+                    json_number(q[1]),
+                    json_number(q[2]),
+                    r.stats.n_draws,
+                    r.stats.n_non_finite,
+                    json_number(r.r_hat),
+                    json_number(r.ess)
+                )
+            })
+            .collect();
+        let swap_acceptance = match self.swap_acceptance {
+            Some(ref acceptance) => {
+                let rows: Vec<String> = (0..acceptance.nrows())
+                    .map(|i| {
+                        let row: Vec<String> = acceptance
+                            .row(i)
+                            .iter()
+                            .map(|&a| json_number(Some(a)))
+                            .collect();
+                        format!("[{}]", row.join(","))
+                    })
+                    .collect();
+                format!("[{}]", rows.join(","))
+            }
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"quantities\":[{}],\"chains\":{},\"draws\":{},\
+             \"swap_acceptance\":{},\"slow_steps\":{},\"divergences\":{},\
+             \"elapsed_secs\":{}}}",
+            rows.join(","),
+            self.n_chains,
+            self.n_draws,
+            swap_acceptance,
+            self.slow_steps
+                .map_or("null".to_string(), |n| n.to_string()),
+            self.divergences
+                .map_or("null".to_string(), |d| d.to_string()),
+            json_number(self.elapsed.map(secs))
+        )
+    }
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1E-9
+}
+
+fn json_number(x: Option<f64>) -> String {
+    match x {
+        Some(x) if x.is_finite() => x.to_string(),
+        _ => "null".to_string(),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let escaped: String = s
+        .chars()
+        .flat_map(|c| match c {
+            '"' => "\\\"".chars().collect(),
+            '\\' => "\\\\".chars().collect(),
+            c if c.is_control() => {
+                format!("\\u{:04x}", c as u32).chars().collect()
+            }
+            c => vec![c],
+        })
+        .collect();
+    format!("\"{}\"", escaped)
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let optional = |x: Option<f64>, precision: usize| match x {
+            Some(x) => format!("{:.*}", precision, x),
+            None => "-".to_string(),
+        };
+        writeln!(
+            f,
+            "{:<12} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8} {:>8}",
+            "parameter", "mean", "sd", "5%", "50%", "95%", "r_hat", "ess"
+        )?;
+        for r in self.rows.iter() {
+            let q = r.stats.quantile_values();
+            writeln!(
+                f,
+                "{:<12} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8} {:>8}",
+                r.stats.name,
+                optional(r.stats.mean, 4),
+                optional(r.stats.sd, 4),
+                optional(q[0], 4),
+                optional(q[1], 4),
+                optional(q[2], 4),
+                optional(r.r_hat, 3),
+                optional(r.ess, 0)
+            )?;
+        }
+        write!(f, "{} chains, {} draws", self.n_chains, self.n_draws)?;
+        if let Some(divergences) = self.divergences {
+            write!(f, ", {} divergences", divergences)?;
+        }
+        if let Some(slow_steps) = self.slow_steps {
+            write!(f, ", {} slow steps", slow_steps)?;
+        }
+        if let Some(elapsed) = self.elapsed {
+            write!(f, ", {:.2}s", secs(elapsed))?;
+        }
+        writeln!(f)?;
+        if let Some(ref acceptance) = self.swap_acceptance {
+            writeln!(f, "swap acceptance between temperatures")?;
+            for i in 0..acceptance.nrows() {
+                let row: Vec<String> = acceptance
+                    .row(i)
+                    .iter()
+                    .map(|a| format!("{:>6.3}", a))
+                    .collect();
+                writeln!(f, "{}", row.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<M> Sample<M> {
+    /// Summarize the named quantities over every draw, with diagnostics
+    /// from the chains and the time taken and slow steps from the sample's
+    /// provenance.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate rmcmc;
+    /// # use rmcmc::runner::Sample;
+    /// # fn main() {
+    /// let chains = vec![vec![1.0, 2.0, 3.0, 4.0], vec![2.0, 1.0, 4.0, 3.0]];
+    /// let summary = Sample::new(vec![], chains).summary(&[("x", |x| *x)]);
+    ///
+    /// assert_eq!(summary.rows[0].stats.mean, Some(2.5));
+    /// assert!(summary.to_string().starts_with("parameter"));
+    /// assert!(summary.to_json().starts_with("{\"quantities\":"));
+    /// # }
+    /// ```
+    pub fn summary(&self, quantities: &[(&str, fn(&M) -> f64)]) -> RunSummary {
+        let rows = quantities
+            .iter()
+            .map(|(name, f)| {
+                let chains: Vec<Vec<f64>> = self
+                    .chains
+                    .iter()
+                    .map(|chain| chain.iter().map(f).collect())
+                    .collect();
+                let values: Vec<f64> =
+                    chains.iter().flat_map(|c| c.iter().cloned()).collect();
+                QuantitySummary {
+                    stats: ParameterSummary::new(name.to_string(), &values),
+                    r_hat: split_r_hat(&chains),
+                    ess: effective_sample_size(&chains),
+                }
+            })
+            .collect();
+        let elapsed = if self.provenance.is_empty() {
+            None
+        } else {
+            Some(self.provenance.iter().map(|p| p.elapsed()).sum())
+        };
+        let slow_steps = if self.has_step_times() {
+            Some(self.slow_steps(SLOW_MADS).len())
+        } else {
+            None
+        };
+        RunSummary {
+            rows,
+            n_chains: self.n_chains(),
+            n_draws: self.n_draws(),
+            divergences: None,
+            slow_steps,
+            elapsed,
+            swap_acceptance: None,
+        }
+    }
+}
+
+/// Posterior means and covariances of named quantities
+#[derive(Clone, Debug, PartialEq)]
+pub struct PosteriorCovariance {
+    pub names: Vec<String>,
+    pub mean: DVector<f64>,
+    pub covariance: DMatrix<f64>,
+    pub n_draws: usize,
+}
+
+impl PosteriorCovariance {
+    fn index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    /// Covariance of two named quantities
+    pub fn get(&self, a: &str, b: &str) -> Option<f64> {
+        Some(self.covariance[(self.index(a)?, self.index(b)?)])
+    }
+
+    /// Correlation matrix, with rows and columns of constant quantities
+    /// set to zero but for a one on the diagonal
+    pub fn correlation(&self) -> DMatrix<f64> {
+        let dim = self.names.len();
+        let sd: Vec<f64> =
+            (0..dim).map(|i| self.covariance[(i, i)].sqrt()).collect();
+        DMatrix::from_fn(dim, dim, |i, j| {
+            if i == j {
+                1.0
+            } else if sd[i] > 0.0 && sd[j] > 0.0 {
+                self.covariance[(i, j)] / (sd[i] * sd[j])
+            } else {
+                0.0
+            }
+        })
+    }
+}
+
+/// Sample covariance of the named quantities over every draw of `sample`,
+/// e.g. to inspect the posterior's geometry or as the initial covariance of
+/// a joint proposal.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # use rmcmc::runner::Sample;
+/// # use rmcmc::summary::posterior_covariance;
+/// # fn main() {
+/// let sample = Sample::new(vec![], vec![vec![(0.0, 0.0), (1.0, 2.0)]]);
+/// let cov = posterior_covariance(&sample, &[("a", |m| m.0), ("b", |m| m.1)])
+///     .unwrap();
+/// assert!((cov.get("a", "b").unwrap() - 1.0).abs() < 1E-12);
+/// assert!((cov.correlation()[(0, 1)] - 1.0).abs() < 1E-12);
+/// # }
+/// ```
+pub fn posterior_covariance<M>(
+    sample: &Sample<M>,
+    quantities: &[(&str, fn(&M) -> f64)],
+) -> Option<PosteriorCovariance> {
+    let n = sample.n_draws();
+    if n < 2 {
+        return None;
+    }
+    let dim = quantities.len();
+    let values = DMatrix::from_fn(n, dim, |_, _| 0.0);
+    let values = sample.draws().enumerate().fold(values, |mut v, (i, m)| {
+        for (j, (_, f)) in quantities.iter().enumerate() {
+            v[(i, j)] = f(m);
+        }
+        v
+    });
+    let mean = DVector::from_fn(dim, |j, _| {
+        values.column(j).iter().sum::<f64>() / n as f64
+    });
+    let centered = DMatrix::from_fn(n, dim, |i, j| values[(i, j)] - mean[j]);
+    let covariance = centered.transpose() * &centered / (n - 1) as f64;
+    Some(PosteriorCovariance {
+        names: quantities.iter().map(|(name, _)| name.to_string()).collect(),
+        mean,
+        covariance,
+        n_draws: n,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parameter::ParamId;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::Gaussian;
+    use rv::traits::Rv;
+
+    const SEED: [u8; 32] = [0; 32];
+
+    fn iid(mean: f64, n: usize, rng: &mut StdRng) -> Vec<f64> {
+        Gaussian::new(mean, 1.0).unwrap().sample(n, rng)
+    }
+
+    #[test]
+    fn diagnostics_of_mixed_and_stuck_chains() {
+        let mut rng = StdRng::from_seed(SEED);
+        let mixed: Vec<Vec<f64>> =
+            (0..4).map(|_| iid(0.0, 1000, &mut rng)).collect();
+        assert!((split_r_hat(&mixed).unwrap() - 1.0).abs() < 0.01);
+        let ess = effective_sample_size(&mixed).unwrap();
+        assert!(ess > 3000.0 && ess < 5000.0);
+
+        let stuck = vec![iid(0.0, 1000, &mut rng), iid(3.0, 1000, &mut rng)];
+        assert!(split_r_hat(&stuck).unwrap() > 1.5);
+
+        // AR(1) draws with coefficient 0.9 are worth (1 - 0.9) / (1 + 0.9)
+        // independent draws each.
+        let noise = iid(0.0, 20000, &mut rng);
+        let ar: Vec<f64> = noise
+            .iter()
+            .scan(0.0, |x, e| {
+                *x = 0.9 * *x + e;
+                Some(*x)
+            })
+            .collect();
+        let ess = effective_sample_size(&[ar]).unwrap();
+        let expected = 20000.0 * 0.1 / 1.9;
+        assert!((ess - expected).abs() < 0.25 * expected);
+
+        assert_eq!(split_r_hat(&mixed[..1]), None);
+        assert_eq!(effective_sample_size(&[vec![1.0; 10]]), None);
+    }
+
+    #[test]
+    fn summary_prints_and_serializes() {
+        let mut rng = StdRng::from_seed(SEED);
+        let chains = vec![iid(0.0, 100, &mut rng), iid(0.0, 100, &mut rng)];
+        let summary = Sample::new(vec![ParamId("x".to_string())], chains)
+            .summary(&[("x", |x| *x), ("\"x\"", |_| 1.0)])
+            .count_divergences(&[
+                vec![Event::NumericalWarning {
+                    parameter: ParamId("x".to_string()),
+                    message: "NaN acceptance ratio".to_string(),
+                }],
+                vec![],
+            ]);
+        assert_eq!(summary.divergences, Some(1));
+        assert_eq!(summary.elapsed, None);
+
+        let text = summary.to_string();
+        assert_eq!(text.lines().count(), 4);
+        assert!(text.lines().last().unwrap().ends_with("1 divergences"));
+        // A constant has no diagnostics.
+        let constant: Vec<&str> =
+            text.lines().nth(2).unwrap().split_whitespace().collect();
+        assert_eq!(constant[constant.len() - 2..], ["-", "-"]);
+
+        let json = summary.to_json();
+        assert!(json.contains("\"name\":\"\\\"x\\\"\""));
+        assert!(json.contains("\"r_hat\":null"));
+        assert!(json.contains("\"divergences\":1,\"elapsed_secs\":null}"));
+        assert!(json.contains("\"swap_acceptance\":null,"));
+
+        let acceptance = DMatrix::from_row_slice(2, 2, &[1.0, 0.25, 0.25, 1.0]);
+        let tempered = summary.with_swap_acceptance(acceptance);
+        let text = tempered.to_string();
+        assert_eq!(text.lines().count(), 7);
+        assert_eq!(text.lines().last().unwrap(), " 0.250  1.000");
+        assert!(tempered
+            .to_json()
+            .contains("\"swap_acceptance\":[[1,0.25],[0.25,1]],"));
+    }
+
+    #[test]
+    fn non_finite_quantities_are_reported_as_null() {
+        let nan = std::f64::NAN;
+        let chains = vec![vec![nan, 1.0, nan, 3.0], vec![nan; 4]];
+        let summary = Sample::new(vec![], chains)
+            .summary(&[("x", |x| *x), ("nan", |_| std::f64::NAN)]);
+        assert_eq!(summary.rows[0].stats.mean, Some(2.0));
+        assert_eq!(summary.rows[0].stats.n_non_finite, 6);
+        assert_eq!(summary.rows[1].stats.mean, None);
+
+        let json = summary.to_json();
+        assert!(json.contains("\"name\":\"nan\",\"mean\":null"));
+        assert!(json.contains("\"draws\":0,\"non_finite\":8"));
+        assert_eq!(summary.to_string().lines().count(), 4);
+    }
+
+    #[test]
+    fn covariance_of_correlated_draws() {
+        let mut rng = StdRng::from_seed(SEED);
+        // b = a + noise, so var(a) = 1, var(b) = 2 and cov(a, b) = 1.
+        let a = iid(0.0, 20000, &mut rng);
+        let noise = iid(0.0, 20000, &mut rng);
+        let draws: Vec<(f64, f64, f64)> = a
+            .iter()
+            .zip(noise.iter())
+            .map(|(a, e)| (*a, a + e, 3.0))
+            .collect();
+        let (first, second) = draws.split_at(10000);
+        let sample = Sample::new(vec![], vec![first.to_vec(), second.to_vec()]);
+
+        let cov = posterior_covariance(
+            &sample,
+            &[("a", |m| m.0), ("b", |m| m.1), ("c", |m| m.2)],
+        ).unwrap();
+        assert_eq!(cov.n_draws, 20000);
+        assert!((cov.get("a", "a").unwrap() - 1.0).abs() < 0.05);
+        assert!((cov.get("b", "b").unwrap() - 2.0).abs() < 0.1);
+        assert!((cov.get("a", "b").unwrap() - 1.0).abs() < 0.05);
+        assert_eq!(cov.get("a", "b"), cov.get("b", "a"));
+        assert_eq!(cov.get("a", "d"), None);
+        assert_eq!(cov.mean[2], 3.0);
+
+        let correlation = cov.correlation();
+        assert!((correlation[(0, 1)] - 0.5f64.sqrt()).abs() < 0.02);
+        assert_eq!(correlation[(2, 2)], 1.0);
+        assert_eq!(correlation[(0, 2)], 0.0);
+
+        let single = Sample::new(vec![], vec![vec![(1.0, 1.0, 1.0)]]);
+        assert!(posterior_covariance(&single, &[("a", |m| m.0)]).is_none());
+    }
+}
@@ -0,0 +1,886 @@
+//! Helpers for constructing likelihoods
+
+use std::sync::Arc;
+use special::Gamma;
+use nalgebra::DVector;
+use rv::dist::Mixture;
+use rv::traits::{Mean, Rv};
+use lens::Lens;
+use utils::quadrature::GaussHermite;
+
+/// Log likelihood of i.i.d. `data` under the distribution `dist` gives for
+/// the model.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate rv;
+/// # use rmcmc::utils::likelihood::log_likelihood_from_data;
+/// # use rv::dist::Gaussian;
+/// # fn main() {
+/// struct Model {
+///     mu: f64,
+/// }
+///
+/// let log_likelihood = log_likelihood_from_data(
+///     &[0.1, -0.3, 0.2],
+///     |m: &Model| Gaussian::new(m.mu, 1.0).unwrap(),
+/// );
+/// let near = log_likelihood(&Model { mu: 0.0 });
+/// let far = log_likelihood(&Model { mu: 3.0 });
+/// assert!(near > far);
+/// # }
+/// ```
+pub fn log_likelihood_from_data<X, D, M>(
+    data: &[X],
+    dist: fn(&M) -> D,
+) -> impl Fn(&M) -> f64 + Clone + Sync
+where
+    X: Clone + Sync,
+    D: Rv<X>,
+{
+    let data = data.to_vec();
+    move |m: &M| {
+        let d = dist(m);
+        data.iter().map(|x| d.ln_f(x)).sum()
+    }
+}
+
+/// Log likelihood of `data` with each observation's term multiplied by its
+/// weight, e.g. a frequency weight for aggregated data or an importance
+/// weight for survey data.
+///
+/// Weights must be finite and non-negative; a zero weight drops its
+/// observation.
+pub fn weighted_log_likelihood_from_data<X, D, M>(
+    data: &[X],
+    weights: &[f64],
+    dist: fn(&M) -> D,
+) -> impl Fn(&M) -> f64 + Clone + Sync
+where
+    X: Clone + Sync,
+    D: Rv<X>,
+{
+    assert_eq!(
+        data.len(),
+        weights.len(),
+        "There must be one weight per observation."
+    );
+    assert!(
+        weights.iter().all(|w| w.is_finite() && *w >= 0.0),
+        "Weights must be finite and non-negative."
+    );
+
+    let weighted: Vec<(X, f64)> = data
+        .iter()
+        .cloned()
+        .zip(weights.iter().cloned())
+        .filter(|(_, w)| *w > 0.0)
+        .collect();
+    move |m: &M| {
+        let d = dist(m);
+        weighted.iter().map(|(x, w)| w * d.ln_f(x)).sum()
+    }
+}
+
+/// Log likelihood contribution of a single observation record
+///
+/// Implement this for structured observations, e.g. a response with its
+/// covariates, to build likelihoods with `log_likelihood_from_records`.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate rv;
+/// # use rmcmc::utils::likelihood::*;
+/// # use rv::dist::Gaussian;
+/// # use rv::traits::Rv;
+/// # fn main() {
+/// struct Line {
+///     slope: f64,
+/// }
+///
+/// #[derive(Clone)]
+/// struct Obs {
+///     x: f64,
+///     y: f64,
+/// }
+///
+/// impl DatumLogLikelihood<Line> for Obs {
+///     fn ln_f(&self, m: &Line) -> f64 {
+///         Gaussian::new(m.slope * self.x, 1.0).unwrap().ln_f(&self.y)
+///     }
+/// }
+///
+/// let records = vec![Obs { x: 1.0, y: 2.1 }, Obs { x: 2.0, y: 3.9 }];
+/// let log_likelihood = log_likelihood_from_records(records);
+/// let good = log_likelihood(&Line { slope: 2.0 });
+/// let bad = log_likelihood(&Line { slope: -2.0 });
+/// assert!(good > bad);
+/// # }
+/// ```
+pub trait DatumLogLikelihood<M> {
+    /// Log likelihood of this observation given the model
+    fn ln_f(&self, model: &M) -> f64;
+}
+
+/// Log likelihood of independent observation records.
+pub fn log_likelihood_from_records<I, T, M>(
+    records: I,
+) -> impl Fn(&M) -> f64 + Clone + Sync
+where
+    I: IntoIterator<Item = T>,
+    T: DatumLogLikelihood<M> + Clone + Sync,
+{
+    let records: Vec<T> = records.into_iter().collect();
+    move |m: &M| records.iter().map(|r| r.ln_f(m)).sum()
+}
+
+/// Log likelihood of independent `(response, covariates)` pairs, where
+/// `dist` gives the response's distribution for the model and covariates.
+pub fn log_likelihood_from_pairs<I, Y, C, D, M>(
+    pairs: I,
+    dist: fn(&M, &C) -> D,
+) -> impl Fn(&M) -> f64 + Clone + Sync
+where
+    I: IntoIterator<Item = (Y, C)>,
+    Y: Clone + Sync,
+    C: Clone + Sync,
+    D: Rv<Y>,
+{
+    let pairs: Vec<(Y, C)> = pairs.into_iter().collect();
+    move |m: &M| pairs.iter().map(|(y, c)| dist(m, c).ln_f(y)).sum()
+}
+
+/// Poisson changepoint likelihood backed by cumulative sufficient statistics
+///
+/// The counts before the switch point are Poisson with the early rate and
+/// the counts at or after it are Poisson with the late rate. Prefix sums of
+/// the counts and of `ln(y!)` are computed once so each evaluation is O(1)
+/// regardless of the number of observations.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # use rmcmc::utils::likelihood::ChangepointLikelihood;
+/// # fn main() {
+/// let counts = vec![4, 5, 4, 1, 0, 1];
+/// let cp = ChangepointLikelihood::new(&counts);
+///
+/// // All counts before the switch point share the early rate.
+/// let ln_f = cp.ln_f(3, 4.3, 0.7);
+/// assert!(ln_f < 0.0);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChangepointLikelihood {
+    // Sum of the counts y[0..i]
+    count_sums: Vec<f64>,
+    // Sum of ln(y!) over y[0..i]
+    ln_fact_sums: Vec<f64>,
+}
+
+impl ChangepointLikelihood {
+    pub fn new(counts: &[u32]) -> Self {
+        let mut count_sums = Vec::with_capacity(counts.len() + 1);
+        let mut ln_fact_sums = Vec::with_capacity(counts.len() + 1);
+        count_sums.push(0.0);
+        ln_fact_sums.push(0.0);
+
+        let (mut sum, mut ln_fact_sum) = (0.0, 0.0);
+        for &y in counts {
+            let y = f64::from(y);
+            sum += y;
+            ln_fact_sum += Gamma::ln_gamma(y + 1.0).0;
+            count_sums.push(sum);
+            ln_fact_sums.push(ln_fact_sum);
+        }
+
+        ChangepointLikelihood {
+            count_sums,
+            ln_fact_sums,
+        }
+    }
+
+    /// Number of observations
+    pub fn len(&self) -> usize {
+        self.count_sums.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Poisson log likelihood of the observations in `start..end` at `rate`.
+    pub fn segment_ln_f(&self, start: usize, end: usize, rate: f64) -> f64 {
+        let end = end.min(self.len());
+        let start = start.min(end);
+        let n = (end - start) as f64;
+        if n == 0.0 {
+            return 0.0;
+        }
+        let sum = self.count_sums[end] - self.count_sums[start];
+        let ln_fact = self.ln_fact_sums[end] - self.ln_fact_sums[start];
+        if rate <= 0.0 {
+            return if rate == 0.0 && sum == 0.0 {
+                0.0
+            } else {
+                std::f64::NEG_INFINITY
+            };
+        }
+        sum * rate.ln() - n * rate - ln_fact
+    }
+
+    /// Log likelihood with the rate switching from `early_rate` to
+    /// `late_rate` at index `switch`. A switch past the end of the data
+    /// assigns every observation to the early rate.
+    pub fn ln_f(&self, switch: usize, early_rate: f64, late_rate: f64) -> f64 {
+        let n = self.len();
+        self.segment_ln_f(0, switch, early_rate)
+            + self.segment_ln_f(switch, n, late_rate)
+    }
+
+    /// Produce a log likelihood over a model, reading the switch point and
+    /// rates through lenses, suitable for use in a stepper.
+    pub fn log_likelihood<M>(
+        &self,
+        switch: Lens<u32, M>,
+        early_rate: Lens<f64, M>,
+        late_rate: Lens<f64, M>,
+    ) -> impl Fn(&M) -> f64 + Clone + Sync {
+        let cp = self.clone();
+        move |m: &M| {
+            cp.ln_f(
+                switch.get(m) as usize,
+                early_rate.get(m),
+                late_rate.get(m),
+            )
+        }
+    }
+}
+
+/// Observations indexed by group for models with a per-group random effect
+///
+/// Observations are stored contiguously by group, so a group's distribution
+/// is built once and scored against all of its observations, and a single
+/// group can be scored without touching the others, e.g. when updating one
+/// element of the effects vector.
+///
+/// Scalar effects can instead be integrated out of the likelihood by
+/// adaptive Gauss-Hermite quadrature with `marginal_log_likelihood`, so
+/// only the effects' distribution is sampled rather than one effect per
+/// group. With a single observation per group this marginalizes a latent
+/// variable per observation.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate rv;
+/// # use rmcmc::utils::likelihood::GroupedLikelihood;
+/// # use rv::dist::Gaussian;
+/// # fn main() {
+/// // Two repeated measurements of subject 0 and one of subject 1
+/// let ys = vec![1.1, 0.9, -2.0];
+/// let subjects = vec![0, 0, 1];
+/// let grouped = GroupedLikelihood::new(&ys, &subjects, 2);
+///
+/// let dist = |effect: f64| Gaussian::new(effect, 1.0).unwrap();
+/// let good = grouped.ln_f(&[1.0, -2.0], dist);
+/// let bad = grouped.ln_f(&[-2.0, 1.0], dist);
+/// assert!(good > bad);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct GroupedLikelihood<X> {
+    // Observations ordered by group
+    data: Vec<X>,
+    // Group g's observations are data[offsets[g]..offsets[g + 1]]
+    offsets: Vec<usize>,
+}
+
+impl<X: Clone> GroupedLikelihood<X> {
+    /// Index `data`, where observation `i` belongs to group `groups[i]` and
+    /// there are `n_groups` groups. Groups may have no observations.
+    pub fn new(data: &[X], groups: &[usize], n_groups: usize) -> Self {
+        assert_eq!(
+            data.len(),
+            groups.len(),
+            "There must be one group index per observation."
+        );
+        assert!(
+            groups.iter().all(|&g| g < n_groups),
+            "Group indices must be less than the number of groups."
+        );
+
+        let mut offsets = vec![0; n_groups + 1];
+        groups.iter().for_each(|&g| offsets[g + 1] += 1);
+        for g in 0..n_groups {
+            offsets[g + 1] += offsets[g];
+        }
+
+        let mut order: Vec<usize> = (0..data.len()).collect();
+        order.sort_by_key(|&i| groups[i]);
+        let data = order.into_iter().map(|i| data[i].clone()).collect();
+
+        GroupedLikelihood { data, offsets }
+    }
+
+    /// Number of groups
+    pub fn n_groups(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Observations of group `g`
+    pub fn group(&self, g: usize) -> &[X] {
+        &self.data[self.offsets[g]..self.offsets[g + 1]]
+    }
+
+    /// Log likelihood of group `g`'s observations under `dist`.
+    pub fn group_ln_f<D: Rv<X>>(&self, g: usize, dist: &D) -> f64 {
+        self.group(g).iter().map(|x| dist.ln_f(x)).sum()
+    }
+
+    /// Log likelihood of every observation, where `dist` gives the
+    /// distribution of a group's observations from its effect. Groups
+    /// without observations are skipped.
+    pub fn ln_f<D, F>(&self, effects: &[f64], dist: F) -> f64
+    where
+        D: Rv<X>,
+        F: Fn(f64) -> D,
+    {
+        assert_eq!(
+            effects.len(),
+            self.n_groups(),
+            "There must be one effect per group."
+        );
+        effects
+            .iter()
+            .enumerate()
+            .filter(|&(g, _)| self.offsets[g] < self.offsets[g + 1])
+            .map(|(g, &effect)| self.group_ln_f(g, &dist(effect)))
+            .sum()
+    }
+
+    /// Produce a log likelihood over a model, reading the group effects
+    /// through a lens, suitable for use in a stepper. `dist` gives a
+    /// group's distribution from the model and the group's effect.
+    pub fn log_likelihood<M, D>(
+        &self,
+        effects: Lens<DVector<f64>, M>,
+        dist: fn(&M, f64) -> D,
+    ) -> impl Fn(&M) -> f64 + Clone + Sync
+    where
+        X: Sync,
+        D: Rv<X>,
+    {
+        let grouped = self.clone();
+        move |m: &M| {
+            let effects = effects.get(m);
+            grouped.ln_f(effects.as_slice(), |effect| dist(m, effect))
+        }
+    }
+
+    /// Log likelihood of every observation with each group's effect
+    /// integrated out, where `effect` is the effects' distribution and
+    /// `dist` gives a group's distribution from its effect.
+    ///
+    /// Each group's integral is estimated by `rule` adapted to the mode of
+    /// the group's posterior of its effect, searched for from the mean of
+    /// `effect`, so that posterior should be unimodal. Groups without
+    /// observations contribute nothing.
+    pub fn marginal_ln_f<E, D, F>(
+        &self,
+        effect: &E,
+        dist: F,
+        rule: &GaussHermite,
+    ) -> f64
+    where
+        E: Rv<f64> + Mean<f64>,
+        D: Rv<X>,
+        F: Fn(f64) -> D,
+    {
+        let start = effect.mean().unwrap_or(0.0);
+        (0..self.n_groups())
+            .filter(|&g| self.offsets[g] < self.offsets[g + 1])
+            .map(|g| {
+                let ln_f =
+                    |u: f64| effect.ln_f(&u) + self.group_ln_f(g, &dist(u));
+                rule.ln_integral(ln_f, start)
+            })
+            .sum()
+    }
+
+    /// Produce a log likelihood over a model with the group effects
+    /// integrated out by `marginal_ln_f` with an `n_nodes` point rule,
+    /// suitable for use in a stepper. `effect` gives the effects'
+    /// distribution from the model and `dist` a group's distribution from
+    /// the model and the group's effect.
+    ///
+    /// Five to ten nodes are usually plenty, as the rule is adapted to each
+    /// group; one node is the Laplace approximation.
+    pub fn marginal_log_likelihood<M, E, D>(
+        &self,
+        effect: fn(&M) -> E,
+        dist: fn(&M, f64) -> D,
+        n_nodes: usize,
+    ) -> impl Fn(&M) -> f64 + Clone + Sync
+    where
+        X: Sync,
+        E: Rv<f64> + Mean<f64>,
+        D: Rv<X>,
+    {
+        let grouped = self.clone();
+        let rule = GaussHermite::new(n_nodes);
+        move |m: &M| {
+            grouped.marginal_ln_f(&effect(m), |u| dist(m, u), &rule)
+        }
+    }
+}
+
+/// Observations of a finite mixture, each from an unobserved component
+///
+/// The model gives the mixture's weights and components as an `rv`
+/// `Mixture`, and each observation's component is handled in one of two
+/// ways, chosen per model:
+///
+/// - summed out: `marginal_log_likelihood` adds up the components with
+///   log-sum-exp, so no latent variables are sampled;
+/// - sampled: the model keeps an assignment per observation, drawn by an
+///   `AssignmentGibbs` stepper, and the other parameters' steppers use
+///   `complete_log_likelihood`.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # extern crate rv;
+/// # use rmcmc::utils::likelihood::MixtureLikelihood;
+/// # use rv::dist::{Gaussian, Mixture};
+/// # fn main() {
+/// let mixture = Mixture::new(
+///     vec![0.5, 0.5],
+///     vec![Gaussian::new(-2.0, 1.0).unwrap(), Gaussian::standard()],
+/// ).unwrap();
+/// let likelihood = MixtureLikelihood::new(vec![-2.1, 0.2]);
+///
+/// // Summing out the assignments beats any one of them.
+/// let marginal = likelihood.marginal_ln_f(&mixture);
+/// assert!(marginal > likelihood.complete_ln_f(&[0, 1], &mixture));
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MixtureLikelihood<X> {
+    data: Arc<Vec<X>>,
+}
+
+impl<X> Clone for MixtureLikelihood<X> {
+    fn clone(&self) -> Self {
+        MixtureLikelihood {
+            data: self.data.clone(),
+        }
+    }
+}
+
+impl<X> MixtureLikelihood<X> {
+    pub fn new(data: Vec<X>) -> Self {
+        MixtureLikelihood {
+            data: Arc::new(data),
+        }
+    }
+
+    /// The observations
+    pub fn data(&self) -> &[X] {
+        &self.data
+    }
+
+    /// Number of observations
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Log likelihood with each observation's component summed out
+    pub fn marginal_ln_f<D: Rv<X>>(&self, mixture: &Mixture<D>) -> f64 {
+        self.data.iter().map(|x| mixture.ln_f(x)).sum()
+    }
+
+    /// Joint log likelihood of the observations and their components, where
+    /// observation `i` is from component `assignments[i]`
+    pub fn complete_ln_f<D: Rv<X>>(
+        &self,
+        assignments: &[usize],
+        mixture: &Mixture<D>,
+    ) -> f64 {
+        assert_eq!(
+            assignments.len(),
+            self.len(),
+            "There must be one assignment per observation."
+        );
+        self.data
+            .iter()
+            .zip(assignments.iter())
+            .map(|(x, &k)| {
+                mixture.weights[k].ln() + mixture.components[k].ln_f(x)
+            })
+            .sum()
+    }
+
+    /// Unnormalized log probabilities of each component having produced
+    /// observation `i`
+    pub fn component_ln_weights<D: Rv<X>>(
+        &self,
+        i: usize,
+        mixture: &Mixture<D>,
+    ) -> Vec<f64> {
+        let x = &self.data[i];
+        mixture
+            .weights
+            .iter()
+            .zip(mixture.components.iter())
+            .map(|(w, component)| w.ln() + component.ln_f(x))
+            .collect()
+    }
+
+    /// Produce a log likelihood over a model with the components summed
+    /// out, suitable for use in a stepper. `mixture` gives the model's
+    /// mixture.
+    pub fn marginal_log_likelihood<M, D>(
+        &self,
+        mixture: fn(&M) -> Mixture<D>,
+    ) -> impl Fn(&M) -> f64 + Clone + Sync
+    where
+        X: Send + Sync,
+        D: Rv<X>,
+    {
+        let likelihood = self.clone();
+        move |m: &M| likelihood.marginal_ln_f(&mixture(m))
+    }
+
+    /// Produce the joint log likelihood of the observations and the
+    /// assignments read through a lens, suitable for use in the steppers
+    /// of the mixture's weights and components.
+    pub fn complete_log_likelihood<M, D>(
+        &self,
+        assignments: Lens<Vec<usize>, M>,
+        mixture: fn(&M) -> Mixture<D>,
+    ) -> impl Fn(&M) -> f64 + Clone + Sync
+    where
+        X: Send + Sync,
+        D: Rv<X>,
+    {
+        let likelihood = self.clone();
+        move |m: &M| {
+            likelihood.complete_ln_f(&assignments.get(m), &mixture(m))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rv::dist::{Gaussian, Poisson};
+    use utils::quadrature::gauss_kronrod;
+
+    fn naive(counts: &[u32], switch: usize, early: f64, late: f64) -> f64 {
+        let early_dist = Poisson::new(early).unwrap();
+        let late_dist = Poisson::new(late).unwrap();
+        counts
+            .iter()
+            .enumerate()
+            .map(|(i, y)| {
+                if i < switch {
+                    early_dist.ln_f(y)
+                } else {
+                    late_dist.ln_f(y)
+                }
+            })
+            .sum()
+    }
+
+    #[test]
+    fn matches_naive_evaluation() {
+        let counts: Vec<u32> = vec![4, 5, 4, 1, 0, 4, 3, 4, 0, 6, 3, 3, 4, 0];
+        let cp = ChangepointLikelihood::new(&counts);
+        for switch in 0..(counts.len() + 2) {
+            let expected = naive(&counts, switch, 3.1, 0.9);
+            let ln_f = cp.ln_f(switch, 3.1, 0.9);
+            assert!(
+                (ln_f - expected).abs() < 1E-8,
+                "switch = {}: {} != {}",
+                switch,
+                ln_f,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn log_likelihood_reads_model() {
+        #[derive(Clone, Debug)]
+        struct Model {
+            switch: u32,
+            early: f64,
+            late: f64,
+        }
+
+        let counts: Vec<u32> = vec![4, 5, 4, 1, 0, 1];
+        let cp = ChangepointLikelihood::new(&counts);
+        let log_likelihood = cp.log_likelihood(
+            make_lens!(Model, u32, switch),
+            make_lens!(Model, f64, early),
+            make_lens!(Model, f64, late),
+        );
+
+        let m = Model { switch: 3, early: 4.0, late: 0.5 };
+        assert!((log_likelihood(&m) - naive(&counts, 3, 4.0, 0.5)).abs() < 1E-8);
+    }
+
+    #[test]
+    fn nonpositive_rates() {
+        let cp = ChangepointLikelihood::new(&[0, 0, 2]);
+        assert_eq!(cp.ln_f(2, 0.0, 1.0), naive(&[2], 0, 1.0, 1.0));
+        assert!(cp.ln_f(3, 0.0, 1.0).is_infinite());
+        assert!(cp.ln_f(1, -1.0, 1.0).is_infinite());
+    }
+
+    #[derive(Clone, Debug)]
+    struct Mean {
+        mu: f64,
+    }
+
+    fn gaussian(m: &Mean) -> Gaussian {
+        Gaussian::new(m.mu, 1.0).unwrap()
+    }
+
+    #[test]
+    fn log_likelihood_from_data_sums_terms() {
+        let data = vec![0.5, -1.0, 2.0];
+        let log_likelihood = log_likelihood_from_data(&data, gaussian);
+
+        let m = Mean { mu: 0.3 };
+        let expected: f64 =
+            data.iter().map(|x| gaussian(&m).ln_f(x)).sum();
+        assert!((log_likelihood(&m) - expected).abs() < 1E-10);
+    }
+
+    #[test]
+    fn frequency_weights_match_repeated_data() {
+        let data = vec![0.5, -1.0, 2.0];
+        let weights = vec![3.0, 0.0, 2.0];
+        let repeated = vec![0.5, 0.5, 0.5, 2.0, 2.0];
+
+        let weighted =
+            weighted_log_likelihood_from_data(&data, &weights, gaussian);
+        let unweighted = log_likelihood_from_data(&repeated, gaussian);
+
+        let m = Mean { mu: -0.2 };
+        assert!((weighted(&m) - unweighted(&m)).abs() < 1E-10);
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_weights_panic() {
+        let _ = weighted_log_likelihood_from_data(&[1.0], &[-1.0], gaussian);
+    }
+
+    #[derive(Clone, Debug)]
+    struct Line {
+        intercept: f64,
+        slope: f64,
+    }
+
+    #[derive(Clone, Debug)]
+    struct Obs {
+        x: f64,
+        y: f64,
+    }
+
+    impl DatumLogLikelihood<Line> for Obs {
+        fn ln_f(&self, m: &Line) -> f64 {
+            line(m, &self.x).ln_f(&self.y)
+        }
+    }
+
+    fn line(m: &Line, x: &f64) -> Gaussian {
+        Gaussian::new(m.intercept + m.slope * x, 0.5).unwrap()
+    }
+
+    #[test]
+    fn records_and_pairs_agree() {
+        let xs = vec![0.0, 1.0, 2.5, -1.0];
+        let ys = vec![0.9, 3.2, 6.1, -0.8];
+
+        let records = xs.iter().zip(ys.iter()).map(|(&x, &y)| Obs { x, y });
+        let from_records = log_likelihood_from_records(records);
+        let from_pairs = log_likelihood_from_pairs(
+            ys.iter().cloned().zip(xs.iter().cloned()),
+            line,
+        );
+
+        let m = Line {
+            intercept: 1.0,
+            slope: 2.0,
+        };
+        let expected: f64 = xs
+            .iter()
+            .zip(ys.iter())
+            .map(|(x, y)| line(&m, x).ln_f(y))
+            .sum();
+        assert!((from_records(&m) - expected).abs() < 1E-10);
+        assert!((from_pairs(&m) - expected).abs() < 1E-10);
+    }
+
+    #[derive(Clone, Debug)]
+    struct Panel {
+        sigma: f64,
+        effects: DVector<f64>,
+    }
+
+    fn subject(m: &Panel, effect: f64) -> Gaussian {
+        Gaussian::new(effect, m.sigma).unwrap()
+    }
+
+    #[test]
+    fn grouped_matches_per_observation_evaluation() {
+        let ys = vec![0.3, 1.2, -0.7, 2.2, 0.0, 1.9];
+        let groups = vec![2, 0, 2, 1, 0, 1];
+        // Group 3 has no observations.
+        let grouped = GroupedLikelihood::new(&ys, &groups, 4);
+        assert_eq!(grouped.group(0), &[1.2, 0.0]);
+        assert_eq!(grouped.group(2), &[0.3, -0.7]);
+        assert!(grouped.group(3).is_empty());
+
+        let m = Panel {
+            sigma: 0.8,
+            effects: DVector::from_column_slice(4, &[0.5, 2.0, -0.1, 9.0]),
+        };
+        let expected: f64 = ys
+            .iter()
+            .zip(groups.iter())
+            .map(|(y, &g)| subject(&m, m.effects[g]).ln_f(y))
+            .sum();
+
+        let log_likelihood = grouped.log_likelihood(
+            make_lens_clone!(Panel, DVector<f64>, effects),
+            subject,
+        );
+        assert!((log_likelihood(&m) - expected).abs() < 1E-10);
+
+        let g1 = grouped.group_ln_f(1, &subject(&m, 2.0));
+        let expected_g1 =
+            subject(&m, 2.0).ln_f(&2.2) + subject(&m, 2.0).ln_f(&1.9);
+        assert!((g1 - expected_g1).abs() < 1E-10);
+    }
+
+    #[derive(Clone, Debug)]
+    struct RandomIntercept {
+        mu: f64,
+        sigma: f64,
+        tau: f64,
+    }
+
+    fn intercept(m: &RandomIntercept) -> Gaussian {
+        Gaussian::new(m.mu, m.tau).unwrap()
+    }
+
+    fn measurement(m: &RandomIntercept, effect: f64) -> Gaussian {
+        Gaussian::new(effect, m.sigma).unwrap()
+    }
+
+    #[test]
+    fn marginal_matches_the_gaussian_random_intercept_model() {
+        let ys = vec![0.3, 1.2, -0.7, 2.2, 0.0, 1.9, 4.0];
+        let groups = vec![2, 0, 2, 1, 0, 1, 1];
+        let grouped = GroupedLikelihood::new(&ys, &groups, 4);
+        let m = RandomIntercept {
+            mu: 0.5,
+            sigma: 0.8,
+            tau: 1.7,
+        };
+
+        // Each group's observations are jointly normal with variance
+        // σ²I + τ²11ᵀ.
+        let (s2, t2) = (m.sigma * m.sigma, m.tau * m.tau);
+        let expected: f64 = (0..4)
+            .map(|g| {
+                let r: Vec<f64> =
+                    grouped.group(g).iter().map(|y| y - m.mu).collect();
+                let k = r.len() as f64;
+                let sum: f64 = r.iter().sum();
+                let sum_sq: f64 = r.iter().map(|x| x * x).sum();
+                let ln_det = (k - 1.0) * s2.ln() + (s2 + k * t2).ln();
+                let quad =
+                    sum_sq / s2 - t2 * sum * sum / (s2 * (s2 + k * t2));
+                let ln_2pi = (2.0 * std::f64::consts::PI).ln();
+                -0.5 * (k * ln_2pi + ln_det + quad)
+            })
+            .sum();
+
+        // The integrands are Gaussian, so even the Laplace rule is exact.
+        for &n in &[1, 5] {
+            let log_likelihood =
+                grouped.marginal_log_likelihood(intercept, measurement, n);
+            assert!((log_likelihood(&m) - expected).abs() < 1E-6);
+        }
+    }
+
+    #[test]
+    fn marginal_integrates_a_poisson_lognormal_model() {
+        let counts: Vec<u32> = vec![0, 3, 1, 12, 9, 15, 2];
+        let groups = vec![0, 0, 0, 1, 1, 1, 2];
+        let grouped = GroupedLikelihood::new(&counts, &groups, 3);
+        let effect = Gaussian::new(1.0, 0.9).unwrap();
+        let dist = |u: f64| Poisson::new(u.exp()).unwrap();
+
+        let expected: f64 = (0..3)
+            .map(|g| {
+                let f = |u: f64| {
+                    (effect.ln_f(&u) + grouped.group_ln_f(g, &dist(u))).exp()
+                };
+                gauss_kronrod(f, -10.0, 10.0, 1E-14).ln()
+            })
+            .sum();
+        for &(n, tol) in &[(9, 1E-4), (20, 1E-8)] {
+            let rule = GaussHermite::new(n);
+            let estimate = grouped.marginal_ln_f(&effect, dist, &rule);
+            assert!((estimate - expected).abs() < tol);
+        }
+        let laplace = GaussHermite::new(1);
+        let laplace = grouped.marginal_ln_f(&effect, dist, &laplace);
+        assert!((laplace - expected).abs() < 0.1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_range_group_panics() {
+        GroupedLikelihood::new(&[1.0, 2.0], &[0, 2], 2);
+    }
+
+    #[test]
+    fn marginal_sums_the_complete_likelihood_over_assignments() {
+        let mixture = Mixture::new(
+            vec![0.3, 0.7],
+            vec![Gaussian::new(-1.0, 1.0).unwrap(), Gaussian::standard()],
+        ).unwrap();
+        let likelihood = MixtureLikelihood::new(vec![-0.5, 1.5, 0.1]);
+
+        let mut total = 0.0;
+        for a in 0..2 {
+            for b in 0..2 {
+                for c in 0..2 {
+                    let assignments = [a, b, c];
+                    total +=
+                        likelihood.complete_ln_f(&assignments, &mixture).exp();
+                }
+            }
+        }
+        let marginal = likelihood.marginal_ln_f(&mixture);
+        assert!((marginal - total.ln()).abs() < 1E-10);
+
+        let ln_weights = likelihood.component_ln_weights(1, &mixture);
+        let expected = 0.7f64.ln() + Gaussian::standard().ln_f(&1.5);
+        assert!((ln_weights[1] - expected).abs() < 1E-12);
+    }
+}
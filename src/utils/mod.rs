@@ -8,6 +8,21 @@ use rv::traits::*;
 use std::marker::PhantomData;
 use rand::Rng;
 
+pub mod likelihood;
+pub mod quadrature;
+
+/// `InvalidInput` error with `err` as its message, e.g. for a rejected
+/// configuration
+pub fn invalid_input<E: Display>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err.to_string())
+}
+
+/// `InvalidData` error with `err` as its message, e.g. for a malformed
+/// file or message
+pub fn invalid_data<E: Display>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
 pub fn multiple_tries<F: FnMut(usize) -> bool>(
     n_tries: usize,
     mut f: F,
@@ -85,7 +100,7 @@ where
     T: Rv<X> + Support<X>
 {
     fn supports(&self, x: &Vec<X>) -> bool {
-        x.iter().all(|y| self.base.supports(y)
+        x.iter().all(|y| self.base.supports(y))
     }
 }
 
@@ -105,7 +120,7 @@ where
     X: Clone
 {
     fn mean(&self) -> Option<Vec<X>> {
-        self.base.mean().map(|m| (0..self.dims).map(|_| m.clone()).collect()
+        self.base.mean().map(|m| (0..self.dims).map(|_| m.clone()).collect())
     }
 }
 
@@ -115,7 +130,7 @@ where
     X: Clone
 {
     fn median(&self) -> Option<Vec<X>> {
-        self.base.median().map(|m| (0..self.dims).map(|_| m.clone()).collect()
+        self.base.median().map(|m| (0..self.dims).map(|_| m.clone()).collect())
     }
 }
 
@@ -125,7 +140,7 @@ where
     X: Clone
 {
     fn mode(&self) -> Option<Vec<X>> {
-        self.base.mode().map(|m| (0..self.dims).map(|_| m.clone()).collect()
+        self.base.mode().map(|m| (0..self.dims).map(|_| m.clone()).collect())
     }
 }
 
@@ -135,6 +150,6 @@ where
     X: Clone
 {
     fn variance(&self) -> Option<Vec<X>> {
-        self.base.variance().map(|m| (0..self.dims).map(|_| m.clone()).collect()
+        self.base.variance().map(|m| (0..self.dims).map(|_| m.clone()).collect())
     }
 }
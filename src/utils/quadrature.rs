@@ -0,0 +1,469 @@
+//! Deterministic quadrature for one dimensional integrals
+//!
+//! Small adaptive rules for checking sampler output against exact
+//! marginals in tests, e.g. the CDF of a posterior known only up to a
+//! constant, for `ks_test`, and Gauss-Hermite rules for integrating
+//! low-dimensional latent variables out of likelihoods.
+
+use rv::misc::logsumexp;
+
+/// Deepest bisection of the adaptive rules
+const MAX_DEPTH: usize = 50;
+
+/// Nodes of the 15 point Kronrod rule on [-1, 1], the odd ones shared with
+/// the 7 point Gauss rule
+const KRONROD_NODES: [f64; 8] = [
+    0.991455371120812639206854697526329,
+    0.949107912342758524526189684047851,
+    0.864864423359769072789712788640926,
+    0.741531185599394439863864773280788,
+    0.586087235467691130294144845693013,
+    0.405845151377397166906606412076961,
+    0.207784955007898467600689403773245,
+    0.0,
+];
+
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022935322010529224963732008058970,
+    0.063092092629978553290700663189204,
+    0.104790010322250183839876322541518,
+    0.140653259715525918745189590510238,
+    0.169004726639267902826583426598550,
+    0.190350578064785409913256402421014,
+    0.204432940075298892414161999234649,
+    0.209482141084727828012999174891714,
+];
+
+const GAUSS_WEIGHTS: [f64; 4] = [
+    0.129484966168869693270611432679082,
+    0.279705391489276667901467771423780,
+    0.381830050505118944950369775488975,
+    0.417959183673469387755102040816327,
+];
+
+/// Integral of `f` over `[a, b]` by adaptive Simpson's rule, bisecting
+/// until the estimated error is below `tol`.
+pub fn simpson<F: Fn(f64) -> f64>(f: F, a: f64, b: f64, tol: f64) -> f64 {
+    let fa = f(a);
+    let fb = f(b);
+    let m = 0.5 * (a + b);
+    let fm = f(m);
+    let whole = (b - a) / 6.0 * (fa + 4.0 * fm + fb);
+    simpson_step(&f, a, b, (fa, fm, fb), whole, tol, MAX_DEPTH)
+}
+
+fn simpson_step<F: Fn(f64) -> f64>(
+    f: &F,
+    a: f64,
+    b: f64,
+    (fa, fm, fb): (f64, f64, f64),
+    whole: f64,
+    tol: f64,
+    depth: usize,
+) -> f64 {
+    let m = 0.5 * (a + b);
+    let lm = 0.5 * (a + m);
+    let rm = 0.5 * (m + b);
+    let flm = f(lm);
+    let frm = f(rm);
+    let left = (m - a) / 6.0 * (fa + 4.0 * flm + fm);
+    let right = (b - m) / 6.0 * (fm + 4.0 * frm + fb);
+    let delta = left + right - whole;
+    if depth == 0 || delta.abs() <= 15.0 * tol {
+        // Richardson extrapolation of the two estimates
+        left + right + delta / 15.0
+    } else {
+        let tol = tol / 2.0;
+        simpson_step(f, a, m, (fa, flm, fm), left, tol, depth - 1)
+            + simpson_step(f, m, b, (fm, frm, fb), right, tol, depth - 1)
+    }
+}
+
+/// 15 point Gauss-Kronrod estimate of the integral of `f` over `[a, b]`
+/// with its error, the difference from the embedded 7 point Gauss rule.
+fn kronrod15<F: Fn(f64) -> f64>(f: &F, a: f64, b: f64) -> (f64, f64) {
+    let center = 0.5 * (a + b);
+    let half = 0.5 * (b - a);
+    let f_center = f(center);
+    let mut kronrod = KRONROD_WEIGHTS[7] * f_center;
+    let mut gauss = GAUSS_WEIGHTS[3] * f_center;
+    for (i, &x) in KRONROD_NODES[..7].iter().enumerate() {
+        let sum = f(center - half * x) + f(center + half * x);
+        kronrod += KRONROD_WEIGHTS[i] * sum;
+        if i % 2 == 1 {
+            gauss += GAUSS_WEIGHTS[i / 2] * sum;
+        }
+    }
+    (kronrod * half, ((kronrod - gauss) * half).abs())
+}
+
+/// Integral of `f` over `[a, b]` by adaptive Gauss-Kronrod quadrature,
+/// bisecting until the estimated error is below `tol`.
+///
+/// Converges much faster than `simpson` for smooth integrands.
+pub fn gauss_kronrod<F: Fn(f64) -> f64>(
+    f: F,
+    a: f64,
+    b: f64,
+    tol: f64,
+) -> f64 {
+    gauss_kronrod_step(&f, a, b, tol, MAX_DEPTH)
+}
+
+fn gauss_kronrod_step<F: Fn(f64) -> f64>(
+    f: &F,
+    a: f64,
+    b: f64,
+    tol: f64,
+    depth: usize,
+) -> f64 {
+    let (estimate, error) = kronrod15(f, a, b);
+    if depth == 0 || error <= tol {
+        estimate
+    } else {
+        let m = 0.5 * (a + b);
+        gauss_kronrod_step(f, a, m, tol / 2.0, depth - 1)
+            + gauss_kronrod_step(f, m, b, tol / 2.0, depth - 1)
+    }
+}
+
+/// Newton iterations allowed when locating the mode of an integrand
+const MAX_NEWTON: usize = 100;
+
+/// Gauss-Hermite rule with `n` nodes, exact for `∫ p(x) exp(-x²) dx` with
+/// `p` a polynomial of degree below `2n`
+///
+/// `ln_integral` adapts the rule to the integrand, centering it on the
+/// mode and scaling it by the curvature there, so a few nodes integrate a
+/// likelihood times a prior over a scalar latent variable closely; a
+/// single node is the Laplace approximation.
+///
+/// # Example
+/// ```
+/// # extern crate rmcmc;
+/// # use rmcmc::utils::quadrature::GaussHermite;
+/// # fn main() {
+/// let rule = GaussHermite::new(5);
+/// // ∫ x² exp(-x²) dx = √π / 2
+/// let estimate = rule.integrate(|x| x * x);
+/// assert!((estimate - std::f64::consts::PI.sqrt() / 2.0).abs() < 1E-12);
+///
+/// // The integral of an unnormalized N(3, 0.5²) density, from its log
+/// let ln_f = |u: f64| -2.0 * (u - 3.0) * (u - 3.0);
+/// let expected = (0.5 * (2.0 * std::f64::consts::PI).sqrt()).ln();
+/// assert!((rule.ln_integral(ln_f, 0.0) - expected).abs() < 1E-9);
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct GaussHermite {
+    nodes: Vec<f64>,
+    weights: Vec<f64>,
+}
+
+impl GaussHermite {
+    /// Rule with `n` nodes, found by Newton's method on the orthonormal
+    /// Hermite polynomials. Panics if `n` is zero.
+    pub fn new(n: usize) -> Self {
+        assert!(n > 0, "A Gauss-Hermite rule needs at least one node.");
+        let mut nodes = vec![0.0; n];
+        let mut weights = vec![0.0; n];
+        let nf = n as f64;
+        // π^(-1/4), the normalized zeroth Hermite polynomial
+        let h0 = std::f64::consts::PI.powf(-0.25);
+        let mut z = 0.0;
+        // Roots are symmetric, so find the non-negative half, largest first
+        for i in 0..(n + 1) / 2 {
+            z = match i {
+                0 => (2.0 * nf + 1.0).sqrt()
+                    - 1.85575 * (2.0 * nf + 1.0).powf(-1.0 / 6.0),
+                1 => z - 1.14 * nf.powf(0.426) / z,
+                2 => 1.86 * z - 0.86 * nodes[0],
+                3 => 1.91 * z - 0.91 * nodes[1],
+                _ => 2.0 * z - nodes[i - 2],
+            };
+            let mut derivative = 0.0;
+            for _ in 0..MAX_NEWTON {
+                let (mut p1, mut p2) = (h0, 0.0);
+                for j in 0..n {
+                    let p3 = p2;
+                    p2 = p1;
+                    let j = j as f64;
+                    p1 = z * (2.0 / (j + 1.0)).sqrt() * p2
+                        - (j / (j + 1.0)).sqrt() * p3;
+                }
+                derivative = (2.0 * nf).sqrt() * p2;
+                let step = p1 / derivative;
+                z -= step;
+                if step.abs() <= 1E-14 * z.abs().max(1.0) {
+                    break;
+                }
+            }
+            nodes[i] = z;
+            nodes[n - 1 - i] = -z;
+            weights[i] = 2.0 / (derivative * derivative);
+            weights[n - 1 - i] = weights[i];
+        }
+        GaussHermite { nodes, weights }
+    }
+
+    /// Nodes and weights of the rule
+    pub fn nodes(&self) -> (&[f64], &[f64]) {
+        (&self.nodes, &self.weights)
+    }
+
+    /// Estimate of `∫ f(x) exp(-x²) dx` over the real line
+    pub fn integrate<F: Fn(f64) -> f64>(&self, f: F) -> f64 {
+        self.nodes
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(&x, &w)| w * f(x))
+            .sum()
+    }
+
+    /// Estimate of `ln ∫ exp(ln_f(u)) du` over the real line by adaptive
+    /// Gauss-Hermite quadrature
+    ///
+    /// The rule is centered on the mode of `ln_f`, found by Newton's method
+    /// from `start`, and scaled by `ln_f`'s curvature there, so `ln_f`
+    /// should be unimodal and finite at `start`. Where the curvature is not
+    /// negative the rule is left unscaled.
+    pub fn ln_integral<F: Fn(f64) -> f64>(&self, ln_f: F, start: f64) -> f64 {
+        let (mode, curvature) = find_mode(&ln_f, start);
+        let scale = if curvature < 0.0 && curvature.is_finite() {
+            (-curvature).sqrt().recip()
+        } else {
+            1.0
+        };
+        let root2 = std::f64::consts::SQRT_2;
+        let terms: Vec<f64> = self
+            .nodes
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(&x, &w)| w.ln() + x * x + ln_f(mode + root2 * scale * x))
+            .collect();
+        let ln_max =
+            terms.iter().cloned().fold(std::f64::NEG_INFINITY, f64::max);
+        if !ln_max.is_finite() {
+            return ln_max;
+        }
+        (root2 * scale).ln() + logsumexp(&terms)
+    }
+}
+
+/// First and second derivatives of `f` at `x` by central differences
+fn derivatives<F: Fn(f64) -> f64>(f: &F, x: f64) -> (f64, f64) {
+    let h = 1E-4 * x.abs().max(1.0);
+    let (lower, center, upper) = (f(x - h), f(x), f(x + h));
+    (
+        (upper - lower) / (2.0 * h),
+        (upper - 2.0 * center + lower) / (h * h),
+    )
+}
+
+/// Mode of `ln_f` near `start` by damped Newton steps, with `ln_f`'s second
+/// derivative there
+fn find_mode<F: Fn(f64) -> f64>(ln_f: &F, start: f64) -> (f64, f64) {
+    let mut x = start;
+    let (mut gradient, mut curvature) = derivatives(ln_f, x);
+    for _ in 0..MAX_NEWTON {
+        let concave = curvature < 0.0 && curvature.is_finite();
+        if !concave || !gradient.is_finite() {
+            break;
+        }
+        let current = ln_f(x);
+        let mut step = -gradient / curvature;
+        // Halve steps which overshoot to a lower point
+        for _ in 0..MAX_DEPTH {
+            if ln_f(x + step) >= current {
+                break;
+            }
+            step /= 2.0;
+        }
+        x += step;
+        let next = derivatives(ln_f, x);
+        gradient = next.0;
+        curvature = next.1;
+        if step.abs() <= 1E-10 * x.abs().max(1.0) {
+            break;
+        }
+    }
+    (x, curvature)
+}
+
+/// A one dimensional density known up to a constant, normalized by
+/// quadrature over an interval holding essentially all of its mass
+pub struct Marginal<F>
+where
+    F: Fn(f64) -> f64,
+{
+    ln_f: F,
+    lower: f64,
+    upper: f64,
+    // Log density subtracted before exponentiating, to avoid overflow
+    shift: f64,
+    // Integral of the shifted density over [lower, upper]
+    norm: f64,
+    tol: f64,
+}
+
+impl<F> Marginal<F>
+where
+    F: Fn(f64) -> f64,
+{
+    /// Normalize the unnormalized log density `ln_f` over
+    /// `[lower, upper]`.
+    pub fn new(ln_f: F, lower: f64, upper: f64) -> Self {
+        assert!(lower < upper, "lower must be less than upper.");
+        let shift = (0..=200)
+            .map(|i| ln_f(lower + (upper - lower) * i as f64 / 200.0))
+            .filter(|x| x.is_finite())
+            .fold(std::f64::NEG_INFINITY, f64::max);
+        assert!(shift.is_finite(), "ln_f is not finite on the interval.");
+        let mut marginal = Marginal {
+            ln_f,
+            lower,
+            upper,
+            shift,
+            norm: 1.0,
+            tol: 1E-10,
+        };
+        marginal.norm = marginal.integrate(|_| 1.0, upper);
+        marginal
+    }
+
+    /// Integral of `g` times the shifted density over `[lower, x]`
+    fn integrate<G: Fn(f64) -> f64>(&self, g: G, x: f64) -> f64 {
+        let x = x.min(self.upper);
+        if x <= self.lower {
+            return 0.0;
+        }
+        let density = |y: f64| g(y) * ((self.ln_f)(y) - self.shift).exp();
+        gauss_kronrod(density, self.lower, x, self.tol) / self.norm
+    }
+
+    /// Normalized density at `x`
+    pub fn pdf(&self, x: f64) -> f64 {
+        if x < self.lower || x > self.upper {
+            0.0
+        } else {
+            ((self.ln_f)(x) - self.shift).exp() / self.norm
+        }
+    }
+
+    /// Cumulative distribution at `x`, e.g. to compare draws with `ks_test`
+    pub fn cdf(&self, x: f64) -> f64 {
+        self.integrate(|_| 1.0, x).min(1.0)
+    }
+
+    /// Mean of the distribution
+    pub fn mean(&self) -> f64 {
+        self.integrate(|y| y, self.upper)
+    }
+
+    /// Variance of the distribution
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.integrate(|y| (y - mean) * (y - mean), self.upper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rv::dist::{Gamma, Gaussian};
+    use rv::misc::ks_test;
+    use rv::traits::{Cdf, Rv};
+
+    const SEED: [u8; 32] = [0; 32];
+
+    #[test]
+    fn rules_integrate_known_functions() {
+        let pi = std::f64::consts::PI;
+        assert!((simpson(f64::sin, 0.0, pi, 1E-10) - 2.0).abs() < 1E-9);
+        assert!((gauss_kronrod(f64::sin, 0.0, pi, 1E-12) - 2.0).abs() < 1E-12);
+
+        let cubic = |x: f64| x * x * x - 2.0 * x;
+        assert!((simpson(cubic, -1.0, 3.0, 1E-10) - 12.0).abs() < 1E-9);
+
+        // A singular derivative and a sharp peak only adaptivity resolves
+        let root = |x: f64| x.sqrt();
+        assert!((simpson(root, 0.0, 1.0, 1E-10) - 2.0 / 3.0).abs() < 1E-8);
+        let peak = |x: f64| (-1E4 * (x - 0.3) * (x - 0.3)).exp();
+        let expected = (pi / 1E4).sqrt();
+        let estimate = gauss_kronrod(peak, 0.0, 1.0, 1E-12);
+        assert!((estimate - expected).abs() < 1E-9);
+    }
+
+    #[test]
+    fn marginal_matches_a_known_distribution() {
+        let gamma = Gamma::new(3.0, 2.0).unwrap();
+        // Unnormalized and shifted far from zero on the log scale
+        let ln_f = |x: f64| 2.0 * x.ln() - 2.0 * x + 500.0;
+        let marginal = Marginal::new(ln_f, 0.0, 40.0);
+
+        assert!((marginal.mean() - 1.5).abs() < 1E-8);
+        assert!((marginal.variance() - 0.75).abs() < 1E-8);
+        assert!((marginal.pdf(1.0) - gamma.f(&1.0)).abs() < 1E-8);
+        assert!((marginal.cdf(2.0) - gamma.cdf(&2.0)).abs() < 1E-8);
+        assert_eq!(marginal.cdf(-1.0), 0.0);
+        assert_eq!(marginal.cdf(50.0), 1.0);
+    }
+
+    #[test]
+    fn marginal_validates_draws() {
+        let mut rng = StdRng::from_seed(SEED);
+        let xs: Vec<f64> =
+            Gaussian::new(1.0, 2.0).unwrap().sample(500, &mut rng);
+        let marginal =
+            Marginal::new(|x: f64| -(x - 1.0) * (x - 1.0) / 8.0, -20.0, 20.0);
+        let (_, p) = ks_test(&xs, |x| marginal.cdf(x));
+        assert!(p > 0.2);
+
+        let shifted = Marginal::new(|x: f64| -x * x / 8.0, -20.0, 20.0);
+        let (_, p) = ks_test(&xs, |x| shifted.cdf(x));
+        assert!(p < 0.01);
+    }
+
+    #[test]
+    fn gauss_hermite_is_exact_for_low_degree_polynomials() {
+        let root_pi = std::f64::consts::PI.sqrt();
+        for &n in &[1, 2, 3, 4, 5, 10, 20] {
+            let rule = GaussHermite::new(n);
+            let (nodes, weights) = rule.nodes();
+            assert_eq!(nodes.len(), n);
+            assert!(nodes.windows(2).all(|x| x[0] > x[1]));
+            assert!((weights.iter().sum::<f64>() - root_pi).abs() < 1E-12);
+            // ∫ x^2k exp(-x²) dx = Γ(k + 1/2), odd moments vanish
+            let mut moment = root_pi;
+            for k in 0..n {
+                let even = rule.integrate(|x| x.powi(2 * k as i32));
+                assert!((even - moment).abs() < 1E-10 * moment);
+                let odd = rule.integrate(|x| x.powi(2 * k as i32 + 1));
+                assert!(odd.abs() < 1E-10 * moment);
+                moment *= k as f64 + 0.5;
+            }
+        }
+    }
+
+    #[test]
+    fn adaptive_gauss_hermite_integrates_a_skewed_log_density() {
+        // ln Γ(3) = ln ∫ u² exp(-u) du on the log scale u = e^v
+        let ln_f = |v: f64| 3.0 * v - v.exp() + 700.0;
+        let expected = 2f64.ln() + 700.0;
+        let laplace = GaussHermite::new(1).ln_integral(ln_f, 0.0);
+        assert!((laplace - expected).abs() < 0.05);
+        // The error falls quickly with more nodes.
+        let estimate = GaussHermite::new(15).ln_integral(ln_f, 0.0);
+        assert!((estimate - expected).abs() < 1E-4);
+        let estimate = GaussHermite::new(40).ln_integral(ln_f, 0.0);
+        assert!((estimate - expected).abs() < 1E-7);
+
+        // Far from the start, where the unadapted rule has no mass
+        let narrow = |u: f64| -50.0 * (u - 40.0) * (u - 40.0);
+        let expected = (std::f64::consts::PI / 50.0).sqrt().ln();
+        let estimate = GaussHermite::new(3).ln_integral(narrow, 0.0);
+        assert!((estimate - expected).abs() < 1E-8);
+    }
+}
@@ -0,0 +1,413 @@
+//! Conjugate models with analytic posteriors
+//!
+//! Each fixture is a scalar parameter with a prior, fixed data and the
+//! posterior those give in closed form. Run a stepper for the fixture's
+//! parameter and likelihood and compare its draws with the posterior:
+//!
+//! ```
+//! # extern crate rmcmc;
+//! # extern crate rand;
+//! # use rmcmc::runner::Runner;
+//! # use rmcmc::steppers::SRWM;
+//! # use rmcmc::validation::conjugate;
+//! # use rand::SeedableRng;
+//! # use rand::rngs::StdRng;
+//! # fn main() {
+//! let mut rng = StdRng::from_seed([0; 32]);
+//! let fixture = conjugate::normal_normal();
+//! let stepper = SRWM::new(
+//!     fixture.parameter(),
+//!     fixture.log_likelihood(),
+//!     Some(0.5),
+//! ).unwrap();
+//! let runner = Runner::new(stepper).warmup(500).samples(2000).thinning(5);
+//! assert!(fixture.validate(&runner, &mut rng, 5));
+//! # }
+//! ```
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use rand::prelude::*;
+use rv::dist::{Beta, Gamma, Gaussian};
+use rv::misc::ks_test;
+use rv::traits::{Cdf, Rv};
+
+use lens::Lens;
+use parameter::Parameter;
+use runner::Runner;
+use steppers::SteppingAlg;
+use utils::multiple_tries;
+use utils::quadrature::Marginal;
+use validation::failure::{Failure, FailureCapture};
+
+/// Significance level of the KS test in `Fixture::validate`
+pub const ALPHA: f64 = 0.05;
+
+/// Model of every fixture, a single real parameter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scalar {
+    pub value: f64,
+}
+
+type Func = Arc<dyn Fn(f64) -> f64 + Send + Sync>;
+
+/// A scalar parameter with its prior, likelihood and analytic posterior
+#[derive(Clone)]
+pub struct Fixture<P>
+where
+    P: Rv<f64> + Clone,
+{
+    pub name: String,
+    pub prior: P,
+    /// Support of the posterior, e.g. for `SRWM::bounded`
+    pub bounds: (f64, f64),
+    /// Model the chains start from
+    pub init: Scalar,
+    ln_likelihood: Func,
+    posterior_cdf: Func,
+}
+
+impl<P> fmt::Debug for Fixture<P>
+where
+    P: Rv<f64> + Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fixture {{ name: {} }}", self.name)
+    }
+}
+
+impl<P> Fixture<P>
+where
+    P: Rv<f64> + Clone,
+{
+    /// Fixture with log likelihood `ln_likelihood` of the parameter's value
+    /// and posterior CDF `posterior_cdf`, unbounded and starting at `init`.
+    pub fn new<L, C>(
+        name: &str,
+        prior: P,
+        init: f64,
+        ln_likelihood: L,
+        posterior_cdf: C,
+    ) -> Self
+    where
+        L: 'static + Fn(f64) -> f64 + Send + Sync,
+        C: 'static + Fn(f64) -> f64 + Send + Sync,
+    {
+        Fixture {
+            name: name.to_string(),
+            prior,
+            bounds: (std::f64::NEG_INFINITY, std::f64::INFINITY),
+            init: Scalar { value: init },
+            ln_likelihood: Arc::new(ln_likelihood),
+            posterior_cdf: Arc::new(posterior_cdf),
+        }
+    }
+
+    /// Restrict the support to `[lower, upper]`.
+    pub fn bounded(&self, lower: f64, upper: f64) -> Self {
+        Fixture {
+            bounds: (lower, upper),
+            ..(*self).clone()
+        }
+    }
+
+    /// The parameter, named after the fixture
+    pub fn parameter(&self) -> Parameter<P, f64, Scalar> {
+        Parameter::new(
+            self.name.clone(),
+            self.prior.clone(),
+            Lens::new(|m: &Scalar| m.value, |_, value| Scalar { value }),
+        )
+    }
+
+    /// Log likelihood of the fixture's data
+    pub fn log_likelihood(
+        &self,
+    ) -> impl Fn(&Scalar) -> f64 + Clone + Send + Sync {
+        let ln_likelihood = self.ln_likelihood.clone();
+        move |m: &Scalar| ln_likelihood(m.value)
+    }
+
+    /// Posterior CDF at `x`
+    pub fn posterior_cdf(&self, x: f64) -> f64 {
+        (self.posterior_cdf)(x)
+    }
+
+    /// KS test p-value of `draws` against the posterior.
+    ///
+    /// Draws from a chain are correlated, which makes the test reject too
+    /// often; thin them so they are close to independent.
+    pub fn p_value(&self, draws: &[f64]) -> f64 {
+        ks_test(draws, |x| self.posterior_cdf(x)).1
+    }
+
+    /// Whether `runner`, started from `init`, samples the posterior: any of
+    /// `n_tries` runs passing the KS test at level `ALPHA`.
+    ///
+    /// When every run fails and `RMCMC_FAILURE_DIR` is set, the last is
+    /// recorded there so it can be replayed; use `validate_into` to learn
+    /// where, or why it could not be.
+    pub fn validate<A, R>(
+        &self,
+        runner: &Runner<Scalar, A, R>,
+        rng: &mut R,
+        n_tries: usize,
+    ) -> bool
+    where
+        A: 'static + SteppingAlg<Scalar, R> + Send + Sync + Clone,
+        R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+    {
+        match FailureCapture::from_env() {
+            Some(capture) => self
+                .validate_into(runner, rng, n_tries, &capture)
+                .map_or(false, |path| path.is_none()),
+            None => self.check(runner, rng, n_tries).is_none(),
+        }
+    }
+
+    /// As `validate`, recording a failure in `capture` and returning the
+    /// path it was written to, or `None` if the posterior was sampled.
+    pub fn validate_into<A, R>(
+        &self,
+        runner: &Runner<Scalar, A, R>,
+        rng: &mut R,
+        n_tries: usize,
+        capture: &FailureCapture,
+    ) -> io::Result<Option<PathBuf>>
+    where
+        A: 'static + SteppingAlg<Scalar, R> + Send + Sync + Clone,
+        R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+    {
+        match self.check(runner, rng, n_tries) {
+            Some(failure) => capture.record(&failure).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The last of `n_tries` runs of `runner` if every one of them fails
+    /// the KS test at level `ALPHA`, or `None` if any passes.
+    pub fn check<A, R>(
+        &self,
+        runner: &Runner<Scalar, A, R>,
+        rng: &mut R,
+        n_tries: usize,
+    ) -> Option<Failure>
+    where
+        A: 'static + SteppingAlg<Scalar, R> + Send + Sync + Clone,
+        R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+    {
+        let mut failure = None;
+        let passed = multiple_tries(n_tries, |_| {
+            let mut seed = R::Seed::default();
+            rng.fill(seed.as_mut());
+            let seed_bytes = seed.as_mut().to_vec();
+            let sample = self.draws(runner, seed);
+            let (statistic, p_value) =
+                ks_test(&sample, |x| self.posterior_cdf(x));
+            if p_value > ALPHA {
+                return true;
+            }
+            failure = Some(Failure {
+                test: self.name.clone(),
+                seed: seed_bytes,
+                config: config(runner),
+                statistic,
+                p_value,
+                sample,
+            });
+            false
+        });
+        failure.filter(|_| !passed)
+    }
+
+    /// Rerun the run which gave `failure`, returning its draws.
+    pub fn replay<A, R>(
+        &self,
+        runner: &Runner<Scalar, A, R>,
+        failure: &Failure,
+    ) -> io::Result<Vec<f64>>
+    where
+        A: 'static + SteppingAlg<Scalar, R> + Send + Sync + Clone,
+        R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+    {
+        Ok(self.draws(runner, failure.seed_as()?))
+    }
+
+    fn draws<A, R>(&self, runner: &Runner<Scalar, A, R>, seed: R::Seed)
+        -> Vec<f64>
+    where
+        A: 'static + SteppingAlg<Scalar, R> + Send + Sync + Clone,
+        R: SeedableRng + Rng + fmt::Debug + Send + Sync,
+    {
+        runner
+            .sample_from_seed(seed, self.init)
+            .draws()
+            .map(|m| m.value)
+            .collect()
+    }
+}
+
+fn config<A, R>(runner: &Runner<Scalar, A, R>) -> String
+where
+    A: SteppingAlg<Scalar, R> + Send + Sync + Clone,
+    R: SeedableRng + Rng,
+{
+    format!(
+        "chains: {}, warmup: {}, samples: {}, thinning: {}, stepper: {:?}",
+        runner.n_chains,
+        runner.warmup_steps,
+        runner.samples,
+        runner.thinning,
+        runner.stepper
+    )
+}
+
+/// Data of `normal_normal`, summing to 12
+pub const NORMAL_DATA: [f64; 10] =
+    [0.62, 1.91, 1.26, 0.35, 2.48, 1.07, 1.73, 0.84, 1.55, 0.19];
+
+/// Mean of Gaussian data with unit variance under a N(0, 2) prior
+pub fn normal_normal() -> Fixture<Gaussian> {
+    let n = NORMAL_DATA.len() as f64;
+    let sum: f64 = NORMAL_DATA.iter().sum();
+    let precision = 0.25 + n;
+    let posterior =
+        Gaussian::new(sum / precision, precision.recip().sqrt()).unwrap();
+    Fixture::new(
+        "normal_normal",
+        Gaussian::new(0.0, 2.0).unwrap(),
+        0.0,
+        move |mu| {
+            -0.5 * NORMAL_DATA.iter().map(|x| (x - mu).powi(2)).sum::<f64>()
+        },
+        move |x| posterior.cdf(&x),
+    )
+}
+
+/// Successes and trials of `beta_binomial`
+pub const BINOMIAL_DATA: (u32, u32) = (7, 20);
+
+/// Success probability of binomial data under a Beta(2, 2) prior
+pub fn beta_binomial() -> Fixture<Beta> {
+    let (k, n) = BINOMIAL_DATA;
+    let posterior = Beta::new(2.0 + k as f64, 2.0 + (n - k) as f64).unwrap();
+    // rv's Beta has no CDF, so the posterior's is found by quadrature.
+    let marginal = Marginal::new(move |p| posterior.ln_f(&p), 0.0, 1.0);
+    Fixture::new(
+        "beta_binomial",
+        Beta::new(2.0, 2.0).unwrap(),
+        0.5,
+        move |p| {
+            if p <= 0.0 || p >= 1.0 {
+                return std::f64::NEG_INFINITY;
+            }
+            k as f64 * p.ln() + (n - k) as f64 * (1.0 - p).ln()
+        },
+        move |x| marginal.cdf(x),
+    )
+    .bounded(0.0, 1.0)
+}
+
+/// Counts of `gamma_poisson`, summing to 31
+pub const POISSON_DATA: [u32; 8] = [3, 1, 4, 1, 5, 9, 2, 6];
+
+/// Rate of Poisson counts under a Gamma(2, 1) prior
+pub fn gamma_poisson() -> Fixture<Gamma> {
+    let n = POISSON_DATA.len() as f64;
+    let sum = POISSON_DATA.iter().sum::<u32>() as f64;
+    let posterior = Gamma::new(2.0 + sum, 1.0 + n).unwrap();
+    Fixture::new(
+        "gamma_poisson",
+        Gamma::new(2.0, 1.0).unwrap(),
+        1.0,
+        move |rate| {
+            if rate <= 0.0 {
+                return std::f64::NEG_INFINITY;
+            }
+            sum * rate.ln() - n * rate
+        },
+        move |x| posterior.cdf(&x),
+    )
+    .bounded(0.0, std::f64::INFINITY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rv::traits::{Mean, Variance};
+    use steppers::SRWM;
+
+    const SEED: [u8; 32] = [0; 32];
+    const N_TRIES: usize = 5;
+
+    fn srwm_validates<P>(fixture: &Fixture<P>) -> bool
+    where
+        P: 'static + Rv<f64> + Mean<f64> + Variance<f64>,
+        P: Clone + fmt::Debug + Send + Sync,
+    {
+        let mut rng = StdRng::from_seed(SEED);
+        let (lower, upper) = fixture.bounds;
+        let stepper =
+            SRWM::new(fixture.parameter(), fixture.log_likelihood(), None)
+                .unwrap()
+                .bounded(lower, upper);
+        let runner = Runner::new(stepper)
+            .chains(2)
+            .warmup(1000)
+            .samples(2000)
+            .thinning(5);
+        fixture.validate(&runner, &mut rng, N_TRIES)
+    }
+
+    #[test]
+    fn srwm_samples_every_posterior() {
+        assert!(srwm_validates(&normal_normal()));
+        assert!(srwm_validates(&beta_binomial()));
+        assert!(srwm_validates(&gamma_poisson()));
+    }
+
+    #[test]
+    fn prior_draws_fail_validation() {
+        let mut rng = StdRng::from_seed(SEED);
+        let fixture = gamma_poisson();
+        let draws = fixture.prior.sample(2000, &mut rng);
+        assert!(fixture.p_value(&draws) < ALPHA);
+
+        let cdf = fixture.posterior_cdf(33.0 / 9.0);
+        assert!(cdf > 0.4 && cdf < 0.6);
+    }
+
+    #[test]
+    fn failures_are_recorded_and_replayed() {
+        let mut rng = StdRng::from_seed(SEED);
+        let fixture = gamma_poisson();
+        // Without warmup the chains stay near the initial value.
+        let stepper =
+            SRWM::new(fixture.parameter(), fixture.log_likelihood(), None)
+                .unwrap()
+                .bounded(0.0, std::f64::INFINITY);
+        let runner = Runner::new(stepper).warmup(0).samples(20);
+
+        let dir = std::env::temp_dir()
+            .join(format!("rmcmc_replay_test_{}", std::process::id()));
+        let capture = FailureCapture::new(&dir);
+        let fixture = Fixture {
+            init: Scalar { value: 50.0 },
+            ..fixture
+        };
+        let path = fixture
+            .validate_into(&runner, &mut rng, 2, &capture)
+            .unwrap()
+            .unwrap();
+        assert!(path.starts_with(&dir));
+
+        let failure = Failure::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(failure.test, "gamma_poisson");
+        assert!(failure.p_value <= ALPHA);
+        assert_eq!(fixture.replay(&runner, &failure).unwrap(), failure.sample);
+    }
+}
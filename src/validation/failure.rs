@@ -0,0 +1,214 @@
+//! Capture of failed statistical validations for replay
+//!
+//! Statistical tests fail by chance now and then, and a failure seen once
+//! is hard to tell from a bug without the draws that caused it. A `Failure`
+//! records the seed, configuration and sample of a failed test, and a
+//! `FailureCapture` writes it to a directory so the run can be replayed
+//! from the seed and the sample inspected.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use utils::invalid_data;
+
+/// Environment variable naming the directory failures are written to
+pub const FAILURE_DIR_VAR: &str = "RMCMC_FAILURE_DIR";
+
+/// A failed statistical test
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    /// Name of the test
+    pub test: String,
+    /// Seed the run was made from
+    pub seed: Vec<u8>,
+    /// Description of the run's configuration
+    pub config: String,
+    /// Test statistic, e.g. the KS distance
+    pub statistic: f64,
+    pub p_value: f64,
+    /// The sample which failed the test
+    pub sample: Vec<f64>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> io::Result<Vec<u8>> {
+    if !s.is_ascii() {
+        return Err(invalid_data("seed has a non-hexadecimal digit"));
+    }
+    if s.len() % 2 != 0 {
+        return Err(invalid_data("seed has an odd number of digits"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(invalid_data))
+        .collect()
+}
+
+impl Failure {
+    /// The seed as a generator's seed type, e.g. `StdRng`'s `[u8; 32]`.
+    pub fn seed_as<S: Default + AsMut<[u8]>>(&self) -> io::Result<S> {
+        let mut seed = S::default();
+        if seed.as_mut().len() != self.seed.len() {
+            return Err(invalid_data(format!(
+                "seed has {} bytes, expected {}",
+                self.seed.len(),
+                seed.as_mut().len()
+            )));
+        }
+        seed.as_mut().copy_from_slice(&self.seed);
+        Ok(seed)
+    }
+
+    /// Parse a failure written by `FailureCapture::record`.
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let mut lines = s.lines();
+        let mut field = |name: &str| -> io::Result<String> {
+            let line = lines
+                .next()
+                .ok_or_else(|| invalid_data(format!("missing {}", name)))?;
+            let prefix = format!("{}: ", name);
+            if line.starts_with(&prefix) {
+                Ok(line[prefix.len()..].to_string())
+            } else {
+                Err(invalid_data(format!("expected {}", name)))
+            }
+        };
+        let test = field("test")?;
+        let seed = unhex(&field("seed")?)?;
+        let config = field("config")?;
+        let statistic = field("statistic")?.parse().map_err(invalid_data)?;
+        let p_value = field("p_value")?.parse().map_err(invalid_data)?;
+        if lines.next() != Some("sample:") {
+            return Err(invalid_data("expected sample"));
+        }
+        let sample = lines
+            .map(|x| x.parse().map_err(invalid_data))
+            .collect::<io::Result<Vec<f64>>>()?;
+        Ok(Failure {
+            test,
+            seed,
+            config,
+            statistic,
+            p_value,
+            sample,
+        })
+    }
+
+    /// Read a failure written by `FailureCapture::record`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Failure::parse(&fs::read_to_string(path)?)
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "test: {}", self.test)?;
+        writeln!(f, "seed: {}", hex(&self.seed))?;
+        writeln!(f, "config: {}", self.config.replace('\n', " "))?;
+        writeln!(f, "statistic: {}", self.statistic)?;
+        writeln!(f, "p_value: {}", self.p_value)?;
+        writeln!(f, "sample:")?;
+        for x in self.sample.iter() {
+            writeln!(f, "{}", x)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where failures are recorded
+#[derive(Clone, Debug, PartialEq)]
+pub struct FailureCapture {
+    dir: PathBuf,
+}
+
+impl FailureCapture {
+    /// Record failures in `dir`, created when the first is recorded.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        FailureCapture {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Record failures in the directory named by `RMCMC_FAILURE_DIR`, or
+    /// `None` when it is unset, so that nothing is written by default.
+    pub fn from_env() -> Option<Self> {
+        env::var_os(FAILURE_DIR_VAR).map(FailureCapture::new)
+    }
+
+    /// Directory failures are recorded in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Write `failure` to a new file in the directory, returning its path.
+    pub fn record(&self, failure: &Failure) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let name: String = failure
+            .test
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let path = self.dir.join(format!("{}-{}.txt", name, nanos));
+        fs::write(&path, failure.to_string())?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure() -> Failure {
+        Failure {
+            test: "gamma_poisson/srwm".to_string(),
+            seed: vec![0, 1, 254, 255],
+            config: "chains: 2\nthinning: 5".to_string(),
+            statistic: 0.125,
+            p_value: 1E-5,
+            sample: vec![1.5, -0.25, 3.0],
+        }
+    }
+
+    #[test]
+    fn failures_round_trip_through_files() {
+        let dir = env::temp_dir()
+            .join(format!("rmcmc_failure_test_{}", std::process::id()));
+        let capture = FailureCapture::new(&dir);
+        let path = capture.record(&failure()).unwrap();
+        assert!(path.starts_with(&dir));
+
+        let loaded = Failure::load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.config, "chains: 2 thinning: 5");
+        assert_eq!(
+            loaded,
+            Failure {
+                config: loaded.config.clone(),
+                ..failure()
+            }
+        );
+        assert_eq!(loaded.seed_as::<[u8; 4]>().unwrap(), [0, 1, 254, 255]);
+        assert!(loaded.seed_as::<[u8; 32]>().is_err());
+    }
+
+    #[test]
+    fn malformed_failures_are_rejected() {
+        let text = failure().to_string();
+        let err = Failure::parse(&text.replace("seed: ", "sed: ")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(Failure::parse(&text.replace("1.5", "x")).is_err());
+        assert!(Failure::parse(&text.replace("00", "0")).is_err());
+        assert!(Failure::parse(&text.replace("0001", "0é1")).is_err());
+    }
+}
@@ -0,0 +1,8 @@
+//! Validation of steppers against known posteriors
+//!
+//! Fixtures whose posteriors are known in closed form, so any stepper,
+//! including ones defined outside this crate, can be checked to sample from
+//! the right distribution.
+
+pub mod conjugate;
+pub mod failure;